@@ -0,0 +1,68 @@
+use crate::resp::{errors::Error, types::RespType};
+use crate::utils::acl::{self, User};
+use crate::utils::context::Context;
+use crate::utils::session::SessionState;
+
+use super::resp_command::{RESPCommand, RESPCommandName, RESPMinMaxArgs};
+
+/// `ACL SETUSER | GETUSER | DELUSER | LIST | USERS | WHOAMI | CAT`
+pub struct Acl(pub Vec<String>);
+
+impl RESPCommandName for Acl {
+    fn command_name(&self) -> &'static str {
+        "acl"
+    }
+}
+
+impl RESPMinMaxArgs for Acl {
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> usize {
+        usize::MAX
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for Acl {
+    /// Only runs for a connection, scripts can't call it
+    fn execute(&mut self, _: &mut Context) -> RespType {
+        Error::custom("ERR This Redis command is not allowed from script").into()
+    }
+
+    fn execute_for(&mut self, session: &mut SessionState, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        let subcommand = self.0[0].to_lowercase();
+
+        let reply = match (subcommand.as_str(), &self.0[1..]) {
+            ("setuser", [user, rules @ ..]) => ctx.acl.set_user(user, rules).map(|_| RespType::ok()),
+            ("getuser", [user]) => Ok(ctx.acl.get(user).map_or(RespType::Null, User::to_resp)),
+            ("deluser", users @ [_, ..]) => ctx.acl.delete(users).map(|count| RespType::Integer(count as i64)),
+            ("list", []) => Ok(RespType::array(
+                ctx.acl.users().map(|user| RespType::bulk_string(user.describe())).collect(),
+            )),
+            ("users", []) => Ok(RespType::array(
+                ctx.acl.users().map(|user| RespType::bulk_string(&user.name)).collect(),
+            )),
+            ("whoami", []) => Ok(RespType::bulk_string(&session.user)),
+            ("cat", []) => acl::Acl::cat(None),
+            ("cat", [category]) => acl::Acl::cat(Some(category)),
+            ("setuser" | "getuser" | "deluser" | "list" | "users" | "whoami" | "cat", _) => Err(Error::custom(
+                format!("ERR wrong number of arguments for 'acl|{subcommand}' command"),
+            )),
+            _ => Err(Error::custom(format!("ERR unknown subcommand '{}'. Try ACL HELP.", self.0[0]))),
+        };
+
+        reply.unwrap_or_else(Into::into)
+    }
+}
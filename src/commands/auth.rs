@@ -0,0 +1,156 @@
+use crate::resp::{errors::Error, types::RespType};
+use crate::utils::acl::{User, DEFAULT_USER};
+use crate::utils::context::Context;
+use crate::utils::session::SessionState;
+
+use super::resp_command::{RESPCommand, RESPCommandName, RESPMinMaxArgs};
+
+/// `AUTH [username] password`
+pub struct Auth(pub Vec<String>);
+
+impl RESPCommandName for Auth {
+    fn command_name(&self) -> &'static str {
+        "auth"
+    }
+}
+
+impl RESPMinMaxArgs for Auth {
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> usize {
+        2
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for Auth {
+    /// Only runs for a connection, scripts can't call it
+    fn execute(&mut self, _: &mut Context) -> RespType {
+        Error::custom("ERR This Redis command is not allowed from script").into()
+    }
+
+    fn execute_for(&mut self, session: &mut SessionState, ctx: &mut Context) -> RespType {
+        let (user, password) = match self.0.as_slice() {
+            [password] => (DEFAULT_USER, password),
+            [user, password] => (user.as_str(), password),
+            _ => {
+                return Error::WrongNumberOfArguments {
+                    command: self.command_name().to_string(),
+                }
+                .into()
+            }
+        };
+
+        if self.0.len() == 1 && ctx.acl.get(DEFAULT_USER).is_some_and(User::is_nopass) {
+            return Error::custom(
+                "ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?",
+            )
+            .into();
+        }
+
+        match session.authenticate(user, password, &ctx.acl) {
+            Ok(()) => RespType::ok(),
+            Err(err) => err.into(),
+        }
+    }
+}
+
+/// `HELLO [protover [AUTH username password] [SETNAME name]]`, switches the
+/// protocol of the connection
+pub struct Hello(pub Vec<String>);
+
+impl RESPCommandName for Hello {
+    fn command_name(&self) -> &'static str {
+        "hello"
+    }
+}
+
+impl RESPMinMaxArgs for Hello {
+    fn min_args(&self) -> usize {
+        0
+    }
+
+    fn max_args(&self) -> usize {
+        usize::MAX
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for Hello {
+    /// Only runs for a connection, scripts can't call it
+    fn execute(&mut self, _: &mut Context) -> RespType {
+        Error::custom("ERR This Redis command is not allowed from script").into()
+    }
+
+    fn execute_for(&mut self, session: &mut SessionState, ctx: &mut Context) -> RespType {
+        let mut args = self.0.iter();
+        let mut protocol = session.protocol;
+
+        if let Some(version) = args.next() {
+            protocol = match version.parse::<u8>() {
+                Ok(version @ 2..=3) => version,
+                Ok(_) => {
+                    return Error::custom("NOPROTO sorry, this protocol version is not supported.").into()
+                }
+                Err(_) => return Error::custom("ERR Protocol version is not an integer or out of range").into(),
+            };
+        }
+
+        let mut name = None;
+        let mut auth = None;
+
+        while let Some(option) = args.next() {
+            match (option.to_lowercase().as_str(), args.next()) {
+                ("setname", Some(value)) => name = Some(value.to_string()),
+                ("auth", Some(user)) => match args.next() {
+                    Some(password) => auth = Some((user, password)),
+                    None => return Error::Syntax.into(),
+                },
+                _ => return Error::Syntax.into(),
+            }
+        }
+
+        match auth {
+            Some((user, password)) => {
+                if let Err(err) = session.authenticate(user, password, &ctx.acl) {
+                    return err.into();
+                }
+            }
+            None if !session.authenticated => {
+                return Error::custom(
+                    "NOAUTH HELLO must be called with the client already authenticated, otherwise the HELLO <proto> AUTH <user> <pass> option can be used to authenticate the client and select the RESP protocol version at the same time",
+                )
+                .into()
+            }
+            None => {}
+        }
+
+        session.protocol = protocol;
+
+        if name.is_some() {
+            session.name = name;
+        }
+
+        if let Some(client) = ctx.clients.get_mut(&session.id) {
+            client.protocol = protocol;
+        }
+
+        RespType::Map(vec![
+            (RespType::bulk_string("server"), RespType::bulk_string("redis")),
+            (RespType::bulk_string("version"), RespType::bulk_string("7.2.0")),
+            (RespType::bulk_string("proto"), RespType::Integer(protocol as i64)),
+            (RespType::bulk_string("id"), RespType::Integer(session.id as i64)),
+            (RespType::bulk_string("mode"), RespType::bulk_string("standalone")),
+            (RespType::bulk_string("role"), RespType::bulk_string(ctx.config.role.to_string())),
+            (RespType::bulk_string("modules"), RespType::array(vec![])),
+        ])
+    }
+}
@@ -0,0 +1,502 @@
+use crate::models::{BloomFilter, StoreValue, Value, MAX_EXPANSION};
+use crate::resp::errors::Error;
+use crate::resp::types::RespType;
+use crate::utils::context::Context;
+
+use super::resp_command::{RESPCommand, RESPCommandName, RESPMinMaxArgs};
+
+/// Looks up the filter stored at `key`, `WRONGTYPE` when the key holds another type
+fn get_filter<'a>(ctx: &'a mut Context, key: &str) -> Result<Option<&'a mut BloomFilter>, Error> {
+    ctx.remove_if_expired(key);
+
+    match ctx.store.get_mut(key) {
+        Some(StoreValue {
+            data: Value::BloomFilter(filter),
+            ..
+        }) => Ok(Some(filter)),
+        Some(_) => Err(Error::WrongType),
+        None => Ok(None),
+    }
+}
+
+/// Same as [`get_filter`] but creates the filter with the configured defaults
+fn get_or_create_filter<'a>(ctx: &'a mut Context, key: &str) -> Result<&'a mut BloomFilter, Error> {
+    if get_filter(ctx, key)?.is_none() {
+        let filter = BloomFilter::new(
+            ctx.config.bf_initial_size,
+            ctx.config.bf_error_rate,
+            ctx.config.bf_expansion_factor,
+        )
+        .map_err(Error::custom)?;
+
+        ctx.store
+            .insert(key.to_string(), Value::BloomFilter(filter).into());
+    }
+
+    Ok(get_filter(ctx, key)?.expect("filter was just created"))
+}
+
+fn insert_items(filter: &mut BloomFilter, items: &[String]) -> Vec<RespType> {
+    items
+        .iter()
+        .map(|item| match filter.insert(item.as_bytes()) {
+            Ok(added) => RespType::Integer(added as i64),
+            Err(message) => Error::custom(message).into(),
+        })
+        .collect()
+}
+
+pub struct BfReserve(pub Vec<String>);
+
+impl RESPCommandName for BfReserve {
+    fn command_name(&self) -> &'static str {
+        "bf.reserve"
+    }
+}
+
+impl RESPMinMaxArgs for BfReserve {
+    fn min_args(&self) -> usize {
+        3
+    }
+
+    fn max_args(&self) -> usize {
+        6
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for BfReserve {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        let key = &self.0[0];
+        let error_rate = match self.0[1].parse::<f64>() {
+            Ok(rate) if rate > 0.0 && rate < 1.0 => rate,
+            Ok(_) => return Error::custom("ERR (0 < error rate range < 1)").into(),
+            Err(_) => return Error::custom("ERR bad error rate").into(),
+        };
+        let capacity = match self.0[2].parse::<u64>() {
+            Ok(capacity) if capacity > 0 => capacity,
+            _ => return Error::custom("ERR (capacity should be larger than 0)").into(),
+        };
+
+        let mut expansion = ctx.config.bf_expansion_factor;
+        let mut options = self.0.iter().skip(3);
+
+        while let Some(option) = options.next() {
+            match option.to_lowercase().as_str() {
+                "nonscaling" => expansion = 0,
+                "expansion" => {
+                    let value = options.next().and_then(|v| v.parse::<u32>().ok());
+
+                    match value {
+                        Some(value @ 1..=MAX_EXPANSION) => expansion = value,
+                        _ => return Error::custom("ERR bad expansion").into(),
+                    }
+                }
+                _ => return Error::Syntax.into(),
+            }
+        }
+
+        match get_filter(ctx, key) {
+            Ok(Some(_)) => return Error::custom("ERR item exists").into(),
+            Ok(None) => {}
+            Err(err) => return err.into(),
+        }
+
+        let filter = match BloomFilter::new(capacity, error_rate, expansion) {
+            Ok(filter) => filter,
+            Err(err) => return Error::custom(err).into(),
+        };
+        ctx.store
+            .insert(key.to_string(), Value::BloomFilter(filter).into());
+
+        RespType::ok()
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.0.iter().take(1).cloned().collect()
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+}
+
+pub struct BfAdd(pub Vec<String>);
+
+impl RESPCommandName for BfAdd {
+    fn command_name(&self) -> &'static str {
+        "bf.add"
+    }
+}
+
+impl RESPMinMaxArgs for BfAdd {
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn max_args(&self) -> usize {
+        2
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for BfAdd {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        match get_or_create_filter(ctx, &self.0[0]) {
+            Ok(filter) => {
+                let reply = insert_items(filter, &self.0[1..]).remove(0);
+                ctx.store.touch(&self.0[0]);
+                reply
+            }
+            Err(err) => err.into(),
+        }
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.0.iter().take(1).cloned().collect()
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+}
+
+pub struct BfMadd(pub Vec<String>);
+
+impl RESPCommandName for BfMadd {
+    fn command_name(&self) -> &'static str {
+        "bf.madd"
+    }
+}
+
+impl RESPMinMaxArgs for BfMadd {
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn max_args(&self) -> usize {
+        usize::MAX
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for BfMadd {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        match get_or_create_filter(ctx, &self.0[0]) {
+            Ok(filter) => {
+                let replies = insert_items(filter, &self.0[1..]);
+                ctx.store.touch(&self.0[0]);
+                RespType::array(replies)
+            }
+            Err(err) => err.into(),
+        }
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.0.iter().take(1).cloned().collect()
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+}
+
+pub struct BfExists(pub Vec<String>);
+
+impl RESPCommandName for BfExists {
+    fn command_name(&self) -> &'static str {
+        "bf.exists"
+    }
+}
+
+impl RESPMinMaxArgs for BfExists {
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn max_args(&self) -> usize {
+        2
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for BfExists {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        match get_filter(ctx, &self.0[0]) {
+            Ok(filter) => {
+                let exists = filter.is_some_and(|f| f.contains(self.0[1].as_bytes()));
+
+                RespType::Integer(exists as i64)
+            }
+            Err(err) => err.into(),
+        }
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.0.iter().take(1).cloned().collect()
+    }
+}
+
+pub struct BfMexists(pub Vec<String>);
+
+impl RESPCommandName for BfMexists {
+    fn command_name(&self) -> &'static str {
+        "bf.mexists"
+    }
+}
+
+impl RESPMinMaxArgs for BfMexists {
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn max_args(&self) -> usize {
+        usize::MAX
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for BfMexists {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        match get_filter(ctx, &self.0[0]) {
+            Ok(filter) => {
+                let values = self.0[1..]
+                    .iter()
+                    .map(|item| {
+                        let exists = filter
+                            .as_ref()
+                            .is_some_and(|f| f.contains(item.as_bytes()));
+
+                        RespType::Integer(exists as i64)
+                    })
+                    .collect();
+
+                RespType::array(values)
+            }
+            Err(err) => err.into(),
+        }
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.0.iter().take(1).cloned().collect()
+    }
+}
+
+pub struct BfInfo(pub Vec<String>);
+
+impl RESPCommandName for BfInfo {
+    fn command_name(&self) -> &'static str {
+        "bf.info"
+    }
+}
+
+impl RESPMinMaxArgs for BfInfo {
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> usize {
+        2
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for BfInfo {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        let filter = match get_filter(ctx, &self.0[0]) {
+            Ok(Some(filter)) => filter,
+            Ok(None) => return Error::custom("ERR not found").into(),
+            Err(err) => return err.into(),
+        };
+
+        let expansion = if filter.is_scaling() {
+            RespType::Integer(filter.expansion as i64)
+        } else {
+            RespType::Null
+        };
+
+        let fields = vec![
+            ("Capacity", RespType::Integer(filter.capacity() as i64)),
+            ("Size", RespType::Integer(filter.size() as i64)),
+            ("Number of filters", RespType::Integer(filter.layers.len() as i64)),
+            ("Number of items inserted", RespType::Integer(filter.items() as i64)),
+            ("Expansion rate", expansion),
+        ];
+
+        match self.0.get(1).map(|field| field.to_lowercase()) {
+            None => RespType::array(
+                fields
+                    .into_iter()
+                    .flat_map(|(name, value)| [RespType::simple_string(name), value])
+                    .collect(),
+            ),
+            Some(field) => {
+                let index = match field.as_str() {
+                    "capacity" => 0,
+                    "size" => 1,
+                    "filters" => 2,
+                    "items" => 3,
+                    "expansion" => 4,
+                    _ => return Error::custom("ERR Invalid information value").into(),
+                };
+
+                let value = fields.into_iter().nth(index).map(|(_, v)| v);
+
+                RespType::array(value.into_iter().collect())
+            }
+        }
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.0.iter().take(1).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn add_and_exists() {
+        let mut ctx = Context::default();
+
+        assert_eq!(BfAdd(args(&["bf", "foo"])).execute(&mut ctx), RespType::Integer(1));
+        assert_eq!(BfAdd(args(&["bf", "foo"])).execute(&mut ctx), RespType::Integer(0));
+        assert_eq!(BfExists(args(&["bf", "foo"])).execute(&mut ctx), RespType::Integer(1));
+        assert_eq!(BfExists(args(&["bf", "bar"])).execute(&mut ctx), RespType::Integer(0));
+        assert_eq!(BfExists(args(&["missing", "foo"])).execute(&mut ctx), RespType::Integer(0));
+    }
+
+    #[test]
+    fn madd_and_mexists() {
+        let mut ctx = Context::default();
+
+        let response = BfMadd(args(&["bf", "a", "b", "a"])).execute(&mut ctx);
+        assert_eq!(
+            response,
+            RespType::array(vec![RespType::Integer(1), RespType::Integer(1), RespType::Integer(0)])
+        );
+
+        let response = BfMexists(args(&["bf", "a", "c"])).execute(&mut ctx);
+        assert_eq!(
+            response,
+            RespType::array(vec![RespType::Integer(1), RespType::Integer(0)])
+        );
+    }
+
+    #[test]
+    fn reserve_existing_key() {
+        let mut ctx = Context::default();
+
+        let response = BfReserve(args(&["bf", "0.01", "100"])).execute(&mut ctx);
+        assert_eq!(response, RespType::ok());
+
+        let response = BfReserve(args(&["bf", "0.01", "100"])).execute(&mut ctx);
+        assert_eq!(response, Error::custom("ERR item exists").into());
+    }
+
+    #[test]
+    fn reserve_nonscaling_filter() {
+        let mut ctx = Context::default();
+
+        BfReserve(args(&["bf", "0.01", "1", "NONSCALING"])).execute(&mut ctx);
+        BfAdd(args(&["bf", "a"])).execute(&mut ctx);
+
+        let response = BfAdd(args(&["bf", "b"])).execute(&mut ctx);
+        assert_eq!(response, Error::custom("ERR non scaling filter is full").into());
+
+        let response = BfInfo(args(&["bf", "expansion"])).execute(&mut ctx);
+        assert_eq!(response, RespType::array(vec![RespType::Null]));
+    }
+
+    #[test]
+    fn info_fields() {
+        let mut ctx = Context::default();
+
+        BfReserve(args(&["bf", "0.01", "10", "EXPANSION", "4"])).execute(&mut ctx);
+        BfAdd(args(&["bf", "a"])).execute(&mut ctx);
+
+        let response = BfInfo(args(&["bf", "capacity"])).execute(&mut ctx);
+        assert_eq!(response, RespType::array(vec![RespType::Integer(10)]));
+
+        let response = BfInfo(args(&["bf", "items"])).execute(&mut ctx);
+        assert_eq!(response, RespType::array(vec![RespType::Integer(1)]));
+
+        let response = BfInfo(args(&["missing"])).execute(&mut ctx);
+        assert_eq!(response, Error::custom("ERR not found").into());
+    }
+
+    #[test]
+    fn wrong_type() {
+        let mut ctx = Context::default();
+        ctx.store.insert("key".to_string(), StoreValue::from("value"));
+
+        let response = BfAdd(args(&["key", "a"])).execute(&mut ctx);
+
+        assert_eq!(response, RespType::SimpleError(Error::WrongType));
+    }
+}
@@ -0,0 +1,150 @@
+use crate::resp::{errors::Error, types::RespType};
+use crate::utils::context::Context;
+use crate::utils::session::SessionState;
+use crate::utils::tracking::TrackingOptions;
+
+use super::resp_command::{RESPCommand, RESPCommandName, RESPMinMaxArgs};
+
+/// `CLIENT ID | SETNAME | GETNAME | TRACKING | CACHING | GETREDIR`
+pub struct Client(pub Vec<String>);
+
+impl RESPCommandName for Client {
+    fn command_name(&self) -> &'static str {
+        "client"
+    }
+}
+
+impl RESPMinMaxArgs for Client {
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> usize {
+        usize::MAX
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for Client {
+    /// Only runs for a connection, scripts can't call it
+    fn execute(&mut self, _: &mut Context) -> RespType {
+        Error::custom("ERR This Redis command is not allowed from script").into()
+    }
+
+    fn execute_for(&mut self, session: &mut SessionState, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        let args = &self.0;
+        let subcommand = args[0].to_lowercase();
+
+        match (subcommand.as_str(), args.len()) {
+            ("id", 1) => RespType::Integer(session.id as i64),
+            ("setname", 2) => {
+                session.name = Some(args[1].to_string());
+                RespType::ok()
+            }
+            ("getname", 1) => match &session.name {
+                Some(name) => RespType::bulk_string(name),
+                None => RespType::Null,
+            },
+            ("tracking", 2..) => tracking(&args[1..], session, ctx),
+            ("caching", 2) => caching(&args[1], session, ctx),
+            ("getredir", 1) => match ctx.tracking.options(session.id) {
+                Some(options) => RespType::Integer(options.redirect.map_or(0, |id| id as i64)),
+                None => RespType::Integer(-1),
+            },
+            ("id" | "setname" | "getname" | "tracking" | "caching" | "getredir", _) => Error::custom(format!(
+                "ERR wrong number of arguments for 'client|{subcommand}' command"
+            ))
+            .into(),
+            _ => Error::custom(format!("ERR unknown subcommand '{}'. Try CLIENT HELP.", args[0])).into(),
+        }
+    }
+}
+
+/// `CLIENT TRACKING ON|OFF [REDIRECT id] [PREFIX prefix ...] [BCAST] [OPTIN] [OPTOUT] [NOLOOP]`
+fn tracking(args: &[String], session: &mut SessionState, ctx: &mut Context) -> RespType {
+    let enable = match args[0].to_lowercase().as_str() {
+        "on" => true,
+        "off" => false,
+        _ => return Error::Syntax.into(),
+    };
+
+    let mut options = TrackingOptions::default();
+    let mut rest = args[1..].iter();
+
+    while let Some(option) = rest.next() {
+        match option.to_lowercase().as_str() {
+            "redirect" => match rest.next().map(|id| id.parse::<u64>()) {
+                Some(Ok(id)) => options.redirect = Some(id),
+                Some(Err(_)) => return Error::NotAnInteger.into(),
+                None => return Error::Syntax.into(),
+            },
+            "prefix" => match rest.next() {
+                Some(prefix) => options.prefixes.push(prefix.to_string()),
+                None => return Error::Syntax.into(),
+            },
+            "bcast" => options.bcast = true,
+            "optin" => options.optin = true,
+            "optout" => options.optout = true,
+            "noloop" => options.noloop = true,
+            _ => return Error::Syntax.into(),
+        }
+    }
+
+    session.caching = None;
+
+    if !enable {
+        ctx.tracking.disable(session.id);
+        return RespType::ok();
+    }
+
+    if !options.bcast && !options.prefixes.is_empty() {
+        return Error::custom("ERR PREFIX option requires BCAST mode to be enabled").into();
+    }
+
+    if options.optin && options.optout {
+        return Error::custom("ERR You can't use OPTIN and OPTOUT at the same time").into();
+    }
+
+    if options.bcast && (options.optin || options.optout) {
+        return Error::custom("ERR OPTIN and OPTOUT are not compatible with BCAST").into();
+    }
+
+    if let Some(redirect) = options.redirect {
+        if redirect != session.id && !ctx.clients.contains_key(&redirect) {
+            return Error::custom("ERR The client ID you want redirect to does not exist").into();
+        }
+    }
+
+    ctx.tracking.enable(session.id, options);
+
+    RespType::ok()
+}
+
+/// `CLIENT CACHING YES|NO`, overrides the OPTIN/OPTOUT mode for the next command
+fn caching(value: &str, session: &mut SessionState, ctx: &Context) -> RespType {
+    let options = ctx.tracking.options(session.id);
+
+    match value.to_lowercase().as_str() {
+        "yes" if options.is_some_and(|options| options.optin) => session.caching = Some(true),
+        "no" if options.is_some_and(|options| options.optout) => session.caching = Some(false),
+        "yes" | "no" => {
+            return Error::custom(
+                "ERR CLIENT CACHING can be called only when the client is in tracking mode with OPTIN or OPTOUT mode enabled",
+            )
+            .into()
+        }
+        _ => return Error::Syntax.into(),
+    }
+
+    RespType::ok()
+}
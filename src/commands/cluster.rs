@@ -0,0 +1,397 @@
+use crate::resp::{errors::Error, types::RespType};
+use crate::utils::cluster::{self, ClusterNode, FailoverMode, NodeRole};
+use crate::utils::cluster_bus;
+use crate::utils::context::Context;
+use crate::utils::hash;
+
+use super::resp_command::{RESPCommand, RESPCommandName, RESPMinMaxArgs};
+
+/// `CLUSTER <subcommand> [arg ...]`, only known in cluster mode
+pub struct Cluster(pub Vec<String>);
+
+impl RESPCommandName for Cluster {
+    fn command_name(&self) -> &'static str {
+        "cluster"
+    }
+}
+
+impl RESPMinMaxArgs for Cluster {
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> usize {
+        usize::MAX
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for Cluster {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        if ctx.cluster.is_none() {
+            return Error::custom("ERR This instance has cluster support disabled").into();
+        }
+
+        match self.run(ctx) {
+            Ok(reply) => reply,
+            Err(err) => err.into(),
+        }
+    }
+}
+
+impl Cluster {
+    fn run(&self, ctx: &mut Context) -> Result<RespType, Error> {
+        let cluster = ctx.cluster.as_mut().expect("cluster mode");
+        let subcommand = self.0[0].to_lowercase();
+        let args = &self.0[1..];
+
+        let reply = match (subcommand.as_str(), args.len()) {
+            ("myid", 0) => RespType::bulk_string(&cluster.myself),
+            ("info", 0) => {
+                let myself = cluster.myself();
+                let my_epoch = match &myself.master_id {
+                    Some(master) => cluster.nodes.get(master).map_or(0, |master| master.config_epoch),
+                    None => myself.config_epoch,
+                };
+                let state = if cluster.is_ok() { "ok" } else { "fail" };
+                let (pfail, fail) = cluster.failing_slots();
+                let fields = [
+                    ("cluster_state", state.to_string()),
+                    ("cluster_slots_assigned", cluster.assigned_slots().to_string()),
+                    ("cluster_slots_ok", (cluster.assigned_slots() - pfail - fail).to_string()),
+                    ("cluster_slots_pfail", pfail.to_string()),
+                    ("cluster_slots_fail", fail.to_string()),
+                    ("cluster_known_nodes", cluster.nodes.len().to_string()),
+                    ("cluster_size", cluster.size().to_string()),
+                    ("cluster_current_epoch", cluster.current_epoch.to_string()),
+                    ("cluster_my_epoch", my_epoch.to_string()),
+                ];
+
+                RespType::bulk_string(fields.iter().map(|(name, value)| format!("{name}:{value}\r\n")).collect::<String>())
+            }
+            ("nodes", 0) => RespType::bulk_string(cluster.describe()),
+            ("slots", 0) => {
+                let mut slots = Vec::new();
+
+                for node in cluster.nodes.values() {
+                    for (start, end) in cluster.slot_ranges(&node.id) {
+                        let mut entry = vec![RespType::Integer(start as i64), RespType::Integer(end as i64)];
+
+                        entry.push(describe_endpoint(node));
+                        entry.extend(replicas_of(cluster, &node.id).map(describe_endpoint));
+                        slots.push((start, RespType::array(entry)));
+                    }
+                }
+
+                slots.sort_by_key(|(start, _)| *start);
+
+                RespType::array(slots.into_iter().map(|(_, entry)| entry).collect())
+            }
+            ("shards", 0) => {
+                let shards = cluster
+                    .nodes
+                    .values()
+                    .filter(|node| node.role == NodeRole::Master)
+                    .map(|master| {
+                        let slots = cluster
+                            .slot_ranges(&master.id)
+                            .into_iter()
+                            .flat_map(|(start, end)| [RespType::Integer(start as i64), RespType::Integer(end as i64)])
+                            .collect();
+                        let nodes = std::iter::once(master)
+                            .chain(replicas_of(cluster, &master.id))
+                            .map(|node| describe_shard_node(node, ctx.config.master_repl_offset, &cluster.myself))
+                            .collect();
+
+                        RespType::Map(vec![
+                            (RespType::bulk_string("slots"), RespType::array(slots)),
+                            (RespType::bulk_string("nodes"), RespType::array(nodes)),
+                        ])
+                    })
+                    .collect();
+
+                RespType::array(shards)
+            }
+            ("keyslot", 1) => RespType::Integer(hash::key_slot(&args[0]) as i64),
+            ("countkeysinslot", 1) => {
+                let slot = cluster::parse_slot(&args[0])?;
+
+                RespType::Integer(cluster.keys_in_slot(&ctx.store, slot).count() as i64)
+            }
+            ("getkeysinslot", 2) => {
+                let slot = cluster::parse_slot(&args[0])?;
+                let count = match args[1].parse::<i64>() {
+                    Ok(count) if count >= 0 => count as usize,
+                    _ => return Err(Error::custom("ERR Invalid number of keys")),
+                };
+
+                let keys = cluster.keys_in_slot(&ctx.store, slot).take(count).map(RespType::bulk_string).collect();
+
+                RespType::array(keys)
+            }
+            ("addslots" | "delslots", n) if n > 0 => {
+                let slots = args.iter().map(|slot| cluster::parse_slot(slot)).collect::<Result<Vec<_>, _>>()?;
+                let slots = unique(slots)?;
+
+                match subcommand.as_str() {
+                    "addslots" => cluster.add_slots(&slots)?,
+                    _ => cluster.del_slots(&slots)?,
+                }
+
+                RespType::ok()
+            }
+            ("addslotsrange" | "delslotsrange", n) if n > 0 && n % 2 == 0 => {
+                let mut slots = Vec::new();
+
+                for range in args.chunks(2) {
+                    let (start, end) = (cluster::parse_slot(&range[0])?, cluster::parse_slot(&range[1])?);
+
+                    if start > end {
+                        return Err(Error::custom(format!(
+                            "ERR start slot number {start} is greater than end slot number {end}"
+                        )));
+                    }
+
+                    slots.extend(start..=end);
+                }
+
+                let slots = unique(slots)?;
+
+                match subcommand.as_str() {
+                    "addslotsrange" => cluster.add_slots(&slots)?,
+                    _ => cluster.del_slots(&slots)?,
+                }
+
+                RespType::ok()
+            }
+            ("meet", 2 | 3) => {
+                let port = args[1].parse::<u16>().map_err(|_| Error::custom(format!("ERR Invalid base port specified: {}", args[1])))?;
+                let cport = match args.get(2) {
+                    Some(cport) => cport
+                        .parse::<u16>()
+                        .map_err(|_| Error::custom(format!("ERR Invalid bus port specified: {cport}")))?,
+                    None => port.checked_add(cluster::BUS_PORT_OFFSET).unwrap_or(0),
+                };
+
+                cluster.meet(&args[0], port, cport)?;
+
+                RespType::ok()
+            }
+            ("forget", 1) => {
+                cluster.forget(&args[0])?;
+
+                RespType::ok()
+            }
+            ("replicate", 1) => {
+                cluster.replicate(&args[0], ctx.store.iter().next().is_some())?;
+
+                RespType::ok()
+            }
+            ("failover", 0 | 1) => {
+                let mode = match args.first().map(|mode| mode.to_lowercase()).as_deref() {
+                    None => FailoverMode::Default,
+                    Some("force") => FailoverMode::Force,
+                    Some("takeover") => FailoverMode::Takeover,
+                    Some(_) => return Err(Error::Syntax),
+                };
+
+                cluster.failover(mode)?;
+
+                RespType::ok()
+            }
+            ("setslot", 2 | 3) => {
+                let slot = cluster::parse_slot(&args[0])?;
+
+                match (args[1].to_lowercase().as_str(), args.get(2)) {
+                    ("migrating", Some(id)) => cluster.set_slot_migrating(slot, id)?,
+                    ("importing", Some(id)) => cluster.set_slot_importing(slot, id)?,
+                    ("stable", None) => cluster.set_slot_stable(slot)?,
+                    ("node", Some(id)) => {
+                        let keys = cluster.keys_in_slot(&ctx.store, slot).count();
+
+                        cluster.set_slot_node(slot, id, keys)?
+                    }
+                    _ => {
+                        return Err(Error::custom(
+                            "ERR Invalid CLUSTER SETSLOT action or number of arguments. Try CLUSTER HELP",
+                        ))
+                    }
+                }
+
+                RespType::ok()
+            }
+            ("saveconfig", 0) => {
+                cluster_bus::save_config(ctx)
+                    .map_err(|err| Error::custom(format!("ERR error saving the cluster node config: {err}")))?;
+
+                RespType::ok()
+            }
+            (
+                "myid" | "info" | "nodes" | "slots" | "shards" | "keyslot" | "countkeysinslot" | "getkeysinslot"
+                | "addslots" | "delslots" | "addslotsrange" | "delslotsrange" | "meet" | "forget" | "replicate"
+                | "failover" | "setslot" | "saveconfig",
+                _,
+            ) => {
+                return Err(Error::custom(format!(
+                    "ERR wrong number of arguments for 'cluster|{subcommand}' command"
+                )))
+            }
+            _ => {
+                return Err(Error::custom(format!(
+                    "ERR unknown subcommand '{}'. Try CLUSTER HELP.",
+                    self.0[0]
+                )))
+            }
+        };
+
+        Ok(reply)
+    }
+}
+
+/// Rejects slots given more than once
+fn unique(slots: Vec<u16>) -> Result<Vec<u16>, Error> {
+    let mut seen = vec![false; hash::SLOT_COUNT as usize];
+
+    for slot in &slots {
+        if std::mem::replace(&mut seen[*slot as usize], true) {
+            return Err(Error::custom(format!("ERR Slot {slot} specified multiple times")));
+        }
+    }
+
+    Ok(slots)
+}
+
+fn replicas_of<'a>(cluster: &'a cluster::Cluster, id: &'a str) -> impl Iterator<Item = &'a ClusterNode> {
+    cluster.nodes.values().filter(move |node| node.master_id.as_deref() == Some(id))
+}
+
+fn describe_endpoint(node: &ClusterNode) -> RespType {
+    RespType::array(vec![
+        RespType::bulk_string(&node.host),
+        RespType::Integer(node.port as i64),
+        RespType::bulk_string(&node.id),
+    ])
+}
+
+fn describe_shard_node(node: &ClusterNode, offset: u64, myself: &str) -> RespType {
+    let role = match node.role {
+        NodeRole::Master => "master",
+        NodeRole::Replica => "replica",
+    };
+    // Other nodes announce theirs in their pings
+    let offset = if node.id == myself { offset } else { node.repl_offset };
+    let health = if node.fail || node.pfail { "fail" } else { "online" };
+
+    RespType::Map(vec![
+        (RespType::bulk_string("id"), RespType::bulk_string(&node.id)),
+        (RespType::bulk_string("port"), RespType::Integer(node.port as i64)),
+        (RespType::bulk_string("ip"), RespType::bulk_string(&node.host)),
+        (RespType::bulk_string("endpoint"), RespType::bulk_string(&node.host)),
+        (RespType::bulk_string("role"), RespType::bulk_string(role)),
+        (RespType::bulk_string("replication-offset"), RespType::Integer(offset as i64)),
+        (RespType::bulk_string("health"), RespType::bulk_string(health)),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{StoreValue, Value};
+
+    fn cluster_context() -> Context {
+        Context {
+            cluster: Some(cluster::Cluster::new("a".repeat(40), "127.0.0.1".to_string(), 30001)),
+            ..Default::default()
+        }
+    }
+
+    fn run(ctx: &mut Context, args: &[&str]) -> RespType {
+        Cluster(args.iter().map(|arg| arg.to_string()).collect()).execute(ctx)
+    }
+
+    fn info(ctx: &mut Context) -> String {
+        match run(ctx, &["info"]) {
+            RespType::BulkString { value, .. } => value,
+            reply => panic!("unexpected reply {reply:?}"),
+        }
+    }
+
+    #[test]
+    fn disabled_outside_cluster_mode() {
+        let mut ctx = Context::default();
+
+        assert_eq!(
+            run(&mut ctx, &["info"]),
+            Error::custom("ERR This instance has cluster support disabled").into()
+        );
+    }
+
+    #[test]
+    fn assigns_slots() {
+        let mut ctx = cluster_context();
+
+        assert!(info(&mut ctx).starts_with("cluster_state:fail\r\ncluster_slots_assigned:0\r\n"));
+        assert_eq!(run(&mut ctx, &["addslotsrange", "0", "16383"]), RespType::ok());
+        assert!(info(&mut ctx).starts_with("cluster_state:ok\r\ncluster_slots_assigned:16384\r\n"));
+        assert_eq!(
+            run(&mut ctx, &["addslots", "1"]),
+            Error::custom("ERR Slot 1 is already busy").into()
+        );
+        assert_eq!(
+            run(&mut ctx, &["delslots", "1", "1"]),
+            Error::custom("ERR Slot 1 specified multiple times").into()
+        );
+        assert_eq!(
+            run(&mut ctx, &["addslots", "16384"]),
+            Error::custom("ERR Invalid or out of range slot").into()
+        );
+        assert_eq!(run(&mut ctx, &["delslotsrange", "100", "16383"]), RespType::ok());
+
+        let node = RespType::array(vec![
+            RespType::bulk_string("127.0.0.1"),
+            RespType::Integer(30001),
+            RespType::bulk_string("a".repeat(40)),
+        ]);
+        assert_eq!(
+            run(&mut ctx, &["slots"]),
+            RespType::array(vec![RespType::array(vec![RespType::Integer(0), RespType::Integer(99), node])])
+        );
+        assert_eq!(
+            run(&mut ctx, &["nodes"]),
+            RespType::bulk_string(format!("{} 127.0.0.1:30001@40001 myself,master - 0 0 0 connected 0-99\n", "a".repeat(40)))
+        );
+    }
+
+    #[test]
+    fn counts_keys_in_slots() {
+        let mut ctx = cluster_context();
+
+        for key in ["{user}1", "{user}2", "other"] {
+            ctx.store.insert(key.to_string(), StoreValue::new(Value::String("v".to_string()), None));
+        }
+
+        let slot = hash::key_slot("user");
+
+        assert_eq!(run(&mut ctx, &["keyslot", "{user}1"]), RespType::Integer(slot as i64));
+        assert_eq!(run(&mut ctx, &["countkeysinslot", &slot.to_string()]), RespType::Integer(2));
+
+        let RespType::Array { values, .. } = run(&mut ctx, &["getkeysinslot", &slot.to_string(), "1"]) else {
+            panic!("expected an array");
+        };
+        assert_eq!(values.len(), 1);
+        assert_eq!(
+            run(&mut ctx, &["getkeysinslot", &slot.to_string(), "-1"]),
+            Error::custom("ERR Invalid number of keys").into()
+        );
+    }
+}
@@ -1,9 +1,26 @@
 use super::resp_command::RESPCommand;
+use super::Cluster;
+use super::Config;
 use super::Echo;
+use super::{Dump, Restore};
+use super::PExpireAt;
+use super::FlushDb;
 use super::Get;
 use super::Ping;
 use super::Set;
 use super::Info;
+use super::{BgRewriteAof, BgSave, LastSave, Save};
+use super::{Publish, Pubsub, SPublish};
+use super::Sentinel;
+use super::{Acl, Auth, Hello};
+use super::Client;
+use super::Migrate;
+use super::{ReplicaOf, Wait, WaitAof};
+use super::Unwatch;
+use super::{Eval, Fcall, Function, Script};
+use super::{BfAdd, BfExists, BfInfo, BfMadd, BfMexists, BfReserve};
+use super::{CmsIncrBy, CmsInfo, CmsInitByDim, CmsInitByProb, CmsMerge, CmsQuery};
+use super::{TsAdd, TsCreate, TsCreateRule, TsDeleteRule, TsGet, TsInfo, TsRange, TsRevRange};
 
 use crate::resp::errors::Error;
 use crate::resp::types::RespType;
@@ -11,6 +28,24 @@ use crate::resp::types::RespType;
 pub struct Command(String, Vec<String>);
 
 impl Command {
+    pub fn new(name: String, args: Vec<String>) -> Self {
+        Self(name, args)
+    }
+
+    /// Name of the command as sent by the client
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+
+    pub fn args(&self) -> &[String] {
+        &self.1
+    }
+
+    /// Name followed by the arguments, as sent by the client
+    pub fn parts(&self) -> Vec<String> {
+        std::iter::once(self.0.clone()).chain(self.1.iter().cloned()).collect()
+    }
+
     /// Returns the execute of this [`Command`].
     ///
     /// # Errors
@@ -48,6 +83,57 @@ impl Command {
 
                 Ok(Box::new(info))
             }
+            "flushdb" | "flushall" => Ok(Box::new(FlushDb(args))),
+            "config" => Ok(Box::new(Config(args))),
+            "cluster" => Ok(Box::new(Cluster(args))),
+            "pexpireat" => Ok(Box::new(PExpireAt(args))),
+            "dump" => Ok(Box::new(Dump(args))),
+            "restore" | "restore-asking" => Ok(Box::new(Restore(args))),
+            "bgrewriteaof" => Ok(Box::new(BgRewriteAof(args))),
+            "save" => Ok(Box::new(Save(args))),
+            "bgsave" => Ok(Box::new(BgSave(args))),
+            "lastsave" => Ok(Box::new(LastSave(args))),
+            "publish" => Ok(Box::new(Publish(args))),
+            "pubsub" => Ok(Box::new(Pubsub(args))),
+            "spublish" => Ok(Box::new(SPublish(args))),
+            "sentinel" => Ok(Box::new(Sentinel(args))),
+            "auth" => Ok(Box::new(Auth(args))),
+            "hello" => Ok(Box::new(Hello(args))),
+            "acl" => Ok(Box::new(Acl(args))),
+            "client" => Ok(Box::new(Client(args))),
+            "replicaof" | "slaveof" => Ok(Box::new(ReplicaOf(args))),
+            "wait" => Ok(Box::new(Wait(args))),
+            "waitaof" => Ok(Box::new(WaitAof(args))),
+            "migrate" => Ok(Box::new(Migrate(args))),
+            "unwatch" => Ok(Box::new(Unwatch(args))),
+            "eval" => Ok(Box::new(Eval::new(args, false, false))),
+            "evalsha" => Ok(Box::new(Eval::new(args, true, false))),
+            "eval_ro" => Ok(Box::new(Eval::new(args, false, true))),
+            "evalsha_ro" => Ok(Box::new(Eval::new(args, true, true))),
+            "script" => Ok(Box::new(Script(args))),
+            "fcall" => Ok(Box::new(Fcall::new(args, false))),
+            "fcall_ro" => Ok(Box::new(Fcall::new(args, true))),
+            "function" => Ok(Box::new(Function(args))),
+            "bf.reserve" => Ok(Box::new(BfReserve(args))),
+            "bf.add" => Ok(Box::new(BfAdd(args))),
+            "bf.madd" => Ok(Box::new(BfMadd(args))),
+            "bf.exists" => Ok(Box::new(BfExists(args))),
+            "bf.mexists" => Ok(Box::new(BfMexists(args))),
+            "bf.info" => Ok(Box::new(BfInfo(args))),
+            "cms.initbydim" => Ok(Box::new(CmsInitByDim(args))),
+            "cms.initbyprob" => Ok(Box::new(CmsInitByProb(args))),
+            "cms.incrby" => Ok(Box::new(CmsIncrBy(args))),
+            "cms.query" => Ok(Box::new(CmsQuery(args))),
+            "cms.merge" => Ok(Box::new(CmsMerge(args))),
+            "cms.info" => Ok(Box::new(CmsInfo(args))),
+            "ts.create" => Ok(Box::new(TsCreate(args))),
+            "ts.add" => Ok(Box::new(TsAdd(args))),
+            "ts.get" => Ok(Box::new(TsGet(args))),
+            "ts.range" => Ok(Box::new(TsRange(args))),
+            "ts.revrange" => Ok(Box::new(TsRevRange(args))),
+            "ts.createrule" => Ok(Box::new(TsCreateRule(args))),
+            "ts.deleterule" => Ok(Box::new(TsDeleteRule(args))),
+            "ts.info" => Ok(Box::new(TsInfo(args))),
             _ => Err(Error::UnknownCommand {
                 command: self.0.clone(),
             }),
@@ -99,9 +185,7 @@ impl TryFrom<RespType> for Command {
                     Err(Error::WrongType)
                 }
             }
-            _ => {
-                return Err(Error::WrongType);
-            }
+            _ => Err(Error::WrongType),
         }
     }
 }
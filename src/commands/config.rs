@@ -0,0 +1,162 @@
+use crate::resp::{errors::Error, types::RespType};
+use crate::utils::context::Context;
+use crate::utils::glob;
+
+use super::resp_command::{RESPCommand, RESPCommandName, RESPMinMaxArgs};
+
+/// `CONFIG GET pattern [pattern ...] | SET parameter value [parameter value ...]`
+pub struct Config(pub Vec<String>);
+
+impl RESPCommandName for Config {
+    fn command_name(&self) -> &'static str {
+        "config"
+    }
+}
+
+impl RESPMinMaxArgs for Config {
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> usize {
+        usize::MAX
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl Config {
+    fn get(&self, ctx: &Context) -> RespType {
+        let patterns: Vec<String> = self.0[1..].iter().map(|p| p.to_lowercase()).collect();
+
+        let entries = ctx
+            .config
+            .parameters()
+            .into_iter()
+            .filter(|(name, _)| patterns.iter().any(|pattern| glob::matches(pattern, name)))
+            .map(|(name, value)| (RespType::bulk_string(name), RespType::bulk_string(value)))
+            .collect();
+
+        RespType::Map(entries)
+    }
+
+    fn set(&self, ctx: &mut Context) -> RespType {
+        for pair in self.0[1..].chunks(2) {
+            let name = pair[0].to_lowercase();
+
+            if let Err(err) = ctx.config.set(&name, &pair[1]) {
+                return Error::custom(format!(
+                    "ERR CONFIG SET failed (possibly related to argument '{name}') - {err}"
+                ))
+                .into();
+            }
+
+            if let (Some(aof), "appendfsync") = (ctx.aof.as_mut(), name.as_str()) {
+                aof.set_fsync(ctx.config.appendfsync);
+            }
+
+            if name == "repl-backlog-size" {
+                ctx.replicas.resize_backlog(ctx.config.repl_backlog_size as usize);
+            }
+
+            if name == "appendonly" {
+                if let Err(err) = ctx.set_appendonly(ctx.config.appendonly) {
+                    ctx.config.appendonly = ctx.aof.is_some();
+
+                    return Error::custom(format!(
+                        "ERR CONFIG SET failed (possibly related to argument '{name}') - {err}"
+                    ))
+                    .into();
+                }
+            }
+        }
+
+        RespType::ok()
+    }
+}
+
+impl RESPCommand for Config {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        let subcommand = self.0[0].to_lowercase();
+        let args = self.0.len() - 1;
+
+        match subcommand.as_str() {
+            "get" if args > 0 => self.get(ctx),
+            "set" if args > 0 && self.0.len() % 2 == 1 => self.set(ctx),
+            "get" | "set" => Error::custom(format!(
+                "ERR wrong number of arguments for 'config|{subcommand}' command"
+            ))
+            .into(),
+            _ => Error::custom(format!(
+                "ERR unknown subcommand '{}'. Try CONFIG HELP.",
+                self.0[0]
+            ))
+            .into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn set_and_get_keyspace_events() {
+        let mut ctx = Context::default();
+
+        let response = Config(args(&["SET", "notify-keyspace-events", "KEA"])).execute(&mut ctx);
+        assert_eq!(response, RespType::ok());
+
+        let response = Config(args(&["GET", "notify-*"])).execute(&mut ctx);
+        assert_eq!(
+            response,
+            RespType::Map(vec![(
+                RespType::bulk_string("notify-keyspace-events"),
+                RespType::bulk_string("AKE"),
+            )])
+        );
+    }
+
+    #[test]
+    fn set_memory_with_unit() {
+        let mut ctx = Context::default();
+
+        let response = Config(args(&["SET", "auto-aof-rewrite-min-size", "1mb"])).execute(&mut ctx);
+        assert_eq!(response, RespType::ok());
+        assert_eq!(ctx.config.auto_aof_rewrite_min_size, 1024 * 1024);
+
+        let response = Config(args(&["SET", "auto-aof-rewrite-min-size", "1xb"])).execute(&mut ctx);
+        assert!(matches!(response, RespType::SimpleError(Error::Custom { .. })));
+    }
+
+    #[test]
+    fn set_invalid_value() {
+        let mut ctx = Context::default();
+
+        let response = Config(args(&["SET", "notify-keyspace-events", "Kq"])).execute(&mut ctx);
+
+        assert!(matches!(response, RespType::SimpleError(Error::Custom { .. })));
+    }
+
+    #[test]
+    fn set_unknown_parameter() {
+        let mut ctx = Context::default();
+
+        let response = Config(args(&["SET", "foo", "bar"])).execute(&mut ctx);
+
+        assert!(matches!(response, RespType::SimpleError(Error::Custom { .. })));
+    }
+}
@@ -0,0 +1,494 @@
+use crate::models::{CountMinSketch, StoreValue, Value};
+use crate::resp::errors::Error;
+use crate::resp::types::RespType;
+use crate::utils::context::Context;
+
+use super::resp_command::{RESPCommand, RESPCommandName, RESPMinMaxArgs};
+
+/// Looks up the sketch stored at `key`, `WRONGTYPE` when the key holds another type
+fn get_sketch<'a>(ctx: &'a mut Context, key: &str) -> Result<&'a mut CountMinSketch, Error> {
+    ctx.remove_if_expired(key);
+
+    match ctx.store.get_mut(key) {
+        Some(StoreValue {
+            data: Value::CountMinSketch(sketch),
+            ..
+        }) => Ok(sketch),
+        Some(_) => Err(Error::WrongType),
+        None => Err(Error::custom("CMS: key does not exist")),
+    }
+}
+
+fn create_sketch(ctx: &mut Context, key: &str, sketch: Result<CountMinSketch, &'static str>) -> RespType {
+    let sketch = match sketch {
+        Ok(sketch) => sketch,
+        Err(err) => return Error::custom(err).into(),
+    };
+
+    ctx.remove_if_expired(key);
+
+    if ctx.store.contains_key(key) {
+        return Error::custom("CMS: key already exists").into();
+    }
+
+    ctx.store
+        .insert(key.to_string(), Value::CountMinSketch(sketch).into());
+
+    RespType::ok()
+}
+
+pub struct CmsInitByDim(pub Vec<String>);
+
+impl RESPCommandName for CmsInitByDim {
+    fn command_name(&self) -> &'static str {
+        "cms.initbydim"
+    }
+}
+
+impl RESPMinMaxArgs for CmsInitByDim {
+    fn min_args(&self) -> usize {
+        3
+    }
+
+    fn max_args(&self) -> usize {
+        3
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for CmsInitByDim {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        let width = self.0[1].parse::<u32>().ok().filter(|w| *w > 0);
+        let depth = self.0[2].parse::<u32>().ok().filter(|d| *d > 0);
+
+        match (width, depth) {
+            (Some(width), Some(depth)) => {
+                create_sketch(ctx, &self.0[0], CountMinSketch::new(width, depth))
+            }
+            (None, _) => Error::custom("CMS: invalid width").into(),
+            (_, None) => Error::custom("CMS: invalid depth").into(),
+        }
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.0.iter().take(1).cloned().collect()
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+}
+
+pub struct CmsInitByProb(pub Vec<String>);
+
+impl RESPCommandName for CmsInitByProb {
+    fn command_name(&self) -> &'static str {
+        "cms.initbyprob"
+    }
+}
+
+impl RESPMinMaxArgs for CmsInitByProb {
+    fn min_args(&self) -> usize {
+        3
+    }
+
+    fn max_args(&self) -> usize {
+        3
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for CmsInitByProb {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        let in_range = |v: &f64| *v > 0.0 && *v < 1.0;
+        let error = self.0[1].parse::<f64>().ok().filter(in_range);
+        let probability = self.0[2].parse::<f64>().ok().filter(in_range);
+
+        match (error, probability) {
+            (Some(error), Some(probability)) => create_sketch(
+                ctx,
+                &self.0[0],
+                CountMinSketch::from_probability(error, probability),
+            ),
+            (None, _) => Error::custom("CMS: invalid overestimation value").into(),
+            (_, None) => Error::custom("CMS: invalid prob value").into(),
+        }
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.0.iter().take(1).cloned().collect()
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+}
+
+pub struct CmsIncrBy(pub Vec<String>);
+
+impl RESPCommandName for CmsIncrBy {
+    fn command_name(&self) -> &'static str {
+        "cms.incrby"
+    }
+}
+
+impl RESPMinMaxArgs for CmsIncrBy {
+    fn min_args(&self) -> usize {
+        3
+    }
+
+    fn max_args(&self) -> usize {
+        usize::MAX
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.args_len() >= self.min_args() && self.args_len() % 2 == 1
+    }
+}
+
+impl RESPCommand for CmsIncrBy {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        let mut increments = Vec::new();
+
+        for pair in self.0[1..].chunks(2) {
+            match pair[1].parse::<u32>() {
+                Ok(by) => increments.push((pair[0].as_bytes(), by)),
+                Err(_) => return Error::custom("CMS: Cannot parse number").into(),
+            }
+        }
+
+        let sketch = match get_sketch(ctx, &self.0[0]) {
+            Ok(sketch) => sketch,
+            Err(err) => return err.into(),
+        };
+
+        let values = increments
+            .into_iter()
+            .map(|(item, by)| RespType::Integer(sketch.increment(item, by) as i64))
+            .collect();
+        ctx.store.touch(&self.0[0]);
+
+        RespType::array(values)
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.0.iter().take(1).cloned().collect()
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+}
+
+pub struct CmsQuery(pub Vec<String>);
+
+impl RESPCommandName for CmsQuery {
+    fn command_name(&self) -> &'static str {
+        "cms.query"
+    }
+}
+
+impl RESPMinMaxArgs for CmsQuery {
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn max_args(&self) -> usize {
+        usize::MAX
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for CmsQuery {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        let sketch = match get_sketch(ctx, &self.0[0]) {
+            Ok(sketch) => sketch,
+            Err(err) => return err.into(),
+        };
+
+        let values = self.0[1..]
+            .iter()
+            .map(|item| RespType::Integer(sketch.query(item.as_bytes()) as i64))
+            .collect();
+
+        RespType::array(values)
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.0.iter().take(1).cloned().collect()
+    }
+}
+
+pub struct CmsMerge(pub Vec<String>);
+
+impl RESPCommandName for CmsMerge {
+    fn command_name(&self) -> &'static str {
+        "cms.merge"
+    }
+}
+
+impl RESPMinMaxArgs for CmsMerge {
+    fn min_args(&self) -> usize {
+        3
+    }
+
+    fn max_args(&self) -> usize {
+        usize::MAX
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl CmsMerge {
+    /// Source keys and their weights, `None` on a malformed argument list
+    fn sources(&self) -> Option<Vec<(String, i64)>> {
+        let count = self.0.get(1)?.parse::<usize>().ok().filter(|c| *c > 0)?;
+        let end = 2usize.checked_add(count)?;
+        let keys = self.0.get(2..end)?;
+        let rest = &self.0[end..];
+
+        let weights = match rest.split_first() {
+            None => vec![1; count],
+            Some((option, weights)) if option.eq_ignore_ascii_case("weights") => {
+                if weights.len() != count {
+                    return None;
+                }
+
+                weights
+                    .iter()
+                    .map(|w| w.parse::<i64>().ok())
+                    .collect::<Option<Vec<i64>>>()?
+            }
+            Some(_) => return None,
+        };
+
+        Some(keys.iter().cloned().zip(weights).collect())
+    }
+}
+
+impl RESPCommand for CmsMerge {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        let sources = match self.sources() {
+            Some(sources) => sources,
+            None => return Error::Syntax.into(),
+        };
+
+        let mut snapshots = Vec::with_capacity(sources.len());
+
+        for (key, weight) in &sources {
+            match get_sketch(ctx, key) {
+                Ok(sketch) => snapshots.push((sketch.clone(), *weight)),
+                Err(err) => return err.into(),
+            }
+        }
+
+        let dest = match get_sketch(ctx, &self.0[0]) {
+            Ok(dest) => dest,
+            Err(err) => return err.into(),
+        };
+
+        if snapshots.iter().any(|(sketch, _)| !dest.same_dimensions(sketch)) {
+            return Error::custom("CMS: width/depth is not equal").into();
+        }
+
+        let sources: Vec<(&CountMinSketch, i64)> =
+            snapshots.iter().map(|(sketch, weight)| (sketch, *weight)).collect();
+        if let Err(err) = dest.merge(&sources) {
+            return Error::custom(err).into();
+        }
+
+        ctx.store.touch(&self.0[0]);
+
+        RespType::ok()
+    }
+
+    fn keys(&self) -> Vec<String> {
+        let sources = self.sources().unwrap_or_default();
+
+        self.0.iter().take(1).cloned().chain(sources.into_iter().map(|(key, _)| key)).collect()
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+}
+
+pub struct CmsInfo(pub Vec<String>);
+
+impl RESPCommandName for CmsInfo {
+    fn command_name(&self) -> &'static str {
+        "cms.info"
+    }
+}
+
+impl RESPMinMaxArgs for CmsInfo {
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> usize {
+        1
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for CmsInfo {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        match get_sketch(ctx, &self.0[0]) {
+            Ok(sketch) => RespType::array(vec![
+                RespType::simple_string("width"),
+                RespType::Integer(sketch.width as i64),
+                RespType::simple_string("depth"),
+                RespType::Integer(sketch.depth as i64),
+                RespType::simple_string("count"),
+                RespType::Integer(sketch.count as i64),
+            ]),
+            Err(err) => err.into(),
+        }
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.0.iter().take(1).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn init_incr_and_query() {
+        let mut ctx = Context::default();
+
+        let response = CmsInitByDim(args(&["cms", "100", "5"])).execute(&mut ctx);
+        assert_eq!(response, RespType::ok());
+
+        let response = CmsIncrBy(args(&["cms", "foo", "3", "bar", "1"])).execute(&mut ctx);
+        assert_eq!(
+            response,
+            RespType::array(vec![RespType::Integer(3), RespType::Integer(1)])
+        );
+
+        let response = CmsQuery(args(&["cms", "foo", "baz"])).execute(&mut ctx);
+        assert_eq!(
+            response,
+            RespType::array(vec![RespType::Integer(3), RespType::Integer(0)])
+        );
+    }
+
+    #[test]
+    fn init_existing_key() {
+        let mut ctx = Context::default();
+
+        CmsInitByProb(args(&["cms", "0.01", "0.01"])).execute(&mut ctx);
+        let response = CmsInitByProb(args(&["cms", "0.01", "0.01"])).execute(&mut ctx);
+
+        assert_eq!(response, Error::custom("CMS: key already exists").into());
+    }
+
+    #[test]
+    fn incrby_missing_key() {
+        let mut ctx = Context::default();
+
+        let response = CmsIncrBy(args(&["cms", "foo", "1"])).execute(&mut ctx);
+
+        assert_eq!(response, Error::custom("CMS: key does not exist").into());
+    }
+
+    #[test]
+    fn merge_with_weights() {
+        let mut ctx = Context::default();
+
+        for key in ["a", "b", "dest"] {
+            CmsInitByDim(args(&[key, "50", "4"])).execute(&mut ctx);
+        }
+
+        CmsIncrBy(args(&["a", "foo", "2"])).execute(&mut ctx);
+        CmsIncrBy(args(&["b", "foo", "1"])).execute(&mut ctx);
+
+        let response = CmsMerge(args(&["dest", "2", "a", "b", "WEIGHTS", "1", "3"])).execute(&mut ctx);
+        assert_eq!(response, RespType::ok());
+
+        let response = CmsQuery(args(&["dest", "foo"])).execute(&mut ctx);
+        assert_eq!(response, RespType::array(vec![RespType::Integer(5)]));
+
+        let response = CmsMerge(args(&["dest", "18446744073709551615", "a"])).execute(&mut ctx);
+        assert_eq!(response, Error::Syntax.into());
+    }
+
+    #[test]
+    fn merge_different_dimensions() {
+        let mut ctx = Context::default();
+
+        CmsInitByDim(args(&["a", "10", "4"])).execute(&mut ctx);
+        CmsInitByDim(args(&["dest", "50", "4"])).execute(&mut ctx);
+
+        let response = CmsMerge(args(&["dest", "1", "a"])).execute(&mut ctx);
+
+        assert_eq!(response, Error::custom("CMS: width/depth is not equal").into());
+    }
+}
@@ -0,0 +1,340 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::models::StoreValue;
+use crate::resp::types::{bytes_to_string, string_to_bytes};
+use crate::resp::{errors::Error, types::RespType};
+use crate::utils::context::Context;
+use crate::utils::notifications::KeyspaceEvents;
+use crate::utils::rdb;
+
+use super::resp_command::{RESPCommand, RESPCommandName, RESPMinMaxArgs};
+
+/// `DUMP key`
+pub struct Dump(pub Vec<String>);
+
+impl RESPCommandName for Dump {
+    fn command_name(&self) -> &'static str {
+        "dump"
+    }
+}
+
+impl RESPMinMaxArgs for Dump {
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> usize {
+        1
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for Dump {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        let key = &self.0[0];
+
+        ctx.remove_if_expired(key);
+
+        match ctx.store.get(key) {
+            Some(value) => RespType::bulk_string(bytes_to_string(&rdb::dump(&value.data))),
+            None => RespType::Null,
+        }
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.0.iter().take(1).cloned().collect()
+    }
+}
+
+/// `RESTORE key ttl serialized-value [REPLACE] [ABSTTL] [IDLETIME seconds] [FREQ frequency]`
+pub struct Restore(pub Vec<String>);
+
+impl RESPCommandName for Restore {
+    fn command_name(&self) -> &'static str {
+        "restore"
+    }
+}
+
+impl RESPMinMaxArgs for Restore {
+    fn min_args(&self) -> usize {
+        3
+    }
+
+    fn max_args(&self) -> usize {
+        8
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for Restore {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        let key = &self.0[0];
+        let mut replace = false;
+        let mut absttl = false;
+        let mut idletime = false;
+        let mut freq = false;
+        let mut args = self.0[3..].iter();
+
+        // The access time and frequency only drive eviction, which keys don't
+        // track here, so both are validated and dropped
+        while let Some(arg) = args.next() {
+            match arg.to_lowercase().as_str() {
+                "replace" => replace = true,
+                "absttl" => absttl = true,
+                "idletime" if !freq => {
+                    match args.next().map(|value| value.parse::<i64>()) {
+                        Some(Ok(seconds)) if seconds >= 0 => idletime = true,
+                        Some(Ok(_)) => {
+                            return Error::Custom {
+                                message: "ERR Invalid IDLETIME value, must be >= 0".to_string(),
+                            }
+                            .into()
+                        }
+                        Some(Err(_)) => return Error::NotAnInteger.into(),
+                        None => return Error::Syntax.into(),
+                    };
+                }
+                "freq" if !idletime => {
+                    match args.next().map(|value| value.parse::<i64>()) {
+                        Some(Ok(frequency)) if (0..=255).contains(&frequency) => freq = true,
+                        Some(Ok(_)) => {
+                            return Error::Custom {
+                                message: "ERR Invalid FREQ value, must be >= 0 and <= 255".to_string(),
+                            }
+                            .into()
+                        }
+                        Some(Err(_)) => return Error::NotAnInteger.into(),
+                        None => return Error::Syntax.into(),
+                    };
+                }
+                _ => return Error::Syntax.into(),
+            }
+        }
+
+        let ttl = match self.0[1].parse::<i64>() {
+            Ok(ttl) if ttl >= 0 => ttl as u64,
+            Ok(_) => {
+                return Error::Custom {
+                    message: "ERR Invalid TTL value, must be >= 0".to_string(),
+                }
+                .into()
+            }
+            Err(_) => return Error::NotAnInteger.into(),
+        };
+
+        ctx.remove_if_expired(key);
+
+        if !replace && ctx.store.contains_key(key) {
+            return Error::Custom {
+                message: "BUSYKEY Target key name already exists.".to_string(),
+            }
+            .into();
+        }
+
+        let payload = string_to_bytes(&self.0[2]);
+
+        if !rdb::verify_dump(&payload) {
+            return Error::Custom {
+                message: "ERR DUMP payload version or checksum are wrong".to_string(),
+            }
+            .into();
+        }
+
+        let data = match rdb::read_dump(&payload) {
+            Ok(data) => data,
+            Err(_) => {
+                return Error::Custom {
+                    message: "ERR Bad data format".to_string(),
+                }
+                .into()
+            }
+        };
+
+        let expires_at = match (ttl, absttl) {
+            (0, _) => None,
+            (ttl, true) => Some(UNIX_EPOCH + Duration::from_millis(ttl)),
+            (ttl, false) => Some(SystemTime::now() + Duration::from_millis(ttl)),
+        };
+
+        // Already expired, the key is only deleted when it gets replaced
+        if expires_at.is_some_and(|at| at <= SystemTime::now()) {
+            if ctx.store.remove(key).is_some() {
+                ctx.notify(KeyspaceEvents::GENERIC, "del", key);
+            }
+
+            return RespType::SimpleString {
+                value: "OK".to_string(),
+            };
+        }
+
+        let mut value = StoreValue::new(data, None);
+
+        if let Some(at) = expires_at {
+            value.set_expires_at(at);
+        }
+
+        ctx.store.insert(key.to_string(), value);
+        ctx.notify(KeyspaceEvents::GENERIC, "restore", key);
+
+        if expires_at.is_some() {
+            ctx.notify(KeyspaceEvents::GENERIC, "expire", key);
+        }
+
+        RespType::SimpleString {
+            value: "OK".to_string(),
+        }
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.0.iter().take(1).cloned().collect()
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+
+    /// Relative ttls are persisted as absolute ones, and a payload that
+    /// expired on arrival as the deletion of the key it replaced
+    fn propagate(&self, ctx: &Context) -> Option<Vec<Vec<String>>> {
+        let key = &self.0[0];
+
+        let Some(value) = ctx.store.get(key) else {
+            return Some(vec![vec!["PEXPIREAT".to_string(), key.to_string(), "0".to_string()]]);
+        };
+
+        let ttl = match value.expires_at() {
+            Some(at) => at.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis().to_string(),
+            None => "0".to_string(),
+        };
+
+        Some(vec![vec![
+            "RESTORE".to_string(),
+            key.to_string(),
+            ttl,
+            self.0[2].to_string(),
+            "REPLACE".to_string(),
+            "ABSTTL".to_string(),
+        ]])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::models::Value;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    fn dump(ctx: &mut Context, key: &str) -> String {
+        match Dump(args(&[key])).execute(ctx) {
+            RespType::BulkString { value, .. } => value,
+            response => panic!("unexpected response {response:?}"),
+        }
+    }
+
+    #[test]
+    fn dump_and_restore_redis_payload() {
+        let mut ctx = Context::default();
+
+        // `DUMP mykey` of the integer 10 from the Redis documentation
+        let payload = b"\x00\xc0\n\t\x00\xbem\x06\x89Z(\x00\n";
+
+        let response = Restore(vec!["key".to_string(), "0".to_string(), bytes_to_string(payload)]).execute(&mut ctx);
+        assert_eq!(response.to_string(), "+OK\r\n");
+        assert_eq!(ctx.store.get("key").unwrap().data, Value::String("10".to_string()));
+
+        let dumped = string_to_bytes(&dump(&mut ctx, "key"));
+        assert_eq!(dumped[..5], [0x00, 0xc0, 0x0a, 11, 0]);
+        assert_eq!(Dump(args(&["missing"])).execute(&mut ctx), RespType::Null);
+    }
+
+    #[test]
+    fn restore_round_trip() {
+        let mut ctx = Context::default();
+        let list: VecDeque<String> = ["a", "b", "1"].iter().map(|item| item.to_string()).collect();
+        ctx.store.insert("list".to_string(), StoreValue::new(Value::List(list.clone()), None));
+
+        let payload = dump(&mut ctx, "list");
+
+        let response = Restore(vec!["list".to_string(), "0".to_string(), payload.clone()]).execute(&mut ctx);
+        assert_eq!(response.to_string(), "-BUSYKEY Target key name already exists.\r\n");
+
+        let mut restore = Restore(vec!["copy".to_string(), "5000".to_string(), payload]);
+        assert_eq!(restore.execute(&mut ctx).to_string(), "+OK\r\n");
+        assert_eq!(ctx.store.get("copy").unwrap().data, Value::List(list));
+
+        let propagated = restore.propagate(&ctx).unwrap();
+        assert_eq!(propagated[0][..2], ["RESTORE", "copy"]);
+        assert_eq!(propagated[0][4..], ["REPLACE", "ABSTTL"]);
+        assert!(propagated[0][2].parse::<u128>().unwrap() > 5000);
+    }
+
+    #[test]
+    fn restore_options() {
+        let mut ctx = Context::default();
+        ctx.store.insert("key".to_string(), StoreValue::from("old"));
+        let payload = bytes_to_string(&rdb::dump(&Value::String("new".to_string())));
+
+        let restore = |ctx: &mut Context, extra: &[&str]| {
+            let mut arguments = vec!["key".to_string(), "0".to_string(), payload.clone()];
+            arguments.extend(args(extra));
+            Restore(arguments).execute(ctx).to_string()
+        };
+
+        assert_eq!(restore(&mut ctx, &["IDLETIME", "-1"]), "-ERR Invalid IDLETIME value, must be >= 0\r\n");
+        assert_eq!(restore(&mut ctx, &["FREQ", "256"]), "-ERR Invalid FREQ value, must be >= 0 and <= 255\r\n");
+        assert_eq!(restore(&mut ctx, &["IDLETIME", "1", "FREQ", "1"]), "-ERR syntax error\r\n");
+        assert_eq!(restore(&mut ctx, &["REPLACE", "IDLETIME", "100"]), "+OK\r\n");
+        assert_eq!(ctx.store.get("key").unwrap().data, Value::String("new".to_string()));
+
+        let mut expired = Restore(args(&["key", "1000", &payload, "REPLACE", "ABSTTL"]));
+        assert_eq!(expired.execute(&mut ctx).to_string(), "+OK\r\n");
+        assert!(!ctx.store.contains_key("key"));
+        assert_eq!(expired.propagate(&ctx), Some(vec![args(&["PEXPIREAT", "key", "0"])]));
+    }
+
+    #[test]
+    fn rejects_bad_payloads() {
+        let mut ctx = Context::default();
+        let mut payload = rdb::dump(&Value::String("value".to_string()));
+
+        let last = payload.len() - 1;
+        payload[last] ^= 1;
+        let response = Restore(vec!["key".to_string(), "0".to_string(), bytes_to_string(&payload)]).execute(&mut ctx);
+        assert_eq!(response.to_string(), "-ERR DUMP payload version or checksum are wrong\r\n");
+
+        let mut payload = vec![0, 10, b'x'];
+        payload.extend(11u16.to_le_bytes());
+        let checksum = crate::utils::hash::crc64(0, &payload);
+        payload.extend(checksum.to_le_bytes());
+        let response = Restore(vec!["key".to_string(), "0".to_string(), bytes_to_string(&payload)]).execute(&mut ctx);
+        assert_eq!(response.to_string(), "-ERR Bad data format\r\n");
+
+        let response = Restore(args(&["key", "-1", "x"])).execute(&mut ctx);
+        assert_eq!(response.to_string(), "-ERR Invalid TTL value, must be >= 0\r\n");
+    }
+}
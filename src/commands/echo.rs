@@ -35,7 +35,7 @@ impl RESPCommand for Echo {
             .into();
         }
 
-        let message = self.0.get(0);
+        let message = self.0.first();
 
         if message.is_none() {
             return Error::WrongNumberOfArguments {
@@ -46,11 +46,7 @@ impl RESPCommand for Echo {
 
         let val = message.unwrap();
 
-        RespType::BulkString {
-            len: val.len(),
-            value: val.to_string(),
-        }
-        .into()
+        RespType::bulk_string(val)
     }
 }
 
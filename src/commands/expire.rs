@@ -0,0 +1,125 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::resp::{errors::Error, types::RespType};
+use crate::utils::context::Context;
+use crate::utils::notifications::KeyspaceEvents;
+
+use super::resp_command::{RESPCommand, RESPCommandName, RESPMinMaxArgs};
+
+/// `PEXPIREAT key unix-time-milliseconds`
+pub struct PExpireAt(pub Vec<String>);
+
+impl RESPCommandName for PExpireAt {
+    fn command_name(&self) -> &'static str {
+        "pexpireat"
+    }
+}
+
+impl RESPMinMaxArgs for PExpireAt {
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn max_args(&self) -> usize {
+        2
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for PExpireAt {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        let key = &self.0[0];
+        let at = match self.0[1].parse::<u64>() {
+            Ok(millis) => UNIX_EPOCH + Duration::from_millis(millis),
+            Err(_) => return Error::NotAnInteger.into(),
+        };
+
+        ctx.remove_if_expired(key);
+
+        if !ctx.store.contains_key(key) {
+            return RespType::Integer(0);
+        }
+
+        if at <= SystemTime::now() {
+            ctx.store.remove(key);
+            ctx.notify(KeyspaceEvents::GENERIC, "del", key);
+
+            return RespType::Integer(1);
+        }
+
+        if let Some(value) = ctx.store.get_mut(key) {
+            value.set_expires_at(at);
+        }
+
+        ctx.store.touch(key);
+        ctx.notify(KeyspaceEvents::GENERIC, "expire", key);
+
+        RespType::Integer(1)
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.0.iter().take(1).cloned().collect()
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::StoreValue;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    fn unix_millis(time: SystemTime) -> String {
+        time.duration_since(UNIX_EPOCH).unwrap().as_millis().to_string()
+    }
+
+    #[test]
+    fn sets_absolute_expiry() {
+        let mut ctx = Context::default();
+        let at = SystemTime::now() + Duration::from_secs(60);
+
+        ctx.store.insert("key".to_string(), StoreValue::from("value"));
+
+        let response = PExpireAt(args(&["key", &unix_millis(at)])).execute(&mut ctx);
+        assert_eq!(response, RespType::Integer(1));
+
+        let expires_at = ctx.store.get("key").unwrap().expires_at().unwrap();
+        assert!(expires_at.duration_since(at).unwrap_or_else(|err| err.duration()) < Duration::from_millis(1));
+    }
+
+    #[test]
+    fn past_time_deletes_key() {
+        let mut ctx = Context::default();
+
+        ctx.store.insert("key".to_string(), StoreValue::from("value"));
+
+        let response = PExpireAt(args(&["key", "1000"])).execute(&mut ctx);
+        assert_eq!(response, RespType::Integer(1));
+        assert!(!ctx.store.contains_key("key"));
+    }
+
+    #[test]
+    fn missing_key() {
+        let mut ctx = Context::default();
+
+        let response = PExpireAt(args(&["key", "1000"])).execute(&mut ctx);
+
+        assert_eq!(response, RespType::Integer(0));
+    }
+}
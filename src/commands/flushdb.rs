@@ -0,0 +1,80 @@
+use crate::resp::{errors::Error, types::RespType};
+use crate::utils::context::Context;
+
+use super::resp_command::{RESPCommand, RESPCommandName, RESPMinMaxArgs};
+
+/// `FLUSHDB`/`FLUSHALL`, there is a single database so both behave the same
+pub struct FlushDb(pub Vec<String>);
+
+impl RESPCommandName for FlushDb {
+    fn command_name(&self) -> &'static str {
+        "flushdb"
+    }
+}
+
+impl RESPMinMaxArgs for FlushDb {
+    fn min_args(&self) -> usize {
+        0
+    }
+
+    fn max_args(&self) -> usize {
+        1
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for FlushDb {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        if let Some(mode) = self.0.first() {
+            if !mode.eq_ignore_ascii_case("sync") && !mode.eq_ignore_ascii_case("async") {
+                return Error::Syntax.into();
+            }
+        }
+
+        ctx.store.clear();
+
+        RespType::ok()
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::StoreValue;
+
+    #[test]
+    fn removes_every_key() {
+        let mut ctx = Context::default();
+        ctx.store.insert("a".to_string(), StoreValue::from("1"));
+        ctx.store.insert("b".to_string(), StoreValue::from("2"));
+
+        assert_eq!(FlushDb(vec![]).execute(&mut ctx), RespType::ok());
+        assert!(!ctx.store.contains_key("a"));
+        assert!(!ctx.store.contains_key("b"));
+    }
+
+    #[test]
+    fn validates_mode() {
+        let mut ctx = Context::default();
+
+        assert_eq!(FlushDb(vec!["ASYNC".to_string()]).execute(&mut ctx), RespType::ok());
+        assert_eq!(
+            FlushDb(vec!["later".to_string()]).execute(&mut ctx),
+            RespType::SimpleError(Error::Syntax)
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use crate::resp::{errors::Error, types::RespType};
 use crate::utils::context::Context;
+use crate::utils::notifications::KeyspaceEvents;
 
 use super::resp_command::{RESPCommand, RESPCommandName, RESPMinMaxArgs};
 
@@ -27,8 +28,6 @@ impl RESPMinMaxArgs for Get {
 
 impl RESPCommand for Get {
     fn execute(&mut self, ctx: &mut Context) -> RespType {
-        let store = &mut ctx.store;
-
         if self.is_invalid() {
             return Error::WrongNumberOfArguments {
                 command: self.command_name().to_string(),
@@ -36,7 +35,7 @@ impl RESPCommand for Get {
             .into();
         }
 
-        let key = self.0.get(0);
+        let key = self.0.first();
 
         if key.is_none() {
             return Error::WrongNumberOfArguments {
@@ -47,27 +46,27 @@ impl RESPCommand for Get {
 
         let key = key.unwrap();
 
+        ctx.remove_if_expired(key);
 
-        let store_value = store.get(key.as_str());
+        let store_value = ctx.store.get(key.as_str());
 
         if store_value.is_none() {
+            ctx.notify(KeyspaceEvents::KEY_MISS, "keymiss", key);
             return RespType::Null;
         }
 
         let store_value = store_value.unwrap();
-        let value = store_value.data.clone();
-
-        if store_value.is_expired() {
-            store.remove(key);
-            return RespType::Null;
-        }
 
-        let bulk_string = RespType::BulkString {
-            len: value.len(),
-            value: value.to_string(),
+        let value = match store_value.data.as_string() {
+            Some(value) => value,
+            None => return Error::WrongType.into(),
         };
 
-        return bulk_string;
+        RespType::bulk_string(value)
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.0.iter().take(1).cloned().collect()
     }
 }
 
@@ -75,6 +74,7 @@ impl RESPCommand for Get {
 mod tests {
     use super::*;
     use crate::models::StoreValue;
+    use crate::utils::pubsub::Subscription;
     use crate::utils::context::Context;
 
     #[test]
@@ -143,7 +143,7 @@ mod tests {
         let expire_time = Some(std::time::Duration::from_secs(100));
 
         let store_value = StoreValue {
-            data: "value".to_string(),
+            data: "value".into(),
             created_at,
             expire_time,
         };
@@ -160,4 +160,26 @@ mod tests {
 
         assert_eq!(result, RespType::Null);
     }
+
+    #[test]
+    fn lazy_expiry_emits_expired_event() {
+        let mut ctx = Context::default();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut value = StoreValue::new("value", Some(std::time::Duration::from_secs(1)));
+        value.created_at = std::time::SystemTime::now() - std::time::Duration::from_secs(10);
+
+        ctx.config.notify_keyspace_events = "Ex".parse().unwrap();
+        ctx.pubsub.subscribe(Subscription::Channel, "__keyevent@0__:expired", 1, &tx);
+        ctx.store.insert("key".to_string(), value);
+
+        assert_eq!(Get(vec!["key".to_string()]).execute(&mut ctx), RespType::Null);
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            RespType::Push(vec![
+                RespType::bulk_string("message"),
+                RespType::bulk_string("__keyevent@0__:expired"),
+                RespType::bulk_string("key"),
+            ])
+        );
+    }
 }
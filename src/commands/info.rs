@@ -1,3 +1,5 @@
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
 use crate::resp::{errors::Error, types::RespType};
 use crate::utils::context::Context;
 
@@ -13,7 +15,7 @@ impl RESPCommandName for Info {
 
 impl RESPMinMaxArgs for Info {
     fn min_args(&self) -> usize {
-        1
+        0
     }
 
     fn max_args(&self) -> usize {
@@ -25,6 +27,176 @@ impl RESPMinMaxArgs for Info {
     }
 }
 
+fn server(ctx: &Context) -> String {
+    let mode = if ctx.sentinel.is_some() { "sentinel" } else { "standalone" };
+    let fields = [
+        ("redis_mode", mode.to_string()),
+        ("run_id", ctx.run_id.to_string()),
+        ("tcp_port", ctx.config.port.to_string()),
+    ];
+
+    let mut info = String::from("# Server\r\n");
+
+    for (name, value) in fields {
+        info.push_str(&format!("{name}:{value}\r\n"));
+    }
+
+    info
+}
+
+fn cluster(ctx: &Context) -> String {
+    format!("# Cluster\r\ncluster_enabled:{}\r\n", ctx.cluster.is_some() as u8)
+}
+
+/// Masters monitored in sentinel mode
+fn sentinel(ctx: &Context) -> String {
+    let Some(sentinel) = &ctx.sentinel else {
+        return String::new();
+    };
+
+    let mut info = format!(
+        "# Sentinel\r\nsentinel_masters:{}\r\nsentinel_tilt:0\r\n",
+        sentinel.masters.len()
+    );
+
+    for (i, master) in sentinel.masters.values().enumerate() {
+        let status = match (master.odown, master.link.is_sdown()) {
+            (true, _) => "odown",
+            (false, true) => "sdown",
+            (false, false) => "ok",
+        };
+
+        info.push_str(&format!(
+            "master{i}:name={},status={status},address={},slaves={},sentinels={}\r\n",
+            master.name,
+            master.current_addr(),
+            master.replicas.len(),
+            master.sentinels.len() + 1
+        ));
+    }
+
+    info
+}
+
+fn replication(ctx: &Context) -> String {
+    let mut info = format!("# Replication\r\nrole:{}\r\n", ctx.config.role);
+
+    if let Some(master) = &ctx.config.replication {
+        let link = &ctx.master_link;
+        let seconds = |at: Option<Instant>| at.map_or(-1, |at| at.elapsed().as_secs() as i64);
+        let last_io = if link.up { seconds(link.last_io) } else { -1 };
+
+        let mut fields = vec![
+            ("master_host", master.host.to_string()),
+            ("master_port", master.port.to_string()),
+            ("master_link_status", if link.up { "up" } else { "down" }.to_string()),
+            ("master_last_io_seconds_ago", last_io.to_string()),
+            ("master_sync_in_progress", (link.sync_in_progress as u8).to_string()),
+            ("slave_read_repl_offset", ctx.config.master_repl_offset.to_string()),
+            ("slave_repl_offset", ctx.config.master_repl_offset.to_string()),
+        ];
+
+        if !link.up {
+            fields.push(("master_link_down_since_seconds", seconds(link.down_since).to_string()));
+        }
+
+        fields.push(("slave_priority", ctx.config.replica_priority.to_string()));
+        fields.push(("slave_read_only", (ctx.config.replica_read_only as u8).to_string()));
+
+        for (name, value) in fields {
+            info.push_str(&format!("{name}:{value}\r\n"));
+        }
+    }
+
+    info.push_str(&format!("connected_slaves:{}\r\n", ctx.replicas.len()));
+
+    let online = ctx.replicas.iter().map(|replica| (replica, "online"));
+    let waiting = ctx.replicas.waiting().map(|replica| (replica, "wait_bgsave"));
+
+    for (i, (replica, state)) in online.chain(waiting).enumerate() {
+        info.push_str(&format!(
+            "slave{i}:ip={},port={},state={state},offset={},lag={}\r\n",
+            replica.ip,
+            replica.listening_port,
+            replica.ack_offset,
+            replica.last_ack.elapsed().as_secs()
+        ));
+    }
+
+    let config = &ctx.config;
+    let backlog = ctx.replicas.backlog();
+    let second_repl_offset = config.second_repl_offset.map_or(-1, |offset| offset as i64);
+
+    let fields = [
+        ("master_replid", config.master_replid.to_string()),
+        ("master_replid2", config.master_replid2.to_string()),
+        ("master_repl_offset", config.master_repl_offset.to_string()),
+        ("second_repl_offset", second_repl_offset.to_string()),
+        ("repl_backlog_active", (backlog.is_some() as u8).to_string()),
+        ("repl_backlog_size", backlog.map_or(config.repl_backlog_size, |backlog| backlog.size() as u64).to_string()),
+        ("repl_backlog_first_byte_offset", backlog.map_or(0, |backlog| backlog.first_offset()).to_string()),
+        ("repl_backlog_histlen", backlog.map_or(0, |backlog| backlog.histlen()).to_string()),
+    ];
+
+    for (name, value) in fields {
+        info.push_str(&format!("{name}:{value}\r\n"));
+    }
+
+    info
+}
+
+fn persistence(ctx: &Context) -> String {
+    let aof = ctx.aof.as_ref();
+    let seconds = |duration: Option<Duration>| duration.map_or(-1, |duration| duration.as_secs() as i64);
+    let status = |ok: Option<bool>| if ok == Some(false) { "err" } else { "ok" };
+
+    let rdb = &ctx.rdb;
+    let last_save = rdb.last_save.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+
+    let mut fields = vec![
+        ("loading", "0".to_string()),
+        ("rdb_changes_since_last_save", rdb.changes.to_string()),
+        ("rdb_bgsave_in_progress", (rdb.is_saving() as u8).to_string()),
+        ("rdb_last_save_time", last_save.to_string()),
+        ("rdb_last_bgsave_status", status(Some(rdb.last_bgsave_ok)).to_string()),
+        ("rdb_last_bgsave_time_sec", seconds(rdb.last_bgsave_time).to_string()),
+        ("rdb_current_bgsave_time_sec", seconds(rdb.current_bgsave_time()).to_string()),
+        ("aof_enabled", (aof.is_some() as u8).to_string()),
+        ("aof_rewrite_in_progress", (aof.is_some_and(|aof| aof.is_rewriting()) as u8).to_string()),
+        ("aof_rewrite_scheduled", "0".to_string()),
+        (
+            "aof_last_rewrite_time_sec",
+            seconds(aof.and_then(|aof| aof.last_rewrite_time)).to_string(),
+        ),
+        (
+            "aof_current_rewrite_time_sec",
+            seconds(aof.and_then(|aof| aof.current_rewrite_time())).to_string(),
+        ),
+        (
+            "aof_last_bgrewrite_status",
+            status(aof.map(|aof| aof.last_rewrite_ok)).to_string(),
+        ),
+        ("aof_rewrites", aof.map_or(0, |aof| aof.rewrites).to_string()),
+        (
+            "aof_last_write_status",
+            status(aof.map(|aof| aof.last_write_ok)).to_string(),
+        ),
+    ];
+
+    if let Some(aof) = aof {
+        fields.push(("aof_current_size", aof.current_size().to_string()));
+        fields.push(("aof_base_size", aof.base_size().to_string()));
+    }
+
+    let mut info = String::from("# Persistence\r\n");
+
+    for (name, value) in fields {
+        info.push_str(&format!("{name}:{value}\r\n"));
+    }
+
+    info
+}
+
 impl RESPCommand for Info {
     fn execute(&mut self, ctx: &mut Context) -> RespType {
         if self.is_invalid() {
@@ -34,32 +206,48 @@ impl RESPCommand for Info {
             .into();
         }
 
-        let key = self.0.get(0);
+        let section = self.0.first().map(|section| section.to_lowercase());
 
-        if key.is_none() {
-            return Error::WrongNumberOfArguments {
-                command: self.command_name().to_string(),
+        let info = match section.as_deref() {
+            // Sentinels have no dataset to report about
+            None | Some("all" | "default" | "everything") if ctx.sentinel.is_some() => {
+                [server(ctx), sentinel(ctx)].join("\r\n")
             }
-            .into();
-        }
+            None | Some("all" | "default" | "everything") => {
+                [server(ctx), persistence(ctx), replication(ctx), cluster(ctx)].join("\r\n")
+            }
+            Some("server") => server(ctx),
+            Some("sentinel") => sentinel(ctx),
+            Some("cluster") => cluster(ctx),
+            Some("persistence") => persistence(ctx),
+            Some("replication") => replication(ctx),
+            Some(_) => String::new(),
+        };
 
+        RespType::bulk_string(info)
+    }
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        // role
-        let role = &ctx.config.role;
-        let master_replid = &ctx.config.master_replid;
-        let master_repl_offset = &ctx.config.master_repl_offset;
-        
-        let response = String::from("#Replication\r\n");
-        let role = format!("role:{}\r\n", role.to_string());
-        let master_replid = format!("master_replid:{}\r\n", master_replid.to_string());
-        let master_repl_offset = format!("master_repl_offset:{}\r\n", master_repl_offset.to_string());
-        
-        
-        let info = response + &role + &master_replid + &master_repl_offset;
+    #[test]
+    fn sections() {
+        let mut ctx = Context::default();
 
+        let RespType::BulkString { value, .. } = Info(vec!["replication".to_string()]).execute(&mut ctx) else {
+            panic!("expected a bulk string");
+        };
+        assert!(value.starts_with("# Replication\r\nrole:master\r\n"));
+        assert!(!value.contains("# Persistence"));
 
-        RespType::BulkString{ len: info.len(), value: info }
+        let RespType::BulkString { value, .. } = Info(vec![]).execute(&mut ctx) else {
+            panic!("expected a bulk string");
+        };
+        assert!(value.contains("aof_enabled:0\r\n"));
+        assert!(value.contains("aof_last_bgrewrite_status:ok\r\n"));
+        assert!(value.contains("# Replication"));
+        assert!(value.starts_with("# Server\r\nredis_mode:standalone\r\n"));
     }
 }
-
@@ -0,0 +1,69 @@
+use crate::config::Role;
+use crate::resp::{errors::Error, types::RespType};
+use crate::utils::context::Context;
+use crate::utils::migrate::Migration;
+use crate::utils::session::SessionState;
+
+use super::resp_command::{RESPCommand, RESPCommandName, RESPMinMaxArgs};
+
+/// `MIGRATE host port key|"" destination-db timeout [COPY] [REPLACE]
+/// [AUTH password | AUTH2 username password] [KEYS key ...]`, dumps the keys
+/// to move and leaves the connection sending them to the target
+pub struct Migrate(pub Vec<String>);
+
+impl RESPCommandName for Migrate {
+    fn command_name(&self) -> &'static str {
+        "migrate"
+    }
+}
+
+impl RESPMinMaxArgs for Migrate {
+    fn min_args(&self) -> usize {
+        5
+    }
+
+    fn max_args(&self) -> usize {
+        usize::MAX
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for Migrate {
+    /// Only runs for a connection, scripts can't call it
+    fn execute(&mut self, _: &mut Context) -> RespType {
+        Error::custom("ERR This Redis command is not allowed from script").into()
+    }
+
+    fn execute_for(&mut self, session: &mut SessionState, ctx: &mut Context) -> RespType {
+        if ctx.config.role == Role::Slave && ctx.config.replica_read_only {
+            return Error::custom("READONLY You can't write against a read only replica.").into();
+        }
+
+        match Migration::new(&self.0, ctx) {
+            Ok(Some(migration)) => {
+                session.migration = Some(migration);
+
+                RespType::Null
+            }
+            Ok(None) => RespType::simple_string("NOKEY"),
+            Err(err) => err.into(),
+        }
+    }
+
+    fn keys(&self) -> Vec<String> {
+        Migration::keys(&self.0)
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+
+    /// The keys are deleted, and the deletion propagated, once the target
+    /// restored them
+    fn propagate(&self, _ctx: &Context) -> Option<Vec<Vec<String>>> {
+        Some(Vec::new())
+    }
+}
@@ -1,14 +1,51 @@
+mod acl;
+mod auth;
+mod bloom;
+mod client;
+mod cluster;
 mod command;
+mod config;
+mod count_min_sketch;
+mod dump;
 mod echo;
+mod expire;
+mod flushdb;
 mod get;
+mod migrate;
+mod persistence;
 mod ping;
+mod pubsub;
+mod replication;
 mod resp_command;
+mod scripting;
+mod sentinel;
 mod set;
+mod time_series;
+mod unwatch;
 mod info;
 
+pub use acl::Acl;
+pub use auth::{Auth, Hello};
+pub use bloom::{BfAdd, BfExists, BfInfo, BfMadd, BfMexists, BfReserve};
+pub use client::Client;
+pub use cluster::Cluster;
 pub use command::Command;
+pub use config::Config;
+pub use count_min_sketch::{CmsIncrBy, CmsInfo, CmsInitByDim, CmsInitByProb, CmsMerge, CmsQuery};
+pub use dump::{Dump, Restore};
 pub use echo::Echo;
+pub use expire::PExpireAt;
+pub use flushdb::FlushDb;
 pub use get::Get;
+pub use migrate::Migrate;
+pub use persistence::{BgRewriteAof, BgSave, LastSave, Save};
 pub use ping::Ping;
+pub use pubsub::{Publish, Pubsub, SPublish};
+pub use replication::{ReplicaOf, Wait, WaitAof};
+pub use resp_command::RESPCommand;
+pub use scripting::{Eval, Fcall, Function, Script};
+pub use sentinel::Sentinel;
 pub use set::Set;
+pub use time_series::{TsAdd, TsCreate, TsCreateRule, TsDeleteRule, TsGet, TsInfo, TsRange, TsRevRange};
+pub use unwatch::Unwatch;
 pub use info::Info;
@@ -0,0 +1,273 @@
+use std::time::UNIX_EPOCH;
+
+use crate::resp::{errors::Error, types::RespType};
+use crate::utils::context::Context;
+
+use super::resp_command::{RESPCommand, RESPCommandName, RESPMinMaxArgs};
+
+/// `BGREWRITEAOF`, compacts the append only file without blocking clients
+pub struct BgRewriteAof(pub Vec<String>);
+
+impl RESPCommandName for BgRewriteAof {
+    fn command_name(&self) -> &'static str {
+        "bgrewriteaof"
+    }
+}
+
+impl RESPMinMaxArgs for BgRewriteAof {
+    fn min_args(&self) -> usize {
+        0
+    }
+
+    fn max_args(&self) -> usize {
+        0
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for BgRewriteAof {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        match ctx.aof.as_ref() {
+            None => return Error::custom("ERR Append only file is disabled, enable it with CONFIG SET appendonly yes").into(),
+            Some(aof) if aof.is_rewriting() => {
+                return Error::custom("ERR Background append only file rewriting already in progress").into();
+            }
+            Some(_) => {}
+        }
+
+        match ctx.rewrite_aof() {
+            Ok(()) => RespType::simple_string("Background append only file rewriting started"),
+            Err(err) => Error::custom(format!("ERR Can't start the background append only file rewrite: {err}")).into(),
+        }
+    }
+}
+
+/// `SAVE`, writes a snapshot while blocking every client
+pub struct Save(pub Vec<String>);
+
+impl RESPCommandName for Save {
+    fn command_name(&self) -> &'static str {
+        "save"
+    }
+}
+
+impl RESPMinMaxArgs for Save {
+    fn min_args(&self) -> usize {
+        0
+    }
+
+    fn max_args(&self) -> usize {
+        0
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for Save {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        if ctx.rdb.is_saving() {
+            return Error::custom("ERR Background save already in progress").into();
+        }
+
+        match ctx.save() {
+            Ok(()) => RespType::ok(),
+            Err(err) => {
+                eprintln!("err: saving {}: {}", ctx.rdb_path().display(), err);
+                Error::custom("ERR").into()
+            }
+        }
+    }
+}
+
+/// `BGSAVE [SCHEDULE]`, writes a snapshot without blocking clients
+pub struct BgSave(pub Vec<String>);
+
+impl RESPCommandName for BgSave {
+    fn command_name(&self) -> &'static str {
+        "bgsave"
+    }
+}
+
+impl RESPMinMaxArgs for BgSave {
+    fn min_args(&self) -> usize {
+        0
+    }
+
+    fn max_args(&self) -> usize {
+        1
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for BgSave {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        // Saving runs in a thread, there is never anything to wait for
+        // before starting, so SCHEDULE starts right away too
+        if self.0.first().is_some_and(|option| !option.eq_ignore_ascii_case("schedule")) {
+            return Error::Syntax.into();
+        }
+
+        if ctx.rdb.is_saving() {
+            return Error::custom("ERR Background save already in progress").into();
+        }
+
+        match ctx.bgsave() {
+            Ok(()) => RespType::simple_string("Background saving started"),
+            Err(err) => Error::custom(format!("ERR Can't start the background save: {err}")).into(),
+        }
+    }
+}
+
+/// `LASTSAVE`, unix time of the last successful snapshot
+pub struct LastSave(pub Vec<String>);
+
+impl RESPCommandName for LastSave {
+    fn command_name(&self) -> &'static str {
+        "lastsave"
+    }
+}
+
+impl RESPMinMaxArgs for LastSave {
+    fn min_args(&self) -> usize {
+        0
+    }
+
+    fn max_args(&self) -> usize {
+        0
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for LastSave {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        let seconds = ctx.rdb.last_save.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+
+        RespType::Integer(seconds as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::models::StoreValue;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("persistence-test-{}-{name}", std::process::id()))
+    }
+
+    fn wait_for_rewrite(ctx: &mut Context) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+
+        while ctx.aof.as_ref().unwrap().is_rewriting() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+            ctx.cron();
+        }
+    }
+
+    #[test]
+    fn save_and_bgsave() {
+        let dir = temp_dir("save");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut ctx = Context::default();
+        ctx.config.dir = dir.to_string_lossy().to_string();
+        ctx.store.insert("a".to_string(), StoreValue::from("1"));
+        ctx.rdb.changes = 3;
+
+        assert_eq!(Save(vec![]).execute(&mut ctx), RespType::ok());
+        assert!(dir.join("dump.rdb").exists());
+        assert_eq!(ctx.rdb.changes, 0);
+
+        let response = BgSave(vec![]).execute(&mut ctx);
+        assert_eq!(response, RespType::simple_string("Background saving started"));
+
+        let response = Save(vec![]).execute(&mut ctx);
+        assert!(matches!(response, RespType::SimpleError(Error::Custom { .. })));
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while ctx.rdb.is_saving() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+            ctx.cron();
+        }
+
+        assert!(ctx.rdb.last_bgsave_ok);
+        assert!(matches!(LastSave(vec![]).execute(&mut ctx), RespType::Integer(seconds) if seconds > 0));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn requires_appendonly() {
+        let mut ctx = Context::default();
+
+        let response = BgRewriteAof(vec![]).execute(&mut ctx);
+
+        assert!(matches!(response, RespType::SimpleError(Error::Custom { .. })));
+    }
+
+    #[test]
+    fn rewrite_in_background() {
+        let dir = temp_dir("rewrite");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let mut ctx = Context::default();
+        ctx.config.dir = dir.to_string_lossy().to_string();
+        ctx.store.insert("a".to_string(), StoreValue::from("1"));
+        ctx.set_appendonly(true).unwrap();
+
+        let response = BgRewriteAof(vec![]).execute(&mut ctx);
+        assert!(matches!(response, RespType::SimpleError(Error::Custom { .. })));
+
+        wait_for_rewrite(&mut ctx);
+
+        let response = BgRewriteAof(vec![]).execute(&mut ctx);
+        assert_eq!(response, RespType::simple_string("Background append only file rewriting started"));
+
+        wait_for_rewrite(&mut ctx);
+        assert_eq!(ctx.aof.as_ref().unwrap().rewrites, 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
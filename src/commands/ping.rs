@@ -36,19 +36,14 @@ impl RESPCommand for Ping {
             .into();
         }
 
-        let message = self.0.get(0);
+        let message = self.0.first();
 
-        let response = match message {
-            Some(v) => RespType::BulkString {
-                len: v.len(),
-                value: v.to_string(),
-            },
+        match message {
+            Some(v) => RespType::bulk_string(v),
             None => RespType::SimpleString {
                 value: "PONG".to_string(),
             },
-        };
-
-        response
+        }
     }
 }
 
@@ -0,0 +1,235 @@
+use crate::resp::{errors::Error, types::RespType};
+use crate::utils::context::Context;
+
+use super::resp_command::{RESPCommand, RESPCommandName, RESPMinMaxArgs};
+
+pub struct Publish(pub Vec<String>);
+
+impl RESPCommandName for Publish {
+    fn command_name(&self) -> &'static str {
+        "publish"
+    }
+}
+
+impl RESPMinMaxArgs for Publish {
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn max_args(&self) -> usize {
+        2
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for Publish {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        RespType::Integer(ctx.pubsub.publish(&self.0[0], &self.0[1]) as i64)
+    }
+}
+
+pub struct SPublish(pub Vec<String>);
+
+impl RESPCommandName for SPublish {
+    fn command_name(&self) -> &'static str {
+        "spublish"
+    }
+}
+
+impl RESPMinMaxArgs for SPublish {
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn max_args(&self) -> usize {
+        2
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for SPublish {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        RespType::Integer(ctx.pubsub.spublish(&self.0[0], &self.0[1]) as i64)
+    }
+}
+
+/// `PUBSUB CHANNELS [pattern] | NUMSUB [channel ...] | NUMPAT |
+/// SHARDCHANNELS [pattern] | SHARDNUMSUB [channel ...]`
+pub struct Pubsub(pub Vec<String>);
+
+impl RESPCommandName for Pubsub {
+    fn command_name(&self) -> &'static str {
+        "pubsub"
+    }
+}
+
+impl RESPMinMaxArgs for Pubsub {
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> usize {
+        usize::MAX
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for Pubsub {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        let subcommand = self.0[0].to_lowercase();
+        let args = &self.0[1..];
+
+        match (subcommand.as_str(), args.len()) {
+            ("channels", 0 | 1) => {
+                let mut channels = ctx.pubsub.channels(args.first().map(|p| p.as_str()));
+                channels.sort();
+
+                RespType::array(channels.into_iter().map(RespType::bulk_string).collect())
+            }
+            ("shardchannels", 0 | 1) => {
+                let mut channels = ctx.pubsub.shard_channels(args.first().map(|p| p.as_str()));
+                channels.sort();
+
+                RespType::array(channels.into_iter().map(RespType::bulk_string).collect())
+            }
+            ("numsub" | "shardnumsub", _) => {
+                let values = args
+                    .iter()
+                    .flat_map(|channel| {
+                        let count = match subcommand.as_str() {
+                            "numsub" => ctx.pubsub.numsub(channel),
+                            _ => ctx.pubsub.shard_numsub(channel),
+                        };
+
+                        [RespType::bulk_string(channel), RespType::Integer(count as i64)]
+                    })
+                    .collect();
+
+                RespType::array(values)
+            }
+            ("numpat", 0) => RespType::Integer(ctx.pubsub.numpat() as i64),
+            ("channels" | "shardchannels" | "numpat", _) => Error::custom(format!(
+                "ERR wrong number of arguments for 'pubsub|{subcommand}' command"
+            ))
+            .into(),
+            _ => Error::custom(format!(
+                "ERR unknown subcommand '{}'. Try PUBSUB HELP.",
+                self.0[0]
+            ))
+            .into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use super::*;
+    use crate::utils::pubsub::Subscription;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn publish_without_subscribers() {
+        let mut ctx = Context::default();
+
+        let response = Publish(args(&["news", "hello"])).execute(&mut ctx);
+
+        assert_eq!(response, RespType::Integer(0));
+    }
+
+    #[test]
+    fn introspection() {
+        let mut ctx = Context::default();
+        let (tx, _rx) = unbounded_channel();
+
+        ctx.pubsub.subscribe(Subscription::Channel, "news.tech", 1, &tx);
+        ctx.pubsub.subscribe(Subscription::Channel, "news.art", 2, &tx);
+        ctx.pubsub.subscribe(Subscription::Pattern, "news.*", 1, &tx);
+
+        let response = Pubsub(args(&["CHANNELS", "news.t*"])).execute(&mut ctx);
+        assert_eq!(response, RespType::array(vec![RespType::bulk_string("news.tech")]));
+
+        let response = Pubsub(args(&["NUMSUB", "news.art", "other"])).execute(&mut ctx);
+        assert_eq!(
+            response,
+            RespType::array(vec![
+                RespType::bulk_string("news.art"),
+                RespType::Integer(1),
+                RespType::bulk_string("other"),
+                RespType::Integer(0),
+            ])
+        );
+
+        let response = Pubsub(args(&["NUMPAT"])).execute(&mut ctx);
+        assert_eq!(response, RespType::Integer(1));
+    }
+
+    #[test]
+    fn shard_introspection() {
+        let mut ctx = Context::default();
+        let (tx, _rx) = unbounded_channel();
+
+        ctx.pubsub.subscribe(Subscription::Shard, "orders", 1, &tx);
+        ctx.pubsub.subscribe(Subscription::Channel, "news", 1, &tx);
+
+        let response = Pubsub(args(&["SHARDCHANNELS"])).execute(&mut ctx);
+        assert_eq!(response, RespType::array(vec![RespType::bulk_string("orders")]));
+
+        let response = Pubsub(args(&["SHARDNUMSUB", "orders", "news"])).execute(&mut ctx);
+        assert_eq!(
+            response,
+            RespType::array(vec![
+                RespType::bulk_string("orders"),
+                RespType::Integer(1),
+                RespType::bulk_string("news"),
+                RespType::Integer(0),
+            ])
+        );
+
+        let response = SPublish(args(&["orders", "hello"])).execute(&mut ctx);
+        assert_eq!(response, RespType::Integer(1));
+    }
+
+    #[test]
+    fn unknown_subcommand() {
+        let mut ctx = Context::default();
+
+        let response = Pubsub(args(&["FOO"])).execute(&mut ctx);
+
+        assert!(matches!(response, RespType::SimpleError(Error::Custom { .. })));
+    }
+}
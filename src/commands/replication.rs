@@ -0,0 +1,226 @@
+use std::time::{Duration, Instant};
+
+use crate::config::Role;
+use crate::resp::{errors::Error, types::RespType};
+use crate::utils::context::Context;
+use crate::utils::replication;
+use crate::utils::session::{Blocked, SessionState};
+
+use super::resp_command::{RESPCommand, RESPCommandName, RESPMinMaxArgs};
+
+/// `REPLICAOF host port`, or `REPLICAOF NO ONE` to become a master
+pub struct ReplicaOf(pub Vec<String>);
+
+impl RESPCommandName for ReplicaOf {
+    fn command_name(&self) -> &'static str {
+        "replicaof"
+    }
+}
+
+impl RESPMinMaxArgs for ReplicaOf {
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn max_args(&self) -> usize {
+        2
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for ReplicaOf {
+    /// Only runs for a connection, scripts can't call it
+    fn execute(&mut self, _: &mut Context) -> RespType {
+        Error::custom("ERR This Redis command is not allowed from script").into()
+    }
+
+    fn execute_for(&mut self, session: &mut SessionState, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        // The cluster decides which master a node replicates
+        if ctx.cluster.is_some() {
+            return Error::custom("ERR REPLICAOF not allowed in cluster mode.").into();
+        }
+
+        let (host, port) = (&self.0[0], &self.0[1]);
+
+        if host.eq_ignore_ascii_case("no") && port.eq_ignore_ascii_case("one") {
+            replication::promote(ctx);
+            eprintln!("MASTER MODE enabled (user request from 'id={}')", session.id);
+
+            return RespType::ok();
+        }
+
+        let Ok(port) = port.parse::<u16>() else {
+            return Error::custom("ERR Invalid master port").into();
+        };
+
+        if ctx.config.replication.as_ref().is_some_and(|master| master.host == *host && master.port == port) {
+            return RespType::simple_string("OK Already connected to specified master");
+        }
+
+        replication::follow(ctx, host.to_string(), port);
+        eprintln!("REPLICAOF {host}:{port} enabled (user request from 'id={}')", session.id);
+
+        RespType::ok()
+    }
+}
+
+/// `WAIT numreplicas timeout`, blocks until `numreplicas` replicas
+/// acknowledged the last write of the client
+pub struct Wait(pub Vec<String>);
+
+impl RESPCommandName for Wait {
+    fn command_name(&self) -> &'static str {
+        "wait"
+    }
+}
+
+impl RESPMinMaxArgs for Wait {
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn max_args(&self) -> usize {
+        2
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for Wait {
+    /// Only runs for a connection, scripts can't call it
+    fn execute(&mut self, _: &mut Context) -> RespType {
+        Error::custom("ERR This Redis command is not allowed from script").into()
+    }
+
+    fn execute_for(&mut self, session: &mut SessionState, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        if ctx.config.role == Role::Slave {
+            return Error::custom(
+                "ERR WAIT cannot be used with replica instances. Please also note that since Redis 4.0 if a replica is configured to be writable (which is not the default) writes to replicas are just local and are not propagated.",
+            )
+            .into();
+        }
+
+        let replicas = match self.0[0].parse::<i64>() {
+            Ok(replicas) => replicas.max(0) as usize,
+            Err(_) => return Error::NotAnInteger.into(),
+        };
+
+        let timeout = match parse_timeout(&self.0[1]) {
+            Ok(timeout) => timeout,
+            Err(err) => return err.into(),
+        };
+
+        let blocked = Blocked {
+            offset: session.write_offset,
+            aof: false,
+            local: false,
+            replicas,
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
+        };
+
+        session.block(blocked, ctx)
+    }
+}
+
+/// `WAITAOF numlocal numreplicas timeout`, blocks until the last write of
+/// the client is fsynced to the local append only file, when `numlocal` is
+/// set, and to the ones of `numreplicas` replicas
+pub struct WaitAof(pub Vec<String>);
+
+impl RESPCommandName for WaitAof {
+    fn command_name(&self) -> &'static str {
+        "waitaof"
+    }
+}
+
+impl RESPMinMaxArgs for WaitAof {
+    fn min_args(&self) -> usize {
+        3
+    }
+
+    fn max_args(&self) -> usize {
+        3
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for WaitAof {
+    /// Only runs for a connection, scripts can't call it
+    fn execute(&mut self, _: &mut Context) -> RespType {
+        Error::custom("ERR This Redis command is not allowed from script").into()
+    }
+
+    fn execute_for(&mut self, session: &mut SessionState, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        let (local, replicas) = match (self.0[0].parse::<i64>(), self.0[1].parse::<i64>()) {
+            (Ok(local), Ok(replicas)) => (local, replicas.max(0) as usize),
+            _ => return Error::NotAnInteger.into(),
+        };
+
+        let timeout = match parse_timeout(&self.0[2]) {
+            Ok(timeout) => timeout,
+            Err(err) => return err.into(),
+        };
+
+        if ctx.config.role == Role::Slave {
+            return Error::custom(
+                "ERR WAITAOF cannot be used with replica instances. Please also note that writes to replicas are just local and are not propagated.",
+            )
+            .into();
+        }
+
+        if local > 0 && ctx.aof.is_none() {
+            return Error::custom("ERR WAITAOF cannot be used when numlocal is set but appendonly is disabled.").into();
+        }
+
+        let blocked = Blocked {
+            offset: session.write_offset,
+            aof: true,
+            local: local > 0,
+            replicas,
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
+        };
+
+        session.block(blocked, ctx)
+    }
+}
+
+/// Timeout in milliseconds of a blocking command, `None` when it is `0`
+fn parse_timeout(value: &str) -> Result<Option<Duration>, Error> {
+    match value.parse::<i64>() {
+        Ok(0) => Ok(None),
+        Ok(millis) if millis > 0 => Ok(Some(Duration::from_millis(millis as u64))),
+        Ok(_) => Err(Error::custom("ERR timeout is negative")),
+        Err(_) => Err(Error::custom(
+            "ERR timeout is not an integer or out of range",
+        )),
+    }
+}
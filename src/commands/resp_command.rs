@@ -1,5 +1,6 @@
 use crate::context::Context;
 use crate::resp::types::RespType; 
+use crate::utils::session::SessionState;
 
 pub trait RESPCommandName {
     /// Get the name of the command
@@ -23,5 +24,40 @@ pub trait RESPMinMaxArgs {
 
 pub trait RESPCommand: RESPCommandName + RESPMinMaxArgs + Send {
     fn execute(&mut self, ctx: &mut Context) -> RespType;
+
+    /// Executes the command for the connection it came from
+    ///
+    /// The commands about the connection itself, like `AUTH` or `CLIENT`,
+    /// read and change its state here, the others just execute.
+    fn execute_for(&mut self, _session: &mut SessionState, ctx: &mut Context) -> RespType {
+        self.execute(ctx)
+    }
+
+    /// Keys the command accesses, in the order they appear in the arguments
+    fn keys(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Whether the command may modify the keyspace
+    fn is_write(&self) -> bool {
+        false
+    }
+
+    /// Commands persisted for this execution of a write command, `None` to
+    /// persist it as received
+    ///
+    /// Called right after [`RESPCommand::execute`] succeeded, commands whose
+    /// effect depends on the time they ran at rewrite themselves here so that
+    /// replaying them gives the same result.
+    fn propagate(&self, _ctx: &Context) -> Option<Vec<Vec<String>>> {
+        None
+    }
+
+    /// ACL user of the client running the command, commands running other
+    /// commands (scripts) check them against its permissions
+    ///
+    /// Not set for the commands of the master or the append only file,
+    /// which may run anything.
+    fn set_user(&mut self, _user: &str) {}
 }
 
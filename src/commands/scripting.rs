@@ -0,0 +1,693 @@
+use crate::resp::{
+    errors::Error,
+    types::{bytes_to_string, string_to_bytes, RespType},
+};
+use crate::utils::context::Context;
+use crate::utils::functions::RestorePolicy;
+use crate::utils::glob;
+use crate::utils::scripting::{self, Execution};
+
+use super::resp_command::{RESPCommand, RESPCommandName, RESPMinMaxArgs};
+
+/// `EVAL script numkeys [key ...] [arg ...]`, also `EVALSHA` taking the
+/// SHA1 of a cached script and the read only `EVAL_RO` and `EVALSHA_RO`
+pub struct Eval {
+    args: Vec<String>,
+    by_sha: bool,
+    read_only: bool,
+    /// ACL user the commands of the script are checked against
+    user: Option<String>,
+    /// Writes of the script, replicated in its place
+    propagated: Vec<Vec<String>>,
+}
+
+impl Eval {
+    pub fn new(args: Vec<String>, by_sha: bool, read_only: bool) -> Self {
+        Self {
+            args,
+            by_sha,
+            read_only,
+            user: None,
+            propagated: Vec::new(),
+        }
+    }
+
+}
+
+/// Number of keys following the script or function of `args`, checked
+/// against the arguments
+fn numkeys(command: &str, args: &[String]) -> Result<usize, Error> {
+    let numkeys = match args.get(1) {
+        Some(numkeys) => numkeys.parse::<i64>().map_err(|_| Error::NotAnInteger)?,
+        None => return Err(Error::WrongNumberOfArguments { command: command.to_string() }),
+    };
+
+    if numkeys < 0 {
+        return Err(Error::custom("ERR Number of keys can't be negative"));
+    }
+
+    if numkeys as usize > args.len() - 2 {
+        return Err(Error::custom("ERR Number of keys can't be greater than number of args"));
+    }
+
+    Ok(numkeys as usize)
+}
+
+/// Keys of a script or function call, none when its arguments are invalid
+fn keys(command: &str, args: &[String]) -> Vec<String> {
+    match numkeys(command, args) {
+        Ok(numkeys) => args[2..2 + numkeys].to_vec(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Keeps the writes of a script or function for its command to propagate,
+/// or propagates them right away when it failed
+///
+/// What the script wrote before failing stays written, while the command
+/// is only propagated when it succeeds.
+fn settle(ctx: &mut Context, execution: Execution, propagated: &mut Vec<Vec<String>>) -> RespType {
+    if matches!(execution.reply, RespType::SimpleError(_)) {
+        ctx.propagate(execution.propagated);
+    } else {
+        *propagated = execution.propagated;
+    }
+
+    execution.reply
+}
+
+impl RESPCommandName for Eval {
+    fn command_name(&self) -> &'static str {
+        match (self.by_sha, self.read_only) {
+            (false, false) => "eval",
+            (true, false) => "evalsha",
+            (false, true) => "eval_ro",
+            (true, true) => "evalsha_ro",
+        }
+    }
+}
+
+impl RESPMinMaxArgs for Eval {
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn max_args(&self) -> usize {
+        usize::MAX
+    }
+
+    fn args_len(&self) -> usize {
+        self.args.len()
+    }
+}
+
+impl RESPCommand for Eval {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        let numkeys = match numkeys(self.command_name(), &self.args) {
+            Ok(numkeys) => numkeys,
+            Err(err) => return err.into(),
+        };
+
+        let sha = if self.by_sha {
+            self.args[0].to_lowercase()
+        } else {
+            match ctx.scripts.load(&self.args[0]) {
+                Ok(sha) => sha,
+                Err(err) => return err.into(),
+            }
+        };
+
+        let Some(script) = ctx.scripts.get(&sha).cloned() else {
+            return Error::custom("NOSCRIPT No matching script. Please use EVAL.").into();
+        };
+
+        let (keys, args) = self.args[2..].split_at(numkeys);
+        let execution = scripting::eval(ctx, &script, &sha, keys, args, self.read_only, self.user.as_deref());
+
+        settle(ctx, execution, &mut self.propagated)
+    }
+
+    fn keys(&self) -> Vec<String> {
+        keys(self.command_name(), &self.args)
+    }
+
+    /// Only known once the script ran, scripts that wrote nothing aren't
+    /// replicated
+    fn is_write(&self) -> bool {
+        !self.propagated.is_empty()
+    }
+
+    /// The writes of the script, replayed atomically like a transaction
+    fn propagate(&self, _ctx: &Context) -> Option<Vec<Vec<String>>> {
+        (!self.propagated.is_empty()).then(|| self.propagated.clone())
+    }
+
+    fn set_user(&mut self, user: &str) {
+        self.user = Some(user.to_string());
+    }
+}
+
+/// `SCRIPT LOAD script | EXISTS sha1 [sha1 ...] | FLUSH [ASYNC|SYNC] | KILL`
+pub struct Script(pub Vec<String>);
+
+impl RESPCommandName for Script {
+    fn command_name(&self) -> &'static str {
+        "script"
+    }
+}
+
+impl RESPMinMaxArgs for Script {
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> usize {
+        usize::MAX
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for Script {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        let subcommand = self.0[0].to_lowercase();
+        let args = &self.0[1..];
+
+        match (subcommand.as_str(), args.len()) {
+            ("load", 1) => match ctx.scripts.load(&args[0]) {
+                Ok(sha) => RespType::bulk_string(sha),
+                Err(err) => err.into(),
+            },
+            ("exists", 1..) => RespType::array(
+                args.iter()
+                    .map(|sha| RespType::Integer(ctx.scripts.contains(sha) as i64))
+                    .collect(),
+            ),
+            ("flush", 0..=1) => {
+                if args.first().is_some_and(|mode| !matches!(mode.to_lowercase().as_str(), "async" | "sync")) {
+                    return Error::custom("ERR SCRIPT FLUSH only support SYNC|ASYNC option").into();
+                }
+
+                ctx.scripts.flush();
+
+                RespType::ok()
+            }
+            // Scripts running past the threshold are killed before reaching
+            // the context, by then nothing runs
+            ("kill", 0) => scripting::kill(false),
+            ("load" | "exists" | "flush" | "kill", _) => Error::custom(format!(
+                "ERR wrong number of arguments for 'script|{subcommand}' command"
+            ))
+            .into(),
+            _ => Error::custom(format!("ERR unknown subcommand '{}'. Try SCRIPT HELP.", self.0[0])).into(),
+        }
+    }
+}
+
+/// `FCALL function numkeys [key ...] [arg ...]`, also the read only
+/// `FCALL_RO`
+pub struct Fcall {
+    args: Vec<String>,
+    read_only: bool,
+    /// ACL user the commands of the function are checked against
+    user: Option<String>,
+    /// Writes of the function, replicated in its place
+    propagated: Vec<Vec<String>>,
+}
+
+impl Fcall {
+    pub fn new(args: Vec<String>, read_only: bool) -> Self {
+        Self {
+            args,
+            read_only,
+            user: None,
+            propagated: Vec::new(),
+        }
+    }
+}
+
+impl RESPCommandName for Fcall {
+    fn command_name(&self) -> &'static str {
+        if self.read_only {
+            "fcall_ro"
+        } else {
+            "fcall"
+        }
+    }
+}
+
+impl RESPMinMaxArgs for Fcall {
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn max_args(&self) -> usize {
+        usize::MAX
+    }
+
+    fn args_len(&self) -> usize {
+        self.args.len()
+    }
+}
+
+impl RESPCommand for Fcall {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        let numkeys = match numkeys(self.command_name(), &self.args) {
+            Ok(numkeys) => numkeys,
+            Err(err) => return err.into(),
+        };
+
+        let Some((library, function)) = ctx.functions.get(&self.args[0]) else {
+            return Error::custom("ERR Function not found").into();
+        };
+
+        let (library, function) = (library.clone(), function.clone());
+        let (keys, args) = self.args[2..].split_at(numkeys);
+        let execution = scripting::fcall(ctx, &library, &function, keys, args, self.read_only, self.user.as_deref());
+
+        settle(ctx, execution, &mut self.propagated)
+    }
+
+    fn keys(&self) -> Vec<String> {
+        keys(self.command_name(), &self.args)
+    }
+
+    /// Only known once the function ran, like for scripts
+    fn is_write(&self) -> bool {
+        !self.propagated.is_empty()
+    }
+
+    fn propagate(&self, _ctx: &Context) -> Option<Vec<Vec<String>>> {
+        (!self.propagated.is_empty()).then(|| self.propagated.clone())
+    }
+
+    fn set_user(&mut self, user: &str) {
+        self.user = Some(user.to_string());
+    }
+}
+
+/// `FUNCTION LOAD [REPLACE] code | DELETE library | FLUSH [ASYNC|SYNC] |
+/// LIST [LIBRARYNAME pattern] [WITHCODE] | DUMP | RESTORE payload
+/// [FLUSH|APPEND|REPLACE] | KILL | STATS`
+pub struct Function(pub Vec<String>);
+
+impl Function {
+    fn load(&self, ctx: &mut Context, args: &[String]) -> RespType {
+        let (replace, code) = match args {
+            [code] => (false, code),
+            [option, code] if option.eq_ignore_ascii_case("replace") => (true, code),
+            [option, _] => return Error::custom(format!("ERR Unknown option given: {option}")).into(),
+            _ => unreachable!("arity is checked"),
+        };
+
+        match ctx.functions.load(code, replace) {
+            Ok(name) => RespType::bulk_string(name),
+            Err(err) => err.into(),
+        }
+    }
+
+    fn list(&self, ctx: &Context, args: &[String]) -> RespType {
+        let mut with_code = false;
+        let mut pattern = None;
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            match arg.to_lowercase().as_str() {
+                "withcode" => with_code = true,
+                "libraryname" => match args.next() {
+                    Some(name) => pattern = Some(name),
+                    None => return Error::custom("ERR library name argument was not given").into(),
+                },
+                _ => return Error::custom(format!("ERR Unknown argument {arg}")).into(),
+            }
+        }
+
+        let libraries = ctx
+            .functions
+            .libraries()
+            .filter(|library| match pattern {
+                Some(pattern) => glob::matches(pattern, &library.name),
+                None => true,
+            })
+            .map(|library| {
+                let functions = library
+                    .functions
+                    .iter()
+                    .map(|function| {
+                        let description = match &function.description {
+                            Some(description) => RespType::bulk_string(description),
+                            None => RespType::Null,
+                        };
+                        let flags = function.flags.names().into_iter().map(RespType::bulk_string).collect();
+
+                        RespType::Map(vec![
+                            (RespType::bulk_string("name"), RespType::bulk_string(&function.name)),
+                            (RespType::bulk_string("description"), description),
+                            (RespType::bulk_string("flags"), RespType::array(flags)),
+                        ])
+                    })
+                    .collect();
+
+                let mut entries = vec![
+                    (RespType::bulk_string("library_name"), RespType::bulk_string(&library.name)),
+                    (RespType::bulk_string("engine"), RespType::bulk_string("LUA")),
+                    (RespType::bulk_string("functions"), RespType::array(functions)),
+                ];
+
+                if with_code {
+                    entries.push((RespType::bulk_string("library_code"), RespType::bulk_string(&library.code)));
+                }
+
+                RespType::Map(entries)
+            })
+            .collect();
+
+        RespType::array(libraries)
+    }
+
+    fn restore(&self, ctx: &mut Context, args: &[String]) -> RespType {
+        let policy = match args.get(1).map(|policy| policy.to_lowercase()).as_deref() {
+            None | Some("append") => RestorePolicy::Append,
+            Some("replace") => RestorePolicy::Replace,
+            Some("flush") => RestorePolicy::Flush,
+            Some(_) => {
+                return Error::custom("ERR Wrong restore policy given, value should be either FLUSH, APPEND or REPLACE.")
+                    .into()
+            }
+        };
+
+        match ctx.functions.restore(&string_to_bytes(&args[0]), policy) {
+            Ok(()) => RespType::ok(),
+            Err(err) => err.into(),
+        }
+    }
+
+    /// Nothing runs while the context is free, scripts running past the
+    /// threshold are only reachable by `FUNCTION KILL`
+    fn stats(&self, ctx: &Context) -> RespType {
+        let engine = RespType::Map(vec![
+            (
+                RespType::bulk_string("libraries_count"),
+                RespType::Integer(ctx.functions.libraries().count() as i64),
+            ),
+            (
+                RespType::bulk_string("functions_count"),
+                RespType::Integer(ctx.functions.function_count() as i64),
+            ),
+        ]);
+
+        RespType::Map(vec![
+            (RespType::bulk_string("running_script"), RespType::Null),
+            (
+                RespType::bulk_string("engines"),
+                RespType::Map(vec![(RespType::bulk_string("LUA"), engine)]),
+            ),
+        ])
+    }
+}
+
+impl RESPCommandName for Function {
+    fn command_name(&self) -> &'static str {
+        "function"
+    }
+}
+
+impl RESPMinMaxArgs for Function {
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> usize {
+        usize::MAX
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for Function {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        let subcommand = self.0[0].to_lowercase();
+        let args = &self.0[1..];
+
+        match (subcommand.as_str(), args.len()) {
+            ("load", 1..=2) => self.load(ctx, args),
+            ("delete", 1) => match ctx.functions.delete(&args[0]) {
+                Ok(()) => RespType::ok(),
+                Err(err) => err.into(),
+            },
+            ("flush", 0..=1) => {
+                if args.first().is_some_and(|mode| !matches!(mode.to_lowercase().as_str(), "async" | "sync")) {
+                    return Error::custom("ERR FUNCTION FLUSH only supports SYNC|ASYNC option").into();
+                }
+
+                ctx.functions.flush();
+
+                RespType::ok()
+            }
+            ("list", _) => self.list(ctx, args),
+            ("dump", 0) => RespType::bulk_string(bytes_to_string(&ctx.functions.dump())),
+            ("restore", 1..=2) => self.restore(ctx, args),
+            // Like `SCRIPT KILL`, functions running past the threshold are
+            // killed before reaching the context
+            ("kill", 0) => scripting::kill(true),
+            ("stats", 0) => self.stats(ctx),
+            ("load" | "delete" | "flush" | "dump" | "restore" | "kill" | "stats", _) => Error::custom(format!(
+                "ERR wrong number of arguments for 'function|{subcommand}' command"
+            ))
+            .into(),
+            _ => Error::custom(format!("ERR unknown subcommand '{}'. Try FUNCTION HELP.", self.0[0])).into(),
+        }
+    }
+
+    fn is_write(&self) -> bool {
+        self.0
+            .first()
+            .is_some_and(|subcommand| matches!(subcommand.to_lowercase().as_str(), "load" | "delete" | "flush" | "restore"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::Get;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    fn eval(ctx: &mut Context, values: &[&str]) -> RespType {
+        Eval::new(args(values), false, false).execute(ctx)
+    }
+
+    #[test]
+    fn eval_and_evalsha() {
+        let mut ctx = Context::default();
+        let script = "return {KEYS[1], ARGV[1], #KEYS, #ARGV}";
+        let reply = eval(&mut ctx, &[script, "1", "key", "arg"]);
+
+        assert_eq!(
+            reply,
+            RespType::array(vec![
+                RespType::bulk_string("key"),
+                RespType::bulk_string("arg"),
+                RespType::Integer(1),
+                RespType::Integer(1),
+            ])
+        );
+
+        let RespType::BulkString { value: sha, .. } = Script(args(&["load", script])).execute(&mut ctx) else {
+            panic!("expected the SHA1 of the script");
+        };
+
+        assert_eq!(Eval::new(args(&[&sha, "1", "key", "arg"]), true, false).execute(&mut ctx), reply);
+        assert_eq!(
+            Eval::new(args(&["ffffffffffffffffffffffffffffffffffffffff", "0"]), true, false).execute(&mut ctx),
+            Error::custom("NOSCRIPT No matching script. Please use EVAL.").into()
+        );
+    }
+
+    #[test]
+    fn numkeys_errors() {
+        let mut ctx = Context::default();
+
+        assert_eq!(eval(&mut ctx, &["return 1", "x"]), Error::NotAnInteger.into());
+        assert_eq!(
+            eval(&mut ctx, &["return 1", "-1"]),
+            Error::custom("ERR Number of keys can't be negative").into()
+        );
+        assert_eq!(
+            eval(&mut ctx, &["return 1", "2", "a"]),
+            Error::custom("ERR Number of keys can't be greater than number of args").into()
+        );
+        assert_eq!(Eval::new(args(&["return 1", "2", "a", "b", "c"]), false, false).keys(), args(&["a", "b"]));
+    }
+
+    #[test]
+    fn writes_are_propagated() {
+        let mut ctx = Context::default();
+        let mut command = Eval::new(args(&["return 1", "0"]), false, false);
+
+        command.execute(&mut ctx);
+        assert!(!command.is_write());
+
+        let script = "redis.call('SET', 'a', '1') redis.call('SET', 'b', '2')";
+        let mut command = Eval::new(args(&[script, "0"]), false, false);
+
+        assert_eq!(command.execute(&mut ctx), RespType::Null);
+        assert!(command.is_write());
+        assert_eq!(
+            command.propagate(&ctx).unwrap(),
+            vec![args(&["SET", "a", "1"]), args(&["SET", "b", "2"])]
+        );
+        assert_eq!(Get(args(&["b"])).execute(&mut ctx), RespType::bulk_string("2"));
+    }
+
+    #[test]
+    fn script_subcommands() {
+        let mut ctx = Context::default();
+        let sha = match Script(args(&["load", "return 1"])).execute(&mut ctx) {
+            RespType::BulkString { value, .. } => value,
+            reply => panic!("unexpected reply {reply:?}"),
+        };
+
+        assert_eq!(sha, "e0e1f9fabfc9d4800c877a703b823ac0578ff8db");
+        assert_eq!(
+            Script(args(&["exists", &sha, "nope"])).execute(&mut ctx),
+            RespType::array(vec![RespType::Integer(1), RespType::Integer(0)])
+        );
+        assert_eq!(Script(args(&["flush", "async"])).execute(&mut ctx), RespType::ok());
+        assert!(!ctx.scripts.contains(&sha));
+        assert!(matches!(
+            Script(args(&["load", "return +"])).execute(&mut ctx),
+            RespType::SimpleError(Error::Custom { message }) if message.starts_with("ERR Error compiling script")
+        ));
+    }
+
+    const LIBRARY: &str = "#!lua name=lib\n\
+        redis.register_function('set', function(keys, args) return redis.call('SET', keys[1], args[1]) end)\n\
+        redis.register_function{function_name='get', callback=function(keys) return redis.call('GET', keys[1]) end, flags={'no-writes'}}";
+
+    fn function(ctx: &mut Context, values: &[&str]) -> RespType {
+        Function(args(values)).execute(ctx)
+    }
+
+    #[test]
+    fn fcall() {
+        let mut ctx = Context::default();
+
+        assert_eq!(function(&mut ctx, &["load", LIBRARY]), RespType::bulk_string("lib"));
+
+        let mut command = Fcall::new(args(&["set", "1", "key", "value"]), false);
+
+        assert_eq!(command.execute(&mut ctx), RespType::ok());
+        assert_eq!(command.propagate(&ctx).unwrap(), vec![args(&["SET", "key", "value"])]);
+        assert_eq!(
+            Fcall::new(args(&["get", "1", "key"]), true).execute(&mut ctx),
+            RespType::bulk_string("value")
+        );
+        assert_eq!(
+            Fcall::new(args(&["set", "1", "key", "value"]), true).execute(&mut ctx),
+            Error::custom("ERR Can not execute a script with write flag using *_ro command.").into()
+        );
+        assert_eq!(
+            Fcall::new(args(&["nope", "0"]), false).execute(&mut ctx),
+            Error::custom("ERR Function not found").into()
+        );
+        assert_eq!(Fcall::new(args(&["get", "1", "a", "b"]), false).keys(), args(&["a"]));
+
+        function(&mut ctx, &["load", "replace", &LIBRARY.replace("redis.call('GET', keys[1])", "redis.call('NOPE')")]);
+
+        assert_eq!(
+            Fcall::new(args(&["get", "0"]), false).execute(&mut ctx),
+            Error::custom("ERR Unknown Redis command called from script script: get, on @user_function:3.").into()
+        );
+    }
+
+    #[test]
+    fn function_subcommands() {
+        let mut ctx = Context::default();
+
+        function(&mut ctx, &["load", LIBRARY]);
+
+        let listed = |with_code: bool| {
+            let mut entries = vec![
+                (RespType::bulk_string("library_name"), RespType::bulk_string("lib")),
+                (RespType::bulk_string("engine"), RespType::bulk_string("LUA")),
+                (
+                    RespType::bulk_string("functions"),
+                    RespType::array(vec![
+                        RespType::Map(vec![
+                            (RespType::bulk_string("name"), RespType::bulk_string("set")),
+                            (RespType::bulk_string("description"), RespType::Null),
+                            (RespType::bulk_string("flags"), RespType::array(vec![])),
+                        ]),
+                        RespType::Map(vec![
+                            (RespType::bulk_string("name"), RespType::bulk_string("get")),
+                            (RespType::bulk_string("description"), RespType::Null),
+                            (RespType::bulk_string("flags"), RespType::array(vec![RespType::bulk_string("no-writes")])),
+                        ]),
+                    ]),
+                ),
+            ];
+
+            if with_code {
+                entries.push((RespType::bulk_string("library_code"), RespType::bulk_string(LIBRARY)));
+            }
+
+            RespType::array(vec![RespType::Map(entries)])
+        };
+
+        assert_eq!(function(&mut ctx, &["list"]), listed(false));
+        assert_eq!(function(&mut ctx, &["list", "withcode", "libraryname", "l*"]), listed(true));
+        assert_eq!(function(&mut ctx, &["list", "libraryname", "x*"]), RespType::array(vec![]));
+
+        let RespType::BulkString { value: payload, .. } = function(&mut ctx, &["dump"]) else {
+            panic!("expected the payload of the libraries");
+        };
+
+        assert_eq!(function(&mut ctx, &["flush"]), RespType::ok());
+        assert_eq!(function(&mut ctx, &["list"]), RespType::array(vec![]));
+        assert_eq!(function(&mut ctx, &["restore", &payload]), RespType::ok());
+        assert_eq!(
+            function(&mut ctx, &["restore", &payload, "bogus"]),
+            Error::custom("ERR Wrong restore policy given, value should be either FLUSH, APPEND or REPLACE.").into()
+        );
+        assert_eq!(function(&mut ctx, &["restore", &payload, "replace"]), RespType::ok());
+        assert_eq!(function(&mut ctx, &["delete", "lib"]), RespType::ok());
+        assert_eq!(function(&mut ctx, &["delete", "lib"]), Error::custom("ERR Library not found").into());
+        assert!(Function(args(&["load", LIBRARY])).is_write());
+        assert!(!Function(args(&["list"])).is_write());
+    }
+}
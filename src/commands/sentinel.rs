@@ -0,0 +1,411 @@
+use std::time::Duration;
+
+use crate::resp::{errors::Error, types::RespType};
+use crate::utils::context::Context;
+use crate::utils::sentinel::{self, Addr, MonitoredMaster};
+
+use super::resp_command::{RESPCommand, RESPCommandName, RESPMinMaxArgs};
+
+/// `SENTINEL <subcommand> [arg ...]`, only known in sentinel mode
+pub struct Sentinel(pub Vec<String>);
+
+impl RESPCommandName for Sentinel {
+    fn command_name(&self) -> &'static str {
+        "sentinel"
+    }
+}
+
+impl RESPMinMaxArgs for Sentinel {
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> usize {
+        usize::MAX
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for Sentinel {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if ctx.sentinel.is_none() {
+            return Error::UnknownCommand {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        let reply = self.run(ctx);
+        sentinel::publish_events(ctx);
+
+        reply
+    }
+}
+
+impl Sentinel {
+    fn run(&self, ctx: &mut Context) -> RespType {
+        let sentinel = ctx.sentinel.as_mut().expect("sentinel mode");
+        let subcommand = self.0[0].to_lowercase();
+        let args = &self.0[1..];
+
+        match (subcommand.as_str(), args.len()) {
+            ("myid", 0) => RespType::bulk_string(&sentinel.myid),
+            ("masters", 0) => RespType::array(sentinel.masters.values().map(describe_master).collect()),
+            ("master", 1) => match sentinel.masters.get(&args[0]) {
+                Some(master) => describe_master(master),
+                None => no_such_master(),
+            },
+            ("replicas" | "slaves", 1) => match sentinel.masters.get(&args[0]) {
+                Some(master) => RespType::array(describe_replicas(master)),
+                None => no_such_master(),
+            },
+            ("sentinels", 1) => match sentinel.masters.get(&args[0]) {
+                Some(master) => RespType::array(describe_sentinels(master)),
+                None => no_such_master(),
+            },
+            ("get-master-addr-by-name", 1) => match sentinel.masters.get(&args[0]) {
+                Some(master) => {
+                    let addr = master.current_addr();
+
+                    RespType::array(vec![
+                        RespType::bulk_string(&addr.host),
+                        RespType::bulk_string(addr.port.to_string()),
+                    ])
+                }
+                None => RespType::NullArray,
+            },
+            ("is-master-down-by-addr", 4) => {
+                let (Ok(port), Ok(epoch)) = (args[1].parse::<u16>(), args[2].parse::<u64>()) else {
+                    return Error::NotAnInteger.into();
+                };
+                let Some(master) = sentinel.master_by_addr(&Addr::new(&args[0], port)) else {
+                    return RespType::array(vec![
+                        RespType::Integer(0),
+                        RespType::bulk_string("*"),
+                        RespType::Integer(0),
+                    ]);
+                };
+
+                let down = master.link.is_sdown();
+                let name = master.name.clone();
+
+                // `*` only asks for the state of the master, a run id also
+                // asks for a vote in the epoch
+                let (leader, leader_epoch) = match args[3].as_str() {
+                    "*" => (None, 0),
+                    candidate => sentinel.vote(&name, epoch, candidate),
+                };
+
+                RespType::array(vec![
+                    RespType::Integer(down as i64),
+                    RespType::bulk_string(leader.unwrap_or_else(|| "*".to_string())),
+                    RespType::Integer(leader_epoch as i64),
+                ])
+            }
+            ("monitor", 4) => {
+                let Ok(port) = args[2].parse::<u16>() else {
+                    return Error::custom("ERR Invalid port").into();
+                };
+                let quorum = match args[3].parse::<i64>() {
+                    Ok(quorum) if quorum > 0 => quorum as usize,
+                    Ok(_) => return Error::custom("ERR Quorum must be 1 or greater.").into(),
+                    Err(_) => return Error::NotAnInteger.into(),
+                };
+
+                if sentinel.masters.contains_key(&args[0]) {
+                    return Error::custom("ERR Duplicated master name").into();
+                }
+
+                sentinel.monitor(MonitoredMaster::new(args[0].clone(), Addr::new(&args[1], port), quorum));
+
+                RespType::ok()
+            }
+            ("remove", 1) => match sentinel.remove(&args[0]) {
+                true => RespType::ok(),
+                false => no_such_master(),
+            },
+            ("set", n) if n >= 3 && n % 2 == 1 => {
+                let Some(master) = sentinel.masters.get_mut(&args[0]) else {
+                    return no_such_master();
+                };
+
+                for option in args[1..].chunks(2) {
+                    if let Err(err) = set_option(master, &option[0], &option[1]) {
+                        return err.into();
+                    }
+                }
+
+                RespType::ok()
+            }
+            ("failover", 1) => {
+                let Some(master) = sentinel.masters.get(&args[0]) else {
+                    return no_such_master();
+                };
+
+                if master.failover.is_some() {
+                    return Error::custom("INPROG Failover already in progress").into();
+                }
+
+                if master.select_replica().is_none() {
+                    return Error::custom("NOGOODSLAVE No suitable replica to promote").into();
+                }
+
+                sentinel.start_failover(&args[0], true);
+
+                RespType::ok()
+            }
+            ("ckquorum", 1) => {
+                let Some(master) = sentinel.masters.get(&args[0]) else {
+                    return no_such_master();
+                };
+
+                let voters = master.sentinels.len() + 1;
+                let usable = 1 + master.sentinels.values().filter(|peer| !peer.link.is_sdown()).count();
+
+                if usable < master.quorum {
+                    return Error::custom(format!(
+                        "NOQUORUM {usable} usable Sentinels. Not enough available Sentinels to reach the specified quorum for this master"
+                    ))
+                    .into();
+                }
+
+                if usable < voters / 2 + 1 {
+                    return Error::custom(format!(
+                        "NOQUORUM {usable} usable Sentinels. Not enough available Sentinels to reach the majority and authorize a failover"
+                    ))
+                    .into();
+                }
+
+                RespType::simple_string(format!(
+                    "OK {usable} usable Sentinels. Quorum and failover authorization can be reached"
+                ))
+            }
+            (
+                "myid" | "masters" | "master" | "replicas" | "slaves" | "sentinels" | "get-master-addr-by-name"
+                | "is-master-down-by-addr" | "monitor" | "remove" | "set" | "failover" | "ckquorum",
+                _,
+            ) => Error::custom(format!(
+                "ERR wrong number of arguments for 'sentinel|{subcommand}' command"
+            ))
+            .into(),
+            _ => Error::custom(format!(
+                "ERR unknown subcommand '{}'. Try SENTINEL HELP.",
+                self.0[0]
+            ))
+            .into(),
+        }
+    }
+}
+
+fn no_such_master() -> RespType {
+    Error::custom("ERR No such master with that name").into()
+}
+
+fn set_option(master: &mut MonitoredMaster, option: &str, value: &str) -> Result<(), Error> {
+    let value: u64 = match value.parse() {
+        Ok(value) if value > 0 => value,
+        _ => {
+            return Err(Error::custom(format!(
+                "ERR Invalid argument '{value}' for SENTINEL SET '{option}'"
+            )))
+        }
+    };
+
+    match option.to_lowercase().as_str() {
+        "down-after-milliseconds" => master.down_after = Duration::from_millis(value),
+        "failover-timeout" => master.failover_timeout = Duration::from_millis(value),
+        "parallel-syncs" => master.parallel_syncs = value as usize,
+        "quorum" => master.quorum = value as usize,
+        _ => return Err(Error::custom(format!("ERR Unknown option or number of arguments for SENTINEL SET '{option}'"))),
+    }
+
+    Ok(())
+}
+
+fn fields(fields: Vec<(&str, String)>) -> RespType {
+    RespType::Map(
+        fields
+            .into_iter()
+            .map(|(field, value)| (RespType::bulk_string(field), RespType::bulk_string(value)))
+            .collect(),
+    )
+}
+
+fn describe_master(master: &MonitoredMaster) -> RespType {
+    let failover_state = match &master.failover {
+        Some(failover) => failover.state.to_string(),
+        None => "none".to_string(),
+    };
+
+    fields(vec![
+        ("name", master.name.clone()),
+        ("ip", master.addr.host.clone()),
+        ("port", master.addr.port.to_string()),
+        ("runid", master.run_id.clone().unwrap_or_default()),
+        ("flags", master.flags()),
+        ("last-ok-ping-reply", master.link.last_ok_ping.elapsed().as_millis().to_string()),
+        ("num-slaves", master.replicas.len().to_string()),
+        ("num-other-sentinels", master.sentinels.len().to_string()),
+        ("quorum", master.quorum.to_string()),
+        ("config-epoch", master.config_epoch.to_string()),
+        ("down-after-milliseconds", master.down_after.as_millis().to_string()),
+        ("failover-timeout", master.failover_timeout.as_millis().to_string()),
+        ("parallel-syncs", master.parallel_syncs.to_string()),
+        ("failover-state", failover_state),
+    ])
+}
+
+fn describe_replicas(master: &MonitoredMaster) -> Vec<RespType> {
+    master
+        .replicas
+        .values()
+        .map(|replica| {
+            let flags = match replica.link.is_sdown() {
+                true => "slave,s_down",
+                false => "slave",
+            };
+            let (master_host, master_port) = match &replica.master {
+                Some(addr) => (addr.host.clone(), addr.port.to_string()),
+                None => ("?".to_string(), "0".to_string()),
+            };
+            let link_status = match replica.master_link_up {
+                true => "ok",
+                false => "err",
+            };
+
+            fields(vec![
+                ("name", replica.addr.to_string()),
+                ("ip", replica.addr.host.clone()),
+                ("port", replica.addr.port.to_string()),
+                ("runid", replica.run_id.clone().unwrap_or_default()),
+                ("flags", flags.to_string()),
+                ("last-ok-ping-reply", replica.link.last_ok_ping.elapsed().as_millis().to_string()),
+                ("master-link-status", link_status.to_string()),
+                ("master-host", master_host),
+                ("master-port", master_port),
+                ("slave-priority", replica.priority.to_string()),
+                ("slave-repl-offset", replica.repl_offset.to_string()),
+            ])
+        })
+        .collect()
+}
+
+fn describe_sentinels(master: &MonitoredMaster) -> Vec<RespType> {
+    master
+        .sentinels
+        .values()
+        .map(|peer| {
+            let flags = match peer.link.is_sdown() {
+                true => "sentinel,s_down",
+                false => "sentinel",
+            };
+
+            fields(vec![
+                ("name", peer.run_id.clone()),
+                ("ip", peer.addr.host.clone()),
+                ("port", peer.addr.port.to_string()),
+                ("runid", peer.run_id.clone()),
+                ("flags", flags.to_string()),
+                ("last-hello-message", peer.last_hello.elapsed().as_millis().to_string()),
+                ("voted-leader", peer.leader.clone().unwrap_or_else(|| "?".to_string())),
+                ("voted-leader-epoch", peer.leader_epoch.to_string()),
+            ])
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sentinel_context() -> Context {
+        Context {
+            sentinel: Some(sentinel::Sentinel::new("a".repeat(40))),
+            ..Default::default()
+        }
+    }
+
+    fn run(ctx: &mut Context, args: &[&str]) -> RespType {
+        Sentinel(args.iter().map(|arg| arg.to_string()).collect()).execute(ctx)
+    }
+
+    #[test]
+    fn unknown_outside_sentinel_mode() {
+        let mut ctx = Context::default();
+
+        assert_eq!(
+            run(&mut ctx, &["myid"]),
+            Error::UnknownCommand {
+                command: "sentinel".to_string()
+            }
+            .into()
+        );
+    }
+
+    #[test]
+    fn monitors_masters() {
+        let mut ctx = sentinel_context();
+
+        assert_eq!(run(&mut ctx, &["monitor", "mymaster", "127.0.0.1", "6379", "2"]), RespType::ok());
+        assert_eq!(
+            run(&mut ctx, &["monitor", "mymaster", "127.0.0.1", "6380", "2"]),
+            Error::custom("ERR Duplicated master name").into()
+        );
+        assert_eq!(
+            run(&mut ctx, &["monitor", "other", "127.0.0.1", "6380", "0"]),
+            Error::custom("ERR Quorum must be 1 or greater.").into()
+        );
+        assert_eq!(
+            run(&mut ctx, &["get-master-addr-by-name", "mymaster"]),
+            RespType::array(vec![RespType::bulk_string("127.0.0.1"), RespType::bulk_string("6379")])
+        );
+        assert_eq!(run(&mut ctx, &["get-master-addr-by-name", "other"]), RespType::NullArray);
+        assert_eq!(run(&mut ctx, &["set", "mymaster", "down-after-milliseconds", "1000"]), RespType::ok());
+        assert_eq!(
+            ctx.sentinel.as_ref().unwrap().masters["mymaster"].down_after,
+            Duration::from_millis(1000)
+        );
+        assert_eq!(
+            run(&mut ctx, &["failover", "mymaster"]),
+            Error::custom("NOGOODSLAVE No suitable replica to promote").into()
+        );
+        assert_eq!(run(&mut ctx, &["remove", "mymaster"]), RespType::ok());
+        assert_eq!(run(&mut ctx, &["master", "mymaster"]), no_such_master());
+    }
+
+    #[test]
+    fn answers_down_state_and_votes() {
+        let mut ctx = sentinel_context();
+        run(&mut ctx, &["monitor", "mymaster", "127.0.0.1", "6379", "2"]);
+
+        let reply = |down, leader: &str, epoch| {
+            RespType::array(vec![RespType::Integer(down), RespType::bulk_string(leader), RespType::Integer(epoch)])
+        };
+        let candidate = "b".repeat(40);
+
+        assert_eq!(run(&mut ctx, &["is-master-down-by-addr", "127.0.0.1", "6379", "1", "*"]), reply(0, "*", 0));
+
+        let master = ctx.sentinel.as_mut().unwrap().masters.get_mut("mymaster").unwrap();
+        master.link.sdown_since = Some(std::time::Instant::now());
+
+        assert_eq!(
+            run(&mut ctx, &["is-master-down-by-addr", "127.0.0.1", "6379", "1", &candidate]),
+            reply(1, &candidate, 1)
+        );
+        assert_eq!(
+            run(&mut ctx, &["is-master-down-by-addr", "127.0.0.1", "6379", "1", &"c".repeat(40)]),
+            reply(1, &candidate, 1)
+        );
+        assert_eq!(run(&mut ctx, &["is-master-down-by-addr", "127.0.0.1", "6380", "1", "*"]), reply(0, "*", 0));
+    }
+}
@@ -1,9 +1,10 @@
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use super::resp_command::{RESPCommand, RESPCommandName, RESPMinMaxArgs};
 use crate::models::StoreValue;
 use crate::resp::{errors::Error, types::RespType};
 use crate::utils::context::Context;
+use crate::utils::notifications::KeyspaceEvents;
 
 pub struct Set (
     pub Vec<String>
@@ -30,32 +31,46 @@ impl RESPMinMaxArgs for Set {
 }
 
 impl Set {
-    fn get_duration(kind: String, value: Option<&String>) -> Option<Duration> {
-        if value.is_none() {
-            return None;
+    /// Ttl set by `EX`, `PX`, `EXAT` or `PXAT`, `None` for other options
+    ///
+    /// Like Redis, the expiry time must be positive and fit in signed
+    /// milliseconds since the unix epoch.
+    fn get_duration(kind: String, value: Option<&String>) -> Result<Option<Duration>, Error> {
+        if !matches!(kind.as_str(), "ex" | "px" | "exat" | "pxat") {
+            return Ok(None);
         }
 
-        let value = value.unwrap();
-        let value = value.parse::<u64>();
+        let value = value.ok_or(Error::Syntax)?.parse::<i64>().map_err(|_| Error::NotAnInteger)?;
+        let invalid = || Error::custom("ERR invalid expire time in 'set' command");
 
-        if value.is_err() {
-            return None;
+        if value <= 0 {
+            return Err(invalid());
         }
 
-        let value = value.unwrap();
-
-        return match kind.as_str() {
-            "px" => Some(Duration::from_millis(value)),
-            "ex" => Some(Duration::from_secs(value)),
-            _ => None,
+        let millis = match kind.as_str() {
+            "ex" | "exat" => value.checked_mul(1000).ok_or_else(invalid)?,
+            _ => value,
         };
+        let now = unix_millis(SystemTime::now()) as i64;
+
+        match kind.as_str() {
+            "ex" | "px" => {
+                millis.checked_add(now).ok_or_else(invalid)?;
+
+                Ok(Some(Duration::from_millis(millis as u64)))
+            }
+            _ => Ok(Some(Duration::from_millis(millis.saturating_sub(now).max(0) as u64))),
+        }
     }
 }
 
+/// Milliseconds since the unix epoch, as used by `PEXPIREAT`
+fn unix_millis(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis()
+}
+
 impl RESPCommand for Set {
     fn execute(&mut self, ctx: &mut Context) -> RespType {
-        let store = &mut ctx.store;
-
         if self.is_invalid() {
             return Error::WrongNumberOfArguments {
                 command: self.command_name().to_string(),
@@ -63,7 +78,7 @@ impl RESPCommand for Set {
             .into();
         }
 
-        let key = self.0.get(0);
+        let key = self.0.first();
         let value = self.0.get(1);
 
         if key.is_none() || value.is_none() {
@@ -81,18 +96,51 @@ impl RESPCommand for Set {
             let opt = opt.to_lowercase();
             let kind = opt;
 
-            duration = Set::get_duration(kind, next_opt);
+            duration = match Set::get_duration(kind, next_opt) {
+                Ok(duration) => duration,
+                Err(err) => return err.into(),
+            };
         }
 
         let key = key.unwrap();
         let value = value.unwrap();
 
         let value = StoreValue::new(value.to_string(), duration);
-        let _ = store.insert(key.to_string(), value);
+        ctx.remove_if_expired(key);
+
+        if ctx.store.insert(key.to_string(), value).is_none() {
+            ctx.notify(KeyspaceEvents::NEW, "new", key);
+        }
+
+        ctx.notify(KeyspaceEvents::STRING, "set", key);
 
-        return RespType::SimpleString {
+        if duration.is_some() {
+            ctx.notify(KeyspaceEvents::GENERIC, "expire", key);
+        }
+
+        RespType::SimpleString {
             value: "OK".to_string(),
-        };
+        }
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.0.iter().take(1).cloned().collect()
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+
+    /// Relative ttls are persisted as an absolute `PEXPIREAT`, so replaying
+    /// the command later doesn't extend them
+    fn propagate(&self, ctx: &Context) -> Option<Vec<Vec<String>>> {
+        let key = &self.0[0];
+        let expires_at = ctx.store.get(key)?.expires_at()?;
+
+        Some(vec![
+            vec!["SET".to_string(), key.to_string(), self.0[1].to_string()],
+            vec!["PEXPIREAT".to_string(), key.to_string(), unix_millis(expires_at).to_string()],
+        ])
     }
 }
 
@@ -100,6 +148,7 @@ impl RESPCommand for Set {
 mod tests {
     use super::*;
     use crate::utils::context::Context;
+    use crate::utils::pubsub::Subscription;
 
     #[test]
     fn set_value() {
@@ -150,7 +199,7 @@ mod tests {
         let store_value = store.get(key.as_str());
 
         assert!(store_value.is_some());
-        let duration = Set::get_duration(px, Some(&ttl));
+        let duration = Set::get_duration(px, Some(&ttl)).unwrap();
 
         assert_eq!(store_value, Some(&StoreValue::new(value, duration)));
     }
@@ -191,5 +240,85 @@ mod tests {
             })
         );
     }
-}
 
+    #[test]
+    fn rejects_invalid_expire_times() {
+        let mut context = Context::default();
+        let invalid = "-ERR invalid expire time in 'set' command\r\n";
+
+        for (option, value) in [
+            ("EX", "0"),
+            ("PX", "-1"),
+            ("EX", "18446744073709551615"),
+            ("EX", "9223372036854775807"),
+            ("PX", "9223372036854775807"),
+            ("EXAT", "0"),
+        ] {
+            let args = ["key", "value", option, value].map(str::to_string).to_vec();
+            let reply = Set(args).execute(&mut context).to_string();
+
+            // Beyond `i64` the value is not even an integer for Redis
+            match value.parse::<i64>() {
+                Ok(_) => assert_eq!(reply, invalid, "{option} {value}"),
+                Err(_) => assert_eq!(reply, "-ERR value is not an integer or out of range\r\n"),
+            }
+        }
+
+        assert!(!context.store.contains_key("key"));
+
+        let at = (unix_millis(SystemTime::now()) + 10_000).to_string();
+        let mut set = Set(["key", "value", "PXAT", &at].map(str::to_string).to_vec());
+        assert_eq!(set.execute(&mut context), RespType::ok());
+        assert_eq!(set.propagate(&context).unwrap()[1][2], at);
+    }
+
+    #[test]
+    fn propagates_absolute_expiry() {
+        let mut context = Context::default();
+        let mut set = Set(vec!["key".to_string(), "value".to_string(), "PX".to_string(), "1000".to_string()]);
+
+        set.execute(&mut context);
+        let commands = set.propagate(&context).unwrap();
+
+        let expected = unix_millis(SystemTime::now() + Duration::from_millis(1000));
+        let pexpireat: u128 = commands[1][2].parse().unwrap();
+
+        assert_eq!(commands[0], vec!["SET", "key", "value"]);
+        assert_eq!(commands[1][..2], ["PEXPIREAT", "key"]);
+        assert!(pexpireat <= expected && pexpireat + 100 > expected);
+
+        let mut set = Set(vec!["key".to_string(), "value".to_string()]);
+        set.execute(&mut context);
+        assert_eq!(set.propagate(&context), None);
+    }
+
+    #[test]
+    fn emits_keyspace_events() {
+        let mut context = Context::default();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+
+        context.config.notify_keyspace_events = "KEA".parse().unwrap();
+        context.pubsub.subscribe(Subscription::Channel, "__keyevent@0__:set", 1, &tx);
+        context.pubsub.subscribe(Subscription::Channel, "__keyspace@0__:key", 1, &tx);
+
+        Set(vec!["key".to_string(), "value".to_string()]).execute(&mut context);
+
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            RespType::Push(vec![
+                RespType::bulk_string("message"),
+                RespType::bulk_string("__keyspace@0__:key"),
+                RespType::bulk_string("set"),
+            ])
+        );
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            RespType::Push(vec![
+                RespType::bulk_string("message"),
+                RespType::bulk_string("__keyevent@0__:set"),
+                RespType::bulk_string("key"),
+            ])
+        );
+        assert!(rx.try_recv().is_err());
+    }
+}
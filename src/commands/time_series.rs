@@ -0,0 +1,849 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::models::{aggregate, Aggregation, CompactionRule, DuplicatePolicy, StoreValue, TimeSeries, Value};
+use crate::resp::errors::Error;
+use crate::resp::types::RespType;
+use crate::utils::context::Context;
+
+use super::resp_command::{RESPCommand, RESPCommandName, RESPMinMaxArgs};
+
+/// Looks up the series stored at `key`, `WRONGTYPE` when the key holds another type
+fn get_series<'a>(ctx: &'a mut Context, key: &str) -> Result<Option<&'a mut TimeSeries>, Error> {
+    ctx.remove_if_expired(key);
+
+    match ctx.store.get_mut(key) {
+        Some(StoreValue {
+            data: Value::TimeSeries(series),
+            ..
+        }) => Ok(Some(series)),
+        Some(_) => Err(Error::WrongType),
+        None => Ok(None),
+    }
+}
+
+fn get_existing_series<'a>(ctx: &'a mut Context, key: &str) -> Result<&'a mut TimeSeries, Error> {
+    get_series(ctx, key)?.ok_or_else(|| Error::custom("ERR TSDB: the key does not exist"))
+}
+
+fn format_value(value: f64) -> RespType {
+    RespType::simple_string(value.to_string())
+}
+
+fn sample_to_resp((timestamp, value): (u64, f64)) -> RespType {
+    RespType::array(vec![RespType::Integer(timestamp as i64), format_value(value)])
+}
+
+/// Options shared by `TS.CREATE` and `TS.ADD`
+#[derive(Default)]
+struct SeriesOptions {
+    retention: Option<u64>,
+    chunk_size: Option<usize>,
+    duplicate_policy: Option<DuplicatePolicy>,
+    on_duplicate: Option<DuplicatePolicy>,
+    labels: Vec<(String, String)>,
+}
+
+impl SeriesOptions {
+    fn parse(args: &[String]) -> Result<Self, Error> {
+        let mut options = SeriesOptions::default();
+        let mut args = args.iter();
+
+        while let Some(option) = args.next() {
+            match option.to_uppercase().as_str() {
+                "RETENTION" => {
+                    let value = args.next().and_then(|v| v.parse::<u64>().ok());
+                    options.retention =
+                        Some(value.ok_or_else(|| Error::custom("ERR TSDB: invalid RETENTION value"))?);
+                }
+                "CHUNK_SIZE" => {
+                    let value = args.next().and_then(|v| v.parse::<usize>().ok());
+                    let value = value
+                        .filter(|v| (48..=1_048_576).contains(v) && v % 8 == 0)
+                        .ok_or_else(|| Error::custom("ERR TSDB: invalid CHUNK_SIZE value"))?;
+                    options.chunk_size = Some(value);
+                }
+                "ENCODING" => {
+                    // Chunks are always compressed
+                    args.next();
+                }
+                "DUPLICATE_POLICY" | "ON_DUPLICATE" => {
+                    let policy = args.next().and_then(|v| v.parse::<DuplicatePolicy>().ok());
+                    let policy =
+                        policy.ok_or_else(|| Error::custom("ERR TSDB: Unknown DUPLICATE_POLICY"))?;
+
+                    if option.eq_ignore_ascii_case("on_duplicate") {
+                        options.on_duplicate = Some(policy);
+                    } else {
+                        options.duplicate_policy = Some(policy);
+                    }
+                }
+                "LABELS" => {
+                    let rest: Vec<&String> = args.by_ref().collect();
+
+                    if rest.is_empty() || rest.len() % 2 == 1 {
+                        return Err(Error::custom("ERR TSDB: wrong number of labels"));
+                    }
+
+                    options.labels = rest
+                        .chunks(2)
+                        .map(|pair| (pair[0].to_string(), pair[1].to_string()))
+                        .collect();
+                }
+                _ => return Err(Error::custom("ERR TSDB: wrong parameters")),
+            }
+        }
+
+        Ok(options)
+    }
+
+    fn build(self, ctx: &Context) -> TimeSeries {
+        let mut series = TimeSeries::new(
+            self.retention.unwrap_or(ctx.config.ts_retention_policy),
+            self.chunk_size.unwrap_or(ctx.config.ts_chunk_size_bytes),
+            self.duplicate_policy.unwrap_or(ctx.config.ts_duplicate_policy),
+        );
+        series.labels = self.labels;
+        series
+    }
+}
+
+/// Milliseconds timestamp, non negative and at most `i64::MAX` like in RedisTimeSeries
+fn parse_timestamp(value: &str) -> Result<u64, Error> {
+    if value == "*" {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        return Ok(now.as_millis() as u64);
+    }
+
+    match value.parse::<i64>() {
+        Ok(timestamp) if timestamp >= 0 => Ok(timestamp as u64),
+        _ => Err(Error::custom("ERR TSDB: invalid timestamp")),
+    }
+}
+
+fn parse_value(value: &str) -> Result<f64, Error> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|v| v.is_finite())
+        .ok_or_else(|| Error::custom("ERR TSDB: invalid value"))
+}
+
+pub struct TsCreate(pub Vec<String>);
+
+impl RESPCommandName for TsCreate {
+    fn command_name(&self) -> &'static str {
+        "ts.create"
+    }
+}
+
+impl RESPMinMaxArgs for TsCreate {
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> usize {
+        usize::MAX
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for TsCreate {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        let options = match SeriesOptions::parse(&self.0[1..]) {
+            Ok(options) => options,
+            Err(err) => return err.into(),
+        };
+
+        let key = &self.0[0];
+        ctx.remove_if_expired(key);
+
+        if ctx.store.contains_key(key) {
+            return Error::custom("ERR TSDB: key already exists").into();
+        }
+
+        let series = options.build(ctx);
+        ctx.store
+            .insert(key.to_string(), Value::TimeSeries(series).into());
+
+        RespType::ok()
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.0.iter().take(1).cloned().collect()
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+}
+
+pub struct TsAdd(pub Vec<String>);
+
+impl RESPCommandName for TsAdd {
+    fn command_name(&self) -> &'static str {
+        "ts.add"
+    }
+}
+
+impl RESPMinMaxArgs for TsAdd {
+    fn min_args(&self) -> usize {
+        3
+    }
+
+    fn max_args(&self) -> usize {
+        usize::MAX
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for TsAdd {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        let key = &self.0[0];
+        let parsed = parse_timestamp(&self.0[1]).and_then(|timestamp| {
+            let value = parse_value(&self.0[2])?;
+            let options = SeriesOptions::parse(&self.0[3..])?;
+
+            Ok((timestamp, value, options))
+        });
+
+        let (timestamp, value, options) = match parsed {
+            Ok(parsed) => parsed,
+            Err(err) => return err.into(),
+        };
+        let on_duplicate = options.on_duplicate;
+
+        match get_series(ctx, key) {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                let series = options.build(ctx);
+                ctx.store
+                    .insert(key.to_string(), Value::TimeSeries(series).into());
+            }
+            Err(err) => return err.into(),
+        }
+
+        let series = get_series(ctx, key)
+            .ok()
+            .flatten()
+            .expect("series was just created");
+
+        if let Err(message) = series.add(timestamp, value, on_duplicate) {
+            return Error::custom(message).into();
+        }
+
+        let compacted = series.compact(timestamp);
+        ctx.store.touch(key);
+
+        for sample in compacted {
+            if let Ok(Some(dest)) = get_series(ctx, &sample.dest) {
+                let _ = dest.add(sample.timestamp, sample.value, Some(DuplicatePolicy::Last));
+                ctx.store.touch(&sample.dest);
+            }
+        }
+
+        // `*` is resolved once, the command is persisted with the actual timestamp
+        self.0[1] = timestamp.to_string();
+
+        RespType::Integer(timestamp as i64)
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.0.iter().take(1).cloned().collect()
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+
+    fn propagate(&self, _ctx: &Context) -> Option<Vec<Vec<String>>> {
+        let command = std::iter::once("TS.ADD".to_string()).chain(self.0.iter().cloned());
+
+        Some(vec![command.collect()])
+    }
+}
+
+pub struct TsGet(pub Vec<String>);
+
+impl RESPCommandName for TsGet {
+    fn command_name(&self) -> &'static str {
+        "ts.get"
+    }
+}
+
+impl RESPMinMaxArgs for TsGet {
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> usize {
+        1
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for TsGet {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        match get_existing_series(ctx, &self.0[0]) {
+            Ok(series) => match series.last_sample() {
+                Some(sample) => sample_to_resp(sample),
+                None => RespType::array(vec![]),
+            },
+            Err(err) => err.into(),
+        }
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.0.iter().take(1).cloned().collect()
+    }
+}
+
+/// Shared implementation of `TS.RANGE` and `TS.REVRANGE`
+fn range(args: &[String], ctx: &mut Context, reverse: bool) -> RespType {
+    let parse_bound = |value: &str| match value {
+        "-" => Ok(0),
+        "+" => Ok(i64::MAX as u64),
+        _ => parse_timestamp(value),
+    };
+
+    let from = match parse_bound(&args[1]) {
+        Ok(from) => from,
+        Err(err) => return err.into(),
+    };
+    let to = match parse_bound(&args[2]) {
+        Ok(to) => to,
+        Err(err) => return err.into(),
+    };
+
+    let mut count: Option<usize> = None;
+    let mut aggregation: Option<(Aggregation, u64)> = None;
+    let mut options = args[3..].iter();
+
+    while let Some(option) = options.next() {
+        match option.to_uppercase().as_str() {
+            "COUNT" => match options.next().and_then(|v| v.parse::<usize>().ok()) {
+                Some(value) => count = Some(value),
+                None => return Error::custom("ERR TSDB: Couldn't parse COUNT").into(),
+            },
+            "AGGREGATION" => {
+                let kind = options.next().and_then(|v| v.parse::<Aggregation>().ok());
+                let bucket = options
+                    .next()
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .filter(|b| *b > 0);
+
+                match (kind, bucket) {
+                    (Some(kind), Some(bucket)) => aggregation = Some((kind, bucket)),
+                    (None, _) => return Error::custom("ERR TSDB: Unknown aggregation type").into(),
+                    (_, None) => {
+                        return Error::custom("ERR TSDB: bucketDuration must be greater than zero").into()
+                    }
+                }
+            }
+            _ => return Error::custom("ERR TSDB: wrong parameters").into(),
+        }
+    }
+
+    let series = match get_existing_series(ctx, &args[0]) {
+        Ok(series) => series,
+        Err(err) => return err.into(),
+    };
+
+    let mut samples = series.range(from, to);
+
+    if let Some((kind, bucket)) = aggregation {
+        samples = aggregate(&samples, kind, bucket);
+    }
+
+    if reverse {
+        samples.reverse();
+    }
+
+    if let Some(count) = count {
+        samples.truncate(count);
+    }
+
+    RespType::array(samples.into_iter().map(sample_to_resp).collect())
+}
+
+pub struct TsRange(pub Vec<String>);
+
+impl RESPCommandName for TsRange {
+    fn command_name(&self) -> &'static str {
+        "ts.range"
+    }
+}
+
+impl RESPMinMaxArgs for TsRange {
+    fn min_args(&self) -> usize {
+        3
+    }
+
+    fn max_args(&self) -> usize {
+        8
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for TsRange {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        range(&self.0, ctx, false)
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.0.iter().take(1).cloned().collect()
+    }
+}
+
+pub struct TsRevRange(pub Vec<String>);
+
+impl RESPCommandName for TsRevRange {
+    fn command_name(&self) -> &'static str {
+        "ts.revrange"
+    }
+}
+
+impl RESPMinMaxArgs for TsRevRange {
+    fn min_args(&self) -> usize {
+        3
+    }
+
+    fn max_args(&self) -> usize {
+        8
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for TsRevRange {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        range(&self.0, ctx, true)
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.0.iter().take(1).cloned().collect()
+    }
+}
+
+pub struct TsCreateRule(pub Vec<String>);
+
+impl RESPCommandName for TsCreateRule {
+    fn command_name(&self) -> &'static str {
+        "ts.createrule"
+    }
+}
+
+impl RESPMinMaxArgs for TsCreateRule {
+    fn min_args(&self) -> usize {
+        5
+    }
+
+    fn max_args(&self) -> usize {
+        5
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for TsCreateRule {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        let (source, dest) = (&self.0[0], &self.0[1]);
+
+        if !self.0[2].eq_ignore_ascii_case("aggregation") {
+            return Error::custom("ERR TSDB: wrong parameters").into();
+        }
+
+        let aggregation = match self.0[3].parse::<Aggregation>() {
+            Ok(aggregation) => aggregation,
+            Err(_) => return Error::custom("ERR TSDB: Unknown aggregation type").into(),
+        };
+        let bucket = match self.0[4].parse::<u64>() {
+            Ok(bucket) if bucket > 0 => bucket,
+            _ => return Error::custom("ERR TSDB: bucketDuration must be greater than zero").into(),
+        };
+
+        if source == dest {
+            return Error::custom("ERR TSDB: the source key and destination key should be different").into();
+        }
+
+        match get_existing_series(ctx, dest) {
+            Ok(series) if series.source.is_some() => {
+                return Error::custom("ERR TSDB: the destination key already has a src rule").into()
+            }
+            Ok(series) if !series.rules.is_empty() => {
+                return Error::custom("ERR TSDB: the destination key already has a dst rule").into()
+            }
+            Ok(series) => series.source = Some(source.to_string()),
+            Err(err) => return err.into(),
+        }
+
+        match get_existing_series(ctx, source) {
+            Ok(series) if series.source.is_some() => {
+                if let Ok(dest) = get_existing_series(ctx, dest) {
+                    dest.source = None;
+                }
+
+                Error::custom("ERR TSDB: the source key already has a src rule").into()
+            }
+            Ok(series) => {
+                series
+                    .rules
+                    .push(CompactionRule::new(dest.to_string(), aggregation, bucket));
+                ctx.store.touch(source);
+                ctx.store.touch(dest);
+
+                RespType::ok()
+            }
+            Err(err) => {
+                if let Ok(dest) = get_existing_series(ctx, dest) {
+                    dest.source = None;
+                }
+
+                err.into()
+            }
+        }
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.0.iter().take(2).cloned().collect()
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+}
+
+pub struct TsDeleteRule(pub Vec<String>);
+
+impl RESPCommandName for TsDeleteRule {
+    fn command_name(&self) -> &'static str {
+        "ts.deleterule"
+    }
+}
+
+impl RESPMinMaxArgs for TsDeleteRule {
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn max_args(&self) -> usize {
+        2
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for TsDeleteRule {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        let (source, dest) = (&self.0[0], &self.0[1]);
+
+        let removed = match get_existing_series(ctx, source) {
+            Ok(series) => {
+                let before = series.rules.len();
+                series.rules.retain(|rule| &rule.dest != dest);
+                before != series.rules.len()
+            }
+            Err(err) => return err.into(),
+        };
+
+        if !removed {
+            return Error::custom("ERR TSDB: compaction rule does not exist").into();
+        }
+
+        if let Ok(dest) = get_existing_series(ctx, dest) {
+            dest.source = None;
+        }
+
+        ctx.store.touch(source);
+        ctx.store.touch(dest);
+
+        RespType::ok()
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.0.iter().take(2).cloned().collect()
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+}
+
+pub struct TsInfo(pub Vec<String>);
+
+impl RESPCommandName for TsInfo {
+    fn command_name(&self) -> &'static str {
+        "ts.info"
+    }
+}
+
+impl RESPMinMaxArgs for TsInfo {
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> usize {
+        1
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for TsInfo {
+    fn execute(&mut self, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        let series = match get_existing_series(ctx, &self.0[0]) {
+            Ok(series) => series,
+            Err(err) => return err.into(),
+        };
+
+        let labels = series
+            .labels
+            .iter()
+            .map(|(name, value)| {
+                RespType::array(vec![RespType::bulk_string(name), RespType::bulk_string(value)])
+            })
+            .collect();
+
+        let rules = series
+            .rules
+            .iter()
+            .map(|rule| {
+                RespType::array(vec![
+                    RespType::bulk_string(&rule.dest),
+                    RespType::Integer(rule.bucket as i64),
+                    RespType::simple_string(rule.aggregation.to_string().to_uppercase()),
+                ])
+            })
+            .collect();
+
+        let source = match &series.source {
+            Some(source) => RespType::bulk_string(source),
+            None => RespType::Null,
+        };
+
+        let fields = vec![
+            ("totalSamples", RespType::Integer(series.total_samples() as i64)),
+            ("memoryUsage", RespType::Integer(series.memory_usage() as i64)),
+            ("firstTimestamp", RespType::Integer(series.first_timestamp().unwrap_or(0) as i64)),
+            ("lastTimestamp", RespType::Integer(series.last_timestamp().unwrap_or(0) as i64)),
+            ("retentionTime", RespType::Integer(series.retention as i64)),
+            ("chunkCount", RespType::Integer(series.chunks.len() as i64)),
+            ("chunkSize", RespType::Integer(series.chunk_size as i64)),
+            ("chunkType", RespType::simple_string("compressed")),
+            ("duplicatePolicy", RespType::simple_string(series.duplicate_policy.to_string())),
+            ("labels", RespType::array(labels)),
+            ("sourceKey", source),
+            ("rules", RespType::array(rules)),
+        ];
+
+        RespType::array(
+            fields
+                .into_iter()
+                .flat_map(|(name, value)| [RespType::simple_string(name), value])
+                .collect(),
+        )
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.0.iter().take(1).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    fn samples(values: &[(i64, &str)]) -> RespType {
+        RespType::array(
+            values
+                .iter()
+                .map(|(ts, v)| RespType::array(vec![RespType::Integer(*ts), RespType::simple_string(*v)]))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn add_and_range() {
+        let mut ctx = Context::default();
+
+        for (ts, value) in [("10", "1"), ("20", "2.5"), ("30", "3")] {
+            TsAdd(args(&["ts", ts, value])).execute(&mut ctx);
+        }
+
+        let response = TsRange(args(&["ts", "-", "+"])).execute(&mut ctx);
+        assert_eq!(response, samples(&[(10, "1"), (20, "2.5"), (30, "3")]));
+
+        let response = TsRevRange(args(&["ts", "15", "+", "COUNT", "1"])).execute(&mut ctx);
+        assert_eq!(response, samples(&[(30, "3")]));
+
+        let response = TsGet(args(&["ts"])).execute(&mut ctx);
+        assert_eq!(response, RespType::array(vec![RespType::Integer(30), RespType::simple_string("3")]));
+    }
+
+    #[test]
+    fn rejects_timestamps_out_of_range() {
+        let mut ctx = Context::default();
+        assert_eq!(
+            TsAdd(args(&["ts", "-1", "1"])).execute(&mut ctx),
+            Error::custom("ERR TSDB: invalid timestamp").into()
+        );
+        assert_eq!(
+            TsAdd(args(&["ts", "9223372036854775808", "1"])).execute(&mut ctx),
+            Error::custom("ERR TSDB: invalid timestamp").into()
+        );
+        assert_eq!(
+            TsAdd(args(&["ts", "9223372036854775807", "1"])).execute(&mut ctx),
+            RespType::Integer(i64::MAX)
+        );
+    }
+
+    #[test]
+    fn range_with_aggregation() {
+        let mut ctx = Context::default();
+
+        for (ts, value) in [("0", "1"), ("5", "3"), ("10", "10"), ("12", "20")] {
+            TsAdd(args(&["ts", ts, value])).execute(&mut ctx);
+        }
+
+        let response = TsRange(args(&["ts", "-", "+", "AGGREGATION", "avg", "10"])).execute(&mut ctx);
+        assert_eq!(response, samples(&[(0, "2"), (10, "15")]));
+
+        let response = TsRange(args(&["ts", "-", "+", "AGGREGATION", "max", "100"])).execute(&mut ctx);
+        assert_eq!(response, samples(&[(0, "20")]));
+    }
+
+    #[test]
+    fn duplicate_policy() {
+        let mut ctx = Context::default();
+
+        TsCreate(args(&["ts", "DUPLICATE_POLICY", "SUM"])).execute(&mut ctx);
+        TsAdd(args(&["ts", "10", "1"])).execute(&mut ctx);
+        TsAdd(args(&["ts", "10", "2"])).execute(&mut ctx);
+
+        let response = TsRange(args(&["ts", "-", "+"])).execute(&mut ctx);
+        assert_eq!(response, samples(&[(10, "3")]));
+
+        let response = TsAdd(args(&["ts", "10", "2", "ON_DUPLICATE", "BLOCK"])).execute(&mut ctx);
+        assert!(matches!(response, RespType::SimpleError(_)));
+    }
+
+    #[test]
+    fn retention() {
+        let mut ctx = Context::default();
+
+        TsCreate(args(&["ts", "RETENTION", "100"])).execute(&mut ctx);
+        TsAdd(args(&["ts", "1000", "1"])).execute(&mut ctx);
+
+        let response = TsAdd(args(&["ts", "10", "1"])).execute(&mut ctx);
+        assert_eq!(response, Error::custom("ERR TSDB: Timestamp is older than retention").into());
+    }
+
+    #[test]
+    fn compaction_rule() {
+        let mut ctx = Context::default();
+
+        TsCreate(args(&["src"])).execute(&mut ctx);
+        TsCreate(args(&["dest"])).execute(&mut ctx);
+
+        let response = TsCreateRule(args(&["src", "dest", "AGGREGATION", "sum", "10"])).execute(&mut ctx);
+        assert_eq!(response, RespType::ok());
+
+        for (ts, value) in [("1", "1"), ("5", "2"), ("11", "4"), ("25", "1")] {
+            TsAdd(args(&["src", ts, value])).execute(&mut ctx);
+        }
+
+        let response = TsRange(args(&["dest", "-", "+"])).execute(&mut ctx);
+        assert_eq!(response, samples(&[(0, "3"), (10, "4")]));
+
+        let response = TsCreateRule(args(&["dest", "src", "AGGREGATION", "sum", "10"])).execute(&mut ctx);
+        assert!(matches!(response, RespType::SimpleError(_)));
+    }
+
+    #[test]
+    fn missing_key() {
+        let mut ctx = Context::default();
+
+        let response = TsRange(args(&["missing", "-", "+"])).execute(&mut ctx);
+
+        assert_eq!(response, Error::custom("ERR TSDB: the key does not exist").into());
+    }
+}
@@ -0,0 +1,48 @@
+use crate::resp::{errors::Error, types::RespType};
+use crate::utils::context::Context;
+use crate::utils::session::SessionState;
+
+use super::resp_command::{RESPCommand, RESPCommandName, RESPMinMaxArgs};
+
+/// `UNWATCH`, forgets the keys registered with `WATCH`
+pub struct Unwatch(pub Vec<String>);
+
+impl RESPCommandName for Unwatch {
+    fn command_name(&self) -> &'static str {
+        "unwatch"
+    }
+}
+
+impl RESPMinMaxArgs for Unwatch {
+    fn min_args(&self) -> usize {
+        0
+    }
+
+    fn max_args(&self) -> usize {
+        0
+    }
+
+    fn args_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl RESPCommand for Unwatch {
+    /// Only runs for a connection, scripts can't call it
+    fn execute(&mut self, _: &mut Context) -> RespType {
+        Error::custom("ERR This Redis command is not allowed from script").into()
+    }
+
+    fn execute_for(&mut self, session: &mut SessionState, ctx: &mut Context) -> RespType {
+        if self.is_invalid() {
+            return Error::WrongNumberOfArguments {
+                command: self.command_name().to_string(),
+            }
+            .into();
+        }
+
+        session.unwatch(ctx);
+
+        RespType::ok()
+    }
+}
@@ -0,0 +1,143 @@
+//! Syntax tree of a parsed script
+//!
+//! Variables are resolved while parsing: locals are slots of the frame of
+//! their function, upvalues index the variables a closure captured, anything
+//! else is a global.
+
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct FunctionBody {
+    /// Slots of the parameters
+    pub params: Vec<usize>,
+    pub is_vararg: bool,
+    /// Name of every local slot, for error messages
+    pub locals: Vec<String>,
+    pub upvalues: Vec<Upvalue>,
+    pub body: Block,
+}
+
+/// Where a closure finds a captured variable when it is created
+#[derive(Debug)]
+pub struct Upvalue {
+    pub name: String,
+    /// A local slot of the enclosing function, or one of its upvalues
+    pub from_local: bool,
+    pub index: usize,
+}
+
+pub type Block = Vec<Statement>;
+
+#[derive(Debug)]
+pub struct Statement {
+    pub line: u32,
+    pub kind: Stat,
+}
+
+#[derive(Debug)]
+pub enum Stat {
+    /// A function call, the only expression allowed as a statement
+    Call(Expr),
+    Local {
+        slots: Vec<usize>,
+        values: Vec<Expr>,
+    },
+    LocalFunction {
+        slot: usize,
+        function: Arc<FunctionBody>,
+    },
+    Assign {
+        targets: Vec<Expr>,
+        values: Vec<Expr>,
+    },
+    If {
+        branches: Vec<(Expr, Block)>,
+        otherwise: Option<Block>,
+    },
+    While {
+        condition: Expr,
+        body: Block,
+    },
+    Repeat {
+        body: Block,
+        condition: Expr,
+    },
+    NumericFor {
+        slot: usize,
+        start: Expr,
+        end: Expr,
+        step: Option<Expr>,
+        body: Block,
+    },
+    GenericFor {
+        slots: Vec<usize>,
+        values: Vec<Expr>,
+        body: Block,
+    },
+    Do(Block),
+    Return(Vec<Expr>),
+    Break,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    Concat,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    Len,
+}
+
+#[derive(Debug)]
+pub enum Expr {
+    Nil,
+    True,
+    False,
+    Number(f64),
+    String(Arc<[u8]>),
+    Vararg,
+    Local(usize),
+    Upvalue(usize),
+    Global(Arc<[u8]>),
+    Index(Box<Expr>, Box<Expr>),
+    Call(Box<Expr>, Vec<Expr>),
+    /// `object:name(args)`
+    Method(Box<Expr>, Arc<[u8]>, Vec<Expr>),
+    Function(Arc<FunctionBody>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Table(Vec<Field>),
+    /// Parentheses keep only the first value of calls and `...`
+    Paren(Box<Expr>),
+}
+
+#[derive(Debug)]
+pub enum Field {
+    /// Next integer key
+    Positional(Expr),
+    Keyed(Expr, Expr),
+}
+
+impl Expr {
+    /// Whether the expression may evaluate to several values
+    pub fn is_multi(&self) -> bool {
+        matches!(self, Expr::Call(..) | Expr::Method(..) | Expr::Vararg)
+    }
+}
@@ -0,0 +1,752 @@
+//! Tree walking evaluation of parsed scripts
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use super::ast::{BinaryOp, Block, Expr, Field, FunctionBody, Stat, UnaryOp};
+use super::value::{format_number, Closure, Function, LuaError, Table, TableRef, Value};
+
+/// Deepest nesting of function calls
+const MAX_CALLS: usize = 200;
+
+/// Statements run between two checks of [`Host::interrupted`]
+const CHECK_INTERVAL: u32 = 1000;
+
+/// What scripts reach outside of the interpreter
+pub trait Host {
+    /// Runs the function `name` of a library registered with
+    /// [`Lua::register`]
+    fn invoke(&mut self, name: &str, args: Vec<Value>) -> Result<Vec<Value>, LuaError>;
+
+    /// Checked regularly while a script runs, the error returned stops it
+    fn interrupted(&mut self) -> Option<LuaError>;
+}
+
+/// State of the interpreter running a script
+pub struct Lua<'a> {
+    pub globals: TableRef,
+    /// Library the methods of strings come from
+    pub strings: TableRef,
+    pub host: &'a mut dyn Host,
+    /// Name of the chunk in error messages
+    chunk: String,
+    /// Line of the statement being run
+    line: u32,
+    /// Lines the running functions were called from
+    call_lines: Vec<u32>,
+    steps: u32,
+    /// State of `math.random`, the same for every run
+    pub(crate) random: u64,
+}
+
+/// Locals of a running function
+struct Frame<'f> {
+    locals: Vec<Option<Rc<RefCell<Value>>>>,
+    upvalues: &'f [Rc<RefCell<Value>>],
+    varargs: Vec<Value>,
+    body: &'f FunctionBody,
+}
+
+enum Flow {
+    Normal,
+    Break,
+    Return(Vec<Value>),
+}
+
+/// Where an assignment stores its value
+enum Place {
+    Local(usize),
+    Upvalue(usize),
+    Index(Value, Value),
+}
+
+fn new_cell(value: Value) -> Rc<RefCell<Value>> {
+    Rc::new(RefCell::new(value))
+}
+
+impl<'a> Lua<'a> {
+    /// Interpreter with the standard libraries loaded
+    pub fn new(host: &'a mut dyn Host, chunk: &str) -> Self {
+        let mut lua = Self {
+            globals: Rc::new(RefCell::new(Table::new())),
+            strings: Rc::new(RefCell::new(Table::new())),
+            host,
+            chunk: chunk.to_string(),
+            line: 0,
+            call_lines: Vec::new(),
+            steps: 0,
+            random: 0,
+        };
+
+        super::stdlib::open(&mut lua);
+
+        lua
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.globals.borrow_mut().set_str(name, value);
+    }
+
+    /// Adds the global table `library` with functions implemented by the host
+    pub fn register(&mut self, library: &str, functions: &[&'static str]) -> TableRef {
+        let table = Rc::new(RefCell::new(Table::new()));
+
+        for name in functions {
+            let name: &'static str = name;
+
+            table
+                .borrow_mut()
+                .set_str(name, Value::native(name, move |lua, args| lua.host.invoke(name, args)));
+        }
+
+        self.set_global(library, Value::Table(table.clone()));
+
+        table
+    }
+
+    /// Runs a parsed chunk with `args` as `...`
+    pub fn run(&mut self, chunk: &Arc<FunctionBody>, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+        let closure = Rc::new(Closure {
+            body: chunk.clone(),
+            upvalues: Vec::new(),
+        });
+
+        self.call_closure(&closure, args)
+    }
+
+    /// Error raised at the running line, with its position in the message
+    pub fn error(&self, message: impl std::fmt::Display) -> LuaError {
+        self.error_at(1, message)
+    }
+
+    /// Error with the position of the function `level` calls up the stack,
+    /// as `error` raises
+    pub fn error_at(&self, level: usize, message: impl std::fmt::Display) -> LuaError {
+        let line = match level {
+            0 | 1 => Some(self.line),
+            level => self.call_lines.len().checked_sub(level - 1).map(|index| self.call_lines[index]),
+        };
+        let message = match line {
+            Some(line) if line > 0 => format!("{}:{line}: {message}", self.chunk),
+            _ => message.to_string(),
+        };
+
+        LuaError {
+            value: Value::string(message),
+            line: self.line,
+            fatal: false,
+        }
+    }
+
+    fn type_error(&self, action: &str, value: &Value, variable: Option<String>) -> LuaError {
+        match variable {
+            Some(variable) => self.error(format!("attempt to {action} {variable} (a {} value)", value.type_name())),
+            None => self.error(format!("attempt to {action} a {} value", value.type_name())),
+        }
+    }
+
+    /// Calls a function value, or a table with a `__call` metamethod
+    pub fn call(&mut self, function: &Value, mut args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+        match function {
+            Value::Function(Function::Lua(closure)) => self.call_closure(&closure.clone(), args),
+            Value::Function(Function::Native(native)) => {
+                let native = native.clone();
+
+                (native.call)(self, args).map_err(|mut err| {
+                    if err.line == 0 {
+                        err.line = self.line;
+                    }
+
+                    err
+                })
+            }
+            value => match self.metamethod(value, "__call") {
+                Some(handler) => {
+                    args.insert(0, value.clone());
+                    self.call(&handler, args)
+                }
+                None => Err(self.type_error("call", value, None)),
+            },
+        }
+    }
+
+    fn call_closure(&mut self, closure: &Rc<Closure>, mut args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+        if self.call_lines.len() >= MAX_CALLS {
+            return Err(self.error("stack overflow"));
+        }
+
+        let body = &closure.body;
+        let mut frame = Frame {
+            locals: vec![None; body.locals.len()],
+            upvalues: &closure.upvalues,
+            varargs: Vec::new(),
+            body,
+        };
+
+        if body.is_vararg && args.len() > body.params.len() {
+            frame.varargs = args.split_off(body.params.len());
+        }
+
+        let mut args = args.into_iter();
+
+        for slot in &body.params {
+            frame.locals[*slot] = Some(new_cell(args.next().unwrap_or_default()));
+        }
+
+        self.call_lines.push(self.line);
+
+        let flow = self.exec_block(&mut frame, &body.body);
+
+        self.line = self.call_lines.pop().unwrap();
+
+        match flow? {
+            Flow::Return(values) => Ok(values),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    fn metamethod(&self, value: &Value, event: &str) -> Option<Value> {
+        let Value::Table(table) = value else {
+            return None;
+        };
+        let metatable = table.borrow().metatable.clone()?;
+        let handler = metatable.borrow().get_str(event);
+
+        (!handler.is_nil()).then_some(handler)
+    }
+
+    /// `object[key]`, following `__index` metamethods
+    pub fn index(&mut self, object: &Value, key: &Value) -> Result<Value, LuaError> {
+        let mut object = object.clone();
+
+        for _ in 0..100 {
+            let handler = match &object {
+                Value::Table(table) => {
+                    let value = table.borrow().get(key);
+
+                    if !value.is_nil() {
+                        return Ok(value);
+                    }
+
+                    match self.metamethod(&object, "__index") {
+                        Some(handler) => handler,
+                        None => return Ok(Value::Nil),
+                    }
+                }
+                Value::String(_) => return Ok(self.strings.borrow().get(key)),
+                value => return Err(self.type_error("index", value, None)),
+            };
+
+            if let Value::Function(_) = handler {
+                let values = self.call(&handler, vec![object, key.clone()])?;
+
+                return Ok(values.into_iter().next().unwrap_or_default());
+            }
+
+            object = handler;
+        }
+
+        Err(self.error("loop in gettable"))
+    }
+
+    /// `object[key] = value`, following `__newindex` metamethods
+    pub fn set_index(&mut self, object: &Value, key: Value, value: Value) -> Result<(), LuaError> {
+        let mut object = object.clone();
+
+        for _ in 0..100 {
+            let Value::Table(table) = &object else {
+                return Err(self.type_error("index", &object, None));
+            };
+
+            if table.borrow().readonly {
+                return Err(self.error("Attempt to modify a readonly table"));
+            }
+
+            let handler = match self.metamethod(&object, "__newindex") {
+                Some(handler) if table.borrow().get(&key).is_nil() => handler,
+                _ => {
+                    return table.borrow_mut().set(key, value).map_err(|message| self.error(message));
+                }
+            };
+
+            if let Value::Function(_) = handler {
+                self.call(&handler, vec![object, key, value])?;
+
+                return Ok(());
+            }
+
+            object = handler;
+        }
+
+        Err(self.error("loop in settable"))
+    }
+
+    /// `tostring`, using the `__tostring` metamethod of tables
+    pub fn tostring(&mut self, value: &Value) -> Result<Rc<[u8]>, LuaError> {
+        if let Some(handler) = self.metamethod(value, "__tostring") {
+            let result = self.call(&handler, vec![value.clone()])?;
+
+            return match result.into_iter().next() {
+                Some(Value::String(s)) => Ok(s),
+                Some(Value::Number(n)) => Ok(Rc::from(format_number(n).as_bytes())),
+                _ => Err(self.error("'__tostring' must return a string")),
+            };
+        }
+
+        Ok(match value {
+            Value::Nil => Rc::from(&b"nil"[..]),
+            Value::Boolean(b) => Rc::from(b.to_string().as_bytes()),
+            Value::Number(n) => Rc::from(format_number(*n).as_bytes()),
+            Value::String(s) => s.clone(),
+            value => Rc::from(format!("{}: {:#x}", value.type_name(), value.address()).as_bytes()),
+        })
+    }
+
+    fn step(&mut self) -> Result<(), LuaError> {
+        self.steps += 1;
+
+        if self.steps == CHECK_INTERVAL {
+            self.steps = 0;
+
+            if let Some(mut err) = self.host.interrupted() {
+                err.line = self.line;
+                err.fatal = true;
+
+                return Err(err);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn exec_block(&mut self, frame: &mut Frame, block: &Block) -> Result<Flow, LuaError> {
+        // Counted on its own so that loops with an empty body are interrupted
+        self.step()?;
+
+        for statement in block {
+            self.line = statement.line;
+            self.step()?;
+
+            match &statement.kind {
+                Stat::Call(call) => {
+                    self.eval_multi(frame, call)?;
+                }
+                Stat::Local { slots, values } => {
+                    let mut values = self.eval_list(frame, values)?.into_iter();
+
+                    for slot in slots {
+                        frame.locals[*slot] = Some(new_cell(values.next().unwrap_or_default()));
+                    }
+                }
+                Stat::LocalFunction { slot, function } => {
+                    let cell = new_cell(Value::Nil);
+
+                    // The function sees itself, for recursion
+                    frame.locals[*slot] = Some(cell.clone());
+                    *cell.borrow_mut() = self.closure(frame, function);
+                }
+                Stat::Assign { targets, values } => self.assign(frame, targets, values)?,
+                Stat::If { branches, otherwise } => {
+                    let mut chosen = otherwise.as_ref();
+
+                    for (condition, body) in branches {
+                        if self.eval(frame, condition)?.truthy() {
+                            chosen = Some(body);
+                            break;
+                        }
+                    }
+
+                    if let Some(body) = chosen {
+                        match self.exec_block(frame, body)? {
+                            Flow::Normal => {}
+                            flow => return Ok(flow),
+                        }
+                    }
+                }
+                Stat::While { condition, body } => {
+                    while self.eval(frame, condition)?.truthy() {
+                        match self.exec_block(frame, body)? {
+                            Flow::Normal => {}
+                            Flow::Break => break,
+                            flow => return Ok(flow),
+                        }
+
+                        self.line = statement.line;
+                    }
+                }
+                Stat::Repeat { body, condition } => loop {
+                    match self.exec_block(frame, body)? {
+                        Flow::Normal => {}
+                        Flow::Break => break,
+                        flow => return Ok(flow),
+                    }
+
+                    if self.eval(frame, condition)?.truthy() {
+                        break;
+                    }
+                },
+                Stat::NumericFor {
+                    slot,
+                    start,
+                    end,
+                    step,
+                    body,
+                } => {
+                    let start = self.eval(frame, start)?.to_number();
+                    let end = self.eval(frame, end)?.to_number();
+                    let step = match step {
+                        Some(step) => self.eval(frame, step)?.to_number(),
+                        None => Some(1.0),
+                    };
+                    let Some(mut value) = start else {
+                        return Err(self.error("'for' initial value must be a number"));
+                    };
+                    let Some(end) = end else {
+                        return Err(self.error("'for' limit must be a number"));
+                    };
+                    let Some(step) = step else {
+                        return Err(self.error("'for' step must be a number"));
+                    };
+
+                    while (step > 0.0 && value <= end) || (step <= 0.0 && value >= end) {
+                        frame.locals[*slot] = Some(new_cell(Value::Number(value)));
+
+                        match self.exec_block(frame, body)? {
+                            Flow::Normal => {}
+                            Flow::Break => break,
+                            flow => return Ok(flow),
+                        }
+
+                        value += step;
+                    }
+                }
+                Stat::GenericFor { slots, values, body } => {
+                    let mut values = self.eval_list(frame, values)?.into_iter();
+                    let iterator = values.next().unwrap_or_default();
+                    let state = values.next().unwrap_or_default();
+                    let mut control = values.next().unwrap_or_default();
+
+                    loop {
+                        let mut results = self.call(&iterator, vec![state.clone(), control.clone()])?.into_iter();
+
+                        control = results.next().unwrap_or_default();
+
+                        if control.is_nil() {
+                            break;
+                        }
+
+                        frame.locals[slots[0]] = Some(new_cell(control.clone()));
+
+                        for slot in &slots[1..] {
+                            frame.locals[*slot] = Some(new_cell(results.next().unwrap_or_default()));
+                        }
+
+                        match self.exec_block(frame, body)? {
+                            Flow::Normal => {}
+                            Flow::Break => break,
+                            flow => return Ok(flow),
+                        }
+
+                        self.line = statement.line;
+                    }
+                }
+                Stat::Do(body) => match self.exec_block(frame, body)? {
+                    Flow::Normal => {}
+                    flow => return Ok(flow),
+                },
+                Stat::Return(values) => return Ok(Flow::Return(self.eval_list(frame, values)?)),
+                Stat::Break => return Ok(Flow::Break),
+            }
+        }
+
+        Ok(Flow::Normal)
+    }
+
+    fn assign(&mut self, frame: &mut Frame, targets: &[Expr], values: &[Expr]) -> Result<(), LuaError> {
+        let mut places = Vec::with_capacity(targets.len());
+
+        for target in targets {
+            places.push(match target {
+                Expr::Local(slot) => Place::Local(*slot),
+                Expr::Upvalue(index) => Place::Upvalue(*index),
+                Expr::Global(name) => Place::Index(Value::Table(self.globals.clone()), Value::string(&name[..])),
+                Expr::Index(object, key) => {
+                    let object_value = self.eval(frame, object)?;
+
+                    if !matches!(object_value, Value::Table(_)) {
+                        return Err(self.type_error("index", &object_value, variable_name(frame, object)));
+                    }
+
+                    Place::Index(object_value, self.eval(frame, key)?)
+                }
+                _ => unreachable!("the parser only allows variables as targets"),
+            });
+        }
+
+        let mut values = self.eval_list(frame, values)?.into_iter();
+
+        for place in places {
+            let value = values.next().unwrap_or_default();
+
+            match place {
+                Place::Local(slot) => match &frame.locals[slot] {
+                    Some(cell) => *cell.borrow_mut() = value,
+                    None => frame.locals[slot] = Some(new_cell(value)),
+                },
+                Place::Upvalue(index) => *frame.upvalues[index].borrow_mut() = value,
+                Place::Index(object, key) => self.set_index(&object, key, value)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn closure(&self, frame: &mut Frame, body: &Arc<FunctionBody>) -> Value {
+        let upvalues = body
+            .upvalues
+            .iter()
+            .map(|upvalue| match upvalue.from_local {
+                true => frame.locals[upvalue.index].get_or_insert_with(|| new_cell(Value::Nil)).clone(),
+                false => frame.upvalues[upvalue.index].clone(),
+            })
+            .collect();
+
+        Value::Function(Function::Lua(Rc::new(Closure {
+            body: body.clone(),
+            upvalues,
+        })))
+    }
+
+    /// Values of an expression list, the last expression giving all its
+    /// values
+    fn eval_list(&mut self, frame: &mut Frame, exprs: &[Expr]) -> Result<Vec<Value>, LuaError> {
+        let mut values = Vec::with_capacity(exprs.len());
+
+        for (position, expr) in exprs.iter().enumerate() {
+            if position + 1 == exprs.len() && expr.is_multi() {
+                values.extend(self.eval_multi(frame, expr)?);
+            } else {
+                values.push(self.eval(frame, expr)?);
+            }
+        }
+
+        Ok(values)
+    }
+
+    /// Every value of calls and `...`
+    fn eval_multi(&mut self, frame: &mut Frame, expr: &Expr) -> Result<Vec<Value>, LuaError> {
+        match expr {
+            Expr::Call(function, args) => {
+                let function_value = self.eval(frame, function)?;
+
+                if !matches!(function_value, Value::Function(_)) && self.metamethod(&function_value, "__call").is_none() {
+                    return Err(self.type_error("call", &function_value, variable_name(frame, function)));
+                }
+
+                let args = self.eval_list(frame, args)?;
+
+                self.call(&function_value, args)
+            }
+            Expr::Method(object, name, args) => {
+                let object_value = self.eval(frame, object)?;
+
+                if !matches!(object_value, Value::Table(_) | Value::String(_)) {
+                    return Err(self.type_error("index", &object_value, variable_name(frame, object)));
+                }
+
+                let function = self.index(&object_value, &Value::string(&name[..]))?;
+
+                if !matches!(function, Value::Function(_)) && self.metamethod(&function, "__call").is_none() {
+                    let method = format!("method '{}'", String::from_utf8_lossy(name));
+
+                    return Err(self.type_error("call", &function, Some(method)));
+                }
+
+                let mut values = vec![object_value];
+
+                values.extend(self.eval_list(frame, args)?);
+                self.call(&function, values)
+            }
+            Expr::Vararg => Ok(frame.varargs.clone()),
+            expr => Ok(vec![self.eval(frame, expr)?]),
+        }
+    }
+
+    fn eval(&mut self, frame: &mut Frame, expr: &Expr) -> Result<Value, LuaError> {
+        Ok(match expr {
+            Expr::Nil => Value::Nil,
+            Expr::True => Value::Boolean(true),
+            Expr::False => Value::Boolean(false),
+            Expr::Number(n) => Value::Number(*n),
+            Expr::String(s) => Value::string(&s[..]),
+            Expr::Vararg => frame.varargs.first().cloned().unwrap_or_default(),
+            Expr::Local(slot) => match &frame.locals[*slot] {
+                Some(cell) => cell.borrow().clone(),
+                None => Value::Nil,
+            },
+            Expr::Upvalue(index) => frame.upvalues[*index].borrow().clone(),
+            Expr::Global(name) => {
+                let globals = Value::Table(self.globals.clone());
+
+                self.index(&globals, &Value::string(&name[..]))?
+            }
+            Expr::Index(object, key) => {
+                let object_value = self.eval(frame, object)?;
+
+                if !matches!(object_value, Value::Table(_) | Value::String(_)) {
+                    return Err(self.type_error("index", &object_value, variable_name(frame, object)));
+                }
+
+                let key = self.eval(frame, key)?;
+
+                self.index(&object_value, &key)?
+            }
+            Expr::Call(..) | Expr::Method(..) => self.eval_multi(frame, expr)?.into_iter().next().unwrap_or_default(),
+            Expr::Function(body) => self.closure(frame, body),
+            Expr::Paren(inner) => self.eval(frame, inner)?,
+            Expr::Table(fields) => self.table(frame, fields)?,
+            Expr::Unary(op, operand) => {
+                let value = self.eval(frame, operand)?;
+
+                match op {
+                    UnaryOp::Not => Value::Boolean(!value.truthy()),
+                    UnaryOp::Neg => match value.to_number() {
+                        Some(n) => Value::Number(-n),
+                        None => {
+                            return Err(self.type_error("perform arithmetic on", &value, variable_name(frame, operand)))
+                        }
+                    },
+                    UnaryOp::Len => match &value {
+                        Value::String(s) => Value::Number(s.len() as f64),
+                        Value::Table(table) => Value::Number(table.borrow().len() as f64),
+                        value => return Err(self.type_error("get length of", value, variable_name(frame, operand))),
+                    },
+                }
+            }
+            Expr::Binary(BinaryOp::And, left, right) => {
+                let left = self.eval(frame, left)?;
+
+                if left.truthy() {
+                    self.eval(frame, right)?
+                } else {
+                    left
+                }
+            }
+            Expr::Binary(BinaryOp::Or, left, right) => {
+                let left = self.eval(frame, left)?;
+
+                if left.truthy() {
+                    left
+                } else {
+                    self.eval(frame, right)?
+                }
+            }
+            Expr::Binary(op, left_expr, right_expr) => {
+                let left = self.eval(frame, left_expr)?;
+                let right = self.eval(frame, right_expr)?;
+
+                self.binary(*op, left, right, || {
+                    (variable_name(frame, left_expr), variable_name(frame, right_expr))
+                })?
+            }
+        })
+    }
+
+    fn binary(
+        &mut self,
+        op: BinaryOp,
+        left: Value,
+        right: Value,
+        names: impl FnOnce() -> (Option<String>, Option<String>),
+    ) -> Result<Value, LuaError> {
+        let arithmetic = |a: f64, b: f64| match op {
+            BinaryOp::Add => a + b,
+            BinaryOp::Sub => a - b,
+            BinaryOp::Mul => a * b,
+            BinaryOp::Div => a / b,
+            BinaryOp::Mod => a - (a / b).floor() * b,
+            _ => a.powf(b),
+        };
+
+        match op {
+            BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod | BinaryOp::Pow => {
+                match (left.to_number(), right.to_number()) {
+                    (Some(a), Some(b)) => Ok(Value::Number(arithmetic(a, b))),
+                    (Some(_), None) => Err(self.type_error("perform arithmetic on", &right, names().1)),
+                    _ => Err(self.type_error("perform arithmetic on", &left, names().0)),
+                }
+            }
+            BinaryOp::Concat => match (left.to_bytes(), right.to_bytes()) {
+                (Some(a), Some(b)) => {
+                    let mut joined = Vec::with_capacity(a.len() + b.len());
+
+                    joined.extend_from_slice(&a);
+                    joined.extend_from_slice(&b);
+
+                    Ok(Value::String(Rc::from(joined)))
+                }
+                (Some(_), None) => Err(self.type_error("concatenate", &right, names().1)),
+                _ => Err(self.type_error("concatenate", &left, names().0)),
+            },
+            BinaryOp::Eq => Ok(Value::Boolean(left == right)),
+            BinaryOp::Ne => Ok(Value::Boolean(left != right)),
+            BinaryOp::Lt => Ok(Value::Boolean(self.less_than(&left, &right)?)),
+            BinaryOp::Gt => Ok(Value::Boolean(self.less_than(&right, &left)?)),
+            BinaryOp::Le => Ok(Value::Boolean(!self.less_than(&right, &left)?)),
+            BinaryOp::Ge => Ok(Value::Boolean(!self.less_than(&left, &right)?)),
+            BinaryOp::And | BinaryOp::Or => unreachable!("short circuited"),
+        }
+    }
+
+    /// `a < b` for two numbers or two strings
+    pub fn less_than(&self, a: &Value, b: &Value) -> Result<bool, LuaError> {
+        match (a, b) {
+            (Value::Number(a), Value::Number(b)) => Ok(a < b),
+            (Value::String(a), Value::String(b)) => Ok(a < b),
+            (a, b) if a.type_name() == b.type_name() => {
+                Err(self.error(format!("attempt to compare two {} values", a.type_name())))
+            }
+            (a, b) => Err(self.error(format!("attempt to compare {} with {}", a.type_name(), b.type_name()))),
+        }
+    }
+
+    fn table(&mut self, frame: &mut Frame, fields: &[Field]) -> Result<Value, LuaError> {
+        let mut table = Table::new();
+        let mut positional = Vec::new();
+
+        for (position, field) in fields.iter().enumerate() {
+            match field {
+                Field::Positional(expr) if position + 1 == fields.len() && expr.is_multi() => {
+                    positional.extend(self.eval_multi(frame, expr)?);
+                }
+                Field::Positional(expr) => positional.push(self.eval(frame, expr)?),
+                Field::Keyed(key, value) => {
+                    let key = self.eval(frame, key)?;
+                    let value = self.eval(frame, value)?;
+
+                    table.set(key, value).map_err(|message| self.error(message))?;
+                }
+            }
+        }
+
+        // Positional fields win over keyed ones for the same key
+        table.set_array(positional);
+
+        Ok(Value::table(table))
+    }
+}
+
+/// How a variable is named in error messages, like `local 'x'`
+fn variable_name(frame: &Frame, expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::Global(name) => Some(format!("global '{}'", String::from_utf8_lossy(name))),
+        Expr::Local(slot) => Some(format!("local '{}'", frame.body.locals[*slot])),
+        Expr::Upvalue(index) => Some(format!("upvalue '{}'", frame.body.upvalues[*index].name)),
+        Expr::Index(_, key) => match &**key {
+            Expr::String(name) => Some(format!("field '{}'", String::from_utf8_lossy(name))),
+            _ => None,
+        },
+        _ => None,
+    }
+}
@@ -0,0 +1,503 @@
+//! Splits the source of a script into tokens
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Name(String),
+    String(Vec<u8>),
+    Number(f64),
+    And,
+    Break,
+    Do,
+    Else,
+    Elseif,
+    End,
+    False,
+    For,
+    Function,
+    If,
+    In,
+    Local,
+    Nil,
+    Not,
+    Or,
+    Repeat,
+    Return,
+    Then,
+    True,
+    Until,
+    While,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    Caret,
+    Hash,
+    Eq,
+    Ne,
+    Le,
+    Ge,
+    Lt,
+    Gt,
+    Assign,
+    LeftParen,
+    RightParen,
+    LeftBrace,
+    RightBrace,
+    LeftBracket,
+    RightBracket,
+    Semicolon,
+    Colon,
+    Comma,
+    Dot,
+    Concat,
+    Dots,
+    Eof,
+}
+
+impl Token {
+    /// How the token appears in error messages, after `near`
+    pub fn describe(&self) -> String {
+        match self {
+            Token::Name(name) => name.clone(),
+            Token::String(value) => value.iter().map(|byte| *byte as char).collect(),
+            Token::Number(value) => super::value::format_number(*value),
+            Token::Eof => "<eof>".to_string(),
+            token => token.symbol().to_string(),
+        }
+    }
+
+    fn symbol(&self) -> &'static str {
+        match self {
+            Token::And => "and",
+            Token::Break => "break",
+            Token::Do => "do",
+            Token::Else => "else",
+            Token::Elseif => "elseif",
+            Token::End => "end",
+            Token::False => "false",
+            Token::For => "for",
+            Token::Function => "function",
+            Token::If => "if",
+            Token::In => "in",
+            Token::Local => "local",
+            Token::Nil => "nil",
+            Token::Not => "not",
+            Token::Or => "or",
+            Token::Repeat => "repeat",
+            Token::Return => "return",
+            Token::Then => "then",
+            Token::True => "true",
+            Token::Until => "until",
+            Token::While => "while",
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::Star => "*",
+            Token::Slash => "/",
+            Token::Percent => "%",
+            Token::Caret => "^",
+            Token::Hash => "#",
+            Token::Eq => "==",
+            Token::Ne => "~=",
+            Token::Le => "<=",
+            Token::Ge => ">=",
+            Token::Lt => "<",
+            Token::Gt => ">",
+            Token::Assign => "=",
+            Token::LeftParen => "(",
+            Token::RightParen => ")",
+            Token::LeftBrace => "{",
+            Token::RightBrace => "}",
+            Token::LeftBracket => "[",
+            Token::RightBracket => "]",
+            Token::Semicolon => ";",
+            Token::Colon => ":",
+            Token::Comma => ",",
+            Token::Dot => ".",
+            Token::Concat => "..",
+            Token::Dots => "...",
+            Token::Name(_) | Token::String(_) | Token::Number(_) | Token::Eof => "",
+        }
+    }
+}
+
+fn keyword(name: &str) -> Option<Token> {
+    Some(match name {
+        "and" => Token::And,
+        "break" => Token::Break,
+        "do" => Token::Do,
+        "else" => Token::Else,
+        "elseif" => Token::Elseif,
+        "end" => Token::End,
+        "false" => Token::False,
+        "for" => Token::For,
+        "function" => Token::Function,
+        "if" => Token::If,
+        "in" => Token::In,
+        "local" => Token::Local,
+        "nil" => Token::Nil,
+        "not" => Token::Not,
+        "or" => Token::Or,
+        "repeat" => Token::Repeat,
+        "return" => Token::Return,
+        "then" => Token::Then,
+        "true" => Token::True,
+        "until" => Token::Until,
+        "while" => Token::While,
+        _ => return None,
+    })
+}
+
+/// Tokens of `source` with the line they start on, ending with [`Token::Eof`]
+///
+/// Errors are reported as `chunk:line: message`, like the ones of the parser.
+pub fn tokenize(source: &[u8], chunk: &str) -> Result<Vec<(Token, u32)>, String> {
+    let mut lexer = Lexer {
+        source,
+        position: 0,
+        line: 1,
+        chunk,
+    };
+    let mut tokens = Vec::new();
+
+    loop {
+        let token = lexer.next()?;
+        let done = token == Token::Eof;
+
+        tokens.push((token, lexer.line));
+
+        if done {
+            return Ok(tokens);
+        }
+    }
+}
+
+struct Lexer<'a> {
+    source: &'a [u8],
+    position: usize,
+    line: u32,
+    chunk: &'a str,
+}
+
+impl Lexer<'_> {
+    fn peek(&self) -> u8 {
+        self.source.get(self.position).copied().unwrap_or(0)
+    }
+
+    fn peek_at(&self, offset: usize) -> u8 {
+        self.source.get(self.position + offset).copied().unwrap_or(0)
+    }
+
+    fn at_end(&self) -> bool {
+        self.position >= self.source.len()
+    }
+
+    fn error(&self, message: &str, near: &str) -> String {
+        format!("{}:{}: {message} near '{near}'", self.chunk, self.line)
+    }
+
+    fn newline(&mut self) {
+        let first = self.peek();
+
+        self.position += 1;
+
+        // \r\n and \n\r count as one line break
+        if matches!(self.peek(), b'\r' | b'\n') && self.peek() != first {
+            self.position += 1;
+        }
+
+        self.line += 1;
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        loop {
+            if self.at_end() {
+                return Ok(Token::Eof);
+            }
+
+            let c = self.peek();
+
+            match c {
+                b'\n' | b'\r' => self.newline(),
+                b' ' | b'\t' | 0x0b | 0x0c => self.position += 1,
+                b'-' if self.peek_at(1) == b'-' => {
+                    self.position += 2;
+
+                    if self.peek() == b'[' {
+                        if let Some(level) = self.long_bracket_level() {
+                            self.long_string(level, "comment")?;
+                            continue;
+                        }
+                    }
+
+                    while !self.at_end() && !matches!(self.peek(), b'\n' | b'\r') {
+                        self.position += 1;
+                    }
+                }
+                b'[' => {
+                    return match self.long_bracket_level() {
+                        Some(level) => Ok(Token::String(self.long_string(level, "string")?)),
+                        None => {
+                            self.position += 1;
+                            Ok(Token::LeftBracket)
+                        }
+                    };
+                }
+                b'"' | b'\'' => return self.string(c),
+                b'.' if self.peek_at(1).is_ascii_digit() => return self.number(),
+                b'0'..=b'9' => return self.number(),
+                c if c.is_ascii_alphabetic() || c == b'_' => {
+                    let start = self.position;
+
+                    while self.peek().is_ascii_alphanumeric() || self.peek() == b'_' {
+                        self.position += 1;
+                    }
+
+                    let name = String::from_utf8_lossy(&self.source[start..self.position]).to_string();
+
+                    return Ok(keyword(&name).unwrap_or(Token::Name(name)));
+                }
+                _ => return self.symbol(),
+            }
+        }
+    }
+
+    fn symbol(&mut self) -> Result<Token, String> {
+        let c = self.peek();
+        let next = self.peek_at(1);
+        let (token, len) = match (c, next) {
+            (b'=', b'=') => (Token::Eq, 2),
+            (b'~', b'=') => (Token::Ne, 2),
+            (b'<', b'=') => (Token::Le, 2),
+            (b'>', b'=') => (Token::Ge, 2),
+            (b'.', b'.') if self.peek_at(2) == b'.' => (Token::Dots, 3),
+            (b'.', b'.') => (Token::Concat, 2),
+            (b'+', _) => (Token::Plus, 1),
+            (b'-', _) => (Token::Minus, 1),
+            (b'*', _) => (Token::Star, 1),
+            (b'/', _) => (Token::Slash, 1),
+            (b'%', _) => (Token::Percent, 1),
+            (b'^', _) => (Token::Caret, 1),
+            (b'#', _) => (Token::Hash, 1),
+            (b'<', _) => (Token::Lt, 1),
+            (b'>', _) => (Token::Gt, 1),
+            (b'=', _) => (Token::Assign, 1),
+            (b'(', _) => (Token::LeftParen, 1),
+            (b')', _) => (Token::RightParen, 1),
+            (b'{', _) => (Token::LeftBrace, 1),
+            (b'}', _) => (Token::RightBrace, 1),
+            (b']', _) => (Token::RightBracket, 1),
+            (b';', _) => (Token::Semicolon, 1),
+            (b':', _) => (Token::Colon, 1),
+            (b',', _) => (Token::Comma, 1),
+            (b'.', _) => (Token::Dot, 1),
+            _ => return Err(self.error("unexpected symbol", &(c as char).to_string())),
+        };
+
+        self.position += len;
+
+        Ok(token)
+    }
+
+    /// Level of the long bracket `[==[` starting here, if it is one
+    fn long_bracket_level(&self) -> Option<usize> {
+        let mut level = 0;
+
+        while self.peek_at(1 + level) == b'=' {
+            level += 1;
+        }
+
+        (self.peek_at(1 + level) == b'[').then_some(level)
+    }
+
+    fn long_string(&mut self, level: usize, what: &str) -> Result<Vec<u8>, String> {
+        self.position += level + 2;
+
+        // A line break right after the opening bracket is skipped
+        if matches!(self.peek(), b'\n' | b'\r') {
+            self.newline();
+        }
+
+        let mut value = Vec::new();
+
+        loop {
+            if self.at_end() {
+                return Err(self.error(&format!("unfinished long {what}"), "<eof>"));
+            }
+
+            match self.peek() {
+                b']' if (1..=level).all(|i| self.peek_at(i) == b'=') && self.peek_at(level + 1) == b']' => {
+                    self.position += level + 2;
+
+                    return Ok(value);
+                }
+                b'\n' | b'\r' => {
+                    self.newline();
+                    value.push(b'\n');
+                }
+                c => {
+                    value.push(c);
+                    self.position += 1;
+                }
+            }
+        }
+    }
+
+    fn string(&mut self, quote: u8) -> Result<Token, String> {
+        let start = self.position;
+        let mut value = Vec::new();
+
+        self.position += 1;
+
+        loop {
+            if self.at_end() {
+                return Err(self.error("unfinished string", "<eof>"));
+            }
+
+            match self.peek() {
+                c if c == quote => {
+                    self.position += 1;
+
+                    return Ok(Token::String(value));
+                }
+                b'\n' | b'\r' => {
+                    let near = String::from_utf8_lossy(&self.source[start..self.position]).to_string();
+
+                    return Err(self.error("unfinished string", &near));
+                }
+                b'\\' => {
+                    self.position += 1;
+
+                    let escaped = match self.peek() {
+                        b'n' => b'\n',
+                        b't' => b'\t',
+                        b'r' => b'\r',
+                        b'a' => 0x07,
+                        b'b' => 0x08,
+                        b'f' => 0x0c,
+                        b'v' => 0x0b,
+                        b'\\' => b'\\',
+                        b'"' => b'"',
+                        b'\'' => b'\'',
+                        b'\n' | b'\r' => {
+                            self.newline();
+                            value.push(b'\n');
+                            continue;
+                        }
+                        b'x' if self.peek_at(1).is_ascii_hexdigit() && self.peek_at(2).is_ascii_hexdigit() => {
+                            let digits = &self.source[self.position + 1..self.position + 3];
+                            let byte = u8::from_str_radix(std::str::from_utf8(digits).unwrap(), 16).unwrap();
+
+                            self.position += 3;
+                            value.push(byte);
+                            continue;
+                        }
+                        c if c.is_ascii_digit() => {
+                            let mut code = 0u32;
+                            let mut digits = 0;
+
+                            while digits < 3 && self.peek().is_ascii_digit() {
+                                code = code * 10 + (self.peek() - b'0') as u32;
+                                self.position += 1;
+                                digits += 1;
+                            }
+
+                            if code > 255 {
+                                let near = String::from_utf8_lossy(&self.source[start..self.position]).to_string();
+
+                                return Err(self.error("escape sequence too large", &near));
+                            }
+
+                            value.push(code as u8);
+                            continue;
+                        }
+                        0 if self.at_end() => continue,
+                        // Unknown escapes stand for the character itself
+                        c => c,
+                    };
+
+                    self.position += 1;
+                    value.push(escaped);
+                }
+                c => {
+                    value.push(c);
+                    self.position += 1;
+                }
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Token, String> {
+        let start = self.position;
+
+        if self.peek() == b'0' && matches!(self.peek_at(1), b'x' | b'X') {
+            self.position += 2;
+        }
+
+        loop {
+            match self.peek() {
+                b'e' | b'E' if matches!(self.peek_at(1), b'+' | b'-') && !self.is_hex(start) => self.position += 2,
+                c if c.is_ascii_alphanumeric() || c == b'.' || c == b'_' => self.position += 1,
+                _ => break,
+            }
+        }
+
+        let text = String::from_utf8_lossy(&self.source[start..self.position]).to_string();
+
+        match super::value::parse_number(&text) {
+            Some(value) => Ok(Token::Number(value)),
+            None => Err(self.error("malformed number", &text)),
+        }
+    }
+
+    fn is_hex(&self, start: usize) -> bool {
+        self.source[start..].starts_with(b"0x") || self.source[start..].starts_with(b"0X")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(source: &str) -> Vec<Token> {
+        tokenize(source.as_bytes(), "test").unwrap().into_iter().map(|(token, _)| token).collect()
+    }
+
+    #[test]
+    fn tokenizes_scripts() {
+        assert_eq!(
+            tokens("local x = 0x1F + 1.5e2 -- comment\nreturn x..'a\\n\\65'"),
+            vec![
+                Token::Local,
+                Token::Name("x".to_string()),
+                Token::Assign,
+                Token::Number(31.0),
+                Token::Plus,
+                Token::Number(150.0),
+                Token::Return,
+                Token::Name("x".to_string()),
+                Token::Concat,
+                Token::String(b"a\nA".to_vec()),
+                Token::Eof,
+            ]
+        );
+        assert_eq!(
+            tokens("--[==[ long\ncomment ]==] x = [[\nline]] ..."),
+            vec![
+                Token::Name("x".to_string()),
+                Token::Assign,
+                Token::String(b"line".to_vec()),
+                Token::Dots,
+                Token::Eof,
+            ]
+        );
+        assert_eq!(
+            tokenize(b"x = 'open", "user_script").unwrap_err(),
+            "user_script:1: unfinished string near '<eof>'"
+        );
+        assert_eq!(
+            tokenize(b"\n\nx = 3x", "user_script").unwrap_err(),
+            "user_script:3: malformed number near '3x'"
+        );
+    }
+}
@@ -0,0 +1,18 @@
+//! Interpreter for the Lua 5.1 scripts of `EVAL`
+//!
+//! Scripts are parsed into a syntax tree which is evaluated directly. The
+//! server reaches in through [`Host`], the libraries of [`stdlib`] cover
+//! what Redis exposes to scripts apart from the `redis` table.
+
+mod ast;
+mod interpreter;
+mod lexer;
+mod parser;
+mod pattern;
+mod stdlib;
+mod value;
+
+pub use ast::FunctionBody;
+pub use interpreter::{Host, Lua};
+pub use parser::parse;
+pub use value::{LuaError, Table, TableRef, Value};
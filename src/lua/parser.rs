@@ -0,0 +1,870 @@
+//! Recursive descent parser turning tokens into a [`FunctionBody`]
+
+use std::sync::Arc;
+
+use super::ast::{BinaryOp, Block, Expr, Field, FunctionBody, Stat, Statement, UnaryOp, Upvalue};
+use super::lexer::{tokenize, Token};
+
+/// Deepest nesting of blocks and expressions
+const MAX_LEVELS: usize = 200;
+
+/// Priority of unary operators, above every binary one but `^`
+const UNARY_PRIORITY: u8 = 8;
+
+/// Parses `source` as the body of a vararg function
+///
+/// Errors are the messages of the Lua compiler, `chunk:line: message near
+/// 'token'`.
+pub fn parse(source: &[u8], chunk: &str) -> Result<Arc<FunctionBody>, String> {
+    let mut parser = Parser {
+        tokens: tokenize(source, chunk)?,
+        position: 0,
+        chunk,
+        functions: vec![FunctionState::new(true)],
+        levels: 0,
+    };
+
+    parser.open_block();
+
+    let body = parser.block()?;
+
+    if parser.peek() != &Token::Eof {
+        return Err(parser.error_near("'<eof>' expected"));
+    }
+
+    let state = parser.functions.pop().unwrap();
+
+    Ok(Arc::new(FunctionBody {
+        params: Vec::new(),
+        is_vararg: true,
+        locals: state.locals,
+        upvalues: state.upvalues,
+        body,
+    }))
+}
+
+/// Scopes of the function being parsed
+struct FunctionState {
+    /// Names of the locals visible in each open block and their slots
+    blocks: Vec<Vec<(String, usize)>>,
+    locals: Vec<String>,
+    upvalues: Vec<Upvalue>,
+    is_vararg: bool,
+    loops: usize,
+}
+
+impl FunctionState {
+    fn new(is_vararg: bool) -> Self {
+        Self {
+            blocks: Vec::new(),
+            locals: Vec::new(),
+            upvalues: Vec::new(),
+            is_vararg,
+            loops: 0,
+        }
+    }
+}
+
+enum Variable {
+    Local(usize),
+    Upvalue(usize),
+}
+
+struct Parser<'a> {
+    tokens: Vec<(Token, u32)>,
+    position: usize,
+    chunk: &'a str,
+    functions: Vec<FunctionState>,
+    levels: usize,
+}
+
+fn binary_op(token: &Token) -> Option<(BinaryOp, u8, u8)> {
+    // Left and right priorities, right associative operators bind less on
+    // their right
+    Some(match token {
+        Token::Or => (BinaryOp::Or, 1, 1),
+        Token::And => (BinaryOp::And, 2, 2),
+        Token::Lt => (BinaryOp::Lt, 3, 3),
+        Token::Gt => (BinaryOp::Gt, 3, 3),
+        Token::Le => (BinaryOp::Le, 3, 3),
+        Token::Ge => (BinaryOp::Ge, 3, 3),
+        Token::Ne => (BinaryOp::Ne, 3, 3),
+        Token::Eq => (BinaryOp::Eq, 3, 3),
+        Token::Concat => (BinaryOp::Concat, 5, 4),
+        Token::Plus => (BinaryOp::Add, 6, 6),
+        Token::Minus => (BinaryOp::Sub, 6, 6),
+        Token::Star => (BinaryOp::Mul, 7, 7),
+        Token::Slash => (BinaryOp::Div, 7, 7),
+        Token::Percent => (BinaryOp::Mod, 7, 7),
+        Token::Caret => (BinaryOp::Pow, 10, 9),
+        _ => return None,
+    })
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position].0
+    }
+
+    fn peek_next(&self) -> &Token {
+        &self.tokens[(self.position + 1).min(self.tokens.len() - 1)].0
+    }
+
+    fn line(&self) -> u32 {
+        self.tokens[self.position].1
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.position].0.clone();
+
+        if self.position + 1 < self.tokens.len() {
+            self.position += 1;
+        }
+
+        token
+    }
+
+    fn accept(&mut self, token: &Token) -> bool {
+        if self.peek() == token {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn error_near(&self, message: &str) -> String {
+        format!("{}:{}: {message} near '{}'", self.chunk, self.line(), self.peek().describe())
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), String> {
+        if self.accept(&token) {
+            Ok(())
+        } else {
+            Err(self.error_near(&format!("'{}' expected", token.describe())))
+        }
+    }
+
+    /// Expects the token closing the construct `opening` started on `line`
+    fn expect_closing(&mut self, token: Token, opening: Token, line: u32) -> Result<(), String> {
+        if self.accept(&token) {
+            return Ok(());
+        }
+
+        if line == self.line() {
+            return Err(self.error_near(&format!("'{}' expected", token.describe())));
+        }
+
+        Err(self.error_near(&format!(
+            "'{}' expected (to close '{}' at line {line})",
+            token.describe(),
+            opening.describe()
+        )))
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Token::Name(name) => {
+                let name = name.clone();
+                self.advance();
+                Ok(name)
+            }
+            _ => Err(self.error_near("<name> expected")),
+        }
+    }
+
+    fn enter(&mut self) -> Result<(), String> {
+        self.levels += 1;
+
+        if self.levels > MAX_LEVELS {
+            return Err(self.error_near("chunk has too many syntax levels"));
+        }
+
+        Ok(())
+    }
+
+    fn leave(&mut self) {
+        self.levels -= 1;
+    }
+
+    fn function(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().unwrap()
+    }
+
+    fn open_block(&mut self) {
+        self.function().blocks.push(Vec::new());
+    }
+
+    fn close_block(&mut self) {
+        self.function().blocks.pop();
+    }
+
+    /// New local visible from now on in the current block
+    fn declare(&mut self, name: String) -> usize {
+        let function = self.function();
+        let slot = function.locals.len();
+
+        function.locals.push(name.clone());
+        function.blocks.last_mut().unwrap().push((name, slot));
+
+        slot
+    }
+
+    fn variable(&mut self, name: String) -> Expr {
+        let level = self.functions.len() - 1;
+
+        match self.find(level, &name) {
+            Some(Variable::Local(slot)) => Expr::Local(slot),
+            Some(Variable::Upvalue(index)) => Expr::Upvalue(index),
+            None => Expr::Global(Arc::from(name.as_bytes())),
+        }
+    }
+
+    /// Finds `name` in the function at `level`, capturing it from the
+    /// enclosing functions when needed
+    fn find(&mut self, level: usize, name: &str) -> Option<Variable> {
+        let function = &self.functions[level];
+
+        for block in function.blocks.iter().rev() {
+            if let Some((_, slot)) = block.iter().rev().find(|(local, _)| local == name) {
+                return Some(Variable::Local(*slot));
+            }
+        }
+
+        if let Some(index) = function.upvalues.iter().position(|upvalue| upvalue.name == name) {
+            return Some(Variable::Upvalue(index));
+        }
+
+        if level == 0 {
+            return None;
+        }
+
+        let (from_local, index) = match self.find(level - 1, name)? {
+            Variable::Local(slot) => (true, slot),
+            Variable::Upvalue(index) => (false, index),
+        };
+        let upvalues = &mut self.functions[level].upvalues;
+
+        upvalues.push(Upvalue {
+            name: name.to_string(),
+            from_local,
+            index,
+        });
+
+        Some(Variable::Upvalue(upvalues.len() - 1))
+    }
+
+    fn block_follows(&self) -> bool {
+        matches!(
+            self.peek(),
+            Token::Else | Token::Elseif | Token::End | Token::Until | Token::Eof
+        )
+    }
+
+    /// Statements up to the end of the block, the caller opens and closes
+    /// its scope
+    fn block(&mut self) -> Result<Block, String> {
+        self.enter()?;
+
+        let mut statements = Vec::new();
+
+        while !self.block_follows() {
+            if self.accept(&Token::Semicolon) {
+                continue;
+            }
+
+            let line = self.line();
+
+            if self.accept(&Token::Return) {
+                let values = if self.block_follows() || self.peek() == &Token::Semicolon {
+                    Vec::new()
+                } else {
+                    self.expression_list()?
+                };
+
+                self.accept(&Token::Semicolon);
+                statements.push(Statement {
+                    line,
+                    kind: Stat::Return(values),
+                });
+
+                if !self.block_follows() {
+                    return Err(self.error_near("'<eof>' expected"));
+                }
+
+                break;
+            }
+
+            if self.accept(&Token::Break) {
+                if self.function().loops == 0 {
+                    return Err(format!("{}:{line}: no loop to break near '{}'", self.chunk, self.peek().describe()));
+                }
+
+                statements.push(Statement { line, kind: Stat::Break });
+                self.accept(&Token::Semicolon);
+
+                if !self.block_follows() {
+                    return Err(self.error_near("'end' expected"));
+                }
+
+                break;
+            }
+
+            let kind = self.statement()?;
+
+            statements.push(Statement { line, kind });
+        }
+
+        self.leave();
+
+        Ok(statements)
+    }
+
+    fn scoped_block(&mut self) -> Result<Block, String> {
+        self.open_block();
+
+        let block = self.block();
+
+        self.close_block();
+
+        block
+    }
+
+    fn loop_body(&mut self) -> Result<Block, String> {
+        self.function().loops += 1;
+
+        let body = self.scoped_block();
+
+        self.function().loops -= 1;
+
+        body
+    }
+
+    fn statement(&mut self) -> Result<Stat, String> {
+        let line = self.line();
+
+        match self.peek() {
+            Token::If => {
+                self.advance();
+
+                let mut branches = Vec::new();
+                let mut otherwise = None;
+
+                loop {
+                    let condition = self.expression()?;
+
+                    self.expect(Token::Then)?;
+                    branches.push((condition, self.scoped_block()?));
+
+                    if self.accept(&Token::Elseif) {
+                        continue;
+                    }
+
+                    if self.accept(&Token::Else) {
+                        otherwise = Some(self.scoped_block()?);
+                    }
+
+                    self.expect_closing(Token::End, Token::If, line)?;
+
+                    return Ok(Stat::If { branches, otherwise });
+                }
+            }
+            Token::While => {
+                self.advance();
+
+                let condition = self.expression()?;
+
+                self.expect(Token::Do)?;
+
+                let body = self.loop_body()?;
+
+                self.expect_closing(Token::End, Token::While, line)?;
+
+                Ok(Stat::While { condition, body })
+            }
+            Token::Do => {
+                self.advance();
+
+                let body = self.scoped_block()?;
+
+                self.expect_closing(Token::End, Token::Do, line)?;
+
+                Ok(Stat::Do(body))
+            }
+            Token::Repeat => {
+                self.advance();
+                self.function().loops += 1;
+                // The condition sees the locals of the body
+                self.open_block();
+
+                let body = self.block()?;
+
+                self.function().loops -= 1;
+                self.expect_closing(Token::Until, Token::Repeat, line)?;
+
+                let condition = self.expression()?;
+
+                self.close_block();
+
+                Ok(Stat::Repeat { body, condition })
+            }
+            Token::For => {
+                self.advance();
+                self.for_statement(line)
+            }
+            Token::Function => {
+                self.advance();
+
+                let name = self.name()?;
+                let mut target = self.variable(name);
+                let mut is_method = false;
+
+                while matches!(self.peek(), Token::Dot | Token::Colon) {
+                    is_method = self.advance() == Token::Colon;
+
+                    let key = self.name()?;
+
+                    target = Expr::Index(Box::new(target), Box::new(Expr::String(Arc::from(key.as_bytes()))));
+
+                    if is_method {
+                        break;
+                    }
+                }
+
+                let function = self.function_body(is_method, line)?;
+
+                Ok(Stat::Assign {
+                    targets: vec![target],
+                    values: vec![Expr::Function(function)],
+                })
+            }
+            Token::Local => {
+                self.advance();
+
+                if self.accept(&Token::Function) {
+                    let name = self.name()?;
+                    let slot = self.declare(name);
+                    let function = self.function_body(false, line)?;
+
+                    return Ok(Stat::LocalFunction { slot, function });
+                }
+
+                let mut names = vec![self.name()?];
+
+                while self.accept(&Token::Comma) {
+                    names.push(self.name()?);
+                }
+
+                let values = if self.accept(&Token::Assign) {
+                    self.expression_list()?
+                } else {
+                    Vec::new()
+                };
+                let slots = names.into_iter().map(|name| self.declare(name)).collect();
+
+                Ok(Stat::Local { slots, values })
+            }
+            _ => self.expression_statement(),
+        }
+    }
+
+    fn for_statement(&mut self, line: u32) -> Result<Stat, String> {
+        let first = self.name()?;
+
+        if self.accept(&Token::Assign) {
+            let start = self.expression()?;
+
+            self.expect(Token::Comma)?;
+
+            let end = self.expression()?;
+            let step = if self.accept(&Token::Comma) {
+                Some(self.expression()?)
+            } else {
+                None
+            };
+
+            self.expect(Token::Do)?;
+            self.open_block();
+
+            let slot = self.declare(first);
+            let body = self.loop_body();
+
+            self.close_block();
+
+            let body = body?;
+
+            self.expect_closing(Token::End, Token::For, line)?;
+
+            return Ok(Stat::NumericFor {
+                slot,
+                start,
+                end,
+                step,
+                body,
+            });
+        }
+
+        let mut names = vec![first];
+
+        while self.accept(&Token::Comma) {
+            names.push(self.name()?);
+        }
+
+        if names.len() == 1 && self.peek() != &Token::In {
+            return Err(self.error_near("'=' or 'in' expected"));
+        }
+
+        self.expect(Token::In)?;
+
+        let values = self.expression_list()?;
+
+        self.expect(Token::Do)?;
+        self.open_block();
+
+        let slots = names.into_iter().map(|name| self.declare(name)).collect();
+        let body = self.loop_body();
+
+        self.close_block();
+
+        let body = body?;
+
+        self.expect_closing(Token::End, Token::For, line)?;
+
+        Ok(Stat::GenericFor { slots, values, body })
+    }
+
+    fn expression_statement(&mut self) -> Result<Stat, String> {
+        let first = self.suffixed_expression()?;
+
+        if !matches!(self.peek(), Token::Assign | Token::Comma) {
+            return match first {
+                Expr::Call(..) | Expr::Method(..) => Ok(Stat::Call(first)),
+                _ => Err(self.error_near("syntax error")),
+            };
+        }
+
+        let mut targets = vec![first];
+
+        while self.accept(&Token::Comma) {
+            targets.push(self.suffixed_expression()?);
+        }
+
+        if targets.iter().any(|target| !matches!(target, Expr::Local(_) | Expr::Upvalue(_) | Expr::Global(_) | Expr::Index(..))) {
+            return Err(self.error_near("syntax error"));
+        }
+
+        self.expect(Token::Assign)?;
+
+        let values = self.expression_list()?;
+
+        Ok(Stat::Assign { targets, values })
+    }
+
+    fn function_body(&mut self, is_method: bool, line: u32) -> Result<Arc<FunctionBody>, String> {
+        self.functions.push(FunctionState::new(false));
+        self.open_block();
+
+        let mut params = Vec::new();
+
+        if is_method {
+            params.push(self.declare("self".to_string()));
+        }
+
+        self.expect(Token::LeftParen)?;
+
+        if !self.accept(&Token::RightParen) {
+            loop {
+                if self.accept(&Token::Dots) {
+                    self.function().is_vararg = true;
+                    break;
+                }
+
+                let name = self.name()?;
+
+                params.push(self.declare(name));
+
+                if !self.accept(&Token::Comma) {
+                    break;
+                }
+            }
+
+            self.expect(Token::RightParen)?;
+        }
+
+        let body = self.block()?;
+
+        self.expect_closing(Token::End, Token::Function, line)?;
+
+        let state = self.functions.pop().unwrap();
+
+        Ok(Arc::new(FunctionBody {
+            params,
+            is_vararg: state.is_vararg,
+            locals: state.locals,
+            upvalues: state.upvalues,
+            body,
+        }))
+    }
+
+    fn expression_list(&mut self) -> Result<Vec<Expr>, String> {
+        let mut values = vec![self.expression()?];
+
+        while self.accept(&Token::Comma) {
+            values.push(self.expression()?);
+        }
+
+        Ok(values)
+    }
+
+    fn expression(&mut self) -> Result<Expr, String> {
+        self.subexpression(0)
+    }
+
+    /// Expression whose binary operators bind more than `limit`
+    fn subexpression(&mut self, limit: u8) -> Result<Expr, String> {
+        self.enter()?;
+
+        let unary = match self.peek() {
+            Token::Not => Some(UnaryOp::Not),
+            Token::Minus => Some(UnaryOp::Neg),
+            Token::Hash => Some(UnaryOp::Len),
+            _ => None,
+        };
+
+        let mut left = match unary {
+            Some(op) => {
+                self.advance();
+
+                let operand = self.subexpression(UNARY_PRIORITY)?;
+
+                match (op, operand) {
+                    (UnaryOp::Neg, Expr::Number(n)) => Expr::Number(-n),
+                    (op, operand) => Expr::Unary(op, Box::new(operand)),
+                }
+            }
+            None => self.simple_expression()?,
+        };
+
+        while let Some((op, left_priority, right_priority)) = binary_op(self.peek()) {
+            if left_priority <= limit {
+                break;
+            }
+
+            self.advance();
+
+            let right = self.subexpression(right_priority)?;
+
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+
+        self.leave();
+
+        Ok(left)
+    }
+
+    fn simple_expression(&mut self) -> Result<Expr, String> {
+        let line = self.line();
+        let expr = match self.peek() {
+            Token::Number(n) => Expr::Number(*n),
+            Token::String(s) => Expr::String(Arc::from(s.as_slice())),
+            Token::Nil => Expr::Nil,
+            Token::True => Expr::True,
+            Token::False => Expr::False,
+            Token::Dots => {
+                if !self.function().is_vararg {
+                    return Err(self.error_near("cannot use '...' outside a vararg function"));
+                }
+
+                Expr::Vararg
+            }
+            Token::LeftBrace => return self.table(),
+            Token::Function => {
+                self.advance();
+
+                return Ok(Expr::Function(self.function_body(false, line)?));
+            }
+            _ => return self.suffixed_expression(),
+        };
+
+        self.advance();
+
+        Ok(expr)
+    }
+
+    fn primary_expression(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Token::Name(_) => {
+                let name = self.name()?;
+
+                Ok(self.variable(name))
+            }
+            Token::LeftParen => {
+                let line = self.line();
+
+                self.advance();
+
+                let expr = self.expression()?;
+
+                self.expect_closing(Token::RightParen, Token::LeftParen, line)?;
+
+                Ok(Expr::Paren(Box::new(expr)))
+            }
+            _ => Err(self.error_near("unexpected symbol")),
+        }
+    }
+
+    fn suffixed_expression(&mut self) -> Result<Expr, String> {
+        let mut expr = self.primary_expression()?;
+
+        loop {
+            match self.peek() {
+                Token::Dot => {
+                    self.advance();
+
+                    let key = self.name()?;
+
+                    expr = Expr::Index(Box::new(expr), Box::new(Expr::String(Arc::from(key.as_bytes()))));
+                }
+                Token::LeftBracket => {
+                    self.advance();
+
+                    let key = self.expression()?;
+
+                    self.expect(Token::RightBracket)?;
+                    expr = Expr::Index(Box::new(expr), Box::new(key));
+                }
+                Token::Colon => {
+                    self.advance();
+
+                    let name = self.name()?;
+                    let args = self.call_arguments()?;
+
+                    expr = Expr::Method(Box::new(expr), Arc::from(name.as_bytes()), args);
+                }
+                Token::LeftParen | Token::String(_) | Token::LeftBrace => {
+                    let args = self.call_arguments()?;
+
+                    expr = Expr::Call(Box::new(expr), args);
+                }
+                _ => return Ok(expr),
+            }
+        }
+    }
+
+    fn call_arguments(&mut self) -> Result<Vec<Expr>, String> {
+        match self.peek() {
+            Token::String(s) => {
+                let arg = Expr::String(Arc::from(s.as_slice()));
+
+                self.advance();
+
+                Ok(vec![arg])
+            }
+            Token::LeftBrace => Ok(vec![self.table()?]),
+            Token::LeftParen => {
+                let line = self.line();
+
+                self.advance();
+
+                if self.accept(&Token::RightParen) {
+                    return Ok(Vec::new());
+                }
+
+                let args = self.expression_list()?;
+
+                self.expect_closing(Token::RightParen, Token::LeftParen, line)?;
+
+                Ok(args)
+            }
+            _ => Err(self.error_near("function arguments expected")),
+        }
+    }
+
+    fn table(&mut self) -> Result<Expr, String> {
+        let line = self.line();
+        let mut fields = Vec::new();
+
+        self.expect(Token::LeftBrace)?;
+
+        while self.peek() != &Token::RightBrace {
+            let field = match (self.peek(), self.peek_next()) {
+                (Token::Name(_), Token::Assign) => {
+                    let key = self.name()?;
+
+                    self.advance();
+                    Field::Keyed(Expr::String(Arc::from(key.as_bytes())), self.expression()?)
+                }
+                (Token::LeftBracket, _) => {
+                    self.advance();
+
+                    let key = self.expression()?;
+
+                    self.expect(Token::RightBracket)?;
+                    self.expect(Token::Assign)?;
+                    Field::Keyed(key, self.expression()?)
+                }
+                _ => Field::Positional(self.expression()?),
+            };
+
+            fields.push(field);
+
+            if !self.accept(&Token::Comma) && !self.accept(&Token::Semicolon) {
+                break;
+            }
+        }
+
+        self.expect_closing(Token::RightBrace, Token::LeftBrace, line)?;
+
+        Ok(Expr::Table(fields))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> String {
+        parse(source.as_bytes(), "user_script").unwrap_err()
+    }
+
+    #[test]
+    fn resolves_variables() {
+        let chunk = parse(b"local a = 1\nlocal function f() return a + b end\nreturn f", "test").unwrap();
+
+        assert_eq!(chunk.locals, vec!["a", "f"]);
+
+        let Stat::LocalFunction { function, .. } = &chunk.body[1].kind else {
+            panic!("expected a local function");
+        };
+
+        assert_eq!(function.upvalues.len(), 1);
+        assert!(function.upvalues[0].from_local);
+        assert_eq!(chunk.body[1].line, 2);
+
+        let Stat::Return(values) = &function.body[0].kind else {
+            panic!("expected a return");
+        };
+
+        assert!(matches!(
+            &values[0],
+            Expr::Binary(BinaryOp::Add, left, right) if matches!(**left, Expr::Upvalue(0)) && matches!(**right, Expr::Global(_))
+        ));
+    }
+
+    #[test]
+    fn reports_syntax_errors() {
+        assert_eq!(error("x ="), "user_script:1: unexpected symbol near '<eof>'");
+        assert_eq!(error("local x 5"), "user_script:1: unexpected symbol near '5'");
+        assert_eq!(error("return 1 2"), "user_script:1: '<eof>' expected near '2'");
+        assert_eq!(
+            error("if x then\nreturn 1"),
+            "user_script:2: 'end' expected (to close 'if' at line 1) near '<eof>'"
+        );
+        assert_eq!(error("x"), "user_script:1: syntax error near '<eof>'");
+        assert_eq!(error("break"), "user_script:1: no loop to break near '<eof>'");
+        assert_eq!(
+            error("function f() return ... end"),
+            "user_script:1: cannot use '...' outside a vararg function near '...'"
+        );
+    }
+}
@@ -0,0 +1,446 @@
+//! Lua patterns, as used by `string.find`, `match`, `gmatch` and `gsub`
+//!
+//! A port of the matcher of the Lua 5.1 string library: character classes
+//! (`%a`, `[a-z]`...), the `* + - ?` repetitions, anchors, captures including
+//! position captures `()`, back references `%1`, balanced matches `%b()` and
+//! frontiers `%f[set]`.
+
+use super::value::Value;
+
+const MAX_CAPTURES: usize = 32;
+/// Deepest nesting of the backtracking matcher
+const MAX_DEPTH: usize = 200;
+const ESCAPE: u8 = b'%';
+/// Characters making a pattern something else than a plain string
+pub const SPECIALS: &[u8] = b"^$*+?.([%-";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CaptureLen {
+    Closed(usize),
+    Position,
+    Unfinished,
+}
+
+pub struct Matcher<'a> {
+    src: &'a [u8],
+    pattern: &'a [u8],
+    level: usize,
+    captures: [(usize, CaptureLen); MAX_CAPTURES],
+    depth: usize,
+}
+
+impl<'a> Matcher<'a> {
+    pub fn new(src: &'a [u8], pattern: &'a [u8]) -> Self {
+        Self {
+            src,
+            pattern,
+            level: 0,
+            captures: [(0, CaptureLen::Unfinished); MAX_CAPTURES],
+            depth: 0,
+        }
+    }
+
+    fn pattern_at(&self, p: usize) -> u8 {
+        self.pattern.get(p).copied().unwrap_or(0)
+    }
+
+    fn src_at(&self, s: usize) -> u8 {
+        self.src.get(s).copied().unwrap_or(0)
+    }
+
+    /// End of the match of the pattern from `p` on the subject at `s`
+    pub fn match_at(&mut self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        self.level = 0;
+        self.depth = 0;
+        self.do_match(s, p)
+    }
+
+    fn do_match(&mut self, mut s: usize, mut p: usize) -> Result<Option<usize>, String> {
+        self.depth += 1;
+
+        if self.depth > MAX_DEPTH {
+            return Err("pattern too complex".to_string());
+        }
+
+        let result = loop {
+            if p >= self.pattern.len() {
+                break Some(s);
+            }
+
+            match self.pattern[p] {
+                b'(' => {
+                    break if self.pattern_at(p + 1) == b')' {
+                        self.start_capture(s, p + 2, CaptureLen::Position)?
+                    } else {
+                        self.start_capture(s, p + 1, CaptureLen::Unfinished)?
+                    };
+                }
+                b')' => break self.end_capture(s, p + 1)?,
+                b'$' if p + 1 == self.pattern.len() => break (s == self.src.len()).then_some(s),
+                ESCAPE if self.pattern_at(p + 1) == b'b' => match self.match_balance(s, p + 2)? {
+                    Some(end) => {
+                        s = end;
+                        p += 4;
+                        continue;
+                    }
+                    None => break None,
+                },
+                ESCAPE if self.pattern_at(p + 1) == b'f' => {
+                    p += 2;
+
+                    if self.pattern_at(p) != b'[' {
+                        return Err("missing '[' after '%f' in pattern".to_string());
+                    }
+
+                    let end = self.class_end(p)?;
+                    let previous = if s == 0 { 0 } else { self.src[s - 1] };
+
+                    if self.match_bracket_class(previous, p, end - 1) || !self.match_bracket_class(self.src_at(s), p, end - 1) {
+                        break None;
+                    }
+
+                    p = end;
+                }
+                ESCAPE if self.pattern_at(p + 1).is_ascii_digit() => {
+                    match self.match_capture(s, self.pattern_at(p + 1))? {
+                        Some(end) => {
+                            s = end;
+                            p += 2;
+                        }
+                        None => break None,
+                    }
+                }
+                _ => {
+                    let end = self.class_end(p)?;
+                    let matches = s < self.src.len() && self.single_match(self.src[s], p, end);
+
+                    match self.pattern_at(end) {
+                        b'?' => {
+                            if matches {
+                                if let Some(result) = self.do_match(s + 1, end + 1)? {
+                                    break Some(result);
+                                }
+                            }
+
+                            p = end + 1;
+                        }
+                        b'*' => break self.max_expand(s, p, end)?,
+                        b'+' => break if matches { self.max_expand(s + 1, p, end)? } else { None },
+                        b'-' => break self.min_expand(s, p, end)?,
+                        _ => {
+                            if !matches {
+                                break None;
+                            }
+
+                            s += 1;
+                            p = end;
+                        }
+                    }
+                }
+            }
+        };
+
+        self.depth -= 1;
+
+        Ok(result)
+    }
+
+    /// Position right after the single character class starting at `p`
+    fn class_end(&self, mut p: usize) -> Result<usize, String> {
+        let c = self.pattern_at(p);
+
+        p += 1;
+
+        match c {
+            ESCAPE => {
+                if p >= self.pattern.len() {
+                    return Err("malformed pattern (ends with '%')".to_string());
+                }
+
+                Ok(p + 1)
+            }
+            b'[' => {
+                if self.pattern_at(p) == b'^' {
+                    p += 1;
+                }
+
+                // The first character is part of the set even if it is ']'
+                loop {
+                    if p >= self.pattern.len() {
+                        return Err("malformed pattern (missing ']')".to_string());
+                    }
+
+                    let c = self.pattern[p];
+
+                    p += 1;
+
+                    if c == ESCAPE && p < self.pattern.len() {
+                        p += 1;
+                    }
+
+                    if self.pattern_at(p) == b']' {
+                        return Ok(p + 1);
+                    }
+                }
+            }
+            _ => Ok(p),
+        }
+    }
+
+    fn single_match(&self, c: u8, p: usize, end: usize) -> bool {
+        match self.pattern[p] {
+            b'.' => true,
+            ESCAPE => match_class(c, self.pattern[p + 1]),
+            b'[' => self.match_bracket_class(c, p, end - 1),
+            literal => literal == c,
+        }
+    }
+
+    /// Whether `c` is in the set from `[` at `p` to `]` at `end`
+    fn match_bracket_class(&self, c: u8, mut p: usize, end: usize) -> bool {
+        let mut found = true;
+
+        if self.pattern_at(p + 1) == b'^' {
+            found = false;
+            p += 1;
+        }
+
+        p += 1;
+
+        while p < end {
+            if self.pattern[p] == ESCAPE {
+                p += 1;
+
+                if match_class(c, self.pattern_at(p)) {
+                    return found;
+                }
+            } else if self.pattern_at(p + 1) == b'-' && p + 2 < end {
+                if self.pattern[p] <= c && c <= self.pattern[p + 2] {
+                    return found;
+                }
+
+                p += 2;
+            } else if self.pattern[p] == c {
+                return found;
+            }
+
+            p += 1;
+        }
+
+        !found
+    }
+
+    fn max_expand(&mut self, s: usize, p: usize, end: usize) -> Result<Option<usize>, String> {
+        let mut count = 0;
+
+        while s + count < self.src.len() && self.single_match(self.src[s + count], p, end) {
+            count += 1;
+        }
+
+        loop {
+            if let Some(result) = self.do_match(s + count, end + 1)? {
+                return Ok(Some(result));
+            }
+
+            if count == 0 {
+                return Ok(None);
+            }
+
+            count -= 1;
+        }
+    }
+
+    fn min_expand(&mut self, mut s: usize, p: usize, end: usize) -> Result<Option<usize>, String> {
+        loop {
+            if let Some(result) = self.do_match(s, end + 1)? {
+                return Ok(Some(result));
+            }
+
+            if s < self.src.len() && self.single_match(self.src[s], p, end) {
+                s += 1;
+            } else {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn start_capture(&mut self, s: usize, p: usize, what: CaptureLen) -> Result<Option<usize>, String> {
+        if self.level >= MAX_CAPTURES {
+            return Err("too many captures".to_string());
+        }
+
+        self.captures[self.level] = (s, what);
+        self.level += 1;
+
+        let result = self.do_match(s, p)?;
+
+        if result.is_none() {
+            self.level -= 1;
+        }
+
+        Ok(result)
+    }
+
+    fn end_capture(&mut self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        let Some(open) = (0..self.level).rev().find(|level| self.captures[*level].1 == CaptureLen::Unfinished) else {
+            return Err("invalid pattern capture".to_string());
+        };
+
+        self.captures[open].1 = CaptureLen::Closed(s - self.captures[open].0);
+
+        let result = self.do_match(s, p)?;
+
+        if result.is_none() {
+            self.captures[open].1 = CaptureLen::Unfinished;
+        }
+
+        Ok(result)
+    }
+
+    fn match_balance(&self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        if p + 1 >= self.pattern.len() {
+            return Err("unbalanced pattern".to_string());
+        }
+
+        let (open, close) = (self.pattern[p], self.pattern[p + 1]);
+
+        if s >= self.src.len() || self.src[s] != open {
+            return Ok(None);
+        }
+
+        let mut depth = 1;
+
+        for position in s + 1..self.src.len() {
+            if self.src[position] == close {
+                depth -= 1;
+
+                if depth == 0 {
+                    return Ok(Some(position + 1));
+                }
+            } else if self.src[position] == open {
+                depth += 1;
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn match_capture(&self, s: usize, index: u8) -> Result<Option<usize>, String> {
+        let index = (index - b'1') as usize;
+        let len = match self.captures.get(index) {
+            Some((_, CaptureLen::Closed(len))) if index < self.level => *len,
+            _ => return Err("invalid capture index".to_string()),
+        };
+        let start = self.captures[index].0;
+
+        if self.src.len() - s >= len && self.src[start..start + len] == self.src[s..s + len] {
+            Ok(Some(s + len))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Capture `index` of the match from `s` to `end`, the whole match for
+    /// the first one of a pattern without captures
+    pub fn capture(&self, index: usize, s: usize, end: usize) -> Result<Value, String> {
+        if index >= self.level {
+            if index == 0 {
+                return Ok(Value::string(&self.src[s..end]));
+            }
+
+            return Err("invalid capture index".to_string());
+        }
+
+        let (start, len) = self.captures[index];
+
+        match len {
+            CaptureLen::Closed(len) => Ok(Value::string(&self.src[start..start + len])),
+            CaptureLen::Position => Ok(Value::Number(start as f64 + 1.0)),
+            CaptureLen::Unfinished => Err("unfinished capture".to_string()),
+        }
+    }
+
+    /// Every capture of the match from `s` to `end`
+    pub fn captures(&self, s: usize, end: usize) -> Result<Vec<Value>, String> {
+        (0..self.level.max(1)).map(|index| self.capture(index, s, end)).collect()
+    }
+
+    /// Number of captures of the last match
+    pub fn level(&self) -> usize {
+        self.level
+    }
+}
+
+fn match_class(c: u8, class: u8) -> bool {
+    let matches = match class.to_ascii_lowercase() {
+        b'a' => c.is_ascii_alphabetic(),
+        b'c' => c.is_ascii_control(),
+        b'd' => c.is_ascii_digit(),
+        b'l' => c.is_ascii_lowercase(),
+        b'p' => c.is_ascii_punctuation(),
+        b's' => c.is_ascii_whitespace() || c == 0x0b,
+        b'u' => c.is_ascii_uppercase(),
+        b'w' => c.is_ascii_alphanumeric(),
+        b'x' => c.is_ascii_hexdigit(),
+        b'z' => c == 0,
+        _ => return class == c,
+    };
+
+    if class.is_ascii_uppercase() {
+        !matches
+    } else {
+        matches
+    }
+}
+
+/// Start and end of the first match of `pattern` in `src` from `init`,
+/// with the matcher holding its captures
+pub fn find<'a>(src: &'a [u8], pattern: &'a [u8], init: usize) -> Result<Option<(usize, usize, Matcher<'a>)>, String> {
+    let anchored = pattern.first() == Some(&b'^');
+    let start = usize::from(anchored);
+    let mut matcher = Matcher::new(src, pattern);
+    let mut s = init;
+
+    loop {
+        if let Some(end) = matcher.match_at(s, start)? {
+            return Ok(Some((s, end, matcher)));
+        }
+
+        s += 1;
+
+        if anchored || s > src.len() {
+            return Ok(None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn first_match(src: &str, pattern: &str) -> Option<Vec<Value>> {
+        let (start, end, matcher) = find(src.as_bytes(), pattern.as_bytes(), 0).unwrap()?;
+
+        Some(matcher.captures(start, end).unwrap())
+    }
+
+    #[test]
+    fn matches_patterns() {
+        assert_eq!(first_match("hello world", "o w"), Some(vec![Value::from("o w")]));
+        assert_eq!(first_match("key:123:x", "(%a+):(%d+)"), Some(vec![Value::from("key"), Value::from("123")]));
+        assert_eq!(first_match("  trim  ", "^%s*(.-)%s*$"), Some(vec![Value::from("trim")]));
+        assert_eq!(first_match("f(a(b)c)d", "%b()"), Some(vec![Value::from("(a(b)c)")]));
+        assert_eq!(first_match("THE (quick) fox", "%f[%a]%a+"), Some(vec![Value::from("THE")]));
+        assert_eq!(first_match("abcabc", "()b()"), Some(vec![Value::from(2i64), Value::from(3i64)]));
+        assert_eq!(first_match("xyzzy", "(z)%1"), Some(vec![Value::from("z")]));
+        assert_eq!(first_match("a]b", "[]]"), Some(vec![Value::from("]")]));
+        assert_eq!(first_match("abc", "^b"), None);
+        assert_eq!(first_match("a-b", "[%-x]"), Some(vec![Value::from("-")]));
+
+        assert_eq!(find(b"x", b"[a", 0).err().unwrap(), "malformed pattern (missing ']')");
+        assert_eq!(find(b"x", b"%", 0).err().unwrap(), "malformed pattern (ends with '%')");
+
+        let (start, end, matcher) = find(b"x", b"(x", 0).unwrap().unwrap();
+        assert_eq!(matcher.captures(start, end).unwrap_err(), "unfinished capture");
+    }
+}
@@ -0,0 +1,1597 @@
+//! Libraries available to scripts: the base functions, `string`, `table`
+//! and `math` of Lua 5.1, plus `cjson` and `bit` as Redis provides them
+//!
+//! Functions touching the outside world (`io`, `os`, `load`...) are left out.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::interpreter::Lua;
+use super::pattern::{self, Matcher};
+use super::value::{format_float, format_number, parse_number, LuaError, Table, TableRef, Value};
+
+type NativeResult = Result<Vec<Value>, LuaError>;
+type NativeFunction = fn(&mut Lua<'_>, Vec<Value>) -> NativeResult;
+
+/// Longest string `string.rep` and `gsub` build
+const MAX_STRING: usize = 512 * 1024 * 1024;
+
+/// Loads the libraries into the globals of `lua`
+pub fn open(lua: &mut Lua<'_>) {
+    let base: &[(&'static str, NativeFunction)] = &[
+        ("assert", assert),
+        ("error", error),
+        ("pcall", pcall),
+        ("xpcall", xpcall),
+        ("type", type_of),
+        ("tostring", tostring),
+        ("tonumber", tonumber),
+        ("ipairs", ipairs),
+        ("pairs", pairs),
+        ("next", next),
+        ("select", select),
+        ("unpack", unpack),
+        ("rawget", rawget),
+        ("rawset", rawset),
+        ("rawequal", rawequal),
+        ("setmetatable", setmetatable),
+        ("getmetatable", getmetatable),
+        ("print", print),
+        ("collectgarbage", collectgarbage),
+    ];
+
+    for (name, function) in base {
+        lua.set_global(name, Value::native(name, *function));
+    }
+
+    lua.set_global("_G", Value::Table(lua.globals.clone()));
+    lua.set_global("_VERSION", Value::from("Lua 5.1"));
+
+    // As after `srand48(0)`
+    lua.random = 0x330E;
+
+    let strings = library(
+        lua,
+        "string",
+        &[
+            ("len", string_len),
+            ("sub", string_sub),
+            ("upper", string_upper),
+            ("lower", string_lower),
+            ("rep", string_rep),
+            ("reverse", string_reverse),
+            ("byte", string_byte),
+            ("char", string_char),
+            ("format", string_format),
+            ("find", string_find),
+            ("match", string_match),
+            ("gmatch", string_gmatch),
+            ("gsub", string_gsub),
+        ],
+    );
+
+    lua.strings = strings;
+
+    library(
+        lua,
+        "table",
+        &[
+            ("insert", table_insert),
+            ("remove", table_remove),
+            ("concat", table_concat),
+            ("sort", table_sort),
+            ("getn", table_getn),
+            ("maxn", table_maxn),
+        ],
+    );
+
+    let math = library(
+        lua,
+        "math",
+        &[
+            ("abs", |lua, args| math_unary(lua, args, "abs", f64::abs)),
+            ("ceil", |lua, args| math_unary(lua, args, "ceil", f64::ceil)),
+            ("floor", |lua, args| math_unary(lua, args, "floor", f64::floor)),
+            ("sqrt", |lua, args| math_unary(lua, args, "sqrt", f64::sqrt)),
+            ("sin", |lua, args| math_unary(lua, args, "sin", f64::sin)),
+            ("cos", |lua, args| math_unary(lua, args, "cos", f64::cos)),
+            ("tan", |lua, args| math_unary(lua, args, "tan", f64::tan)),
+            ("asin", |lua, args| math_unary(lua, args, "asin", f64::asin)),
+            ("acos", |lua, args| math_unary(lua, args, "acos", f64::acos)),
+            ("atan", |lua, args| math_unary(lua, args, "atan", f64::atan)),
+            ("exp", |lua, args| math_unary(lua, args, "exp", f64::exp)),
+            ("log", |lua, args| math_unary(lua, args, "log", f64::ln)),
+            ("log10", |lua, args| math_unary(lua, args, "log10", f64::log10)),
+            ("deg", |lua, args| math_unary(lua, args, "deg", f64::to_degrees)),
+            ("rad", |lua, args| math_unary(lua, args, "rad", f64::to_radians)),
+            ("pow", |lua, args| math_binary(lua, args, "pow", f64::powf)),
+            ("atan2", |lua, args| math_binary(lua, args, "atan2", f64::atan2)),
+            ("fmod", |lua, args| math_binary(lua, args, "fmod", |a, b| a % b)),
+            ("ldexp", |lua, args| math_binary(lua, args, "ldexp", |m, e| m * 2f64.powi(e as i32))),
+            ("modf", math_modf),
+            ("frexp", math_frexp),
+            ("min", math_min),
+            ("max", math_max),
+            ("random", math_random),
+            ("randomseed", math_randomseed),
+        ],
+    );
+
+    math.borrow_mut().set_str("pi", Value::Number(std::f64::consts::PI));
+    math.borrow_mut().set_str("huge", Value::Number(f64::INFINITY));
+
+    library(lua, "cjson", &[("encode", cjson_encode), ("decode", cjson_decode)]);
+
+    library(
+        lua,
+        "bit",
+        &[
+            ("tobit", |lua, args| Ok(vec![bit_value(to_bits(lua, &args, 0, "tobit")?)])),
+            ("bnot", |lua, args| Ok(vec![bit_value(!to_bits(lua, &args, 0, "bnot")?)])),
+            ("band", |lua, args| bit_fold(lua, args, "band", |a, b| a & b)),
+            ("bor", |lua, args| bit_fold(lua, args, "bor", |a, b| a | b)),
+            ("bxor", |lua, args| bit_fold(lua, args, "bxor", |a, b| a ^ b)),
+            ("lshift", |lua, args| bit_shift(lua, args, "lshift", |a, n| a << n)),
+            ("rshift", |lua, args| bit_shift(lua, args, "rshift", |a, n| a >> n)),
+            ("arshift", |lua, args| bit_shift(lua, args, "arshift", |a, n| ((a as i32) >> n) as u32)),
+            ("rol", |lua, args| bit_shift(lua, args, "rol", u32::rotate_left)),
+            ("ror", |lua, args| bit_shift(lua, args, "ror", u32::rotate_right)),
+            ("bswap", |lua, args| Ok(vec![bit_value(to_bits(lua, &args, 0, "bswap")?.swap_bytes())])),
+            ("tohex", bit_tohex),
+        ],
+    );
+}
+
+fn library(lua: &mut Lua<'_>, name: &str, functions: &[(&'static str, NativeFunction)]) -> TableRef {
+    let table = Rc::new(RefCell::new(Table::new()));
+
+    for (function_name, function) in functions {
+        table.borrow_mut().set_str(function_name, Value::native(function_name, *function));
+    }
+
+    lua.set_global(name, Value::Table(table.clone()));
+
+    table
+}
+
+fn arg(args: &[Value], index: usize) -> Value {
+    args.get(index).cloned().unwrap_or_default()
+}
+
+fn bad_argument(lua: &Lua<'_>, index: usize, function: &str, message: &str) -> LuaError {
+    lua.error(format!("bad argument #{} to '{function}' ({message})", index + 1))
+}
+
+fn expected(lua: &Lua<'_>, args: &[Value], index: usize, function: &str, what: &str) -> LuaError {
+    let got = match args.get(index) {
+        Some(value) => value.type_name(),
+        None => "no value",
+    };
+
+    bad_argument(lua, index, function, &format!("{what} expected, got {got}"))
+}
+
+fn check_any(lua: &Lua<'_>, args: &[Value], index: usize, function: &str) -> Result<Value, LuaError> {
+    match args.get(index) {
+        Some(value) => Ok(value.clone()),
+        None => Err(bad_argument(lua, index, function, "value expected")),
+    }
+}
+
+fn check_number(lua: &Lua<'_>, args: &[Value], index: usize, function: &str) -> Result<f64, LuaError> {
+    arg(args, index)
+        .to_number()
+        .ok_or_else(|| expected(lua, args, index, function, "number"))
+}
+
+fn check_integer(lua: &Lua<'_>, args: &[Value], index: usize, function: &str) -> Result<i64, LuaError> {
+    Ok(check_number(lua, args, index, function)? as i64)
+}
+
+fn opt_integer(lua: &Lua<'_>, args: &[Value], index: usize, function: &str, default: i64) -> Result<i64, LuaError> {
+    match arg(args, index) {
+        Value::Nil => Ok(default),
+        _ => check_integer(lua, args, index, function),
+    }
+}
+
+fn check_string(lua: &Lua<'_>, args: &[Value], index: usize, function: &str) -> Result<Rc<[u8]>, LuaError> {
+    arg(args, index)
+        .to_bytes()
+        .ok_or_else(|| expected(lua, args, index, function, "string"))
+}
+
+fn check_table(lua: &Lua<'_>, args: &[Value], index: usize, function: &str) -> Result<TableRef, LuaError> {
+    match arg(args, index) {
+        Value::Table(table) => Ok(table),
+        _ => Err(expected(lua, args, index, function, "table")),
+    }
+}
+
+/// Raw assignment, refused on the read only tables of the sandbox
+fn raw_set(lua: &Lua<'_>, table: &TableRef, key: Value, value: Value) -> Result<(), LuaError> {
+    if table.borrow().readonly {
+        return Err(lua.error("Attempt to modify a readonly table"));
+    }
+
+    table.borrow_mut().set(key, value).map_err(|message| lua.error(message))
+}
+
+fn assert(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let value = check_any(lua, &args, 0, "assert")?;
+
+    if value.truthy() {
+        return Ok(args);
+    }
+
+    match args.get(1) {
+        Some(message) => match message.to_bytes() {
+            Some(message) => Err(lua.error(String::from_utf8_lossy(&message))),
+            None => Err(expected(lua, &args, 1, "assert", "string")),
+        },
+        None => Err(lua.error("assertion failed!")),
+    }
+}
+
+fn error(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let level = opt_integer(lua, &args, 1, "error", 1)?;
+
+    match arg(&args, 0) {
+        Value::String(message) if level > 0 => Err(lua.error_at(level as usize, String::from_utf8_lossy(&message))),
+        value => {
+            let mut err = LuaError::new(value);
+
+            err.line = lua.error("").line;
+
+            Err(err)
+        }
+    }
+}
+
+fn pcall(lua: &mut Lua<'_>, mut args: Vec<Value>) -> NativeResult {
+    let function = check_any(lua, &args, 0, "pcall")?;
+
+    args.remove(0);
+
+    match lua.call(&function, args) {
+        Ok(mut values) => {
+            values.insert(0, Value::Boolean(true));
+            Ok(values)
+        }
+        Err(err) if !err.fatal => Ok(vec![Value::Boolean(false), err.value]),
+        Err(err) => Err(err),
+    }
+}
+
+fn xpcall(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let function = arg(&args, 0);
+    let handler = arg(&args, 1);
+
+    match lua.call(&function, Vec::new()) {
+        Ok(mut values) => {
+            values.insert(0, Value::Boolean(true));
+            Ok(values)
+        }
+        Err(err) if !err.fatal => {
+            let handled = lua.call(&handler, vec![err.value])?;
+
+            Ok(vec![Value::Boolean(false), handled.into_iter().next().unwrap_or_default()])
+        }
+        Err(err) => Err(err),
+    }
+}
+
+fn type_of(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let value = check_any(lua, &args, 0, "type")?;
+
+    Ok(vec![Value::from(value.type_name())])
+}
+
+fn tostring(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let value = check_any(lua, &args, 0, "tostring")?;
+
+    Ok(vec![Value::String(lua.tostring(&value)?)])
+}
+
+fn tonumber(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let base = opt_integer(lua, &args, 1, "tonumber", 10)?;
+    let value = check_any(lua, &args, 0, "tonumber")?;
+
+    if base == 10 {
+        return Ok(vec![value.to_number().map(Value::Number).unwrap_or_default()]);
+    }
+
+    if !(2..=36).contains(&base) {
+        return Err(bad_argument(lua, 1, "tonumber", "base out of range"));
+    }
+
+    let text = check_string(lua, &args, 0, "tonumber")?;
+    let text = String::from_utf8_lossy(&text);
+    let text = text.trim();
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+
+    if digits.is_empty() {
+        return Ok(vec![Value::Nil]);
+    }
+
+    let mut number = 0.0;
+
+    for c in digits.chars() {
+        match c.to_digit(base as u32) {
+            Some(digit) => number = number * base as f64 + digit as f64,
+            None => return Ok(vec![Value::Nil]),
+        }
+    }
+
+    Ok(vec![Value::Number(if negative { -number } else { number })])
+}
+
+fn ipairs(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let table = check_table(lua, &args, 0, "ipairs")?;
+    let iterator = Value::native("ipairs_aux", |lua, args| {
+        let table = check_table(lua, &args, 0, "ipairs_aux")?;
+        let index = check_number(lua, &args, 1, "ipairs_aux")? + 1.0;
+        let value = table.borrow().get(&Value::Number(index));
+
+        Ok(if value.is_nil() { Vec::new() } else { vec![Value::Number(index), value] })
+    });
+
+    Ok(vec![iterator, Value::Table(table), Value::Number(0.0)])
+}
+
+fn pairs(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let table = check_table(lua, &args, 0, "pairs")?;
+
+    Ok(vec![Value::native("next", next), Value::Table(table), Value::Nil])
+}
+
+fn next(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let table = check_table(lua, &args, 0, "next")?;
+    let next = table.borrow().next(&arg(&args, 1));
+
+    match next {
+        Ok(Some((key, value))) => Ok(vec![key, value]),
+        Ok(None) => Ok(vec![Value::Nil]),
+        Err(()) => Err(lua.error("invalid key to 'next'")),
+    }
+}
+
+fn select(lua: &mut Lua<'_>, mut args: Vec<Value>) -> NativeResult {
+    let count = args.len() as i64 - 1;
+
+    if let Value::String(s) = arg(&args, 0) {
+        if &s[..] == b"#" {
+            return Ok(vec![Value::Number(count as f64)]);
+        }
+    }
+
+    let index = check_integer(lua, &args, 0, "select")?;
+    let index = match index {
+        index if index < 0 => count + index,
+        0 => return Err(bad_argument(lua, 0, "select", "index out of range")),
+        index => index - 1,
+    };
+
+    if index < 0 {
+        return Err(bad_argument(lua, 0, "select", "index out of range"));
+    }
+
+    args.remove(0);
+
+    Ok(args.into_iter().skip(index as usize).collect())
+}
+
+fn unpack(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let table = check_table(lua, &args, 0, "unpack")?;
+    let start = opt_integer(lua, &args, 1, "unpack", 1)?;
+    let len = table.borrow().len() as i64;
+    let end = opt_integer(lua, &args, 2, "unpack", len)?;
+
+    if start > end {
+        return Ok(Vec::new());
+    }
+
+    if end - start >= 8000 {
+        return Err(lua.error("too many results to unpack"));
+    }
+
+    let table = table.borrow();
+
+    Ok((start..=end).map(|index| table.get(&Value::Number(index as f64))).collect())
+}
+
+fn rawget(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let table = check_table(lua, &args, 0, "rawget")?;
+    let value = table.borrow().get(&arg(&args, 1));
+
+    Ok(vec![value])
+}
+
+fn rawset(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let table = check_table(lua, &args, 0, "rawset")?;
+
+    raw_set(lua, &table, arg(&args, 1), arg(&args, 2))?;
+
+    Ok(vec![Value::Table(table)])
+}
+
+fn rawequal(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let a = check_any(lua, &args, 0, "rawequal")?;
+    let b = check_any(lua, &args, 1, "rawequal")?;
+
+    Ok(vec![Value::Boolean(a == b)])
+}
+
+fn setmetatable(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let table = check_table(lua, &args, 0, "setmetatable")?;
+    let metatable = match arg(&args, 1) {
+        Value::Nil => None,
+        Value::Table(metatable) => Some(metatable),
+        _ => return Err(bad_argument(lua, 1, "setmetatable", "nil or table expected")),
+    };
+
+    if table.borrow().readonly {
+        return Err(lua.error("Attempt to modify a readonly table"));
+    }
+
+    let protected = table
+        .borrow()
+        .metatable
+        .as_ref()
+        .is_some_and(|current| !current.borrow().get_str("__metatable").is_nil());
+
+    if protected {
+        return Err(lua.error("cannot change a protected metatable"));
+    }
+
+    table.borrow_mut().metatable = metatable;
+
+    Ok(vec![Value::Table(table)])
+}
+
+fn getmetatable(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let value = check_any(lua, &args, 0, "getmetatable")?;
+    let Value::Table(table) = value else {
+        return Ok(vec![Value::Nil]);
+    };
+    let Some(metatable) = table.borrow().metatable.clone() else {
+        return Ok(vec![Value::Nil]);
+    };
+    let protected = metatable.borrow().get_str("__metatable");
+
+    Ok(vec![if protected.is_nil() { Value::Table(metatable) } else { protected }])
+}
+
+/// Writes to the standard output of the server
+fn print(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let mut line = Vec::new();
+
+    for (index, value) in args.iter().enumerate() {
+        if index > 0 {
+            line.push(b'\t');
+        }
+
+        line.extend_from_slice(&lua.tostring(value)?);
+    }
+
+    println!("{}", String::from_utf8_lossy(&line));
+
+    Ok(Vec::new())
+}
+
+/// Memory is reference counted, there is nothing to collect
+fn collectgarbage(_lua: &mut Lua<'_>, _args: Vec<Value>) -> NativeResult {
+    Ok(vec![Value::Number(0.0)])
+}
+
+/// Start of a substring from a 1-based position, negative ones counting
+/// from the end
+fn relative_position(position: i64, len: usize) -> i64 {
+    if position < 0 {
+        len as i64 + position + 1
+    } else {
+        position
+    }
+}
+
+fn string_len(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let s = check_string(lua, &args, 0, "len")?;
+
+    Ok(vec![Value::Number(s.len() as f64)])
+}
+
+fn string_sub(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let s = check_string(lua, &args, 0, "sub")?;
+    let start = relative_position(opt_integer(lua, &args, 1, "sub", 1)?, s.len()).max(1);
+    let end = relative_position(opt_integer(lua, &args, 2, "sub", -1)?, s.len()).min(s.len() as i64);
+
+    if start > end {
+        return Ok(vec![Value::from("")]);
+    }
+
+    Ok(vec![Value::string(&s[start as usize - 1..end as usize])])
+}
+
+fn string_upper(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let s = check_string(lua, &args, 0, "upper")?;
+
+    Ok(vec![Value::string(s.to_ascii_uppercase())])
+}
+
+fn string_lower(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let s = check_string(lua, &args, 0, "lower")?;
+
+    Ok(vec![Value::string(s.to_ascii_lowercase())])
+}
+
+fn string_rep(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let s = check_string(lua, &args, 0, "rep")?;
+    let count = check_integer(lua, &args, 1, "rep")?;
+
+    if count <= 0 {
+        return Ok(vec![Value::from("")]);
+    }
+
+    if s.len().saturating_mul(count as usize) > MAX_STRING {
+        return Err(lua.error("resulting string too large"));
+    }
+
+    Ok(vec![Value::string(s.repeat(count as usize))])
+}
+
+fn string_reverse(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let s = check_string(lua, &args, 0, "reverse")?;
+
+    Ok(vec![Value::string(s.iter().rev().copied().collect::<Vec<u8>>())])
+}
+
+fn string_byte(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let s = check_string(lua, &args, 0, "byte")?;
+    let start = relative_position(opt_integer(lua, &args, 1, "byte", 1)?, s.len());
+    let end = relative_position(opt_integer(lua, &args, 2, "byte", start)?, s.len()).min(s.len() as i64);
+    let start = start.max(1);
+
+    if start > end {
+        return Ok(Vec::new());
+    }
+
+    Ok(s[start as usize - 1..end as usize].iter().map(|byte| Value::Number(*byte as f64)).collect())
+}
+
+fn string_char(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let mut bytes = Vec::with_capacity(args.len());
+
+    for index in 0..args.len() {
+        match check_integer(lua, &args, index, "char")? {
+            code @ 0..=255 => bytes.push(code as u8),
+            _ => return Err(bad_argument(lua, index, "char", "invalid value")),
+        }
+    }
+
+    Ok(vec![Value::string(bytes)])
+}
+
+/// `string.format`, the conversions of C's `printf` plus `%q`
+fn string_format(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let format = check_string(lua, &args, 0, "format")?;
+    let mut output = Vec::with_capacity(format.len());
+    let mut next_arg = 1;
+    let mut position = 0;
+
+    while position < format.len() {
+        let c = format[position];
+
+        position += 1;
+
+        if c != b'%' {
+            output.push(c);
+            continue;
+        }
+
+        if format.get(position) == Some(&b'%') {
+            output.push(b'%');
+            position += 1;
+            continue;
+        }
+
+        let start = position;
+
+        while position < format.len() && b"-+ #0".contains(&format[position]) {
+            position += 1;
+        }
+
+        let flags = &format[start..position];
+        let width_start = position;
+
+        while position < format.len() && format[position].is_ascii_digit() {
+            position += 1;
+        }
+
+        let width_digits = position - width_start;
+        let width: usize = std::str::from_utf8(&format[width_start..position]).unwrap().parse().unwrap_or(0);
+        let mut precision = None;
+
+        if format.get(position) == Some(&b'.') {
+            position += 1;
+
+            let precision_start = position;
+
+            while position < format.len() && format[position].is_ascii_digit() {
+                position += 1;
+            }
+
+            if position - precision_start > 2 {
+                return Err(lua.error("invalid format (width or precision too long)"));
+            }
+
+            precision = Some(std::str::from_utf8(&format[precision_start..position]).unwrap().parse().unwrap_or(0));
+        }
+
+        if flags.len() > 5 || width_digits > 2 {
+            return Err(lua.error("invalid format (width or precision too long)"));
+        }
+
+        let Some(&conversion) = format.get(position) else {
+            return Err(lua.error("invalid option '%' to 'format'"));
+        };
+
+        position += 1;
+
+        let index = next_arg;
+
+        next_arg += 1;
+
+        let spec = Spec {
+            left: flags.contains(&b'-'),
+            zero: flags.contains(&b'0'),
+            plus: flags.contains(&b'+'),
+            space: flags.contains(&b' '),
+            alternate: flags.contains(&b'#'),
+            width,
+            precision,
+        };
+        let formatted = match conversion {
+            b'd' | b'i' => {
+                let n = check_number(lua, &args, index, "format")? as i64;
+
+                spec.number(n < 0, n.unsigned_abs().to_string(), true)
+            }
+            b'u' => spec.number(false, (check_number(lua, &args, index, "format")? as i64 as u64).to_string(), true),
+            b'x' => spec.radix(format!("{:x}", check_number(lua, &args, index, "format")? as i64), "0x"),
+            b'X' => spec.radix(format!("{:X}", check_number(lua, &args, index, "format")? as i64), "0X"),
+            b'o' => spec.radix(format!("{:o}", check_number(lua, &args, index, "format")? as i64), "0"),
+            b'c' => spec.pad(vec![check_number(lua, &args, index, "format")? as i64 as u8]),
+            b'e' | b'E' | b'f' | b'F' | b'g' | b'G' => {
+                let n = check_number(lua, &args, index, "format")?;
+                let mut digits = format_float(
+                    n.abs(),
+                    (conversion as char).to_ascii_lowercase(),
+                    precision.unwrap_or(6),
+                    spec.alternate,
+                );
+
+                if conversion.is_ascii_uppercase() {
+                    digits = digits.to_uppercase();
+                }
+
+                spec.number(n.is_sign_negative() && !n.is_nan(), digits, n.is_finite())
+            }
+            b'q' => quote(&check_string(lua, &args, index, "format")?),
+            b's' => {
+                let value = check_any(lua, &args, index, "format")?;
+                let mut s = lua.tostring(&value)?.to_vec();
+
+                if let Some(precision) = precision {
+                    s.truncate(precision);
+                }
+
+                spec.pad(s)
+            }
+            c => return Err(lua.error(format!("invalid option '%{}' to 'format'", c as char))),
+        };
+
+        output.extend_from_slice(&formatted);
+    }
+
+    Ok(vec![Value::string(output)])
+}
+
+/// Flags, width and precision of a `string.format` conversion
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    fn pad(&self, value: Vec<u8>) -> Vec<u8> {
+        if value.len() >= self.width {
+            return value;
+        }
+
+        let padding = vec![b' '; self.width - value.len()];
+
+        if self.left {
+            [value, padding].concat()
+        } else {
+            [padding, value].concat()
+        }
+    }
+
+    /// Signed number, `zero_pad` is off for infinities and NaN
+    fn number(&self, negative: bool, mut digits: String, zero_pad: bool) -> Vec<u8> {
+        // The precision of integers is their minimum number of digits
+        if let Some(precision) = self.precision.filter(|_| digits.bytes().all(|b| b.is_ascii_digit())) {
+            if digits.len() < precision {
+                digits = format!("{}{digits}", "0".repeat(precision - digits.len()));
+            }
+        }
+
+        let sign = match (negative, self.plus, self.space) {
+            (true, _, _) => "-",
+            (false, true, _) => "+",
+            (false, false, true) => " ",
+            _ => "",
+        };
+
+        if self.zero && !self.left && zero_pad && sign.len() + digits.len() < self.width {
+            let zeros = "0".repeat(self.width - sign.len() - digits.len());
+
+            return format!("{sign}{zeros}{digits}").into_bytes();
+        }
+
+        self.pad(format!("{sign}{digits}").into_bytes())
+    }
+
+    fn radix(&self, digits: String, prefix: &str) -> Vec<u8> {
+        let digits = if self.alternate && digits != "0" { format!("{prefix}{digits}") } else { digits };
+
+        self.number(false, digits, true)
+    }
+}
+
+/// `%q`, a string literal reading back as the same string
+fn quote(s: &[u8]) -> Vec<u8> {
+    let mut quoted = vec![b'"'];
+
+    for byte in s {
+        match byte {
+            b'"' | b'\\' | b'\n' => quoted.extend_from_slice(&[b'\\', *byte]),
+            b'\r' => quoted.extend_from_slice(b"\\r"),
+            0 => quoted.extend_from_slice(b"\\000"),
+            byte => quoted.push(*byte),
+        }
+    }
+
+    quoted.push(b'"');
+
+    quoted
+}
+
+fn pattern_error(lua: &Lua<'_>, message: String) -> LuaError {
+    lua.error(message)
+}
+
+/// `string.find` and `string.match`
+fn find(lua: &mut Lua<'_>, args: Vec<Value>, function: &str, is_find: bool) -> NativeResult {
+    let s = check_string(lua, &args, 0, function)?;
+    let pattern = check_string(lua, &args, 1, function)?;
+    let init = relative_position(opt_integer(lua, &args, 2, function, 1)?, s.len()).max(1) as usize - 1;
+
+    if init > s.len() {
+        return Ok(vec![Value::Nil]);
+    }
+
+    let plain = arg(&args, 3).truthy() || !pattern.iter().any(|c| pattern::SPECIALS.contains(c));
+
+    if is_find && plain {
+        let found = if pattern.is_empty() {
+            Some(init)
+        } else {
+            s[init..].windows(pattern.len()).position(|window| window == &pattern[..]).map(|at| at + init)
+        };
+
+        return Ok(match found {
+            Some(start) => vec![Value::Number(start as f64 + 1.0), Value::Number((start + pattern.len()) as f64)],
+            None => vec![Value::Nil],
+        });
+    }
+
+    let Some((start, end, matcher)) = pattern::find(&s, &pattern, init).map_err(|err| pattern_error(lua, err))? else {
+        return Ok(vec![Value::Nil]);
+    };
+
+    if !is_find {
+        return matcher.captures(start, end).map_err(|err| pattern_error(lua, err));
+    }
+
+    let mut values = vec![Value::Number(start as f64 + 1.0), Value::Number(end as f64)];
+
+    if matcher.level() > 0 {
+        values.extend(matcher.captures(start, end).map_err(|err| pattern_error(lua, err))?);
+    }
+
+    Ok(values)
+}
+
+fn string_find(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    find(lua, args, "find", true)
+}
+
+fn string_match(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    find(lua, args, "match", false)
+}
+
+fn string_gmatch(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let s = check_string(lua, &args, 0, "gmatch")?;
+    let pattern = check_string(lua, &args, 1, "gmatch")?;
+    let position = RefCell::new(0);
+    let iterator = Value::native("gmatch_aux", move |lua, _| {
+        let mut matcher = Matcher::new(&s, &pattern);
+        let mut start = *position.borrow();
+
+        while start <= s.len() {
+            if let Some(end) = matcher.match_at(start, 0).map_err(|err| pattern_error(lua, err))? {
+                // An empty match moves on so the iteration ends
+                *position.borrow_mut() = if end == start { end + 1 } else { end };
+
+                return matcher.captures(start, end).map_err(|err| pattern_error(lua, err));
+            }
+
+            start += 1;
+        }
+
+        *position.borrow_mut() = start;
+
+        Ok(vec![Value::Nil])
+    });
+
+    Ok(vec![iterator])
+}
+
+fn string_gsub(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let s = check_string(lua, &args, 0, "gsub")?;
+    let pattern = check_string(lua, &args, 1, "gsub")?;
+    let replacement = arg(&args, 2);
+
+    if !matches!(replacement, Value::Number(_) | Value::String(_) | Value::Table(_) | Value::Function(_)) {
+        return Err(bad_argument(lua, 2, "gsub", "string/function/table expected"));
+    }
+
+    let max = match arg(&args, 3) {
+        Value::Nil => i64::MAX,
+        _ => check_integer(lua, &args, 3, "gsub")?,
+    };
+    let anchored = pattern.first() == Some(&b'^');
+    let pattern_start = usize::from(anchored);
+    let mut matcher = Matcher::new(&s, &pattern);
+    let mut output = Vec::with_capacity(s.len());
+    let mut position = 0;
+    let mut count = 0;
+
+    while count < max {
+        let end = matcher.match_at(position, pattern_start).map_err(|err| pattern_error(lua, err))?;
+
+        if let Some(end) = end {
+            count += 1;
+
+            let whole = &s[position..end];
+            let replaced = match &replacement {
+                Value::Table(table) => {
+                    let key = matcher.capture(0, position, end).map_err(|err| pattern_error(lua, err))?;
+                    let value = Value::Table(table.clone());
+
+                    lua.index(&value, &key)?
+                }
+                Value::Function(_) => {
+                    let captures = matcher.captures(position, end).map_err(|err| pattern_error(lua, err))?;
+
+                    lua.call(&replacement, captures)?.into_iter().next().unwrap_or_default()
+                }
+                _ => {
+                    let template = replacement.to_bytes().unwrap();
+
+                    Value::string(expand(lua, &matcher, &template, whole, position, end)?)
+                }
+            };
+
+            match replaced {
+                Value::Nil | Value::Boolean(false) => output.extend_from_slice(whole),
+                value => match value.to_bytes() {
+                    Some(bytes) => output.extend_from_slice(&bytes),
+                    None => return Err(lua.error(format!("invalid replacement value (a {})", value.type_name()))),
+                },
+            }
+
+            if output.len() > MAX_STRING {
+                return Err(lua.error("resulting string too large"));
+            }
+        }
+
+        match end {
+            Some(end) if end > position => position = end,
+            _ if position < s.len() => {
+                output.push(s[position]);
+                position += 1;
+            }
+            _ => break,
+        }
+
+        if anchored {
+            break;
+        }
+    }
+
+    output.extend_from_slice(&s[position.min(s.len())..]);
+
+    Ok(vec![Value::string(output), Value::Number(count as f64)])
+}
+
+/// Replacement string of `gsub` with `%0` to `%9` replaced by the captures
+fn expand(
+    lua: &Lua<'_>,
+    matcher: &Matcher,
+    template: &[u8],
+    whole: &[u8],
+    start: usize,
+    end: usize,
+) -> Result<Vec<u8>, LuaError> {
+    let mut expanded = Vec::with_capacity(template.len());
+    let mut bytes = template.iter();
+
+    while let Some(byte) = bytes.next() {
+        if *byte != b'%' {
+            expanded.push(*byte);
+            continue;
+        }
+
+        match bytes.next() {
+            Some(b'0') => expanded.extend_from_slice(whole),
+            Some(digit @ b'1'..=b'9') => {
+                let capture = matcher
+                    .capture((digit - b'1') as usize, start, end)
+                    .map_err(|err| pattern_error(lua, err))?;
+
+                expanded.extend_from_slice(&capture.to_bytes().unwrap());
+            }
+            Some(other) => expanded.push(*other),
+            None => {}
+        }
+    }
+
+    Ok(expanded)
+}
+
+fn table_insert(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let table = check_table(lua, &args, 0, "insert")?;
+    let end = table.borrow().len() as i64 + 1;
+
+    match args.len() {
+        2 => raw_set(lua, &table, Value::Number(end as f64), arg(&args, 1))?,
+        3 => {
+            let position = check_integer(lua, &args, 1, "insert")?;
+
+            for index in (position + 1..=end).rev() {
+                let previous = table.borrow().get(&Value::Number((index - 1) as f64));
+
+                raw_set(lua, &table, Value::Number(index as f64), previous)?;
+            }
+
+            raw_set(lua, &table, Value::Number(position as f64), arg(&args, 2))?;
+        }
+        _ => return Err(lua.error("wrong number of arguments to 'insert'")),
+    }
+
+    Ok(Vec::new())
+}
+
+fn table_remove(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let table = check_table(lua, &args, 0, "remove")?;
+    let end = table.borrow().len() as i64;
+    let position = opt_integer(lua, &args, 1, "remove", end)?;
+
+    if end == 0 {
+        return Ok(Vec::new());
+    }
+
+    let removed = table.borrow().get(&Value::Number(position as f64));
+
+    for index in position..end {
+        let next = table.borrow().get(&Value::Number((index + 1) as f64));
+
+        raw_set(lua, &table, Value::Number(index as f64), next)?;
+    }
+
+    raw_set(lua, &table, Value::Number(end as f64), Value::Nil)?;
+
+    Ok(vec![removed])
+}
+
+fn table_concat(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let table = check_table(lua, &args, 0, "concat")?;
+    let separator = match arg(&args, 1) {
+        Value::Nil => Rc::from(&b""[..]),
+        _ => check_string(lua, &args, 1, "concat")?,
+    };
+    let start = opt_integer(lua, &args, 2, "concat", 1)?;
+    let len = table.borrow().len() as i64;
+    let end = opt_integer(lua, &args, 3, "concat", len)?;
+    let mut joined = Vec::new();
+
+    for index in start..=end {
+        let value = table.borrow().get(&Value::Number(index as f64));
+
+        match value.to_bytes() {
+            Some(bytes) => joined.extend_from_slice(&bytes),
+            None => return Err(lua.error(format!("invalid value (at index {index}) in table for 'concat'"))),
+        }
+
+        if index < end {
+            joined.extend_from_slice(&separator);
+        }
+    }
+
+    Ok(vec![Value::string(joined)])
+}
+
+fn table_sort(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let table = check_table(lua, &args, 0, "sort")?;
+    let comparator = arg(&args, 1);
+
+    if !matches!(comparator, Value::Nil | Value::Function(_)) {
+        return Err(expected(lua, &args, 1, "sort", "function"));
+    }
+
+    let values = table.borrow().array().to_vec();
+    let mut less = |lua: &mut Lua<'_>, a: &Value, b: &Value| -> Result<bool, LuaError> {
+        match &comparator {
+            Value::Nil => lua.less_than(a, b),
+            function => Ok(lua.call(function, vec![a.clone(), b.clone()])?.first().is_some_and(Value::truthy)),
+        }
+    };
+    let sorted = merge_sort(lua, values, &mut less)?;
+
+    for (index, value) in sorted.into_iter().enumerate() {
+        raw_set(lua, &table, Value::Number(index as f64 + 1.0), value)?;
+    }
+
+    Ok(Vec::new())
+}
+
+/// Stable sort with a comparison that may fail
+fn merge_sort(
+    lua: &mut Lua<'_>,
+    mut values: Vec<Value>,
+    less: &mut impl FnMut(&mut Lua<'_>, &Value, &Value) -> Result<bool, LuaError>,
+) -> Result<Vec<Value>, LuaError> {
+    if values.len() <= 1 {
+        return Ok(values);
+    }
+
+    let right = values.split_off(values.len() / 2);
+    let left = merge_sort(lua, values, less)?;
+    let right = merge_sort(lua, right, less)?;
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        if less(lua, b, a)? {
+            merged.push(right.next().unwrap());
+        } else {
+            merged.push(left.next().unwrap());
+        }
+    }
+
+    merged.extend(left);
+    merged.extend(right);
+
+    Ok(merged)
+}
+
+fn table_getn(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let table = check_table(lua, &args, 0, "getn")?;
+    let len = table.borrow().len();
+
+    Ok(vec![Value::Number(len as f64)])
+}
+
+fn table_maxn(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let table = check_table(lua, &args, 0, "maxn")?;
+    let table = table.borrow();
+    let mut max = 0.0;
+    let mut key = Value::Nil;
+
+    while let Ok(Some((next, _))) = table.next(&key) {
+        if let Value::Number(n) = next {
+            if n > max {
+                max = n;
+            }
+        }
+
+        key = next;
+    }
+
+    Ok(vec![Value::Number(max)])
+}
+
+fn math_unary(lua: &mut Lua<'_>, args: Vec<Value>, name: &str, function: fn(f64) -> f64) -> NativeResult {
+    Ok(vec![Value::Number(function(check_number(lua, &args, 0, name)?))])
+}
+
+fn math_binary(lua: &mut Lua<'_>, args: Vec<Value>, name: &str, function: fn(f64, f64) -> f64) -> NativeResult {
+    let a = check_number(lua, &args, 0, name)?;
+    let b = check_number(lua, &args, 1, name)?;
+
+    Ok(vec![Value::Number(function(a, b))])
+}
+
+fn math_modf(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let n = check_number(lua, &args, 0, "modf")?;
+
+    Ok(vec![Value::Number(n.trunc()), Value::Number(n.fract())])
+}
+
+fn math_frexp(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let n = check_number(lua, &args, 0, "frexp")?;
+
+    if n == 0.0 || !n.is_finite() {
+        return Ok(vec![Value::Number(n), Value::Number(0.0)]);
+    }
+
+    let exponent = n.abs().log2().floor() + 1.0;
+
+    Ok(vec![Value::Number(n / 2f64.powf(exponent)), Value::Number(exponent)])
+}
+
+fn math_min(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let mut min = check_number(lua, &args, 0, "min")?;
+
+    for index in 1..args.len() {
+        min = min.min(check_number(lua, &args, index, "min")?);
+    }
+
+    Ok(vec![Value::Number(min)])
+}
+
+fn math_max(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let mut max = check_number(lua, &args, 0, "max")?;
+
+    for index in 1..args.len() {
+        max = max.max(check_number(lua, &args, index, "max")?);
+    }
+
+    Ok(vec![Value::Number(max)])
+}
+
+/// `lrand48`, so scripts draw the same numbers on every run
+fn next_random(lua: &mut Lua<'_>) -> f64 {
+    const MAX: u64 = (1 << 31) - 1;
+
+    lua.random = (lua.random.wrapping_mul(0x5DEECE66D).wrapping_add(0xB)) & ((1 << 48) - 1);
+
+    ((lua.random >> 17) % MAX) as f64 / MAX as f64
+}
+
+fn math_random(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let r = next_random(lua);
+
+    let value = match args.len() {
+        0 => r,
+        1 => {
+            let upper = check_number(lua, &args, 0, "random")?;
+
+            if upper < 1.0 {
+                return Err(bad_argument(lua, 0, "random", "interval is empty"));
+            }
+
+            (r * upper).floor() + 1.0
+        }
+        2 => {
+            let lower = check_number(lua, &args, 0, "random")?;
+            let upper = check_number(lua, &args, 1, "random")?;
+
+            if lower > upper {
+                return Err(bad_argument(lua, 1, "random", "interval is empty"));
+            }
+
+            (r * (upper - lower + 1.0)).floor() + lower
+        }
+        _ => return Err(lua.error("wrong number of arguments")),
+    };
+
+    Ok(vec![Value::Number(value)])
+}
+
+fn math_randomseed(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let seed = check_integer(lua, &args, 0, "randomseed")? as i32 as u32 as u64;
+
+    lua.random = (seed << 16) | 0x330E;
+
+    Ok(Vec::new())
+}
+
+/// Deepest nesting `cjson` encodes or decodes
+const MAX_JSON_DEPTH: usize = 1000;
+
+fn cjson_encode(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let value = check_any(lua, &args, 0, "encode")?;
+    let mut output = Vec::new();
+
+    encode_json(lua, &value, &mut output, 0)?;
+
+    Ok(vec![Value::string(output)])
+}
+
+fn encode_json(lua: &Lua<'_>, value: &Value, output: &mut Vec<u8>, depth: usize) -> Result<(), LuaError> {
+    match value {
+        Value::Nil => output.extend_from_slice(b"null"),
+        Value::Boolean(b) => output.extend_from_slice(b.to_string().as_bytes()),
+        Value::Number(n) if !n.is_finite() => {
+            return Err(lua.error("Cannot serialise number: must not be NaN or Inf"));
+        }
+        Value::Number(n) => output.extend_from_slice(format_number(*n).as_bytes()),
+        Value::String(s) => encode_json_string(s, output),
+        Value::Table(table) => {
+            if depth >= MAX_JSON_DEPTH {
+                return Err(lua.error(format!("Cannot serialise, excessive nesting ({})", depth + 1)));
+            }
+
+            let table = table.borrow();
+            let mut entries = Vec::new();
+            let mut key = Value::Nil;
+
+            while let Ok(Some((next, value))) = table.next(&key) {
+                entries.push((next.clone(), value));
+                key = next;
+            }
+
+            let max_index = entries
+                .iter()
+                .map(|(key, _)| match key {
+                    Value::Number(n) if n.fract() == 0.0 && *n >= 1.0 => Some(*n as usize),
+                    _ => None,
+                })
+                .try_fold(0, |max: usize, index| index.map(|index| max.max(index)));
+
+            match max_index {
+                Some(max) if !table.is_empty() => {
+                    if max > 10 && max > entries.len() * 2 {
+                        return Err(lua.error("Cannot serialise table: excessively sparse array"));
+                    }
+
+                    output.push(b'[');
+
+                    for index in 1..=max {
+                        if index > 1 {
+                            output.push(b',');
+                        }
+
+                        encode_json(lua, &table.get(&Value::Number(index as f64)), output, depth + 1)?;
+                    }
+
+                    output.push(b']');
+                }
+                _ => {
+                    output.push(b'{');
+
+                    for (position, (key, value)) in entries.iter().enumerate() {
+                        if position > 0 {
+                            output.push(b',');
+                        }
+
+                        match key.to_bytes() {
+                            Some(key) => encode_json_string(&key, output),
+                            None => {
+                                return Err(lua.error("Cannot serialise table: table key must be a number or string"));
+                            }
+                        }
+
+                        output.push(b':');
+                        encode_json(lua, value, output, depth + 1)?;
+                    }
+
+                    output.push(b'}');
+                }
+            }
+        }
+        value => {
+            return Err(lua.error(format!("Cannot serialise {}: type not supported", value.type_name())));
+        }
+    }
+
+    Ok(())
+}
+
+fn encode_json_string(s: &[u8], output: &mut Vec<u8>) {
+    output.push(b'"');
+
+    for byte in s {
+        match byte {
+            b'"' => output.extend_from_slice(b"\\\""),
+            b'\\' => output.extend_from_slice(b"\\\\"),
+            b'/' => output.extend_from_slice(b"\\/"),
+            b'\n' => output.extend_from_slice(b"\\n"),
+            b'\r' => output.extend_from_slice(b"\\r"),
+            b'\t' => output.extend_from_slice(b"\\t"),
+            0x08 => output.extend_from_slice(b"\\b"),
+            0x0c => output.extend_from_slice(b"\\f"),
+            byte if *byte < 0x20 || *byte == 0x7f => output.extend_from_slice(format!("\\u{:04x}", byte).as_bytes()),
+            byte => output.push(*byte),
+        }
+    }
+
+    output.push(b'"');
+}
+
+fn cjson_decode(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let text = check_string(lua, &args, 0, "decode")?;
+    let mut decoder = JsonDecoder { text: &text, position: 0 };
+    let value = decoder.value(0).map_err(|message| lua.error(message))?;
+
+    decoder.skip_spaces();
+
+    if decoder.position < text.len() {
+        return Err(lua.error(decoder.unexpected("the end")));
+    }
+
+    Ok(vec![value])
+}
+
+struct JsonDecoder<'a> {
+    text: &'a [u8],
+    position: usize,
+}
+
+impl JsonDecoder<'_> {
+    fn skip_spaces(&mut self) {
+        while self.position < self.text.len() && self.text[self.position].is_ascii_whitespace() {
+            self.position += 1;
+        }
+    }
+
+    fn unexpected(&self, what: &str) -> String {
+        format!("Expected {what} but found invalid token at character {}", self.position + 1)
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, String> {
+        if depth >= MAX_JSON_DEPTH {
+            return Err(format!("Found too many nested data structures ({}) at character {}", depth + 1, self.position + 1));
+        }
+
+        self.skip_spaces();
+
+        let rest = &self.text[self.position..];
+
+        match rest.first() {
+            Some(b'{') => {
+                self.position += 1;
+
+                let mut table = Table::new();
+
+                self.skip_spaces();
+
+                if self.text.get(self.position) == Some(&b'}') {
+                    self.position += 1;
+
+                    return Ok(Value::table(table));
+                }
+
+                loop {
+                    self.skip_spaces();
+
+                    if self.text.get(self.position) != Some(&b'"') {
+                        return Err(self.unexpected("object key string"));
+                    }
+
+                    let key = self.string()?;
+
+                    self.skip_spaces();
+
+                    if self.text.get(self.position) != Some(&b':') {
+                        return Err(self.unexpected("colon"));
+                    }
+
+                    self.position += 1;
+
+                    let value = self.value(depth + 1)?;
+
+                    table.set(key, value).unwrap();
+                    self.skip_spaces();
+
+                    match self.text.get(self.position) {
+                        Some(b',') => self.position += 1,
+                        Some(b'}') => {
+                            self.position += 1;
+
+                            return Ok(Value::table(table));
+                        }
+                        _ => return Err(self.unexpected("comma or object end")),
+                    }
+                }
+            }
+            Some(b'[') => {
+                self.position += 1;
+
+                let mut values = Vec::new();
+
+                self.skip_spaces();
+
+                if self.text.get(self.position) == Some(&b']') {
+                    self.position += 1;
+
+                    return Ok(Value::table(Table::new()));
+                }
+
+                loop {
+                    values.push(self.value(depth + 1)?);
+                    self.skip_spaces();
+
+                    match self.text.get(self.position) {
+                        Some(b',') => self.position += 1,
+                        Some(b']') => {
+                            self.position += 1;
+
+                            return Ok(Value::table(Table::from_array(values)));
+                        }
+                        _ => return Err(self.unexpected("comma or array end")),
+                    }
+                }
+            }
+            Some(b'"') => self.string(),
+            _ if rest.starts_with(b"true") => {
+                self.position += 4;
+                Ok(Value::Boolean(true))
+            }
+            _ if rest.starts_with(b"false") => {
+                self.position += 5;
+                Ok(Value::Boolean(false))
+            }
+            _ if rest.starts_with(b"null") => {
+                self.position += 4;
+                Ok(Value::Nil)
+            }
+            Some(c) if c.is_ascii_digit() || *c == b'-' => {
+                let len = rest
+                    .iter()
+                    .position(|c| !(c.is_ascii_digit() || b"+-.eE".contains(c)))
+                    .unwrap_or(rest.len());
+                let number = std::str::from_utf8(&rest[..len]).ok().and_then(parse_number);
+
+                match number {
+                    Some(n) => {
+                        self.position += len;
+                        Ok(Value::Number(n))
+                    }
+                    None => Err(self.unexpected("value")),
+                }
+            }
+            _ => Err(self.unexpected("value")),
+        }
+    }
+
+    fn string(&mut self) -> Result<Value, String> {
+        let start = self.position;
+        let mut bytes = Vec::new();
+
+        self.position += 1;
+
+        loop {
+            let Some(&byte) = self.text.get(self.position) else {
+                self.position = start;
+
+                return Err(self.unexpected("value"));
+            };
+
+            self.position += 1;
+
+            match byte {
+                b'"' => return Ok(Value::string(bytes)),
+                b'\\' => {
+                    let escaped = self.text.get(self.position).copied();
+
+                    self.position += 1;
+
+                    match escaped {
+                        Some(b'n') => bytes.push(b'\n'),
+                        Some(b't') => bytes.push(b'\t'),
+                        Some(b'r') => bytes.push(b'\r'),
+                        Some(b'b') => bytes.push(0x08),
+                        Some(b'f') => bytes.push(0x0c),
+                        Some(b'u') => {
+                            let code = self.unicode_escape()?;
+                            let mut buffer = [0; 4];
+
+                            bytes.extend_from_slice(code.encode_utf8(&mut buffer).as_bytes());
+                        }
+                        Some(c @ (b'"' | b'\\' | b'/')) => bytes.push(c),
+                        _ => {
+                            self.position = start;
+
+                            return Err(self.unexpected("value"));
+                        }
+                    }
+                }
+                byte => bytes.push(byte),
+            }
+        }
+    }
+
+    /// Code point of a `\uXXXX` escape, possibly a surrogate pair
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let hex = |decoder: &mut Self| -> Result<u32, String> {
+            let digits = decoder.text.get(decoder.position..decoder.position + 4);
+            let code = digits
+                .and_then(|digits| std::str::from_utf8(digits).ok())
+                .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+                .ok_or_else(|| decoder.unexpected("unicode escape code"))?;
+
+            decoder.position += 4;
+
+            Ok(code)
+        };
+        let high = hex(self)?;
+
+        if (0xD800..0xDC00).contains(&high) && self.text[self.position..].starts_with(b"\\u") {
+            self.position += 2;
+
+            let low = hex(self)?;
+
+            if (0xDC00..0xE000).contains(&low) {
+                let code = 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00);
+
+                return char::from_u32(code).ok_or_else(|| self.unexpected("unicode escape code"));
+            }
+        }
+
+        char::from_u32(high).ok_or_else(|| self.unexpected("unicode escape code"))
+    }
+}
+
+/// Argument of a `bit` function as 32 bits, wrapping like LuaBitOp
+fn to_bits(lua: &Lua<'_>, args: &[Value], index: usize, function: &str) -> Result<u32, LuaError> {
+    let n = check_number(lua, args, index, function)?;
+
+    Ok((n.round() % 4294967296.0) as i64 as u32)
+}
+
+fn bit_value(bits: u32) -> Value {
+    Value::Number(bits as i32 as f64)
+}
+
+fn bit_fold(lua: &mut Lua<'_>, args: Vec<Value>, name: &str, op: fn(u32, u32) -> u32) -> NativeResult {
+    let mut result = to_bits(lua, &args, 0, name)?;
+
+    for index in 1..args.len() {
+        result = op(result, to_bits(lua, &args, index, name)?);
+    }
+
+    Ok(vec![bit_value(result)])
+}
+
+fn bit_shift(lua: &mut Lua<'_>, args: Vec<Value>, name: &str, op: fn(u32, u32) -> u32) -> NativeResult {
+    let value = to_bits(lua, &args, 0, name)?;
+    let shift = to_bits(lua, &args, 1, name)? & 31;
+
+    Ok(vec![bit_value(op(value, shift))])
+}
+
+fn bit_tohex(lua: &mut Lua<'_>, args: Vec<Value>) -> NativeResult {
+    let value = to_bits(lua, &args, 0, "tohex")?;
+    let digits = match arg(&args, 1) {
+        Value::Nil => 8,
+        _ => check_integer(lua, &args, 1, "tohex")?,
+    };
+    let width = digits.unsigned_abs().min(8) as usize;
+    let hex = format!("{value:08x}");
+    let hex = &hex[8 - width..];
+
+    Ok(vec![Value::from(if digits < 0 { hex.to_uppercase() } else { hex.to_string() }.as_str())])
+}
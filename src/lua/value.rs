@@ -0,0 +1,570 @@
+//! Values scripts work with
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::ast::FunctionBody;
+use super::interpreter::Lua;
+
+pub type TableRef = Rc<RefCell<Table>>;
+
+pub type NativeFn = dyn Fn(&mut Lua<'_>, Vec<Value>) -> Result<Vec<Value>, LuaError>;
+
+#[derive(Clone, Default)]
+pub enum Value {
+    #[default]
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    /// Lua strings are bytes, not necessarily UTF-8
+    String(Rc<[u8]>),
+    Table(TableRef),
+    Function(Function),
+}
+
+#[derive(Clone)]
+pub enum Function {
+    Lua(Rc<Closure>),
+    Native(Rc<Native>),
+}
+
+/// Function of a script with the variables it captured
+pub struct Closure {
+    pub body: std::sync::Arc<FunctionBody>,
+    pub upvalues: Vec<Rc<RefCell<Value>>>,
+}
+
+/// Function of a library, named in argument errors
+pub struct Native {
+    pub name: &'static str,
+    pub call: Box<NativeFn>,
+}
+
+/// Error raised by a script, or by the interpreter running it
+#[derive(Debug, Clone)]
+pub struct LuaError {
+    pub value: Value,
+    /// Line of the script that was running, 0 until known
+    pub line: u32,
+    /// Stops the script instead of being caught by `pcall`
+    pub fatal: bool,
+}
+
+impl LuaError {
+    pub fn new(value: Value) -> Self {
+        Self {
+            value,
+            line: 0,
+            fatal: false,
+        }
+    }
+}
+
+impl Value {
+    pub fn string(value: impl AsRef<[u8]>) -> Self {
+        Value::String(Rc::from(value.as_ref()))
+    }
+
+    pub fn table(table: Table) -> Self {
+        Value::Table(Rc::new(RefCell::new(table)))
+    }
+
+    pub fn native(name: &'static str, call: impl Fn(&mut Lua<'_>, Vec<Value>) -> Result<Vec<Value>, LuaError> + 'static) -> Self {
+        Value::Function(Function::Native(Rc::new(Native {
+            name,
+            call: Box::new(call),
+        })))
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Boolean(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Table(_) => "table",
+            Value::Function(_) => "function",
+        }
+    }
+
+    pub fn is_nil(&self) -> bool {
+        matches!(self, Value::Nil)
+    }
+
+    /// Everything but `nil` and `false` is true
+    pub fn truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Boolean(false))
+    }
+
+    /// The number, or the string converted to one
+    pub fn to_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            Value::String(s) => parse_number(std::str::from_utf8(s).ok()?),
+            _ => None,
+        }
+    }
+
+    /// The string, or the number converted to one
+    pub fn to_bytes(&self) -> Option<Rc<[u8]>> {
+        match self {
+            Value::String(s) => Some(s.clone()),
+            Value::Number(n) => Some(Rc::from(format_number(*n).as_bytes())),
+            _ => None,
+        }
+    }
+
+    /// Address shown by `tostring` for tables and functions
+    pub fn address(&self) -> usize {
+        match self {
+            Value::Table(table) => Rc::as_ptr(table) as *const u8 as usize,
+            Value::Function(Function::Lua(closure)) => Rc::as_ptr(closure) as *const u8 as usize,
+            Value::Function(Function::Native(native)) => Rc::as_ptr(native) as *const u8 as usize,
+            _ => 0,
+        }
+    }
+}
+
+impl PartialEq for Value {
+    /// Raw equality, tables and functions are equal to themselves only
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Table(_), Value::Table(_)) | (Value::Function(_), Value::Function(_)) => {
+                self.address() == other.address()
+            }
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Debug for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Boolean(b) => write!(f, "{b}"),
+            Value::Number(n) => write!(f, "{}", format_number(*n)),
+            Value::String(s) => write!(f, "{:?}", String::from_utf8_lossy(s)),
+            Value::Function(Function::Native(native)) => write!(f, "builtin: {}", native.name),
+            value => write!(f, "{}: {:#x}", value.type_name(), value.address()),
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Boolean(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Number(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Number(value as f64)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::string(value)
+    }
+}
+
+/// Hashable identity of a table key
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Boolean(bool),
+    Number(u64),
+    String(Rc<[u8]>),
+    Reference(usize),
+}
+
+impl Key {
+    fn of(value: &Value) -> Option<Key> {
+        Some(match value {
+            Value::Nil => return None,
+            Value::Boolean(b) => Key::Boolean(*b),
+            // 0.0 and -0.0 are the same key
+            Value::Number(n) => Key::Number(if *n == 0.0 { 0 } else { n.to_bits() }),
+            Value::String(s) => Key::String(s.clone()),
+            value => Key::Reference(value.address()),
+        })
+    }
+}
+
+/// Lua table, with an array part for the keys 1 to n
+///
+/// The other keys are kept in insertion order so `next` can resume after any
+/// of them, removed ones stay as `nil` until new keys are added so traversals
+/// clearing fields go on.
+#[derive(Default)]
+pub struct Table {
+    array: Vec<Value>,
+    entries: Vec<(Value, Value)>,
+    index: HashMap<Key, usize>,
+    live: usize,
+    pub metatable: Option<TableRef>,
+    /// Set on the tables of the sandbox scripts may not modify
+    pub readonly: bool,
+}
+
+/// Position in the array part of integer keys
+fn array_index(key: &Value) -> Option<usize> {
+    match key {
+        Value::Number(n) if n.fract() == 0.0 && *n >= 1.0 && *n <= u32::MAX as f64 => Some(*n as usize - 1),
+        _ => None,
+    }
+}
+
+impl Table {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Table with `values` at keys 1 to n
+    pub fn from_array(values: Vec<Value>) -> Self {
+        let mut table = Self::new();
+
+        table.set_array(values);
+
+        table
+    }
+
+    /// Sets keys 1 to n to `values`, which may have holes
+    pub fn set_array(&mut self, values: Vec<Value>) {
+        for (index, value) in values.into_iter().enumerate() {
+            if index == self.array.len() {
+                self.push(value);
+            } else {
+                self.set(Value::Number(index as f64 + 1.0), value).unwrap();
+            }
+        }
+
+        while self.array.last().is_some_and(Value::is_nil) {
+            self.array.pop();
+        }
+    }
+
+    pub fn get(&self, key: &Value) -> Value {
+        if let Some(index) = array_index(key) {
+            if index < self.array.len() {
+                return self.array[index].clone();
+            }
+        }
+
+        match Key::of(key).and_then(|key| self.index.get(&key)) {
+            Some(position) => self.entries[*position].1.clone(),
+            None => Value::Nil,
+        }
+    }
+
+    pub fn get_str(&self, key: &str) -> Value {
+        self.get(&Value::string(key))
+    }
+
+    /// Sets `key`, an error message when it can't be a key
+    pub fn set(&mut self, key: Value, value: Value) -> Result<(), &'static str> {
+        match &key {
+            Value::Nil => return Err("table index is nil"),
+            Value::Number(n) if n.is_nan() => return Err("table index is NaN"),
+            _ => {}
+        }
+
+        if let Some(index) = array_index(&key) {
+            if index < self.array.len() {
+                self.array[index] = value;
+
+                while self.array.last().is_some_and(Value::is_nil) {
+                    self.array.pop();
+                }
+
+                return Ok(());
+            }
+
+            if index == self.array.len() && !value.is_nil() {
+                self.remove_entry(&key);
+                self.push(value);
+
+                return Ok(());
+            }
+        }
+
+        let id = Key::of(&key).unwrap();
+
+        match self.index.get(&id) {
+            Some(position) => {
+                let entry = &mut self.entries[*position].1;
+
+                match (entry.is_nil(), value.is_nil()) {
+                    (true, false) => self.live += 1,
+                    (false, true) => self.live -= 1,
+                    _ => {}
+                }
+
+                *entry = value;
+            }
+            None if value.is_nil() => {}
+            None => {
+                if self.entries.len() >= 8 && self.live * 2 < self.entries.len() {
+                    self.compact();
+                }
+
+                self.index.insert(id, self.entries.len());
+                self.entries.push((key, value));
+                self.live += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn set_str(&mut self, key: &str, value: Value) {
+        self.set(Value::string(key), value).unwrap();
+    }
+
+    /// Appends `value` after the last element of the array part, moving the
+    /// keys that follow from the other part
+    pub fn push(&mut self, value: Value) {
+        self.array.push(value);
+
+        loop {
+            let next = Value::Number(self.array.len() as f64 + 1.0);
+
+            match self.remove_entry(&next) {
+                Some(value) => self.array.push(value),
+                None => break,
+            }
+        }
+    }
+
+    fn remove_entry(&mut self, key: &Value) -> Option<Value> {
+        let position = self.index.remove(&Key::of(key)?)?;
+        let value = std::mem::take(&mut self.entries[position].1);
+
+        // Keeps the positions of the other entries valid
+        self.entries[position].0 = Value::Nil;
+
+        if value.is_nil() {
+            return None;
+        }
+
+        self.live -= 1;
+
+        Some(value)
+    }
+
+    fn compact(&mut self) {
+        self.entries.retain(|(key, value)| !key.is_nil() && !value.is_nil());
+        self.index = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(position, (key, _))| (Key::of(key).unwrap(), position))
+            .collect();
+    }
+
+    /// Length of the array part, a border as the `#` operator returns
+    pub fn len(&self) -> usize {
+        self.array.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.array.is_empty() && self.live == 0
+    }
+
+    /// Key and value following `key`, the first ones for `nil`, `Err` when
+    /// `key` isn't in the table
+    pub fn next(&self, key: &Value) -> Result<Option<(Value, Value)>, ()> {
+        let start = match (key, array_index(key)) {
+            (Value::Nil, _) => 0,
+            (_, Some(index)) if index < self.array.len() => index + 1,
+            (_, index) => match Key::of(key).and_then(|key| self.index.get(&key)) {
+                Some(position) => self.array.len() + position + 1,
+                // Cleared at the end of the array part while traversing it
+                None if index.is_some() => self.array.len(),
+                None => return Err(()),
+            },
+        };
+
+        for index in start..self.array.len() {
+            if !self.array[index].is_nil() {
+                return Ok(Some((Value::Number(index as f64 + 1.0), self.array[index].clone())));
+            }
+        }
+
+        let skipped = start.saturating_sub(self.array.len());
+
+        Ok(self
+            .entries
+            .iter()
+            .skip(skipped)
+            .find(|(key, value)| !key.is_nil() && !value.is_nil())
+            .cloned())
+    }
+
+    /// Values at keys 1 to n
+    pub fn array(&self) -> &[Value] {
+        &self.array
+    }
+}
+
+/// Formats a number as `tostring` does, `%.14g`
+pub fn format_number(n: f64) -> String {
+    if n.fract() == 0.0 && n.abs() < 1e14 {
+        if n == 0.0 && n.is_sign_negative() {
+            return "-0".to_string();
+        }
+
+        return format!("{}", n as i64);
+    }
+
+    format_float(n, 'g', 14, false)
+}
+
+/// `printf` formatting of a float for the `e`, `f` and `g` conversions
+pub fn format_float(n: f64, conversion: char, precision: usize, alternate: bool) -> String {
+    if n.is_nan() {
+        return if n.is_sign_negative() { "-nan" } else { "nan" }.to_string();
+    }
+
+    if n.is_infinite() {
+        return if n < 0.0 { "-inf" } else { "inf" }.to_string();
+    }
+
+    match conversion {
+        'f' => format!("{n:.precision$}"),
+        'e' => exponent_notation(n, precision),
+        _ => {
+            let precision = precision.max(1);
+            let exponent = exponent_notation(n, precision - 1);
+            let exp: i32 = exponent[exponent.find('e').unwrap() + 1..].parse().unwrap();
+            let formatted = if exp < -4 || exp >= precision as i32 {
+                exponent
+            } else {
+                format!("{n:.*}", (precision as i32 - 1 - exp) as usize)
+            };
+
+            if alternate {
+                return formatted;
+            }
+
+            // Trailing zeros of the fraction are dropped
+            match formatted.find('e') {
+                Some(e) => format!("{}{}", trim_fraction(&formatted[..e]), &formatted[e..]),
+                None => trim_fraction(&formatted).to_string(),
+            }
+        }
+    }
+}
+
+fn trim_fraction(number: &str) -> &str {
+    if number.contains('.') {
+        number.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        number
+    }
+}
+
+/// `%e`, with a sign and at least two digits in the exponent
+fn exponent_notation(n: f64, precision: usize) -> String {
+    let formatted = format!("{n:.precision$e}");
+    let (mantissa, exp) = formatted.split_once('e').unwrap();
+    let exp: i32 = exp.parse().unwrap();
+    let sign = if exp < 0 { '-' } else { '+' };
+
+    format!("{mantissa}e{sign}{:02}", exp.abs())
+}
+
+/// Parses a number as Lua does: decimal, possibly with an exponent, or
+/// hexadecimal, surrounded by spaces
+pub fn parse_number(text: &str) -> Option<f64> {
+    let text = text.trim_matches(|c: char| c.is_ascii_whitespace());
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+
+    if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        if hex.is_empty() || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        let value = hex.bytes().fold(0.0, |acc, b| acc * 16.0 + (b as char).to_digit(16).unwrap() as f64);
+
+        return Some(if negative { -value } else { value });
+    }
+
+    let valid = !digits.is_empty()
+        && digits.bytes().all(|b| b.is_ascii_digit() || matches!(b, b'.' | b'e' | b'E' | b'+' | b'-'))
+        && digits.bytes().next().is_some_and(|b| b.is_ascii_digit() || b == b'.');
+
+    if !valid {
+        return None;
+    }
+
+    let value: f64 = digits.parse().ok()?;
+
+    Some(if negative { -value } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_numbers() {
+        assert_eq!(format_number(42.0), "42");
+        assert_eq!(format_number(-3.5), "-3.5");
+        assert_eq!(format_number(0.1), "0.1");
+        assert_eq!(format_number(1e100), "1e+100");
+        assert_eq!(format_number(1.0 / 3.0), "0.33333333333333");
+        assert_eq!(format_number(f64::INFINITY), "inf");
+        assert_eq!(format_float(0.0001234, 'g', 6, false), "0.0001234");
+        assert_eq!(format_float(1234567.0, 'g', 6, false), "1.23457e+06");
+        assert_eq!(format_float(1.23456, 'f', 2, false), "1.23");
+        assert_eq!(format_float(31415.9, 'e', 3, false), "3.142e+04");
+
+        assert_eq!(parse_number(" 0x10 "), Some(16.0));
+        assert_eq!(parse_number("-1.5e3"), Some(-1500.0));
+        assert_eq!(parse_number(".5"), Some(0.5));
+        assert_eq!(parse_number("1e"), None);
+        assert_eq!(parse_number("abc"), None);
+        assert_eq!(parse_number("inf"), None);
+    }
+
+    #[test]
+    fn tables_keep_arrays_and_traversals() {
+        let mut table = Table::new();
+
+        table.set(Value::from(2i64), Value::from("b")).unwrap();
+        table.set_str("x", Value::from(1i64));
+        table.set(Value::from(1i64), Value::from("a")).unwrap();
+
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.get(&Value::from(2.0)), Value::from("b"));
+
+        let mut keys = Vec::new();
+        let mut key = Value::Nil;
+
+        while let Some((next, _)) = table.next(&key).unwrap() {
+            // Clearing fields while traversing is allowed
+            table.set(next.clone(), Value::Nil).unwrap();
+            keys.push(next.clone());
+            key = next;
+        }
+
+        assert_eq!(keys, vec![Value::from(1i64), Value::from(2i64), Value::from("x")]);
+        assert!(table.is_empty());
+        assert!(table.next(&Value::from("missing")).is_err());
+        assert_eq!(table.set(Value::Nil, Value::from(1i64)), Err("table index is nil"));
+    }
+}
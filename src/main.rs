@@ -1,33 +1,79 @@
-mod models;
 mod commands;
+mod lua;
+mod models;
 mod resp;
 mod utils;
 
 use utils::store;
+use utils::aof::Aof;
+use utils::cluster::Cluster;
+use utils::cluster_bus;
 use utils::config;
 use utils::context::{self, Context};
-use utils::shared_context::{SharedContext, create_shared_context};
+use utils::rdb;
+use utils::replication;
+use utils::sentinel::{self, Sentinel};
+use utils::session::Session;
+use utils::shared_context::{lock, SharedContext, create_shared_context};
 
 use core::result::Result;
-use std::io::Error;
+use std::io::{Error, ErrorKind};
 use std::net::SocketAddr;
-use std::borrow::Cow;
+use std::time::Duration;
+
+use bytes::{Buf, BytesMut};
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::{TcpListener, TcpStream},
+    sync::mpsc::UnboundedReceiver,
 };
 
-use crate::commands::Command;
+use crate::commands::RESPCommand;
 use crate::resp::types::RespType;
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    let config = config::load().unwrap();
-    let port = config.port.clone();
-    let context = Context::new(store::create_store(), config);
+    let config = config::load().map_err(|err| Error::new(ErrorKind::InvalidInput, err.to_string()))?;
+    let port = config.port;
+    let master = config.replication.as_ref().map(|master| (master.host.clone(), master.port));
+    let mut context = Context::new(store::create_store(), config);
+
+    if context.config.cluster_enabled {
+        context.cluster = Some(load_cluster(&context)?);
+    }
+
+    if context.config.sentinel {
+        start_sentinel(&mut context)?;
+    } else if context.config.appendonly {
+        let mut aof = Aof::open(&context.config)?;
+        let truncated = context.config.aof_load_truncated;
+
+        aof.load(&mut context, truncated).map_err(|err| Error::new(ErrorKind::InvalidData, err.to_string()))?;
+        context.aof = Some(aof);
+    } else {
+        let path = context.rdb_path();
+
+        rdb::load(&path, &mut context).map_err(|err| Error::new(ErrorKind::InvalidData, err.to_string()))?;
+    }
+
+    let is_sentinel = context.sentinel.is_some();
+    let bus_port = context.cluster.as_ref().map(|cluster| cluster.myself().cport);
     let shared_context = create_shared_context(context);
     let listener = TcpListener::bind(format!("127.0.0.1:{}", port)).await?;
 
+    tokio::spawn(server_cron(shared_context.clone()));
+
+    if is_sentinel {
+        tokio::spawn(sentinel::run(shared_context.clone()));
+    } else if let Some(bus_port) = bus_port {
+        // The cluster decides which master a node replicates
+        let bus = TcpListener::bind(format!("127.0.0.1:{bus_port}")).await?;
+
+        tokio::spawn(cluster_bus::run(shared_context.clone(), bus));
+    } else if let Some((host, port)) = master {
+        replication::replicate_from(&shared_context, host, port);
+    }
+
 
 
     loop {
@@ -37,7 +83,7 @@ async fn main() -> Result<(), Error> {
             Ok((mut stream, _)) => {
                 let mut context = shared_context.clone();
 
-                let _ = tokio::spawn(async move {
+                tokio::spawn(async move {
                     process_incoming_connections(&mut stream, &mut context)
                         .await
                         .unwrap();
@@ -50,52 +96,173 @@ async fn main() -> Result<(), Error> {
     }
 }
 
+/// Reads the nodes of the cluster from the `cluster-config-file`, a node
+/// without one starts alone
+fn load_cluster(context: &Context) -> Result<Cluster, Error> {
+    let path = std::path::Path::new(&context.config.dir).join(&context.config.cluster_config_file);
+
+    match std::fs::read_to_string(&path) {
+        Ok(config) => Cluster::parse(&config).map_err(|err| {
+            Error::new(ErrorKind::InvalidData, format!("{}: {err}", path.display()))
+        }),
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            Ok(Cluster::new(replication::generate_replid(), "127.0.0.1".to_string(), context.config.port))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Switches the server to sentinel mode, monitoring the masters given with
+/// the `--sentinel-*` flags
+fn start_sentinel(context: &mut Context) -> Result<(), Error> {
+    context.sentinel = Some(Sentinel::new(context.run_id.clone()));
+
+    for directive in std::mem::take(&mut context.config.sentinel_directives) {
+        let description = directive.join(" ");
+
+        if let RespType::SimpleError(err) = commands::Sentinel(directive).execute(context) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("sentinel {description}: {err}")));
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs the periodic housekeeping of the server, like removing expired keys
+/// that are never accessed again
+async fn server_cron(context: SharedContext) {
+    let mut interval = tokio::time::interval(Duration::from_millis(100));
+
+    loop {
+        interval.tick().await;
+        lock(&context).cron();
+    }
+}
+
 async fn process_incoming_connections(stream: &mut TcpStream, context: &mut SharedContext) -> Result<(), Error> {
+    let mut session = Session::new();
+    session.addr = stream.peer_addr().ok();
+    session.register(context);
+    let result = serve_session(stream, context, &mut session).await;
+
+    session.close(context);
+
+    result
+}
+
+async fn serve_session(stream: &mut TcpStream, context: &mut SharedContext, session: &mut Session) -> Result<(), Error> {
+    let mut buffer = BytesMut::with_capacity(4096);
+    let mut messages = session.take_messages().expect("session messages already taken");
+
     loop {
-        let mut buffer: [u8; 1024] = [0; 1024];
-        let bits_len: usize = stream.read(&mut buffer).await?;
+        let bits_len = tokio::select! {
+            read = stream.read_buf(&mut buffer) => read?,
+            Some(message) = messages.recv() => {
+                write(stream, &message.to_bytes(session.state.protocol)).await?;
+                continue;
+            }
+        };
 
         if bits_len == 0 {
             break;
         }
 
-        let input: Cow<'_, str> = String::from_utf8_lossy(&buffer);
-        let resp_type = RespType::try_from(input.to_string()).unwrap();
+        let mut response = Vec::new();
 
-        let command = Command::try_from(resp_type);
+        loop {
+            match RespType::parse(&buffer) {
+                Ok(Some((resp_type, used))) => {
+                    buffer.advance(used);
+                    let mut reply = session.handle(resp_type, context);
 
-        let response: String;
-
-        match command {
-            Ok(comm) => {
-                let command = comm.create_command();
+                    // Messages queued while handling the request go out first
+                    while let Ok(message) = messages.try_recv() {
+                        response.extend(message.to_bytes(session.state.protocol));
+                    }
 
-                match command {
-                    Ok(mut command_executable) => {
-                        let mut context = context.lock().unwrap();
-                        let resp = command_executable.execute(&mut context);
-                        response = resp.to_string();
+                    // WAIT, WAITAOF and MIGRATE block the connection, earlier
+                    // replies of a pipeline are sent before waiting
+                    if let Some(blocked) = session.take_blocked() {
+                        write(stream, &response).await?;
+                        response.clear();
+                        reply = blocked.resolve(context).await;
                     }
-                    Err(err) => {
-                        let resp: RespType = err.into();
-                        response = resp.to_string();
+
+                    if session.is_migrating() {
+                        write(stream, &response).await?;
+                        response.clear();
+                        reply = session.migrate(reply, context).await;
                     }
+
+                    response.extend(reply.to_bytes(session.state.protocol));
+                }
+                Ok(None) => break,
+                Err(err) => {
+                    // The rest of the stream can't be framed anymore, Redis
+                    // closes the connection after the error as well
+                    let resp: RespType = err.into();
+                    response.extend(resp.to_bytes(session.state.protocol));
+                    write(stream, &response).await?;
+
+                    return Ok(());
                 }
-            }
-            Err(err) => {
-                let resp: RespType = err.into();
-                response = resp.to_string();
             }
         }
 
-        write(stream, response.to_string().as_bytes()).await?;
+        write(stream, &response).await?;
+
+        if let Some(replication) = session.take_replication() {
+            return serve_replica(stream, context, session, buffer, replication).await;
+        }
     }
 
     Ok(())
 }
 
+/// Streams the replication stream to a client that became a replica
+///
+/// What the replica sends from then on, like `REPLCONF ACK`, is handled but
+/// never answered.
+async fn serve_replica(
+    stream: &mut TcpStream,
+    context: &mut SharedContext,
+    session: &mut Session,
+    mut buffer: BytesMut,
+    mut replication: UnboundedReceiver<Vec<u8>>,
+) -> Result<(), Error> {
+    loop {
+        tokio::select! {
+            read = stream.read_buf(&mut buffer) => {
+                if read? == 0 {
+                    return Ok(());
+                }
+
+                loop {
+                    match RespType::parse(&buffer) {
+                        Ok(Some((resp_type, used))) => {
+                            buffer.advance(used);
+                            session.handle(resp_type, context);
+                        }
+                        Ok(None) => break,
+                        Err(err) => {
+                            // Nothing after it can be framed, dropping the
+                            // link makes the replica reconnect and sync again
+                            eprintln!("err: bad data from replica: {}", err);
+
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+            data = replication.recv() => match data {
+                Some(data) => write(stream, &data).await?,
+                None => return Ok(()),
+            },
+        }
+    }
+}
+
 async fn write(stream: &mut TcpStream, str: &[u8]) -> Result<(), Error> {
-    stream.write(str).await?;
+    stream.write_all(str).await?;
     Ok(())
 }
-
@@ -0,0 +1,323 @@
+use bytes::{Buf, BufMut};
+
+use crate::utils::hash::murmur64a;
+
+const HASH_SEED: u64 = 0xc6a4_a793_5bd1_e995;
+const TIGHTENING_RATIO: f64 = 0.5;
+
+/// Largest bit array of a single layer, 512MB
+const MAX_LAYER_BITS: u64 = 1 << 32;
+
+/// Largest expansion factor, as in RedisBloom
+pub const MAX_EXPANSION: u32 = 32768;
+
+/// Number of hash functions giving a false positive rate of `error_rate`
+fn hashes_for(error_rate: f64) -> u32 {
+    (-error_rate.ln() / std::f64::consts::LN_2).ceil().max(1.0) as u32
+}
+
+/// A single fixed size layer of a [`BloomFilter`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct BloomLayer {
+    pub(crate) capacity: u64,
+    pub(crate) error_rate: f64,
+    pub(crate) hashes: u32,
+    pub(crate) bits: u64,
+    pub(crate) items: u64,
+    pub(crate) data: Vec<u8>,
+}
+
+impl BloomLayer {
+    /// A layer holding `capacity` items, `None` when its bits would exceed
+    /// the maximum size
+    pub fn new(capacity: u64, error_rate: f64) -> Option<Self> {
+        let ln2 = std::f64::consts::LN_2;
+        let capacity = capacity.max(1);
+        let bits = (-(capacity as f64) * error_rate.ln() / (ln2 * ln2)).ceil().max(8.0);
+        let hashes = hashes_for(error_rate);
+
+        if bits > MAX_LAYER_BITS as f64 {
+            return None;
+        }
+
+        let bits = bits as u64;
+
+        Some(Self {
+            capacity,
+            error_rate,
+            hashes,
+            bits,
+            items: 0,
+            data: vec![0; bits.div_ceil(8) as usize],
+        })
+    }
+
+    fn positions(&self, item: &[u8]) -> impl Iterator<Item = u64> + '_ {
+        let h1 = murmur64a(item, HASH_SEED);
+        let h2 = murmur64a(item, h1);
+
+        (0..self.hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % self.bits)
+    }
+
+    pub fn contains(&self, item: &[u8]) -> bool {
+        self.positions(item)
+            .all(|bit| self.data[(bit / 8) as usize] & (1 << (bit % 8)) != 0)
+    }
+
+    /// Sets the bits of `item`, returns `false` when all of them were already set
+    pub fn insert(&mut self, item: &[u8]) -> bool {
+        let positions: Vec<u64> = self.positions(item).collect();
+        let mut changed = false;
+
+        for bit in positions {
+            let byte = &mut self.data[(bit / 8) as usize];
+            let mask = 1 << (bit % 8);
+
+            if *byte & mask == 0 {
+                *byte |= mask;
+                changed = true;
+            }
+        }
+
+        if changed {
+            self.items += 1;
+        }
+
+        changed
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.items >= self.capacity
+    }
+}
+
+/// Scalable bloom filter, new layers are stacked when the last one fills up
+#[derive(Debug, Clone, PartialEq)]
+pub struct BloomFilter {
+    pub(crate) layers: Vec<BloomLayer>,
+    /// Growth factor of every new layer, `0` means the filter never scales
+    pub(crate) expansion: u32,
+}
+
+impl BloomFilter {
+    pub fn new(capacity: u64, error_rate: f64, expansion: u32) -> Result<Self, &'static str> {
+        let layer = BloomLayer::new(capacity, error_rate).ok_or("ERR filter exceeds the maximum size")?;
+
+        Ok(Self {
+            layers: vec![layer],
+            expansion,
+        })
+    }
+
+    pub fn is_scaling(&self) -> bool {
+        self.expansion > 0
+    }
+
+    pub fn contains(&self, item: &[u8]) -> bool {
+        self.layers.iter().any(|layer| layer.contains(item))
+    }
+
+    /// Adds `item` to the filter
+    ///
+    /// Returns `Ok(false)` when the item was (probably) already present and an
+    /// error when a non scaling filter is out of capacity.
+    pub fn insert(&mut self, item: &[u8]) -> Result<bool, &'static str> {
+        if self.contains(item) {
+            return Ok(false);
+        }
+
+        let last = self.layers.last().expect("bloom filter without layers");
+
+        if last.is_full() {
+            if !self.is_scaling() {
+                return Err("ERR non scaling filter is full");
+            }
+
+            let capacity = last.capacity.saturating_mul(self.expansion as u64);
+            let error_rate = last.error_rate * TIGHTENING_RATIO;
+
+            let layer = BloomLayer::new(capacity, error_rate).ok_or("ERR filter can't grow beyond the maximum size")?;
+
+            self.layers.push(layer);
+        }
+
+        let last = self.layers.last_mut().expect("bloom filter without layers");
+
+        Ok(last.insert(item))
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.layers.iter().map(|layer| layer.capacity).sum()
+    }
+
+    pub fn items(&self) -> u64 {
+        self.layers.iter().map(|layer| layer.items).sum()
+    }
+
+    /// Memory used by the filter bits and bookkeeping, in bytes
+    pub fn size(&self) -> u64 {
+        let layers: u64 = self
+            .layers
+            .iter()
+            .map(|layer| layer.data.len() as u64 + std::mem::size_of::<BloomLayer>() as u64)
+            .sum();
+
+        layers + std::mem::size_of::<Self>() as u64
+    }
+
+    /// Serializes the filter into a self describing little endian payload
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+
+        buf.put_u32_le(self.expansion);
+        buf.put_u32_le(self.layers.len() as u32);
+
+        for layer in &self.layers {
+            buf.put_u64_le(layer.capacity);
+            buf.put_f64_le(layer.error_rate);
+            buf.put_u32_le(layer.hashes);
+            buf.put_u64_le(layer.bits);
+            buf.put_u64_le(layer.items);
+            buf.put_u64_le(layer.data.len() as u64);
+            buf.put_slice(&layer.data);
+        }
+
+        buf
+    }
+
+    /// Reverse of [`BloomFilter::to_bytes`], `None` when the payload is malformed
+    pub fn from_bytes(mut buf: &[u8]) -> Option<Self> {
+        if buf.remaining() < 8 {
+            return None;
+        }
+
+        let expansion = buf.get_u32_le();
+        let count = buf.get_u32_le();
+        let mut layers = Vec::new();
+
+        for _ in 0..count {
+            if buf.remaining() < 44 {
+                return None;
+            }
+
+            let capacity = buf.get_u64_le();
+            let error_rate = buf.get_f64_le();
+            let hashes = buf.get_u32_le();
+            let bits = buf.get_u64_le();
+            let items = buf.get_u64_le();
+            let len = buf.get_u64_le() as usize;
+
+            if buf.remaining() < len || bits == 0 || (len as u64) * 8 < bits {
+                return None;
+            }
+
+            // Every lookup hashes `hashes` times, more than the error rate
+            // calls for would only come from a forged payload
+            let valid_rate = error_rate > 0.0 && error_rate < 1.0;
+
+            if capacity == 0 || !valid_rate || hashes == 0 || hashes > hashes_for(error_rate) {
+                return None;
+            }
+
+            let data = buf[..len].to_vec();
+            buf.advance(len);
+
+            layers.push(BloomLayer {
+                capacity,
+                error_rate,
+                hashes,
+                bits,
+                items,
+                data,
+            });
+        }
+
+        if layers.is_empty() || buf.has_remaining() || expansion > MAX_EXPANSION {
+            return None;
+        }
+
+        Some(Self { layers, expansion })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_and_contains() {
+        let mut filter = BloomFilter::new(100, 0.01, 2).unwrap();
+
+        assert_eq!(filter.insert(b"foo"), Ok(true));
+        assert_eq!(filter.insert(b"foo"), Ok(false));
+        assert!(filter.contains(b"foo"));
+        assert!(!filter.contains(b"bar"));
+        assert_eq!(filter.items(), 1);
+    }
+
+    #[test]
+    fn scales_when_full() {
+        let mut filter = BloomFilter::new(10, 0.01, 2).unwrap();
+
+        for i in 0..50 {
+            filter.insert(format!("item-{i}").as_bytes()).unwrap();
+        }
+
+        assert!(filter.layers.len() > 1);
+        assert_eq!(filter.layers[1].capacity, 20);
+
+        for i in 0..50 {
+            assert!(filter.contains(format!("item-{i}").as_bytes()));
+        }
+    }
+
+    #[test]
+    fn non_scaling_filter_fills_up() {
+        let mut filter = BloomFilter::new(2, 0.01, 0).unwrap();
+
+        filter.insert(b"a").unwrap();
+        filter.insert(b"b").unwrap();
+
+        assert!(filter.insert(b"c").is_err());
+    }
+
+    #[test]
+    fn serialization_roundtrip() {
+        let mut filter = BloomFilter::new(10, 0.001, 4).unwrap();
+
+        for i in 0..30 {
+            filter.insert(format!("{i}").as_bytes()).unwrap();
+        }
+
+        let bytes = filter.to_bytes();
+
+        assert_eq!(BloomFilter::from_bytes(&bytes), Some(filter));
+        assert_eq!(BloomFilter::from_bytes(&bytes[..bytes.len() - 1]), None);
+    }
+
+    #[test]
+    fn rejects_oversized_filters() {
+        assert!(BloomFilter::new(1 << 40, 0.01, 2).is_err());
+        assert!(BloomFilter::new(100, 1e-300, 2).is_ok());
+        assert!(BloomFilter::new(1 << 30, 1e-300, 2).is_err());
+    }
+
+    #[test]
+    fn rejects_forged_layers() {
+        let bytes = BloomFilter::new(100, 0.01, 2).unwrap().to_bytes();
+        let forged = |offset: usize, value: &[u8]| {
+            let mut bytes = bytes.clone();
+            bytes[offset..offset + value.len()].copy_from_slice(value);
+            BloomFilter::from_bytes(&bytes)
+        };
+
+        assert!(forged(0, &0u32.to_le_bytes()).is_some());
+        assert!(forged(0, &u32::MAX.to_le_bytes()).is_none());
+        assert!(forged(8, &0u64.to_le_bytes()).is_none());
+        assert!(forged(16, &0f64.to_le_bytes()).is_none());
+        assert!(forged(16, &1f64.to_le_bytes()).is_none());
+        assert!(forged(16, &f64::NAN.to_le_bytes()).is_none());
+        assert!(forged(24, &u32::MAX.to_le_bytes()).is_none());
+        assert!(forged(24, &0u32.to_le_bytes()).is_none());
+    }
+}
@@ -0,0 +1,203 @@
+use bytes::{Buf, BufMut};
+
+use crate::utils::hash::murmur64a;
+
+/// Most counters a sketch may have, 512MB of them
+const MAX_COUNTERS: u64 = 1 << 27;
+
+/// Count-Min Sketch, estimates item frequencies with a bounded over-count
+#[derive(Debug, Clone, PartialEq)]
+pub struct CountMinSketch {
+    pub(crate) width: u32,
+    pub(crate) depth: u32,
+    pub(crate) count: u64,
+    pub(crate) counters: Vec<u32>,
+}
+
+impl CountMinSketch {
+    /// A sketch of `depth` rows of `width` counters, an error when it
+    /// would have more than [`MAX_COUNTERS`]
+    pub fn new(width: u32, depth: u32) -> Result<Self, &'static str> {
+        let len = (width as u64)
+            .checked_mul(depth as u64)
+            .filter(|len| *len <= MAX_COUNTERS)
+            .ok_or("CMS: width/depth is too large")?;
+
+        Ok(Self {
+            width,
+            depth,
+            count: 0,
+            counters: vec![0; len as usize],
+        })
+    }
+
+    /// Dimensions for an over-count of `error` (as a fraction of the total
+    /// count) with a probability of `probability` of exceeding it
+    pub fn from_probability(error: f64, probability: f64) -> Result<Self, &'static str> {
+        let width = (2.0 / error).ceil() as u32;
+        let depth = (probability.log10() / 0.5_f64.log10()).ceil() as u32;
+
+        Self::new(width.max(1), depth.max(1))
+    }
+
+    fn index(&self, row: u32, item: &[u8]) -> usize {
+        let column = murmur64a(item, row as u64) % self.width as u64;
+
+        (row as usize * self.width as usize) + column as usize
+    }
+
+    pub fn increment(&mut self, item: &[u8], by: u32) -> u32 {
+        self.count += by as u64;
+
+        for row in 0..self.depth {
+            let index = self.index(row, item);
+            self.counters[index] = self.counters[index].saturating_add(by);
+        }
+
+        self.query(item)
+    }
+
+    pub fn query(&self, item: &[u8]) -> u32 {
+        (0..self.depth)
+            .map(|row| self.counters[self.index(row, item)])
+            .min()
+            .unwrap_or(0)
+    }
+
+    pub fn same_dimensions(&self, other: &Self) -> bool {
+        self.width == other.width && self.depth == other.depth
+    }
+
+    /// Replaces the counters with the weighted sum of `sources`
+    ///
+    /// Nothing changes when a weighted sum overflows.
+    pub fn merge(&mut self, sources: &[(&Self, i64)]) -> Result<(), &'static str> {
+        const OVERFLOW: &str = "CMS: MERGE overflow";
+
+        let weighted = |value: u64, weight: i64, acc: i64| {
+            i64::try_from(value)
+                .ok()
+                .and_then(|value| value.checked_mul(weight))
+                .and_then(|value| value.checked_add(acc))
+                .ok_or(OVERFLOW)
+        };
+
+        let mut counters = vec![0_i64; self.counters.len()];
+        let mut count = 0_i64;
+
+        for (source, weight) in sources {
+            for (acc, value) in counters.iter_mut().zip(source.counters.iter()) {
+                *acc = weighted(*value as u64, *weight, *acc)?;
+            }
+
+            count = weighted(source.count, *weight, count)?;
+        }
+
+        self.counters = counters
+            .into_iter()
+            .map(|value| value.clamp(0, u32::MAX as i64) as u32)
+            .collect();
+        self.count = count.max(0) as u64;
+
+        Ok(())
+    }
+
+    /// Serializes the sketch into a self describing little endian payload
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(16 + self.counters.len() * 4);
+
+        buf.put_u32_le(self.width);
+        buf.put_u32_le(self.depth);
+        buf.put_u64_le(self.count);
+
+        for counter in &self.counters {
+            buf.put_u32_le(*counter);
+        }
+
+        buf
+    }
+
+    /// Reverse of [`CountMinSketch::to_bytes`], `None` when the payload is malformed
+    pub fn from_bytes(mut buf: &[u8]) -> Option<Self> {
+        if buf.remaining() < 16 {
+            return None;
+        }
+
+        let width = buf.get_u32_le();
+        let depth = buf.get_u32_le();
+        let count = buf.get_u64_le();
+        let len = width as usize * depth as usize;
+
+        if width == 0 || depth == 0 || len.checked_mul(4) != Some(buf.remaining()) {
+            return None;
+        }
+
+        let counters = (0..len).map(|_| buf.get_u32_le()).collect();
+
+        Some(Self {
+            width,
+            depth,
+            count,
+            counters,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn increment_and_query() {
+        let mut cms = CountMinSketch::new(100, 5).unwrap();
+
+        assert_eq!(cms.increment(b"foo", 3), 3);
+        assert_eq!(cms.increment(b"foo", 2), 5);
+        assert_eq!(cms.query(b"foo"), 5);
+        assert_eq!(cms.query(b"bar"), 0);
+        assert_eq!(cms.count, 5);
+    }
+
+    #[test]
+    fn dimensions_from_probability() {
+        let cms = CountMinSketch::from_probability(0.001, 0.01).unwrap();
+
+        assert_eq!(cms.width, 2000);
+        assert_eq!(cms.depth, 7);
+    }
+
+    #[test]
+    fn merge_with_weights() {
+        let mut a = CountMinSketch::new(50, 4).unwrap();
+        let mut b = CountMinSketch::new(50, 4).unwrap();
+        let mut dest = CountMinSketch::new(50, 4).unwrap();
+
+        a.increment(b"foo", 2);
+        b.increment(b"foo", 3);
+        dest.merge(&[(&a, 1), (&b, 2)]).unwrap();
+
+        assert_eq!(dest.query(b"foo"), 8);
+        assert_eq!(dest.count, 8);
+
+        assert_eq!(dest.merge(&[(&a, i64::MAX)]), Err("CMS: MERGE overflow"));
+        assert_eq!(dest.query(b"foo"), 8);
+    }
+
+    #[test]
+    fn rejects_oversized_sketches() {
+        assert!(CountMinSketch::new(u32::MAX, u32::MAX).is_err());
+        assert!(CountMinSketch::new(1 << 20, 1 << 8).is_err());
+        assert!(CountMinSketch::from_probability(1e-300, 0.01).is_err());
+    }
+
+    #[test]
+    fn serialization_roundtrip() {
+        let mut cms = CountMinSketch::new(10, 3).unwrap();
+        cms.increment(b"foo", 7);
+
+        let bytes = cms.to_bytes();
+
+        assert_eq!(CountMinSketch::from_bytes(&bytes), Some(cms));
+        assert_eq!(CountMinSketch::from_bytes(&bytes[1..]), None);
+    }
+}
@@ -1,4 +1,12 @@
+mod bloom;
+mod count_min_sketch;
+mod stream;
+mod time_series;
+mod ts_chunk;
 mod value;
 
-pub use value::StoreValue;
-
+pub use bloom::{BloomFilter, MAX_EXPANSION};
+pub use count_min_sketch::CountMinSketch;
+pub use stream::{Consumer, ConsumerGroup, PendingEntry, Stream, StreamId};
+pub use time_series::{aggregate, Aggregation, CompactionRule, DuplicatePolicy, TimeSeries};
+pub use value::{StoreValue, Value};
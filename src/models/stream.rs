@@ -0,0 +1,95 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Id of a stream entry, `<milliseconds>-<sequence>`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub(crate) ms: u64,
+    pub(crate) seq: u64,
+}
+
+impl StreamId {
+    pub fn new(ms: u64, seq: u64) -> Self {
+        Self { ms, seq }
+    }
+
+    /// The 128 bit big endian form used as key of the stream radix tree
+    pub fn to_bytes(self) -> [u8; 16] {
+        let mut bytes = [0; 16];
+        bytes[..8].copy_from_slice(&self.ms.to_be_bytes());
+        bytes[8..].copy_from_slice(&self.seq.to_be_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let ms = u64::from_be_bytes(bytes.get(..8)?.try_into().ok()?);
+        let seq = u64::from_be_bytes(bytes.get(8..16)?.try_into().ok()?);
+
+        Some(Self { ms, seq })
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// Entry delivered to a consumer and not acknowledged yet
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingEntry {
+    /// Unix time in milliseconds of the last delivery
+    pub(crate) delivery_time: u64,
+    pub(crate) delivery_count: u64,
+    pub(crate) consumer: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Consumer {
+    pub(crate) name: String,
+    /// Unix times in milliseconds of the last interaction and of the last
+    /// successful read
+    pub(crate) seen_time: u64,
+    pub(crate) active_time: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsumerGroup {
+    pub(crate) name: String,
+    pub(crate) last_id: StreamId,
+    /// Entries read by the group, `None` when it can't be known
+    pub(crate) entries_read: Option<u64>,
+    pub(crate) pending: BTreeMap<StreamId, PendingEntry>,
+    pub(crate) consumers: Vec<Consumer>,
+}
+
+/// Append only log of field-value entries
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stream {
+    pub(crate) entries: BTreeMap<StreamId, Vec<(String, String)>>,
+    pub(crate) last_id: StreamId,
+    pub(crate) max_deleted_id: StreamId,
+    /// Entries added over the lifetime of the stream, deleted ones included
+    pub(crate) entries_added: u64,
+    pub(crate) groups: Vec<ConsumerGroup>,
+}
+
+impl Stream {
+    pub fn first_id(&self) -> StreamId {
+        self.entries.keys().next().copied().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn id_bytes_round_trip() {
+        let id = StreamId::new(1_700_000_000_000, 3);
+
+        assert_eq!(StreamId::from_bytes(&id.to_bytes()), Some(id));
+        assert_eq!(id.to_string(), "1700000000000-3");
+        assert!(StreamId::new(1, 5) < StreamId::new(2, 0));
+    }
+}
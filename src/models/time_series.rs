@@ -0,0 +1,584 @@
+use std::fmt;
+use std::str::FromStr;
+
+use bytes::{Buf, BufMut};
+
+use super::ts_chunk::Chunk;
+
+/// How to resolve a sample written on an existing timestamp
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DuplicatePolicy {
+    Block,
+    First,
+    Last,
+    Min,
+    Max,
+    Sum,
+}
+
+impl DuplicatePolicy {
+    fn resolve(&self, old: f64, new: f64) -> Result<f64, &'static str> {
+        match self {
+            Self::Block => Err("ERR TSDB: Error at upsert, update is not supported when DUPLICATE_POLICY is set to BLOCK mode"),
+            Self::First => Ok(old),
+            Self::Last => Ok(new),
+            Self::Min => Ok(old.min(new)),
+            Self::Max => Ok(old.max(new)),
+            Self::Sum => Ok(old + new),
+        }
+    }
+}
+
+impl FromStr for DuplicatePolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "block" => Ok(Self::Block),
+            "first" => Ok(Self::First),
+            "last" => Ok(Self::Last),
+            "min" => Ok(Self::Min),
+            "max" => Ok(Self::Max),
+            "sum" => Ok(Self::Sum),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for DuplicatePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Block => "block",
+            Self::First => "first",
+            Self::Last => "last",
+            Self::Min => "min",
+            Self::Max => "max",
+            Self::Sum => "sum",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+/// Aggregation applied to the samples of a bucket
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aggregation {
+    Avg,
+    Sum,
+    Min,
+    Max,
+    Count,
+    First,
+    Last,
+    Range,
+}
+
+impl Aggregation {
+    /// Aggregates a non empty list of values
+    pub fn apply(&self, values: &[f64]) -> f64 {
+        let min = || values.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = || values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+
+        match self {
+            Self::Avg => values.iter().sum::<f64>() / values.len() as f64,
+            Self::Sum => values.iter().sum(),
+            Self::Min => min(),
+            Self::Max => max(),
+            Self::Count => values.len() as f64,
+            Self::First => values[0],
+            Self::Last => values[values.len() - 1],
+            Self::Range => max() - min(),
+        }
+    }
+}
+
+impl FromStr for Aggregation {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "avg" => Ok(Self::Avg),
+            "sum" => Ok(Self::Sum),
+            "min" => Ok(Self::Min),
+            "max" => Ok(Self::Max),
+            "count" => Ok(Self::Count),
+            "first" => Ok(Self::First),
+            "last" => Ok(Self::Last),
+            "range" => Ok(Self::Range),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for Aggregation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Avg => "avg",
+            Self::Sum => "sum",
+            Self::Min => "min",
+            Self::Max => "max",
+            Self::Count => "count",
+            Self::First => "first",
+            Self::Last => "last",
+            Self::Range => "range",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+/// Groups samples into `bucket` wide windows aligned to the epoch
+pub fn aggregate(samples: &[(u64, f64)], aggregation: Aggregation, bucket: u64) -> Vec<(u64, f64)> {
+    let mut result = Vec::new();
+    let mut current: Option<u64> = None;
+    let mut values = Vec::new();
+
+    for (timestamp, value) in samples {
+        let start = timestamp - timestamp % bucket;
+
+        if current.is_some_and(|c| c != start) {
+            result.push((current.unwrap(), aggregation.apply(&values)));
+            values.clear();
+        }
+
+        current = Some(start);
+        values.push(*value);
+    }
+
+    if let Some(start) = current {
+        result.push((start, aggregation.apply(&values)));
+    }
+
+    result
+}
+
+/// Downsampling rule writing aggregated buckets into another series
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompactionRule {
+    pub(crate) dest: String,
+    pub(crate) aggregation: Aggregation,
+    pub(crate) bucket: u64,
+    /// Start of the bucket still accepting samples
+    pub(crate) current_bucket: Option<u64>,
+}
+
+impl CompactionRule {
+    pub fn new(dest: String, aggregation: Aggregation, bucket: u64) -> Self {
+        Self {
+            dest,
+            aggregation,
+            bucket,
+            current_bucket: None,
+        }
+    }
+}
+
+/// Sample the compaction rules of a series want written into their destination
+#[derive(Debug, PartialEq)]
+pub struct CompactedSample {
+    pub(crate) dest: String,
+    pub(crate) timestamp: u64,
+    pub(crate) value: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeSeries {
+    pub(crate) chunks: Vec<Chunk>,
+    /// Samples older than `last timestamp - retention` are dropped, `0` keeps everything
+    pub(crate) retention: u64,
+    pub(crate) chunk_size: usize,
+    pub(crate) duplicate_policy: DuplicatePolicy,
+    pub(crate) labels: Vec<(String, String)>,
+    pub(crate) rules: Vec<CompactionRule>,
+    /// Series feeding this one through a compaction rule
+    pub(crate) source: Option<String>,
+}
+
+impl TimeSeries {
+    pub fn new(retention: u64, chunk_size: usize, duplicate_policy: DuplicatePolicy) -> Self {
+        Self {
+            chunks: Vec::new(),
+            retention,
+            chunk_size,
+            duplicate_policy,
+            labels: Vec::new(),
+            rules: Vec::new(),
+            source: None,
+        }
+    }
+
+    pub fn total_samples(&self) -> usize {
+        self.chunks.iter().map(|chunk| chunk.count).sum()
+    }
+
+    pub fn first_timestamp(&self) -> Option<u64> {
+        self.chunks.first().map(|chunk| chunk.first_timestamp)
+    }
+
+    pub fn last_timestamp(&self) -> Option<u64> {
+        self.chunks.last().map(|chunk| chunk.last_timestamp)
+    }
+
+    pub fn last_sample(&self) -> Option<(u64, f64)> {
+        self.chunks.last().and_then(|chunk| chunk.samples().pop())
+    }
+
+    /// Bytes used by the compressed chunks
+    pub fn memory_usage(&self) -> usize {
+        self.chunks.iter().map(|chunk| chunk.size()).sum::<usize>() + std::mem::size_of::<Self>()
+    }
+
+    /// Inserts a sample, `policy` overrides the series duplicate policy
+    ///
+    /// Returns the timestamp and value that ended up stored.
+    pub fn add(
+        &mut self,
+        timestamp: u64,
+        value: f64,
+        policy: Option<DuplicatePolicy>,
+    ) -> Result<(u64, f64), &'static str> {
+        if let Some(last) = self.last_timestamp() {
+            if self.retention > 0 && timestamp < last.saturating_sub(self.retention) {
+                return Err("ERR TSDB: Timestamp is older than retention");
+            }
+
+            if timestamp <= last {
+                let value = self.upsert(timestamp, value, policy.unwrap_or(self.duplicate_policy))?;
+                return Ok((timestamp, value));
+            }
+        }
+
+        let needs_chunk = match self.chunks.last() {
+            Some(chunk) => chunk.size() >= self.chunk_size,
+            None => true,
+        };
+
+        if needs_chunk {
+            self.chunks.push(Chunk::default());
+        }
+
+        self.chunks
+            .last_mut()
+            .expect("chunk was just created")
+            .push(timestamp, value);
+        self.trim();
+
+        Ok((timestamp, value))
+    }
+
+    /// Rewrites the chunk holding `timestamp`, chunks can only be appended to
+    fn upsert(&mut self, timestamp: u64, value: f64, policy: DuplicatePolicy) -> Result<f64, &'static str> {
+        let index = self
+            .chunks
+            .iter()
+            .rposition(|chunk| chunk.first_timestamp <= timestamp)
+            .unwrap_or(0);
+
+        let mut samples = self.chunks[index].samples();
+        let stored = match samples.binary_search_by_key(&timestamp, |(ts, _)| *ts) {
+            Ok(position) => {
+                let resolved = policy.resolve(samples[position].1, value)?;
+                samples[position].1 = resolved;
+                resolved
+            }
+            Err(position) => {
+                samples.insert(position, (timestamp, value));
+                value
+            }
+        };
+
+        self.chunks[index] = Chunk::from_samples(&samples);
+
+        Ok(stored)
+    }
+
+    /// Drops samples that fell out of the retention window
+    fn trim(&mut self) {
+        let last = match self.last_timestamp() {
+            Some(last) if self.retention > 0 => last,
+            _ => return,
+        };
+
+        let cutoff = last.saturating_sub(self.retention);
+
+        self.chunks.retain(|chunk| chunk.last_timestamp >= cutoff);
+
+        if let Some(first) = self.chunks.first_mut() {
+            if first.first_timestamp < cutoff {
+                let samples: Vec<(u64, f64)> = first
+                    .samples()
+                    .into_iter()
+                    .filter(|(ts, _)| *ts >= cutoff)
+                    .collect();
+
+                *first = Chunk::from_samples(&samples);
+            }
+        }
+    }
+
+    /// Samples with `from <= timestamp <= to` in ascending order
+    pub fn range(&self, from: u64, to: u64) -> Vec<(u64, f64)> {
+        let cutoff = match self.last_timestamp() {
+            Some(last) if self.retention > 0 => last.saturating_sub(self.retention),
+            _ => 0,
+        };
+        let from = from.max(cutoff);
+
+        self.chunks
+            .iter()
+            .filter(|chunk| chunk.last_timestamp >= from && chunk.first_timestamp <= to)
+            .flat_map(|chunk| chunk.samples())
+            .filter(|(ts, _)| *ts >= from && *ts <= to)
+            .collect()
+    }
+
+    /// Advances the compaction rules after a sample landed at `timestamp`
+    ///
+    /// Closed buckets (or past buckets that were updated) are aggregated from
+    /// this series and returned so the caller can write them into the
+    /// destination series.
+    pub fn compact(&mut self, timestamp: u64) -> Vec<CompactedSample> {
+        let mut output = Vec::new();
+        let mut rules = std::mem::take(&mut self.rules);
+
+        for rule in rules.iter_mut() {
+            let start = timestamp - timestamp % rule.bucket;
+            let current = *rule.current_bucket.get_or_insert(start);
+
+            let closed = if start > current {
+                rule.current_bucket = Some(start);
+                Some(current)
+            } else if start < current {
+                Some(start)
+            } else {
+                None
+            };
+
+            if let Some(bucket) = closed {
+                let values: Vec<f64> = self
+                    .range(bucket, bucket.saturating_add(rule.bucket - 1))
+                    .into_iter()
+                    .map(|(_, v)| v)
+                    .collect();
+
+                if !values.is_empty() {
+                    output.push(CompactedSample {
+                        dest: rule.dest.clone(),
+                        timestamp: bucket,
+                        value: rule.aggregation.apply(&values),
+                    });
+                }
+            }
+        }
+
+        self.rules = rules;
+        output
+    }
+
+    /// Serializes the series into a self describing little endian payload
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let put_str = |buf: &mut Vec<u8>, s: &str| {
+            buf.put_u32_le(s.len() as u32);
+            buf.put_slice(s.as_bytes());
+        };
+
+        buf.put_u64_le(self.retention);
+        buf.put_u64_le(self.chunk_size as u64);
+        put_str(&mut buf, &self.duplicate_policy.to_string());
+        put_str(&mut buf, self.source.as_deref().unwrap_or(""));
+
+        buf.put_u32_le(self.labels.len() as u32);
+        for (name, value) in &self.labels {
+            put_str(&mut buf, name);
+            put_str(&mut buf, value);
+        }
+
+        buf.put_u32_le(self.rules.len() as u32);
+        for rule in &self.rules {
+            put_str(&mut buf, &rule.dest);
+            put_str(&mut buf, &rule.aggregation.to_string());
+            buf.put_u64_le(rule.bucket);
+            buf.put_u64_le(rule.current_bucket.map_or(u64::MAX, |b| b));
+        }
+
+        buf.put_u32_le(self.chunks.len() as u32);
+        for chunk in &self.chunks {
+            let (count, data) = chunk.to_bytes();
+            buf.put_u64_le(count as u64);
+            buf.put_u32_le(data.len() as u32);
+            buf.put_slice(&data);
+        }
+
+        buf
+    }
+
+    /// Reverse of [`TimeSeries::to_bytes`], `None` when the payload is malformed
+    pub fn from_bytes(mut buf: &[u8]) -> Option<Self> {
+        fn get_u32(buf: &mut &[u8]) -> Option<u32> {
+            (buf.remaining() >= 4).then(|| buf.get_u32_le())
+        }
+
+        fn get_u64(buf: &mut &[u8]) -> Option<u64> {
+            (buf.remaining() >= 8).then(|| buf.get_u64_le())
+        }
+
+        fn get_bytes<'a>(buf: &mut &'a [u8]) -> Option<&'a [u8]> {
+            let len = get_u32(buf)? as usize;
+            let bytes = buf.get(..len)?;
+            buf.advance(len);
+            Some(bytes)
+        }
+
+        fn get_str(buf: &mut &[u8]) -> Option<String> {
+            String::from_utf8(get_bytes(buf)?.to_vec()).ok()
+        }
+
+        let retention = get_u64(&mut buf)?;
+        let chunk_size = get_u64(&mut buf)? as usize;
+        let duplicate_policy = get_str(&mut buf)?.parse().ok()?;
+        let source = Some(get_str(&mut buf)?).filter(|s| !s.is_empty());
+
+        let mut series = TimeSeries::new(retention, chunk_size, duplicate_policy);
+        series.source = source;
+
+        for _ in 0..get_u32(&mut buf)? {
+            series.labels.push((get_str(&mut buf)?, get_str(&mut buf)?));
+        }
+
+        for _ in 0..get_u32(&mut buf)? {
+            let dest = get_str(&mut buf)?;
+            let aggregation = get_str(&mut buf)?.parse().ok()?;
+            // Samples are bucketed modulo it, like `TS.CREATERULE` it must not be zero
+            let bucket = Some(get_u64(&mut buf)?).filter(|bucket| *bucket > 0)?;
+            let current_bucket = Some(get_u64(&mut buf)?).filter(|b| *b != u64::MAX);
+
+            series.rules.push(CompactionRule {
+                dest,
+                aggregation,
+                bucket,
+                current_bucket,
+            });
+        }
+
+        for _ in 0..get_u32(&mut buf)? {
+            let count = get_u64(&mut buf)? as usize;
+            let data = get_bytes(&mut buf)?;
+
+            series.chunks.push(Chunk::from_bytes(count, data)?);
+        }
+
+        if buf.has_remaining() {
+            return None;
+        }
+
+        Some(series)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn series() -> TimeSeries {
+        TimeSeries::new(0, 4096, DuplicatePolicy::Block)
+    }
+
+    #[test]
+    fn add_and_range() {
+        let mut ts = series();
+
+        for i in 0..10 {
+            ts.add(i * 10, i as f64, None).unwrap();
+        }
+
+        assert_eq!(ts.range(20, 40), vec![(20, 2.0), (30, 3.0), (40, 4.0)]);
+        assert_eq!(ts.total_samples(), 10);
+    }
+
+    #[test]
+    fn out_of_order_and_duplicates() {
+        let mut ts = series();
+
+        ts.add(10, 1.0, None).unwrap();
+        ts.add(30, 3.0, None).unwrap();
+        ts.add(20, 2.0, None).unwrap();
+
+        assert_eq!(ts.range(0, 100), vec![(10, 1.0), (20, 2.0), (30, 3.0)]);
+        assert!(ts.add(20, 5.0, None).is_err());
+        assert_eq!(ts.add(20, 5.0, Some(DuplicatePolicy::Sum)), Ok((20, 7.0)));
+    }
+
+    #[test]
+    fn retention_drops_old_samples() {
+        let mut ts = TimeSeries::new(100, 16, DuplicatePolicy::Last);
+
+        for i in 0..100 {
+            ts.add(i * 10, i as f64, None).unwrap();
+        }
+
+        assert_eq!(ts.first_timestamp(), Some(890));
+        assert!(ts.add(100, 1.0, None).is_err());
+        assert_eq!(ts.range(0, u64::MAX).len(), 11);
+    }
+
+    #[test]
+    fn aggregate_buckets() {
+        let samples = vec![(0, 1.0), (5, 3.0), (10, 10.0), (25, 4.0)];
+
+        assert_eq!(aggregate(&samples, Aggregation::Avg, 10), vec![(0, 2.0), (10, 10.0), (20, 4.0)]);
+        assert_eq!(aggregate(&samples, Aggregation::Count, 20), vec![(0, 3.0), (20, 1.0)]);
+    }
+
+    #[test]
+    fn compaction_closes_buckets() {
+        let mut ts = series();
+        ts.rules.push(CompactionRule::new("dest".to_string(), Aggregation::Sum, 10));
+
+        for (timestamp, value) in [(1, 1.0), (5, 2.0), (12, 4.0)] {
+            ts.add(timestamp, value, None).unwrap();
+        }
+
+        assert_eq!(ts.compact(1), vec![]);
+        assert_eq!(
+            ts.compact(12),
+            vec![CompactedSample {
+                dest: "dest".to_string(),
+                timestamp: 0,
+                value: 3.0,
+            }]
+        );
+    }
+
+    #[test]
+    fn compaction_of_the_last_bucket() {
+        let mut ts = series();
+        ts.rules.push(CompactionRule::new("dest".to_string(), Aggregation::Sum, 10));
+
+        let last = u64::MAX - 1;
+
+        ts.add(last, 2.0, None).unwrap();
+        ts.rules[0].current_bucket = Some(u64::MAX);
+
+        assert_eq!(ts.compact(last)[0].value, 2.0);
+    }
+
+    #[test]
+    fn serialization_roundtrip() {
+        let mut ts = TimeSeries::new(1000, 64, DuplicatePolicy::Max);
+        ts.labels.push(("host".to_string(), "a".to_string()));
+        ts.rules.push(CompactionRule::new("dest".to_string(), Aggregation::Avg, 60));
+
+        for i in 0..50 {
+            ts.add(i * 3, i as f64 * 0.5, None).unwrap();
+        }
+
+        let bytes = ts.to_bytes();
+
+        assert_eq!(TimeSeries::from_bytes(&bytes), Some(ts.clone()));
+        assert_eq!(TimeSeries::from_bytes(&bytes[..bytes.len() - 2]), None);
+
+        ts.rules[0].bucket = 0;
+        assert_eq!(TimeSeries::from_bytes(&ts.to_bytes()), None);
+    }
+}
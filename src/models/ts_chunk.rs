@@ -0,0 +1,359 @@
+/// Bit level writer backing the compressed chunks
+#[derive(Debug, Clone, Default, PartialEq)]
+struct BitWriter {
+    data: Vec<u8>,
+    len: usize,
+}
+
+impl BitWriter {
+    fn write_bit(&mut self, bit: bool) {
+        if self.len == self.data.len() * 8 {
+            self.data.push(0);
+        }
+
+        if bit {
+            let last = self.data.last_mut().expect("buffer was just grown");
+            *last |= 1 << (7 - (self.len % 8));
+        }
+
+        self.len += 1;
+    }
+
+    /// Writes the `count` least significant bits of `value`, most significant first
+    fn write_bits(&mut self, value: u64, count: u32) {
+        for i in (0..count).rev() {
+            self.write_bit((value >> i) & 1 == 1);
+        }
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn read_bit(&mut self) -> Option<bool> {
+        let byte = self.data.get(self.position / 8)?;
+        let bit = (byte >> (7 - (self.position % 8))) & 1 == 1;
+        self.position += 1;
+
+        Some(bit)
+    }
+
+    fn read_bits(&mut self, count: u32) -> Option<u64> {
+        let mut value = 0;
+
+        for _ in 0..count {
+            value = (value << 1) | self.read_bit()? as u64;
+        }
+
+        Some(value)
+    }
+}
+
+/// Payload bits of the delta-of-delta buckets, bucket `n` is prefixed by
+/// `n + 1` one bits and a zero bit (omitted for the last bucket)
+const DOD_BUCKETS: [u32; 4] = [7, 9, 12, 64];
+
+fn fits_signed(value: i64, bits: u32) -> bool {
+    if bits >= 64 {
+        return true;
+    }
+
+    let limit = 1_i64 << (bits - 1);
+    value >= -limit && value < limit
+}
+
+/// Most samples `bits` bits can hold, the first takes 128 bits and every
+/// other one at least 2 (an unchanged delta and an unchanged value)
+fn max_samples(bits: usize) -> usize {
+    match bits.checked_sub(128) {
+        Some(rest) => 1 + rest / 2,
+        None => 0,
+    }
+}
+
+fn sign_extend(value: u64, bits: u32) -> i64 {
+    if bits >= 64 {
+        return value as i64;
+    }
+
+    let shift = 64 - bits;
+    ((value << shift) as i64) >> shift
+}
+
+/// Gorilla compressed block of samples
+///
+/// Timestamps are stored as delta-of-delta and values as the XOR against the
+/// previous value, so regular series with slowly changing values take only a
+/// few bits per sample. Samples can only be appended in timestamp order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Chunk {
+    bits: BitWriter,
+    pub(crate) count: usize,
+    pub(crate) first_timestamp: u64,
+    pub(crate) last_timestamp: u64,
+    last_value: u64,
+    last_delta: i64,
+    leading: u32,
+    trailing: u32,
+}
+
+impl Chunk {
+    pub fn from_samples(samples: &[(u64, f64)]) -> Self {
+        let mut chunk = Chunk::default();
+
+        for (timestamp, value) in samples {
+            chunk.push(*timestamp, *value);
+        }
+
+        chunk
+    }
+
+    /// Bytes used by the compressed samples
+    pub fn size(&self) -> usize {
+        self.bits.data.len()
+    }
+
+    /// Appends a sample, the caller guarantees `timestamp > last_timestamp`
+    pub fn push(&mut self, timestamp: u64, value: f64) {
+        let value = value.to_bits();
+
+        if self.count == 0 {
+            self.bits.write_bits(timestamp, 64);
+            self.bits.write_bits(value, 64);
+            self.first_timestamp = timestamp;
+            self.leading = u32::MAX;
+        } else {
+            // Decoding wraps the same way, so the arithmetic can't overflow
+            // and every timestamp round trips, even ones from a payload
+            let delta = timestamp.wrapping_sub(self.last_timestamp) as i64;
+            self.write_timestamp(delta.wrapping_sub(self.last_delta));
+            self.write_value(value ^ self.last_value);
+            self.last_delta = delta;
+        }
+
+        self.count += 1;
+        self.last_timestamp = timestamp;
+        self.last_value = value;
+    }
+
+    fn write_timestamp(&mut self, dod: i64) {
+        if dod == 0 {
+            self.bits.write_bit(false);
+            return;
+        }
+
+        let bucket = DOD_BUCKETS
+            .iter()
+            .position(|bits| fits_signed(dod, *bits))
+            .expect("last bucket fits every value");
+
+        for _ in 0..=bucket {
+            self.bits.write_bit(true);
+        }
+
+        if bucket < DOD_BUCKETS.len() - 1 {
+            self.bits.write_bit(false);
+        }
+
+        self.bits.write_bits(dod as u64, DOD_BUCKETS[bucket]);
+    }
+
+    fn write_value(&mut self, xor: u64) {
+        if xor == 0 {
+            self.bits.write_bit(false);
+            return;
+        }
+
+        self.bits.write_bit(true);
+
+        let leading = xor.leading_zeros().min(63);
+        let trailing = xor.trailing_zeros();
+
+        if self.leading != u32::MAX && leading >= self.leading && trailing >= self.trailing {
+            let meaningful = 64 - self.leading - self.trailing;
+
+            self.bits.write_bit(false);
+            self.bits.write_bits(xor >> self.trailing, meaningful);
+            return;
+        }
+
+        let meaningful = 64 - leading - trailing;
+
+        self.bits.write_bit(true);
+        self.bits.write_bits(leading as u64, 6);
+        self.bits.write_bits((meaningful - 1) as u64, 6);
+        self.bits.write_bits(xor >> trailing, meaningful);
+
+        self.leading = leading;
+        self.trailing = trailing;
+    }
+
+    /// Decompresses every sample of the chunk
+    pub fn samples(&self) -> Vec<(u64, f64)> {
+        // Chunks built by `push` always decode, payloads are checked by `from_bytes`
+        self.decode().unwrap_or_default()
+    }
+
+    /// Samples of the encoded bits, `None` when they are malformed
+    fn decode(&self) -> Option<Vec<(u64, f64)>> {
+        let mut reader = BitReader::new(&self.bits.data);
+        let mut samples = Vec::with_capacity(self.count.min(max_samples(self.bits.len)));
+
+        if self.count == 0 {
+            return Some(samples);
+        }
+
+        let mut timestamp = reader.read_bits(64)?;
+        let mut value = reader.read_bits(64)?;
+        let mut delta = 0_i64;
+        let mut leading = 0_u32;
+        let mut trailing = 0_u32;
+
+        samples.push((timestamp, f64::from_bits(value)));
+
+        for _ in 1..self.count {
+            let dod = Self::read_timestamp(&mut reader)?;
+            delta = delta.wrapping_add(dod);
+            timestamp = timestamp.wrapping_add(delta as u64);
+
+            if reader.read_bit()? {
+                if reader.read_bit()? {
+                    leading = reader.read_bits(6)? as u32;
+                    let meaningful = reader.read_bits(6)? as u32 + 1;
+                    trailing = 64_u32.checked_sub(leading)?.checked_sub(meaningful)?;
+                }
+
+                let meaningful = 64 - leading - trailing;
+                let xor = reader.read_bits(meaningful)?;
+                value ^= xor << trailing;
+            }
+
+            samples.push((timestamp, f64::from_bits(value)));
+        }
+
+        Some(samples)
+    }
+
+    fn read_timestamp(reader: &mut BitReader<'_>) -> Option<i64> {
+        let mut ones = 0;
+
+        while ones < DOD_BUCKETS.len() && reader.read_bit()? {
+            ones += 1;
+        }
+
+        if ones == 0 {
+            return Some(0);
+        }
+
+        let payload = DOD_BUCKETS[ones - 1];
+        let value = reader.read_bits(payload)?;
+
+        Some(sign_extend(value, payload))
+    }
+
+    /// Raw encoded payload, used to persist the chunk
+    pub fn to_bytes(&self) -> (usize, Vec<u8>) {
+        (self.count, self.bits.data.clone())
+    }
+
+    /// Rebuilds a chunk from [`Chunk::to_bytes`]
+    pub fn from_bytes(count: usize, data: &[u8]) -> Option<Self> {
+        if count > max_samples(data.len().saturating_mul(8)) {
+            return None;
+        }
+
+        let partial = Chunk {
+            bits: BitWriter {
+                data: data.to_vec(),
+                len: data.len() * 8,
+            },
+            count,
+            ..Default::default()
+        };
+
+        // Replaying the samples restores the encoder state needed to append
+        let samples = partial.decode()?;
+
+        if samples.len() != count || samples.windows(2).any(|w| w[0].0 >= w[1].0) {
+            return None;
+        }
+
+        Some(Chunk::from_samples(&samples))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip_regular_series() {
+        let samples: Vec<(u64, f64)> = (0..100).map(|i| (1000 + i * 10, 20.5)).collect();
+        let chunk = Chunk::from_samples(&samples);
+
+        assert_eq!(chunk.samples(), samples);
+        // 128 bits for the header and 2 bits per repeated sample
+        assert!(chunk.size() < 16 + 100 / 4 + 8);
+    }
+
+    #[test]
+    fn roundtrip_irregular_series() {
+        let timestamps = [1, 2, 70, 71, 400, 3000, 3001, 90_000, 90_001, 10_000_000_000];
+        let samples: Vec<(u64, f64)> = timestamps
+            .iter()
+            .enumerate()
+            .map(|(i, ts)| (*ts, (i as f64 * 1.37).sin() * 1e6))
+            .collect();
+
+        let chunk = Chunk::from_samples(&samples);
+
+        assert_eq!(chunk.samples(), samples);
+    }
+
+    #[test]
+    fn roundtrip_bytes() {
+        let samples: Vec<(u64, f64)> = (0..20).map(|i| (i * 7, i as f64 / 3.0)).collect();
+        let chunk = Chunk::from_samples(&samples);
+        let (count, data) = chunk.to_bytes();
+        let restored = Chunk::from_bytes(count, &data).unwrap();
+
+        assert_eq!(restored.samples(), samples);
+        assert_eq!(restored, chunk);
+    }
+
+    #[test]
+    fn count_is_bounded_by_data() {
+        let samples: Vec<(u64, f64)> = (0..20).map(|i| (i * 10, 1.0)).collect();
+        let (count, data) = Chunk::from_samples(&samples).to_bytes();
+
+        assert!(Chunk::from_bytes(count, &data).is_some());
+        assert!(Chunk::from_bytes(max_samples(data.len() * 8) + 1, &data).is_none());
+        assert!(Chunk::from_bytes(usize::MAX, &data).is_none());
+        assert!(Chunk::from_bytes(1, &[0; 15]).is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_bits() {
+        // Second sample: same timestamp delta, then a value claiming 63
+        // leading zeros and 64 meaningful bits
+        let mut data = vec![0; 16];
+        data.extend([0x7f, 0xff]);
+
+        assert!(Chunk::from_bytes(2, &data).is_none());
+    }
+
+    #[test]
+    fn extreme_timestamps_roundtrip() {
+        let samples = vec![(0, 1.0), (1, 2.0), (i64::MAX as u64, 3.0), (u64::MAX, 4.0)];
+        let chunk = Chunk::from_samples(&samples);
+
+        assert_eq!(chunk.samples(), samples);
+    }
+}
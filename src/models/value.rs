@@ -1,8 +1,63 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
 use std::time::Duration;
 
-#[derive(Debug)]
+use super::{BloomFilter, CountMinSketch, Stream, TimeSeries};
+
+/// Every kind of data a key can hold
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    List(VecDeque<String>),
+    Set(HashSet<String>),
+    Hash(HashMap<String, String>),
+    /// Members ordered by score, then member
+    SortedSet(Vec<(String, f64)>),
+    Stream(Stream),
+    BloomFilter(BloomFilter),
+    CountMinSketch(CountMinSketch),
+    TimeSeries(TimeSeries),
+}
+
+impl Value {
+    /// Name reported by the `TYPE` command
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Set(_) => "set",
+            Value::Hash(_) => "hash",
+            Value::SortedSet(_) => "zset",
+            Value::Stream(_) => "stream",
+            Value::BloomFilter(_) => "MBbloom--",
+            Value::CountMinSketch(_) => "CMSk-type",
+            Value::TimeSeries(_) => "TSDB-TYPE",
+        }
+    }
+
+    pub fn as_string(&self) -> Option<&String> {
+        match self {
+            Value::String(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.to_string())
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct StoreValue {
-    pub(crate) data: String,
+    pub(crate) data: Value,
     pub(crate) expire_time: Option<Duration>,
     pub(crate) created_at: std::time::SystemTime,
 }
@@ -14,9 +69,9 @@ impl PartialEq for StoreValue {
 }
 
 impl StoreValue {
-    pub fn new(data: String, expire_time: Option<Duration>) -> Self {
+    pub fn new(data: impl Into<Value>, expire_time: Option<Duration>) -> Self {
         Self {
-            data,
+            data: data.into(),
             expire_time,
             created_at: std::time::SystemTime::now(),
         }
@@ -24,9 +79,18 @@ impl StoreValue {
 }
 
 impl StoreValue {
+    /// Absolute time the value expires at
+    pub fn expires_at(&self) -> Option<std::time::SystemTime> {
+        self.expire_time.and_then(|ttl| self.created_at.checked_add(ttl))
+    }
+
+    pub fn set_expires_at(&mut self, at: std::time::SystemTime) {
+        self.expire_time = Some(at.duration_since(self.created_at).unwrap_or_default());
+    }
+
     pub fn is_expired(&self) -> bool {
         if let Some(expire_time) = self.expire_time {
-            self.created_at.elapsed().unwrap() > expire_time
+            self.created_at.elapsed().unwrap_or_default() > expire_time
         } else {
             false
         }
@@ -35,43 +99,39 @@ impl StoreValue {
 
 impl From<String> for StoreValue {
     fn from(data: String) -> Self {
-        Self {
-            data,
-            expire_time: None,
-            created_at: std::time::SystemTime::now(),
-        }
+        Self::new(data, None)
     }
 }
 
 impl From<&str> for StoreValue {
     fn from(data: &str) -> Self {
-        Self {
-            data: data.to_string(),
-            expire_time: None,
-            created_at: std::time::SystemTime::now(),
-        }
+        Self::new(data, None)
+    }
+}
+
+impl From<Value> for StoreValue {
+    fn from(data: Value) -> Self {
+        Self::new(data, None)
     }
 }
 
 impl From<StoreValue> for String {
     fn from(store_value: StoreValue) -> String {
-        store_value.data
+        store_value.to_string()
     }
 }
 
-impl ToString for StoreValue {
-    fn to_string(&self) -> String {
-        self.data.clone()
+impl fmt::Display for StoreValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.data {
+            Value::String(value) => write!(f, "{value}"),
+            other => write!(f, "{}", other.type_name()),
+        }
     }
 }
 
 impl Default for StoreValue {
     fn default() -> Self {
-        Self {
-            data: "".to_string(),
-            expire_time: None,
-            created_at: std::time::SystemTime::now(),
-        }
+        Self::new("", None)
     }
 }
- 
@@ -1,3 +1,5 @@
+use std::fmt;
+
 use super::types::RespType;
 
 #[derive(Debug, PartialEq)]
@@ -6,27 +8,42 @@ pub enum Error {
     WrongType,
     UnknownCommand { command: String },
     WrongNumberOfArguments { command: String },
+    NotAnInteger,
+    NotAFloat,
+    Syntax,
     Unknown,
 }
 
-impl ToString for Error {
-    fn to_string(&self) -> String {
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Custom { message } => message.to_string(),
-            Self::WrongType => {
-                "WRONGTYPE Operation against a key holding the wrong kind of value".to_string()
-            }
-            Self::UnknownCommand { command } => format!("ERR unknown command '{command}'"),
+            Self::Custom { message } => write!(f, "{message}"),
+            Self::WrongType => write!(
+                f,
+                "WRONGTYPE Operation against a key holding the wrong kind of value"
+            ),
+            Self::UnknownCommand { command } => write!(f, "ERR unknown command '{command}'"),
             Self::WrongNumberOfArguments { command } => {
-                format!("wrong number of arguments for '{command}' command")
+                write!(f, "wrong number of arguments for '{command}' command")
             }
-            Self::Unknown => "Unknown error".to_string(),
+            Self::NotAnInteger => write!(f, "ERR value is not an integer or out of range"),
+            Self::NotAFloat => write!(f, "ERR value is not a valid float"),
+            Self::Syntax => write!(f, "ERR syntax error"),
+            Self::Unknown => write!(f, "Unknown error"),
+        }
+    }
+}
+
+impl Error {
+    pub fn custom(message: impl Into<String>) -> Self {
+        Self::Custom {
+            message: message.into(),
         }
     }
 }
 
-impl Into<RespType> for Error {
-    fn into(self) -> RespType {
-        RespType::SimpleError(self)
+impl From<Error> for RespType {
+    fn from(err: Error) -> RespType {
+        RespType::SimpleError(err)
     }
 }
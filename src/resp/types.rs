@@ -1,5 +1,30 @@
 use super::errors::Error;
-use std::str::Lines;
+use std::fmt;
+
+/// Maps every byte to the char with the same code point (Latin-1)
+///
+/// Values from the wire are kept this way so that binary data, like `DUMP`
+/// payloads, survives the trip through a `String` unchanged.
+pub fn bytes_to_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| *byte as char).collect()
+}
+
+/// Inverse of [`bytes_to_string`], the bytes to send on the wire
+pub fn string_to_bytes(value: &str) -> Vec<u8> {
+    value.chars().map(|c| c as u32 as u8).collect()
+}
+
+/// Longest bulk string accepted from a peer, Redis' `proto-max-bulk-len`
+const MAX_BULK_LEN: i64 = 512 * 1024 * 1024;
+
+/// Most elements of an array accepted from a peer
+const MAX_ARRAY_LEN: i64 = 1024 * 1024;
+
+/// Longest line without a terminating CRLF, like inline requests in Redis
+const MAX_LINE_LEN: usize = 64 * 1024;
+
+/// Deepest nesting of arrays accepted from a peer
+const MAX_DEPTH: usize = 32;
 
 /// RESP types from redis protocol
 #[derive(Debug, PartialEq)]
@@ -7,16 +32,21 @@ pub enum RespType {
     BulkString { len: usize, value: String },
     SimpleString { value: String },
     Array { len: usize, values: Vec<RespType> },
+    Integer(i64),
     SimpleError(Error),
     Null,
+    NullArray,
+    /// Out of band data, RESP3 `>` type (plain array for RESP2 clients)
+    Push(Vec<RespType>),
+    /// RESP3 `%` type (flat array of key/values for RESP2 clients)
+    Map(Vec<(RespType, RespType)>),
 }
 
 impl TryFrom<String> for RespType {
     type Error = Error;
 
     fn try_from(value: String) -> Result<Self, Self::Error> {
-        let mut lines = value.lines();
-        return Self::resp_type_parse(&mut lines);
+        Self::try_from(value.as_str())
     }
 }
 
@@ -24,8 +54,10 @@ impl TryFrom<&str> for RespType {
     type Error = Error;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        let mut lines = value.lines();
-        return Self::resp_type_parse(&mut lines);
+        match Self::parse(&string_to_bytes(value))? {
+            Some((resp_type, _)) => Ok(resp_type),
+            None => Err(Error::Unknown),
+        }
     }
 }
 
@@ -35,66 +67,316 @@ impl From<RespType> for String {
     }
 }
 
-impl ToString for RespType {
-    fn to_string(&self) -> String {
+impl fmt::Display for RespType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            RespType::BulkString { len, value } => {
-                if *len == 0 {
-                    return "$-1\r\n".to_string();
-                }
-
-                return format!("${}\r\n{}\r\n", len, value);
-            }
+            RespType::BulkString { len, value } => write!(f, "${}\r\n{}\r\n", len, value),
             RespType::SimpleString { value } => {
-                format!("+{}\r\n", value)
+                write!(f, "+{}\r\n", value)
             }
             RespType::Array { len, values } => {
-                let mut result = format!("*{}\r\n", len);
+                write!(f, "*{}\r\n", len)?;
 
                 for v in values {
-                    result.push_str(&v.to_string());
+                    write!(f, "{}", v)?;
                 }
 
-                result
+                Ok(())
             }
+            RespType::Integer(value) => write!(f, ":{}\r\n", value),
             RespType::SimpleError(err) => {
-                format!("-{}\r\n", err.to_string())
+                write!(f, "-{}\r\n", err)
             }
             // RESP v2 null are null bulk strings this is temp fix for resp v2
-            RespType::Null => "$-1\r\n".to_string(),
+            RespType::Null => write!(f, "$-1\r\n"),
+            RespType::NullArray => write!(f, "*-1\r\n"),
+            RespType::Push(values) => {
+                write!(f, "*{}\r\n", values.len())?;
+
+                for v in values {
+                    write!(f, "{}", v)?;
+                }
+
+                Ok(())
+            }
+            RespType::Map(entries) => {
+                write!(f, "*{}\r\n", entries.len() * 2)?;
+
+                for (key, value) in entries {
+                    write!(f, "{}{}", key, value)?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl RespType {
+    /// Bytes sent to a client speaking protocol version `protocol`
+    pub fn to_bytes(&self, protocol: u8) -> Vec<u8> {
+        string_to_bytes(&self.encode(protocol))
+    }
+
+    /// Serializes the value for a client speaking protocol version `protocol`
+    pub fn encode(&self, protocol: u8) -> String {
+        if protocol < 3 {
+            return self.to_string();
+        }
+
+        match self {
+            RespType::Null | RespType::NullArray => "_\r\n".to_string(),
+            RespType::Array { values, .. } => {
+                let items: String = values.iter().map(|v| v.encode(protocol)).collect();
+                format!("*{}\r\n{}", values.len(), items)
+            }
+            RespType::Push(values) => {
+                let items: String = values.iter().map(|v| v.encode(protocol)).collect();
+                format!(">{}\r\n{}", values.len(), items)
+            }
+            RespType::Map(entries) => {
+                let items: String = entries
+                    .iter()
+                    .map(|(key, value)| key.encode(protocol) + &value.encode(protocol))
+                    .collect();
+                format!("%{}\r\n{}", entries.len(), items)
+            }
+            other => other.to_string(),
+        }
+    }
+}
+
+impl RespType {
+    pub fn bulk_string(value: impl Into<String>) -> Self {
+        let value = value.into();
+
+        RespType::BulkString {
+            len: value.chars().count(),
+            value,
+        }
+    }
+
+    pub fn simple_string(value: impl Into<String>) -> Self {
+        RespType::SimpleString {
+            value: value.into(),
+        }
+    }
+
+    pub fn array(values: Vec<RespType>) -> Self {
+        RespType::Array {
+            len: values.len(),
+            values,
         }
     }
+
+    pub fn ok() -> Self {
+        Self::simple_string("OK")
+    }
 }
 
 impl RespType {
-    // TODO: Refactor to pass characters instead of Lines this can fail when text contain command echo "\r\n"
-    fn resp_type_parse<'a>(lines: &mut Lines<'a>) -> Result<RespType, Error> {
-        let mut chars = lines.next().unwrap().chars();
+    /// Parses one frame from the start of `buf`
+    ///
+    /// Returns `Ok(None)` while the frame is incomplete, otherwise the parsed
+    /// value and the number of bytes it used. Lines not starting with a RESP
+    /// type byte are parsed as inline commands, like `PING\r\n` from telnet.
+    ///
+    /// Lengths are checked before anything is buffered or allocated, a peer
+    /// can't make the server wait for or reserve more than the limits.
+    pub fn parse(buf: &[u8]) -> Result<Option<(RespType, usize)>, Error> {
+        Self::parse_nested(buf, 0)
+    }
 
-        return match chars.next() {
-            Some('*') => {
-                let len_string = chars.collect::<String>();
-                let len = len_string.parse::<usize>().unwrap();
-                let mut values: Vec<RespType> = Vec::new();
+    fn parse_nested(buf: &[u8], depth: usize) -> Result<Option<(RespType, usize)>, Error> {
+        let line_end = match buf.windows(2).position(|w| w == b"\r\n") {
+            Some(position) => position,
+            None if buf.len() > MAX_LINE_LEN => return Err(Error::custom("ERR Protocol error: too big inline request")),
+            None => return Ok(None),
+        };
+
+        let line = &buf[..line_end];
+        let rest = line_end + 2;
+        let text = || bytes_to_string(&line[1..]);
+        let length = || text().parse::<i64>().map_err(|_| Error::custom("ERR Protocol error: invalid length"));
+
+        match line.first() {
+            Some(b'+') => Ok(Some((RespType::SimpleString { value: text() }, rest))),
+            Some(b'-') => Ok(Some((RespType::SimpleError(Error::custom(text())), rest))),
+            Some(b':') => {
+                let value = text().parse::<i64>().map_err(|_| Error::NotAnInteger)?;
+                Ok(Some((RespType::Integer(value), rest)))
+            }
+            Some(b'$') => {
+                let len = length()?;
+
+                if len < 0 {
+                    return Ok(Some((RespType::Null, rest)));
+                }
+
+                if len > MAX_BULK_LEN {
+                    return Err(Error::custom("ERR Protocol error: invalid bulk length"));
+                }
+
+                let len = len as usize;
+
+                if buf.len() < rest + len + 2 {
+                    return Ok(None);
+                }
+
+                let value = bytes_to_string(&buf[rest..rest + len]);
+
+                Ok(Some((RespType::BulkString { len, value }, rest + len + 2)))
+            }
+            Some(b'*') => {
+                let len = length()?;
+
+                if len < 0 {
+                    return Ok(Some((RespType::NullArray, rest)));
+                }
+
+                if len > MAX_ARRAY_LEN {
+                    return Err(Error::custom("ERR Protocol error: invalid multibulk length"));
+                }
+
+                if depth >= MAX_DEPTH {
+                    return Err(Error::custom("ERR Protocol error: too deeply nested array"));
+                }
+
+                // Every element takes at least 3 bytes, don't reserve for
+                // elements that haven't arrived
+                let mut values = Vec::with_capacity((len as usize).min((buf.len() - rest) / 3));
+                let mut consumed = rest;
 
                 for _ in 0..len {
-                    let cm = Self::resp_type_parse(lines).unwrap();
-                    values.push(cm);
+                    match Self::parse_nested(&buf[consumed..], depth + 1)? {
+                        Some((value, used)) => {
+                            values.push(value);
+                            consumed += used;
+                        }
+                        None => return Ok(None),
+                    }
                 }
 
-                Ok(RespType::Array { len, values })
+                Ok(Some((RespType::Array { len: len as usize, values }, consumed)))
             }
-            Some('$') => {
-                let len_string = chars.collect::<String>();
-                let len = len_string.parse::<usize>().unwrap();
-                let value = lines.next().unwrap();
-
-                Ok(RespType::BulkString {
-                    len,
-                    value: value.to_string(),
-                })
+            _ => {
+                let values: Vec<RespType> = bytes_to_string(line)
+                    .split_ascii_whitespace()
+                    .map(RespType::bulk_string)
+                    .collect();
+
+                Ok(Some((RespType::array(values), rest)))
             }
-            _ => return Err(Error::Unknown),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_array_of_bulk_strings() {
+        let input = b"*2\r\n$4\r\necho\r\n$5\r\nhello\r\n";
+        let (value, used) = RespType::parse(input).unwrap().unwrap();
+
+        assert_eq!(used, input.len());
+        assert_eq!(
+            value,
+            RespType::array(vec![RespType::bulk_string("echo"), RespType::bulk_string("hello")])
+        );
+    }
+
+    #[test]
+    fn parse_rejects_oversized_frames() {
+        let protocol_error = |input: &[u8]| match RespType::parse(input) {
+            Err(Error::Custom { message }) => message.starts_with("ERR Protocol error"),
+            _ => false,
         };
+
+        assert!(protocol_error(b"*1000000000000\r\n"));
+        assert!(protocol_error(b"*1048577\r\n"));
+        assert!(protocol_error(b"$536870913\r\n"));
+        assert!(protocol_error(&[b'a'; 70 * 1024]));
+        assert!(protocol_error(&b"*1\r\n".repeat(40)));
+
+        // Within the limits, the frame is only incomplete
+        assert_eq!(RespType::parse(b"*1048576\r\n").unwrap(), None);
+        assert_eq!(RespType::parse(b"$536870912\r\n").unwrap(), None);
+        assert!(RespType::parse(&[b"*1\r\n".repeat(10), b":1\r\n".to_vec()].concat()).unwrap().is_some());
+    }
+
+    #[test]
+    fn parse_incomplete_frame() {
+        assert_eq!(RespType::parse(b"*2\r\n$4\r\necho\r\n$5\r\nhel").unwrap(), None);
+        assert_eq!(RespType::parse(b"*1\r").unwrap(), None);
+    }
+
+    #[test]
+    fn parse_pipelined_frames() {
+        let input = b"*1\r\n$4\r\nping\r\n*1\r\n$4\r\nping\r\n";
+        let (_, used) = RespType::parse(input).unwrap().unwrap();
+        let (value, _) = RespType::parse(&input[used..]).unwrap().unwrap();
+
+        assert_eq!(value, RespType::array(vec![RespType::bulk_string("ping")]));
+    }
+
+    #[test]
+    fn parse_bulk_string_with_crlf() {
+        let input = b"$7\r\nfoo\r\nba\r\n";
+        let (value, _) = RespType::parse(input).unwrap().unwrap();
+
+        assert_eq!(value, RespType::bulk_string("foo\r\nba"));
+    }
+
+    #[test]
+    fn parse_inline_command() {
+        let (value, used) = RespType::parse(b"SET key value\r\n").unwrap().unwrap();
+
+        assert_eq!(used, 15);
+        assert_eq!(
+            value,
+            RespType::array(vec![
+                RespType::bulk_string("SET"),
+                RespType::bulk_string("key"),
+                RespType::bulk_string("value"),
+            ])
+        );
+    }
+
+    #[test]
+    fn encode_resp3_types() {
+        let push = RespType::Push(vec![RespType::bulk_string("message"), RespType::Null]);
+        let map = RespType::Map(vec![(RespType::bulk_string("proto"), RespType::Integer(3))]);
+
+        assert_eq!(push.encode(2), "*2\r\n$7\r\nmessage\r\n$-1\r\n");
+        assert_eq!(push.encode(3), ">2\r\n$7\r\nmessage\r\n_\r\n");
+        assert_eq!(map.encode(2), "*2\r\n$5\r\nproto\r\n:3\r\n");
+        assert_eq!(map.encode(3), "%1\r\n$5\r\nproto\r\n:3\r\n");
+    }
+
+    #[test]
+    fn every_byte_maps_to_one_char() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        let value = bytes_to_string(&bytes);
+
+        assert_eq!(value.chars().count(), 256);
+        assert_eq!(string_to_bytes(&value), bytes);
+        assert!(RespType::bulk_string(value).to_bytes(2).starts_with(b"$256\r\n"));
+    }
+
+    #[test]
+    fn binary_bulk_strings_round_trip() {
+        let input = b"$4\r\n\xc3\xa9\x00\xff\r\n";
+        let (value, _) = RespType::parse(input).unwrap().unwrap();
+
+        assert_eq!(value, RespType::bulk_string(bytes_to_string(b"\xc3\xa9\x00\xff")));
+        assert_eq!(value.to_bytes(2), input.to_vec());
+    }
+
+    #[test]
+    fn empty_bulk_string_is_not_null() {
+        assert_eq!(RespType::bulk_string("").to_string(), "$0\r\n\r\n");
+        assert_eq!(RespType::Null.to_string(), "$-1\r\n");
     }
 }
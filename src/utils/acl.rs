@@ -0,0 +1,751 @@
+//! Users of the server and what they may run, set with `ACL SETUSER`
+//!
+//! Every connection runs its commands as a user, `default` until it
+//! authenticates with `AUTH`. A user lists the commands it may run, by name
+//! or by category, the keys it may read or write and the channels it may
+//! publish or subscribe to. Passwords are only kept as their SHA256.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::resp::{
+    errors::Error,
+    types::{string_to_bytes, RespType},
+};
+
+use super::glob;
+use super::hash::sha256_hex;
+
+/// User every connection starts as
+pub const DEFAULT_USER: &str = "default";
+
+/// Categories of every command, by lowercase name
+const COMMANDS: [(&str, &[&str]); 73] = [
+    ("acl", &["admin", "slow", "dangerous"]),
+    ("asking", &["fast", "connection"]),
+    ("auth", &["fast", "connection"]),
+    ("bf.add", &["write", "bloom"]),
+    ("bf.exists", &["read", "bloom"]),
+    ("bf.info", &["read", "bloom"]),
+    ("bf.madd", &["write", "bloom"]),
+    ("bf.mexists", &["read", "bloom"]),
+    ("bf.reserve", &["write", "bloom"]),
+    ("bgrewriteaof", &["admin", "slow", "dangerous"]),
+    ("bgsave", &["admin", "slow", "dangerous"]),
+    ("client", &["slow", "connection"]),
+    ("cluster", &["admin", "slow", "dangerous"]),
+    ("cms.incrby", &["write", "cms"]),
+    ("cms.info", &["read", "cms"]),
+    ("cms.initbydim", &["write", "cms"]),
+    ("cms.initbyprob", &["write", "cms"]),
+    ("cms.merge", &["write", "cms"]),
+    ("cms.query", &["read", "cms"]),
+    ("config", &["admin", "slow", "dangerous"]),
+    ("discard", &["fast", "transaction"]),
+    ("dump", &["keyspace", "read", "slow"]),
+    ("echo", &["fast", "connection"]),
+    ("eval", &["slow", "scripting"]),
+    ("eval_ro", &["slow", "scripting"]),
+    ("evalsha", &["slow", "scripting"]),
+    ("evalsha_ro", &["slow", "scripting"]),
+    ("exec", &["slow", "transaction"]),
+    ("fcall", &["slow", "scripting"]),
+    ("fcall_ro", &["slow", "scripting"]),
+    ("flushall", &["keyspace", "write", "slow", "dangerous"]),
+    ("flushdb", &["keyspace", "write", "slow", "dangerous"]),
+    ("function", &["slow", "scripting"]),
+    ("get", &["read", "string", "fast"]),
+    ("hello", &["fast", "connection"]),
+    ("info", &["slow", "dangerous"]),
+    ("lastsave", &["fast", "dangerous"]),
+    ("migrate", &["keyspace", "write", "slow", "dangerous"]),
+    ("multi", &["fast", "transaction"]),
+    ("pexpireat", &["keyspace", "write", "fast"]),
+    ("ping", &["fast", "connection"]),
+    ("psubscribe", &["pubsub", "slow"]),
+    ("psync", &["admin", "slow", "dangerous"]),
+    ("publish", &["pubsub", "fast"]),
+    ("pubsub", &["pubsub", "slow"]),
+    ("punsubscribe", &["pubsub", "slow"]),
+    ("quit", &["fast", "connection"]),
+    ("replconf", &["admin", "slow", "dangerous"]),
+    ("replicaof", &["admin", "slow", "dangerous"]),
+    ("restore", &["keyspace", "write", "slow", "dangerous"]),
+    ("restore-asking", &["keyspace", "write", "slow", "dangerous"]),
+    ("save", &["admin", "slow", "dangerous"]),
+    ("script", &["slow", "scripting"]),
+    ("sentinel", &["admin", "slow", "dangerous"]),
+    ("set", &["write", "string", "slow"]),
+    ("slaveof", &["admin", "slow", "dangerous"]),
+    ("spublish", &["pubsub", "fast"]),
+    ("ssubscribe", &["pubsub", "slow"]),
+    ("subscribe", &["pubsub", "slow"]),
+    ("sunsubscribe", &["pubsub", "slow"]),
+    ("ts.add", &["write", "timeseries"]),
+    ("ts.create", &["write", "timeseries"]),
+    ("ts.createrule", &["write", "timeseries"]),
+    ("ts.deleterule", &["write", "timeseries"]),
+    ("ts.get", &["read", "timeseries"]),
+    ("ts.info", &["read", "timeseries"]),
+    ("ts.range", &["read", "timeseries"]),
+    ("ts.revrange", &["read", "timeseries"]),
+    ("unsubscribe", &["pubsub", "slow"]),
+    ("unwatch", &["fast", "transaction"]),
+    ("wait", &["slow", "connection"]),
+    ("waitaof", &["slow", "connection"]),
+    ("watch", &["fast", "transaction"]),
+];
+
+/// Categories listed by `ACL CAT`, `all` being every command
+const CATEGORIES: [&str; 25] = [
+    "keyspace",
+    "read",
+    "write",
+    "set",
+    "sortedset",
+    "list",
+    "hash",
+    "string",
+    "bitmap",
+    "hyperloglog",
+    "geo",
+    "stream",
+    "pubsub",
+    "admin",
+    "fast",
+    "slow",
+    "blocking",
+    "dangerous",
+    "connection",
+    "transaction",
+    "scripting",
+    "bloom",
+    "cms",
+    "timeseries",
+    "all",
+];
+
+/// Commands whose first argument names a subcommand, which rules can
+/// allow or block on its own as `command|subcommand`
+const CONTAINERS: [&str; 8] = ["acl", "client", "cluster", "config", "function", "pubsub", "script", "sentinel"];
+
+fn categories(command: &str) -> Option<&'static [&'static str]> {
+    COMMANDS
+        .iter()
+        .find(|(name, _)| *name == command)
+        .map(|(_, categories)| *categories)
+}
+
+/// Commands of `category`, every command for `all`
+fn commands_in(category: &str) -> impl Iterator<Item = &'static str> + '_ {
+    COMMANDS
+        .iter()
+        .filter(move |(_, categories)| category == "all" || categories.contains(&category))
+        .map(|(name, _)| *name)
+}
+
+/// Whether `command` is a known command that takes subcommands
+pub fn is_container(command: &str) -> bool {
+    CONTAINERS.contains(&command)
+}
+
+/// Checks `user` may run `command` with `args`, its subcommand and the
+/// channels it publishes or subscribes to included
+pub fn check_command(user: &User, command: &str, args: &[String]) -> Result<(), Error> {
+    let subcommand = args.first().filter(|_| is_container(command));
+
+    if !user.can_run(command, subcommand.map(String::as_str)) {
+        let command = match subcommand {
+            Some(subcommand) => format!("{command}|{}", subcommand.to_lowercase()),
+            None => command.to_string(),
+        };
+
+        return Err(Error::custom(format!(
+            "NOPERM User {} has no permissions to run the '{command}' command",
+            user.name
+        )));
+    }
+
+    let channels = match command {
+        "subscribe" | "psubscribe" | "ssubscribe" => args,
+        "publish" | "spublish" => &args[..args.len().min(1)],
+        _ => &[],
+    };
+
+    if !channels
+        .iter()
+        .all(|channel| user.can_access_channel(channel, command == "psubscribe"))
+    {
+        return Err(Error::custom("NOPERM No permissions to access a channel"));
+    }
+
+    Ok(())
+}
+
+/// Checks `user` may access every key of `command`
+pub fn check_keys(user: &User, command: &str, keys: &[String]) -> Result<(), Error> {
+    match keys.iter().all(|key| user.can_access_key(command, key)) {
+        true => Ok(()),
+        false => Err(Error::custom("NOPERM No permissions to access a key")),
+    }
+}
+
+/// Keys matching `pattern` the user may read and/or write
+#[derive(Debug, Clone, PartialEq)]
+struct KeyPattern {
+    pattern: String,
+    read: bool,
+    write: bool,
+}
+
+impl KeyPattern {
+    fn describe(&self) -> String {
+        match (self.read, self.write) {
+            (true, true) => format!("~{}", self.pattern),
+            (true, false) => format!("%R~{}", self.pattern),
+            _ => format!("%W~{}", self.pattern),
+        }
+    }
+}
+
+/// A user of the server and its permissions
+#[derive(Debug, Clone)]
+pub struct User {
+    pub name: String,
+    pub enabled: bool,
+    /// Any password authenticates the user
+    nopass: bool,
+    /// SHA256 of the passwords in lowercase hex
+    passwords: BTreeSet<String>,
+    /// Commands allowed with all their subcommands
+    commands: BTreeSet<String>,
+    /// Subcommands allowed on their own, as `command|subcommand`
+    subcommands: BTreeSet<String>,
+    /// Subcommands blocked even though their command is allowed
+    blocked: BTreeSet<String>,
+    /// Command rules as applied, simplified, reported by `ACL LIST`
+    rules: Vec<String>,
+    keys: Vec<KeyPattern>,
+    channels: Vec<String>,
+}
+
+impl User {
+    /// A new user: disabled, without passwords and allowed nothing
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            enabled: false,
+            nopass: false,
+            passwords: BTreeSet::new(),
+            commands: BTreeSet::new(),
+            subcommands: BTreeSet::new(),
+            blocked: BTreeSet::new(),
+            rules: vec!["-@all".to_string()],
+            keys: Vec::new(),
+            channels: Vec::new(),
+        }
+    }
+
+    /// Whether `password` authenticates the user, disabled or not
+    pub fn check_password(&self, password: &str) -> bool {
+        self.nopass || self.passwords.contains(&sha256_hex(&string_to_bytes(password)))
+    }
+
+    /// Whether the user may authenticate without a password
+    pub fn is_nopass(&self) -> bool {
+        self.nopass
+    }
+
+    /// Applies a single `ACL SETUSER` rule
+    fn apply(&mut self, rule: &str) -> Result<(), String> {
+        let lower = rule.to_lowercase();
+
+        match lower.as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allkeys" => self.apply("~*")?,
+            "resetkeys" => self.keys.clear(),
+            "allchannels" => self.apply("&*")?,
+            "resetchannels" => self.channels.clear(),
+            "allcommands" => self.apply("+@all")?,
+            "nocommands" => self.apply("-@all")?,
+            "reset" => *self = Self::new(&self.name),
+            _ => match rule.as_bytes().first() {
+                Some(b'>') => {
+                    self.nopass = false;
+                    self.passwords.insert(sha256_hex(&string_to_bytes(&rule[1..])));
+                }
+                Some(b'<') => {
+                    if !self.passwords.remove(&sha256_hex(&string_to_bytes(&rule[1..]))) {
+                        return Err("The password you are trying to remove from the user does not exist".to_string());
+                    }
+                }
+                Some(b'#') => {
+                    let hash = &rule[1..];
+
+                    if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)) {
+                        return Err(
+                            "The password hash must be exactly 64 characters and contain only lowercase hexadecimal characters"
+                                .to_string(),
+                        );
+                    }
+
+                    self.nopass = false;
+                    self.passwords.insert(hash.to_string());
+                }
+                Some(b'!') => {
+                    if !self.passwords.remove(&rule[1..]) {
+                        return Err("The password you are trying to remove from the user does not exist".to_string());
+                    }
+                }
+                Some(b'~') => self.add_keys(&rule[1..], true, true),
+                Some(b'%') => {
+                    let (flags, pattern) = rule[1..].split_once('~').ok_or("Syntax error")?;
+                    let flags = flags.to_uppercase();
+
+                    if flags.is_empty() || !flags.chars().all(|flag| flag == 'R' || flag == 'W') {
+                        return Err("Syntax error".to_string());
+                    }
+
+                    self.add_keys(pattern, flags.contains('R'), flags.contains('W'));
+                }
+                Some(b'&') => {
+                    let pattern = &rule[1..];
+
+                    if pattern == "*" {
+                        self.channels = vec!["*".to_string()];
+                    } else if !self.channels.iter().any(|channel| channel == "*" || channel == pattern) {
+                        self.channels.push(pattern.to_string());
+                    }
+                }
+                Some(sign @ (b'+' | b'-')) => self.apply_command(*sign == b'+', &lower[1..])?,
+                _ => return Err("Syntax error".to_string()),
+            },
+        }
+
+        Ok(())
+    }
+
+    fn add_keys(&mut self, pattern: &str, read: bool, write: bool) {
+        let all = KeyPattern {
+            pattern: "*".to_string(),
+            read: true,
+            write: true,
+        };
+
+        if self.keys.contains(&all) {
+            return;
+        }
+
+        if pattern == "*" && read && write {
+            self.keys = vec![all];
+            return;
+        }
+
+        match self.keys.iter_mut().find(|key| key.pattern == pattern) {
+            Some(key) => {
+                key.read |= read;
+                key.write |= write;
+            }
+            None => self.keys.push(KeyPattern {
+                pattern: pattern.to_string(),
+                read,
+                write,
+            }),
+        }
+    }
+
+    /// Applies `+command`, `-command`, `+@category` or `+command|subcommand`
+    fn apply_command(&mut self, allow: bool, name: &str) -> Result<(), String> {
+        const UNKNOWN: &str = "Unknown command or category name in ACL";
+        let sign = if allow { '+' } else { '-' };
+
+        if let Some(category) = name.strip_prefix('@') {
+            if !CATEGORIES.contains(&category) {
+                return Err(UNKNOWN.to_string());
+            }
+
+            for command in commands_in(category) {
+                self.set_command(allow, command);
+            }
+
+            if category == "all" {
+                self.rules.clear();
+            }
+        } else if let Some((command, subcommand)) = name.split_once('|') {
+            if !is_container(command) || subcommand.is_empty() || subcommand.contains('|') {
+                return Err(UNKNOWN.to_string());
+            }
+
+            let full = name.to_string();
+
+            match (allow, self.commands.contains(command)) {
+                (true, true) => {
+                    self.blocked.remove(&full);
+                }
+                (true, false) => {
+                    self.subcommands.insert(full);
+                }
+                (false, true) => {
+                    self.blocked.insert(full);
+                }
+                (false, false) => {
+                    self.subcommands.remove(&full);
+                }
+            }
+        } else if categories(name).is_some() {
+            self.set_command(allow, name);
+        } else {
+            return Err(UNKNOWN.to_string());
+        }
+
+        self.rules.push(format!("{sign}{name}"));
+
+        Ok(())
+    }
+
+    fn set_command(&mut self, allow: bool, command: &str) {
+        let prefix = format!("{command}|");
+
+        self.subcommands.retain(|subcommand| !subcommand.starts_with(&prefix));
+        self.blocked.retain(|subcommand| !subcommand.starts_with(&prefix));
+
+        if allow {
+            self.commands.insert(command.to_string());
+        } else {
+            self.commands.remove(command);
+        }
+    }
+
+    /// Whether the user may run `command`, with the subcommand when it is a container
+    ///
+    /// Unknown commands are left to fail when they run.
+    pub fn can_run(&self, command: &str, subcommand: Option<&str>) -> bool {
+        if categories(command).is_none() {
+            return true;
+        }
+
+        let full = subcommand.map(|subcommand| format!("{command}|{}", subcommand.to_lowercase()));
+
+        match full {
+            Some(full) if self.commands.contains(command) => !self.blocked.contains(&full),
+            Some(full) => self.subcommands.contains(&full),
+            None => self.commands.contains(command),
+        }
+    }
+
+    /// Whether the user may access `key` the way `command` does
+    ///
+    /// Commands neither reading nor writing, like scripts, need both.
+    pub fn can_access_key(&self, command: &str, key: &str) -> bool {
+        let categories = categories(command).unwrap_or_default();
+        let reads = categories.contains(&"read");
+        let writes = categories.contains(&"write");
+        let (read, write) = (reads || !writes, writes || !reads);
+
+        self.keys
+            .iter()
+            .any(|pattern| (pattern.read || !read) && (pattern.write || !write) && glob::matches(&pattern.pattern, key))
+    }
+
+    /// Whether the user may publish or subscribe to `channel`
+    ///
+    /// A pattern subscription must be allowed literally, the channels it
+    /// would match can't be known.
+    pub fn can_access_channel(&self, channel: &str, is_pattern: bool) -> bool {
+        self.channels.iter().any(|allowed| {
+            if is_pattern {
+                allowed == "*" || allowed == channel
+            } else {
+                glob::matches(allowed, channel)
+            }
+        })
+    }
+
+    fn flags(&self) -> Vec<&'static str> {
+        let mut flags = vec![if self.enabled { "on" } else { "off" }];
+
+        if self.nopass {
+            flags.push("nopass");
+        }
+
+        flags
+    }
+
+    fn describe_keys(&self) -> String {
+        self.keys.iter().map(KeyPattern::describe).collect::<Vec<_>>().join(" ")
+    }
+
+    fn describe_channels(&self) -> String {
+        self.channels
+            .iter()
+            .map(|channel| format!("&{channel}"))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Rules recreating the user, as listed by `ACL LIST`
+    pub fn describe(&self) -> String {
+        let mut parts = vec!["user".to_string(), self.name.clone()];
+
+        parts.extend(self.flags().into_iter().map(str::to_string));
+        parts.extend(self.passwords.iter().map(|hash| format!("#{hash}")));
+
+        if !self.keys.is_empty() {
+            parts.push(self.describe_keys());
+        }
+
+        parts.push(match self.channels.is_empty() {
+            true => "resetchannels".to_string(),
+            false => self.describe_channels(),
+        });
+        parts.push(self.rules.join(" "));
+
+        parts.join(" ")
+    }
+
+    /// Reply of `ACL GETUSER`
+    pub fn to_resp(&self) -> RespType {
+        RespType::Map(vec![
+            (
+                RespType::bulk_string("flags"),
+                RespType::array(self.flags().into_iter().map(RespType::bulk_string).collect()),
+            ),
+            (
+                RespType::bulk_string("passwords"),
+                RespType::array(self.passwords.iter().map(RespType::bulk_string).collect()),
+            ),
+            (RespType::bulk_string("commands"), RespType::bulk_string(self.rules.join(" "))),
+            (RespType::bulk_string("keys"), RespType::bulk_string(self.describe_keys())),
+            (RespType::bulk_string("channels"), RespType::bulk_string(self.describe_channels())),
+            (RespType::bulk_string("selectors"), RespType::array(vec![])),
+        ])
+    }
+}
+
+/// Users by name
+#[derive(Debug, Clone)]
+pub struct Acl {
+    users: BTreeMap<String, User>,
+}
+
+impl Default for Acl {
+    /// Only the `default` user, allowed everything without a password
+    fn default() -> Self {
+        let mut user = User::new(DEFAULT_USER);
+
+        for rule in ["on", "nopass", "~*", "&*", "+@all"] {
+            user.apply(rule).expect("valid default user rule");
+        }
+
+        Self {
+            users: BTreeMap::from([(DEFAULT_USER.to_string(), user)]),
+        }
+    }
+}
+
+impl Acl {
+    pub fn get(&self, name: &str) -> Option<&User> {
+        self.users.get(name)
+    }
+
+    pub fn users(&self) -> impl Iterator<Item = &User> {
+        self.users.values()
+    }
+
+    /// Applies `rules` to the user `name`, creating it when missing
+    ///
+    /// Nothing changes when a rule is invalid.
+    pub fn set_user(&mut self, name: &str, rules: &[String]) -> Result<(), Error> {
+        let mut user = self.users.get(name).cloned().unwrap_or_else(|| User::new(name));
+
+        for rule in rules {
+            user.apply(rule).map_err(|reason| {
+                Error::custom(format!("ERR Error in ACL SETUSER modifier '{rule}': {reason}"))
+            })?;
+        }
+
+        self.users.insert(name.to_string(), user);
+
+        Ok(())
+    }
+
+    /// Deletes the users in `names`, returning how many existed
+    pub fn delete(&mut self, names: &[String]) -> Result<usize, Error> {
+        if names.iter().any(|name| name == DEFAULT_USER) {
+            return Err(Error::custom("ERR The 'default' user cannot be removed"));
+        }
+
+        Ok(names.iter().filter(|name| self.users.remove(name.as_str()).is_some()).count())
+    }
+
+    /// Whether `password` authenticates the enabled user `name`
+    pub fn authenticate(&self, name: &str, password: &str) -> bool {
+        self.users
+            .get(name)
+            .is_some_and(|user| user.enabled && user.check_password(password))
+    }
+
+    /// Reply of `ACL CAT [category]`
+    pub fn cat(category: Option<&str>) -> Result<RespType, Error> {
+        match category.map(str::to_lowercase) {
+            None => Ok(RespType::array(
+                CATEGORIES
+                    .iter()
+                    .filter(|category| **category != "all")
+                    .map(|category| RespType::bulk_string(*category))
+                    .collect(),
+            )),
+            Some(category) if CATEGORIES.contains(&category.as_str()) && category != "all" => Ok(RespType::array(
+                commands_in(&category).map(RespType::bulk_string).collect(),
+            )),
+            Some(category) => Err(Error::custom(format!("ERR Unknown category '{category}'"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(rules: &[&str]) -> Vec<String> {
+        rules.iter().map(|rule| rule.to_string()).collect()
+    }
+
+    #[test]
+    fn default_user_is_allowed_everything() {
+        let acl = Acl::default();
+        let user = acl.get(DEFAULT_USER).unwrap();
+
+        assert!(acl.authenticate(DEFAULT_USER, "anything"));
+        assert!(user.can_run("flushall", None));
+        assert!(user.can_run("config", Some("set")));
+        assert!(user.can_access_key("set", "key"));
+        assert!(user.can_access_channel("news", false));
+        assert_eq!(user.describe(), "user default on nopass ~* &* +@all");
+    }
+
+    #[test]
+    fn passwords_are_hashed() {
+        let mut acl = Acl::default();
+
+        acl.set_user("alice", &rules(&["on", ">secret"])).unwrap();
+
+        assert!(acl.authenticate("alice", "secret"));
+        assert!(!acl.authenticate("alice", "wrong"));
+        assert!(!acl.authenticate("bob", "secret"));
+
+        let hash = sha256_hex(b"secret");
+        assert!(acl.get("alice").unwrap().describe().contains(&format!("#{hash}")));
+
+        acl.set_user("alice", &rules(&["off"])).unwrap();
+        assert!(!acl.authenticate("alice", "secret"));
+
+        acl.set_user("alice", &rules(&["on", &format!("!{hash}")])).unwrap();
+        assert!(!acl.authenticate("alice", "secret"));
+
+        assert_eq!(
+            acl.set_user("alice", &rules(&["<secret"])),
+            Err(Error::custom(
+                "ERR Error in ACL SETUSER modifier '<secret': The password you are trying to remove from the user does not exist"
+            ))
+        );
+        assert!(acl.set_user("alice", &rules(&["#abc"])).is_err());
+    }
+
+    #[test]
+    fn command_rules() {
+        let mut acl = Acl::default();
+
+        acl.set_user("alice", &rules(&["+@read", "-dump", "+config|get", "+set"])).unwrap();
+
+        let user = acl.get("alice").unwrap();
+        assert!(user.can_run("get", None));
+        assert!(user.can_run("set", None));
+        assert!(!user.can_run("dump", None));
+        assert!(!user.can_run("flushall", None));
+        assert!(user.can_run("config", Some("GET")));
+        assert!(!user.can_run("config", Some("set")));
+        assert!(user.can_run("nosuchcommand", None));
+        assert_eq!(user.describe(), "user alice off resetchannels -@all +@read -dump +config|get +set");
+
+        acl.set_user("bob", &rules(&["+@all", "-config|set", "-@dangerous"])).unwrap();
+
+        let user = acl.get("bob").unwrap();
+        assert!(user.can_run("get", None));
+        assert!(!user.can_run("flushall", None));
+        assert!(!user.can_run("config", Some("get")));
+
+        assert_eq!(
+            acl.set_user("alice", &rules(&["+nosuchcommand"])),
+            Err(Error::custom(
+                "ERR Error in ACL SETUSER modifier '+nosuchcommand': Unknown command or category name in ACL"
+            ))
+        );
+        assert!(acl.set_user("alice", &rules(&["+@nosuchcategory"])).is_err());
+        assert!(acl.set_user("alice", &rules(&["+get|key"])).is_err());
+        assert!(acl.set_user("alice", &rules(&["bogus"])).is_err());
+    }
+
+    #[test]
+    fn key_and_channel_patterns() {
+        let mut acl = Acl::default();
+
+        acl.set_user("alice", &rules(&["~app:*", "%R~shared:*", "%W~log:*", "&news.*"])).unwrap();
+
+        let user = acl.get("alice").unwrap();
+        assert!(user.can_access_key("set", "app:1"));
+        assert!(user.can_access_key("get", "shared:1"));
+        assert!(!user.can_access_key("set", "shared:1"));
+        assert!(user.can_access_key("set", "log:1"));
+        assert!(!user.can_access_key("get", "log:1"));
+        assert!(!user.can_access_key("eval", "shared:1"));
+        assert!(!user.can_access_key("get", "other"));
+
+        assert!(user.can_access_channel("news.tech", false));
+        assert!(!user.can_access_channel("sport", false));
+        assert!(user.can_access_channel("news.*", true));
+        assert!(!user.can_access_channel("news.t*", true));
+
+        acl.set_user("alice", &rules(&["resetkeys", "allchannels"])).unwrap();
+
+        let user = acl.get("alice").unwrap();
+        assert!(!user.can_access_key("get", "app:1"));
+        assert!(user.can_access_channel("sport", false));
+    }
+
+    #[test]
+    fn invalid_rules_change_nothing() {
+        let mut acl = Acl::default();
+
+        assert!(acl.set_user("alice", &rules(&["on", "+nosuchcommand"])).is_err());
+        assert!(acl.get("alice").is_none());
+
+        assert_eq!(
+            acl.delete(&rules(&["default"])),
+            Err(Error::custom("ERR The 'default' user cannot be removed"))
+        );
+
+        acl.set_user("alice", &[]).unwrap();
+        assert_eq!(acl.delete(&rules(&["alice", "bob"])), Ok(1));
+    }
+
+    #[test]
+    fn lists_categories() {
+        assert!(Acl::cat(None).is_ok());
+        assert_eq!(Acl::cat(Some("nosuch")), Err(Error::custom("ERR Unknown category 'nosuch'")));
+
+        let RespType::Array { values: commands, .. } = Acl::cat(Some("read")).unwrap() else {
+            panic!("expected an array");
+        };
+        assert!(commands.contains(&RespType::bulk_string("get")));
+        assert!(!commands.contains(&RespType::bulk_string("set")));
+    }
+}
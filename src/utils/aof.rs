@@ -0,0 +1,767 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+
+use crate::commands::Command;
+use crate::models::{StoreValue, Value};
+use crate::resp::types::{bytes_to_string, string_to_bytes, RespType};
+
+use super::config::{AppendFsync, Config};
+use super::context::Context;
+use super::rdb;
+
+/// Serializes a command the way clients send it
+pub fn encode(command: &[String]) -> String {
+    RespType::array(command.iter().map(RespType::bulk_string).collect()).to_string()
+}
+
+/// Bytes of the commands executed by one request, wrapped in `MULTI`/`EXEC`
+/// when there are several of them so they are replayed atomically
+pub fn encode_all(commands: &[Vec<String>]) -> Vec<u8> {
+    let mut buffer = String::new();
+    let atomic = commands.len() > 1;
+
+    if atomic {
+        buffer.push_str(&encode(&["MULTI".to_string()]));
+    }
+
+    for command in commands {
+        buffer.push_str(&encode(command));
+    }
+
+    if atomic {
+        buffer.push_str(&encode(&["EXEC".to_string()]));
+    }
+
+    string_to_bytes(&buffer)
+}
+
+/// Commands recreating the function libraries of `functions` and the keys
+/// of `entries`, the content of a rewritten base file
+///
+/// Strings are written with `SET`, every other type through `RESTORE` of its
+/// `DUMP` payload so module types come back exactly as they were, compaction
+/// rules and bucket state included.
+pub fn rewrite_commands(entries: &[(String, StoreValue)], functions: &[String]) -> Vec<Vec<String>> {
+    let mut commands: Vec<Vec<String>> = functions
+        .iter()
+        .map(|code| vec!["FUNCTION".to_string(), "LOAD".to_string(), code.clone()])
+        .collect();
+
+    for (key, value) in entries {
+        let command: Vec<String> = match &value.data {
+            Value::String(data) => vec!["SET".to_string(), key.to_string(), data.to_string()],
+            _ => vec![
+                "RESTORE".to_string(),
+                key.to_string(),
+                "0".to_string(),
+                bytes_to_string(&rdb::dump(&value.data)),
+            ],
+        };
+
+        commands.push(command);
+
+        if let Some(at) = value.expires_at() {
+            let millis = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+            commands.push(vec!["PEXPIREAT".to_string(), key.to_string(), millis.to_string()]);
+        }
+    }
+
+    commands
+}
+
+/// Role of a file listed in the manifest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    /// Snapshot of the dataset written by the last rewrite
+    Base,
+    /// Commands executed since the base was written
+    Incr,
+}
+
+impl FileKind {
+    fn flag(self) -> char {
+        match self {
+            FileKind::Base => 'b',
+            FileKind::Incr => 'i',
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+    pub(crate) name: String,
+    pub(crate) seq: u64,
+    pub(crate) kind: FileKind,
+}
+
+/// Files making up the append only file, tracked in `<appendfilename>.manifest`
+///
+/// Each line reads `file <name> seq <seq> type <b|i>`. The base, if any, is
+/// replayed first, followed by the incremental files in order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Manifest {
+    pub(crate) base: Option<ManifestEntry>,
+    pub(crate) incrs: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut manifest = Manifest::default();
+
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let field = |name: &str| {
+                parts
+                    .chunks(2)
+                    .find(|pair| pair.len() == 2 && pair[0] == name)
+                    .map(|pair| pair[1])
+                    .ok_or_else(|| anyhow!("Invalid AOF manifest line, missing {name}: {line}"))
+            };
+
+            let name = field("file")?.to_string();
+            let seq = field("seq")?
+                .parse::<u64>()
+                .map_err(|_| anyhow!("Invalid AOF manifest line, bad seq: {line}"))?;
+
+            match field("type")? {
+                "b" if manifest.base.is_none() => {
+                    manifest.base = Some(ManifestEntry { name, seq, kind: FileKind::Base });
+                }
+                "b" => return Err(anyhow!("Found duplicate base file information in the AOF manifest")),
+                "i" => manifest.incrs.push(ManifestEntry { name, seq, kind: FileKind::Incr }),
+                // History files are left over by a previous rewrite, they are not replayed
+                "h" => {}
+                kind => return Err(anyhow!("Unknown AOF file type '{kind}' in the manifest")),
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    pub fn encode(&self) -> String {
+        self.files()
+            .map(|entry| format!("file {} seq {} type {}\n", entry.name, entry.seq, entry.kind.flag()))
+            .collect()
+    }
+
+    /// Files in replay order
+    pub fn files(&self) -> impl Iterator<Item = &ManifestEntry> {
+        self.base.iter().chain(self.incrs.iter())
+    }
+
+    fn next_base(&self, filename: &str) -> ManifestEntry {
+        let seq = self.base.as_ref().map_or(1, |base| base.seq + 1);
+
+        ManifestEntry {
+            name: format!("{filename}.{seq}.base.aof"),
+            seq,
+            kind: FileKind::Base,
+        }
+    }
+
+    fn next_incr(&self, filename: &str) -> ManifestEntry {
+        let seq = self.incrs.last().map_or(1, |incr| incr.seq + 1);
+
+        ManifestEntry {
+            name: format!("{filename}.{seq}.incr.aof"),
+            seq,
+            kind: FileKind::Incr,
+        }
+    }
+}
+
+/// Rewrite running in a background thread
+#[derive(Debug)]
+struct Rewrite {
+    handle: JoinHandle<io::Result<u64>>,
+    started: Instant,
+    /// File the thread writes the new base into
+    temp: PathBuf,
+    /// Files replaced by the new base once it is complete
+    replaced: Manifest,
+}
+
+/// Append only log of the write commands executed by the server
+///
+/// The log is split into a base file and incremental files listed in a
+/// manifest, all inside `appenddirname`. A rewrite snapshots the dataset,
+/// switches writes to a fresh incremental file and writes the new base in
+/// a background thread, the older files are deleted once it is complete.
+#[derive(Debug)]
+pub struct Aof {
+    dir: PathBuf,
+    filename: String,
+    manifest: Manifest,
+    /// Incremental file new commands are appended to
+    path: PathBuf,
+    file: File,
+    fsync: AppendFsync,
+    /// Whether something was written since the last fsync
+    pending_fsync: bool,
+    last_fsync: Instant,
+    rewrite: Option<Rewrite>,
+    /// Size of every file of the manifest
+    current_size: u64,
+    /// Size after the last rewrite (or at startup), base of the automatic
+    /// rewrite growth
+    base_size: u64,
+    pub(crate) rewrites: u64,
+    pub(crate) last_rewrite_ok: bool,
+    pub(crate) last_rewrite_time: Option<Duration>,
+    pub(crate) last_write_ok: bool,
+}
+
+impl Aof {
+    /// Opens the append only file described by `config`
+    ///
+    /// A single file at `<dir>/<appendfilename>`, from before the manifest
+    /// existed, becomes the base file.
+    pub fn open(config: &Config) -> io::Result<Self> {
+        let dir = Path::new(&config.dir).join(&config.appenddirname);
+        let filename = config.appendfilename.to_string();
+        let manifest_path = dir.join(format!("{filename}.manifest"));
+
+        fs::create_dir_all(&dir)?;
+
+        let mut manifest = match fs::read_to_string(&manifest_path) {
+            Ok(text) => Manifest::parse(&text).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Manifest::default(),
+            Err(err) => return Err(err),
+        };
+
+        let legacy = Path::new(&config.dir).join(&filename);
+
+        if manifest.files().next().is_none() && legacy.is_file() {
+            let base = manifest.next_base(&filename);
+
+            fs::rename(&legacy, dir.join(&base.name))?;
+            manifest.base = Some(base);
+        }
+
+        if manifest.incrs.is_empty() {
+            manifest.incrs.push(manifest.next_incr(&filename));
+        }
+
+        persist_manifest(&dir, &filename, &manifest)?;
+
+        let path = dir.join(&manifest.incrs.last().expect("manifest has an incr file").name);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+
+        let mut aof = Self {
+            dir,
+            filename,
+            manifest,
+            path,
+            file,
+            fsync: config.appendfsync,
+            pending_fsync: false,
+            last_fsync: Instant::now(),
+            rewrite: None,
+            current_size: 0,
+            base_size: 0,
+            rewrites: 0,
+            last_rewrite_ok: true,
+            last_rewrite_time: None,
+            last_write_ok: true,
+        };
+
+        aof.current_size = aof.files_size();
+        aof.base_size = aof.current_size;
+
+        Ok(aof)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn set_fsync(&mut self, fsync: AppendFsync) {
+        self.fsync = fsync;
+    }
+
+    pub fn current_size(&self) -> u64 {
+        self.current_size
+    }
+
+    pub fn base_size(&self) -> u64 {
+        self.base_size
+    }
+
+    pub fn is_rewriting(&self) -> bool {
+        self.rewrite.is_some()
+    }
+
+    /// How long the running rewrite has been going on
+    pub fn current_rewrite_time(&self) -> Option<Duration> {
+        self.rewrite.as_ref().map(|rewrite| rewrite.started.elapsed())
+    }
+
+    /// Replays every file of the manifest into `context`
+    ///
+    /// Only the last file may be truncated, see [`load`].
+    pub fn load(&mut self, context: &mut Context, truncated: bool) -> Result<usize> {
+        let files: Vec<PathBuf> = self.manifest.files().map(|entry| self.dir.join(&entry.name)).collect();
+        let mut applied = 0;
+
+        for (i, path) in files.iter().enumerate() {
+            applied += load(path, context, truncated && i == files.len() - 1)?;
+        }
+
+        self.current_size = self.files_size();
+        self.base_size = self.current_size;
+
+        Ok(applied)
+    }
+
+    /// Appends `commands`, wrapped in `MULTI`/`EXEC` when there are several of them
+    pub fn append(&mut self, commands: &[Vec<String>]) -> io::Result<()> {
+        let bytes = encode_all(commands);
+        let result = self.file.write_all(&bytes);
+        self.last_write_ok = result.is_ok();
+        result?;
+
+        self.current_size += bytes.len() as u64;
+        self.pending_fsync = true;
+
+        if self.fsync == AppendFsync::Always {
+            self.sync()?;
+        }
+
+        Ok(())
+    }
+
+    /// Flushes the file to disk once per second under `appendfsync everysec`
+    pub fn sync_if_due(&mut self) -> io::Result<()> {
+        if self.fsync == AppendFsync::Everysec && self.last_fsync.elapsed() >= Duration::from_secs(1) {
+            self.sync()?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether everything appended is on disk, as far as the fsync policy
+    /// goes: with `appendfsync no` that is left to the operating system
+    pub fn is_synced(&self) -> bool {
+        !self.pending_fsync || self.fsync == AppendFsync::No
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        if self.pending_fsync {
+            self.file.sync_data()?;
+            self.pending_fsync = false;
+        }
+
+        self.last_fsync = Instant::now();
+
+        Ok(())
+    }
+
+    /// Whether the files grew enough since the last rewrite to rewrite them again
+    pub fn needs_rewrite(&self, percentage: u64, min_size: u64) -> bool {
+        if self.is_rewriting() || percentage == 0 || self.current_size < min_size {
+            return false;
+        }
+
+        let base = self.base_size.max(1);
+
+        self.current_size.saturating_sub(base) * 100 / base >= percentage
+    }
+
+    /// Starts writing the new base file in a background thread
+    ///
+    /// `functions` and `entries` are a copy of the dataset, they are turned
+    /// into commands by the thread so the context is not held meanwhile.
+    /// Commands appended from now on go to a new incremental file, which
+    /// follows the new base in the manifest once the rewrite completes.
+    pub fn start_rewrite(&mut self, functions: Vec<String>, entries: Vec<(String, StoreValue)>) -> io::Result<()> {
+        if self.is_rewriting() {
+            return Err(io::Error::other("a rewrite is already in progress"));
+        }
+
+        self.pending_fsync = true;
+        self.sync()?;
+
+        let replaced = self.manifest.clone();
+        let incr = self.manifest.next_incr(&self.filename);
+        let path = self.dir.join(&incr.name);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+
+        self.manifest.incrs.push(incr);
+
+        if let Err(err) = persist_manifest(&self.dir, &self.filename, &self.manifest) {
+            self.manifest = replaced;
+            let _ = fs::remove_file(&path);
+
+            return Err(err);
+        }
+
+        self.path = path;
+        self.file = file;
+
+        let temp = self.dir.join(format!("temp-rewriteaof-bg-{}.aof", std::process::id()));
+        let target = temp.clone();
+        let handle = thread::spawn(move || write_base(&target, &rewrite_commands(&entries, &functions)));
+
+        self.rewrite = Some(Rewrite {
+            handle,
+            started: Instant::now(),
+            temp,
+            replaced,
+        });
+
+        Ok(())
+    }
+
+    /// Completes the background rewrite once its thread is done
+    ///
+    /// Returns `None` while there is no finished rewrite, otherwise whether
+    /// the new base replaced the previous files.
+    pub fn poll_rewrite(&mut self) -> Option<io::Result<()>> {
+        if !self.rewrite.as_ref().is_some_and(|rewrite| rewrite.handle.is_finished()) {
+            return None;
+        }
+
+        let rewrite = self.rewrite.take().expect("rewrite is running");
+        let result = match rewrite.handle.join() {
+            Ok(result) => result,
+            Err(_) => Err(io::Error::other("rewrite thread panicked")),
+        };
+        let result = result.and_then(|size| self.finish_rewrite(&rewrite.temp, &rewrite.replaced, size));
+
+        if result.is_err() {
+            let _ = fs::remove_file(&rewrite.temp);
+        } else {
+            self.rewrites += 1;
+        }
+
+        self.last_rewrite_ok = result.is_ok();
+        self.last_rewrite_time = Some(rewrite.started.elapsed());
+
+        Some(result)
+    }
+
+    fn finish_rewrite(&mut self, temp: &Path, replaced: &Manifest, size: u64) -> io::Result<()> {
+        let base = self.manifest.next_base(&self.filename);
+        let mut manifest = self.manifest.clone();
+
+        fs::rename(temp, self.dir.join(&base.name))?;
+
+        manifest.base = Some(base);
+        manifest.incrs.retain(|incr| !replaced.incrs.contains(incr));
+        persist_manifest(&self.dir, &self.filename, &manifest)?;
+        self.manifest = manifest;
+
+        for entry in replaced.files() {
+            let _ = fs::remove_file(self.dir.join(&entry.name));
+        }
+
+        let incrs: u64 = self
+            .manifest
+            .incrs
+            .iter()
+            .filter_map(|incr| fs::metadata(self.dir.join(&incr.name)).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+
+        self.current_size = size + incrs;
+        self.base_size = self.current_size;
+
+        Ok(())
+    }
+
+    fn files_size(&self) -> u64 {
+        self.manifest
+            .files()
+            .filter_map(|entry| fs::metadata(self.dir.join(&entry.name)).ok())
+            .map(|metadata| metadata.len())
+            .sum()
+    }
+}
+
+/// Writes the manifest to a temporary file first, so a crash never leaves a
+/// partial one behind
+fn persist_manifest(dir: &Path, filename: &str, manifest: &Manifest) -> io::Result<()> {
+    let path = dir.join(format!("{filename}.manifest"));
+    let temp = dir.join(format!("temp-{filename}.manifest"));
+
+    let mut file = File::create(&temp)?;
+    file.write_all(manifest.encode().as_bytes())?;
+    file.sync_all()?;
+
+    fs::rename(&temp, &path)
+}
+
+/// Body of the rewrite thread, returns the size of the written file
+fn write_base(path: &Path, commands: &[Vec<String>]) -> io::Result<u64> {
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    for command in commands {
+        writer.write_all(&string_to_bytes(&encode(command)))?;
+    }
+
+    let file = writer.into_inner().map_err(|err| err.into_error())?;
+    file.sync_all()?;
+
+    Ok(file.metadata()?.len())
+}
+
+/// Replays the append only file at `path` into `context`
+///
+/// Commands go through [`Command::create_command`] like client requests.
+/// Transactions are only applied once their `EXEC` is read. A file ending
+/// in the middle of a command (or transaction) is truncated to the last
+/// complete one when `truncated` is allowed, otherwise loading fails.
+/// Returns the number of commands applied.
+pub fn load(path: impl AsRef<Path>, context: &mut Context, truncated: bool) -> Result<usize> {
+    let path = path.as_ref();
+
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut offset = 0;
+    let mut applied = 0;
+    // Commands of an open transaction and the offset of its MULTI
+    let mut transaction: Option<(Vec<Command>, usize)> = None;
+
+    while offset < data.len() {
+        let (frame, used) = match RespType::parse(&data[offset..]) {
+            Ok(Some(parsed)) => parsed,
+            Ok(None) => break,
+            Err(err) => return Err(anyhow!("Bad file format reading the append only file: {err}")),
+        };
+
+        let command = Command::try_from(frame)
+            .map_err(|err| anyhow!("Bad file format reading the append only file: {err}"))?;
+
+        match (command.name().to_lowercase().as_str(), transaction.as_mut()) {
+            ("multi", None) => transaction = Some((Vec::new(), offset)),
+            ("exec", Some(_)) => {
+                let (commands, _) = transaction.take().expect("transaction is open");
+
+                for command in commands {
+                    apply(&command, context)?;
+                    applied += 1;
+                }
+            }
+            (_, Some((commands, _))) => commands.push(command),
+            (_, None) => {
+                apply(&command, context)?;
+                applied += 1;
+            }
+        }
+
+        offset += used;
+    }
+
+    let valid = match &transaction {
+        Some((_, start)) => *start,
+        None => offset,
+    };
+
+    if valid < data.len() {
+        if !truncated {
+            return Err(anyhow!(
+                "Unexpected end of file reading the append only file {}, set aof-load-truncated to yes to load it anyway",
+                path.display()
+            ));
+        }
+
+        eprintln!(
+            "AOF {} was truncated, discarding the last {} bytes",
+            path.display(),
+            data.len() - valid
+        );
+        OpenOptions::new().write(true).open(path)?.set_len(valid as u64)?;
+    }
+
+    // Loading is not a modification clients need to hear about
+    context.store.take_modified();
+
+    Ok(applied)
+}
+
+fn apply(command: &Command, context: &mut Context) -> Result<()> {
+    let mut executable = command
+        .create_command()
+        .map_err(|err| anyhow!("Unknown command '{}' reading the append only file: {err}", command.name()))?;
+
+    if let RespType::SimpleError(err) = executable.execute(context) {
+        return Err(anyhow!("Error replaying '{}' from the append only file: {err}", command.name()));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Aggregation, CompactionRule, DuplicatePolicy, TimeSeries};
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("aof-test-{}-{name}", std::process::id()))
+    }
+
+    fn command(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn temp_config(name: &str) -> Config {
+        let dir = temp_path(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        Config {
+            dir: dir.to_string_lossy().to_string(),
+            appendfsync: AppendFsync::Always,
+            ..Config::default()
+        }
+    }
+
+    fn wait_for_rewrite(aof: &mut Aof) -> io::Result<()> {
+        loop {
+            match aof.poll_rewrite() {
+                Some(result) => return result,
+                None => thread::sleep(Duration::from_millis(5)),
+            }
+        }
+    }
+
+    #[test]
+    fn append_and_load() {
+        let config = temp_config("append");
+
+        let mut aof = Aof::open(&config).unwrap();
+        aof.append(&[command(&["SET", "a", "1"])]).unwrap();
+        aof.append(&[command(&["SET", "b", "2"]), command(&["SET", "c", "3"])]).unwrap();
+        drop(aof);
+
+        let mut context = Context::default();
+        let mut aof = Aof::open(&config).unwrap();
+        assert_eq!(aof.load(&mut context, false).unwrap(), 3);
+        assert_eq!(context.store.get("c"), Some(&StoreValue::from("3")));
+        assert_eq!(aof.manifest.encode(), "file appendonly.aof.1.incr.aof seq 1 type i\n");
+
+        fs::remove_dir_all(&config.dir).unwrap();
+    }
+
+    #[test]
+    fn manifest_round_trip() {
+        let text = "file appendonly.aof.2.base.aof seq 2 type b\nfile appendonly.aof.3.incr.aof seq 3 type i\n";
+        let manifest = Manifest::parse(text).unwrap();
+
+        assert_eq!(manifest.base.as_ref().unwrap().seq, 2);
+        assert_eq!(manifest.encode(), text);
+        assert!(Manifest::parse("file a seq 1 type x").is_err());
+        assert!(Manifest::parse("file a type b").is_err());
+    }
+
+    #[test]
+    fn legacy_file_becomes_base() {
+        let config = temp_config("legacy");
+        fs::write(Path::new(&config.dir).join("appendonly.aof"), encode(&command(&["SET", "a", "1"]))).unwrap();
+
+        let mut context = Context::default();
+        let mut aof = Aof::open(&config).unwrap();
+        assert_eq!(aof.load(&mut context, false).unwrap(), 1);
+        assert_eq!(aof.manifest.base.as_ref().unwrap().name, "appendonly.aof.1.base.aof");
+        assert!(!Path::new(&config.dir).join("appendonly.aof").exists());
+
+        fs::remove_dir_all(&config.dir).unwrap();
+    }
+
+    #[test]
+    fn rewrite_replaces_previous_files() {
+        let config = temp_config("rewrite");
+        let mut context = Context::default();
+
+        let mut aof = Aof::open(&config).unwrap();
+        aof.append(&[command(&["SET", "a", "1"])]).unwrap();
+        aof.append(&[command(&["SET", "a", "2"])]).unwrap();
+
+        let expiring = StoreValue::new("3", Some(Duration::from_secs(60)));
+        let mut filter = crate::models::BloomFilter::new(100, 0.01, 2).unwrap();
+        filter.insert(b"item").unwrap();
+        let mut series = TimeSeries::new(0, 4096, DuplicatePolicy::Block);
+        series.rules.push(CompactionRule::new("dest".to_string(), Aggregation::Avg, 10));
+        series.add(5, 1.0, None).unwrap();
+
+        context.store.insert("a".to_string(), StoreValue::from("2"));
+        context.store.insert("b".to_string(), expiring);
+        context.store.insert("f".to_string(), Value::BloomFilter(filter).into());
+        context.store.insert("t".to_string(), Value::TimeSeries(series).into());
+        context.store.insert("h".to_string(), Value::Hash([("k".to_string(), "v".to_string())].into()).into());
+
+        aof.start_rewrite(Vec::new(), context.store.snapshot()).unwrap();
+        assert!(aof.start_rewrite(Vec::new(), Vec::new()).is_err());
+        aof.append(&[command(&["SET", "c", "4"])]).unwrap();
+        wait_for_rewrite(&mut aof).unwrap();
+
+        assert_eq!(
+            aof.manifest.encode(),
+            "file appendonly.aof.1.base.aof seq 1 type b\nfile appendonly.aof.2.incr.aof seq 2 type i\n"
+        );
+        assert!(!aof.dir.join("appendonly.aof.1.incr.aof").exists());
+        assert_eq!(aof.rewrites, 1);
+        drop(aof);
+
+        let mut loaded = Context::default();
+        Aof::open(&config).unwrap().load(&mut loaded, false).unwrap();
+
+        assert_eq!(loaded.store.get("a"), Some(&StoreValue::from("2")));
+        assert_eq!(loaded.store.get("c"), Some(&StoreValue::from("4")));
+        assert!(loaded.store.get("b").unwrap().expires_at().is_some());
+        assert_eq!(loaded.store.get("f").map(|value| &value.data), context.store.get("f").map(|value| &value.data));
+        assert_eq!(loaded.store.get("t").map(|value| &value.data), context.store.get("t").map(|value| &value.data));
+        assert_eq!(loaded.store.get("h").map(|value| &value.data), context.store.get("h").map(|value| &value.data));
+
+        fs::remove_dir_all(&config.dir).unwrap();
+    }
+
+    #[test]
+    fn growth_triggers_rewrite() {
+        let config = temp_config("growth");
+        let mut aof = Aof::open(&config).unwrap();
+
+        assert!(!aof.needs_rewrite(100, 0));
+        aof.append(&[command(&["SET", "a", "1"])]).unwrap();
+        assert!(aof.needs_rewrite(100, 0));
+        assert!(!aof.needs_rewrite(100, 1024));
+        assert!(!aof.needs_rewrite(0, 0));
+
+        fs::remove_dir_all(&config.dir).unwrap();
+    }
+
+    #[test]
+    fn truncated_tail() {
+        let path = temp_path("truncated");
+        let complete = encode(&command(&["SET", "a", "1"]));
+        let partial = encode(&command(&["MULTI"])) + &encode(&command(&["SET", "b", "2"])) + "*3\r\n$3\r\nSET";
+
+        std::fs::write(&path, complete.clone() + &partial).unwrap();
+
+        let mut context = Context::default();
+        assert!(load(&path, &mut context, false).is_err());
+
+        let mut context = Context::default();
+        assert_eq!(load(&path, &mut context, true).unwrap(), 1);
+        assert!(!context.store.contains_key("b"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), complete);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn missing_file_is_empty() {
+        let mut context = Context::default();
+
+        assert_eq!(load(temp_path("missing"), &mut context, false).unwrap(), 0);
+    }
+}
@@ -0,0 +1,1763 @@
+//! Cluster mode, the keyspace split in hash slots served by several nodes
+//!
+//! Every key belongs to one of 16384 slots, the CRC16 of the key modulo the
+//! number of slots, or of its `{hashtag}` when it has one so related keys can
+//! be kept together. Each slot is served by one master, a node receiving a
+//! request for a key it doesn't serve redirects the client with `MOVED`.
+//!
+//! While a slot moves to another master its keys are migrated one by one,
+//! the master still serving it redirects requests for the keys it no longer
+//! has with `ASK`, which the importing master only serves after `ASKING`.
+//!
+//! The nodes talk to each other on the cluster bus: they ping each other,
+//! gossip about the nodes they know and flag the ones not answering. A master
+//! a majority of masters agree failed is replaced by one of its replicas,
+//! elected by the masters, at a new epoch. The most recent epoch wins when
+//! several nodes claim the same slots.
+//!
+//! The nodes of the cluster and the slots they serve are saved to the
+//! `cluster-config-file`, in the format of `CLUSTER NODES`.
+
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::net::IpAddr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::resp::errors::Error;
+
+use super::cluster_bus::{Body, Gossip, Header, Message};
+use super::hash::{key_slot, SLOT_COUNT};
+use super::replication::generate_replid;
+use super::store::Store;
+
+/// Offset of the cluster bus port from the client port
+pub const BUS_PORT_OFFSET: u16 = 10000;
+
+pub const DEFAULT_NODE_TIMEOUT: Duration = Duration::from_millis(15000);
+/// Longest time between two pings of a node
+const PING_PERIOD: Duration = Duration::from_secs(1);
+/// Time before connecting again to a node whose link broke
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+/// Time a forgotten node isn't added back from gossip
+const FORGET_TIME: Duration = Duration::from_secs(60);
+/// Time a manual failover waits for the replica to catch up
+const MANUAL_FAILOVER_TIMEOUT: Duration = Duration::from_secs(5);
+/// Failure reports count for this many node timeouts
+const FAIL_REPORT_VALIDITY_MULT: u32 = 2;
+/// A failed master nobody replaced is working again after this many node
+/// timeouts
+const FAIL_UNDO_TIME_MULT: u32 = 2;
+
+/// Slot number given by a client
+pub fn parse_slot(value: &str) -> Result<u16, Error> {
+    match value.parse::<u16>() {
+        Ok(slot) if slot < SLOT_COUNT => Ok(slot),
+        _ => Err(Error::custom("ERR Invalid or out of range slot")),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeRole {
+    Master,
+    Replica,
+}
+
+impl fmt::Display for NodeRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NodeRole::Master => write!(f, "master"),
+            NodeRole::Replica => write!(f, "slave"),
+        }
+    }
+}
+
+/// How `CLUSTER FAILOVER` replaces the master
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailoverMode {
+    /// Waits for the replica to catch up with the master first
+    Default,
+    /// Starts an election right away, the master may be unreachable
+    Force,
+    /// Takes the slots over without asking the other masters
+    Takeover,
+}
+
+/// Outgoing connection to the bus of a node, messages are queued until the
+/// task writing them connects
+#[derive(Debug)]
+struct Link {
+    sender: UnboundedSender<Vec<u8>>,
+    /// Taken by the task writing the messages
+    receiver: Option<UnboundedReceiver<Vec<u8>>>,
+    created: Instant,
+}
+
+impl Link {
+    fn new() -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        Self {
+            sender,
+            receiver: Some(receiver),
+            created: Instant::now(),
+        }
+    }
+
+    fn is_broken(&self) -> bool {
+        self.sender.is_closed()
+    }
+}
+
+/// Node of the cluster, this one included
+#[derive(Debug)]
+pub struct ClusterNode {
+    pub(crate) id: String,
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    pub(crate) cport: u16,
+    pub(crate) role: NodeRole,
+    /// Master replicated by a replica
+    pub(crate) master_id: Option<String>,
+    /// Epoch of the last change of the slots the node serves
+    pub(crate) config_epoch: u64,
+    /// Replication offset the node last announced
+    pub(crate) repl_offset: u64,
+    /// Not answering pings according to this node
+    pub(crate) pfail: bool,
+    /// Not answering pings according to a majority of masters
+    pub(crate) fail: bool,
+    /// Met with `CLUSTER MEET`, its id is only known once it answers
+    pub(crate) handshake: bool,
+    fail_time: Option<Instant>,
+    /// Masters which reported the node as failing, and when they last did
+    fail_reports: HashMap<String, Instant>,
+    ping_sent: Option<Instant>,
+    pong_received: Option<Instant>,
+    /// Last time this node voted for a replica of the node
+    voted_time: Option<Instant>,
+    created: Instant,
+    link: Option<Link>,
+}
+
+impl ClusterNode {
+    pub fn new(id: String, host: String, port: u16) -> Self {
+        Self {
+            id,
+            host,
+            port,
+            cport: port + BUS_PORT_OFFSET,
+            role: NodeRole::Master,
+            master_id: None,
+            config_epoch: 0,
+            repl_offset: 0,
+            pfail: false,
+            fail: false,
+            handshake: false,
+            fail_time: None,
+            fail_reports: HashMap::new(),
+            ping_sent: None,
+            pong_received: None,
+            voted_time: None,
+            created: Instant::now(),
+            link: None,
+        }
+    }
+
+    /// Whether the link to the node works and it answers pings
+    pub fn is_connected(&self) -> bool {
+        self.link.as_ref().is_some_and(|link| !link.is_broken()) && !self.pfail && !self.fail
+    }
+}
+
+/// Election of a replica replacing its failed master
+#[derive(Debug)]
+struct Election {
+    /// When votes are asked, delayed for the best replica to go first
+    start: Instant,
+    /// Epoch the votes are asked for, 0 until they are
+    epoch: u64,
+    /// Masters which voted for this node
+    votes: HashSet<String>,
+    /// Started by `CLUSTER FAILOVER`, masters vote even if its master works
+    forced: bool,
+}
+
+/// `CLUSTER FAILOVER` in progress
+#[derive(Debug)]
+struct ManualFailover {
+    deadline: Instant,
+    /// Offset of the master to reach before the election, none when forced
+    offset: Option<u64>,
+}
+
+/// Nodes of the cluster and the slots they serve
+#[derive(Debug)]
+pub struct Cluster {
+    /// Id of this node
+    pub(crate) myself: String,
+    pub(crate) current_epoch: u64,
+    /// Last epoch this node voted in
+    pub(crate) last_vote_epoch: u64,
+    pub(crate) nodes: BTreeMap<String, ClusterNode>,
+    /// Node serving each slot
+    slots: Vec<Option<String>>,
+    /// Slots served here moving to another node, `CLUSTER SETSLOT MIGRATING`
+    migrating: BTreeMap<u16, String>,
+    /// Slots moving here from another node, `CLUSTER SETSLOT IMPORTING`
+    importing: BTreeMap<u16, String>,
+    /// Time a node may not answer pings before being flagged as failing
+    pub(crate) node_timeout: Duration,
+    /// Whether the config changed since it was last saved
+    pub(crate) dirty: bool,
+    /// Nodes removed with `CLUSTER FORGET`, not added back by gossip for a while
+    forgotten: HashMap<String, Instant>,
+    election: Option<Election>,
+    manual_failover: Option<ManualFailover>,
+}
+
+impl Cluster {
+    /// Cluster made of this node only, serving no slot
+    pub fn new(id: String, host: String, port: u16) -> Self {
+        let myself = ClusterNode::new(id.clone(), host, port);
+
+        Self {
+            myself: id.clone(),
+            current_epoch: 0,
+            last_vote_epoch: 0,
+            nodes: BTreeMap::from([(id, myself)]),
+            slots: vec![None; SLOT_COUNT as usize],
+            migrating: BTreeMap::new(),
+            importing: BTreeMap::new(),
+            node_timeout: DEFAULT_NODE_TIMEOUT,
+            dirty: true,
+            forgotten: HashMap::new(),
+            election: None,
+            manual_failover: None,
+        }
+    }
+
+    /// Parses a cluster configuration, as written by [`Cluster::config`]
+    pub fn parse(config: &str) -> Result<Self> {
+        let mut myself = None;
+        let mut current_epoch = 0;
+        let mut last_vote_epoch = 0;
+        let mut nodes = BTreeMap::new();
+        let mut slots = vec![None; SLOT_COUNT as usize];
+        let mut migrating = BTreeMap::new();
+        let mut importing = BTreeMap::new();
+
+        for line in config.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let fields: Vec<&str> = line.split(' ').collect();
+
+            if fields[0] == "vars" {
+                for pair in fields[1..].chunks(2) {
+                    match pair {
+                        ["currentEpoch", epoch] => current_epoch = epoch.parse()?,
+                        ["lastVoteEpoch", epoch] => last_vote_epoch = epoch.parse()?,
+                        _ => {}
+                    }
+                }
+
+                continue;
+            }
+
+            if fields.len() < 8 {
+                return Err(anyhow!("Invalid cluster config line '{line}'"));
+            }
+
+            let (host, ports) = fields[1]
+                .split(',')
+                .next()
+                .and_then(|addr| addr.rsplit_once(':'))
+                .ok_or_else(|| anyhow!("Invalid node address '{}'", fields[1]))?;
+            let (port, cport) = match ports.split_once('@') {
+                Some((port, cport)) => (port.parse()?, cport.parse()?),
+                None => (ports.parse()?, ports.parse::<u16>()? + BUS_PORT_OFFSET),
+            };
+            let flags: Vec<&str> = fields[2].split(',').collect();
+            let id = fields[0].to_string();
+
+            if flags.contains(&"myself") {
+                myself = Some(id.clone());
+            }
+
+            let mut node = ClusterNode::new(id.clone(), host.to_string(), port);
+            node.cport = cport;
+            node.role = if flags.contains(&"slave") { NodeRole::Replica } else { NodeRole::Master };
+            node.master_id = (fields[3] != "-").then(|| fields[3].to_string());
+            node.config_epoch = fields[6].parse()?;
+            node.fail = flags.contains(&"fail");
+            node.fail_time = node.fail.then(Instant::now);
+
+            for range in &fields[8..] {
+                // Slots moving, `[slot->-node]` to another node and
+                // `[slot-<-node]` from another one
+                if let Some(state) = range.strip_prefix('[').and_then(|state| state.strip_suffix(']')) {
+                    let (slot, other, states) = match (state.split_once("->-"), state.split_once("-<-")) {
+                        (Some((slot, other)), _) => (slot, other, &mut migrating),
+                        (_, Some((slot, other))) => (slot, other, &mut importing),
+                        _ => return Err(anyhow!("Invalid slot state '{range}'")),
+                    };
+                    let slot = slot.parse::<u16>()?;
+
+                    if slot >= SLOT_COUNT {
+                        return Err(anyhow!("Invalid slot state '{range}'"));
+                    }
+
+                    states.insert(slot, other.to_string());
+                    continue;
+                }
+
+                let (start, end) = range.split_once('-').unwrap_or((range, range));
+                let (start, end) = (start.parse::<usize>()?, end.parse::<usize>()?);
+
+                if start > end || end >= SLOT_COUNT as usize {
+                    return Err(anyhow!("Invalid slot range '{range}'"));
+                }
+
+                for slot in &mut slots[start..=end] {
+                    *slot = Some(id.clone());
+                }
+            }
+
+            nodes.insert(id, node);
+        }
+
+        let myself = myself.ok_or_else(|| anyhow!("No node flagged myself in cluster config"))?;
+
+        Ok(Self {
+            myself,
+            current_epoch,
+            last_vote_epoch,
+            nodes,
+            slots,
+            migrating,
+            importing,
+            node_timeout: DEFAULT_NODE_TIMEOUT,
+            dirty: false,
+            forgotten: HashMap::new(),
+            election: None,
+            manual_failover: None,
+        })
+    }
+
+    pub fn myself(&self) -> &ClusterNode {
+        &self.nodes[&self.myself]
+    }
+
+    fn myself_mut(&mut self) -> &mut ClusterNode {
+        self.nodes.get_mut(&self.myself).expect("myself is a node of the cluster")
+    }
+
+    /// Node serving `slot`
+    pub fn owner(&self, slot: u16) -> Option<&ClusterNode> {
+        self.slots[slot as usize].as_ref().and_then(|id| self.nodes.get(id))
+    }
+
+    /// Slots served by `id`, as inclusive ranges
+    pub fn slot_ranges(&self, id: &str) -> Vec<(u16, u16)> {
+        let mut ranges: Vec<(u16, u16)> = Vec::new();
+
+        for (slot, owner) in self.slots.iter().enumerate() {
+            if owner.as_deref() != Some(id) {
+                continue;
+            }
+
+            let slot = slot as u16;
+
+            match ranges.last_mut() {
+                Some((_, end)) if *end + 1 == slot => *end = slot,
+                _ => ranges.push((slot, slot)),
+            }
+        }
+
+        ranges
+    }
+
+    fn slot_count(&self, id: &str) -> usize {
+        self.slots.iter().filter(|owner| owner.as_deref() == Some(id)).count()
+    }
+
+    pub fn assigned_slots(&self) -> usize {
+        self.slots.iter().filter(|owner| owner.is_some()).count()
+    }
+
+    /// Slots served by nodes flagged as failing, by this node alone and by a
+    /// majority of masters
+    pub fn failing_slots(&self) -> (usize, usize) {
+        let owners = self.slots.iter().flatten().filter_map(|id| self.nodes.get(id));
+
+        owners.fold((0, 0), |(pfail, fail), node| (pfail + node.pfail as usize, fail + node.fail as usize))
+    }
+
+    /// Whether every slot is served by a working master, and this node can
+    /// reach a majority of masters, the cluster refuses queries otherwise
+    pub fn is_ok(&self) -> bool {
+        let mut masters: Vec<&String> = self.slots.iter().flatten().collect();
+        masters.sort();
+        masters.dedup();
+
+        let reachable = masters
+            .iter()
+            .filter_map(|id| self.nodes.get(*id))
+            .filter(|node| node.id == self.myself || !(node.pfail || node.fail))
+            .count();
+
+        self.assigned_slots() == SLOT_COUNT as usize
+            && self.failing_slots().1 == 0
+            && reachable >= self.quorum()
+    }
+
+    /// Masters serving at least one slot
+    pub fn size(&self) -> usize {
+        let mut owners: Vec<&String> = self.slots.iter().flatten().collect();
+        owners.sort();
+        owners.dedup();
+
+        owners.len()
+    }
+
+    /// Masters agreeing on a failure or electing a replica
+    fn quorum(&self) -> usize {
+        self.size() / 2 + 1
+    }
+
+    /// Assigns `slots` to this node, none of them may be served already
+    pub fn add_slots(&mut self, slots: &[u16]) -> Result<(), Error> {
+        for slot in slots {
+            if self.slots[*slot as usize].is_some() {
+                return Err(Error::custom(format!("ERR Slot {slot} is already busy")));
+            }
+        }
+
+        for slot in slots {
+            self.slots[*slot as usize] = Some(self.myself.clone());
+        }
+
+        self.dirty = true;
+
+        Ok(())
+    }
+
+    /// Forgets who serves `slots`, all of them must be served by some node
+    pub fn del_slots(&mut self, slots: &[u16]) -> Result<(), Error> {
+        for slot in slots {
+            if self.slots[*slot as usize].is_none() {
+                return Err(Error::custom(format!("ERR Slot {slot} is already unassigned")));
+            }
+        }
+
+        for slot in slots {
+            self.slots[*slot as usize] = None;
+        }
+
+        self.dirty = true;
+
+        Ok(())
+    }
+
+    /// Checks this node can run `command` accessing `keys`, they must all be
+    /// in the same slot and served here
+    ///
+    /// During the migration of their slot the keys are served by the node
+    /// which has them, the importing node only serves clients which sent
+    /// `ASKING` first.
+    pub fn route(&self, command: &str, keys: &[String], store: &Store, asking: bool) -> Result<(), Error> {
+        let Some(first) = keys.first() else {
+            return Ok(());
+        };
+
+        let slot = key_slot(first);
+
+        if keys[1..].iter().any(|key| key_slot(key) != slot) {
+            return Err(Error::custom("CROSSSLOT Keys in request don't hash to the same slot"));
+        }
+
+        if !self.is_ok() {
+            return Err(Error::custom("CLUSTERDOWN The cluster is down"));
+        }
+
+        let Some(owner) = self.owner(slot) else {
+            return Err(Error::custom("CLUSTERDOWN Hash slot not served"));
+        };
+        let migrating = self.migrating.get(&slot).filter(|_| owner.id == self.myself);
+        let importing = self.importing.contains_key(&slot);
+
+        if migrating.is_some() || importing {
+            if command == "migrate" {
+                return Ok(());
+            }
+
+            let exists = |key: &&String| store.get(key).is_some_and(|value| !value.is_expired());
+            let existing = keys.iter().filter(exists).count();
+
+            if let Some(target) = migrating.and_then(|id| self.nodes.get(id)) {
+                match existing {
+                    existing if existing == keys.len() => {}
+                    0 => return Err(Error::custom(format!("ASK {slot} {}:{}", target.host, target.port))),
+                    _ => return Err(Error::custom("TRYAGAIN Multiple keys request during rehashing of slot")),
+                }
+            }
+
+            if importing && asking {
+                return match keys.len() > 1 && existing < keys.len() {
+                    true => Err(Error::custom("TRYAGAIN Multiple keys request during rehashing of slot")),
+                    false => Ok(()),
+                };
+            }
+        }
+
+        match owner.id == self.myself {
+            true => Ok(()),
+            false => Err(Error::custom(format!("MOVED {slot} {}:{}", owner.host, owner.port))),
+        }
+    }
+
+    /// `CLUSTER SETSLOT slot MIGRATING node`, the slot served here moves to
+    /// the master `id`
+    pub fn set_slot_migrating(&mut self, slot: u16, id: &str) -> Result<(), Error> {
+        self.check_slot_master()?;
+
+        if self.slots[slot as usize].as_ref() != Some(&self.myself) {
+            return Err(Error::custom(format!("ERR I'm not the owner of hash slot {slot}")));
+        }
+
+        self.check_slot_target(id)?;
+        self.migrating.insert(slot, id.to_string());
+        self.dirty = true;
+
+        Ok(())
+    }
+
+    /// `CLUSTER SETSLOT slot IMPORTING node`, the slot moves here from the
+    /// master `id`
+    pub fn set_slot_importing(&mut self, slot: u16, id: &str) -> Result<(), Error> {
+        self.check_slot_master()?;
+
+        if self.slots[slot as usize].as_ref() == Some(&self.myself) {
+            return Err(Error::custom(format!("ERR I'm already the owner of hash slot {slot}")));
+        }
+
+        self.check_slot_target(id)?;
+        self.importing.insert(slot, id.to_string());
+        self.dirty = true;
+
+        Ok(())
+    }
+
+    /// `CLUSTER SETSLOT slot STABLE`, the slot stops moving
+    pub fn set_slot_stable(&mut self, slot: u16) -> Result<(), Error> {
+        self.check_slot_master()?;
+
+        self.migrating.remove(&slot);
+        self.importing.remove(&slot);
+        self.dirty = true;
+
+        Ok(())
+    }
+
+    /// `CLUSTER SETSLOT slot NODE node`, the end of a migration: the master
+    /// `id` serves the slot, which this node may only give away once it
+    /// holds none of its keys
+    ///
+    /// The importing node takes a new config epoch without asking the other
+    /// masters, so its claim on the slot wins over the one of its former
+    /// owner.
+    pub fn set_slot_node(&mut self, slot: u16, id: &str, keys: usize) -> Result<(), Error> {
+        self.check_slot_master()?;
+
+        let Some(node) = self.nodes.get(id) else {
+            return Err(Error::custom(format!("ERR Unknown node {id}")));
+        };
+
+        if node.role != NodeRole::Master {
+            return Err(Error::custom("ERR Target node is not a master"));
+        }
+
+        let mine = self.slots[slot as usize].as_ref() == Some(&self.myself);
+
+        if mine && id != self.myself && keys > 0 {
+            return Err(Error::custom(format!(
+                "ERR Can't assign hashslot {slot} to a different node while I still hold keys for this hash slot."
+            )));
+        }
+
+        if keys == 0 && self.migrating.get(&slot).is_some_and(|target| target == id) {
+            self.migrating.remove(&slot);
+        }
+
+        self.slots[slot as usize] = Some(id.to_string());
+        self.dirty = true;
+
+        if id == self.myself && self.importing.remove(&slot).is_some() {
+            let max_epoch = self.nodes.values().map(|node| node.config_epoch).max().unwrap_or(0).max(self.current_epoch);
+            let config_epoch = self.myself().config_epoch;
+
+            if config_epoch == 0 || config_epoch != max_epoch {
+                self.current_epoch += 1;
+                self.myself_mut().config_epoch = self.current_epoch;
+            }
+
+            self.broadcast(|cluster| Body::Pong(cluster.gossip()));
+        }
+
+        Ok(())
+    }
+
+    fn check_slot_master(&self) -> Result<(), Error> {
+        match self.myself().role {
+            NodeRole::Master => Ok(()),
+            NodeRole::Replica => Err(Error::custom("ERR Please use SETSLOT only with masters.")),
+        }
+    }
+
+    fn check_slot_target(&self, id: &str) -> Result<(), Error> {
+        match self.nodes.get(id) {
+            None => Err(Error::custom(format!("ERR I don't know about node {id}"))),
+            Some(node) if node.role != NodeRole::Master => Err(Error::custom("ERR Target node is not a master")),
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Starts a handshake with the node at `host:port`, `CLUSTER MEET`
+    pub fn meet(&mut self, host: &str, port: u16, cport: u16) -> Result<(), Error> {
+        if host.parse::<IpAddr>().is_err() || port == 0 || cport == 0 {
+            return Err(Error::custom(format!("ERR Invalid node address specified: {host}:{port}")));
+        }
+
+        let pending = self.nodes.values().any(|node| node.handshake && node.host == host && node.port == port);
+
+        if !pending {
+            // Named for now, renamed once the node tells its id
+            let mut node = ClusterNode::new(generate_replid(), host.to_string(), port);
+            node.cport = cport;
+            node.handshake = true;
+            self.nodes.insert(node.id.clone(), node);
+        }
+
+        Ok(())
+    }
+
+    /// Removes the node `id` from the cluster, `CLUSTER FORGET`
+    pub fn forget(&mut self, id: &str) -> Result<(), Error> {
+        if !self.nodes.contains_key(id) {
+            return Err(Error::custom(format!("ERR Unknown node {id}")));
+        }
+        if id == self.myself {
+            return Err(Error::custom("ERR I tried hard but I can't forget myself..."));
+        }
+        if self.myself().master_id.as_deref() == Some(id) {
+            return Err(Error::custom("ERR Can't forget my master!"));
+        }
+
+        self.remove_node(id);
+        self.forgotten.insert(id.to_string(), Instant::now());
+
+        Ok(())
+    }
+
+    fn remove_node(&mut self, id: &str) {
+        self.nodes.remove(id);
+
+        for owner in self.slots.iter_mut().filter(|owner| owner.as_deref() == Some(id)) {
+            *owner = None;
+        }
+        for node in self.nodes.values_mut() {
+            node.fail_reports.remove(id);
+        }
+
+        self.dirty = true;
+    }
+
+    /// Makes this node a replica of the master `id`, `CLUSTER REPLICATE`, a
+    /// master must serve no slot and hold no key to become one
+    pub fn replicate(&mut self, id: &str, has_keys: bool) -> Result<(), Error> {
+        let Some(node) = self.nodes.get(id) else {
+            return Err(Error::custom(format!("ERR Unknown node {id}")));
+        };
+
+        if id == self.myself {
+            return Err(Error::custom("ERR Can't replicate myself"));
+        }
+        if node.role != NodeRole::Master {
+            return Err(Error::custom("ERR I can only replicate a master, not a replica."));
+        }
+        if self.myself().role == NodeRole::Master && (self.slot_count(&self.myself) > 0 || has_keys) {
+            return Err(Error::custom(
+                "ERR To set a master the node must be empty and without assigned slots.",
+            ));
+        }
+
+        self.set_master(id);
+
+        Ok(())
+    }
+
+    fn set_master(&mut self, id: &str) {
+        let myself = self.myself_mut();
+        myself.role = NodeRole::Replica;
+        myself.master_id = Some(id.to_string());
+
+        self.election = None;
+        self.manual_failover = None;
+        self.dirty = true;
+    }
+
+    /// Address of the master this node replicates, when it knows it
+    pub fn master_addr(&self) -> Option<(String, u16)> {
+        let myself = self.myself();
+        let master = self.nodes.get(myself.master_id.as_ref()?)?;
+
+        (myself.role == NodeRole::Replica && !master.handshake).then(|| (master.host.clone(), master.port))
+    }
+
+    /// Replaces the master of this replica, `CLUSTER FAILOVER`
+    pub fn failover(&mut self, mode: FailoverMode) -> Result<(), Error> {
+        let myself = self.myself();
+
+        if myself.role != NodeRole::Replica {
+            return Err(Error::custom("ERR You should send CLUSTER FAILOVER to a replica"));
+        }
+
+        let Some(master) = myself.master_id.as_ref().and_then(|id| self.nodes.get(id)) else {
+            return Err(Error::custom("ERR I'm a replica but my master is unknown to me"));
+        };
+
+        if mode == FailoverMode::Default && (master.fail || master.pfail) {
+            return Err(Error::custom(
+                "ERR Master is down or failed, please use CLUSTER FAILOVER FORCE",
+            ));
+        }
+
+        let offset = master.repl_offset;
+
+        match mode {
+            FailoverMode::Takeover => {
+                self.current_epoch += 1;
+                self.become_master(self.current_epoch);
+            }
+            FailoverMode::Force | FailoverMode::Default => {
+                self.election = None;
+                self.manual_failover = Some(ManualFailover {
+                    deadline: Instant::now() + MANUAL_FAILOVER_TIMEOUT,
+                    offset: (mode == FailoverMode::Default).then_some(offset),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Turns this replica into a master serving the slots of its former
+    /// master, and tells every node
+    fn become_master(&mut self, epoch: u64) {
+        let myself = self.myself_mut();
+        let former = myself.master_id.take();
+        myself.role = NodeRole::Master;
+        myself.config_epoch = myself.config_epoch.max(epoch);
+
+        if let Some(former) = former {
+            for owner in self.slots.iter_mut().filter(|owner| owner.as_ref() == Some(&former)) {
+                *owner = Some(self.myself.clone());
+            }
+        }
+
+        eprintln!("Cluster: failover done, serving the slots of the former master at epoch {epoch}");
+
+        self.election = None;
+        self.manual_failover = None;
+        self.dirty = true;
+        self.broadcast(|cluster| Body::Pong(cluster.gossip()));
+    }
+
+    /// Handles a message received on the bus from `ip`
+    pub fn process(&mut self, message: Message, ip: &str) {
+        let Message { header, body } = message;
+        let sender = header.sender.clone();
+
+        if !self.nodes.contains_key(&sender) {
+            match &body {
+                Body::Meet(_) if !self.forgotten.contains_key(&sender) => {
+                    let mut node = ClusterNode::new(sender.clone(), ip.to_string(), header.port);
+                    node.cport = header.cport;
+                    self.nodes.insert(sender.clone(), node);
+                    self.dirty = true;
+                }
+                Body::Pong(_) => {
+                    // Answer to CLUSTER MEET, the node now has its real id
+                    let met = self
+                        .nodes
+                        .values()
+                        .find(|node| node.handshake && node.host == ip && node.port == header.port)
+                        .map(|node| node.id.clone());
+
+                    let Some(met) = met else {
+                        return;
+                    };
+                    let mut node = self.nodes.remove(&met).expect("node met");
+                    node.id = sender.clone();
+                    node.handshake = false;
+                    self.nodes.insert(sender.clone(), node);
+                    self.dirty = true;
+                }
+                _ => return,
+            }
+        }
+
+        if self.nodes[&sender].handshake {
+            return;
+        }
+
+        if header.current_epoch > self.current_epoch {
+            self.current_epoch = header.current_epoch;
+            self.dirty = true;
+        }
+
+        self.update_node(&header);
+        self.clear_failure(&sender);
+
+        match body {
+            Body::Ping(gossip) | Body::Meet(gossip) => {
+                self.process_gossip(&sender, &gossip);
+                self.send(&sender, Body::Pong(self.gossip()));
+            }
+            Body::Pong(gossip) => {
+                let node = self.nodes.get_mut(&sender).expect("sender is known");
+                node.ping_sent = None;
+                node.pong_received = Some(Instant::now());
+                node.pfail = false;
+
+                self.process_gossip(&sender, &gossip);
+            }
+            Body::Fail(id) => {
+                if let Some(node) = self.nodes.get_mut(&id).filter(|node| node.id != self.myself && !node.fail) {
+                    eprintln!("Cluster: FAIL message received from {sender} about {id}");
+                    node.fail = true;
+                    node.pfail = false;
+                    node.fail_time = Some(Instant::now());
+                    self.dirty = true;
+                }
+            }
+            Body::AuthRequest { forced } => self.vote(&header, forced),
+            Body::AuthAck => {
+                let serves_slots = self.nodes[&sender].role == NodeRole::Master && self.slot_count(&sender) > 0;
+
+                if let Some(election) = &mut self.election {
+                    if serves_slots && election.epoch > 0 && header.current_epoch >= election.epoch {
+                        election.votes.insert(sender);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Updates what is known of the sender of a message from its header
+    fn update_node(&mut self, header: &Header) {
+        let id = &header.sender;
+        let node = self.nodes.get_mut(id).expect("sender is known");
+        node.repl_offset = header.offset;
+
+        match &header.master_id {
+            Some(master) => {
+                let was_master = node.role == NodeRole::Master;
+
+                if was_master || node.master_id.as_ref() != Some(master) {
+                    node.role = NodeRole::Replica;
+                    node.master_id = Some(master.clone());
+                    self.dirty = true;
+                }
+
+                // Replicas serve no slot
+                if was_master {
+                    for owner in self.slots.iter_mut().filter(|owner| owner.as_ref() == Some(id)) {
+                        *owner = None;
+                    }
+                }
+            }
+            None => {
+                if node.role == NodeRole::Replica || node.config_epoch != header.config_epoch {
+                    node.role = NodeRole::Master;
+                    node.master_id = None;
+                    node.config_epoch = header.config_epoch;
+                    self.dirty = true;
+                }
+
+                self.claim_slots(id, &header.slots, header.config_epoch);
+                self.resolve_epoch_collision(id);
+            }
+        }
+    }
+
+    /// Gives the master `id` the slots it claims, unless they are served by
+    /// a node with a more recent config
+    ///
+    /// A master losing all its slots this way was replaced, it becomes a
+    /// replica of the new master, as do its replicas.
+    fn claim_slots(&mut self, id: &str, ranges: &[(u16, u16)], epoch: u64) {
+        let myself = self.myself();
+        let my_master = match myself.role {
+            NodeRole::Master => Some(self.myself.clone()),
+            NodeRole::Replica => myself.master_id.clone(),
+        };
+        let mut moved_from_my_master = false;
+
+        for (start, end) in ranges {
+            for slot in *start as usize..=(*end as usize).min(SLOT_COUNT as usize - 1) {
+                // Only `CLUSTER SETSLOT NODE` ends an import
+                if self.importing.contains_key(&(slot as u16)) {
+                    continue;
+                }
+
+                if let Some(owner) = &self.slots[slot] {
+                    if owner == id || self.nodes.get(owner).is_some_and(|owner| owner.config_epoch >= epoch) {
+                        continue;
+                    }
+                }
+
+                moved_from_my_master |= self.slots[slot].is_some() && self.slots[slot] == my_master;
+
+                if self.slots[slot].as_ref() == Some(&self.myself) {
+                    self.migrating.remove(&(slot as u16));
+                }
+
+                self.slots[slot] = Some(id.to_string());
+                self.dirty = true;
+            }
+        }
+
+        if moved_from_my_master && my_master.is_some_and(|master| self.slot_count(&master) == 0) {
+            eprintln!("Cluster: slots taken over by {id}, replicating it");
+            self.set_master(id);
+        }
+    }
+
+    /// Two masters with the same config epoch, the one with the smaller id
+    /// moves to a new epoch so claims on slots can be ordered
+    fn resolve_epoch_collision(&mut self, id: &str) {
+        let (myself, sender) = (self.myself(), &self.nodes[id]);
+
+        if myself.role != NodeRole::Master
+            || sender.role != NodeRole::Master
+            || myself.config_epoch != sender.config_epoch
+            || sender.id <= myself.id
+        {
+            return;
+        }
+
+        self.current_epoch += 1;
+        self.myself_mut().config_epoch = self.current_epoch;
+        self.dirty = true;
+    }
+
+    /// Records what the master `sender` says about the failure of other nodes,
+    /// and adds the nodes it knows this one doesn't
+    fn process_gossip(&mut self, sender: &str, gossip: &[Gossip]) {
+        let from_master = self.nodes[sender].role == NodeRole::Master;
+        let myself = self.myself.clone();
+
+        for entry in gossip.iter().filter(|entry| entry.id != myself) {
+            match self.nodes.get_mut(&entry.id) {
+                Some(node) if from_master => {
+                    if entry.pfail || entry.fail {
+                        node.fail_reports.insert(sender.to_string(), Instant::now());
+                        self.mark_failing(&entry.id);
+                    } else {
+                        node.fail_reports.remove(sender);
+                    }
+                }
+                Some(_) => {}
+                None if !self.forgotten.contains_key(&entry.id) => {
+                    let mut node = ClusterNode::new(entry.id.clone(), entry.host.clone(), entry.port);
+                    node.cport = entry.cport;
+                    node.role = entry.role;
+                    self.nodes.insert(entry.id.clone(), node);
+                    self.dirty = true;
+                }
+                None => {}
+            }
+        }
+    }
+
+    /// Flags `id` as failed when a majority of masters agree it is failing,
+    /// and tells every node
+    fn mark_failing(&mut self, id: &str) {
+        let quorum = self.quorum();
+        let validity = self.node_timeout * FAIL_REPORT_VALIDITY_MULT;
+        let from_myself = self.myself().role == NodeRole::Master;
+        let Some(node) = self.nodes.get_mut(id) else {
+            return;
+        };
+
+        if !node.pfail || node.fail {
+            return;
+        }
+
+        node.fail_reports.retain(|_, reported| reported.elapsed() <= validity);
+
+        if node.fail_reports.len() + (from_myself as usize) < quorum {
+            return;
+        }
+
+        eprintln!("Cluster: marking node {id} as failing (quorum reached)");
+        node.fail = true;
+        node.pfail = false;
+        node.fail_time = Some(Instant::now());
+        self.dirty = true;
+
+        let id = id.to_string();
+        self.broadcast(|_| Body::Fail(id.clone()));
+    }
+
+    /// Clears the failure of a node heard of again, right away when it serves
+    /// no slot, otherwise once nobody replaced it for a while
+    fn clear_failure(&mut self, id: &str) {
+        let serves_slots = self.slot_count(id) > 0;
+        let undo = self.node_timeout * FAIL_UNDO_TIME_MULT;
+        let node = self.nodes.get_mut(id).expect("node is known");
+
+        if !node.fail {
+            return;
+        }
+
+        if node.role == NodeRole::Replica || !serves_slots || node.fail_time.is_some_and(|time| time.elapsed() > undo) {
+            eprintln!("Cluster: clear FAIL state for node {id}: it is reachable again");
+            node.fail = false;
+            node.fail_time = None;
+            self.dirty = true;
+        }
+    }
+
+    /// Gives the vote of this master to the replica asking for it, at most
+    /// once per epoch and per failed master
+    fn vote(&mut self, header: &Header, forced: bool) {
+        let myself = self.myself();
+
+        if myself.role != NodeRole::Master || self.slot_count(&self.myself) == 0 {
+            return;
+        }
+        if header.current_epoch < self.current_epoch || self.last_vote_epoch == self.current_epoch {
+            return;
+        }
+
+        let Some(master) = header.master_id.as_ref().and_then(|id| self.nodes.get(id)) else {
+            return;
+        };
+
+        if !master.fail && !forced {
+            return;
+        }
+        if master.voted_time.is_some_and(|time| time.elapsed() < self.node_timeout * 2) {
+            return;
+        }
+
+        // The replica must know about the latest config of the slots it claims
+        for (start, end) in &header.slots {
+            for slot in *start..=*end {
+                if self.owner(slot).is_some_and(|owner| owner.config_epoch > header.config_epoch) {
+                    return;
+                }
+            }
+        }
+
+        let master = master.id.clone();
+        self.nodes.get_mut(&master).expect("master is known").voted_time = Some(Instant::now());
+        self.last_vote_epoch = self.current_epoch;
+        self.dirty = true;
+
+        eprintln!("Cluster: failover auth granted to {} for epoch {}", header.sender, self.current_epoch);
+        self.send(&header.sender, Body::AuthAck);
+    }
+
+    /// Runs periodically: pings the nodes, flags the ones not answering and
+    /// replaces the master of this replica when it failed
+    pub fn cron(&mut self, offset: u64) {
+        let now = Instant::now();
+        let node_timeout = self.node_timeout;
+        let handshake_timeout = node_timeout.max(Duration::from_secs(1));
+        let ping_period = PING_PERIOD.min(node_timeout / 2);
+
+        self.myself_mut().repl_offset = offset;
+        self.forgotten.retain(|_, time| time.elapsed() < FORGET_TIME);
+
+        let expired: Vec<String> = self
+            .nodes
+            .values()
+            .filter(|node| node.handshake && node.created.elapsed() > handshake_timeout)
+            .map(|node| node.id.clone())
+            .collect();
+
+        for id in expired {
+            self.nodes.remove(&id);
+        }
+
+        let ids: Vec<String> = self.nodes.keys().filter(|id| **id != self.myself).cloned().collect();
+
+        for id in &ids {
+            let node = self.nodes.get_mut(id).expect("node is known");
+
+            // Connect again to nodes that went away, or stopped answering
+            let stale = node.ping_sent.is_some_and(|sent| sent.elapsed() > node_timeout / 2);
+
+            if node.link.as_ref().is_some_and(|link| {
+                link.created.elapsed() > RECONNECT_DELAY && (link.is_broken() || (stale && link.created.elapsed() > node_timeout / 2))
+            }) {
+                node.link = None;
+            }
+
+            if node.link.is_none() || (node.ping_sent.is_none() && due(node.pong_received, ping_period)) {
+                node.ping_sent.get_or_insert(now);
+
+                let body = match node.handshake {
+                    true => Body::Meet(self.gossip()),
+                    false => Body::Ping(self.gossip()),
+                };
+                self.send(id, body);
+            }
+
+            let node = self.nodes.get_mut(id).expect("node is known");
+
+            if !node.handshake && !node.pfail && !node.fail && node.ping_sent.is_some_and(|sent| sent.elapsed() > node_timeout) {
+                eprintln!("Cluster: *** NODE {id} possibly failing");
+                node.pfail = true;
+            }
+        }
+
+        for id in &ids {
+            self.mark_failing(id);
+        }
+
+        if self.manual_failover.as_ref().is_some_and(|manual| now > manual.deadline) {
+            eprintln!("Cluster: manual failover timed out");
+            self.manual_failover = None;
+
+            if self.election.as_ref().is_some_and(|election| election.forced) {
+                self.election = None;
+            }
+        }
+
+        self.handle_replica_failover();
+    }
+
+    /// Runs the election of this replica when its master failed, or when a
+    /// manual failover is ready
+    fn handle_replica_failover(&mut self) {
+        let myself = self.myself();
+        let Some(master) = myself.master_id.as_ref().and_then(|id| self.nodes.get(id)) else {
+            self.election = None;
+            return;
+        };
+
+        let manual = self.manual_failover.as_ref().is_some_and(|manual| match manual.offset {
+            Some(offset) => myself.repl_offset >= offset,
+            None => true,
+        });
+        let failed = myself.role == NodeRole::Replica && (master.fail || manual) && self.slot_count(&master.id) > 0;
+        // The replica with the most data goes first
+        let rank = self
+            .nodes
+            .values()
+            .filter(|node| node.id != self.myself && node.master_id.as_ref() == Some(&master.id))
+            .filter(|node| !node.fail && node.repl_offset > myself.repl_offset)
+            .count() as u64;
+
+        if !failed {
+            self.election = None;
+            return;
+        }
+
+        let auth_timeout = (self.node_timeout * 2).max(Duration::from_secs(2));
+
+        if self.election.as_ref().is_some_and(|election| election.start.elapsed() > auth_timeout * 2) {
+            self.election = None;
+        }
+
+        let Some(election) = &self.election else {
+            let delay = match manual {
+                true => Duration::ZERO,
+                false => Duration::from_millis(500 + random_below(500) + rank * 1000),
+            };
+
+            eprintln!("Cluster: start of election delayed for {}ms (rank #{rank})", delay.as_millis());
+            self.election = Some(Election {
+                start: Instant::now() + delay,
+                epoch: 0,
+                votes: HashSet::new(),
+                forced: manual,
+            });
+            return;
+        };
+
+        if Instant::now() < election.start || election.start.elapsed() > auth_timeout {
+            return;
+        }
+
+        if election.epoch == 0 {
+            self.current_epoch += 1;
+            self.dirty = true;
+
+            let (epoch, forced) = (self.current_epoch, election.forced);
+            let election = self.election.as_mut().expect("election started");
+            election.epoch = epoch;
+
+            eprintln!("Cluster: starting a failover election for epoch {epoch}");
+            self.broadcast(|_| Body::AuthRequest { forced });
+            return;
+        }
+
+        if election.votes.len() >= self.quorum() {
+            self.become_master(election.epoch);
+        }
+    }
+
+    /// Header of the messages this node sends
+    fn header(&self) -> Header {
+        let myself = self.myself();
+        let master = match &myself.master_id {
+            Some(id) => self.nodes.get(id).unwrap_or(myself),
+            None => myself,
+        };
+
+        Header {
+            sender: self.myself.clone(),
+            current_epoch: self.current_epoch,
+            config_epoch: master.config_epoch,
+            port: myself.port,
+            cport: myself.cport,
+            master_id: myself.master_id.clone(),
+            offset: myself.repl_offset,
+            slots: self.slot_ranges(&master.id),
+        }
+    }
+
+    /// A few random nodes, and every node looking down so failure reports
+    /// spread quickly
+    fn gossip(&self) -> Vec<Gossip> {
+        let mut candidates: Vec<&ClusterNode> = self
+            .nodes
+            .values()
+            .filter(|node| node.id != self.myself && !node.handshake)
+            .collect();
+        let wanted = (candidates.len() / 10).max(3);
+
+        candidates.sort_by_cached_key(|node| (!(node.pfail || node.fail), random_below(u64::MAX)));
+
+        candidates
+            .into_iter()
+            .enumerate()
+            .filter(|(n, node)| *n < wanted || node.pfail || node.fail)
+            .map(|(_, node)| Gossip {
+                id: node.id.clone(),
+                host: node.host.clone(),
+                port: node.port,
+                cport: node.cport,
+                role: node.role,
+                pfail: node.pfail,
+                fail: node.fail,
+            })
+            .collect()
+    }
+
+    /// Queues a message to `id`, connecting to it if needed
+    fn send(&mut self, id: &str, body: Body) {
+        let message = Message {
+            header: self.header(),
+            body,
+        };
+
+        if let Some(node) = self.nodes.get_mut(id) {
+            let link = node.link.get_or_insert_with(Link::new);
+
+            let _ = link.sender.send(message.encode());
+        }
+    }
+
+    /// Sends a message to every other node, `body` is built for each of them
+    fn broadcast(&mut self, body: impl Fn(&Self) -> Body) {
+        let ids: Vec<String> = self
+            .nodes
+            .values()
+            .filter(|node| node.id != self.myself && !node.handshake)
+            .map(|node| node.id.clone())
+            .collect();
+
+        for id in ids {
+            self.send(&id, body(self));
+        }
+    }
+
+    /// Links created since the last call, with the address to connect them to
+    pub fn take_new_links(&mut self) -> Vec<(String, u16, UnboundedReceiver<Vec<u8>>)> {
+        self.nodes
+            .values_mut()
+            .filter_map(|node| {
+                let receiver = node.link.as_mut()?.receiver.take()?;
+
+                Some((node.host.clone(), node.cport, receiver))
+            })
+            .collect()
+    }
+
+    /// Line of `CLUSTER NODES` describing `node`
+    pub fn describe_node(&self, node: &ClusterNode) -> String {
+        let mut flags = Vec::new();
+
+        if node.id == self.myself {
+            flags.push("myself");
+        }
+        flags.push(match node.role {
+            NodeRole::Master => "master",
+            NodeRole::Replica => "slave",
+        });
+        if node.pfail {
+            flags.push("fail?");
+        }
+        if node.fail {
+            flags.push("fail");
+        }
+        if node.handshake {
+            flags.push("handshake");
+        }
+
+        let link_state = match node.id == self.myself || node.is_connected() {
+            true => "connected",
+            false => "disconnected",
+        };
+        let mut line = format!(
+            "{} {}:{}@{} {} {} {} {} {} {link_state}",
+            node.id,
+            node.host,
+            node.port,
+            node.cport,
+            flags.join(","),
+            node.master_id.as_deref().unwrap_or("-"),
+            unix_millis(node.ping_sent),
+            unix_millis(node.pong_received),
+            node.config_epoch
+        );
+
+        for (start, end) in self.slot_ranges(&node.id) {
+            match start == end {
+                true => line.push_str(&format!(" {start}")),
+                false => line.push_str(&format!(" {start}-{end}")),
+            }
+        }
+
+        if node.id == self.myself {
+            for (slot, target) in &self.migrating {
+                line.push_str(&format!(" [{slot}->-{target}]"));
+            }
+            for (slot, source) in &self.importing {
+                line.push_str(&format!(" [{slot}-<-{source}]"));
+            }
+        }
+
+        line
+    }
+
+    /// Every node, one per line, as listed by `CLUSTER NODES`
+    pub fn describe(&self) -> String {
+        self.nodes.values().map(|node| self.describe_node(node) + "\n").collect()
+    }
+
+    /// Content of the `cluster-config-file`, the nodes past their handshake
+    /// followed by the epochs
+    pub fn config(&self) -> String {
+        let mut config: String = self
+            .nodes
+            .values()
+            .filter(|node| !node.handshake)
+            .map(|node| self.describe_node(node) + "\n")
+            .collect();
+
+        config.push_str(&format!(
+            "vars currentEpoch {} lastVoteEpoch {}\n",
+            self.current_epoch, self.last_vote_epoch
+        ));
+
+        config
+    }
+
+    /// Keys of `store` in `slot`
+    pub fn keys_in_slot<'a>(&self, store: &'a Store, slot: u16) -> impl Iterator<Item = &'a String> {
+        store.iter().map(|(key, _)| key).filter(move |key| key_slot(key) == slot)
+    }
+}
+
+/// Whether something done at `last` is due again after `period`
+fn due(last: Option<Instant>, period: Duration) -> bool {
+    match last {
+        Some(last) => last.elapsed() >= period,
+        None => true,
+    }
+}
+
+/// Milliseconds since the epoch at `instant`, 0 for never
+fn unix_millis(instant: Option<Instant>) -> u128 {
+    let Some(instant) = instant else {
+        return 0;
+    };
+    let time = SystemTime::now() - instant.elapsed();
+
+    time.duration_since(UNIX_EPOCH).map_or(0, |time| time.as_millis())
+}
+
+fn random_below(max: u64) -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(Instant::now().elapsed().as_nanos());
+
+    hasher.finish() % max.max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{StoreValue, Value};
+    use crate::resp::types::RespType;
+
+    const CONFIG: &str = "\
+07c37dfeb235213a872192d90877d0cd55635b91 127.0.0.1:30004@40004 slave e7d1eecce10fd6bb5eb35b9f99a514335d9ba9ca 0 1426238317239 4 connected
+67ed2db8d677e59ec4a4cefb06858cf2a1a89fa1 127.0.0.1:30002@40002 master - 0 1426238316232 2 connected 5461-10922
+e7d1eecce10fd6bb5eb35b9f99a514335d9ba9ca 127.0.0.1:30001@40001 myself,master - 0 0 1 connected 0-5460
+292f8b365bb7edb5e285caf0b7e6ddc7265d2f4f 127.0.0.1:30003@40003 master - 0 1426238318243 3 connected 10923-16383
+vars currentEpoch 6 lastVoteEpoch 0
+";
+
+    #[test]
+    fn parses_config() {
+        let cluster = Cluster::parse(CONFIG).unwrap();
+
+        assert_eq!(cluster.myself().port, 30001);
+        assert_eq!(cluster.current_epoch, 6);
+        assert!(cluster.is_ok());
+        assert_eq!(cluster.size(), 3);
+        assert_eq!(cluster.slot_ranges(&cluster.myself), vec![(0, 5460)]);
+        assert_eq!(
+            cluster.describe_node(cluster.myself()),
+            "e7d1eecce10fd6bb5eb35b9f99a514335d9ba9ca 127.0.0.1:30001@40001 myself,master - 0 0 1 connected 0-5460"
+        );
+        assert!(Cluster::parse("vars currentEpoch 0").is_err());
+    }
+
+    #[test]
+    fn routes_keys_to_their_node() {
+        let mut cluster = Cluster::parse(CONFIG).unwrap();
+        let store = Store::default();
+        let route = |cluster: &Cluster, keys: &[&str]| {
+            let keys = keys.iter().map(|key| key.to_string()).collect::<Vec<_>>();
+            cluster.route("get", &keys, &store, false)
+        };
+
+        assert_eq!(route(&cluster, &["b"]), Ok(()));
+        assert_eq!(route(&cluster, &["foo"]), Err(Error::custom("MOVED 12182 127.0.0.1:30003")));
+        assert_eq!(route(&cluster, &["{b}1", "{b}2"]), Ok(()));
+        assert_eq!(
+            route(&cluster, &["b", "foo"]),
+            Err(Error::custom("CROSSSLOT Keys in request don't hash to the same slot"))
+        );
+
+        cluster.del_slots(&[0]).unwrap();
+        assert_eq!(route(&cluster, &["b"]), Err(Error::custom("CLUSTERDOWN The cluster is down")));
+        assert_eq!(cluster.del_slots(&[0]), Err(Error::custom("ERR Slot 0 is already unassigned")));
+        assert_eq!(cluster.add_slots(&[0, 1]), Err(Error::custom("ERR Slot 1 is already busy")));
+        assert_eq!(cluster.add_slots(&[0]), Ok(()));
+    }
+
+    /// Masters a, b and c, d replicating c, seen from `myself`
+    fn cluster_of_four(myself: char) -> Cluster {
+        let flags = |id: char, role: &str| match id == myself {
+            true => format!("myself,{role}"),
+            false => role.to_string(),
+        };
+        let config = format!(
+            "{a} 127.0.0.1:30001@40001 {} - 0 0 1 connected 0-5460\n\
+             {b} 127.0.0.1:30002@40002 {} - 0 0 2 connected 5461-10922\n\
+             {c} 127.0.0.1:30003@40003 {} - 0 0 3 connected 10923-16383\n\
+             {d} 127.0.0.1:30004@40004 {} {c} 0 0 3 connected\n\
+             vars currentEpoch 3 lastVoteEpoch 0\n",
+            flags('a', "master"),
+            flags('b', "master"),
+            flags('c', "master"),
+            flags('d', "slave"),
+            a = id('a'),
+            b = id('b'),
+            c = id('c'),
+            d = id('d'),
+        );
+
+        Cluster::parse(&config).unwrap()
+    }
+
+    fn id(name: char) -> String {
+        name.to_string().repeat(40)
+    }
+
+    fn message(cluster: &Cluster, name: char, body: Body) -> Message {
+        let node = &cluster.nodes[&id(name)];
+
+        Message {
+            header: Header {
+                sender: node.id.clone(),
+                current_epoch: cluster.current_epoch,
+                config_epoch: node.config_epoch,
+                port: node.port,
+                cport: node.cport,
+                master_id: node.master_id.clone(),
+                offset: 0,
+                slots: cluster.slot_ranges(&node.id),
+            },
+            body,
+        }
+    }
+
+    /// Messages queued to the node `name`
+    fn sent(cluster: &mut Cluster, name: char) -> Vec<Message> {
+        let Some(link) = cluster.nodes.get_mut(&id(name)).unwrap().link.as_mut() else {
+            return Vec::new();
+        };
+        let receiver = link.receiver.as_mut().unwrap();
+        let mut messages = Vec::new();
+
+        while let Ok(bytes) = receiver.try_recv() {
+            let (frame, _) = RespType::parse(&bytes).unwrap().unwrap();
+            messages.push(Message::decode(frame).unwrap());
+        }
+
+        messages
+    }
+
+    #[test]
+    fn saves_config() {
+        let mut cluster = cluster_of_four('a');
+        cluster.nodes.get_mut(&id('c')).unwrap().fail = true;
+        cluster.last_vote_epoch = 2;
+
+        let saved = Cluster::parse(&cluster.config()).unwrap();
+
+        assert_eq!(saved.config(), cluster.config());
+        assert!(saved.nodes[&id('c')].fail);
+        assert_eq!((saved.current_epoch, saved.last_vote_epoch), (3, 2));
+        assert!(!saved.is_ok());
+    }
+
+    #[test]
+    fn agrees_on_failures() {
+        let mut cluster = cluster_of_four('a');
+        let gossip = |pfail| Gossip {
+            id: id('c'),
+            host: "127.0.0.1".to_string(),
+            port: 30003,
+            cport: 40003,
+            role: NodeRole::Master,
+            pfail,
+            fail: false,
+        };
+
+        // Reports only count once this node sees the failure too
+        let ping = message(&cluster, 'b', Body::Ping(vec![gossip(true)]));
+        cluster.process(ping, "127.0.0.1");
+        assert!(!cluster.nodes[&id('c')].fail);
+
+        cluster.nodes.get_mut(&id('c')).unwrap().pfail = true;
+        cluster.mark_failing(&id('c'));
+        assert!(cluster.nodes[&id('c')].fail);
+        assert!(!cluster.is_ok());
+        assert!(sent(&mut cluster, 'd').iter().any(|message| message.body == Body::Fail(id('c'))));
+
+        // A master still serving its slots is only cleared after a while
+        let pong = message(&cluster, 'c', Body::Pong(vec![]));
+        cluster.process(pong, "127.0.0.1");
+        assert!(cluster.nodes[&id('c')].fail);
+
+        cluster.nodes.get_mut(&id('c')).unwrap().fail_time = Some(Instant::now() - DEFAULT_NODE_TIMEOUT * 3);
+        let pong = message(&cluster, 'c', Body::Pong(vec![gossip(false)]));
+        cluster.process(pong, "127.0.0.1");
+        assert!(!cluster.nodes[&id('c')].fail);
+        assert!(cluster.is_ok());
+    }
+
+    #[test]
+    fn votes_once_per_epoch_for_failed_masters() {
+        let mut cluster = cluster_of_four('a');
+        let request = |cluster: &Cluster, epoch| {
+            let mut request = message(cluster, 'd', Body::AuthRequest { forced: false });
+            request.header.current_epoch = epoch;
+            request
+        };
+
+        cluster.process(request(&cluster, 4), "127.0.0.1");
+        assert!(!sent(&mut cluster, 'd').iter().any(|message| message.body == Body::AuthAck));
+
+        cluster.nodes.get_mut(&id('c')).unwrap().fail = true;
+        cluster.process(request(&cluster, 5), "127.0.0.1");
+        assert_eq!(cluster.last_vote_epoch, 5);
+        assert!(sent(&mut cluster, 'd').iter().any(|message| message.body == Body::AuthAck));
+
+        // Not twice for the same master
+        cluster.process(request(&cluster, 6), "127.0.0.1");
+        assert_eq!(cluster.last_vote_epoch, 5);
+        assert!(!sent(&mut cluster, 'd').iter().any(|message| message.body == Body::AuthAck));
+    }
+
+    #[test]
+    fn follows_newer_slot_claims() {
+        let mut cluster = cluster_of_four('c');
+
+        // An older claim on served slots is ignored
+        let mut pong = message(&cluster, 'b', Body::Pong(vec![]));
+        pong.header.slots = vec![(10923, 10923)];
+        cluster.process(pong, "127.0.0.1");
+        assert_eq!(cluster.owner(10923).unwrap().id, id('c'));
+
+        // The replica replaced this master, which now replicates it
+        let mut pong = message(&cluster, 'd', Body::Pong(vec![]));
+        pong.header.master_id = None;
+        pong.header.config_epoch = 4;
+        pong.header.current_epoch = 4;
+        pong.header.slots = vec![(10923, 16383)];
+        cluster.process(pong, "127.0.0.1");
+
+        assert_eq!(cluster.owner(16383).unwrap().id, id('d'));
+        assert_eq!(cluster.myself().role, NodeRole::Replica);
+        assert_eq!(cluster.master_addr(), Some(("127.0.0.1".to_string(), 30004)));
+        assert_eq!(cluster.current_epoch, 4);
+    }
+
+    #[test]
+    fn replica_replaces_failed_master() {
+        let mut cluster = cluster_of_four('d');
+        assert_eq!(cluster.failover(FailoverMode::Default), Ok(()));
+        cluster.manual_failover = None;
+
+        cluster.nodes.get_mut(&id('c')).unwrap().fail = true;
+        cluster.cron(0);
+        assert!(cluster.election.as_ref().is_some_and(|election| election.epoch == 0));
+
+        cluster.election.as_mut().unwrap().start = Instant::now();
+        cluster.cron(0);
+        assert_eq!(cluster.current_epoch, 4);
+        assert!(sent(&mut cluster, 'a').iter().any(|message| message.body == Body::AuthRequest { forced: false }));
+
+        for voter in ['a', 'b'] {
+            let ack = message(&cluster, voter, Body::AuthAck);
+            cluster.process(ack, "127.0.0.1");
+        }
+        cluster.cron(0);
+
+        assert_eq!(cluster.myself().role, NodeRole::Master);
+        assert_eq!(cluster.myself().config_epoch, 4);
+        assert_eq!(cluster.slot_ranges(&id('d')), vec![(10923, 16383)]);
+        assert!(cluster.is_ok());
+        assert_eq!(
+            cluster.failover(FailoverMode::Force),
+            Err(Error::custom("ERR You should send CLUSTER FAILOVER to a replica"))
+        );
+    }
+
+    #[test]
+    fn redirects_keys_of_migrating_slots() {
+        let mut cluster = cluster_of_four('a');
+        let mut store = Store::default();
+        let keys = |keys: &[&str]| keys.iter().map(|key| key.to_string()).collect::<Vec<_>>();
+
+        store.insert("{b}1".to_string(), StoreValue::new(Value::String("1".to_string()), None));
+
+        assert_eq!(
+            cluster.set_slot_migrating(12182, &id('b')),
+            Err(Error::custom("ERR I'm not the owner of hash slot 12182"))
+        );
+        assert_eq!(
+            cluster.set_slot_migrating(3300, &id('d')),
+            Err(Error::custom("ERR Target node is not a master"))
+        );
+        assert_eq!(cluster.set_slot_migrating(3300, &id('b')), Ok(()));
+
+        assert_eq!(cluster.route("get", &keys(&["{b}1"]), &store, false), Ok(()));
+        assert_eq!(
+            cluster.route("get", &keys(&["{b}2"]), &store, false),
+            Err(Error::custom("ASK 3300 127.0.0.1:30002"))
+        );
+        assert_eq!(
+            cluster.route("mget", &keys(&["{b}1", "{b}2"]), &store, false),
+            Err(Error::custom("TRYAGAIN Multiple keys request during rehashing of slot"))
+        );
+        assert_eq!(cluster.route("migrate", &keys(&["{b}2"]), &store, false), Ok(()));
+        assert!(Cluster::parse(&cluster.config()).unwrap().config().contains(&format!(" [3300->-{}]", id('b'))));
+
+        assert_eq!(
+            cluster.set_slot_node(3300, &id('b'), 1),
+            Err(Error::custom(
+                "ERR Can't assign hashslot 3300 to a different node while I still hold keys for this hash slot."
+            ))
+        );
+        assert_eq!(cluster.set_slot_node(3300, &id('b'), 0), Ok(()));
+        assert_eq!(cluster.owner(3300).unwrap().id, id('b'));
+        assert!(!cluster.config().contains('['));
+    }
+
+    #[test]
+    fn imports_slots() {
+        let mut cluster = cluster_of_four('b');
+        let store = Store::default();
+        let keys = |keys: &[&str]| keys.iter().map(|key| key.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            cluster.set_slot_importing(5461, &id('a')),
+            Err(Error::custom("ERR I'm already the owner of hash slot 5461"))
+        );
+        assert_eq!(cluster.set_slot_importing(3300, &id('a')), Ok(()));
+
+        assert_eq!(
+            cluster.route("get", &keys(&["b"]), &store, false),
+            Err(Error::custom("MOVED 3300 127.0.0.1:30001"))
+        );
+        assert_eq!(cluster.route("get", &keys(&["b"]), &store, true), Ok(()));
+        assert_eq!(
+            cluster.route("mget", &keys(&["b", "{b}1"]), &store, true),
+            Err(Error::custom("TRYAGAIN Multiple keys request during rehashing of slot"))
+        );
+
+        // The former owner still claims the slot until it learns the new epoch
+        let pong = message(&cluster, 'a', Body::Pong(vec![]));
+        cluster.process(pong, "127.0.0.1");
+        assert_eq!(cluster.owner(3300).unwrap().id, id('a'));
+
+        assert_eq!(cluster.set_slot_node(3300, &id('b'), 0), Ok(()));
+        assert_eq!(cluster.owner(3300).unwrap().id, id('b'));
+        assert_eq!(cluster.myself().config_epoch, 4);
+        assert_eq!(cluster.route("get", &keys(&["b"]), &store, false), Ok(()));
+
+        let mut pong = message(&cluster, 'a', Body::Pong(vec![]));
+        pong.header.slots = vec![(0, 5460)];
+        cluster.process(pong, "127.0.0.1");
+        assert_eq!(cluster.owner(3300).unwrap().id, id('b'));
+    }
+
+    #[test]
+    fn meets_nodes() {
+        let mut cluster = Cluster::new(id('a'), "127.0.0.1".to_string(), 30001);
+        let other = Cluster::new(id('b'), "127.0.0.1".to_string(), 30002);
+
+        assert_eq!(
+            cluster.meet("nohost", 30002, 40002),
+            Err(Error::custom("ERR Invalid node address specified: nohost:30002"))
+        );
+        assert_eq!(cluster.meet("127.0.0.1", 30002, 40002), Ok(()));
+        assert!(cluster.describe().contains("handshake"));
+
+        let pong = Message {
+            header: other.header(),
+            body: Body::Pong(vec![]),
+        };
+        cluster.process(pong, "127.0.0.1");
+
+        assert!(cluster.nodes[&id('b')].pong_received.is_some());
+        assert_eq!(cluster.nodes.len(), 2);
+        assert_eq!(cluster.forget(&id('b')), Ok(()));
+        assert_eq!(cluster.forget(&id('a')), Err(Error::custom("ERR I tried hard but I can't forget myself...")));
+    }
+}
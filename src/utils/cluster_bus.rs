@@ -0,0 +1,399 @@
+//! Cluster bus, the nodes of a cluster talking to each other on their client
+//! port + 10000
+//!
+//! Messages are RESP arrays of bulk strings starting with a header describing
+//! the sender: its epochs, its master if it is a replica and the slots it
+//! serves. `PING`, `PONG` and `MEET` carry gossip about a few other nodes, so
+//! every node eventually learns about the others and which of them look down.
+//!
+//! Each node keeps one outgoing connection to every other node, messages are
+//! only written on it, replies come back on the connection of the other node.
+
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use bytes::{Buf, BytesMut};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::resp::types::RespType;
+
+use super::aof;
+use super::cluster::NodeRole;
+use super::config::Role;
+use super::context::Context;
+use super::replication;
+use super::shared_context::{lock, SharedContext};
+
+const CRON_PERIOD: Duration = Duration::from_millis(100);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// What a node says about itself in every message
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub sender: String,
+    pub current_epoch: u64,
+    /// Epoch of the slots claimed, the one of its master for a replica
+    pub config_epoch: u64,
+    pub port: u16,
+    pub cport: u16,
+    pub master_id: Option<String>,
+    pub offset: u64,
+    /// Slots served, by its master for a replica
+    pub slots: Vec<(u16, u16)>,
+}
+
+/// What a node says about another one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gossip {
+    pub id: String,
+    pub host: String,
+    pub port: u16,
+    pub cport: u16,
+    pub role: NodeRole,
+    pub pfail: bool,
+    pub fail: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Body {
+    Ping(Vec<Gossip>),
+    Pong(Vec<Gossip>),
+    /// Ping of a node asking the receiver to join its cluster
+    Meet(Vec<Gossip>),
+    /// The node with this id failed, as agreed by a majority of masters
+    Fail(String),
+    /// Replica asking masters for their vote to replace its failed master
+    AuthRequest { forced: bool },
+    AuthAck,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub header: Header,
+    pub body: Body,
+}
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        let header = &self.header;
+        let kind = match self.body {
+            Body::Ping(_) => "PING",
+            Body::Pong(_) => "PONG",
+            Body::Meet(_) => "MEET",
+            Body::Fail(_) => "FAIL",
+            Body::AuthRequest { .. } => "AUTH-REQUEST",
+            Body::AuthAck => "AUTH-ACK",
+        };
+        let slots = header
+            .slots
+            .iter()
+            .map(|(start, end)| format!("{start}-{end}"))
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut fields = vec![
+            kind.to_string(),
+            header.sender.clone(),
+            header.current_epoch.to_string(),
+            header.config_epoch.to_string(),
+            header.port.to_string(),
+            header.cport.to_string(),
+            header.master_id.clone().unwrap_or_else(|| "-".to_string()),
+            header.offset.to_string(),
+            if slots.is_empty() { "-".to_string() } else { slots },
+        ];
+
+        match &self.body {
+            Body::Ping(gossip) | Body::Pong(gossip) | Body::Meet(gossip) => {
+                for node in gossip {
+                    let mut flags = node.role.to_string();
+
+                    if node.pfail {
+                        flags.push_str(",pfail");
+                    }
+                    if node.fail {
+                        flags.push_str(",fail");
+                    }
+
+                    fields.extend([
+                        node.id.clone(),
+                        node.host.clone(),
+                        node.port.to_string(),
+                        node.cport.to_string(),
+                        flags,
+                    ]);
+                }
+            }
+            Body::Fail(id) => fields.push(id.clone()),
+            Body::AuthRequest { forced } => fields.push(if *forced { "1" } else { "0" }.to_string()),
+            Body::AuthAck => {}
+        }
+
+        aof::encode(&fields).into_bytes()
+    }
+
+    pub fn decode(frame: RespType) -> Result<Self> {
+        let RespType::Array { values, .. } = frame else {
+            return Err(anyhow!("expected an array"));
+        };
+        let fields = values
+            .into_iter()
+            .map(|value| match value {
+                RespType::BulkString { value, .. } => Ok(value),
+                _ => Err(anyhow!("expected bulk strings")),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if fields.len() < 9 {
+            return Err(anyhow!("truncated header"));
+        }
+
+        let mut slots = Vec::new();
+
+        for range in fields[8].split(',').filter(|range| *range != "-") {
+            let (start, end) = range.split_once('-').ok_or_else(|| anyhow!("invalid slot range '{range}'"))?;
+
+            slots.push((start.parse()?, end.parse()?));
+        }
+
+        let header = Header {
+            sender: fields[1].clone(),
+            current_epoch: fields[2].parse()?,
+            config_epoch: fields[3].parse()?,
+            port: fields[4].parse()?,
+            cport: fields[5].parse()?,
+            master_id: (fields[6] != "-").then(|| fields[6].clone()),
+            offset: fields[7].parse()?,
+            slots,
+        };
+        let rest = &fields[9..];
+
+        let body = match fields[0].as_str() {
+            "PING" | "PONG" | "MEET" => {
+                if rest.len() % 5 != 0 {
+                    return Err(anyhow!("truncated gossip"));
+                }
+
+                let gossip = rest
+                    .chunks(5)
+                    .map(|node| {
+                        let flags: Vec<&str> = node[4].split(',').collect();
+
+                        Ok(Gossip {
+                            id: node[0].clone(),
+                            host: node[1].clone(),
+                            port: node[2].parse()?,
+                            cport: node[3].parse()?,
+                            role: if flags.contains(&"slave") { NodeRole::Replica } else { NodeRole::Master },
+                            pfail: flags.contains(&"pfail"),
+                            fail: flags.contains(&"fail"),
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+
+                match fields[0].as_str() {
+                    "PING" => Body::Ping(gossip),
+                    "PONG" => Body::Pong(gossip),
+                    _ => Body::Meet(gossip),
+                }
+            }
+            "FAIL" if rest.len() == 1 => Body::Fail(rest[0].clone()),
+            "AUTH-REQUEST" if rest.len() == 1 => Body::AuthRequest { forced: rest[0] == "1" },
+            "AUTH-ACK" => Body::AuthAck,
+            kind => return Err(anyhow!("unknown message '{kind}'")),
+        };
+
+        Ok(Self { header, body })
+    }
+}
+
+/// Serves the cluster bus on `listener` and runs the periodic tasks of the
+/// cluster: pinging nodes, detecting failures, failing over and following
+/// the master this node replicates
+pub async fn run(context: SharedContext, listener: TcpListener) {
+    tokio::spawn(cron(context.clone()));
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                let context = context.clone();
+
+                tokio::spawn(async move {
+                    if let Err(err) = receive(stream, addr.ip().to_string(), &context).await {
+                        eprintln!("err: cluster bus {addr}: {err}");
+                    }
+                });
+            }
+            Err(err) => eprintln!("err: cluster bus: {err}"),
+        }
+    }
+}
+
+/// Processes the messages of the node connected from `ip`
+async fn receive(mut stream: TcpStream, ip: String, context: &SharedContext) -> Result<()> {
+    let mut buffer = BytesMut::with_capacity(4096);
+
+    loop {
+        while let Some((frame, used)) = RespType::parse(&buffer).map_err(|err| anyhow!(err.to_string()))? {
+            buffer.advance(used);
+
+            let message = Message::decode(frame)?;
+
+            if let Some(cluster) = lock(context).cluster.as_mut() {
+                cluster.process(message, &ip);
+            }
+        }
+
+        if stream.read_buf(&mut buffer).await? == 0 {
+            return Ok(());
+        }
+    }
+}
+
+/// Writes the messages queued for the node at `host:cport`, until the
+/// connection breaks and the link has to be created again
+async fn send(host: String, cport: u16, mut messages: UnboundedReceiver<Vec<u8>>) {
+    let Ok(Ok(mut stream)) = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect((host.as_str(), cport))).await
+    else {
+        return;
+    };
+    let mut discarded = [0; 512];
+
+    loop {
+        tokio::select! {
+            message = messages.recv() => {
+                let Some(message) = message else {
+                    return;
+                };
+
+                if stream.write_all(&message).await.is_err() {
+                    return;
+                }
+            }
+            // Nothing is expected back, reading only notices the peer going away
+            read = stream.read(&mut discarded) => {
+                if !matches!(read, Ok(n) if n > 0) {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+async fn cron(context: SharedContext) {
+    let mut interval = tokio::time::interval(CRON_PERIOD);
+
+    loop {
+        interval.tick().await;
+
+        let (links, master) = {
+            let mut ctx = lock(&context);
+            let node_timeout = Duration::from_millis(ctx.config.cluster_node_timeout);
+            let offset = ctx.config.master_repl_offset;
+            let Some(cluster) = ctx.cluster.as_mut() else {
+                return;
+            };
+
+            cluster.node_timeout = node_timeout;
+            cluster.cron(offset);
+
+            let links = cluster.take_new_links();
+            let master = cluster.master_addr();
+            let dirty = std::mem::take(&mut cluster.dirty);
+
+            if dirty {
+                if let Err(err) = save_config(&ctx) {
+                    eprintln!("err: saving cluster config: {err}");
+                }
+            }
+
+            // Follow the role the cluster agreed on for this node
+            let master = match master {
+                Some((host, port)) => {
+                    let following = ctx.config.role == Role::Slave
+                        && ctx.config.replication.as_ref().is_some_and(|addr| addr.host == host && addr.port == port);
+
+                    (!following).then_some((host, port))
+                }
+                None => {
+                    if ctx.config.role == Role::Slave && ctx.cluster.as_ref().is_some_and(|c| c.myself().role == NodeRole::Master) {
+                        replication::promote(&mut ctx);
+                    }
+
+                    None
+                }
+            };
+
+            (links, master)
+        };
+
+        for (host, cport, messages) in links {
+            tokio::spawn(send(host, cport, messages));
+        }
+
+        if let Some((host, port)) = master {
+            eprintln!("Cluster: replicating {host}:{port}");
+            replication::replicate_from(&context, host, port);
+        }
+    }
+}
+
+/// Writes the nodes of the cluster to the `cluster-config-file`, replacing it
+/// only once fully written
+pub fn save_config(ctx: &Context) -> std::io::Result<()> {
+    let Some(cluster) = &ctx.cluster else {
+        return Ok(());
+    };
+    let path = Path::new(&ctx.config.dir).join(&ctx.config.cluster_config_file);
+    let temp = path.with_extension("tmp");
+
+    std::fs::write(&temp, cluster.config())?;
+    std::fs::rename(&temp, &path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_messages() {
+        let header = Header {
+            sender: "a".repeat(40),
+            current_epoch: 3,
+            config_epoch: 2,
+            port: 30001,
+            cport: 40001,
+            master_id: None,
+            offset: 42,
+            slots: vec![(0, 100), (200, 200)],
+        };
+        let gossip = Gossip {
+            id: "b".repeat(40),
+            host: "127.0.0.1".to_string(),
+            port: 30002,
+            cport: 40002,
+            role: NodeRole::Replica,
+            pfail: true,
+            fail: false,
+        };
+
+        for body in [
+            Body::Ping(vec![gossip.clone()]),
+            Body::Pong(vec![]),
+            Body::Meet(vec![gossip]),
+            Body::Fail("b".repeat(40)),
+            Body::AuthRequest { forced: true },
+            Body::AuthAck,
+        ] {
+            let message = Message {
+                header: header.clone(),
+                body,
+            };
+            let (frame, _) = RespType::parse(&message.encode()).unwrap().unwrap();
+
+            assert_eq!(Message::decode(frame).unwrap(), message);
+        }
+    }
+}
@@ -1,19 +1,94 @@
+use std::fmt;
+
 use anyhow::Result;
 
-#[derive(Debug)]
+use crate::models::DuplicatePolicy;
+
+use super::notifications::KeyspaceEvents;
+use super::replication::generate_replid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Role {
     Master,
     Slave,
 }
 
-impl ToString for Role {
-    fn to_string(&self) -> String {
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Master => write!(f, "master"),
+            Role::Slave => write!(f, "slave"),
+        }
+    }
+}
+
+/// When the append only file is flushed to disk, `appendfsync`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppendFsync {
+    /// After every write command
+    Always,
+    /// At most once per second
+    Everysec,
+    /// Left to the operating system
+    No,
+}
+
+impl std::str::FromStr for AppendFsync {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "always" => Ok(AppendFsync::Always),
+            "everysec" => Ok(AppendFsync::Everysec),
+            "no" => Ok(AppendFsync::No),
+            _ => Err("argument(s) must be one of the following: always, everysec, no".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for AppendFsync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppendFsync::Always => write!(f, "always"),
+            AppendFsync::Everysec => write!(f, "everysec"),
+            AppendFsync::No => write!(f, "no"),
+        }
+    }
+}
+
+/// How replicas load the snapshot of a full resynchronization, `repl-diskless-load`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisklessLoad {
+    /// Saved to disk first, then loaded from there
+    Disabled,
+    /// Loaded straight from the socket when the dataset is empty
+    OnEmptyDb,
+    /// Loaded straight from the socket, the current dataset being kept
+    /// until the new one is complete
+    Swapdb,
+}
+
+impl std::str::FromStr for DisklessLoad {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "disabled" => Ok(DisklessLoad::Disabled),
+            "on-empty-db" => Ok(DisklessLoad::OnEmptyDb),
+            "swapdb" => Ok(DisklessLoad::Swapdb),
+            _ => Err("argument(s) must be one of the following: disabled, on-empty-db, swapdb".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for DisklessLoad {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Role::Master => "master".to_string(),
-            Role::Slave => "slave".to_string(),
+            DisklessLoad::Disabled => write!(f, "disabled"),
+            DisklessLoad::OnEmptyDb => write!(f, "on-empty-db"),
+            DisklessLoad::Swapdb => write!(f, "swapdb"),
         }
     }
-    
 }
 
 #[derive(Debug)]
@@ -22,7 +97,6 @@ pub struct Replication {
     pub(crate) port: u16,
 }
 
-
 #[derive(Debug)]
 pub struct Config {
     pub(crate) port: u16,
@@ -30,6 +104,75 @@ pub struct Config {
     pub(crate) replication: Option<Replication>,
     pub(crate) master_replid: String,
     pub(crate) master_repl_offset: u64,
+    /// Previous replication history, replicas of the former master can
+    /// still resume it up to `second_repl_offset`
+    pub(crate) master_replid2: String,
+    pub(crate) second_repl_offset: Option<u64>,
+    /// Bytes of the replication stream kept for replicas to resume from
+    pub(crate) repl_backlog_size: u64,
+    /// Whether replicas refuse writes from their clients
+    pub(crate) replica_read_only: bool,
+    /// Whether replicas keep answering with possibly outdated data while
+    /// the link to their master is down
+    pub(crate) replica_serve_stale_data: bool,
+    /// Whether full resynchronizations stream the snapshot straight to the
+    /// replica sockets instead of saving it to disk first
+    pub(crate) repl_diskless_sync: bool,
+    /// Seconds a diskless sync waits for more replicas to share its snapshot
+    pub(crate) repl_diskless_sync_delay: u64,
+    pub(crate) repl_diskless_load: DisklessLoad,
+    /// Preference of sentinels when promoting a replica, lowest first and
+    /// `0` never
+    pub(crate) replica_priority: u64,
+    /// Whether the server runs as a node of a cluster
+    pub(crate) cluster_enabled: bool,
+    /// Nodes of the cluster and their slots, relative to `dir`
+    pub(crate) cluster_config_file: String,
+    /// Milliseconds a node may not answer pings before being flagged failing
+    pub(crate) cluster_node_timeout: u64,
+    /// Whether the server runs as a sentinel instead of a data node
+    pub(crate) sentinel: bool,
+    /// `SENTINEL` subcommands applied at startup, from `--sentinel-*` flags
+    pub(crate) sentinel_directives: Vec<Vec<String>>,
+    /// Error rate of filters created implicitly by `BF.ADD`/`BF.MADD`
+    pub(crate) bf_error_rate: f64,
+    /// Capacity of filters created implicitly by `BF.ADD`/`BF.MADD`
+    pub(crate) bf_initial_size: u64,
+    /// Growth factor of new layers in scalable bloom filters
+    pub(crate) bf_expansion_factor: u32,
+    /// Default retention of new time series in milliseconds, `0` keeps everything
+    pub(crate) ts_retention_policy: u64,
+    /// Default size in bytes of the compressed chunks of new time series
+    pub(crate) ts_chunk_size_bytes: usize,
+    /// Default duplicate policy of new time series
+    pub(crate) ts_duplicate_policy: DuplicatePolicy,
+    /// Keyspace event classes published through pub/sub
+    pub(crate) notify_keyspace_events: KeyspaceEvents,
+    /// Working directory for persistence files
+    pub(crate) dir: String,
+    /// Name of the RDB snapshot inside `dir`
+    pub(crate) dbfilename: String,
+    /// `save <seconds> <changes>` points, a snapshot is taken in the
+    /// background once `changes` writes happened in `seconds`
+    pub(crate) save: Vec<(u64, u64)>,
+    /// Whether write commands are logged to the append only file
+    pub(crate) appendonly: bool,
+    pub(crate) appendfilename: String,
+    /// Directory inside `dir` holding the base and incremental files and
+    /// their manifest
+    pub(crate) appenddirname: String,
+    pub(crate) appendfsync: AppendFsync,
+    /// Load a truncated append only file up to its last complete command
+    /// instead of refusing to start
+    pub(crate) aof_load_truncated: bool,
+    /// Growth over the size after the last rewrite, in percent, triggering
+    /// an automatic rewrite, `0` disables it
+    pub(crate) auto_aof_rewrite_percentage: u64,
+    /// Size in bytes below which the file is never rewritten automatically
+    pub(crate) auto_aof_rewrite_min_size: u64,
+    /// Milliseconds a script runs before other clients get `BUSY` replies
+    /// and it can be stopped with `SCRIPT KILL`
+    pub(crate) busy_reply_threshold: u64,
 }
 
 impl Default for Config {
@@ -38,16 +181,211 @@ impl Default for Config {
             port: 6379,
             role: Role::Master,
             replication: None,
-            master_replid: "8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb".to_string(),
+            master_replid: generate_replid(),
             master_repl_offset: 0,
+            master_replid2: NO_REPLID.to_string(),
+            second_repl_offset: None,
+            repl_backlog_size: 1024 * 1024,
+            replica_read_only: true,
+            replica_serve_stale_data: true,
+            repl_diskless_sync: true,
+            repl_diskless_sync_delay: 5,
+            repl_diskless_load: DisklessLoad::Disabled,
+            replica_priority: 100,
+            cluster_enabled: false,
+            cluster_config_file: "nodes.conf".to_string(),
+            cluster_node_timeout: 15000,
+            sentinel: false,
+            sentinel_directives: Vec::new(),
+            bf_error_rate: 0.01,
+            bf_initial_size: 100,
+            bf_expansion_factor: 2,
+            ts_retention_policy: 0,
+            ts_chunk_size_bytes: 4096,
+            ts_duplicate_policy: DuplicatePolicy::Block,
+            notify_keyspace_events: KeyspaceEvents::default(),
+            dir: ".".to_string(),
+            dbfilename: "dump.rdb".to_string(),
+            save: vec![(3600, 1), (300, 100), (60, 10000)],
+            appendonly: false,
+            appendfilename: "appendonly.aof".to_string(),
+            appenddirname: "appendonlydir".to_string(),
+            appendfsync: AppendFsync::Everysec,
+            aof_load_truncated: true,
+            auto_aof_rewrite_percentage: 100,
+            auto_aof_rewrite_min_size: 64 * 1024 * 1024,
+            busy_reply_threshold: 5000,
         }
     }
 }
 
+/// Replication id of no history
+const NO_REPLID: &str = "0000000000000000000000000000000000000000";
+
+impl Config {
+    /// Starts the replication history `replid`, the current one stays valid
+    /// up to the current offset as the secondary id
+    pub fn shift_replid(&mut self, replid: String) {
+        self.master_replid2 = std::mem::replace(&mut self.master_replid, replid);
+        self.second_repl_offset = Some(self.master_repl_offset + 1);
+    }
+
+    pub fn clear_replid2(&mut self) {
+        self.master_replid2 = NO_REPLID.to_string();
+        self.second_repl_offset = None;
+    }
+
+    /// Parameters exposed through `CONFIG GET`
+    pub fn parameters(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("port", self.port.to_string()),
+            ("notify-keyspace-events", self.notify_keyspace_events.to_string()),
+            ("dir", self.dir.to_string()),
+            ("dbfilename", self.dbfilename.to_string()),
+            ("save", format_save(&self.save)),
+            ("appendonly", yes_no(self.appendonly)),
+            ("appendfilename", self.appendfilename.to_string()),
+            ("appenddirname", self.appenddirname.to_string()),
+            ("appendfsync", self.appendfsync.to_string()),
+            ("aof-load-truncated", yes_no(self.aof_load_truncated)),
+            ("auto-aof-rewrite-percentage", self.auto_aof_rewrite_percentage.to_string()),
+            ("auto-aof-rewrite-min-size", self.auto_aof_rewrite_min_size.to_string()),
+            ("repl-backlog-size", self.repl_backlog_size.to_string()),
+            ("replica-read-only", yes_no(self.replica_read_only)),
+            ("replica-serve-stale-data", yes_no(self.replica_serve_stale_data)),
+            ("repl-diskless-sync", yes_no(self.repl_diskless_sync)),
+            ("repl-diskless-sync-delay", self.repl_diskless_sync_delay.to_string()),
+            ("repl-diskless-load", self.repl_diskless_load.to_string()),
+            ("replica-priority", self.replica_priority.to_string()),
+            ("cluster-enabled", yes_no(self.cluster_enabled)),
+            ("cluster-config-file", self.cluster_config_file.to_string()),
+            ("cluster-node-timeout", self.cluster_node_timeout.to_string()),
+            ("busy-reply-threshold", self.busy_reply_threshold.to_string()),
+            ("lua-time-limit", self.busy_reply_threshold.to_string()),
+        ]
+    }
+
+    /// Changes a parameter at runtime through `CONFIG SET`
+    pub fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        match name {
+            "notify-keyspace-events" => {
+                self.notify_keyspace_events = value.parse().map_err(|err: String| anyhow::anyhow!(err))?;
+            }
+            "appendfsync" => {
+                self.appendfsync = value.parse().map_err(|err: String| anyhow::anyhow!(err))?;
+            }
+            "dbfilename" => self.dbfilename = parse_dbfilename(value)?,
+            "save" => self.save = parse_save(value)?,
+            "appendonly" => self.appendonly = parse_yes_no(name, value)?,
+            "aof-load-truncated" => self.aof_load_truncated = parse_yes_no(name, value)?,
+            "auto-aof-rewrite-percentage" => {
+                self.auto_aof_rewrite_percentage = parse_arg(name, Some(value.to_string()))?;
+            }
+            "auto-aof-rewrite-min-size" => self.auto_aof_rewrite_min_size = parse_memory(name, value)?,
+            "repl-backlog-size" => self.repl_backlog_size = parse_backlog_size(name, value)?,
+            "replica-read-only" => self.replica_read_only = parse_yes_no(name, value)?,
+            "replica-serve-stale-data" => self.replica_serve_stale_data = parse_yes_no(name, value)?,
+            "repl-diskless-sync" => self.repl_diskless_sync = parse_yes_no(name, value)?,
+            "repl-diskless-sync-delay" => {
+                self.repl_diskless_sync_delay = parse_arg(name, Some(value.to_string()))?;
+            }
+            "repl-diskless-load" => {
+                self.repl_diskless_load = value.parse().map_err(|err: String| anyhow::anyhow!(err))?;
+            }
+            "replica-priority" => self.replica_priority = parse_arg(name, Some(value.to_string()))?,
+            "cluster-node-timeout" => self.cluster_node_timeout = parse_arg(name, Some(value.to_string()))?,
+            "busy-reply-threshold" | "lua-time-limit" => {
+                self.busy_reply_threshold = parse_arg(name, Some(value.to_string()))?;
+            }
+            _ => return Err(anyhow::anyhow!("Unknown option")),
+        }
+
+        Ok(())
+    }
+}
+
+fn yes_no(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_string()
+}
+
+fn parse_yes_no(name: &str, value: &str) -> Result<bool> {
+    match value.to_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(anyhow::anyhow!("Invalid value for {name}, argument must be 'yes' or 'no'")),
+    }
+}
+
+fn format_save(points: &[(u64, u64)]) -> String {
+    points
+        .iter()
+        .map(|(seconds, changes)| format!("{seconds} {changes}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Parses `<seconds> <changes>` pairs, an empty value disables snapshots
+fn parse_save(value: &str) -> Result<Vec<(u64, u64)>> {
+    let numbers = value
+        .split_whitespace()
+        .map(|number| number.parse::<u64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| anyhow::anyhow!("Invalid save parameters"))?;
+
+    if numbers.len() % 2 == 1 {
+        return Err(anyhow::anyhow!("Invalid save parameters"));
+    }
+
+    Ok(numbers.chunks(2).map(|pair| (pair[0], pair[1])).collect())
+}
+
+fn parse_dbfilename(value: &str) -> Result<String> {
+    if value.contains('/') || value.contains('\\') {
+        return Err(anyhow::anyhow!("dbfilename can't be a path, just a filename"));
+    }
+
+    Ok(value.to_string())
+}
+
+/// Parses a size like `64mb`, units are powers of 1024 as in redis.conf
+fn parse_memory(name: &str, value: &str) -> Result<u64> {
+    let value = value.to_lowercase();
+    let digits = value.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let unit = match &value[digits.len()..] {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return Err(anyhow::anyhow!("Invalid value for {name}")),
+    };
+
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|size| size.checked_mul(unit))
+        .ok_or_else(|| anyhow::anyhow!("Invalid value for {name}"))
+}
+
+fn parse_backlog_size(name: &str, value: &str) -> Result<u64> {
+    match parse_memory(name, value)? {
+        0 => Err(anyhow::anyhow!("argument must be between 1 and 9223372036854775807 inclusive")),
+        size => Ok(size),
+    }
+}
+
+fn parse_arg<T: std::str::FromStr>(name: &str, value: Option<String>) -> Result<T> {
+    value
+        .and_then(|v| v.parse::<T>().ok())
+        .ok_or_else(|| anyhow::anyhow!("Invalid value for {name}"))
+}
+
 pub fn load() -> Result<Config> {
     let mut args = std::env::args().peekable();
     let mut config = Config::default();
-
+    let mut port = None;
 
     if args.len() == 0 {
         return Ok(config);
@@ -56,45 +394,158 @@ pub fn load() -> Result<Config> {
     while let Some(arg) = args.next() {
         match arg.as_str() {
             "--port" => {
-               let port = args.next().map(|p| p.parse::<u16>().ok()).flatten();
+                port = args.next().and_then(|p| p.parse::<u16>().ok());
 
-               if port.is_none() {
-                   return Err(anyhow::anyhow!("Port is required"));
-               }
-
-               config.port = port.unwrap();
+                if port.is_none() {
+                    return Err(anyhow::anyhow!("Port is required"));
+                }
             }
             "--replicaof" => {
                 let replicaof = args.peek();
                 config.role = Role::Slave;
 
+                if let Some(replicaof) = replicaof {
+                    let replicaof = replicaof.to_string();
 
-                 match replicaof.clone() {
-                    Some(replicaof) => {
-                        let replicaof = replicaof.to_string();
+                    if replicaof.starts_with("--") {
+                        args.next();
+                    } else {
+                        let (host, port) = replicaof
+                            .split_once(' ')
+                            .expect("Invalid replicaof parameter");
 
+                        config.replication = Some(Replication {
+                            host: host.to_string(),
+                            port: port.parse::<u16>().expect("Invalid port"),
+                        });
+                    }
+                };
+            }
+            "--bf-error-rate" => {
+                let rate: f64 = parse_arg(&arg, args.next())?;
 
-                        if replicaof.starts_with("--") {
-                            args.next(); 
-                        } else {
-                            let (host, port) = replicaof.split_once(" ").expect("Invalid replicaof parameter");
+                if rate <= 0.0 || rate >= 1.0 {
+                    return Err(anyhow::anyhow!("bf-error-rate must be between 0 and 1"));
+                }
 
-                            config.replication = Some(Replication {
-                                host: host.to_string(),
-                                port: port.parse::<u16>().expect("Invalid port"),
+                config.bf_error_rate = rate;
+            }
+            "--bf-initial-size" => {
+                config.bf_initial_size = parse_arg(&arg, args.next())?;
+            }
+            "--bf-expansion-factor" => {
+                config.bf_expansion_factor = parse_arg(&arg, args.next())?;
+            }
+            "--ts-retention-policy" => {
+                config.ts_retention_policy = parse_arg(&arg, args.next())?;
+            }
+            "--ts-chunk-size-bytes" => {
+                config.ts_chunk_size_bytes = parse_arg(&arg, args.next())?;
+            }
+            "--ts-duplicate-policy" => {
+                config.ts_duplicate_policy = parse_arg(&arg, args.next())?;
+            }
+            "--notify-keyspace-events" => {
+                config.notify_keyspace_events = parse_arg(&arg, args.next())?;
+            }
+            "--dir" => {
+                config.dir = parse_arg(&arg, args.next())?;
+            }
+            "--dbfilename" => {
+                config.dbfilename = parse_dbfilename(&args.next().unwrap_or_default())?;
+            }
+            "--save" => {
+                config.save = parse_save(&args.next().unwrap_or_default())?;
+            }
+            "--appendonly" => {
+                config.appendonly = parse_yes_no(&arg, &args.next().unwrap_or_default())?;
+            }
+            "--appendfilename" => {
+                config.appendfilename = parse_arg(&arg, args.next())?;
+            }
+            "--appenddirname" => {
+                config.appenddirname = parse_arg(&arg, args.next())?;
+            }
+            "--auto-aof-rewrite-percentage" => {
+                config.auto_aof_rewrite_percentage = parse_arg(&arg, args.next())?;
+            }
+            "--auto-aof-rewrite-min-size" => {
+                config.auto_aof_rewrite_min_size = parse_memory(&arg, &args.next().unwrap_or_default())?;
+            }
+            "--repl-backlog-size" => {
+                config.repl_backlog_size = parse_backlog_size(&arg, &args.next().unwrap_or_default())?;
+            }
+            "--replica-read-only" => {
+                config.replica_read_only = parse_yes_no(&arg, &args.next().unwrap_or_default())?;
+            }
+            "--replica-serve-stale-data" => {
+                config.replica_serve_stale_data = parse_yes_no(&arg, &args.next().unwrap_or_default())?;
+            }
+            "--repl-diskless-sync" => {
+                config.repl_diskless_sync = parse_yes_no(&arg, &args.next().unwrap_or_default())?;
+            }
+            "--repl-diskless-sync-delay" => {
+                config.repl_diskless_sync_delay = parse_arg(&arg, args.next())?;
+            }
+            "--repl-diskless-load" => {
+                config.repl_diskless_load = parse_arg(&arg, args.next())?;
+            }
+            "--appendfsync" => {
+                config.appendfsync = parse_arg(&arg, args.next())?;
+            }
+            "--aof-load-truncated" => {
+                config.aof_load_truncated = parse_yes_no(&arg, &args.next().unwrap_or_default())?;
+            }
+            "--replica-priority" => {
+                config.replica_priority = parse_arg(&arg, args.next())?;
+            }
+            "--cluster-enabled" => {
+                config.cluster_enabled = parse_yes_no(&arg, &args.next().unwrap_or_default())?;
+            }
+            "--cluster-config-file" => {
+                config.cluster_config_file = parse_arg(&arg, args.next())?;
+            }
+            "--cluster-node-timeout" => {
+                config.cluster_node_timeout = parse_arg(&arg, args.next())?;
+            }
+            "--busy-reply-threshold" | "--lua-time-limit" => {
+                config.busy_reply_threshold = parse_arg(&arg, args.next())?;
+            }
+            "--sentinel" => config.sentinel = true,
+            // `--sentinel-monitor "mymaster 127.0.0.1 6379 2"` and alike,
+            // the `SENTINEL` subcommand and its arguments
+            "--sentinel-monitor" | "--sentinel-down-after-milliseconds" | "--sentinel-failover-timeout"
+            | "--sentinel-parallel-syncs" => {
+                let value: String = parse_arg(&arg, args.next())?;
+                let mut directive = vec![arg["--sentinel-".len()..].to_string()];
 
-                            });
+                match arg.as_str() {
+                    "--sentinel-monitor" => directive.extend(value.split_whitespace().map(str::to_string)),
+                    _ => {
+                        let (name, value) = value
+                            .split_once(' ')
+                            .ok_or_else(|| anyhow::anyhow!("Invalid value for {arg}"))?;
 
-                        }
-                    },
-                    None => {}
-                };
+                        directive = vec![
+                            "set".to_string(),
+                            name.to_string(),
+                            directive.remove(0),
+                            value.trim().to_string(),
+                        ];
+                    }
+                }
+
+                config.sentinel_directives.push(directive);
             }
             _ => {}
-            
         }
     }
 
+    config.port = match (port, config.sentinel) {
+        (Some(port), _) => port,
+        (None, true) => 26379,
+        (None, false) => 6379,
+    };
+
     Ok(config)
 }
-
@@ -1,10 +1,77 @@
-use crate::config;
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use crate::config::{self, Role};
+use crate::resp::types::RespType;
 use crate::store;
 
+use super::acl::Acl;
+use super::aof::{self, Aof};
+use super::cluster::Cluster;
+use super::functions::Functions;
+use super::migrate::MigratePool;
+use super::notifications::KeyspaceEvents;
+use super::pubsub::{Outbound, PubSub};
+use super::rdb::{self, Rdb};
+use super::replication::{self, MasterLinkStatus, Replicas};
+use super::scripting::ScriptCache;
+use super::sentinel::Sentinel;
+use super::tracking::Tracking;
+
+/// Channel RESP2 clients subscribe to when redirecting invalidations
+pub const INVALIDATE_CHANNEL: &str = "__redis__:invalidate";
+
+/// Keys with a ttl one round of the active expiry cycle checks
+const ACTIVE_EXPIRE_CYCLE_KEYS: usize = 20;
+
+/// Time one pass of the active expiry cycle runs at most, a quarter of the
+/// period of the cron
+const ACTIVE_EXPIRE_CYCLE_TIME: Duration = Duration::from_millis(25);
+
+/// What other sessions need to reach a connected client
+#[derive(Debug)]
+pub struct Client {
+    pub outbound: Outbound,
+    pub protocol: u8,
+}
+
 #[derive(Debug)]
 pub struct Context {
     pub store: store::Store,
     pub config: config::Config,
+    pub pubsub: PubSub,
+    pub tracking: Tracking,
+    /// Connected clients by session id
+    pub clients: HashMap<u64, Client>,
+    /// Open when `appendonly` is enabled
+    pub aof: Option<Aof>,
+    pub rdb: Rdb,
+    /// Replicas attached to this server
+    pub replicas: Replicas,
+    /// Replication offset up to which the append only file is fsynced
+    pub aof_fsynced_offset: u64,
+    /// Link to the master, when replicating
+    pub master_link: MasterLinkStatus,
+    /// Random id of this run of the server
+    pub run_id: String,
+    /// Nodes and slots, in cluster mode
+    pub cluster: Option<Cluster>,
+    /// Monitoring state, in sentinel mode
+    pub sentinel: Option<Sentinel>,
+    /// Connections kept open by `MIGRATE`
+    pub migrate_pool: MigratePool,
+    /// Scripts loaded by `EVAL` and `SCRIPT LOAD`
+    pub scripts: ScriptCache,
+    /// Libraries loaded by `FUNCTION LOAD`
+    pub functions: Functions,
+    /// Users and their permissions
+    pub acl: Acl,
+    /// The shared context holding this one, for commands starting tasks of
+    /// their own like `REPLICAOF`
+    pub(crate) shared: Weak<Mutex<Context>>,
 }
 
 impl Context {
@@ -12,16 +79,259 @@ impl Context {
         Self {
             store,
             config,
+            pubsub: PubSub::default(),
+            tracking: Tracking::default(),
+            clients: HashMap::new(),
+            aof: None,
+            rdb: Rdb::default(),
+            replicas: Replicas::default(),
+            aof_fsynced_offset: 0,
+            master_link: MasterLinkStatus::default(),
+            run_id: replication::generate_replid(),
+            cluster: None,
+            sentinel: None,
+            migrate_pool: MigratePool::default(),
+            scripts: ScriptCache::default(),
+            functions: Functions::default(),
+            acl: Acl::default(),
+            shared: Weak::new(),
+        }
+    }
+
+    /// Publishes a keyspace event when its class is enabled by `notify-keyspace-events`
+    pub fn notify(&self, class: KeyspaceEvents, event: &str, key: &str) {
+        let events = self.config.notify_keyspace_events;
+
+        if !events.enabled(class) {
+            return;
+        }
+
+        if events.contains(KeyspaceEvents::KEYSPACE) {
+            self.pubsub.publish(&format!("__keyspace@0__:{key}"), event);
+        }
+
+        if events.contains(KeyspaceEvents::KEYEVENT) {
+            self.pubsub.publish(&format!("__keyevent@0__:{event}"), key);
+        }
+    }
+
+    /// Lazily expires `key`, emitting an `expired` event when it is removed
+    pub fn remove_if_expired(&mut self, key: &str) -> bool {
+        let expired = self.store.remove_if_expired(key);
+
+        if expired {
+            self.notify(KeyspaceEvents::EXPIRED, "expired", key);
+        }
+
+        expired
+    }
+
+    /// One pass of the active expiry cycle, run periodically by the server
+    ///
+    /// Rounds of sampled keys go on while more than a quarter of them had
+    /// expired, as many more probably did too.
+    pub fn active_expire_cycle(&mut self) {
+        let start = Instant::now();
+
+        loop {
+            let (removed, checked) = self.store.remove_expired(ACTIVE_EXPIRE_CYCLE_KEYS);
+
+            for key in &removed {
+                self.notify(KeyspaceEvents::EXPIRED, "expired", key);
+                self.rdb.changes += 1;
+            }
+
+            if removed.len() * 4 <= checked || start.elapsed() >= ACTIVE_EXPIRE_CYCLE_TIME {
+                break;
+            }
+        }
+
+        self.invalidate(None);
+    }
+
+    /// Persists the write commands executed by one request
+    pub fn propagate(&mut self, commands: Vec<Vec<String>>) {
+        if commands.is_empty() {
+            return;
+        }
+
+        self.rdb.changes += commands.len() as u64;
+
+        if let Some(aof) = self.aof.as_mut() {
+            if let Err(err) = aof.append(&commands) {
+                eprintln!("err: writing to {}: {}", aof.path().display(), err);
+            }
+        }
+
+        // Replicas forward the stream of their master as they receive it
+        if self.config.role == Role::Master {
+            self.feed_replicas(&aof::encode_all(&commands));
+        }
+    }
+
+    /// Appends `data` to the replication stream, advancing the replication offset
+    pub fn feed_replicas(&mut self, data: &[u8]) {
+        self.config.master_repl_offset += data.len() as u64;
+        self.replicas.feed(data);
+        self.update_fsynced_offset();
+    }
+
+    /// Moves the fsynced offset to the current one once the append only file
+    /// holds everything on disk
+    fn update_fsynced_offset(&mut self) {
+        if self.aof.as_ref().is_some_and(|aof| aof.is_synced())
+            && self.aof_fsynced_offset < self.config.master_repl_offset
+        {
+            self.aof_fsynced_offset = self.config.master_repl_offset;
+            self.replicas.acks().notify_waiters();
+        }
+    }
+
+    /// Where snapshots are saved, `dbfilename` inside `dir`
+    pub fn rdb_path(&self) -> PathBuf {
+        PathBuf::from(&self.config.dir).join(&self.config.dbfilename)
+    }
+
+    /// Saves a snapshot of the dataset, blocking until it is on disk
+    pub fn save(&mut self) -> io::Result<()> {
+        let path = self.rdb_path();
+
+        self.rdb.save(&path, &rdb::encode(&self.store, &self.functions.codes()))
+    }
+
+    /// Saves a snapshot of the current dataset in the background
+    pub fn bgsave(&mut self) -> io::Result<()> {
+        let path = self.rdb_path();
+
+        self.rdb.start_bgsave(path, rdb::encode(&self.store, &self.functions.codes()))
+    }
+
+    /// Starts rewriting the append only file from the current dataset
+    pub fn rewrite_aof(&mut self) -> io::Result<()> {
+        let aof = match self.aof.as_mut() {
+            Some(aof) => aof,
+            None => return Err(io::Error::other("append only file is disabled")),
+        };
+
+        aof.start_rewrite(self.functions.codes(), self.store.snapshot())
+    }
+
+    /// Turns the append only file on or off at runtime
+    ///
+    /// Enabling it rewrites the current dataset as the new base, like Redis
+    /// does, since the files on disk may not match it.
+    pub fn set_appendonly(&mut self, enabled: bool) -> io::Result<()> {
+        if !enabled {
+            self.aof = None;
+            return Ok(());
+        }
+
+        if self.aof.is_none() {
+            self.aof = Some(Aof::open(&self.config)?);
+            self.rewrite_aof()?;
         }
+
+        Ok(())
+    }
+
+    /// Periodic housekeeping of the server
+    pub fn cron(&mut self) {
+        self.active_expire_cycle();
+        self.migrate_pool.close_idle();
+
+        if self.config.role == Role::Master && self.replicas.should_ping() {
+            self.feed_replicas(&aof::encode_all(&[vec!["PING".to_string()]]));
+        }
+
+        let delay = Duration::from_secs(self.config.repl_diskless_sync_delay);
+        self.replicas.start_pending_sync(delay);
+
+        if let Some(Err(err)) = self.rdb.poll_bgsave() {
+            eprintln!("err: background saving: {}", err);
+        }
+
+        if self.rdb.should_save(&self.config.save) {
+            if let Err(err) = self.bgsave() {
+                eprintln!("err: starting background saving: {}", err);
+            }
+        }
+
+        let percentage = self.config.auto_aof_rewrite_percentage;
+        let min_size = self.config.auto_aof_rewrite_min_size;
+
+        if let Some(aof) = self.aof.as_mut() {
+            if let Err(err) = aof.sync_if_due() {
+                eprintln!("err: syncing {}: {}", aof.path().display(), err);
+            }
+        }
+
+        self.update_fsynced_offset();
+
+        let aof = match self.aof.as_mut() {
+            Some(aof) => aof,
+            None => return,
+        };
+
+        if let Some(Err(err)) = aof.poll_rewrite() {
+            eprintln!("err: background append only file rewrite: {}", err);
+        }
+
+        if aof.needs_rewrite(percentage, min_size) {
+            if let Err(err) = self.rewrite_aof() {
+                eprintln!("err: starting automatic append only file rewrite: {}", err);
+            }
+        }
+    }
+
+    /// Sends invalidation messages for the keys modified since the last call
+    ///
+    /// `by` is the session whose command modified them, if any.
+    pub fn invalidate(&mut self, by: Option<u64>) {
+        let (keys, flushed) = self.store.take_modified();
+
+        if flushed {
+            for target in self.tracking.invalidate_all() {
+                self.send_invalidation(target, RespType::Null);
+            }
+        }
+
+        if keys.is_empty() {
+            return;
+        }
+
+        for (target, keys) in self.tracking.invalidate(&keys, by) {
+            let keys = RespType::array(keys.into_iter().map(RespType::bulk_string).collect());
+            self.send_invalidation(target, keys);
+        }
+    }
+
+    /// RESP3 clients get an `invalidate` push, RESP2 clients a message on
+    /// [`INVALIDATE_CHANNEL`] if they are subscribed to it
+    fn send_invalidation(&self, target: u64, keys: RespType) {
+        let client = match self.clients.get(&target) {
+            Some(client) => client,
+            None => return,
+        };
+
+        let message = if client.protocol >= 3 {
+            RespType::Push(vec![RespType::bulk_string("invalidate"), keys])
+        } else if self.pubsub.is_subscribed(INVALIDATE_CHANNEL, target) {
+            RespType::Push(vec![
+                RespType::bulk_string("message"),
+                RespType::bulk_string(INVALIDATE_CHANNEL),
+                keys,
+            ])
+        } else {
+            return;
+        };
+
+        let _ = client.outbound.send(message);
     }
 }
 
 impl Default for Context {
     fn default() -> Self {
-        Self {
-            store: store::create_store(),
-            config: config::load().unwrap(),
-        }
+        Self::new(store::create_store(), config::load().unwrap())
     }
 }
 
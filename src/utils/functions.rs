@@ -0,0 +1,486 @@
+//! Function libraries loaded by `FUNCTION LOAD` and called by `FCALL`
+//!
+//! A library starts with a `#!lua name=<library>` line and registers its
+//! functions with `redis.register_function` when it runs. Interpreter values
+//! don't outlive a run, so libraries are kept as their code: loading one
+//! runs it once to learn the functions it registers, and every `FCALL` runs
+//! it again before calling the function. The code is also what snapshots,
+//! the append only file and `FUNCTION DUMP` persist.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::lua::{self, FunctionBody, Host, Lua, LuaError, Table, Value};
+use crate::resp::{
+    errors::Error,
+    types::{bytes_to_string, string_to_bytes},
+};
+
+use super::rdb;
+use super::scripting::{self, ScriptFlags, FUNCTION_CHUNK_NAME};
+
+/// Longest a library may run while it is loaded
+const LOAD_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// A function registered by a library
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub description: Option<String>,
+    pub flags: ScriptFlags,
+}
+
+/// A loaded library
+#[derive(Debug, Clone)]
+pub struct Library {
+    pub name: String,
+    /// Code as loaded, metadata line included
+    pub code: String,
+    pub body: Arc<FunctionBody>,
+    pub functions: Vec<Function>,
+}
+
+/// How `FUNCTION RESTORE` treats the libraries already loaded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RestorePolicy {
+    /// Fails if a restored library already exists
+    Append,
+    /// Restored libraries replace the ones of the same name
+    Replace,
+    /// Deletes every library first
+    Flush,
+}
+
+/// Libraries by name
+#[derive(Debug, Clone, Default)]
+pub struct Functions {
+    libraries: BTreeMap<String, Library>,
+}
+
+impl Functions {
+    /// Loads the library of `code`, returning its name
+    ///
+    /// An existing library of the same name is only replaced with `replace`.
+    pub fn load(&mut self, code: &str, replace: bool) -> Result<String, Error> {
+        let (name, source) = parse_metadata(code)?;
+
+        if !replace && self.libraries.contains_key(&name) {
+            return Err(Error::custom(format!("ERR Library '{name}' already exists")));
+        }
+
+        self.add(compile(name, code, source)?)
+    }
+
+    /// Adds `library`, replacing the one of the same name
+    fn add(&mut self, library: Library) -> Result<String, Error> {
+        for function in &library.functions {
+            match self.get(&function.name) {
+                Some((owner, _)) if owner.name != library.name => {
+                    return Err(Error::custom(format!("ERR Function {} already exists", function.name)));
+                }
+                _ => {}
+            }
+        }
+
+        let name = library.name.clone();
+
+        self.libraries.insert(name.clone(), library);
+
+        Ok(name)
+    }
+
+    pub fn delete(&mut self, name: &str) -> Result<(), Error> {
+        match self.libraries.remove(name) {
+            Some(_) => Ok(()),
+            None => Err(Error::custom("ERR Library not found")),
+        }
+    }
+
+    pub fn flush(&mut self) {
+        self.libraries.clear();
+    }
+
+    /// The function `name` and the library it belongs to
+    pub fn get(&self, name: &str) -> Option<(&Library, &Function)> {
+        self.libraries.values().find_map(|library| {
+            library
+                .functions
+                .iter()
+                .find(|function| function.name == name)
+                .map(|function| (library, function))
+        })
+    }
+
+    /// Libraries sorted by name
+    pub fn libraries(&self) -> impl Iterator<Item = &Library> {
+        self.libraries.values()
+    }
+
+    pub fn function_count(&self) -> usize {
+        self.libraries.values().map(|library| library.functions.len()).sum()
+    }
+
+    /// Code of every library, what persists them
+    pub fn codes(&self) -> Vec<String> {
+        self.libraries.values().map(|library| library.code.clone()).collect()
+    }
+
+    /// `FUNCTION DUMP` payload of every library
+    pub fn dump(&self) -> Vec<u8> {
+        rdb::dump_functions(&self.codes())
+    }
+
+    /// Loads the libraries of a `FUNCTION DUMP` payload, nothing changes if
+    /// one of them fails to
+    pub fn restore(&mut self, payload: &[u8], policy: RestorePolicy) -> Result<(), Error> {
+        if !rdb::verify_dump(payload) {
+            return Err(Error::custom("ERR payload version or checksum are wrong"));
+        }
+
+        let codes = rdb::read_functions_dump(payload).map_err(|err| Error::custom(format!("ERR {err}")))?;
+        let mut functions = match policy {
+            RestorePolicy::Flush => Functions::default(),
+            _ => self.clone(),
+        };
+
+        for code in codes {
+            let (name, source) = parse_metadata(&code)?;
+
+            if policy == RestorePolicy::Append && functions.libraries.contains_key(&name) {
+                return Err(Error::custom(format!("ERR Library {name} already exists")));
+            }
+
+            functions.add(compile(name, &code, source)?)?;
+        }
+
+        *self = functions;
+
+        Ok(())
+    }
+}
+
+/// Name of the library from the metadata line of `code`, and the code
+/// following it
+fn parse_metadata(code: &str) -> Result<(String, &str), Error> {
+    let Some(rest) = code.strip_prefix("#!") else {
+        return Err(Error::custom("ERR Missing library metadata"));
+    };
+
+    let Some(end) = rest.find('\n') else {
+        return Err(Error::custom("ERR Invalid library metadata"));
+    };
+
+    let mut parts = rest[..end].split(' ').filter(|part| !part.is_empty());
+    let engine = parts.next().unwrap_or_default();
+    let mut name = None;
+
+    for part in parts {
+        match part.strip_prefix("name=") {
+            Some(_) if name.is_some() => {
+                return Err(Error::custom("ERR Invalid metadata value, name argument was given multiple times"));
+            }
+            Some(value) => name = Some(value.to_string()),
+            None => return Err(Error::custom(format!("ERR Invalid metadata value given: {part}"))),
+        }
+    }
+
+    let Some(name) = name else {
+        return Err(Error::custom("ERR Library name was not given"));
+    };
+
+    if !engine.eq_ignore_ascii_case("lua") {
+        return Err(Error::custom(format!("ERR Engine '{engine}' not found")));
+    }
+
+    if !is_valid_name(&name) {
+        return Err(Error::custom(
+            "ERR Library names can only contain letters, numbers, or underscores(_) and must be at least one character long",
+        ));
+    }
+
+    // The metadata line stays as an empty one so line numbers match
+    Ok((name, &rest[end..]))
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+}
+
+/// Compiles the library `name` and runs it to learn its functions
+fn compile(name: String, code: &str, source: &str) -> Result<Library, Error> {
+    let body = lua::parse(&string_to_bytes(source), FUNCTION_CHUNK_NAME)
+        .map_err(|err| Error::custom(format!("ERR Error compiling function: {err}")))?;
+
+    let registry: Rc<RefCell<Vec<Registered>>> = Rc::default();
+    let mut host = LoadHost {
+        started: Instant::now(),
+    };
+
+    let result = {
+        let mut lua = Lua::new(&mut host, FUNCTION_CHUNK_NAME);
+        scripting::open_redis(&mut lua)
+            .borrow_mut()
+            .set_str("register_function", register_function(Rc::clone(&registry)));
+        scripting::sandbox(&mut lua);
+
+        lua.run(&body, Vec::new())
+    };
+
+    if let Err(err) = result {
+        let message = scripting::error_message(&err).unwrap_or_else(|| "ERR unknown error".to_string());
+        let message = message.strip_prefix("ERR ").unwrap_or(&message);
+
+        return Err(Error::custom(format!("ERR Error registering functions: {message}")));
+    }
+
+    let functions: Vec<Function> = registry.borrow().iter().map(|registered| registered.function.clone()).collect();
+
+    if functions.is_empty() {
+        return Err(Error::custom("ERR No functions registered"));
+    }
+
+    Ok(Library {
+        name,
+        code: code.to_string(),
+        body,
+        functions,
+    })
+}
+
+/// A function as `redis.register_function` got it
+pub(super) struct Registered {
+    pub function: Function,
+    pub callback: Value,
+}
+
+/// `redis.register_function(name, callback)`, or with a table of named
+/// arguments `function_name`, `callback`, `flags` and `description`
+///
+/// The functions registered are added to `registry`.
+pub(super) fn register_function(registry: Rc<RefCell<Vec<Registered>>>) -> Value {
+    Value::native("register_function", move |_, args| {
+        let registered = match args.as_slice() {
+            [name, callback] => registration(name, callback, &Value::Nil, &Value::Nil)?,
+            [Value::Table(named)] => {
+                let named = named.borrow();
+                let mut key = Value::Nil;
+
+                while let Ok(Some((next, _))) = named.next(&key) {
+                    let known = next.to_bytes().is_some_and(|name| {
+                        matches!(&*name, b"function_name" | b"callback" | b"flags" | b"description")
+                    });
+
+                    if !known {
+                        return Err(scripting::raise("unknown argument given to redis.register_function"));
+                    }
+
+                    key = next;
+                }
+
+                if named.get_str("function_name").is_nil() {
+                    return Err(scripting::raise("redis.register_function must get a function name argument"));
+                }
+
+                if named.get_str("callback").is_nil() {
+                    return Err(scripting::raise("redis.register_function must get a callback argument"));
+                }
+
+                registration(
+                    &named.get_str("function_name"),
+                    &named.get_str("callback"),
+                    &named.get_str("flags"),
+                    &named.get_str("description"),
+                )?
+            }
+            [_] => {
+                return Err(scripting::raise(
+                    "calling redis.register_function with a single argument is only applicable to Lua table (representing named arguments).",
+                ))
+            }
+            _ => return Err(scripting::raise("wrong number of arguments to redis.register_function")),
+        };
+
+        let mut registry = registry.borrow_mut();
+
+        if registry.iter().any(|other| other.function.name == registered.function.name) {
+            return Err(scripting::raise("Function already exists in the library"));
+        }
+
+        registry.push(registered);
+
+        Ok(Vec::new())
+    })
+}
+
+/// Checks the arguments of `redis.register_function`, `Nil` when not given
+fn registration(name: &Value, callback: &Value, flags: &Value, description: &Value) -> Result<Registered, LuaError> {
+    let Value::String(name) = name else {
+        return Err(scripting::raise("function_name argument given to redis.register_function must be a string"));
+    };
+
+    let name = bytes_to_string(name);
+
+    if !is_valid_name(&name) {
+        return Err(scripting::raise(
+            "Function names can only contain letters, numbers, or underscores(_) and must be at least one character long",
+        ));
+    }
+
+    if callback.type_name() != "function" {
+        return Err(scripting::raise("callback argument given to redis.register_function must be a function"));
+    }
+
+    let flags = match flags {
+        Value::Nil => ScriptFlags::default(),
+        Value::Table(flags) => {
+            let names: Vec<String> = flags
+                .borrow()
+                .array()
+                .iter()
+                .map(|flag| match flag {
+                    Value::String(flag) => Ok(bytes_to_string(flag)),
+                    _ => Err(scripting::raise("unknown flag given")),
+                })
+                .collect::<Result<_, _>>()?;
+
+            ScriptFlags::parse(names.iter().map(String::as_str)).map_err(|_| scripting::raise("unknown flag given"))?
+        }
+        _ => {
+            return Err(scripting::raise(
+                "flags argument to redis.register_function must be a table representing function flags",
+            ))
+        }
+    };
+
+    let description = match description {
+        Value::Nil => None,
+        Value::String(description) => Some(bytes_to_string(description)),
+        _ => {
+            return Err(scripting::raise("description argument given to redis.register_function must be a string"))
+        }
+    };
+
+    Ok(Registered {
+        function: Function {
+            name,
+            description,
+            flags,
+        },
+        callback: callback.clone(),
+    })
+}
+
+/// What libraries reach while they are loaded, commands can't run yet
+struct LoadHost {
+    started: Instant,
+}
+
+impl Host for LoadHost {
+    fn invoke(&mut self, name: &str, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+        match name {
+            "log" => scripting::log(&args),
+            _ => Err(scripting::raise(format!("redis.{name} can't be called while loading a library"))),
+        }
+    }
+
+    fn interrupted(&mut self) -> Option<LuaError> {
+        (self.started.elapsed() > LOAD_TIMEOUT).then(|| {
+            let mut err = Table::new();
+
+            err.set_str("err", Value::string("FUNCTION LOAD timeout"));
+
+            LuaError::new(Value::table(err))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIBRARY: &str = "#!lua name=mylib\nredis.register_function('echo', function(keys, args) return args[1] end)\n\
+        redis.register_function{function_name='ro', callback=function() return 1 end, flags={'no-writes'}, description='read only'}";
+
+    #[test]
+    fn loads_libraries() {
+        let mut functions = Functions::default();
+
+        assert_eq!(functions.load(LIBRARY, false), Ok("mylib".to_string()));
+        assert_eq!(functions.function_count(), 2);
+
+        let (library, function) = functions.get("ro").unwrap();
+
+        assert_eq!(library.name, "mylib");
+        assert_eq!(function.description.as_deref(), Some("read only"));
+        assert!(function.flags.no_writes);
+        assert_eq!(
+            functions.load(LIBRARY, false),
+            Err(Error::custom("ERR Library 'mylib' already exists"))
+        );
+        assert!(functions.load(LIBRARY, true).is_ok());
+        assert_eq!(
+            functions.load(&LIBRARY.replace("mylib", "other"), false),
+            Err(Error::custom("ERR Function echo already exists"))
+        );
+        assert_eq!(functions.delete("mylib"), Ok(()));
+        assert_eq!(functions.delete("mylib"), Err(Error::custom("ERR Library not found")));
+    }
+
+    #[test]
+    fn rejects_invalid_libraries() {
+        let load = |code: &str| Functions::default().load(code, false).unwrap_err().to_string();
+
+        assert_eq!(load("return 1"), "ERR Missing library metadata");
+        assert_eq!(load("#!lua\nreturn 1"), "ERR Library name was not given");
+        assert_eq!(load("#!lua name=a x=y\nreturn 1"), "ERR Invalid metadata value given: x=y");
+        assert_eq!(load("#!js name=a\nreturn 1"), "ERR Engine 'js' not found");
+        assert!(load("#!lua name=a-b\nreturn 1").starts_with("ERR Library names can only contain"));
+        assert!(load("#!lua name=a\nreturn +").starts_with("ERR Error compiling function: user_function:2:"));
+        assert_eq!(load("#!lua name=a\nreturn 1"), "ERR No functions registered");
+        assert_eq!(
+            load("#!lua name=a\nredis.register_function('f', 1)"),
+            "ERR Error registering functions: callback argument given to redis.register_function must be a function"
+        );
+        assert_eq!(
+            load("#!lua name=a\nredis.register_function{function_name='f', callback=function() end, flags={'bogus'}}"),
+            "ERR Error registering functions: unknown flag given"
+        );
+        assert_eq!(
+            load("#!lua name=a\nlocal f = function() end redis.register_function('f', f) redis.register_function('f', f)"),
+            "ERR Error registering functions: Function already exists in the library"
+        );
+        assert!(load("#!lua name=a\nredis.call('PING')").starts_with("ERR Error registering functions:"));
+    }
+
+    #[test]
+    fn dumps_and_restores() {
+        let mut functions = Functions::default();
+
+        functions.load(LIBRARY, false).unwrap();
+
+        let payload = functions.dump();
+        let mut restored = Functions::default();
+
+        assert_eq!(restored.restore(&payload, RestorePolicy::Append), Ok(()));
+        assert_eq!(restored.codes(), vec![LIBRARY.to_string()]);
+        assert_eq!(
+            restored.restore(&payload, RestorePolicy::Append),
+            Err(Error::custom("ERR Library mylib already exists"))
+        );
+        assert_eq!(restored.restore(&payload, RestorePolicy::Replace), Ok(()));
+        assert_eq!(restored.restore(&payload, RestorePolicy::Flush), Ok(()));
+        assert_eq!(restored.function_count(), 2);
+
+        let mut corrupted = payload.clone();
+        corrupted[3] ^= 1;
+
+        assert_eq!(
+            restored.restore(&corrupted, RestorePolicy::Flush),
+            Err(Error::custom("ERR payload version or checksum are wrong"))
+        );
+        assert_eq!(restored.function_count(), 2);
+    }
+}
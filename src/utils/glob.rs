@@ -0,0 +1,154 @@
+use crate::resp::types::string_to_bytes;
+
+/// Glob style matching with the same rules as Redis `stringmatchlen`
+///
+/// Supports `*`, `?`, `[abc]`, `[^abc]`, `[a-z]` and `\` to escape the next
+/// character.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    match_bytes(&string_to_bytes(pattern), &string_to_bytes(text))
+}
+
+fn match_bytes(mut pattern: &[u8], mut text: &[u8]) -> bool {
+    while let Some(&p) = pattern.first() {
+        match p {
+            b'*' => {
+                while pattern.len() > 1 && pattern[1] == b'*' {
+                    pattern = &pattern[1..];
+                }
+
+                if pattern.len() == 1 {
+                    return true;
+                }
+
+                for start in 0..=text.len() {
+                    if match_bytes(&pattern[1..], &text[start..]) {
+                        return true;
+                    }
+                }
+
+                return false;
+            }
+            b'?' => {
+                if text.is_empty() {
+                    return false;
+                }
+
+                text = &text[1..];
+            }
+            b'[' => {
+                let (matched, rest) = match_class(&pattern[1..], text.first().copied());
+
+                if !matched || text.is_empty() {
+                    return false;
+                }
+
+                pattern = rest;
+                text = &text[1..];
+                continue;
+            }
+            b'\\' if pattern.len() >= 2 => {
+                if text.first() != Some(&pattern[1]) {
+                    return false;
+                }
+
+                pattern = &pattern[1..];
+                text = &text[1..];
+            }
+            _ => {
+                if text.first() != Some(&p) {
+                    return false;
+                }
+
+                text = &text[1..];
+            }
+        }
+
+        pattern = &pattern[1..];
+    }
+
+    text.is_empty()
+}
+
+/// Matches `c` against the class starting after `[`, returns the pattern left
+/// after the closing `]`
+fn match_class(mut pattern: &[u8], c: Option<u8>) -> (bool, &[u8]) {
+    let c = match c {
+        Some(c) => c,
+        None => return (false, pattern),
+    };
+
+    let negate = pattern.first() == Some(&b'^');
+
+    if negate {
+        pattern = &pattern[1..];
+    }
+
+    let mut matched = false;
+
+    loop {
+        match pattern {
+            [] => break,
+            [b']', rest @ ..] => {
+                pattern = rest;
+                break;
+            }
+            [b'\\', escaped, rest @ ..] => {
+                matched |= *escaped == c;
+                pattern = rest;
+            }
+            [start, b'-', end, rest @ ..] if *end != b']' => {
+                let (low, high) = if start <= end { (*start, *end) } else { (*end, *start) };
+                matched |= c >= low && c <= high;
+                pattern = rest;
+            }
+            [single, rest @ ..] => {
+                matched |= *single == c;
+                pattern = rest;
+            }
+        }
+    }
+
+    (matched != negate, pattern)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resp::types::bytes_to_string;
+
+    #[test]
+    fn matches_bytes_above_ascii() {
+        let text = bytes_to_string(b"caf\xe9\xff");
+
+        assert!(matches(&bytes_to_string(b"caf\xe9*"), &text));
+        assert!(matches("caf??", &text));
+        assert!(!matches("caf???", &text));
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("*", "anything"));
+        assert!(matches("news.*", "news.tech"));
+        assert!(!matches("news.*", "sport.tech"));
+        assert!(matches("h?llo", "hello"));
+        assert!(!matches("h?llo", "hllo"));
+        assert!(matches("a*b*c", "aXXbYYc"));
+        assert!(!matches("a*b*c", "aXXbYY"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(matches("h[ae]llo", "hallo"));
+        assert!(!matches("h[ae]llo", "hillo"));
+        assert!(matches("h[^e]llo", "hallo"));
+        assert!(!matches("h[^e]llo", "hello"));
+        assert!(matches("h[a-c]llo", "hbllo"));
+        assert!(!matches("h[a-c]llo", "hdllo"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(matches("h\\*llo", "h*llo"));
+        assert!(!matches("h\\*llo", "hello"));
+    }
+}
@@ -0,0 +1,176 @@
+//! Listpacks, the compact encoding of small collections in RDB files
+//!
+//! A listpack is a `u32` total size and a `u16` element count followed by
+//! the elements and a `0xff` terminator. Each element is its encoding, its
+//! data and the length of both, so it can also be walked backwards.
+
+use crate::resp::types::{bytes_to_string, string_to_bytes};
+
+const EOF: u8 = 0xff;
+/// Element count stored when it doesn't fit the header
+const UNKNOWN_COUNT: u16 = u16::MAX;
+
+/// Elements of the listpack `data`, integers formatted as strings
+pub fn decode(data: &[u8]) -> Option<Vec<String>> {
+    let total = u32::from_le_bytes(data.get(..4)?.try_into().ok()?) as usize;
+
+    if total != data.len() || data.last() != Some(&EOF) {
+        return None;
+    }
+
+    let count = u16::from_le_bytes(data.get(4..6)?.try_into().ok()?);
+    let mut elements = Vec::new();
+    let mut position = 6;
+
+    while *data.get(position)? != EOF {
+        let (element, len) = decode_element(&data[position..])?;
+
+        elements.push(element);
+        position += len + backlen_size(len);
+    }
+
+    if position != data.len() - 1 || (count != UNKNOWN_COUNT && count as usize != elements.len()) {
+        return None;
+    }
+
+    Some(elements)
+}
+
+/// One element and the size of its encoding and data
+fn decode_element(data: &[u8]) -> Option<(String, usize)> {
+    let first = data[0];
+    let bytes = |from: usize, len: usize| data.get(from..from + len);
+    let int = |len: usize| {
+        let raw = bytes(1, len)?;
+        let mut buf = [0; 8];
+        buf[..len].copy_from_slice(raw);
+        // Sign extend from the top bit of the last byte
+        if raw[len - 1] & 0x80 != 0 {
+            buf[len..].fill(0xff);
+        }
+        Some((i64::from_le_bytes(buf).to_string(), 1 + len))
+    };
+    let string = |from: usize, len: usize| Some((bytes_to_string(bytes(from, len)?), from + len));
+
+    match first {
+        0x00..=0x7f => Some((first.to_string(), 1)),
+        0x80..=0xbf => string(1, (first & 0x3f) as usize),
+        0xc0..=0xdf => {
+            let value = ((first as i64 & 0x1f) << 8) | *data.get(1)? as i64;
+            let value = if value >= 1 << 12 { value - (1 << 13) } else { value };
+            Some((value.to_string(), 2))
+        }
+        0xe0..=0xef => string(2, ((first as usize & 0x0f) << 8) | *data.get(1)? as usize),
+        0xf0 => string(5, u32::from_le_bytes(bytes(1, 4)?.try_into().ok()?) as usize),
+        0xf1 => int(2),
+        0xf2 => int(3),
+        0xf3 => int(4),
+        0xf4 => int(8),
+        _ => None,
+    }
+}
+
+fn backlen_size(len: usize) -> usize {
+    match len {
+        0..=127 => 1,
+        128..=16382 => 2,
+        16383..=2097150 => 3,
+        2097151..=268435454 => 4,
+        _ => 5,
+    }
+}
+
+/// Listpack holding `elements`, integers among them are stored as such
+pub fn encode<S: AsRef<str>>(elements: &[S]) -> Vec<u8> {
+    let mut data = vec![0; 6];
+
+    for element in elements {
+        let entry = encode_element(element.as_ref());
+        let len = entry.len();
+
+        data.extend(entry);
+
+        // Variable length, most significant 7 bits first, every byte but the
+        // first flagged with the high bit
+        let size = backlen_size(len);
+        for i in (0..size).rev() {
+            let byte = ((len >> (7 * i)) & 0x7f) as u8;
+            data.push(if i == size - 1 { byte } else { byte | 0x80 });
+        }
+    }
+
+    data.push(EOF);
+
+    let total = data.len() as u32;
+    let count = u16::try_from(elements.len()).unwrap_or(UNKNOWN_COUNT);
+
+    data[..4].copy_from_slice(&total.to_le_bytes());
+    data[4..6].copy_from_slice(&count.to_le_bytes());
+
+    data
+}
+
+fn encode_element(element: &str) -> Vec<u8> {
+    let integer = element
+        .parse::<i64>()
+        .ok()
+        .filter(|value| value.to_string() == element);
+
+    if let Some(value) = integer {
+        return match value {
+            0..=127 => vec![value as u8],
+            -4096..=4095 => {
+                let raw = (value & 0x1fff) as u16;
+                vec![0xc0 | (raw >> 8) as u8, raw as u8]
+            }
+            _ if i16::try_from(value).is_ok() => [&[0xf1][..], &(value as i16).to_le_bytes()].concat(),
+            -8_388_608..=8_388_607 => [&[0xf2][..], &(value as i32).to_le_bytes()[..3]].concat(),
+            _ if i32::try_from(value).is_ok() => [&[0xf3][..], &(value as i32).to_le_bytes()].concat(),
+            _ => [&[0xf4][..], &value.to_le_bytes()].concat(),
+        };
+    }
+
+    let bytes = string_to_bytes(element);
+    let len = bytes.len();
+
+    let mut entry = match len {
+        0..=63 => vec![0x80 | len as u8],
+        64..=4095 => vec![0xe0 | (len >> 8) as u8, len as u8],
+        _ => [&[0xf0][..], &(len as u32).to_le_bytes()].concat(),
+    };
+
+    entry.extend(bytes);
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let long = "x".repeat(5000);
+        let elements = vec![
+            "0", "127", "128", "-1", "-4096", "4095", "30000", "-8388608", "2147483647", "-9223372036854775808",
+            "", "hello", "007", &long,
+        ];
+
+        assert_eq!(decode(&encode(&elements)).unwrap(), elements);
+    }
+
+    #[test]
+    fn small_integers_and_strings() {
+        let data = encode(&["5", "ab"]);
+
+        assert_eq!(data, vec![13, 0, 0, 0, 2, 0, 5, 1, 0x82, b'a', b'b', 3, 0xff]);
+    }
+
+    #[test]
+    fn rejects_malformed_data() {
+        let mut data = encode(&["a", "b"]);
+        data[4] = 3;
+
+        assert!(decode(&data).is_none());
+        assert!(decode(&[1, 0]).is_none());
+    }
+}
@@ -0,0 +1,195 @@
+//! LZF, the compression Redis applies to long strings in RDB files
+
+/// Log2 of the number of entries of the match finder hash table
+const HASH_LOG: u32 = 14;
+/// Farthest back reference
+const MAX_OFFSET: usize = 1 << 13;
+/// Longest back reference
+const MAX_REF: usize = (1 << 8) + (1 << 3);
+/// Longest literal run
+const MAX_LITERAL: usize = 1 << 5;
+
+/// Compresses `input` in the liblzf format
+///
+/// The output is a sequence of literal runs (`000LLLLL` followed by L + 1
+/// bytes) and back references (`LLLOOOOO [LLLLLLLL] OOOOOOOO`).
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() + input.len() / 16 + 1);
+    let mut table = vec![0usize; 1 << HASH_LOG];
+    // Position of the header of the current literal run and its length
+    let mut run = 0;
+    let mut literals = 0;
+    let mut position = 0;
+
+    output.push(0);
+
+    while position < input.len() {
+        if position + 2 < input.len() {
+            let hash = hash(&input[position..position + 3]);
+            // Positions are stored plus one so 0 means empty
+            let candidate = table[hash];
+            table[hash] = position + 1;
+
+            if candidate > 0 && position - candidate < MAX_OFFSET {
+                let reference = candidate - 1;
+                let max = (input.len() - position).min(MAX_REF);
+                let len = (0..max)
+                    .take_while(|i| input[reference + i] == input[position + i])
+                    .count();
+
+                if len >= 3 {
+                    // Close the literal run, dropping its header when empty
+                    if literals > 0 {
+                        output[run] = (literals - 1) as u8;
+                    } else {
+                        output.pop();
+                    }
+
+                    let offset = position - reference - 1;
+                    let encoded = len - 2;
+
+                    if encoded < 7 {
+                        output.push(((offset >> 8) + (encoded << 5)) as u8);
+                    } else {
+                        output.push(((offset >> 8) + (7 << 5)) as u8);
+                        output.push((encoded - 7) as u8);
+                    }
+
+                    output.push(offset as u8);
+
+                    position += len;
+                    run = output.len();
+                    literals = 0;
+                    output.push(0);
+
+                    continue;
+                }
+            }
+        }
+
+        output.push(input[position]);
+        position += 1;
+        literals += 1;
+
+        if literals == MAX_LITERAL {
+            output[run] = (literals - 1) as u8;
+            run = output.len();
+            literals = 0;
+            output.push(0);
+        }
+    }
+
+    if literals > 0 {
+        output[run] = (literals - 1) as u8;
+    } else {
+        output.pop();
+    }
+
+    output
+}
+
+/// Reverse of [`compress`], `None` when `input` is malformed or does not
+/// expand to exactly `len` bytes
+pub fn decompress(input: &[u8], len: usize) -> Option<Vec<u8>> {
+    // A 3 byte back reference is the most any input expands to, a longer
+    // declared length can't be right and must not be allocated
+    if len > input.len().saturating_mul(MAX_REF / 3) {
+        return None;
+    }
+
+    let mut output = Vec::with_capacity(len);
+    let mut position = 0;
+
+    while position < input.len() {
+        let control = input[position] as usize;
+        position += 1;
+
+        if control < MAX_LITERAL {
+            let run = input.get(position..position + control + 1)?;
+            output.extend_from_slice(run);
+            position += control + 1;
+
+            if output.len() > len {
+                return None;
+            }
+            continue;
+        }
+
+        let mut length = control >> 5;
+
+        if length == 7 {
+            length += *input.get(position)? as usize;
+            position += 1;
+        }
+
+        let offset = ((control & 0x1f) << 8) + *input.get(position)? as usize + 1;
+        position += 1;
+
+        let start = output.len().checked_sub(offset)?;
+
+        if output.len() + length + 2 > len {
+            return None;
+        }
+
+        // The reference may overlap the bytes it produces
+        for i in 0..length + 2 {
+            output.push(output[start + i]);
+        }
+    }
+
+    (output.len() == len).then_some(output)
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let value = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+
+    (value.wrapping_mul(2_654_435_761) >> (32 - HASH_LOG)) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn back_references() {
+        assert_eq!(compress(b"abcabcabcabc"), vec![2, b'a', b'b', b'c', 0xe0, 0, 2]);
+    }
+
+    #[test]
+    fn literal_runs() {
+        let input: Vec<u8> = (0..40).collect();
+        let output = compress(&input);
+
+        assert_eq!(output[0], 31);
+        assert_eq!(output[33], 7);
+        assert_eq!(output.len(), 42);
+        assert!(compress(&[]).is_empty());
+    }
+
+    #[test]
+    fn repetitive_input_shrinks() {
+        let input = "redis".repeat(100);
+        let output = compress(input.as_bytes());
+
+        assert!(output.len() < 50);
+        assert_eq!(decompress(&output, input.len()), Some(input.into_bytes()));
+    }
+
+    #[test]
+    fn round_trip() {
+        let input: Vec<u8> = (0..5000u32).map(|i| (i * 7 % 251) as u8 ^ (i / 300) as u8).collect();
+
+        assert_eq!(decompress(&compress(&input), input.len()), Some(input));
+        assert_eq!(decompress(&[0xe0, 0, 2], 9), None);
+        assert_eq!(decompress(&[5, b'a'], 6), None);
+    }
+
+    #[test]
+    fn declared_length_is_bounded() {
+        let input = vec![0u8; 100_000];
+
+        assert_eq!(decompress(&compress(&input), input.len()), Some(input));
+        assert_eq!(decompress(&[0, b'a'], usize::MAX), None);
+        assert_eq!(decompress(&[0, b'a', 0xe0, 0, 0], 3), None);
+    }
+}
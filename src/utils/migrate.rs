@@ -0,0 +1,342 @@
+//! `MIGRATE`, moving keys to another instance
+//!
+//! Keys are serialized as with `DUMP` and sent as `RESTORE` commands, then
+//! deleted locally once the target replied, unless `COPY` is given. The
+//! connection to the target is kept for a while, so moving a slot key by key
+//! doesn't connect again for every key.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{anyhow, Result};
+use bytes::{Buf, BytesMut};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::resp::types::{bytes_to_string, string_to_bytes};
+use crate::resp::{errors::Error, types::RespType};
+
+use super::aof;
+use super::context::Context;
+use super::notifications::KeyspaceEvents;
+use super::rdb;
+use super::shared_context::{lock, SharedContext};
+
+/// Time an unused connection to a target stays open
+const POOL_IDLE_TIME: Duration = Duration::from_secs(10);
+
+/// Connections to the targets of `MIGRATE` by `host:port`
+#[derive(Debug, Default)]
+pub struct MigratePool {
+    connections: HashMap<String, (TcpStream, Instant)>,
+}
+
+impl MigratePool {
+    fn take(&mut self, addr: &str) -> Option<TcpStream> {
+        self.connections.remove(addr).map(|(stream, _)| stream)
+    }
+
+    fn put(&mut self, addr: String, stream: TcpStream) {
+        self.connections.insert(addr, (stream, Instant::now()));
+    }
+
+    /// Closes the connections unused for a while
+    pub fn close_idle(&mut self) {
+        self.connections.retain(|_, (_, used)| used.elapsed() < POOL_IDLE_TIME);
+    }
+}
+
+/// `MIGRATE host port key|"" destination-db timeout [COPY] [REPLACE]
+/// [AUTH password | AUTH2 username password] [KEYS key ...]`, with the
+/// serialized keys to send
+#[derive(Debug)]
+pub struct Migration {
+    host: String,
+    port: u16,
+    db: i64,
+    timeout: Duration,
+    copy: bool,
+    replace: bool,
+    auth: Option<Vec<String>>,
+    /// Sent as `RESTORE-ASKING` so a node importing the slot accepts them
+    asking: bool,
+    /// Key, ttl in milliseconds or 0, payload
+    keys: Vec<(String, u64, String)>,
+}
+
+impl Migration {
+    /// Keys of a `MIGRATE` request
+    pub fn keys(args: &[String]) -> Vec<String> {
+        match args.get(2) {
+            Some(key) if !key.is_empty() => vec![key.to_string()],
+            _ => match args.iter().position(|arg| arg.eq_ignore_ascii_case("keys")) {
+                Some(start) if start >= 5 => args[start + 1..].to_vec(),
+                _ => Vec::new(),
+            },
+        }
+    }
+
+    /// Parses a `MIGRATE` request and serializes its keys, `None` when none
+    /// of them exists
+    pub fn new(args: &[String], ctx: &mut Context) -> Result<Option<Self>, Error> {
+        if args.len() < 5 {
+            return Err(Error::WrongNumberOfArguments {
+                command: "migrate".to_string(),
+            });
+        }
+
+        let mut copy = false;
+        let mut replace = false;
+        let mut auth = None;
+        let mut keys = vec![args[2].to_string()];
+        let mut options = args[5..].iter();
+
+        while let Some(option) = options.next() {
+            match option.to_lowercase().as_str() {
+                "copy" => copy = true,
+                "replace" => replace = true,
+                "auth" => {
+                    let password = options.next().ok_or(Error::Syntax)?;
+                    auth = Some(vec!["AUTH".to_string(), password.to_string()]);
+                }
+                "auth2" => {
+                    let (Some(username), Some(password)) = (options.next(), options.next()) else {
+                        return Err(Error::Syntax);
+                    };
+                    auth = Some(vec!["AUTH".to_string(), username.to_string(), password.to_string()]);
+                }
+                "keys" => {
+                    if !args[2].is_empty() {
+                        return Err(Error::custom(
+                            "ERR When using MIGRATE KEYS option, the key argument must be set to the empty string",
+                        ));
+                    }
+
+                    keys = options.by_ref().cloned().collect();
+                }
+                _ => return Err(Error::Syntax),
+            }
+        }
+
+        let port = args[1].parse::<u16>().map_err(|_| Error::NotAnInteger)?;
+        let db = args[3].parse::<i64>().map_err(|_| Error::NotAnInteger)?;
+        let timeout = match args[4].parse::<i64>().map_err(|_| Error::NotAnInteger)? {
+            timeout if timeout <= 0 => 1000,
+            timeout => timeout as u64,
+        };
+
+        let mut dumped = Vec::new();
+
+        for key in keys {
+            ctx.remove_if_expired(&key);
+
+            let Some(value) = ctx.store.get(&key) else {
+                continue;
+            };
+            let ttl = match value.expires_at() {
+                Some(at) => at.duration_since(SystemTime::now()).unwrap_or_default().as_millis().max(1) as u64,
+                None => 0,
+            };
+
+            dumped.push((key, ttl, bytes_to_string(&rdb::dump(&value.data))));
+        }
+
+        if dumped.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            host: args[0].to_string(),
+            port,
+            db,
+            timeout: Duration::from_millis(timeout),
+            copy,
+            replace,
+            auth,
+            asking: ctx.cluster.is_some(),
+            keys: dumped,
+        }))
+    }
+
+    /// Commands sent to the target, replied to in order
+    fn requests(&self) -> Vec<Vec<String>> {
+        let mut requests = Vec::new();
+
+        requests.extend(self.auth.clone());
+
+        // The target is expected to start in the default database
+        if self.db != 0 {
+            requests.push(vec!["SELECT".to_string(), self.db.to_string()]);
+        }
+
+        for (key, ttl, payload) in &self.keys {
+            let command = if self.asking { "RESTORE-ASKING" } else { "RESTORE" };
+            let mut request = vec![command.to_string(), key.to_string(), ttl.to_string(), payload.to_string()];
+
+            if self.replace {
+                request.push("REPLACE".to_string());
+            }
+
+            requests.push(request);
+        }
+
+        requests
+    }
+
+    /// Sends the keys to the target, then deletes the ones it restored
+    /// unless `COPY` was given, and returns the reply of `MIGRATE`
+    pub async fn run(self, context: &SharedContext) -> RespType {
+        let addr = format!("{}:{}", self.host, self.port);
+        let requests = self.requests();
+        let pooled = lock(context).migrate_pool.take(&addr);
+        let retry = pooled.is_some();
+
+        let (stream, replies) = match self.send(pooled, &requests).await {
+            Ok(done) => done,
+            // The pooled connection may have been closed by the target
+            Err(_) if retry => match self.send(None, &requests).await {
+                Ok(done) => done,
+                Err(err) => return err.into(),
+            },
+            Err(err) => return err.into(),
+        };
+
+        let mut ctx = lock(context);
+        ctx.migrate_pool.put(addr, stream);
+
+        let preamble = replies.len() - self.keys.len();
+        let mut error = None;
+
+        // Nothing was restored when authenticating or selecting failed
+        for reply in &replies[..preamble] {
+            if let RespType::SimpleError(err) = reply {
+                return target_error(err).into();
+            }
+        }
+
+        let mut deleted = Vec::new();
+
+        for ((key, _, _), reply) in self.keys.iter().zip(&replies[preamble..]) {
+            if let RespType::SimpleError(err) = reply {
+                error = Some(target_error(err));
+            } else if !self.copy && ctx.store.remove(key).is_some() {
+                ctx.notify(KeyspaceEvents::GENERIC, "del", key);
+                deleted.push(key.to_string());
+            }
+        }
+
+        if !deleted.is_empty() {
+            let mut del = vec!["DEL".to_string()];
+            del.extend(deleted);
+            ctx.propagate(vec![del]);
+        }
+
+        match error {
+            Some(err) => err.into(),
+            None => RespType::ok(),
+        }
+    }
+
+    /// Writes `requests` to the target and reads their replies, on `stream`
+    /// or a new connection
+    async fn send(&self, stream: Option<TcpStream>, requests: &[Vec<String>]) -> Result<(TcpStream, Vec<RespType>), Error> {
+        let mut stream = match stream {
+            Some(stream) => stream,
+            None => tokio::time::timeout(self.timeout, TcpStream::connect((self.host.as_str(), self.port)))
+                .await
+                .ok()
+                .and_then(|stream| stream.ok())
+                .ok_or_else(|| Error::custom("IOERR error or timeout connecting to the client"))?,
+        };
+
+        let payload = string_to_bytes(&requests.iter().map(|request| aof::encode(request)).collect::<String>());
+
+        match tokio::time::timeout(self.timeout, stream.write_all(&payload)).await {
+            Ok(Ok(())) => {}
+            _ => return Err(Error::custom("IOERR error or timeout writing to target instance")),
+        }
+
+        let mut buffer = BytesMut::with_capacity(1024);
+        let mut replies = Vec::with_capacity(requests.len());
+
+        while replies.len() < requests.len() {
+            match tokio::time::timeout(self.timeout, read_reply(&mut stream, &mut buffer)).await {
+                Ok(Ok(reply)) => replies.push(reply),
+                _ => return Err(Error::custom("IOERR error or timeout reading to target instance")),
+            }
+        }
+
+        Ok((stream, replies))
+    }
+}
+
+async fn read_reply(stream: &mut TcpStream, buffer: &mut BytesMut) -> Result<RespType> {
+    loop {
+        if let Some((reply, used)) = RespType::parse(buffer).map_err(|err| anyhow!(err.to_string()))? {
+            buffer.advance(used);
+
+            return Ok(reply);
+        }
+
+        if stream.read_buf(buffer).await? == 0 {
+            return Err(anyhow!("connection closed"));
+        }
+    }
+}
+
+fn target_error(err: &Error) -> Error {
+    Error::custom(format!("ERR Target instance replied with error: {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{StoreValue, Value};
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_requests() {
+        let mut ctx = Context::default();
+        ctx.store.insert("a".to_string(), StoreValue::new(Value::String("1".to_string()), None));
+
+        assert_eq!(Migration::keys(&args(&["h", "1", "a", "0", "10"])), args(&["a"]));
+        assert_eq!(
+            Migration::keys(&args(&["h", "1", "", "0", "10", "COPY", "KEYS", "a", "b"])),
+            args(&["a", "b"])
+        );
+
+        let nokey = Migration::new(&args(&["127.0.0.1", "7000", "missing", "0", "10"]), &mut ctx);
+        assert!(matches!(nokey, Ok(None)));
+
+        let migration = Migration::new(
+            &args(&["127.0.0.1", "7000", "", "1", "0", "REPLACE", "AUTH2", "u", "p", "KEYS", "a", "b"]),
+            &mut ctx,
+        )
+        .unwrap()
+        .unwrap();
+        let requests = migration.requests();
+
+        assert_eq!(migration.timeout, Duration::from_millis(1000));
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0], args(&["AUTH", "u", "p"]));
+        assert_eq!(requests[1], args(&["SELECT", "1"]));
+        assert_eq!(&requests[2][..3], &args(&["RESTORE", "a", "0"])[..]);
+        assert_eq!(requests[2][4], "REPLACE");
+
+        assert_eq!(
+            Migration::new(&args(&["h", "7000", "a", "0", "10", "KEYS", "a"]), &mut ctx).unwrap_err(),
+            Error::custom("ERR When using MIGRATE KEYS option, the key argument must be set to the empty string")
+        );
+        assert_eq!(
+            Migration::new(&args(&["h", "7000", "a", "0", "10", "AUTH"]), &mut ctx).unwrap_err(),
+            Error::Syntax
+        );
+        assert_eq!(
+            Migration::new(&args(&["h", "7000", "a", "zero", "10"]), &mut ctx).unwrap_err(),
+            Error::NotAnInteger
+        );
+    }
+}
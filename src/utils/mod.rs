@@ -1,4 +1,23 @@
+pub mod acl;
+pub mod aof;
 pub mod store;
+pub mod cluster;
+pub mod cluster_bus;
 pub mod config;
 pub mod context;
+pub mod functions;
+pub mod glob;
+pub mod hash;
+pub mod listpack;
+pub mod lzf;
+pub mod migrate;
+pub mod notifications;
+pub mod pubsub;
+pub mod rdb;
+pub mod scripting;
+pub mod replication;
+pub mod sentinel;
+pub mod session;
 pub mod shared_context;
+pub mod tracking;
+pub mod ziplist;
@@ -0,0 +1,125 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Classes of keyspace events enabled through `notify-keyspace-events`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct KeyspaceEvents(u16);
+
+impl KeyspaceEvents {
+    /// `K`, publish on `__keyspace@<db>__:<key>`
+    pub const KEYSPACE: Self = Self(1 << 0);
+    /// `E`, publish on `__keyevent@<db>__:<event>`
+    pub const KEYEVENT: Self = Self(1 << 1);
+    pub const GENERIC: Self = Self(1 << 2);
+    pub const STRING: Self = Self(1 << 3);
+    pub const LIST: Self = Self(1 << 4);
+    pub const SET: Self = Self(1 << 5);
+    pub const HASH: Self = Self(1 << 6);
+    pub const ZSET: Self = Self(1 << 7);
+    pub const EXPIRED: Self = Self(1 << 8);
+    pub const EVICTED: Self = Self(1 << 9);
+    pub const STREAM: Self = Self(1 << 10);
+    pub const KEY_MISS: Self = Self(1 << 11);
+    pub const MODULE: Self = Self(1 << 12);
+    pub const NEW: Self = Self(1 << 13);
+    /// `A`, every class except key misses and new keys
+    pub const ALL: Self = Self(0b1_0111_1111_1100);
+
+    /// Flag characters in the order Redis prints them, `A` is handled apart
+    const FLAGS: [(char, Self); 14] = [
+        ('g', Self::GENERIC),
+        ('$', Self::STRING),
+        ('l', Self::LIST),
+        ('s', Self::SET),
+        ('h', Self::HASH),
+        ('z', Self::ZSET),
+        ('x', Self::EXPIRED),
+        ('e', Self::EVICTED),
+        ('t', Self::STREAM),
+        ('d', Self::MODULE),
+        ('K', Self::KEYSPACE),
+        ('E', Self::KEYEVENT),
+        ('m', Self::KEY_MISS),
+        ('n', Self::NEW),
+    ];
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Whether events of `class` are published on any channel
+    pub fn enabled(self, class: Self) -> bool {
+        self.contains(class) && (self.contains(Self::KEYSPACE) || self.contains(Self::KEYEVENT))
+    }
+}
+
+impl FromStr for KeyspaceEvents {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut events = Self::default();
+
+        for c in value.chars() {
+            let flag = match c {
+                'A' => Self::ALL,
+                _ => match Self::FLAGS.iter().find(|(flag, _)| *flag == c) {
+                    Some((_, class)) => *class,
+                    None => return Err(format!("Invalid event class character '{c}'")),
+                },
+            };
+
+            events.0 |= flag.0;
+        }
+
+        Ok(events)
+    }
+}
+
+impl fmt::Display for KeyspaceEvents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut rest = *self;
+
+        if self.contains(Self::ALL) {
+            write!(f, "A")?;
+            rest.0 &= !Self::ALL.0;
+        }
+
+        for (flag, class) in Self::FLAGS {
+            if rest.contains(class) {
+                write!(f, "{flag}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_format_flags() {
+        let events: KeyspaceEvents = "Ex".parse().unwrap();
+
+        assert!(events.enabled(KeyspaceEvents::EXPIRED));
+        assert!(!events.enabled(KeyspaceEvents::STRING));
+        assert_eq!(events.to_string(), "xE");
+
+        let events: KeyspaceEvents = "KA".parse().unwrap();
+        assert_eq!(events.to_string(), "AK");
+        assert!(!events.contains(KeyspaceEvents::KEY_MISS));
+    }
+
+    #[test]
+    fn classes_need_a_channel_type() {
+        let events: KeyspaceEvents = "g$".parse().unwrap();
+
+        assert!(!events.enabled(KeyspaceEvents::GENERIC));
+    }
+
+    #[test]
+    fn invalid_flag() {
+        assert!("Kq".parse::<KeyspaceEvents>().is_err());
+    }
+}
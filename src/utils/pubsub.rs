@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::resp::types::RespType;
+
+use super::glob;
+use super::hash::key_slot;
+
+/// Queue of out of band messages to a session, drained by its connection
+pub type Outbound = UnboundedSender<RespType>;
+
+/// Namespaces a session can subscribe in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subscription {
+    Channel,
+    Pattern,
+    /// Sharded channels, isolated from the global channels with the same name
+    Shard,
+}
+
+impl Subscription {
+    pub fn subscribe_command(&self) -> &'static str {
+        match self {
+            Subscription::Channel => "subscribe",
+            Subscription::Pattern => "psubscribe",
+            Subscription::Shard => "ssubscribe",
+        }
+    }
+
+    pub fn unsubscribe_command(&self) -> &'static str {
+        match self {
+            Subscription::Channel => "unsubscribe",
+            Subscription::Pattern => "punsubscribe",
+            Subscription::Shard => "sunsubscribe",
+        }
+    }
+}
+
+/// Fans out published messages to the subscribed sessions
+#[derive(Debug, Default)]
+pub struct PubSub {
+    channels: HashMap<String, HashMap<u64, Outbound>>,
+    patterns: HashMap<String, HashMap<u64, Outbound>>,
+    /// Sharded channels grouped by the slot their name hashes to
+    shard_channels: HashMap<u16, HashMap<String, HashMap<u64, Outbound>>>,
+}
+
+fn add(map: &mut HashMap<String, HashMap<u64, Outbound>>, name: &str, session: u64, outbound: &Outbound) {
+    map.entry(name.to_string())
+        .or_default()
+        .insert(session, outbound.clone());
+}
+
+fn remove(map: &mut HashMap<String, HashMap<u64, Outbound>>, name: &str, session: u64) {
+    if let Some(subscribers) = map.get_mut(name) {
+        subscribers.remove(&session);
+
+        if subscribers.is_empty() {
+            map.remove(name);
+        }
+    }
+}
+
+impl PubSub {
+    pub fn subscribe(&mut self, kind: Subscription, name: &str, session: u64, outbound: &Outbound) {
+        match kind {
+            Subscription::Channel => add(&mut self.channels, name, session, outbound),
+            Subscription::Pattern => add(&mut self.patterns, name, session, outbound),
+            Subscription::Shard => {
+                let slot = self.shard_channels.entry(key_slot(name)).or_default();
+                add(slot, name, session, outbound);
+            }
+        }
+    }
+
+    pub fn unsubscribe(&mut self, kind: Subscription, name: &str, session: u64) {
+        match kind {
+            Subscription::Channel => remove(&mut self.channels, name, session),
+            Subscription::Pattern => remove(&mut self.patterns, name, session),
+            Subscription::Shard => {
+                let slot = key_slot(name);
+
+                if let Some(channels) = self.shard_channels.get_mut(&slot) {
+                    remove(channels, name, session);
+
+                    if channels.is_empty() {
+                        self.shard_channels.remove(&slot);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Delivers `message` to the subscribers of `channel` and of the patterns
+    /// matching it, returns how many subscriptions received it
+    pub fn publish(&self, channel: &str, message: &str) -> usize {
+        let mut receivers = 0;
+
+        if let Some(subscribers) = self.channels.get(channel) {
+            for outbound in subscribers.values() {
+                let push = RespType::Push(vec![
+                    RespType::bulk_string("message"),
+                    RespType::bulk_string(channel),
+                    RespType::bulk_string(message),
+                ]);
+
+                if outbound.send(push).is_ok() {
+                    receivers += 1;
+                }
+            }
+        }
+
+        for (pattern, subscribers) in &self.patterns {
+            if !glob::matches(pattern, channel) {
+                continue;
+            }
+
+            for outbound in subscribers.values() {
+                let push = RespType::Push(vec![
+                    RespType::bulk_string("pmessage"),
+                    RespType::bulk_string(pattern),
+                    RespType::bulk_string(channel),
+                    RespType::bulk_string(message),
+                ]);
+
+                if outbound.send(push).is_ok() {
+                    receivers += 1;
+                }
+            }
+        }
+
+        receivers
+    }
+
+    /// Channels with at least one subscriber, optionally filtered by a glob
+    pub fn channels(&self, pattern: Option<&str>) -> Vec<String> {
+        self.channels
+            .keys()
+            .filter(|channel| match pattern {
+                Some(pattern) => glob::matches(pattern, channel),
+                None => true,
+            })
+            .cloned()
+            .collect()
+    }
+
+    pub fn is_subscribed(&self, channel: &str, session: u64) -> bool {
+        self.channels.get(channel).is_some_and(|subscribers| subscribers.contains_key(&session))
+    }
+
+    pub fn numsub(&self, channel: &str) -> usize {
+        self.channels.get(channel).map_or(0, |subscribers| subscribers.len())
+    }
+
+    fn shard_subscribers(&self, channel: &str) -> Option<&HashMap<u64, Outbound>> {
+        self.shard_channels.get(&key_slot(channel))?.get(channel)
+    }
+
+    /// Delivers `message` to the subscribers of the sharded `channel`
+    pub fn spublish(&self, channel: &str, message: &str) -> usize {
+        let mut receivers = 0;
+
+        for outbound in self.shard_subscribers(channel).into_iter().flat_map(|s| s.values()) {
+            let push = RespType::Push(vec![
+                RespType::bulk_string("smessage"),
+                RespType::bulk_string(channel),
+                RespType::bulk_string(message),
+            ]);
+
+            if outbound.send(push).is_ok() {
+                receivers += 1;
+            }
+        }
+
+        receivers
+    }
+
+    /// Sharded channels with at least one subscriber, optionally filtered by a glob
+    pub fn shard_channels(&self, pattern: Option<&str>) -> Vec<String> {
+        self.shard_channels
+            .values()
+            .flat_map(|channels| channels.keys())
+            .filter(|channel| match pattern {
+                Some(pattern) => glob::matches(pattern, channel),
+                None => true,
+            })
+            .cloned()
+            .collect()
+    }
+
+    pub fn shard_numsub(&self, channel: &str) -> usize {
+        self.shard_subscribers(channel).map_or(0, |subscribers| subscribers.len())
+    }
+
+    /// Number of distinct patterns subscribed to
+    pub fn numpat(&self) -> usize {
+        self.patterns.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use super::*;
+
+    #[test]
+    fn publish_to_channel_and_pattern() {
+        let mut pubsub = PubSub::default();
+        let (tx, mut rx) = unbounded_channel();
+
+        pubsub.subscribe(Subscription::Channel, "news.tech", 1, &tx);
+        pubsub.subscribe(Subscription::Pattern, "news.*", 1, &tx);
+
+        assert_eq!(pubsub.publish("news.tech", "hello"), 2);
+        assert_eq!(pubsub.publish("sport", "hello"), 0);
+
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            RespType::Push(vec![
+                RespType::bulk_string("message"),
+                RespType::bulk_string("news.tech"),
+                RespType::bulk_string("hello"),
+            ])
+        );
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            RespType::Push(vec![
+                RespType::bulk_string("pmessage"),
+                RespType::bulk_string("news.*"),
+                RespType::bulk_string("news.tech"),
+                RespType::bulk_string("hello"),
+            ])
+        );
+    }
+
+    #[test]
+    fn introspection() {
+        let mut pubsub = PubSub::default();
+        let (tx, _rx) = unbounded_channel();
+
+        pubsub.subscribe(Subscription::Channel, "a", 1, &tx);
+        pubsub.subscribe(Subscription::Channel, "a", 2, &tx);
+        pubsub.subscribe(Subscription::Channel, "b", 1, &tx);
+        pubsub.subscribe(Subscription::Pattern, "c*", 1, &tx);
+
+        assert_eq!(pubsub.numsub("a"), 2);
+        assert_eq!(pubsub.numpat(), 1);
+        assert_eq!(pubsub.channels(Some("b*")), vec!["b".to_string()]);
+
+        pubsub.unsubscribe(Subscription::Channel, "a", 1);
+        pubsub.unsubscribe(Subscription::Channel, "a", 2);
+        assert_eq!(pubsub.numsub("a"), 0);
+        assert_eq!(pubsub.channels(None), vec!["b".to_string()]);
+    }
+
+    #[test]
+    fn shard_channels_are_isolated() {
+        let mut pubsub = PubSub::default();
+        let (tx, mut rx) = unbounded_channel();
+
+        pubsub.subscribe(Subscription::Shard, "orders", 1, &tx);
+        pubsub.subscribe(Subscription::Pattern, "*", 2, &tx);
+
+        assert_eq!(pubsub.publish("orders", "global"), 1);
+        assert_eq!(pubsub.spublish("orders", "sharded"), 1);
+        assert_eq!(pubsub.numsub("orders"), 0);
+        assert_eq!(pubsub.shard_numsub("orders"), 1);
+        assert_eq!(pubsub.shard_channels(None), vec!["orders".to_string()]);
+        assert!(pubsub.channels(None).is_empty());
+
+        rx.try_recv().unwrap();
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            RespType::Push(vec![
+                RespType::bulk_string("smessage"),
+                RespType::bulk_string("orders"),
+                RespType::bulk_string("sharded"),
+            ])
+        );
+
+        pubsub.unsubscribe(Subscription::Shard, "orders", 1);
+        assert!(pubsub.shard_channels(None).is_empty());
+    }
+}
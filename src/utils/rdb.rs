@@ -0,0 +1,1342 @@
+//! RDB snapshots, in the version 11 format of Redis 7.2
+//!
+//! A file starts with `REDIS0011` and auxiliary fields, followed by the keys
+//! of each database and a CRC64 of everything before it. Module types are
+//! stored as `MODULE_2` values holding a single string, the serialization
+//! of the value in this server. Their type names are private to this server
+//! since that serialization is not the one of RedisBloom or RedisTimeSeries.
+//!
+//! Files written by Redis itself load as well, whatever the encoding of
+//! their values, module types of other modules aside.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+
+use crate::models::{
+    BloomFilter, Consumer, ConsumerGroup, CountMinSketch, PendingEntry, StoreValue, Stream, StreamId, TimeSeries,
+    Value,
+};
+use crate::resp::types::{bytes_to_string, string_to_bytes};
+
+use super::context::Context;
+use super::hash::crc64;
+use super::store::Store;
+use super::{listpack, lzf, ziplist};
+
+pub const RDB_VERSION: u16 = 11;
+
+pub const OPCODE_SLOT_INFO: u8 = 0xf4;
+pub const OPCODE_FUNCTION2: u8 = 0xf5;
+pub const OPCODE_FUNCTION_PRE_GA: u8 = 0xf6;
+pub const OPCODE_MODULE_AUX: u8 = 0xf7;
+pub const OPCODE_IDLE: u8 = 0xf8;
+pub const OPCODE_FREQ: u8 = 0xf9;
+pub const OPCODE_AUX: u8 = 0xfa;
+pub const OPCODE_RESIZEDB: u8 = 0xfb;
+pub const OPCODE_EXPIRETIME_MS: u8 = 0xfc;
+pub const OPCODE_EXPIRETIME: u8 = 0xfd;
+pub const OPCODE_SELECTDB: u8 = 0xfe;
+pub const OPCODE_EOF: u8 = 0xff;
+
+pub const TYPE_STRING: u8 = 0;
+pub const TYPE_LIST: u8 = 1;
+pub const TYPE_SET: u8 = 2;
+pub const TYPE_ZSET: u8 = 3;
+pub const TYPE_HASH: u8 = 4;
+pub const TYPE_ZSET_2: u8 = 5;
+pub const TYPE_MODULE_PRE_GA: u8 = 6;
+pub const TYPE_MODULE_2: u8 = 7;
+pub const TYPE_HASH_ZIPMAP: u8 = 9;
+pub const TYPE_LIST_ZIPLIST: u8 = 10;
+pub const TYPE_SET_INTSET: u8 = 11;
+pub const TYPE_ZSET_ZIPLIST: u8 = 12;
+pub const TYPE_HASH_ZIPLIST: u8 = 13;
+pub const TYPE_LIST_QUICKLIST: u8 = 14;
+pub const TYPE_STREAM_LISTPACKS: u8 = 15;
+pub const TYPE_HASH_LISTPACK: u8 = 16;
+pub const TYPE_ZSET_LISTPACK: u8 = 17;
+pub const TYPE_LIST_QUICKLIST_2: u8 = 18;
+pub const TYPE_STREAM_LISTPACKS_2: u8 = 19;
+pub const TYPE_SET_LISTPACK: u8 = 20;
+pub const TYPE_STREAM_LISTPACKS_3: u8 = 21;
+
+/// Quicklist nodes holding a single element, or a listpack of them
+const QUICKLIST_NODE_PLAIN: u64 = 1;
+const QUICKLIST_NODE_PACKED: u64 = 2;
+/// Elements per listpack when writing list nodes, `list-max-listpack-size`
+const QUICKLIST_NODE_SIZE: usize = 128;
+/// Entries per listpack when writing stream nodes, `stream-node-max-entries`
+const STREAM_NODE_SIZE: usize = 100;
+
+const STREAM_ITEM_FLAG_DELETED: i64 = 1;
+const STREAM_ITEM_FLAG_SAMEFIELDS: i64 = 2;
+
+/// Opcodes inside a `MODULE_2` value
+pub const MODULE_OPCODE_EOF: u64 = 0;
+pub const MODULE_OPCODE_SINT: u64 = 1;
+pub const MODULE_OPCODE_UINT: u64 = 2;
+pub const MODULE_OPCODE_FLOAT: u64 = 3;
+pub const MODULE_OPCODE_DOUBLE: u64 = 4;
+pub const MODULE_OPCODE_STRING: u64 = 5;
+
+/// Encoding version stored in the id of the module types of this server
+pub const MODULE_ENCVER: u64 = 0;
+
+/// Module type names of the values of this server
+pub const MODULE_TYPE_BLOOM: &str = "starterBF";
+pub const MODULE_TYPE_CMS: &str = "starterCM";
+pub const MODULE_TYPE_TS: &str = "starterTS";
+
+/// Special string encodings, flagged by the `11` prefix of the length
+const ENCODING_INT8: u8 = 0xc0;
+const ENCODING_INT16: u8 = 0xc1;
+const ENCODING_INT32: u8 = 0xc2;
+const ENCODING_LZF: u8 = 0xc3;
+
+/// Version reported in the `redis-ver` auxiliary field
+const REDIS_VERSION: &str = "7.2.0";
+
+/// Characters of module type names, their 6 bit codes are packed into the id
+const MODULE_CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Id of a module type, its 9 character name packed with the encoding version
+pub fn module_id(name: &str, encver: u64) -> u64 {
+    let name = name.as_bytes().iter().fold(0u64, |id, c| {
+        let code = MODULE_CHARSET.iter().position(|m| m == c).unwrap_or(0) as u64;
+        (id << 6) | code
+    });
+
+    (name << 10) | (encver & 0x3ff)
+}
+
+/// Name of the module type of id `id`
+pub fn module_name(id: u64) -> String {
+    (0..9)
+        .rev()
+        .map(|i| MODULE_CHARSET[((id >> (10 + 6 * i)) & 0x3f) as usize] as char)
+        .collect()
+}
+
+/// Serializes the function libraries of `functions` and the whole keyspace,
+/// expired keys are left out
+pub fn encode(store: &Store, functions: &[String]) -> Vec<u8> {
+    let mut buf = b"REDIS".to_vec();
+    buf.extend(format!("{RDB_VERSION:04}").as_bytes());
+
+    let ctime = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+
+    write_aux(&mut buf, "redis-ver", REDIS_VERSION);
+    write_aux(&mut buf, "redis-bits", "64");
+    write_aux(&mut buf, "ctime", &ctime.to_string());
+    write_aux(&mut buf, "aof-base", "0");
+
+    for code in functions {
+        buf.push(OPCODE_FUNCTION2);
+        write_string(&mut buf, &string_to_bytes(code));
+    }
+
+    let entries: Vec<_> = store.iter().filter(|(_, value)| !value.is_expired()).collect();
+    let expires = entries.iter().filter(|(_, value)| value.expire_time.is_some()).count();
+
+    if !entries.is_empty() {
+        buf.push(OPCODE_SELECTDB);
+        write_len(&mut buf, 0);
+        buf.push(OPCODE_RESIZEDB);
+        write_len(&mut buf, entries.len() as u64);
+        write_len(&mut buf, expires as u64);
+    }
+
+    for (key, value) in entries {
+        if let Some(at) = value.expires_at() {
+            let millis = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+
+            buf.push(OPCODE_EXPIRETIME_MS);
+            buf.extend(millis.to_le_bytes());
+        }
+
+        buf.push(value_type(&value.data));
+        write_string(&mut buf, &string_to_bytes(key));
+        write_value(&mut buf, &value.data);
+    }
+
+    buf.push(OPCODE_EOF);
+    let checksum = crc64(0, &buf);
+    buf.extend(checksum.to_le_bytes());
+
+    buf
+}
+
+/// RDB type byte preceding a value
+pub fn value_type(value: &Value) -> u8 {
+    match value {
+        Value::String(_) => TYPE_STRING,
+        Value::List(_) => TYPE_LIST_QUICKLIST_2,
+        Value::Set(_) => TYPE_SET,
+        Value::Hash(_) => TYPE_HASH,
+        Value::SortedSet(_) => TYPE_ZSET_2,
+        Value::Stream(_) => TYPE_STREAM_LISTPACKS_3,
+        Value::BloomFilter(_) | Value::CountMinSketch(_) | Value::TimeSeries(_) => TYPE_MODULE_2,
+    }
+}
+
+/// Serializes a value, without its type byte
+pub fn write_value(buf: &mut Vec<u8>, value: &Value) {
+    let (payload, module) = match value {
+        Value::String(data) => return write_string(buf, &string_to_bytes(data)),
+        Value::List(list) => return write_list(buf, list),
+        Value::Set(set) => {
+            write_len(buf, set.len() as u64);
+            for member in set {
+                write_string(buf, &string_to_bytes(member));
+            }
+            return;
+        }
+        Value::Hash(hash) => {
+            write_len(buf, hash.len() as u64);
+            for (field, value) in hash {
+                write_string(buf, &string_to_bytes(field));
+                write_string(buf, &string_to_bytes(value));
+            }
+            return;
+        }
+        Value::SortedSet(members) => {
+            write_len(buf, members.len() as u64);
+            for (member, score) in members {
+                write_string(buf, &string_to_bytes(member));
+                buf.extend(score.to_le_bytes());
+            }
+            return;
+        }
+        Value::Stream(stream) => return write_stream(buf, stream),
+        Value::BloomFilter(filter) => (filter.to_bytes(), MODULE_TYPE_BLOOM),
+        Value::CountMinSketch(sketch) => (sketch.to_bytes(), MODULE_TYPE_CMS),
+        Value::TimeSeries(series) => (series.to_bytes(), MODULE_TYPE_TS),
+    };
+
+    write_len(buf, module_id(module, MODULE_ENCVER));
+    write_len(buf, MODULE_OPCODE_STRING);
+    write_string(buf, &payload);
+    write_len(buf, MODULE_OPCODE_EOF);
+}
+
+/// Quicklist of listpacks, as Redis 7 saves lists
+fn write_list(buf: &mut Vec<u8>, list: &VecDeque<String>) {
+    let elements: Vec<&String> = list.iter().collect();
+    let nodes: Vec<&[&String]> = elements.chunks(QUICKLIST_NODE_SIZE).collect();
+
+    write_len(buf, nodes.len() as u64);
+
+    for node in nodes {
+        write_len(buf, QUICKLIST_NODE_PACKED);
+        write_string(buf, &listpack::encode(node));
+    }
+}
+
+/// Stream entries packed in listpacks keyed by their first id, followed by
+/// the stream metadata and consumer groups
+///
+/// Each listpack starts with a master entry holding the fields of its first
+/// entry, entries with the same fields only store their values.
+fn write_stream(buf: &mut Vec<u8>, stream: &Stream) {
+    let entries: Vec<_> = stream.entries.iter().collect();
+    let nodes: Vec<_> = entries.chunks(STREAM_NODE_SIZE).collect();
+
+    write_len(buf, nodes.len() as u64);
+
+    for node in nodes {
+        let (master, master_fields) = node[0];
+        let names: Vec<&String> = master_fields.iter().map(|(field, _)| field).collect();
+
+        let mut elements = vec![node.len().to_string(), "0".to_string(), names.len().to_string()];
+        elements.extend(names.iter().map(|name| name.to_string()));
+        elements.push("0".to_string());
+
+        for (id, fields) in node {
+            let same = fields.len() == names.len() && fields.iter().zip(&names).all(|((field, _), name)| field == *name);
+            let flags = if same { STREAM_ITEM_FLAG_SAMEFIELDS } else { 0 };
+
+            elements.push(flags.to_string());
+            elements.push((id.ms as i64).wrapping_sub(master.ms as i64).to_string());
+            elements.push((id.seq as i64).wrapping_sub(master.seq as i64).to_string());
+
+            if same {
+                elements.extend(fields.iter().map(|(_, value)| value.to_string()));
+                elements.push((fields.len() + 3).to_string());
+            } else {
+                elements.push(fields.len().to_string());
+                for (field, value) in fields.iter() {
+                    elements.push(field.to_string());
+                    elements.push(value.to_string());
+                }
+                elements.push((fields.len() * 2 + 4).to_string());
+            }
+        }
+
+        write_string(buf, &master.to_bytes());
+        write_string(buf, &listpack::encode(&elements));
+    }
+
+    let first = stream.first_id();
+
+    write_len(buf, stream.entries.len() as u64);
+    for value in [stream.last_id.ms, stream.last_id.seq, first.ms, first.seq] {
+        write_len(buf, value);
+    }
+    for value in [stream.max_deleted_id.ms, stream.max_deleted_id.seq, stream.entries_added] {
+        write_len(buf, value);
+    }
+
+    write_len(buf, stream.groups.len() as u64);
+
+    for group in &stream.groups {
+        write_string(buf, &string_to_bytes(&group.name));
+        write_len(buf, group.last_id.ms);
+        write_len(buf, group.last_id.seq);
+        // Unknown counts are saved as -1
+        write_len(buf, group.entries_read.unwrap_or(u64::MAX));
+
+        write_len(buf, group.pending.len() as u64);
+        for (id, pending) in &group.pending {
+            buf.extend(id.to_bytes());
+            buf.extend(pending.delivery_time.to_le_bytes());
+            write_len(buf, pending.delivery_count);
+        }
+
+        write_len(buf, group.consumers.len() as u64);
+        for consumer in &group.consumers {
+            let owned: Vec<&StreamId> = group
+                .pending
+                .iter()
+                .filter(|(_, pending)| pending.consumer == consumer.name)
+                .map(|(id, _)| id)
+                .collect();
+
+            write_string(buf, &string_to_bytes(&consumer.name));
+            buf.extend(consumer.seen_time.to_le_bytes());
+            buf.extend(consumer.active_time.to_le_bytes());
+            write_len(buf, owned.len() as u64);
+            for id in owned {
+                buf.extend(id.to_bytes());
+            }
+        }
+    }
+}
+
+fn write_aux(buf: &mut Vec<u8>, key: &str, value: &str) {
+    buf.push(OPCODE_AUX);
+    write_string(buf, key.as_bytes());
+    write_string(buf, value.as_bytes());
+}
+
+/// Length prefix, 6, 14, 32 or 64 bits long depending on `len`
+pub fn write_len(buf: &mut Vec<u8>, len: u64) {
+    if len < 1 << 6 {
+        buf.push(len as u8);
+    } else if len < 1 << 14 {
+        buf.push(0x40 | (len >> 8) as u8);
+        buf.push(len as u8);
+    } else if len <= u32::MAX as u64 {
+        buf.push(0x80);
+        buf.extend((len as u32).to_be_bytes());
+    } else {
+        buf.push(0x81);
+        buf.extend(len.to_be_bytes());
+    }
+}
+
+/// Strings holding small integers are stored as integers, long ones are
+/// LZF compressed when that saves space
+pub fn write_string(buf: &mut Vec<u8>, bytes: &[u8]) {
+    if let Some(value) = as_integer(bytes) {
+        if let Ok(value) = i8::try_from(value) {
+            buf.push(ENCODING_INT8);
+            buf.extend(value.to_le_bytes());
+            return;
+        }
+
+        if let Ok(value) = i16::try_from(value) {
+            buf.push(ENCODING_INT16);
+            buf.extend(value.to_le_bytes());
+            return;
+        }
+
+        if let Ok(value) = i32::try_from(value) {
+            buf.push(ENCODING_INT32);
+            buf.extend(value.to_le_bytes());
+            return;
+        }
+    }
+
+    if bytes.len() > 20 {
+        let compressed = lzf::compress(bytes);
+
+        if compressed.len() <= bytes.len() - 4 {
+            buf.push(ENCODING_LZF);
+            write_len(buf, compressed.len() as u64);
+            write_len(buf, bytes.len() as u64);
+            buf.extend(compressed);
+            return;
+        }
+    }
+
+    write_len(buf, bytes.len() as u64);
+    buf.extend(bytes);
+}
+
+/// The integer `bytes` spell, when they are its canonical representation
+fn as_integer(bytes: &[u8]) -> Option<i64> {
+    if bytes.is_empty() || bytes.len() > 11 {
+        return None;
+    }
+
+    let text = std::str::from_utf8(bytes).ok()?;
+    let value = text.parse::<i64>().ok()?;
+
+    (value.to_string() == text).then_some(value)
+}
+
+/// Contents of an RDB file
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    /// Keys of the first database, expired ones left out
+    pub entries: Vec<(String, StoreValue)>,
+    pub aux: HashMap<String, String>,
+    /// Code of the function libraries
+    pub functions: Vec<String>,
+}
+
+/// Cursor over the bytes of an RDB file
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .pos
+            .checked_add(len)
+            .and_then(|end| self.data.get(self.pos..end))
+            .ok_or_else(|| anyhow!("Short read or OOM loading DB. Unrecoverable error, aborting now."))?;
+
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32_le(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    fn u64_le(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into()?))
+    }
+
+    /// A length, or the encoding of a special string when the flag is set
+    fn len_or_encoding(&mut self) -> Result<(u64, bool)> {
+        let first = self.u8()?;
+
+        match first >> 6 {
+            0 => Ok(((first & 0x3f) as u64, false)),
+            1 => Ok(((((first & 0x3f) as u64) << 8) | self.u8()? as u64, false)),
+            2 if first == 0x80 => Ok((u32::from_be_bytes(self.bytes(4)?.try_into()?) as u64, false)),
+            2 if first == 0x81 => Ok((u64::from_be_bytes(self.bytes(8)?.try_into()?), false)),
+            2 => Err(anyhow!("Unknown length encoding {first} in RDB")),
+            _ => Ok(((first & 0x3f) as u64, true)),
+        }
+    }
+
+    fn len(&mut self) -> Result<u64> {
+        match self.len_or_encoding()? {
+            (len, false) => Ok(len),
+            (_, true) => Err(anyhow!("Unexpected string encoding where a length was expected in RDB")),
+        }
+    }
+
+    fn string(&mut self) -> Result<Vec<u8>> {
+        let (len, encoded) = self.len_or_encoding()?;
+
+        if !encoded {
+            return Ok(self.bytes(len as usize)?.to_vec());
+        }
+
+        let value = match len as u8 | 0xc0 {
+            ENCODING_INT8 => self.u8()? as i8 as i64,
+            ENCODING_INT16 => i16::from_le_bytes(self.bytes(2)?.try_into()?) as i64,
+            ENCODING_INT32 => self.u32_le()? as i32 as i64,
+            ENCODING_LZF => {
+                let compressed = self.len()? as usize;
+                let len = self.len()? as usize;
+                let input = self.bytes(compressed)?;
+
+                return lzf::decompress(input, len).ok_or_else(|| anyhow!("Invalid LZF compressed string in RDB"));
+            }
+            encoding => return Err(anyhow!("Unknown RDB string encoding type {}", encoding & 0x3f)),
+        };
+
+        Ok(value.to_string().into_bytes())
+    }
+
+    fn text(&mut self) -> Result<String> {
+        Ok(bytes_to_string(&self.string()?))
+    }
+
+    /// Score of the first sorted set encoding, a length prefixed string
+    fn double_text(&mut self) -> Result<f64> {
+        match self.u8()? {
+            253 => Ok(f64::NAN),
+            254 => Ok(f64::INFINITY),
+            255 => Ok(f64::NEG_INFINITY),
+            len => {
+                let text = std::str::from_utf8(self.bytes(len as usize)?)?;
+                text.parse().map_err(|_| anyhow!("Invalid double value in RDB: {text}"))
+            }
+        }
+    }
+
+    fn double(&mut self) -> Result<f64> {
+        Ok(f64::from_le_bytes(self.bytes(8)?.try_into()?))
+    }
+
+    fn stream_id(&mut self) -> Result<StreamId> {
+        Ok(StreamId::new(self.len()?, self.len()?))
+    }
+
+    fn raw_stream_id(&mut self) -> Result<StreamId> {
+        StreamId::from_bytes(self.bytes(16)?).ok_or_else(|| anyhow!("Invalid stream id in RDB"))
+    }
+}
+
+/// Parses an RDB file, of any version up to the one of Redis 7.4
+pub fn parse(data: &[u8]) -> Result<Snapshot> {
+    if data.len() < 9 || &data[..5] != b"REDIS" {
+        return Err(anyhow!("Wrong signature trying to load DB from file"));
+    }
+
+    let version = std::str::from_utf8(&data[5..9]).ok().and_then(|version| version.parse::<u32>().ok());
+
+    let version = match version {
+        Some(version) if (1..=12).contains(&version) => version,
+        _ => return Err(anyhow!("Can't handle RDB format version {}", bytes_to_string(&data[5..9]))),
+    };
+
+    let mut reader = Reader { data, pos: 9 };
+    let mut snapshot = Snapshot::default();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+    let mut db = 0;
+    let mut skipped = 0;
+    let mut expire_at = None;
+
+    loop {
+        match reader.u8()? {
+            OPCODE_EXPIRETIME_MS => expire_at = Some(reader.u64_le()?),
+            OPCODE_EXPIRETIME => expire_at = Some(reader.u32_le()? as u64 * 1000),
+            OPCODE_FREQ => {
+                reader.u8()?;
+            }
+            OPCODE_IDLE => {
+                reader.len()?;
+            }
+            OPCODE_AUX => {
+                let key = reader.text()?;
+                let value = reader.text()?;
+                snapshot.aux.insert(key, value);
+            }
+            OPCODE_RESIZEDB => {
+                reader.len()?;
+                reader.len()?;
+            }
+            OPCODE_SELECTDB => db = reader.len()?,
+            OPCODE_SLOT_INFO => {
+                for _ in 0..3 {
+                    reader.len()?;
+                }
+            }
+            OPCODE_MODULE_AUX => {
+                let name = module_name(reader.len()?);
+                return Err(anyhow!("The RDB file contains AUX module data I can't load: no matching module '{name}'"));
+            }
+            OPCODE_FUNCTION2 => snapshot.functions.push(reader.text()?),
+            OPCODE_FUNCTION_PRE_GA => return Err(anyhow!("Pre-release function format not supported")),
+            OPCODE_EOF => break,
+            kind => {
+                let key = reader.text()?;
+                let value = read_value(&mut reader, kind)?;
+
+                match expire_at.take() {
+                    _ if db != 0 => skipped += 1,
+                    Some(at) if at <= now => {}
+                    Some(at) => {
+                        let mut value = StoreValue::new(value, None);
+                        value.set_expires_at(UNIX_EPOCH + Duration::from_millis(at));
+                        snapshot.entries.push((key, value));
+                    }
+                    None => snapshot.entries.push((key, StoreValue::new(value, None))),
+                }
+            }
+        }
+    }
+
+    if skipped > 0 {
+        eprintln!("Warning: skipped {skipped} keys of databases other than 0 loading the RDB file");
+    }
+
+    // Checksums are optional since version 5, a zero one was disabled
+    if version >= 5 {
+        let end = reader.pos;
+        let expected = reader.u64_le()?;
+
+        if expected != 0 && expected != crc64(0, &data[..end]) {
+            return Err(anyhow!("Wrong RDB checksum. Aborting now."));
+        }
+    }
+
+    Ok(snapshot)
+}
+
+/// Reads a value of type `kind`
+fn read_value(reader: &mut Reader, kind: u8) -> Result<Value> {
+    let bad = |encoding: &str| anyhow!("Invalid {encoding} in RDB value of type {kind}");
+
+    let value = match kind {
+        TYPE_STRING => Value::String(reader.text()?),
+        TYPE_LIST => Value::List((0..reader.len()?).map(|_| reader.text()).collect::<Result<_>>()?),
+        TYPE_SET => Value::Set((0..reader.len()?).map(|_| reader.text()).collect::<Result<_>>()?),
+        TYPE_ZSET | TYPE_ZSET_2 => {
+            let mut members = Vec::new();
+
+            for _ in 0..reader.len()? {
+                let member = reader.text()?;
+                let score = if kind == TYPE_ZSET { reader.double_text()? } else { reader.double()? };
+                members.push((member, score));
+            }
+
+            sorted_set(members)
+        }
+        TYPE_HASH => {
+            let mut hash = HashMap::new();
+
+            for _ in 0..reader.len()? {
+                let field = reader.text()?;
+                hash.insert(field, reader.text()?);
+            }
+
+            Value::Hash(hash)
+        }
+        TYPE_MODULE_PRE_GA => return Err(anyhow!("This RDB file contains modules data in the pre-GA format, not supported")),
+        TYPE_MODULE_2 => read_module(reader)?,
+        TYPE_HASH_ZIPMAP => {
+            let pairs = ziplist::decode_zipmap(&reader.string()?).ok_or_else(|| bad("zipmap"))?;
+            Value::Hash(pairs.into_iter().collect())
+        }
+        TYPE_LIST_ZIPLIST => Value::List(ziplist::decode(&reader.string()?).ok_or_else(|| bad("ziplist"))?.into()),
+        TYPE_SET_INTSET => {
+            let members = ziplist::decode_intset(&reader.string()?).ok_or_else(|| bad("intset"))?;
+            Value::Set(members.into_iter().collect())
+        }
+        TYPE_SET_LISTPACK => {
+            let members = listpack::decode(&reader.string()?).ok_or_else(|| bad("listpack"))?;
+            Value::Set(members.into_iter().collect())
+        }
+        TYPE_ZSET_ZIPLIST | TYPE_ZSET_LISTPACK => {
+            let data = reader.string()?;
+            let elements = match kind {
+                TYPE_ZSET_ZIPLIST => ziplist::decode(&data).ok_or_else(|| bad("ziplist"))?,
+                _ => listpack::decode(&data).ok_or_else(|| bad("listpack"))?,
+            };
+
+            if elements.len() % 2 == 1 {
+                return Err(bad("sorted set"));
+            }
+
+            let members = elements
+                .chunks(2)
+                .map(|pair| Ok((pair[0].clone(), pair[1].parse::<f64>().map_err(|_| bad("score"))?)))
+                .collect::<Result<_>>()?;
+
+            sorted_set(members)
+        }
+        TYPE_HASH_ZIPLIST | TYPE_HASH_LISTPACK => {
+            let data = reader.string()?;
+            let elements = match kind {
+                TYPE_HASH_ZIPLIST => ziplist::decode(&data).ok_or_else(|| bad("ziplist"))?,
+                _ => listpack::decode(&data).ok_or_else(|| bad("listpack"))?,
+            };
+
+            if elements.len() % 2 == 1 {
+                return Err(bad("hash"));
+            }
+
+            Value::Hash(elements.chunks(2).map(|pair| (pair[0].clone(), pair[1].clone())).collect())
+        }
+        TYPE_LIST_QUICKLIST | TYPE_LIST_QUICKLIST_2 => {
+            let mut list = VecDeque::new();
+
+            for _ in 0..reader.len()? {
+                let container = if kind == TYPE_LIST_QUICKLIST { QUICKLIST_NODE_PACKED } else { reader.len()? };
+                let data = reader.string()?;
+
+                match container {
+                    QUICKLIST_NODE_PLAIN => list.push_back(bytes_to_string(&data)),
+                    QUICKLIST_NODE_PACKED if kind == TYPE_LIST_QUICKLIST => {
+                        list.extend(ziplist::decode(&data).ok_or_else(|| bad("ziplist"))?)
+                    }
+                    QUICKLIST_NODE_PACKED => list.extend(listpack::decode(&data).ok_or_else(|| bad("listpack"))?),
+                    _ => return Err(anyhow!("Unknown quicklist node container {container} in RDB")),
+                }
+            }
+
+            Value::List(list)
+        }
+        TYPE_STREAM_LISTPACKS | TYPE_STREAM_LISTPACKS_2 | TYPE_STREAM_LISTPACKS_3 => {
+            Value::Stream(read_stream(reader, kind)?)
+        }
+        _ => return Err(anyhow!("Unknown RDB encoding type {kind}")),
+    };
+
+    Ok(value)
+}
+
+fn sorted_set(mut members: Vec<(String, f64)>) -> Value {
+    members.sort_by(|(a, x), (b, y)| x.total_cmp(y).then_with(|| a.cmp(b)));
+    Value::SortedSet(members)
+}
+
+/// Module value, only the types of this server, in the encoding version it
+/// writes, can be loaded
+fn read_module(reader: &mut Reader) -> Result<Value> {
+    let id = reader.len()?;
+    let name = module_name(id);
+    let encver = id & 0x3ff;
+
+    if ![MODULE_TYPE_BLOOM, MODULE_TYPE_CMS, MODULE_TYPE_TS].contains(&name.as_str()) {
+        return Err(anyhow!(
+            "Unsupported module data: the RDB file contains module data for the module type '{name}', that is not supported by this server"
+        ));
+    }
+
+    if encver != MODULE_ENCVER {
+        return Err(anyhow!(
+            "Unsupported module data: encoding version {encver} of the module type '{name}' is not supported by this server"
+        ));
+    }
+
+    let mut payload = None;
+
+    loop {
+        match reader.len()? {
+            MODULE_OPCODE_EOF => break,
+            MODULE_OPCODE_SINT | MODULE_OPCODE_UINT => {
+                reader.len()?;
+            }
+            MODULE_OPCODE_FLOAT => {
+                reader.bytes(4)?;
+            }
+            MODULE_OPCODE_DOUBLE => {
+                reader.bytes(8)?;
+            }
+            MODULE_OPCODE_STRING => {
+                let data = reader.string()?;
+                payload.get_or_insert(data);
+            }
+            opcode => return Err(anyhow!("Unknown module opcode {opcode} in RDB value of type '{name}'")),
+        }
+    }
+
+    let payload = payload.unwrap_or_default();
+    let value = match name.as_str() {
+        MODULE_TYPE_BLOOM => BloomFilter::from_bytes(&payload).map(Value::BloomFilter),
+        MODULE_TYPE_CMS => CountMinSketch::from_bytes(&payload).map(Value::CountMinSketch),
+        _ => TimeSeries::from_bytes(&payload).map(Value::TimeSeries),
+    };
+
+    value.ok_or_else(|| anyhow!("Bad data for the module type '{name}' in RDB"))
+}
+
+/// Stream in any of its three encodings, the later ones add metadata
+fn read_stream(reader: &mut Reader, kind: u8) -> Result<Stream> {
+    let bad = || anyhow!("Invalid stream listpack in RDB");
+    let mut stream = Stream::default();
+
+    for _ in 0..reader.len()? {
+        let master = StreamId::from_bytes(&reader.string()?).ok_or_else(bad)?;
+        let elements = listpack::decode(&reader.string()?).ok_or_else(bad)?;
+        let mut elements = elements.into_iter();
+        let mut next = || elements.next().ok_or_else(bad);
+        let int = |element: String| element.parse::<i64>().map_err(|_| bad());
+
+        let count = int(next()?)?;
+        let deleted = int(next()?)?;
+        let names = (0..int(next()?)?).map(|_| next()).collect::<Result<Vec<_>>>()?;
+        next()?;
+
+        for _ in 0..count + deleted {
+            let flags = int(next()?)?;
+            let ms = master.ms.wrapping_add(int(next()?)? as u64);
+            let seq = master.seq.wrapping_add(int(next()?)? as u64);
+
+            let fields = if flags & STREAM_ITEM_FLAG_SAMEFIELDS != 0 {
+                names.iter().map(|name| Ok((name.clone(), next()?))).collect::<Result<Vec<_>>>()?
+            } else {
+                (0..int(next()?)?).map(|_| Ok((next()?, next()?))).collect::<Result<Vec<_>>>()?
+            };
+
+            // Count of the elements of the entry, to walk the listpack backwards
+            next()?;
+
+            if flags & STREAM_ITEM_FLAG_DELETED == 0 {
+                stream.entries.insert(StreamId::new(ms, seq), fields);
+            }
+        }
+    }
+
+    let length = reader.len()?;
+    stream.last_id = reader.stream_id()?;
+
+    if kind == TYPE_STREAM_LISTPACKS {
+        stream.entries_added = length;
+    } else {
+        reader.stream_id()?;
+        stream.max_deleted_id = reader.stream_id()?;
+        stream.entries_added = reader.len()?;
+    }
+
+    for _ in 0..reader.len()? {
+        let name = reader.text()?;
+        let last_id = reader.stream_id()?;
+        let entries_read = match kind {
+            TYPE_STREAM_LISTPACKS => None,
+            _ => Some(reader.len()?).filter(|&read| read != u64::MAX),
+        };
+
+        let mut pending = BTreeMap::new();
+
+        for _ in 0..reader.len()? {
+            let id = reader.raw_stream_id()?;
+            let delivery_time = reader.u64_le()?;
+            let delivery_count = reader.len()?;
+
+            pending.insert(id, PendingEntry { delivery_time, delivery_count, consumer: String::new() });
+        }
+
+        let mut consumers = Vec::new();
+
+        for _ in 0..reader.len()? {
+            let name = reader.text()?;
+            let seen_time = reader.u64_le()?;
+            let active_time = if kind == TYPE_STREAM_LISTPACKS_3 { reader.u64_le()? } else { seen_time };
+
+            for _ in 0..reader.len()? {
+                let id = reader.raw_stream_id()?;
+                let entry = pending
+                    .get_mut(&id)
+                    .ok_or_else(|| anyhow!("Consumer entry {id} not found in the group pending entries list in RDB"))?;
+
+                entry.consumer = name.clone();
+            }
+
+            consumers.push(Consumer { name, seen_time, active_time });
+        }
+
+        stream.groups.push(ConsumerGroup { name, last_id, entries_read, pending, consumers });
+    }
+
+    Ok(stream)
+}
+
+/// `DUMP` payload of `value`, its type and serialization followed by the RDB
+/// version and a CRC64 of all of it
+pub fn dump(value: &Value) -> Vec<u8> {
+    let mut buf = vec![value_type(value)];
+    write_value(&mut buf, value);
+    buf.extend(RDB_VERSION.to_le_bytes());
+
+    let checksum = crc64(0, &buf);
+    buf.extend(checksum.to_le_bytes());
+
+    buf
+}
+
+/// `FUNCTION DUMP` payload of the libraries of `functions`, framed like the
+/// one of `DUMP`
+pub fn dump_functions(functions: &[String]) -> Vec<u8> {
+    let mut buf = Vec::new();
+
+    for code in functions {
+        buf.push(OPCODE_FUNCTION2);
+        write_string(&mut buf, &string_to_bytes(code));
+    }
+
+    buf.extend(RDB_VERSION.to_le_bytes());
+
+    let checksum = crc64(0, &buf);
+    buf.extend(checksum.to_le_bytes());
+
+    buf
+}
+
+/// Code of the libraries of a verified `FUNCTION DUMP` payload
+pub fn read_functions_dump(payload: &[u8]) -> Result<Vec<String>> {
+    let mut reader = Reader {
+        data: &payload[..payload.len().saturating_sub(10)],
+        pos: 0,
+    };
+    let mut functions = Vec::new();
+
+    while reader.pos < reader.data.len() {
+        match reader.u8()? {
+            OPCODE_FUNCTION2 => functions.push(reader.text()?),
+            _ => return Err(anyhow!("given type is not a function")),
+        }
+    }
+
+    Ok(functions)
+}
+
+/// Whether `payload` ends with a version this server reads and a matching checksum
+pub fn verify_dump(payload: &[u8]) -> bool {
+    if payload.len() < 10 {
+        return false;
+    }
+
+    let (data, footer) = payload.split_at(payload.len() - 8);
+    let version = u16::from_le_bytes([data[data.len() - 2], data[data.len() - 1]]);
+
+    version <= RDB_VERSION && crc64(0, data) == u64::from_le_bytes(footer.try_into().unwrap_or_default())
+}
+
+/// Value of a verified `DUMP` payload
+pub fn read_dump(payload: &[u8]) -> Result<Value> {
+    let mut reader = Reader {
+        data: &payload[..payload.len().saturating_sub(10)],
+        pos: 0,
+    };
+
+    let kind = reader.u8()?;
+    let value = read_value(&mut reader, kind)?;
+
+    if reader.pos != reader.data.len() {
+        return Err(anyhow!("Trailing data after the value of the DUMP payload"));
+    }
+
+    Ok(value)
+}
+
+/// Loads the snapshot at `path` into the keyspace, a missing file is an
+/// empty one
+pub fn load(path: impl AsRef<Path>, context: &mut Context) -> Result<usize> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err.into()),
+    };
+
+    let snapshot = parse(&data)?;
+    let loaded = snapshot.entries.len();
+
+    for code in &snapshot.functions {
+        context
+            .functions
+            .load(code, true)
+            .map_err(|err| anyhow!("Failed loading library from the RDB file: {err}"))?;
+    }
+
+    for (key, value) in snapshot.entries {
+        context.store.insert(key, value);
+    }
+    context.store.take_modified();
+
+    Ok(loaded)
+}
+
+/// Writes `data` next to `path` first and renames it, a crash never leaves a
+/// partial snapshot behind
+pub fn write_file(path: &Path, data: &[u8]) -> io::Result<()> {
+    let temp = path.with_file_name(format!("temp-{}.rdb", std::process::id()));
+
+    let result = File::create(&temp).and_then(|mut file| {
+        file.write_all(data)?;
+        file.sync_all()
+    });
+
+    match result.and_then(|_| fs::rename(&temp, path)) {
+        Ok(()) => Ok(()),
+        Err(err) => {
+            let _ = fs::remove_file(&temp);
+            Err(err)
+        }
+    }
+}
+
+/// Snapshot being written by a background thread
+#[derive(Debug)]
+struct BackgroundSave {
+    handle: JoinHandle<io::Result<()>>,
+    started: Instant,
+    /// Changes covered by the snapshot
+    changes: u64,
+}
+
+/// State of the snapshots, the counterpart of [`super::aof::Aof`] for RDB files
+#[derive(Debug)]
+pub struct Rdb {
+    bgsave: Option<BackgroundSave>,
+    /// Write commands executed since the last successful save
+    pub(crate) changes: u64,
+    pub(crate) last_save: SystemTime,
+    pub(crate) last_bgsave_ok: bool,
+    pub(crate) last_bgsave_time: Option<Duration>,
+    /// When the last failed background save started, failures are retried
+    /// by the save points only after a delay
+    last_failure: Option<Instant>,
+}
+
+impl Default for Rdb {
+    fn default() -> Self {
+        Self {
+            bgsave: None,
+            changes: 0,
+            last_save: SystemTime::now(),
+            last_bgsave_ok: true,
+            last_bgsave_time: None,
+            last_failure: None,
+        }
+    }
+}
+
+impl Rdb {
+    /// Delay before save points retry a failed background save
+    const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+    pub fn is_saving(&self) -> bool {
+        self.bgsave.is_some()
+    }
+
+    /// How long the running background save has been going on
+    pub fn current_bgsave_time(&self) -> Option<Duration> {
+        self.bgsave.as_ref().map(|bgsave| bgsave.started.elapsed())
+    }
+
+    /// Writes `data` to `path` right away
+    pub fn save(&mut self, path: &Path, data: &[u8]) -> io::Result<()> {
+        write_file(path, data)?;
+
+        self.changes = 0;
+        self.last_save = SystemTime::now();
+
+        Ok(())
+    }
+
+    /// Writes `data` to `path` in a background thread
+    pub fn start_bgsave(&mut self, path: PathBuf, data: Vec<u8>) -> io::Result<()> {
+        if self.is_saving() {
+            return Err(io::Error::other("a background save is already in progress"));
+        }
+
+        let handle = thread::spawn(move || write_file(&path, &data));
+
+        self.bgsave = Some(BackgroundSave {
+            handle,
+            started: Instant::now(),
+            changes: self.changes,
+        });
+
+        Ok(())
+    }
+
+    /// Records the outcome of the background save once its thread is done
+    pub fn poll_bgsave(&mut self) -> Option<io::Result<()>> {
+        if !self.bgsave.as_ref().is_some_and(|bgsave| bgsave.handle.is_finished()) {
+            return None;
+        }
+
+        let bgsave = self.bgsave.take().expect("background save is running");
+        let result = match bgsave.handle.join() {
+            Ok(result) => result,
+            Err(_) => Err(io::Error::other("background save thread panicked")),
+        };
+
+        match &result {
+            Ok(()) => {
+                // Changes made while saving are not in the snapshot
+                self.changes = self.changes.saturating_sub(bgsave.changes);
+                self.last_save = SystemTime::now();
+                self.last_failure = None;
+            }
+            Err(_) => self.last_failure = Some(bgsave.started),
+        }
+
+        self.last_bgsave_ok = result.is_ok();
+        self.last_bgsave_time = Some(bgsave.started.elapsed());
+
+        Some(result)
+    }
+
+    /// Whether one of the `save <seconds> <changes>` points is reached
+    pub fn should_save(&self, points: &[(u64, u64)]) -> bool {
+        if self.is_saving() || self.last_failure.is_some_and(|at| at.elapsed() < Self::RETRY_DELAY) {
+            return false;
+        }
+
+        let elapsed = self.last_save.elapsed().unwrap_or_default().as_secs();
+
+        points
+            .iter()
+            .any(|(seconds, changes)| self.changes >= *changes && elapsed >= *seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded_len(len: u64) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_len(&mut buf, len);
+        buf
+    }
+
+    fn encoded_string(value: &str) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_string(&mut buf, value.as_bytes());
+        buf
+    }
+
+    #[test]
+    fn length_encoding() {
+        assert_eq!(encoded_len(10), vec![0x0a]);
+        assert_eq!(encoded_len(700), vec![0x42, 0xbc]);
+        assert_eq!(encoded_len(17000), vec![0x80, 0, 0, 0x42, 0x68]);
+        assert_eq!(encoded_len(1 << 33), vec![0x81, 0, 0, 0, 2, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn string_encoding() {
+        assert_eq!(encoded_string("hi"), vec![2, b'h', b'i']);
+        assert_eq!(encoded_string("-5"), vec![0xc0, 0xfb]);
+        assert_eq!(encoded_string("1000"), vec![0xc1, 0xe8, 0x03]);
+        assert_eq!(encoded_string("100000"), vec![0xc2, 0xa0, 0x86, 0x01, 0x00]);
+        assert_eq!(encoded_string("007"), vec![3, b'0', b'0', b'7']);
+
+        let long = encoded_string(&"a".repeat(100));
+        assert_eq!(long[0], 0xc3);
+        assert_eq!(&long[2..4], &[0x40, 100]);
+    }
+
+    #[test]
+    fn module_ids() {
+        let id = module_id("MBbloom--", 4);
+
+        // `M` is the 13th character of the charset, `-` the 63rd
+        assert_eq!(id >> 58, 12);
+        assert_eq!((id >> 10) & 0x3f, 62);
+        assert_eq!(id & 0x3ff, 4);
+        assert_ne!(module_id("MBbloom--", 0), module_id("CMSk-type", 0));
+    }
+
+    #[test]
+    fn module_types_use_private_names() {
+        let filter = BloomFilter::new(100, 0.01, 2).unwrap();
+        let payload = dump(&Value::BloomFilter(filter));
+        let mut reader = Reader { data: &payload[1..], pos: 0 };
+
+        assert_eq!(payload[0], TYPE_MODULE_2);
+        assert_eq!(module_name(reader.len().unwrap()), MODULE_TYPE_BLOOM);
+    }
+
+    #[test]
+    fn snapshot_layout() {
+        let mut store = Store::default();
+        store.insert("key".to_string(), StoreValue::new("value", Some(Duration::from_secs(60))));
+        store.insert("cms".to_string(), Value::CountMinSketch(CountMinSketch::new(2, 2).unwrap()).into());
+        store.insert("gone".to_string(), StoreValue::new("x", Some(Duration::ZERO)));
+        std::thread::sleep(Duration::from_millis(1));
+
+        let data = encode(&store, &[]);
+
+        assert!(data.starts_with(b"REDIS0011\xfa\x09redis-ver\x057.2.0"));
+        assert_eq!(data[data.len() - 9], OPCODE_EOF);
+
+        let checksum = u64::from_le_bytes(data[data.len() - 8..].try_into().unwrap());
+        assert_eq!(checksum, crc64(0, &data[..data.len() - 8]));
+
+        let resize = data.iter().position(|b| *b == OPCODE_RESIZEDB).unwrap();
+        assert_eq!(&data[resize - 2..resize + 3], &[OPCODE_SELECTDB, 0, OPCODE_RESIZEDB, 2, 1]);
+        assert!(!data.windows(4).any(|window| window == b"gone"));
+    }
+
+    fn stream() -> Stream {
+        let mut stream = Stream::default();
+
+        for i in 1..=150u64 {
+            let fields = if i % 3 == 1 {
+                vec![("other".to_string(), i.to_string())]
+            } else {
+                vec![("name".to_string(), format!("n{i}")), ("n".to_string(), i.to_string())]
+            };
+            stream.entries.insert(StreamId::new(1000 + i / 2, i % 2), fields);
+        }
+
+        stream.last_id = StreamId::new(1075, 0);
+        stream.max_deleted_id = StreamId::new(900, 1);
+        stream.entries_added = 160;
+
+        let mut pending = BTreeMap::new();
+        pending.insert(
+            StreamId::new(1001, 0),
+            PendingEntry { delivery_time: 1700000000000, delivery_count: 2, consumer: "alice".to_string() },
+        );
+
+        stream.groups.push(ConsumerGroup {
+            name: "group".to_string(),
+            last_id: StreamId::new(1001, 0),
+            entries_read: None,
+            pending,
+            consumers: vec![Consumer { name: "alice".to_string(), seen_time: 1, active_time: 2 }],
+        });
+
+        stream
+    }
+
+    #[test]
+    fn round_trip() {
+        let values = vec![
+            ("string", Value::String("value \u{ff}".to_string())),
+            ("number", Value::String("-12345".to_string())),
+            ("list", Value::List((0..300).map(|i| format!("item{i}")).collect())),
+            ("set", Value::Set(["a", "b", "12"].iter().map(|member| member.to_string()).collect())),
+            ("hash", Value::Hash([("field".to_string(), "value".to_string())].into_iter().collect())),
+            ("zset", Value::SortedSet(vec![("b".to_string(), -1.5), ("a".to_string(), 2.0)])),
+            ("stream", Value::Stream(stream())),
+            ("cms", Value::CountMinSketch(CountMinSketch::new(4, 2).unwrap())),
+        ];
+
+        let mut store = Store::default();
+        for (key, value) in values {
+            store.insert(key.to_string(), StoreValue::new(value, None));
+        }
+        store.insert("ttl".to_string(), StoreValue::new("x", Some(Duration::from_secs(60))));
+
+        let functions = vec!["#!lua name=lib\nredis.register_function('f', function() end)".to_string()];
+        let snapshot = parse(&encode(&store, &functions)).unwrap();
+        let loaded: HashMap<_, _> = snapshot.entries.into_iter().collect();
+
+        assert_eq!(snapshot.functions, functions);
+        assert_eq!(loaded.len(), 9);
+        assert_eq!(snapshot.aux.get("redis-ver").map(String::as_str), Some(REDIS_VERSION));
+        assert!(loaded["ttl"].expire_time.is_some());
+
+        for (key, value) in store.iter() {
+            assert_eq!(loaded[key].data, value.data, "{key}");
+        }
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let mut store = Store::default();
+        store.insert("key".to_string(), "value".into());
+
+        let mut data = encode(&store, &[]);
+        let last = data.len() - 1;
+        data[last] ^= 1;
+
+        assert!(parse(&data).unwrap_err().to_string().contains("Wrong RDB checksum"));
+
+        data[last - 7..].fill(0);
+        assert_eq!(parse(&data).unwrap().entries.len(), 1);
+    }
+
+    #[test]
+    fn legacy_encodings() {
+        let mut data = b"REDIS0006".to_vec();
+
+        let intset = [&2u32.to_le_bytes()[..], &2u32.to_le_bytes(), &(-3i16).to_le_bytes(), &7i16.to_le_bytes()].concat();
+        data.push(TYPE_SET_INTSET);
+        write_string(&mut data, b"set");
+        write_string(&mut data, &intset);
+
+        data.push(TYPE_HASH_ZIPMAP);
+        write_string(&mut data, b"hash");
+        write_string(&mut data, b"\x01\x03foo\x03\x00bar\xff");
+
+        data.push(TYPE_ZSET);
+        write_string(&mut data, b"zset");
+        data.extend(b"\x02\x01b\x03inf\x01a\x01\x31");
+
+        data.push(OPCODE_EXPIRETIME);
+        data.extend(1u32.to_le_bytes());
+        data.push(TYPE_STRING);
+        write_string(&mut data, b"expired");
+        write_string(&mut data, b"x");
+
+        data.extend([OPCODE_SELECTDB, 1, TYPE_STRING]);
+        write_string(&mut data, b"other");
+        write_string(&mut data, b"db");
+
+        data.push(OPCODE_EOF);
+        data.extend(0u64.to_le_bytes());
+
+        let loaded: HashMap<_, _> = parse(&data).unwrap().entries.into_iter().collect();
+
+        assert_eq!(loaded.len(), 3);
+        assert_eq!(loaded["set"].data, Value::Set(["-3".to_string(), "7".to_string()].into_iter().collect()));
+        assert_eq!(loaded["hash"].data, Value::Hash([("foo".to_string(), "bar".to_string())].into_iter().collect()));
+        assert_eq!(
+            loaded["zset"].data,
+            Value::SortedSet(vec![("a".to_string(), 1.0), ("b".to_string(), f64::INFINITY)])
+        );
+    }
+
+    #[test]
+    fn unsupported_data() {
+        let mut data = b"REDIS0011".to_vec();
+        data.push(TYPE_MODULE_2);
+        write_string(&mut data, b"key");
+        write_len(&mut data, module_id("ReJSON-RL", 3));
+
+        assert_eq!(
+            parse(&data).unwrap_err().to_string(),
+            "Unsupported module data: the RDB file contains module data for the module type 'ReJSON-RL', that is not supported by this server"
+        );
+
+        let mut data = b"REDIS0011".to_vec();
+        data.push(TYPE_MODULE_2);
+        write_string(&mut data, b"key");
+        write_len(&mut data, module_id("MBbloom--", 4));
+        assert!(parse(&data).unwrap_err().to_string().starts_with("Unsupported module data"));
+
+        let mut data = b"REDIS0011".to_vec();
+        data.push(TYPE_MODULE_2);
+        write_string(&mut data, b"key");
+        write_len(&mut data, module_id(MODULE_TYPE_TS, MODULE_ENCVER + 1));
+
+        assert_eq!(
+            parse(&data).unwrap_err().to_string(),
+            "Unsupported module data: encoding version 1 of the module type 'starterTS' is not supported by this server"
+        );
+        assert!(parse(b"REDIS0013\xff").unwrap_err().to_string().contains("Can't handle RDB format version 0013"));
+        assert!(parse(b"RADIS0011\xff").is_err());
+    }
+
+    #[test]
+    fn save_points() {
+        let rdb = Rdb {
+            changes: 5,
+            ..Default::default()
+        };
+
+        assert!(rdb.should_save(&[(0, 5)]));
+        assert!(!rdb.should_save(&[(0, 6)]));
+        assert!(!rdb.should_save(&[(3600, 1)]));
+        assert!(!rdb.should_save(&[]));
+    }
+}
@@ -0,0 +1,872 @@
+//! Master-replica replication
+//!
+//! A replica connects to its master, introduces itself with `REPLCONF` and
+//! asks for the dataset with `PSYNC`. The master answers `+FULLRESYNC`,
+//! sends an RDB snapshot and from then on every write command it executes,
+//! byte for byte as counted by the replication offset.
+//!
+//! The latest part of that stream is kept in a backlog, a replica that lost
+//! its link asks to continue from its offset and, if the master still holds
+//! it, gets `+CONTINUE` and the missing bytes instead of a new snapshot.
+//!
+//! Replicas acknowledge the offset they processed, and fsynced to their
+//! append only file, with `REPLCONF ACK` every second and whenever the
+//! master asks with `REPLCONF GETACK`, which is what `WAIT` and `WAITAOF`
+//! block on.
+//!
+//! With `repl-diskless-sync` the snapshot is streamed to replicas without
+//! touching the disk, framed by a random end marker, `$EOF:<mark>`, instead
+//! of its length. Replicas asking for one within `repl-diskless-sync-delay`
+//! share it, getting the stream since its offset along with it.
+//!
+//! `REPLICAOF` switches the role at runtime. A promoted replica starts a new
+//! history and keeps the previous one as its secondary id, so the other
+//! replicas of its former master can continue from it.
+
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use bytes::{Buf, BytesMut};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+use crate::resp::types::{bytes_to_string, RespType};
+
+use super::aof;
+use super::config::{DisklessLoad, Replication, Role};
+use super::context::Context;
+use super::rdb;
+use super::session::Session;
+use super::shared_context::{lock, SharedContext};
+
+/// Queue of replication stream bytes to a replica, drained by its connection
+pub type ReplicaStream = UnboundedSender<Vec<u8>>;
+
+/// Interval of the `PING`s sent to replicas, `repl-ping-replica-period`
+const PING_PERIOD: Duration = Duration::from_secs(10);
+/// Delay before connecting again to an unreachable master
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+/// Length of the random end marker of diskless snapshots
+const EOF_MARK_LEN: usize = 40;
+/// Interval of the acknowledgements replicas send unprompted
+const ACK_PERIOD: Duration = Duration::from_secs(1);
+
+/// Random 40 characters hex id of a replication history
+pub fn generate_replid() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+    let state = RandomState::new();
+
+    let id: String = (0..3u64)
+        .map(|i| {
+            let mut hasher = state.build_hasher();
+            hasher.write_u64(i);
+            hasher.write_u128(nanos);
+            hasher.write_u32(std::process::id());
+            format!("{:016x}", hasher.finish())
+        })
+        .collect();
+
+    id[..40].to_string()
+}
+
+/// The `$<len>` framed snapshot starting a full resynchronization
+pub fn snapshot_payload(snapshot: &[u8]) -> Vec<u8> {
+    let mut payload = format!("${}\r\n", snapshot.len()).into_bytes();
+    payload.extend(snapshot);
+    payload
+}
+
+/// The `$EOF:<mark>` framed snapshot of a diskless full resynchronization,
+/// followed by its 40 bytes random `mark`
+pub fn eof_payload(snapshot: &[u8]) -> Vec<u8> {
+    let mark = generate_replid();
+    let mut payload = format!("$EOF:{mark}\r\n").into_bytes();
+    payload.extend(snapshot);
+    payload.extend(mark.as_bytes());
+    payload
+}
+
+/// Circular buffer of the latest bytes of the replication stream
+#[derive(Debug)]
+pub struct Backlog {
+    data: VecDeque<u8>,
+    size: usize,
+    /// Replication offset of the last byte held
+    offset: u64,
+}
+
+impl Backlog {
+    /// Empty backlog of `size` bytes continuing the stream at `offset`
+    pub fn new(size: usize, offset: u64) -> Self {
+        Self {
+            data: VecDeque::with_capacity(size),
+            size,
+            offset,
+        }
+    }
+
+    pub fn feed(&mut self, data: &[u8]) {
+        let skip = data.len().saturating_sub(self.size);
+
+        self.data.extend(&data[skip..]);
+        self.offset += data.len() as u64;
+        self.trim();
+    }
+
+    /// Bytes of the stream from `offset` on, `None` once they were overwritten
+    ///
+    /// Offsets count bytes from 1, as in the `PSYNC` requests of replicas.
+    pub fn since(&self, offset: u64) -> Option<Vec<u8>> {
+        let first = self.first_offset();
+
+        if offset < first || offset > self.offset + 1 {
+            return None;
+        }
+
+        Some(self.data.range((offset - first) as usize..).copied().collect())
+    }
+
+    pub fn resize(&mut self, size: usize) {
+        self.size = size;
+        self.trim();
+    }
+
+    fn trim(&mut self) {
+        let excess = self.data.len().saturating_sub(self.size);
+        self.data.drain(..excess);
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Offset of the oldest byte held
+    pub fn first_offset(&self) -> u64 {
+        self.offset + 1 - self.data.len() as u64
+    }
+
+    pub fn histlen(&self) -> usize {
+        self.data.len()
+    }
+}
+
+/// Replica attached to this server
+#[derive(Debug)]
+pub struct Replica {
+    stream: ReplicaStream,
+    pub(crate) ip: String,
+    /// Port the replica accepts clients on, from `REPLCONF listening-port`
+    pub(crate) listening_port: u16,
+    /// Capabilities announced with `REPLCONF capa`
+    pub(crate) capabilities: Vec<String>,
+    /// Offsets the replica acknowledged as processed and as fsynced to its
+    /// append only file
+    pub(crate) ack_offset: u64,
+    pub(crate) aof_offset: u64,
+    pub(crate) last_ack: Instant,
+}
+
+/// Diskless full resynchronization waiting for more replicas before it starts
+#[derive(Debug)]
+struct PendingSync {
+    payload: Vec<u8>,
+    /// Replication offset of the snapshot
+    offset: u64,
+    started: Instant,
+    /// Replication stream since the snapshot
+    stream: Vec<u8>,
+    replicas: Vec<(u64, Replica)>,
+}
+
+/// Master side of replication, the replicas fed by this server
+#[derive(Debug)]
+pub struct Replicas {
+    replicas: HashMap<u64, Replica>,
+    /// Created along with the first replica, on replicas once they synced
+    backlog: Option<Backlog>,
+    pending: Option<PendingSync>,
+    last_ping: Instant,
+    /// Woken up whenever acknowledged offsets move
+    acks: Arc<Notify>,
+}
+
+impl Default for Replicas {
+    fn default() -> Self {
+        Self {
+            replicas: HashMap::new(),
+            backlog: None,
+            pending: None,
+            last_ping: Instant::now(),
+            acks: Arc::new(Notify::new()),
+        }
+    }
+}
+
+impl Replicas {
+    /// Attaches the replica of session `id`, `payload` being what it needs
+    /// before the replication stream, a snapshot or the backlog it missed
+    pub fn add(&mut self, id: u64, mut replica: Replica, payload: Vec<u8>) {
+        if replica.stream.send(payload).is_ok() {
+            replica.capabilities.dedup();
+            self.replicas.insert(id, replica);
+        }
+    }
+
+    /// Attaches the replica of session `id` through a diskless sync, sharing
+    /// the snapshot of the one waiting to start if any
+    ///
+    /// Returns the replication offset of the snapshot, `snapshot` is only
+    /// called when a new one is needed.
+    pub fn add_diskless(
+        &mut self,
+        id: u64,
+        replica: Replica,
+        offset: u64,
+        snapshot: impl FnOnce() -> Vec<u8>,
+        delay: Duration,
+    ) -> u64 {
+        let pending = self.pending.get_or_insert_with(|| PendingSync {
+            payload: eof_payload(&snapshot()),
+            offset,
+            started: Instant::now(),
+            stream: Vec::new(),
+            replicas: Vec::new(),
+        });
+
+        pending.replicas.push((id, replica));
+        let offset = pending.offset;
+
+        self.start_pending_sync(delay);
+
+        offset
+    }
+
+    /// Sends the pending diskless sync to its replicas once it waited `delay`
+    pub fn start_pending_sync(&mut self, delay: Duration) {
+        let pending = match self.pending.take() {
+            Some(pending) if pending.started.elapsed() >= delay => pending,
+            pending => {
+                self.pending = pending;
+                return;
+            }
+        };
+
+        for (id, replica) in pending.replicas {
+            let mut payload = pending.payload.clone();
+            payload.extend(&pending.stream);
+
+            self.add(id, replica, payload);
+        }
+    }
+
+    /// Replicas waiting for a diskless sync to start
+    pub fn waiting(&self) -> impl Iterator<Item = &Replica> {
+        self.pending.iter().flat_map(|pending| pending.replicas.iter().map(|(_, replica)| replica))
+    }
+
+    pub fn remove(&mut self, id: u64) {
+        self.replicas.remove(&id);
+
+        if let Some(pending) = self.pending.as_mut() {
+            pending.replicas.retain(|(replica, _)| *replica != id);
+        }
+    }
+
+    /// Replicas attached, including the ones waiting for a sync to start
+    pub fn len(&self) -> usize {
+        self.replicas.len() + self.waiting().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Closes the links to every replica, they reconnect and learn about a
+    /// new history with `PSYNC`
+    pub fn disconnect(&mut self) {
+        self.replicas.clear();
+        self.pending = None;
+    }
+
+    /// Replicas in the order they attached
+    pub fn iter(&self) -> impl Iterator<Item = &Replica> {
+        let mut ids: Vec<&u64> = self.replicas.keys().collect();
+        ids.sort();
+
+        ids.into_iter().map(|id| &self.replicas[id])
+    }
+
+    /// Records an acknowledgement of the replica of session `id`
+    pub fn ack(&mut self, id: u64, offset: u64, aof_offset: Option<u64>) {
+        if let Some(replica) = self.replicas.get_mut(&id) {
+            replica.ack_offset = replica.ack_offset.max(offset);
+            replica.aof_offset =
+                aof_offset.map_or(replica.aof_offset, |aof| replica.aof_offset.max(aof));
+            replica.last_ack = Instant::now();
+
+            self.acks.notify_waiters();
+        }
+    }
+
+    /// Replicas that processed the stream up to `offset`
+    pub fn acked(&self, offset: u64) -> usize {
+        self.replicas
+            .values()
+            .filter(|replica| replica.ack_offset >= offset)
+            .count()
+    }
+
+    /// Replicas that fsynced the stream up to `offset` to their append only file
+    pub fn fsynced(&self, offset: u64) -> usize {
+        self.replicas
+            .values()
+            .filter(|replica| replica.aof_offset >= offset)
+            .count()
+    }
+
+    /// Notified whenever an acknowledgement arrives or the local append only
+    /// file gets fsynced
+    pub fn acks(&self) -> Arc<Notify> {
+        self.acks.clone()
+    }
+
+    pub fn backlog(&self) -> Option<&Backlog> {
+        self.backlog.as_ref()
+    }
+
+    /// Starts keeping a backlog at `offset` unless there is one already
+    pub fn create_backlog(&mut self, size: usize, offset: u64) {
+        self.backlog.get_or_insert_with(|| Backlog::new(size, offset));
+    }
+
+    /// Discards the backlog for an empty one at `offset`, the history it
+    /// held is no longer the one of the dataset
+    pub fn reset_backlog(&mut self, size: usize, offset: u64) {
+        self.backlog = Some(Backlog::new(size, offset));
+    }
+
+    pub fn resize_backlog(&mut self, size: usize) {
+        if let Some(backlog) = self.backlog.as_mut() {
+            backlog.resize(size);
+        }
+    }
+
+    /// Sends `data` to every replica, dropping the ones that disconnected
+    pub fn feed(&mut self, data: &[u8]) {
+        if let Some(backlog) = self.backlog.as_mut() {
+            backlog.feed(data);
+        }
+
+        if let Some(pending) = self.pending.as_mut() {
+            pending.stream.extend(data);
+        }
+
+        self.replicas.retain(|_, replica| replica.stream.send(data.to_vec()).is_ok());
+    }
+
+    /// Whether replicas are due a `PING`, so they can tell the link is alive
+    pub fn should_ping(&mut self) -> bool {
+        if self.is_empty() || self.last_ping.elapsed() < PING_PERIOD {
+            return false;
+        }
+
+        self.last_ping = Instant::now();
+        true
+    }
+}
+
+impl Replica {
+    pub fn new(stream: ReplicaStream, ip: String, listening_port: u16, capabilities: Vec<String>) -> Self {
+        Self {
+            stream,
+            ip,
+            listening_port,
+            capabilities,
+            ack_offset: 0,
+            aof_offset: 0,
+            last_ack: Instant::now(),
+        }
+    }
+}
+
+/// State of the link of a replica to its master
+#[derive(Debug, Default)]
+pub struct MasterLinkStatus {
+    /// Whether the replication stream flows
+    pub(crate) up: bool,
+    /// Set while a snapshot is transferred and loaded
+    pub(crate) sync_in_progress: bool,
+    /// Last time something was read from the master
+    pub(crate) last_io: Option<Instant>,
+    /// When the link was lost, `None` if it was never up
+    pub(crate) down_since: Option<Instant>,
+    /// Whether the dataset matches the history of the master up to our
+    /// offset, so a lost link can be resumed with a partial resynchronization
+    pub(crate) synced: bool,
+    task: Option<JoinHandle<()>>,
+}
+
+impl MasterLinkStatus {
+    fn disconnected(&mut self) {
+        if self.up {
+            self.down_since = Some(Instant::now());
+        }
+
+        self.up = false;
+        self.sync_in_progress = false;
+    }
+
+    /// Stops replicating, keeping whether the dataset is synced
+    fn stop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+
+        self.disconnected();
+        self.last_io = None;
+    }
+}
+
+/// Makes the server a replica of `host:port`, replacing the link to its
+/// previous master if any
+///
+/// A former master resumes its own history from the new master, which can
+/// continue it if it was one of its replicas.
+pub fn replicate_from(context: &SharedContext, host: String, port: u16) {
+    follow(&mut lock(context), host, port);
+}
+
+/// [`replicate_from`] for callers already holding the context
+pub fn follow(ctx: &mut Context, host: String, port: u16) {
+    if ctx.config.role == Role::Master {
+        ctx.master_link.synced = true;
+    }
+
+    ctx.master_link.stop();
+    ctx.master_link.down_since = None;
+    ctx.config.role = Role::Slave;
+    ctx.config.replication = Some(Replication {
+        host: host.clone(),
+        port,
+    });
+    ctx.replicas.disconnect();
+
+    if let Some(context) = ctx.shared.upgrade() {
+        ctx.master_link.task = Some(tokio::spawn(replicate(context, host, port)));
+    }
+}
+
+/// Turns a replica into a master, `REPLICAOF NO ONE`
+///
+/// The history of the former master stays valid up to the current offset,
+/// so its other replicas can continue from this server.
+pub fn promote(ctx: &mut Context) {
+    if ctx.config.role == Role::Master {
+        return;
+    }
+
+    ctx.master_link.stop();
+    ctx.master_link.down_since = None;
+    ctx.config.role = Role::Master;
+    ctx.config.replication = None;
+    ctx.config.shift_replid(generate_replid());
+
+    let (size, offset) = (ctx.config.repl_backlog_size as usize, ctx.config.master_repl_offset);
+    ctx.replicas.create_backlog(size, offset);
+    ctx.replicas.disconnect();
+}
+
+/// Replicates the master at `host:port` until the role changes, connecting
+/// again whenever the link breaks
+async fn replicate(context: SharedContext, host: String, port: u16) {
+    loop {
+        if let Err(err) = sync_with_master(&context, &host, port).await {
+            eprintln!("err: replicating {host}:{port}: {err}");
+        }
+
+        lock(&context).master_link.disconnected();
+
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+/// Connection to the master, buffering what was read past the last reply
+struct MasterLink {
+    stream: TcpStream,
+    buffer: BytesMut,
+    context: SharedContext,
+}
+
+impl MasterLink {
+    async fn fill(&mut self) -> Result<()> {
+        if self.stream.read_buf(&mut self.buffer).await? == 0 {
+            return Err(anyhow!("connection closed by the master"));
+        }
+
+        lock(&self.context).master_link.last_io = Some(Instant::now());
+
+        Ok(())
+    }
+
+    /// Next line sent by the master, without its terminator
+    async fn line(&mut self) -> Result<String> {
+        loop {
+            if let Some(end) = self.buffer.iter().position(|byte| *byte == b'\n') {
+                let line = bytes_to_string(&self.buffer[..end]);
+                self.buffer.advance(end + 1);
+
+                return Ok(line.trim_end_matches('\r').to_string());
+            }
+
+            self.fill().await?;
+        }
+    }
+
+    /// Sends `command` and returns the status line answering it
+    async fn request(&mut self, command: &[&str]) -> Result<String> {
+        let command: Vec<String> = command.iter().map(|arg| arg.to_string()).collect();
+        self.stream.write_all(aof::encode(&command).as_bytes()).await?;
+
+        let reply = self.line().await?;
+
+        match reply.strip_prefix('-') {
+            Some(err) => Err(anyhow!("{} failed: {err}", command.join(" "))),
+            None => Ok(reply),
+        }
+    }
+
+    async fn bytes(&mut self, len: usize) -> Result<Vec<u8>> {
+        while self.buffer.len() < len {
+            self.fill().await?;
+        }
+
+        Ok(self.buffer.split_to(len).to_vec())
+    }
+
+    /// Bytes up to the end `mark` of a diskless snapshot, which is skipped
+    async fn until(&mut self, mark: &[u8]) -> Result<Vec<u8>> {
+        let mut searched = 0;
+
+        loop {
+            if let Some(end) = self.buffer[searched..].windows(mark.len()).position(|window| window == mark) {
+                let data = self.buffer.split_to(searched + end).to_vec();
+                self.buffer.advance(mark.len());
+
+                return Ok(data);
+            }
+
+            searched = self.buffer.len().saturating_sub(mark.len() - 1);
+            self.fill().await?;
+        }
+    }
+}
+
+async fn sync_with_master(context: &SharedContext, host: &str, port: u16) -> Result<()> {
+    let stream = TcpStream::connect((host, port)).await?;
+    let mut link = MasterLink {
+        stream,
+        buffer: BytesMut::with_capacity(4096),
+        context: context.clone(),
+    };
+
+    let (listening_port, replid, offset) = {
+        let context = lock(context);
+        let config = &context.config;
+
+        match context.master_link.synced {
+            true => (config.port, config.master_replid.clone(), (config.master_repl_offset + 1).to_string()),
+            false => (config.port, "?".to_string(), "-1".to_string()),
+        }
+    };
+
+    link.request(&["PING"]).await?;
+    link.request(&["REPLCONF", "listening-port", &listening_port.to_string()]).await?;
+    link.request(&["REPLCONF", "capa", "eof", "capa", "psync2"]).await?;
+
+    let reply = link.request(&["PSYNC", &replid, &offset]).await?;
+    let (replid, offset) = match reply.split(' ').collect::<Vec<_>>()[..] {
+        ["+FULLRESYNC", replid, offset] => (replid.to_string(), offset.parse::<u64>()?),
+        ["+CONTINUE", ..] => {
+            let new = reply.split(' ').nth(1).map(str::to_string);
+
+            // The master moved to a new history, like after a failover
+            if let Some(new) = new {
+                let config = &mut lock(context).config;
+
+                if new != config.master_replid {
+                    config.shift_replid(new);
+                }
+            }
+
+            eprintln!("MASTER <-> REPLICA sync: Master accepted a Partial Resynchronization.");
+
+            return stream_from_master(context, link).await;
+        }
+        _ => return Err(anyhow!("unexpected reply to PSYNC: {reply}")),
+    };
+
+    {
+        let mut context = lock(context);
+
+        context.master_link.synced = false;
+        context.master_link.sync_in_progress = true;
+    }
+
+    // The master sends newlines to keep the link alive while it prepares the snapshot
+    let header = loop {
+        let line = link.line().await?;
+
+        if !line.is_empty() {
+            break line;
+        }
+    };
+
+    let (diskless, path) = {
+        let context = lock(context);
+        let diskless = match context.config.repl_diskless_load {
+            DisklessLoad::Disabled => false,
+            DisklessLoad::OnEmptyDb => context.store.iter().next().is_none(),
+            DisklessLoad::Swapdb => true,
+        };
+
+        (diskless, context.rdb_path())
+    };
+
+    let data = match header.strip_prefix("$EOF:") {
+        Some(mark) if mark.len() == EOF_MARK_LEN => link.until(mark.as_bytes()).await?,
+        _ => {
+            let len = header
+                .strip_prefix('$')
+                .and_then(|len| len.parse::<usize>().ok())
+                .ok_or_else(|| anyhow!("unexpected snapshot header from the master: {header}"))?;
+
+            link.bytes(len).await?
+        }
+    };
+
+    // Without diskless load the snapshot goes through the RDB file, which
+    // then holds the dataset of the master
+    let snapshot = match diskless {
+        true => {
+            eprintln!("MASTER <-> REPLICA sync: Loading {} bytes straight from the socket", data.len());
+            rdb::parse(&data)?
+        }
+        false => {
+            eprintln!("MASTER <-> REPLICA sync: Saving {} bytes to disk", data.len());
+            rdb::write_file(&path, &data)?;
+            drop(data);
+
+            rdb::parse(&fs::read(&path)?)?
+        }
+    };
+
+    {
+        let mut context = lock(context);
+
+        context.store.clear();
+        for (key, value) in snapshot.entries {
+            context.store.insert(key, value);
+        }
+        context.invalidate(None);
+
+        context.functions.flush();
+        for code in &snapshot.functions {
+            if let Err(err) = context.functions.load(code, true) {
+                eprintln!("err: loading a library of the master: {err}");
+            }
+        }
+
+        let size = context.config.repl_backlog_size as usize;
+
+        context.config.master_replid = replid;
+        context.config.master_repl_offset = offset;
+        context.config.clear_replid2();
+        context.replicas.reset_backlog(size, offset);
+        context.master_link.synced = true;
+    }
+
+    eprintln!("MASTER <-> REPLICA sync: finished with success");
+
+    stream_from_master(context, link).await
+}
+
+/// `REPLCONF ACK <offset> FACK <aof offset>` for the current offsets
+fn ack(context: &SharedContext) -> Vec<u8> {
+    let context = lock(context);
+    let command = [
+        "REPLCONF".to_string(),
+        "ACK".to_string(),
+        context.config.master_repl_offset.to_string(),
+        "FACK".to_string(),
+        context.aof_fsynced_offset.to_string(),
+    ];
+
+    aof::encode(&command).into_bytes()
+}
+
+fn is_getack(frame: &RespType) -> bool {
+    let RespType::Array { values, .. } = frame else {
+        return false;
+    };
+
+    match &values[..] {
+        [RespType::BulkString { value: command, .. }, RespType::BulkString {
+            value: subcommand, ..
+        }, ..] => {
+            command.eq_ignore_ascii_case("replconf") && subcommand.eq_ignore_ascii_case("getack")
+        }
+        _ => false,
+    }
+}
+
+/// Applies the commands the master streams, forwarding them as received to
+/// the replicas of this server
+async fn stream_from_master(context: &SharedContext, mut link: MasterLink) -> Result<()> {
+    let mut session = Session::new();
+    session.is_master = true;
+
+    {
+        let mut context = lock(context);
+
+        context.master_link.up = true;
+        context.master_link.sync_in_progress = false;
+    }
+
+    let mut acks = tokio::time::interval(ACK_PERIOD);
+
+    let result = loop {
+        match RespType::parse(&link.buffer) {
+            Ok(Some((frame, used))) => {
+                let raw = link.buffer.split_to(used);
+
+                // The acknowledged offset doesn't include the request for it
+                if is_getack(&frame) {
+                    if let Err(err) = link.stream.write_all(&ack(context)).await {
+                        break Err(err.into());
+                    }
+                } else {
+                    session.handle(frame, context);
+                }
+
+                lock(context).feed_replicas(&raw);
+            }
+            Ok(None) => {
+                tokio::select! {
+                    filled = link.fill() => {
+                        if let Err(err) = filled {
+                            break Err(err);
+                        }
+                    }
+                    _ = acks.tick() => {
+                        if let Err(err) = link.stream.write_all(&ack(context)).await {
+                            break Err(err.into());
+                        }
+                    }
+                }
+            }
+            Err(err) => break Err(anyhow!("bad replication stream from the master: {err}")),
+        }
+    };
+
+    session.close(context);
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replids_are_random_hex() {
+        let first = generate_replid();
+
+        assert_eq!(first.len(), 40);
+        assert!(first.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(first, generate_replid());
+    }
+
+    #[test]
+    fn feeding_drops_disconnected_replicas() {
+        let mut replicas = Replicas::default();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let (closed, _) = tokio::sync::mpsc::unbounded_channel();
+
+        replicas.add(1, Replica::new(tx, "127.0.0.1".to_string(), 6380, vec![]), snapshot_payload(b"REDIS"));
+        replicas.add(2, Replica::new(closed, "127.0.0.1".to_string(), 6381, vec![]), snapshot_payload(b"REDIS"));
+        assert_eq!(replicas.len(), 1);
+
+        replicas.feed(b"*1\r\n$4\r\nPING\r\n");
+
+        assert_eq!(rx.try_recv().unwrap(), b"$5\r\nREDIS");
+        assert_eq!(rx.try_recv().unwrap(), b"*1\r\n$4\r\nPING\r\n");
+
+        drop(rx);
+        replicas.feed(b"*1\r\n$4\r\nPING\r\n");
+        assert!(replicas.is_empty());
+    }
+
+    #[test]
+    fn backlog_keeps_latest_bytes() {
+        let mut backlog = Backlog::new(8, 100);
+
+        assert_eq!(backlog.since(101), Some(vec![]));
+        assert_eq!(backlog.since(100), None);
+
+        backlog.feed(b"hello");
+        backlog.feed(b" world");
+
+        assert_eq!(backlog.histlen(), 8);
+        assert_eq!(backlog.first_offset(), 104);
+        assert_eq!(backlog.since(104).unwrap(), b"lo world");
+        assert_eq!(backlog.since(109).unwrap(), b"rld");
+        assert_eq!(backlog.since(112).unwrap(), b"");
+        assert_eq!(backlog.since(103), None);
+        assert_eq!(backlog.since(113), None);
+
+        backlog.feed(b"a much longer write");
+        assert_eq!(backlog.since(backlog.first_offset()).unwrap(), b"er write");
+
+        backlog.resize(3);
+        assert_eq!(backlog.since(backlog.first_offset()).unwrap(), b"ite");
+    }
+
+    #[test]
+    fn diskless_syncs_share_snapshots() {
+        let mut replicas = Replicas::default();
+        let (first, mut first_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (second, mut second_rx) = tokio::sync::mpsc::unbounded_channel();
+        let delay = Duration::from_secs(60);
+
+        let replica = Replica::new(first, "127.0.0.1".to_string(), 6380, vec![]);
+        assert_eq!(replicas.add_diskless(1, replica, 10, || b"REDIS".to_vec(), delay), 10);
+        replicas.feed(b"*1\r\n$4\r\nPING\r\n");
+
+        let replica = Replica::new(second, "127.0.0.1".to_string(), 6381, vec![]);
+        let offset = replicas.add_diskless(2, replica, 24, || panic!("the snapshot is shared"), delay);
+        assert_eq!(offset, 10);
+        assert_eq!(replicas.waiting().count(), 2);
+        assert!(first_rx.try_recv().is_err());
+
+        replicas.start_pending_sync(Duration::ZERO);
+        assert_eq!(replicas.waiting().count(), 0);
+        assert_eq!(replicas.iter().count(), 2);
+
+        let payload = first_rx.try_recv().unwrap();
+        assert_eq!(payload, second_rx.try_recv().unwrap());
+
+        let mark = &payload[5..5 + EOF_MARK_LEN];
+        let mut expected = format!("$EOF:{}\r\nREDIS", bytes_to_string(mark)).into_bytes();
+        expected.extend(mark);
+        expected.extend(b"*1\r\n$4\r\nPING\r\n");
+        assert_eq!(payload, expected);
+    }
+}
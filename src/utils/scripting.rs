@@ -0,0 +1,1031 @@
+//! Lua scripts run by `EVAL` and `EVALSHA`, and functions run by `FCALL`
+//!
+//! Scripts run atomically: the command holds the lock on the context for
+//! the whole run, and `redis.call` executes commands straight against it.
+//! Other sessions can't take the lock meanwhile, so what they need to know
+//! about the running script, to reply `BUSY` or to kill it, is kept in a
+//! process-wide state instead.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::commands::Command;
+use crate::lua::{self, FunctionBody, Host, Lua, LuaError, Table, TableRef, Value};
+use crate::resp::{
+    errors::Error,
+    types::{bytes_to_string, string_to_bytes, RespType},
+};
+
+use super::acl;
+use super::config::Role;
+use super::context::Context;
+use super::functions::{self, Function, Library, Registered};
+use super::hash::sha1_hex;
+use super::shared_context::blocking;
+
+/// Name of the chunk of scripts in error messages
+const CHUNK_NAME: &str = "user_script";
+
+/// Name of the chunk of function libraries in error messages
+pub(super) const FUNCTION_CHUNK_NAME: &str = "user_function";
+
+/// Stack of the thread scripts run on
+const SCRIPT_STACK_SIZE: usize = 64 * 1024 * 1024;
+
+/// Commands scripts can't call
+const NOSCRIPT_COMMANDS: [&str; 35] = [
+    "eval",
+    "evalsha",
+    "eval_ro",
+    "evalsha_ro",
+    "script",
+    "fcall",
+    "fcall_ro",
+    "function",
+    "multi",
+    "exec",
+    "discard",
+    "watch",
+    "unwatch",
+    "subscribe",
+    "unsubscribe",
+    "psubscribe",
+    "punsubscribe",
+    "ssubscribe",
+    "sunsubscribe",
+    "wait",
+    "waitaof",
+    "psync",
+    "replconf",
+    "replicaof",
+    "slaveof",
+    "migrate",
+    "client",
+    "config",
+    "save",
+    "bgsave",
+    "bgrewriteaof",
+    "sentinel",
+    "auth",
+    "hello",
+    "acl",
+];
+
+/// Functions of the `redis` table, run by [`ScriptHost`]
+const REDIS_FUNCTIONS: [&str; 9] = [
+    "call",
+    "pcall",
+    "sha1hex",
+    "error_reply",
+    "status_reply",
+    "log",
+    "setresp",
+    "set_repl",
+    "replicate_commands",
+];
+
+/// Flags declared in the `#!lua flags=...` line starting a script
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ScriptFlags {
+    /// The script only reads, it may run on read only replicas and with
+    /// `EVAL_RO`
+    pub no_writes: bool,
+    pub allow_oom: bool,
+    pub allow_stale: bool,
+    /// The script refuses to run in cluster mode
+    pub no_cluster: bool,
+    pub allow_cross_slot_keys: bool,
+}
+
+impl ScriptFlags {
+    /// Parses flag names, returning the first unknown one on failure
+    pub fn parse<'a>(names: impl Iterator<Item = &'a str>) -> Result<Self, String> {
+        let mut flags = Self::default();
+
+        for name in names {
+            match name {
+                "no-writes" => flags.no_writes = true,
+                "allow-oom" => flags.allow_oom = true,
+                "allow-stale" => flags.allow_stale = true,
+                "no-cluster" => flags.no_cluster = true,
+                "allow-cross-slot-keys" => flags.allow_cross_slot_keys = true,
+                name => return Err(name.to_string()),
+            }
+        }
+
+        Ok(flags)
+    }
+
+    /// Names of the flags set, as listed by `FUNCTION LIST`
+    pub fn names(&self) -> Vec<&'static str> {
+        [
+            (self.no_writes, "no-writes"),
+            (self.allow_oom, "allow-oom"),
+            (self.allow_stale, "allow-stale"),
+            (self.no_cluster, "no-cluster"),
+            (self.allow_cross_slot_keys, "allow-cross-slot-keys"),
+        ]
+        .into_iter()
+        .filter_map(|(set, name)| set.then_some(name))
+        .collect()
+    }
+}
+
+/// A compiled script
+#[derive(Debug, Clone)]
+pub struct CachedScript {
+    pub body: Arc<FunctionBody>,
+    /// Flags of the shebang line, `None` for scripts without one which
+    /// run with the permissive defaults of older versions
+    pub flags: Option<ScriptFlags>,
+}
+
+/// Scripts loaded by `EVAL` and `SCRIPT LOAD`, by the SHA1 of their source
+#[derive(Debug, Default)]
+pub struct ScriptCache {
+    scripts: HashMap<String, CachedScript>,
+}
+
+impl ScriptCache {
+    /// Compiles and caches `source`, returning its SHA1
+    pub fn load(&mut self, source: &str) -> Result<String, Error> {
+        let sha = sha1_hex(&string_to_bytes(source));
+
+        if !self.scripts.contains_key(&sha) {
+            let script = compile(source)?;
+
+            self.scripts.insert(sha.clone(), script);
+        }
+
+        Ok(sha)
+    }
+
+    pub fn get(&self, sha: &str) -> Option<&CachedScript> {
+        self.scripts.get(&sha.to_lowercase())
+    }
+
+    pub fn contains(&self, sha: &str) -> bool {
+        self.scripts.contains_key(&sha.to_lowercase())
+    }
+
+    pub fn flush(&mut self) {
+        self.scripts.clear();
+    }
+}
+
+/// Compiles a script, after its optional shebang line
+fn compile(source: &str) -> Result<CachedScript, Error> {
+    let bytes = string_to_bytes(source);
+    let (flags, code) = match bytes.strip_prefix(b"#!") {
+        Some(_) => {
+            let end = bytes.iter().position(|b| *b == b'\n').unwrap_or(bytes.len());
+            let shebang = bytes_to_string(&bytes[2..end]);
+            let mut words = shebang.split_whitespace();
+
+            match words.next() {
+                Some("lua") => {}
+                _ => {
+                    return Err(Error::custom(format!("ERR Unexpected engine in script shebang: {shebang}")));
+                }
+            }
+
+            let mut flags = ScriptFlags::default();
+
+            for word in words {
+                let Some(names) = word.strip_prefix("flags=") else {
+                    return Err(Error::custom(format!("ERR Unknown lua shebang option: {word}")));
+                };
+
+                flags = ScriptFlags::parse(names.split(',').filter(|name| !name.is_empty()))
+                    .map_err(|flag| Error::custom(format!("ERR Unexpected flag in script shebang: {flag}")))?;
+            }
+
+            // The shebang line stays as an empty one so line numbers match
+            (Some(flags), &bytes[end..])
+        }
+        None => (None, &bytes[..]),
+    };
+
+    let body = lua::parse(code, CHUNK_NAME)
+        .map_err(|err| Error::custom(format!("ERR Error compiling script (new function): {err}")))?;
+
+    Ok(CachedScript { body, flags })
+}
+
+/// The script holding the context, as seen by other sessions
+#[derive(Debug)]
+struct Running {
+    started: Instant,
+    threshold: Duration,
+    /// Set once the script wrote, it can't be killed anymore
+    wrote: bool,
+    /// Set by `SCRIPT KILL`, the script stops at its next check
+    killed: bool,
+    /// Set for functions, which are killed by `FUNCTION KILL` instead
+    function: bool,
+}
+
+static RUNNING: Mutex<Option<Running>> = Mutex::new(None);
+
+/// Whether a script has been running for longer than `busy-reply-threshold`
+pub fn is_busy() -> bool {
+    RUNNING
+        .lock()
+        .unwrap()
+        .as_ref()
+        .is_some_and(|running| running.started.elapsed() >= running.threshold)
+}
+
+/// Reply to commands sent while a script is busy
+pub fn busy_error() -> Error {
+    let function = RUNNING.lock().unwrap().as_ref().is_some_and(|running| running.function);
+
+    slow_error(function)
+}
+
+fn slow_error(function: bool) -> Error {
+    let kill = if function { "FUNCTION" } else { "SCRIPT" };
+
+    Error::custom(format!(
+        "BUSY Redis is busy running a script. You can only call {kill} KILL or SHUTDOWN NOSAVE."
+    ))
+}
+
+/// `SCRIPT KILL` and `FUNCTION KILL` when `function` is set, stops the
+/// running script unless it already wrote
+pub fn kill(function: bool) -> RespType {
+    match RUNNING.lock().unwrap().as_mut() {
+        None => Error::custom("NOTBUSY No scripts in execution right now.").into(),
+        Some(running) if running.wrote => Error::custom(
+            "UNKILLABLE Sorry the script already executed write commands against the dataset. You can either wait the script termination or kill the server in a hard way using the SHUTDOWN NOSAVE command.",
+        )
+        .into(),
+        // Each kind of script is killed by its own command
+        Some(running) if running.function != function => slow_error(running.function).into(),
+        Some(running) => {
+            running.killed = true;
+
+            RespType::ok()
+        }
+    }
+}
+
+/// Outcome of a script run
+pub struct Execution {
+    pub reply: RespType,
+    /// Write commands the script executed, replicated in its place
+    pub propagated: Vec<Vec<String>>,
+}
+
+/// What runs, as named in its errors
+#[derive(Debug, Clone, Copy)]
+enum Origin<'a> {
+    /// A script, by its SHA1
+    Script(&'a str),
+    /// A function of a library, by its name
+    Function(&'a str),
+}
+
+impl Origin<'_> {
+    fn chunk(&self) -> &'static str {
+        match self {
+            Origin::Script(_) => CHUNK_NAME,
+            Origin::Function(_) => FUNCTION_CHUNK_NAME,
+        }
+    }
+}
+
+/// Runs a cached script with `KEYS` and `ARGV` set
+///
+/// `read_only` is set by `EVAL_RO` and `EVALSHA_RO`, the commands of the
+/// script must be allowed to `user` when there is one.
+pub fn eval(
+    ctx: &mut Context,
+    script: &CachedScript,
+    sha: &str,
+    keys: &[String],
+    args: &[String],
+    read_only: bool,
+    user: Option<&str>,
+) -> Execution {
+    run(ctx, script.flags, read_only, user, Origin::Script(sha), |lua| {
+        lua.set_global("KEYS", strings_table(keys));
+        lua.set_global("ARGV", strings_table(args));
+        open_redis(lua);
+        sandbox(lua);
+
+        lua.run(&script.body, Vec::new())
+    })
+}
+
+/// Runs `function` of `library`, which gets the keys and the arguments as
+/// its two parameters
+///
+/// `read_only` is set by `FCALL_RO`, `user` is checked like for scripts.
+pub fn fcall(
+    ctx: &mut Context,
+    library: &Library,
+    function: &Function,
+    keys: &[String],
+    args: &[String],
+    read_only: bool,
+    user: Option<&str>,
+) -> Execution {
+    run(ctx, Some(function.flags), read_only, user, Origin::Function(&function.name), |lua| {
+        let registry: Rc<RefCell<Vec<Registered>>> = Rc::default();
+
+        open_redis(lua)
+            .borrow_mut()
+            .set_str("register_function", functions::register_function(Rc::clone(&registry)));
+        sandbox(lua);
+
+        // Interpreter values don't outlive a run, the library registers its
+        // functions again
+        lua.run(&library.body, Vec::new())?;
+
+        let callback = registry
+            .borrow()
+            .iter()
+            .find(|registered| registered.function.name == function.name)
+            .map(|registered| registered.callback.clone())
+            .ok_or_else(|| raise("Function not found"))?;
+
+        lua.call(&callback, vec![strings_table(keys), strings_table(args)])
+    })
+}
+
+/// Runs `script` in an interpreter for the command of a client
+fn run(
+    ctx: &mut Context,
+    flags: Option<ScriptFlags>,
+    read_only: bool,
+    user: Option<&str>,
+    origin: Origin<'_>,
+    script: impl FnOnce(&mut Lua<'_>) -> Result<Vec<Value>, LuaError> + Send,
+) -> Execution {
+    if let Some(flags) = flags {
+        if let Err(err) = check_flags(ctx, flags, read_only) {
+            return Execution {
+                reply: err.into(),
+                propagated: Vec::new(),
+            };
+        }
+    }
+
+    *RUNNING.lock().unwrap() = Some(Running {
+        started: Instant::now(),
+        threshold: Duration::from_millis(ctx.config.busy_reply_threshold),
+        wrote: false,
+        killed: false,
+        function: matches!(origin, Origin::Function(_)),
+    });
+
+    let mut host = ScriptHost {
+        ctx,
+        read_only: read_only || flags.is_some_and(|flags| flags.no_writes),
+        user,
+        protocol: 2,
+        propagated: Vec::new(),
+    };
+
+    // Other connections keep being served meanwhile, to reply `BUSY`
+    let reply = blocking(|| {
+        on_script_stack(|| match script(&mut Lua::new(&mut host, origin.chunk())) {
+            Ok(values) => to_resp(&values.into_iter().next().unwrap_or_default()),
+            Err(err) => script_error(&err, origin),
+        })
+    });
+
+    *RUNNING.lock().unwrap() = None;
+
+    Execution {
+        reply,
+        propagated: host.propagated,
+    }
+}
+
+/// Runs `f` on a thread of its own, with a stack deep enough for the
+/// nesting the interpreter and `cjson` allow even in debug builds, which
+/// the stacks of the runtime workers are not
+fn on_script_stack<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    std::thread::scope(|scope| {
+        let thread = std::thread::Builder::new()
+            .name("script".to_string())
+            .stack_size(SCRIPT_STACK_SIZE)
+            .spawn_scoped(scope, f)
+            .expect("spawning the script thread");
+
+        thread.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+/// Refuses to run scripts whose flags don't allow it right now
+fn check_flags(ctx: &Context, flags: ScriptFlags, read_only: bool) -> Result<(), Error> {
+    if flags.no_cluster && ctx.cluster.is_some() {
+        return Err(Error::custom("ERR Can not run script on cluster, 'no-cluster' flag is set."));
+    }
+
+    if !flags.no_writes {
+        if read_only {
+            return Err(Error::custom("ERR Can not execute a script with write flag using *_ro command."));
+        }
+
+        if ctx.config.role == Role::Slave && ctx.config.replica_read_only {
+            return Err(Error::custom("READONLY You can't write against a read only replica."));
+        }
+    }
+
+    Ok(())
+}
+
+/// Message of an error raised by a script
+pub(super) fn error_message(err: &LuaError) -> Option<String> {
+    match &err.value {
+        Value::String(message) => Some(format!("ERR {}", bytes_to_string(message))),
+        Value::Table(table) => match table.borrow().get_str("err") {
+            Value::String(message) => Some(bytes_to_string(&message)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Reply of a script that raised an error, with where it happened
+fn script_error(err: &LuaError, origin: Origin<'_>) -> RespType {
+    let name = match origin {
+        Origin::Script(name) | Origin::Function(name) => name,
+    };
+    let location = format!("script: {name}, on @{}:{}.", origin.chunk(), err.line);
+    let message = error_message(err).unwrap_or_else(|| "ERR unknown error".to_string());
+
+    Error::custom(format!("{message} {location}")).into()
+}
+
+fn strings_table(values: &[String]) -> Value {
+    let values = values.iter().map(|value| Value::string(string_to_bytes(value))).collect();
+
+    Value::table(Table::from_array(values))
+}
+
+/// Adds the `redis` table
+pub(super) fn open_redis(lua: &mut Lua<'_>) -> TableRef {
+    let table = lua.register("redis", &REDIS_FUNCTIONS);
+    let mut redis = table.borrow_mut();
+    let constants = [
+        ("LOG_DEBUG", 0.0),
+        ("LOG_VERBOSE", 1.0),
+        ("LOG_NOTICE", 2.0),
+        ("LOG_WARNING", 3.0),
+        ("REPL_NONE", 0.0),
+        ("REPL_AOF", 1.0),
+        ("REPL_SLAVE", 2.0),
+        ("REPL_REPLICA", 2.0),
+        ("REPL_ALL", 3.0),
+    ];
+
+    for (name, value) in constants {
+        redis.set_str(name, Value::Number(value));
+    }
+
+    drop(redis);
+
+    table
+}
+
+/// Makes the globals and libraries read only, and reading an undefined
+/// global an error, so scripts can't leak state into each other
+pub(super) fn sandbox(lua: &mut Lua<'_>) {
+    let globals = lua.globals.clone();
+    let mut key = Value::Nil;
+
+    while let Ok(Some((name, value))) = globals.borrow().next(&key) {
+        match value {
+            Value::Table(library) if !Rc::ptr_eq(&library, &globals) => library.borrow_mut().readonly = true,
+            _ => {}
+        }
+
+        key = name;
+    }
+
+    let mut metatable = Table::new();
+
+    metatable.set_str(
+        "__index",
+        Value::native("__index", |lua, args| {
+            let name = args.get(1).and_then(Value::to_bytes).unwrap_or_default();
+
+            Err(lua.error(format!(
+                "Script attempted to access nonexistent global variable '{}'",
+                bytes_to_string(&name)
+            )))
+        }),
+    );
+
+    let mut globals = globals.borrow_mut();
+
+    globals.metatable = Some(Rc::new(RefCell::new(metatable)));
+    globals.readonly = true;
+}
+
+/// `{err = message}`, how errors of commands reach scripts
+fn error_table(message: impl AsRef<[u8]>) -> Value {
+    let mut table = Table::new();
+
+    table.set_str("err", Value::string(message));
+
+    Value::table(table)
+}
+
+/// Converts the reply of a command for a script, following the RESP2 or
+/// RESP3 rules chosen with `redis.setresp`
+fn to_lua(reply: RespType, protocol: u8) -> Value {
+    match reply {
+        RespType::Integer(n) => Value::Number(n as f64),
+        RespType::BulkString { value, .. } => Value::string(string_to_bytes(&value)),
+        RespType::SimpleString { value } => {
+            let mut table = Table::new();
+
+            table.set_str("ok", Value::string(string_to_bytes(&value)));
+
+            Value::table(table)
+        }
+        RespType::SimpleError(err) => error_table(string_to_bytes(&err.to_string())),
+        RespType::Null | RespType::NullArray if protocol >= 3 => Value::Nil,
+        RespType::Null | RespType::NullArray => Value::Boolean(false),
+        RespType::Array { values, .. } | RespType::Push(values) => {
+            let mut table = Table::new();
+
+            table.set_array(values.into_iter().map(|value| to_lua(value, protocol)).collect());
+
+            Value::table(table)
+        }
+        RespType::Map(entries) if protocol >= 3 => {
+            let mut map = Table::new();
+
+            for (key, value) in entries {
+                let _ = map.set(to_lua(key, protocol), to_lua(value, protocol));
+            }
+
+            let mut table = Table::new();
+
+            table.set_str("map", Value::table(map));
+
+            Value::table(table)
+        }
+        RespType::Map(entries) => {
+            let values = entries
+                .into_iter()
+                .flat_map(|(key, value)| [to_lua(key, protocol), to_lua(value, protocol)])
+                .collect();
+
+            Value::table(Table::from_array(values))
+        }
+    }
+}
+
+/// Converts what a script returned to its reply
+fn to_resp(value: &Value) -> RespType {
+    match value {
+        Value::Number(n) => RespType::Integer(*n as i64),
+        Value::String(s) => RespType::bulk_string(bytes_to_string(s)),
+        Value::Boolean(true) => RespType::Integer(1),
+        Value::Table(table) => {
+            let table = table.borrow();
+
+            if let Value::String(err) = table.get_str("err") {
+                return Error::custom(bytes_to_string(&err)).into();
+            }
+
+            if let Value::String(ok) = table.get_str("ok") {
+                return RespType::simple_string(bytes_to_string(&ok));
+            }
+
+            if let Value::Table(map) = table.get_str("map") {
+                let map = map.borrow();
+                let mut entries = Vec::new();
+                let mut key = Value::Nil;
+
+                while let Ok(Some((next, value))) = map.next(&key) {
+                    entries.push((to_resp(&next), to_resp(&value)));
+                    key = next;
+                }
+
+                return RespType::Map(entries);
+            }
+
+            let values = (1..)
+                .map(|index| table.get(&Value::Number(index as f64)))
+                .take_while(|value| !value.is_nil())
+                .map(|value| to_resp(&value))
+                .collect();
+
+            RespType::array(values)
+        }
+        _ => RespType::Null,
+    }
+}
+
+/// What scripts reach through the `redis` table
+struct ScriptHost<'c> {
+    ctx: &'c mut Context,
+    /// Refuses write commands, for `*_RO` commands and `no-writes` scripts
+    read_only: bool,
+    /// ACL user of the client running the script, unrestricted when `None`
+    user: Option<&'c str>,
+    /// Version of the replies converted for the script, set by `redis.setresp`
+    protocol: u8,
+    propagated: Vec<Vec<String>>,
+}
+
+impl ScriptHost<'_> {
+    /// `redis.call` and `redis.pcall`, the reply of the command or the
+    /// error to raise
+    fn call(&mut self, args: Vec<Value>) -> Result<Value, Error> {
+        if args.is_empty() {
+            return Err(Error::custom("ERR Please specify at least one argument for this redis lib call"));
+        }
+
+        let mut parts = Vec::with_capacity(args.len());
+
+        for arg in &args {
+            match arg {
+                Value::String(_) | Value::Number(_) => parts.push(bytes_to_string(&arg.to_bytes().unwrap())),
+                _ => return Err(Error::custom("ERR Lua redis lib command arguments must be strings or integers")),
+            }
+        }
+
+        let name = parts.remove(0);
+        let request = Command::new(name.clone(), parts);
+
+        if NOSCRIPT_COMMANDS.contains(&name.to_lowercase().as_str()) {
+            return Err(Error::custom("ERR This Redis command is not allowed from script"));
+        }
+
+        let mut command = request
+            .create_command()
+            .map_err(|_| Error::custom("ERR Unknown Redis command called from script"))?;
+
+        if command.is_invalid() {
+            return Err(Error::custom("ERR Wrong number of args calling Redis command from script"));
+        }
+
+        if let Some(user) = self.user {
+            self.check_user(user, command.command_name(), request.args(), &command.keys())?;
+        }
+
+        if command.is_write() {
+            self.check_write()?;
+        }
+
+        if let Some(cluster) = &self.ctx.cluster {
+            if cluster.route(command.command_name(), &command.keys(), &self.ctx.store, false).is_err() {
+                return Err(Error::custom("ERR Script attempted to access a non local key in a cluster node"));
+            }
+        }
+
+        let reply = command.execute(self.ctx);
+
+        if command.is_write() && !matches!(reply, RespType::SimpleError(_)) {
+            match command.propagate(self.ctx) {
+                Some(commands) => self.propagated.extend(commands),
+                None => self.propagated.push(request.parts()),
+            }
+
+            if let Some(running) = RUNNING.lock().unwrap().as_mut() {
+                running.wrote = true;
+            }
+        }
+
+        Ok(to_lua(reply, self.protocol))
+    }
+
+    /// Same checks as for the commands of the client
+    fn check_user(&self, user: &str, command: &str, args: &[String], keys: &[String]) -> Result<(), Error> {
+        let user = self
+            .ctx
+            .acl
+            .get(user)
+            .ok_or_else(|| Error::custom("NOAUTH Authentication required."))?;
+
+        acl::check_command(user, command, args)?;
+        acl::check_keys(user, command, keys)
+    }
+
+    fn check_write(&self) -> Result<(), Error> {
+        if self.read_only {
+            return Err(Error::custom("ERR Write commands are not allowed from read-only scripts."));
+        }
+
+        if self.ctx.config.role == Role::Slave && self.ctx.config.replica_read_only {
+            return Err(Error::custom("READONLY You can't write against a read only replica."));
+        }
+
+        Ok(())
+    }
+}
+
+/// `redis.log(level, message...)`
+pub(super) fn log(args: &[Value]) -> Result<Vec<Value>, LuaError> {
+    if args.len() < 2 {
+        return Err(raise("redis.log() requires two arguments or more."));
+    }
+
+    if !matches!(args[0], Value::Number(_)) {
+        return Err(raise("First argument must be a number (log level)."));
+    }
+
+    let message: Vec<String> = args[1..]
+        .iter()
+        .filter_map(Value::to_bytes)
+        .map(|bytes| bytes_to_string(&bytes))
+        .collect();
+
+    eprintln!("{}", message.join(" "));
+
+    Ok(Vec::new())
+}
+
+/// Raises `message` as an error of the script
+pub(super) fn raise(message: impl std::fmt::Display) -> LuaError {
+    LuaError::new(Value::string(message.to_string()))
+}
+
+impl Host for ScriptHost<'_> {
+    fn invoke(&mut self, name: &str, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+        match name {
+            "call" => match self.call(args) {
+                Ok(Value::Table(table)) if !table.borrow().get_str("err").is_nil() => {
+                    Err(LuaError::new(Value::Table(table)))
+                }
+                Ok(value) => Ok(vec![value]),
+                Err(err) => Err(LuaError::new(error_table(string_to_bytes(&err.to_string())))),
+            },
+            "pcall" => match self.call(args) {
+                Ok(value) => Ok(vec![value]),
+                Err(err) => Ok(vec![error_table(string_to_bytes(&err.to_string()))]),
+            },
+            "sha1hex" => match args.as_slice() {
+                [value] => {
+                    let bytes = value.to_bytes().unwrap_or_default();
+
+                    Ok(vec![Value::from(sha1_hex(&bytes).as_str())])
+                }
+                _ => Err(raise("wrong number of arguments")),
+            },
+            "error_reply" | "status_reply" => match args.as_slice() {
+                [Value::String(message)] => {
+                    let mut table = Table::new();
+                    let field = if name == "error_reply" { "err" } else { "ok" };
+
+                    table.set_str(field, Value::String(message.clone()));
+
+                    Ok(vec![Value::table(table)])
+                }
+                _ => Err(raise("wrong number or type of arguments")),
+            },
+            "log" => log(&args),
+            "setresp" => match args.as_slice() {
+                [Value::Number(n)] if *n == 2.0 || *n == 3.0 => {
+                    self.protocol = *n as u8;
+
+                    Ok(Vec::new())
+                }
+                [_] => Err(raise("RESP version must be 2 or 3.")),
+                _ => Err(raise("redis.setresp() requires one argument.")),
+            },
+            // Scripts are always replicated by their effects
+            "replicate_commands" => Ok(vec![Value::Boolean(true)]),
+            "set_repl" => match args.as_slice() {
+                [Value::Number(_)] => Ok(Vec::new()),
+                _ => Err(raise("redis.set_repl() requires one argument.")),
+            },
+            _ => unreachable!("unregistered function {name}"),
+        }
+    }
+
+    fn interrupted(&mut self) -> Option<LuaError> {
+        let killed = RUNNING.lock().unwrap().as_ref().is_some_and(|running| running.killed);
+
+        killed.then(|| LuaError::new(error_table("ERR Script killed by user with SCRIPT KILL...")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(ctx: &mut Context, source: &str, keys: &[&str], args: &[&str]) -> Execution {
+        let sha = ctx.scripts.load(source).unwrap();
+        let script = ctx.scripts.get(&sha).unwrap().clone();
+        let keys: Vec<String> = keys.iter().map(|key| key.to_string()).collect();
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+
+        eval(ctx, &script, &sha, &keys, &args, false, None)
+    }
+
+    fn reply(source: &str) -> RespType {
+        run(&mut Context::default(), source, &[], &[]).reply
+    }
+
+    fn error(reply: RespType) -> String {
+        match reply {
+            RespType::SimpleError(err) => err.to_string(),
+            reply => panic!("expected an error, got {reply:?}"),
+        }
+    }
+
+    #[test]
+    fn calls_commands() {
+        let mut ctx = Context::default();
+        let execution = run(
+            &mut ctx,
+            "redis.call('SET', KEYS[1], ARGV[1]) return redis.call('GET', KEYS[1])",
+            &["key"],
+            &["value"],
+        );
+
+        assert_eq!(execution.reply, RespType::bulk_string("value"));
+        assert_eq!(execution.propagated, vec![vec!["SET".to_string(), "key".to_string(), "value".to_string()]]);
+
+        let execution = run(&mut ctx, "return redis.call('GET', 'missing')", &[], &[]);
+
+        assert_eq!(execution.reply, RespType::Null);
+        assert!(execution.propagated.is_empty());
+    }
+
+    #[test]
+    fn converts_values() {
+        assert_eq!(reply("return 3.99"), RespType::Integer(3));
+        assert_eq!(reply("return true"), RespType::Integer(1));
+        assert_eq!(reply("return false"), RespType::Null);
+        assert_eq!(reply("return nil"), RespType::Null);
+        assert_eq!(
+            reply("return {1, 'two', {3}, nil, 5}"),
+            RespType::array(vec![
+                RespType::Integer(1),
+                RespType::bulk_string("two"),
+                RespType::array(vec![RespType::Integer(3)]),
+            ])
+        );
+        assert_eq!(reply("return {ok = 'fine'}"), RespType::simple_string("fine"));
+        assert_eq!(reply("return redis.status_reply('PONG')"), RespType::simple_string("PONG"));
+        assert_eq!(error(reply("return redis.error_reply('MY error')")), "MY error");
+        assert_eq!(reply("return redis.call('PING')"), RespType::simple_string("PONG"));
+        assert_eq!(reply("return redis.call('GET', 'missing') == false"), RespType::Integer(1));
+        assert_eq!(
+            reply("redis.setresp(3) return redis.call('GET', 'missing') == nil"),
+            RespType::Integer(1)
+        );
+        assert_eq!(
+            reply("return redis.sha1hex('')"),
+            RespType::bulk_string("da39a3ee5e6b4b0d3255bfef95601890afd80709")
+        );
+    }
+
+    #[test]
+    fn reports_errors() {
+        let sha = sha1_hex(b"return redis.call('nosuch')");
+
+        assert_eq!(
+            error(reply("return redis.call('nosuch')")),
+            format!("ERR Unknown Redis command called from script script: {sha}, on @user_script:1.")
+        );
+        assert_eq!(
+            reply("local r = redis.pcall('nosuch') return r.err"),
+            RespType::bulk_string("ERR Unknown Redis command called from script")
+        );
+        assert_eq!(
+            reply("local r = redis.pcall('nosuch') return type(r)"),
+            RespType::bulk_string("table")
+        );
+        assert!(error(reply("return redis.call('GET')")).starts_with("ERR Wrong number of args calling Redis command"));
+        assert!(error(reply("return redis.call('EVAL', 'return 1', '0')")).starts_with("ERR This Redis command is not allowed"));
+
+        let sha = sha1_hex(b"\nreturn x");
+
+        assert_eq!(
+            error(reply("\nreturn x")),
+            format!(
+                "ERR user_script:2: Script attempted to access nonexistent global variable 'x' script: {sha}, on @user_script:2."
+            )
+        );
+        assert!(error(reply("x = 1")).contains("Attempt to modify a readonly table"));
+        assert!(error(reply("string.x = 1")).contains("Attempt to modify a readonly table"));
+        assert_eq!(
+            ScriptCache::default().load("return 1 +"),
+            Err(Error::custom(
+                "ERR Error compiling script (new function): user_script:1: unexpected symbol near '<eof>'"
+            ))
+        );
+    }
+
+    #[test]
+    fn read_only_scripts() {
+        let mut ctx = Context::default();
+        let sha = ctx.scripts.load("return redis.call('SET', 'k', 'v')").unwrap();
+        let script = ctx.scripts.get(&sha).unwrap().clone();
+        let execution = eval(&mut ctx, &script, &sha, &[], &[], true, None);
+
+        assert!(error(execution.reply).starts_with("ERR Write commands are not allowed from read-only scripts."));
+        assert!(!ctx.store.contains_key("k"));
+
+        let execution = run(&mut ctx, "#!lua flags=no-writes\nreturn redis.call('SET', 'k', 'v')", &[], &[]);
+
+        assert!(error(execution.reply).starts_with("ERR Write commands are not allowed from read-only scripts."));
+
+        let sha = ctx.scripts.load("#!lua\nreturn 1").unwrap();
+        let script = ctx.scripts.get(&sha).unwrap().clone();
+
+        assert_eq!(
+            error(eval(&mut ctx, &script, &sha, &[], &[], true, None).reply),
+            "ERR Can not execute a script with write flag using *_ro command."
+        );
+    }
+
+    #[test]
+    fn parses_shebangs() {
+        let mut ctx = Context::default();
+
+        assert_eq!(
+            ctx.scripts.load("#!js\nreturn 1"),
+            Err(Error::custom("ERR Unexpected engine in script shebang: js"))
+        );
+        assert_eq!(
+            ctx.scripts.load("#!lua flags=no-writes,bogus\nreturn 1"),
+            Err(Error::custom("ERR Unexpected flag in script shebang: bogus"))
+        );
+
+        let sha = ctx.scripts.load("#!lua flags=no-writes\nreturn 1").unwrap();
+        let script = ctx.scripts.get(&sha).unwrap();
+
+        assert!(script.flags.unwrap().no_writes);
+        assert!(ctx.scripts.contains(&sha.to_uppercase()));
+        assert_eq!(run(&mut ctx, "#!lua\nreturn error('x')", &[], &[]).reply, {
+            let sha = sha1_hex(b"#!lua\nreturn error('x')");
+
+            Error::custom(format!("ERR user_script:2: x script: {sha}, on @user_script:2.")).into()
+        });
+    }
+
+    #[test]
+    fn runs_the_standard_libraries() {
+        assert_eq!(
+            reply("return string.format('%s-%05.1f-%d', 'a', 3.14159, 42)"),
+            RespType::bulk_string("a-003.1-42")
+        );
+        assert_eq!(reply("return cjson.encode({1, 2, {a = 'b'}})"), RespType::bulk_string("[1,2,{\"a\":\"b\"}]"));
+        assert_eq!(reply("return cjson.decode('{\"a\":[1,2]}').a[2]"), RespType::Integer(2));
+        assert_eq!(
+            reply("local t = {} for w in string.gmatch('one two three', '%a+') do t[#t + 1] = w:upper() end return table.concat(t, ',')"),
+            RespType::bulk_string("ONE,TWO,THREE")
+        );
+        assert_eq!(reply("return (string.gsub('hello world', 'o', '0'))"), RespType::bulk_string("hell0 w0rld"));
+        assert_eq!(reply("local t = {3, 1, 2} table.sort(t) return t"), reply("return {1, 2, 3}"));
+        assert_eq!(reply("return bit.band(0xff, 0x0f)"), RespType::Integer(15));
+        assert_eq!(reply("return tonumber('0x10') + tonumber('z', 36)"), RespType::Integer(51));
+        assert_eq!(reply("return select('#', 1, nil, 3)"), RespType::Integer(3));
+        assert_eq!(
+            reply("local ok, err = pcall(error, {code = 7}) return err.code"),
+            RespType::Integer(7)
+        );
+        assert_eq!(
+            reply("local function fib(n) if n < 2 then return n end return fib(n - 1) + fib(n - 2) end return fib(20)"),
+            RespType::Integer(6765)
+        );
+        assert_eq!(
+            reply("local fs = {} for i = 1, 3 do fs[i] = function() return i end end return fs[1]() + fs[3]()"),
+            RespType::Integer(4)
+        );
+    }
+
+    #[test]
+    fn deep_nesting_fails_cleanly() {
+        let mut ctx = Context::default();
+        let execution = run(
+            &mut ctx,
+            "local function f(n) if n == 0 then return 0 end return 1 + f(n - 1) end return f(tonumber(ARGV[1]))",
+            &[],
+            &["195"],
+        );
+        assert_eq!(execution.reply, RespType::Integer(195));
+
+        let execution = run(
+            &mut ctx,
+            "local function f(n) if n == 0 then return 0 end return 1 + f(n - 1) end return f(tonumber(ARGV[1]))",
+            &[],
+            &["500"],
+        );
+        assert!(error(execution.reply).contains("stack overflow"));
+
+        let nested = "[".repeat(999) + &"]".repeat(999);
+        let execution = run(&mut ctx, "return #cjson.encode(cjson.decode(ARGV[1]))", &[], &[&nested]);
+        assert_eq!(execution.reply, RespType::Integer(1998));
+
+        let nested = "[".repeat(1001) + &"]".repeat(1001);
+        let execution = run(&mut ctx, "return cjson.decode(ARGV[1])", &[], &[&nested]);
+        assert!(error(execution.reply).contains("too many nested data structures"));
+    }
+}
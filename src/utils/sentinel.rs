@@ -0,0 +1,1520 @@
+//! Sentinel mode, monitoring masters and failing them over
+//!
+//! A sentinel pings every instance of the masters it monitors each second and
+//! asks them for `INFO` every ten seconds, which is how it discovers their
+//! replicas. An instance that doesn't answer for `down-after-milliseconds` is
+//! subjectively down.
+//!
+//! Sentinels find each other through the `__sentinel__:hello` channel of the
+//! monitored instances, where each one announces itself and the configuration
+//! it knows of every two seconds. While a master is down, a sentinel asks the
+//! others whether they agree with `SENTINEL is-master-down-by-addr`; once
+//! `quorum` of them do, the master is objectively down.
+//!
+//! The failover is then run by a single sentinel, elected for a new epoch with
+//! the same request: each sentinel votes for the first one asking in an epoch.
+//! The leader promotes the best replica with `REPLICAOF NO ONE`, points the
+//! other replicas to it and announces the new configuration, tagged with the
+//! epoch, over the hello channel so the other sentinels adopt it.
+
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use bytes::{Buf, BytesMut};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+
+use crate::resp::types::RespType;
+
+use super::aof;
+use super::context::Context;
+use super::shared_context::{lock, SharedContext};
+
+/// Channel sentinels announce themselves on
+pub const HELLO_CHANNEL: &str = "__sentinel__:hello";
+
+const TIMER_PERIOD: Duration = Duration::from_millis(100);
+const PING_PERIOD: Duration = Duration::from_secs(1);
+const INFO_PERIOD: Duration = Duration::from_secs(10);
+/// `INFO` period of replicas while their master is down or failing over
+const FAST_INFO_PERIOD: Duration = Duration::from_secs(1);
+const HELLO_PERIOD: Duration = Duration::from_secs(2);
+/// Period of the `is-master-down-by-addr` requests while a master is down
+const ASK_PERIOD: Duration = Duration::from_secs(1);
+/// How long the answer of another sentinel about a master counts
+const ASK_VALIDITY: Duration = Duration::from_secs(5);
+/// How long a candidate waits to be elected before giving up
+const ELECTION_TIMEOUT: Duration = Duration::from_secs(10);
+/// Random delay added to failover attempts, so sentinels don't all ask for
+/// votes at once
+const MAX_DESYNC: Duration = Duration::from_millis(1000);
+/// Delay between attempts to fix the configuration of a replica
+const RECONFIGURE_PERIOD: Duration = Duration::from_secs(4);
+const QUERY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Address of a monitored instance
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Addr {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+}
+
+impl Addr {
+    pub fn new(host: impl Into<String>, port: u16) -> Self {
+        Self {
+            host: host.into(),
+            port,
+        }
+    }
+}
+
+impl fmt::Display for Addr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.host, self.port)
+    }
+}
+
+/// What the sentinel knows about the connection to an instance
+#[derive(Debug)]
+pub struct Link {
+    pub(crate) last_ok_ping: Instant,
+    last_ping: Option<Instant>,
+    ping_pending: bool,
+    /// When the oldest ping still without a valid reply was sent
+    unanswered_since: Option<Instant>,
+    pub(crate) last_info: Option<Instant>,
+    info_pending: bool,
+    last_hello: Option<Instant>,
+    pub(crate) sdown_since: Option<Instant>,
+    /// Task reading the hello channel of the instance
+    subscription: Option<JoinHandle<()>>,
+    subscribing: bool,
+}
+
+impl Default for Link {
+    fn default() -> Self {
+        Self {
+            last_ok_ping: Instant::now(),
+            last_ping: None,
+            ping_pending: false,
+            unanswered_since: None,
+            last_info: None,
+            info_pending: false,
+            last_hello: None,
+            sdown_since: None,
+            subscription: None,
+            subscribing: false,
+        }
+    }
+}
+
+/// Whether `period` elapsed since `last`, or it never happened
+fn due(last: Option<Instant>, period: Duration) -> bool {
+    match last {
+        Some(at) => at.elapsed() >= period,
+        None => true,
+    }
+}
+
+impl Drop for Link {
+    fn drop(&mut self) {
+        if let Some(subscription) = self.subscription.take() {
+            subscription.abort();
+        }
+    }
+}
+
+impl Link {
+    pub fn is_sdown(&self) -> bool {
+        self.sdown_since.is_some()
+    }
+
+    /// Whether the instance left a ping unanswered for longer than `down_after`
+    fn is_unresponsive(&self, down_after: Duration) -> bool {
+        self.unanswered_since.is_some_and(|at| at.elapsed() > down_after)
+    }
+
+    /// Records a ping about to be sent when one is due, instances are pinged
+    /// at least as often as they may be declared down
+    fn ping(&mut self, down_after: Duration) -> bool {
+        if self.ping_pending || !due(self.last_ping, PING_PERIOD.min(down_after)) {
+            return false;
+        }
+
+        let now = Instant::now();
+        self.ping_pending = true;
+        self.last_ping = Some(now);
+        self.unanswered_since.get_or_insert(now);
+        true
+    }
+
+    fn info_due(&self, period: Duration) -> bool {
+        !self.info_pending && due(self.last_info, period)
+    }
+
+    fn hello_due(&mut self) -> bool {
+        if !due(self.last_hello, HELLO_PERIOD) {
+            return false;
+        }
+
+        self.last_hello = Some(Instant::now());
+        true
+    }
+}
+
+/// Replica of a monitored master, as reported by its `INFO`
+#[derive(Debug)]
+pub struct MonitoredReplica {
+    pub(crate) addr: Addr,
+    pub(crate) link: Link,
+    pub(crate) run_id: Option<String>,
+    /// Whether the instance says it is a master, like a former master coming back
+    pub(crate) role_master: bool,
+    pub(crate) master: Option<Addr>,
+    pub(crate) master_link_up: bool,
+    pub(crate) master_link_down: Duration,
+    pub(crate) priority: u64,
+    pub(crate) repl_offset: u64,
+    /// When it was last told which master to replicate
+    reconfigured: Option<Instant>,
+    /// Set once it replicates the promoted replica during a failover
+    reconfigured_done: bool,
+}
+
+impl MonitoredReplica {
+    fn new(addr: Addr) -> Self {
+        Self {
+            addr,
+            link: Link::default(),
+            run_id: None,
+            role_master: false,
+            master: None,
+            master_link_up: false,
+            master_link_down: Duration::ZERO,
+            priority: 100,
+            repl_offset: 0,
+            reconfigured: None,
+            reconfigured_done: false,
+        }
+    }
+}
+
+/// Another sentinel monitoring the same master
+#[derive(Debug)]
+pub struct PeerSentinel {
+    pub(crate) addr: Addr,
+    pub(crate) run_id: String,
+    pub(crate) link: Link,
+    pub(crate) last_hello: Instant,
+    /// Its opinion about the master, from `is-master-down-by-addr`
+    pub(crate) master_down: bool,
+    last_reply: Option<Instant>,
+    last_ask: Option<Instant>,
+    ask_pending: bool,
+    /// The sentinel it voted for, in `leader_epoch`
+    pub(crate) leader: Option<String>,
+    pub(crate) leader_epoch: u64,
+}
+
+/// Steps of a failover, in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailoverState {
+    WaitStart,
+    SelectSlave,
+    SendSlaveofNoOne,
+    WaitPromotion,
+    ReconfSlaves,
+}
+
+impl fmt::Display for FailoverState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FailoverState::WaitStart => write!(f, "wait_start"),
+            FailoverState::SelectSlave => write!(f, "select_slave"),
+            FailoverState::SendSlaveofNoOne => write!(f, "send_slaveof_noone"),
+            FailoverState::WaitPromotion => write!(f, "wait_promotion"),
+            FailoverState::ReconfSlaves => write!(f, "reconf_slaves"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Failover {
+    pub(crate) state: FailoverState,
+    pub(crate) epoch: u64,
+    started: Instant,
+    /// When `state` was entered
+    since: Instant,
+    /// Started with `SENTINEL FAILOVER`, without asking the other sentinels
+    forced: bool,
+    pub(crate) promoted: Option<Addr>,
+}
+
+impl Failover {
+    fn set_state(&mut self, state: FailoverState) {
+        self.state = state;
+        self.since = Instant::now();
+    }
+}
+
+/// Master monitored by this sentinel, `SENTINEL MONITOR`
+#[derive(Debug)]
+pub struct MonitoredMaster {
+    pub(crate) name: String,
+    pub(crate) addr: Addr,
+    pub(crate) link: Link,
+    pub(crate) run_id: Option<String>,
+    /// Whether the instance says it is a master, and since when it says so
+    pub(crate) role_master: bool,
+    role_reported: Instant,
+    pub(crate) quorum: usize,
+    pub(crate) down_after: Duration,
+    pub(crate) failover_timeout: Duration,
+    pub(crate) parallel_syncs: usize,
+    /// Epoch of the failover that produced the current configuration
+    pub(crate) config_epoch: u64,
+    pub(crate) replicas: BTreeMap<Addr, MonitoredReplica>,
+    pub(crate) sentinels: BTreeMap<String, PeerSentinel>,
+    pub(crate) odown: bool,
+    /// The sentinel this one voted for, in `leader_epoch`
+    pub(crate) leader: Option<String>,
+    pub(crate) leader_epoch: u64,
+    pub(crate) failover: Option<Failover>,
+    /// When the last failover was attempted, a new one waits twice the
+    /// failover timeout
+    failover_start_time: Option<Instant>,
+}
+
+impl MonitoredMaster {
+    pub fn new(name: String, addr: Addr, quorum: usize) -> Self {
+        Self {
+            name,
+            addr,
+            link: Link::default(),
+            run_id: None,
+            role_master: true,
+            role_reported: Instant::now(),
+            quorum,
+            down_after: Duration::from_secs(30),
+            failover_timeout: Duration::from_secs(180),
+            parallel_syncs: 1,
+            config_epoch: 0,
+            replicas: BTreeMap::new(),
+            sentinels: BTreeMap::new(),
+            odown: false,
+            leader: None,
+            leader_epoch: 0,
+            failover: None,
+            failover_start_time: None,
+        }
+    }
+
+    /// Address clients should use, the promoted replica once it took over
+    pub fn current_addr(&self) -> &Addr {
+        match &self.failover {
+            Some(Failover {
+                state: FailoverState::ReconfSlaves,
+                promoted: Some(promoted),
+                ..
+            }) => promoted,
+            _ => &self.addr,
+        }
+    }
+
+    /// Flags as listed by `SENTINEL MASTERS`
+    pub fn flags(&self) -> String {
+        let mut flags = vec!["master"];
+
+        if self.link.is_sdown() {
+            flags.push("s_down");
+        }
+        if self.odown {
+            flags.push("o_down");
+        }
+        if self.failover.is_some() {
+            flags.push("failover_in_progress");
+        }
+
+        flags.join(",")
+    }
+
+    fn describe(&self) -> String {
+        format!("master {} {} {}", self.name, self.addr.host, self.addr.port)
+    }
+
+    fn describe_replica(&self, addr: &Addr) -> String {
+        format!(
+            "slave {addr} {} {} @ {} {} {}",
+            addr.host, addr.port, self.name, self.addr.host, self.addr.port
+        )
+    }
+
+    fn describe_sentinel(&self, peer: &PeerSentinel) -> String {
+        format!(
+            "sentinel {} {} {} @ {} {} {}",
+            peer.run_id, peer.addr.host, peer.addr.port, self.name, self.addr.host, self.addr.port
+        )
+    }
+
+    /// Replica to promote: reachable, recently refreshed and not disconnected
+    /// from the master for too long, by priority, then offset and run id
+    pub fn select_replica(&self) -> Option<Addr> {
+        let mut max_master_down = self.down_after * 10;
+
+        if let Some(since) = self.link.sdown_since {
+            max_master_down += since.elapsed();
+        }
+
+        let info_validity = match self.link.is_sdown() {
+            true => FAST_INFO_PERIOD * 5,
+            false => INFO_PERIOD * 3,
+        };
+
+        let mut candidates: Vec<&MonitoredReplica> = self
+            .replicas
+            .values()
+            .filter(|replica| !replica.link.is_sdown() && !replica.role_master && replica.priority > 0)
+            .filter(|replica| replica.link.last_ok_ping.elapsed() <= PING_PERIOD * 5)
+            .filter(|replica| replica.link.last_info.is_some_and(|at| at.elapsed() <= info_validity))
+            .filter(|replica| replica.master_link_down <= max_master_down)
+            .collect();
+
+        candidates.sort_by(|a, b| {
+            a.priority
+                .cmp(&b.priority)
+                .then(b.repl_offset.cmp(&a.repl_offset))
+                .then(match (&a.run_id, &b.run_id) {
+                    (Some(a), Some(b)) => a.cmp(b),
+                    (a, b) => b.is_some().cmp(&a.is_some()),
+                })
+        });
+
+        candidates.first().map(|replica| replica.addr.clone())
+    }
+
+    /// Starts monitoring `addr` as the master, the previous one and its other
+    /// replicas becoming its replicas
+    fn switch_to(&mut self, addr: Addr) {
+        let old = std::mem::replace(&mut self.addr, addr.clone());
+        let mut replicas: Vec<Addr> = self.replicas.keys().filter(|replica| **replica != addr).cloned().collect();
+
+        if old != addr {
+            replicas.push(old);
+        }
+
+        self.replicas = replicas.into_iter().map(|addr| (addr.clone(), MonitoredReplica::new(addr))).collect();
+        self.link = Link::default();
+        self.run_id = None;
+        self.role_master = true;
+        self.role_reported = Instant::now();
+        self.odown = false;
+        self.failover = None;
+
+        for peer in self.sentinels.values_mut() {
+            peer.master_down = false;
+        }
+    }
+}
+
+/// Request to an instance, performed outside of the lock on the context
+#[derive(Debug)]
+enum Action {
+    Ping { master: String, instance: Instance },
+    Info { master: String, instance: Instance },
+    Hello { addr: Addr, message: Vec<String> },
+    Subscribe { master: String, instance: Instance },
+    AskPeer { master: String, peer: String, addr: Addr, request: Vec<String> },
+    Command { addr: Addr, command: Vec<String> },
+}
+
+/// Instance of a monitored master, identified by its address since the
+/// configuration may change while a request is in flight
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Instance {
+    Master(Addr),
+    Replica(Addr),
+    Sentinel(String, Addr),
+}
+
+impl Instance {
+    fn addr(&self) -> &Addr {
+        match self {
+            Instance::Master(addr) | Instance::Replica(addr) | Instance::Sentinel(_, addr) => addr,
+        }
+    }
+}
+
+/// State of the sentinel
+#[derive(Debug)]
+pub struct Sentinel {
+    pub(crate) myid: String,
+    /// Highest epoch seen, each failover attempt starts a new one
+    pub(crate) current_epoch: u64,
+    pub(crate) masters: BTreeMap<String, MonitoredMaster>,
+    /// Events published to the clients of the sentinel, and logged
+    events: Vec<(String, String)>,
+}
+
+impl Sentinel {
+    pub fn new(myid: String) -> Self {
+        Self {
+            myid,
+            current_epoch: 0,
+            masters: BTreeMap::new(),
+            events: Vec::new(),
+        }
+    }
+
+    fn event(&mut self, kind: &str, message: String) {
+        self.events.push((kind.to_string(), message));
+    }
+
+    pub fn master_by_addr(&self, addr: &Addr) -> Option<&MonitoredMaster> {
+        self.masters.values().find(|master| master.addr == *addr)
+    }
+
+    pub fn monitor(&mut self, master: MonitoredMaster) {
+        self.event("+monitor", format!("{} quorum {}", master.describe(), master.quorum));
+        self.masters.insert(master.name.clone(), master);
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        match self.masters.remove(name) {
+            Some(master) => {
+                self.event("-monitor", master.describe());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Votes for `candidate` as the leader of `epoch`, unless this sentinel
+    /// already voted in that epoch, and returns its vote
+    pub fn vote(&mut self, name: &str, epoch: u64, candidate: &str) -> (Option<String>, u64) {
+        if epoch > self.current_epoch {
+            self.current_epoch = epoch;
+            self.event("+new-epoch", epoch.to_string());
+        }
+
+        let current_epoch = self.current_epoch;
+        let myid = self.myid.clone();
+        let Some(master) = self.masters.get_mut(name) else {
+            return (None, 0);
+        };
+
+        if master.leader_epoch < epoch && current_epoch <= epoch {
+            master.leader = Some(candidate.to_string());
+            master.leader_epoch = current_epoch;
+
+            // Voting for another sentinel delays our own attempts
+            if candidate != myid {
+                master.failover_start_time = Some(Instant::now() + random_delay(MAX_DESYNC));
+            }
+
+            let message = format!("{} {candidate} {current_epoch}", master.describe());
+            self.event("+vote-for-leader", message);
+        }
+
+        let master = &self.masters[name];
+
+        (master.leader.clone(), master.leader_epoch)
+    }
+
+    /// Starts a failover of `name` in a new epoch
+    pub fn start_failover(&mut self, name: &str, forced: bool) {
+        self.current_epoch += 1;
+
+        let epoch = self.current_epoch;
+        let Some(master) = self.masters.get_mut(name) else {
+            return;
+        };
+        let now = Instant::now();
+
+        master.failover = Some(Failover {
+            state: FailoverState::WaitStart,
+            epoch,
+            started: now,
+            since: now,
+            forced,
+            promoted: None,
+        });
+        master.failover_start_time = Some(now + random_delay(MAX_DESYNC));
+
+        // Ask for votes right away
+        for peer in master.sentinels.values_mut() {
+            peer.last_ask = None;
+        }
+
+        let description = master.describe();
+        self.event("+new-epoch", epoch.to_string());
+        self.event("+try-failover", description);
+    }
+
+    /// Sentinel elected for `epoch` by a majority of the sentinels, and at
+    /// least `quorum` of them, counting the vote of this one
+    fn leader(&mut self, name: &str, epoch: u64) -> Option<String> {
+        let master = &self.masters[name];
+        let mut votes: HashMap<String, usize> = HashMap::new();
+
+        for peer in master.sentinels.values() {
+            if let (Some(leader), true) = (&peer.leader, peer.leader_epoch == epoch) {
+                *votes.entry(leader.clone()).or_default() += 1;
+            }
+        }
+
+        let voters = master.sentinels.len() + 1;
+        let quorum = master.quorum;
+        let winner = votes
+            .iter()
+            .max_by(|a, b| a.1.cmp(b.1).then(a.0.cmp(b.0)))
+            .map(|(leader, _)| leader.clone());
+
+        let candidate = winner.unwrap_or_else(|| self.myid.clone());
+        let (vote, vote_epoch) = self.vote(name, epoch, &candidate);
+
+        if let (Some(vote), true) = (vote, vote_epoch == epoch) {
+            *votes.entry(vote).or_default() += 1;
+        }
+
+        votes
+            .into_iter()
+            .max_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(&b.0)))
+            .filter(|(_, count)| *count > voters / 2 && *count >= quorum)
+            .map(|(leader, _)| leader)
+    }
+
+    /// Runs the periodic checks and returns the requests to send
+    fn timer(&mut self, port: u16) -> Vec<Action> {
+        let mut actions = Vec::new();
+        let names: Vec<String> = self.masters.keys().cloned().collect();
+
+        for name in names {
+            self.schedule(&name, port, &mut actions);
+            self.check_down(&name);
+            self.check_failover(&name, &mut actions);
+        }
+
+        actions
+    }
+
+    /// Pings, `INFO` requests, hellos and subscriptions due for `name`
+    fn schedule(&mut self, name: &str, port: u16, actions: &mut Vec<Action>) {
+        let myid = self.myid.clone();
+        let current_epoch = self.current_epoch;
+        let master = self.masters.get_mut(name).expect("monitored master");
+        let addr = master.current_addr().clone();
+        let down_after = master.down_after;
+        let hello = vec![
+            String::new(),
+            port.to_string(),
+            myid.clone(),
+            current_epoch.to_string(),
+            master.name.clone(),
+            addr.host.clone(),
+            addr.port.to_string(),
+            master.config_epoch.to_string(),
+        ];
+        let replica_info_period = match master.link.is_sdown() || master.failover.is_some() {
+            true => FAST_INFO_PERIOD,
+            false => INFO_PERIOD,
+        };
+
+        let mut instances: Vec<(Instance, &mut Link, Duration)> =
+            vec![(Instance::Master(master.addr.clone()), &mut master.link, INFO_PERIOD)];
+
+        for replica in master.replicas.values_mut() {
+            instances.push((Instance::Replica(replica.addr.clone()), &mut replica.link, replica_info_period));
+        }
+
+        for (instance, link, info_period) in instances {
+            if link.ping(down_after) {
+                actions.push(Action::Ping {
+                    master: name.to_string(),
+                    instance: instance.clone(),
+                });
+            }
+
+            if link.info_due(info_period) {
+                link.info_pending = true;
+                actions.push(Action::Info {
+                    master: name.to_string(),
+                    instance: instance.clone(),
+                });
+            }
+
+            if link.hello_due() {
+                actions.push(Action::Hello {
+                    addr: instance.addr().clone(),
+                    message: hello.clone(),
+                });
+            }
+
+            if !link.subscribing {
+                link.subscribing = true;
+                actions.push(Action::Subscribe {
+                    master: name.to_string(),
+                    instance,
+                });
+            }
+        }
+
+        let master_sdown = master.link.is_sdown();
+        let voting = master.failover.is_some();
+        let master_addr = master.addr.clone();
+
+        for peer in master.sentinels.values_mut() {
+            if peer.link.ping(down_after) {
+                actions.push(Action::Ping {
+                    master: name.to_string(),
+                    instance: Instance::Sentinel(peer.run_id.clone(), peer.addr.clone()),
+                });
+            }
+
+            // Answers too old don't count anymore
+            if peer.last_reply.is_some_and(|at| at.elapsed() > ASK_VALIDITY) {
+                peer.master_down = false;
+                peer.leader = None;
+                peer.last_reply = None;
+            }
+
+            if !master_sdown || peer.ask_pending || !due(peer.last_ask, ASK_PERIOD) {
+                continue;
+            }
+
+            peer.ask_pending = true;
+            peer.last_ask = Some(Instant::now());
+
+            let runid = if voting { myid.clone() } else { "*".to_string() };
+            let request = vec![
+                "SENTINEL".to_string(),
+                "is-master-down-by-addr".to_string(),
+                master_addr.host.clone(),
+                master_addr.port.to_string(),
+                current_epoch.to_string(),
+                runid,
+            ];
+
+            actions.push(Action::AskPeer {
+                master: name.to_string(),
+                peer: peer.run_id.clone(),
+                addr: peer.addr.clone(),
+                request,
+            });
+        }
+    }
+
+    /// Updates the subjective and objective down states of `name` and its
+    /// instances
+    fn check_down(&mut self, name: &str) {
+        let master = self.masters.get_mut(name).expect("monitored master");
+        let down_after = master.down_after;
+        let mut events = Vec::new();
+
+        // A master saying it is a replica for too long is as good as down
+        let demoted = !master.role_master && master.role_reported.elapsed() > down_after + INFO_PERIOD * 2;
+        let master_down = master.link.is_unresponsive(down_after) || demoted;
+
+        match (master_down, master.link.sdown_since) {
+            (true, None) => {
+                master.link.sdown_since = Some(Instant::now());
+                events.push(("+sdown", master.describe()));
+            }
+            (false, Some(_)) => {
+                master.link.sdown_since = None;
+                events.push(("-sdown", master.describe()));
+            }
+            _ => {}
+        }
+
+        let mut replica_events = Vec::new();
+
+        for replica in master.replicas.values_mut() {
+            let down = replica.link.is_unresponsive(down_after);
+
+            match (down, replica.link.sdown_since) {
+                (true, None) => {
+                    replica.link.sdown_since = Some(Instant::now());
+                    replica_events.push(("+sdown", replica.addr.clone()));
+                }
+                (false, Some(_)) => {
+                    replica.link.sdown_since = None;
+                    replica_events.push(("-sdown", replica.addr.clone()));
+                }
+                _ => {}
+            }
+        }
+
+        for (kind, addr) in replica_events {
+            events.push((kind, master.describe_replica(&addr)));
+        }
+
+        for peer in master.sentinels.values_mut() {
+            let down = peer.link.is_unresponsive(down_after);
+
+            match (down, peer.link.sdown_since) {
+                (true, None) => peer.link.sdown_since = Some(Instant::now()),
+                (false, Some(_)) => peer.link.sdown_since = None,
+                _ => {}
+            }
+        }
+
+        let agreeing = 1 + master.sentinels.values().filter(|peer| peer.master_down).count();
+        let odown = master.link.is_sdown() && agreeing >= master.quorum;
+
+        match (odown, master.odown) {
+            (true, false) => {
+                master.odown = true;
+                events.push(("+odown", format!("{} #quorum {agreeing}/{}", master.describe(), master.quorum)));
+            }
+            (false, true) => {
+                master.odown = false;
+                events.push(("-odown", master.describe()));
+            }
+            _ => {}
+        }
+
+        for (kind, message) in events {
+            self.event(kind, message);
+        }
+    }
+
+    /// Starts a failover of an objectively down master, or moves the one in
+    /// progress forward
+    fn check_failover(&mut self, name: &str, actions: &mut Vec<Action>) {
+        let master = &self.masters[name];
+
+        if master.failover.is_none() {
+            let cooldown = master.failover_timeout * 2;
+            let recent = master.failover_start_time.is_some_and(|at| Instant::now() < at + cooldown);
+
+            if master.odown && !recent {
+                self.start_failover(name, false);
+            }
+
+            return;
+        }
+
+        let master = &self.masters[name];
+        let failover = master.failover.as_ref().expect("failover in progress");
+        let (state, epoch, forced) = (failover.state, failover.epoch, failover.forced);
+        let timed_out = failover.since.elapsed() > master.failover_timeout;
+
+        match state {
+            FailoverState::WaitStart => {
+                let leader = if forced { Some(self.myid.clone()) } else { self.leader(name, epoch) };
+
+                if leader.as_deref() != Some(self.myid.as_str()) {
+                    let master = &self.masters[name];
+                    let started = master.failover.as_ref().expect("failover in progress").started;
+
+                    if started.elapsed() > master.failover_timeout.min(ELECTION_TIMEOUT) {
+                        self.abort_failover(name, "-failover-abort-not-elected");
+                    }
+
+                    return;
+                }
+
+                let description = self.masters[name].describe();
+                self.event("+elected-leader", description.clone());
+                self.event("+failover-state-select-slave", description);
+                self.set_failover_state(name, FailoverState::SelectSlave);
+            }
+            FailoverState::SelectSlave => {
+                let Some(promoted) = self.masters[name].select_replica() else {
+                    self.abort_failover(name, "-failover-abort-no-good-slave");
+                    return;
+                };
+
+                let master = self.masters.get_mut(name).expect("monitored master");
+                let description = master.describe_replica(&promoted);
+                master.failover.as_mut().expect("failover in progress").promoted = Some(promoted);
+
+                self.event("+selected-slave", description.clone());
+                self.event("+failover-state-send-slaveof-noone", description);
+                self.set_failover_state(name, FailoverState::SendSlaveofNoOne);
+            }
+            FailoverState::SendSlaveofNoOne => {
+                let master = &self.masters[name];
+                let promoted = master.failover.as_ref().and_then(|failover| failover.promoted.clone());
+                let reachable = promoted
+                    .as_ref()
+                    .and_then(|addr| master.replicas.get(addr))
+                    .is_some_and(|replica| !replica.link.is_sdown());
+
+                if !reachable {
+                    if timed_out {
+                        self.abort_failover(name, "-failover-abort-slave-timeout");
+                    }
+
+                    return;
+                }
+
+                let promoted = promoted.expect("promoted replica");
+                let description = master.describe_replica(&promoted);
+
+                actions.push(Action::Command {
+                    addr: promoted,
+                    command: ["REPLICAOF", "NO", "ONE"].map(str::to_string).to_vec(),
+                });
+
+                self.event("+failover-state-wait-promotion", description);
+                self.set_failover_state(name, FailoverState::WaitPromotion);
+            }
+            FailoverState::WaitPromotion => {
+                if timed_out {
+                    self.abort_failover(name, "-failover-abort-slave-timeout");
+                }
+            }
+            FailoverState::ReconfSlaves => self.reconfigure_replicas(name, timed_out, actions),
+        }
+    }
+
+    fn set_failover_state(&mut self, name: &str, state: FailoverState) {
+        if let Some(failover) = self.masters.get_mut(name).and_then(|master| master.failover.as_mut()) {
+            failover.set_state(state);
+        }
+    }
+
+    fn abort_failover(&mut self, name: &str, reason: &str) {
+        let master = self.masters.get_mut(name).expect("monitored master");
+        master.failover = None;
+
+        let description = master.describe();
+        self.event(reason, description);
+    }
+
+    /// Points the remaining replicas to the promoted one, `parallel-syncs` at
+    /// a time, and switches to it once they all follow it
+    fn reconfigure_replicas(&mut self, name: &str, timed_out: bool, actions: &mut Vec<Action>) {
+        let master = self.masters.get_mut(name).expect("monitored master");
+        let promoted = master
+            .failover
+            .as_ref()
+            .and_then(|failover| failover.promoted.clone())
+            .expect("promoted replica");
+        let mut in_progress = master
+            .replicas
+            .values()
+            .filter(|replica| replica.reconfigured.is_some() && !replica.reconfigured_done)
+            .count();
+        let mut events = Vec::new();
+
+        for replica in master.replicas.values_mut() {
+            if replica.addr == promoted || replica.reconfigured_done {
+                continue;
+            }
+
+            if replica.reconfigured.is_some() {
+                if replica.master.as_ref() == Some(&promoted) && replica.master_link_up {
+                    replica.reconfigured_done = true;
+                    events.push(("+slave-reconf-done", replica.addr.clone()));
+                }
+
+                continue;
+            }
+
+            if replica.link.is_sdown() || in_progress >= master.parallel_syncs {
+                continue;
+            }
+
+            replica.reconfigured = Some(Instant::now());
+            in_progress += 1;
+            events.push(("+slave-reconf-sent", replica.addr.clone()));
+            actions.push(Action::Command {
+                addr: replica.addr.clone(),
+                command: vec![
+                    "REPLICAOF".to_string(),
+                    promoted.host.clone(),
+                    promoted.port.to_string(),
+                ],
+            });
+        }
+
+        let done = master
+            .replicas
+            .values()
+            .all(|replica| replica.addr == promoted || replica.reconfigured_done || replica.link.is_sdown());
+
+        let mut messages: Vec<(&str, String)> = events
+            .into_iter()
+            .map(|(kind, addr)| (kind, master.describe_replica(&addr)))
+            .collect();
+
+        if done || timed_out {
+            let old = master.addr.clone();
+            let kind = if done { "+failover-end" } else { "+failover-end-for-timeout" };
+
+            messages.push((kind, master.describe()));
+            messages.push((
+                "+switch-master",
+                format!("{} {} {} {} {}", master.name, old.host, old.port, promoted.host, promoted.port),
+            ));
+            master.switch_to(promoted);
+        }
+
+        for (kind, message) in messages {
+            self.event(kind, message);
+        }
+    }
+
+    fn link_mut(&mut self, name: &str, instance: &Instance) -> Option<&mut Link> {
+        let master = self.masters.get_mut(name)?;
+
+        match instance {
+            Instance::Master(addr) if *addr == master.addr => Some(&mut master.link),
+            Instance::Master(_) => None,
+            Instance::Replica(addr) => master.replicas.get_mut(addr).map(|replica| &mut replica.link),
+            Instance::Sentinel(run_id, _) => master.sentinels.get_mut(run_id).map(|peer| &mut peer.link),
+        }
+    }
+
+    fn ping_reply(&mut self, name: &str, instance: &Instance, ok: bool) {
+        if let Some(link) = self.link_mut(name, instance) {
+            link.ping_pending = false;
+
+            if ok {
+                link.last_ok_ping = Instant::now();
+                link.unanswered_since = None;
+            }
+        }
+    }
+
+    fn subscribed(&mut self, name: &str, instance: &Instance, subscription: JoinHandle<()>) {
+        match self.link_mut(name, instance) {
+            Some(link) => link.subscription = Some(subscription),
+            None => subscription.abort(),
+        }
+    }
+
+    /// Applies the `INFO` of an instance, `None` when it couldn't be fetched
+    fn info_reply(&mut self, name: &str, instance: &Instance, info: Option<&str>, actions: &mut Vec<Action>) {
+        let Some(link) = self.link_mut(name, instance) else {
+            return;
+        };
+
+        link.info_pending = false;
+
+        let Some(info) = info else {
+            return;
+        };
+
+        link.last_info = Some(Instant::now());
+
+        let fields: HashMap<&str, &str> = info
+            .lines()
+            .filter_map(|line| line.trim_end_matches('\r').split_once(':'))
+            .collect();
+
+        match instance {
+            Instance::Master(_) => self.master_info(name, &fields),
+            Instance::Replica(addr) => self.replica_info(name, addr, &fields, actions),
+            Instance::Sentinel(..) => {}
+        }
+    }
+
+    fn master_info(&mut self, name: &str, fields: &HashMap<&str, &str>) {
+        let master = self.masters.get_mut(name).expect("monitored master");
+        let role_master = fields.get("role") == Some(&"master");
+
+        master.run_id = fields.get("run_id").map(|id| id.to_string());
+
+        if role_master != master.role_master {
+            master.role_master = role_master;
+            master.role_reported = Instant::now();
+        }
+
+        let mut discovered = Vec::new();
+
+        for (field, value) in fields {
+            if !field.starts_with("slave") || !field[5..].chars().all(|c| c.is_ascii_digit()) {
+                continue;
+            }
+
+            let entries: HashMap<&str, &str> = value.split(',').filter_map(|entry| entry.split_once('=')).collect();
+            let (Some(ip), Some(Ok(port))) = (entries.get("ip"), entries.get("port").map(|port| port.parse())) else {
+                continue;
+            };
+
+            let addr = Addr::new(*ip, port);
+
+            if !master.replicas.contains_key(&addr) {
+                master.replicas.insert(addr.clone(), MonitoredReplica::new(addr.clone()));
+                discovered.push(addr);
+            }
+        }
+
+        let messages: Vec<String> = discovered.iter().map(|addr| master.describe_replica(addr)).collect();
+
+        for message in messages {
+            self.event("+slave", message);
+        }
+    }
+
+    fn replica_info(&mut self, name: &str, addr: &Addr, fields: &HashMap<&str, &str>, actions: &mut Vec<Action>) {
+        let master = self.masters.get_mut(name).expect("monitored master");
+        let master_addr = master.addr.clone();
+        let master_usable = !master.link.is_sdown() && master.role_master;
+        let failover = master.failover.as_ref().map(|failover| (failover.state, failover.promoted.clone()));
+        let Some(replica) = master.replicas.get_mut(addr) else {
+            return;
+        };
+
+        replica.run_id = fields.get("run_id").map(|id| id.to_string());
+        replica.role_master = fields.get("role") == Some(&"master");
+        replica.master = match (fields.get("master_host"), fields.get("master_port").map(|port| port.parse())) {
+            (Some(host), Some(Ok(port))) => Some(Addr::new(*host, port)),
+            _ => None,
+        };
+        replica.master_link_up = fields.get("master_link_status") == Some(&"up");
+        replica.master_link_down = match fields.get("master_link_down_since_seconds").map(|secs| secs.parse::<u64>()) {
+            Some(Ok(seconds)) => Duration::from_secs(seconds),
+            _ => Duration::ZERO,
+        };
+        replica.priority = fields.get("slave_priority").and_then(|p| p.parse().ok()).unwrap_or(100);
+        replica.repl_offset = fields.get("slave_repl_offset").and_then(|o| o.parse().ok()).unwrap_or(0);
+
+        if let Some((FailoverState::WaitPromotion, Some(promoted))) = &failover {
+            if promoted == addr && replica.role_master {
+                let description = master.describe_replica(addr);
+                let failover = master.failover.as_mut().expect("failover in progress");
+
+                master.config_epoch = failover.epoch;
+                failover.set_state(FailoverState::ReconfSlaves);
+
+                // Announce the new configuration right away
+                master.link.last_hello = None;
+                for replica in master.replicas.values_mut() {
+                    replica.link.last_hello = None;
+                }
+
+                self.event("+promoted-slave", description.clone());
+                self.event("+failover-state-reconf-slaves", description);
+            }
+
+            return;
+        }
+
+        if failover.is_some() || !master_usable || !due(replica.reconfigured, RECONFIGURE_PERIOD) {
+            return;
+        }
+
+        // Former masters coming back and replicas of another master follow
+        // the current configuration
+        let kind = match (replica.role_master, &replica.master) {
+            (true, _) => "+convert-to-slave",
+            (false, Some(current)) if *current != master_addr => "+fix-slave-config",
+            _ => return,
+        };
+
+        replica.reconfigured = Some(Instant::now());
+        actions.push(Action::Command {
+            addr: addr.clone(),
+            command: vec!["REPLICAOF".to_string(), master_addr.host.clone(), master_addr.port.to_string()],
+        });
+
+        let description = master.describe_replica(addr);
+        self.event(kind, description);
+    }
+
+    fn ask_reply(&mut self, name: &str, run_id: &str, reply: Option<RespType>) {
+        let Some(peer) = self.masters.get_mut(name).and_then(|master| master.sentinels.get_mut(run_id)) else {
+            return;
+        };
+
+        peer.ask_pending = false;
+
+        let Some(RespType::Array { values, .. }) = reply else {
+            return;
+        };
+
+        if let [RespType::Integer(down), RespType::BulkString { value: leader, .. }, RespType::Integer(epoch)] =
+            &values[..]
+        {
+            peer.master_down = *down == 1;
+            peer.last_reply = Some(Instant::now());
+
+            if leader != "*" {
+                peer.leader = Some(leader.to_string());
+                peer.leader_epoch = *epoch as u64;
+            }
+        }
+    }
+
+    /// Applies a message of the hello channel, discovering the sentinel that
+    /// sent it and adopting the configuration it announces if newer
+    pub fn process_hello(&mut self, message: &str) {
+        let parts: Vec<&str> = message.split(',').collect();
+
+        let [ip, port, run_id, current_epoch, name, master_ip, master_port, config_epoch] = parts[..] else {
+            return;
+        };
+        let (Ok(port), Ok(current_epoch), Ok(master_port), Ok(config_epoch)) = (
+            port.parse::<u16>(),
+            current_epoch.parse::<u64>(),
+            master_port.parse::<u16>(),
+            config_epoch.parse::<u64>(),
+        ) else {
+            return;
+        };
+
+        if run_id == self.myid {
+            return;
+        }
+
+        let mut events = Vec::new();
+        let Some(master) = self.masters.get_mut(name) else {
+            return;
+        };
+
+        let addr = Addr::new(ip, port);
+
+        match master.sentinels.get_mut(run_id) {
+            Some(peer) => {
+                peer.last_hello = Instant::now();
+                peer.addr = addr;
+            }
+            None => {
+                // A sentinel restarted with a new id at the same address
+                master.sentinels.retain(|_, peer| peer.addr != addr);
+
+                let peer = PeerSentinel {
+                    addr,
+                    run_id: run_id.to_string(),
+                    link: Link::default(),
+                    last_hello: Instant::now(),
+                    master_down: false,
+                    last_reply: None,
+                    last_ask: None,
+                    ask_pending: false,
+                    leader: None,
+                    leader_epoch: 0,
+                };
+
+                events.push(("+sentinel", master.describe_sentinel(&peer)));
+                master.sentinels.insert(run_id.to_string(), peer);
+            }
+        }
+
+        let announced = Addr::new(master_ip, master_port);
+
+        if config_epoch > master.config_epoch {
+            master.config_epoch = config_epoch;
+
+            if announced != master.addr {
+                let old = master.addr.clone();
+
+                events.push(("+config-update-from", format!("sentinel {run_id} {ip} {port} @ {name}")));
+                events.push((
+                    "+switch-master",
+                    format!("{name} {} {} {} {}", old.host, old.port, announced.host, announced.port),
+                ));
+                master.switch_to(announced);
+            }
+        }
+
+        if current_epoch > self.current_epoch {
+            self.current_epoch = current_epoch;
+            events.push(("+new-epoch", current_epoch.to_string()));
+        }
+
+        for (kind, message) in events {
+            self.event(kind, message);
+        }
+    }
+}
+
+/// Publishes the events the sentinel recorded to its subscribers and logs them
+pub fn publish_events(ctx: &mut Context) {
+    let Some(sentinel) = ctx.sentinel.as_mut() else {
+        return;
+    };
+
+    for (kind, message) in std::mem::take(&mut sentinel.events) {
+        eprintln!("{kind} {message}");
+        ctx.pubsub.publish(&kind, &message);
+    }
+}
+
+/// Runs the sentinel for as long as the server runs
+pub async fn run(context: SharedContext) {
+    let mut interval = tokio::time::interval(TIMER_PERIOD);
+
+    loop {
+        interval.tick().await;
+
+        let actions = {
+            let mut ctx = lock(&context);
+            let port = ctx.config.port;
+            let Some(sentinel) = ctx.sentinel.as_mut() else {
+                return;
+            };
+
+            let actions = sentinel.timer(port);
+            publish_events(&mut ctx);
+            actions
+        };
+
+        for action in actions {
+            tokio::spawn(perform(context.clone(), action));
+        }
+    }
+}
+
+async fn perform(context: SharedContext, action: Action) {
+    match action {
+        Action::Ping { master, instance } => {
+            let ping = ["PING".to_string()];
+            let ok = match query(instance.addr(), &ping).await {
+                Ok(RespType::SimpleString { value }) => value == "PONG",
+                // Busy but alive
+                Ok(RespType::SimpleError(err)) => {
+                    let err = err.to_string();
+                    err.starts_with("LOADING") || err.starts_with("MASTERDOWN")
+                }
+                _ => false,
+            };
+
+            with_sentinel(&context, |sentinel| sentinel.ping_reply(&master, &instance, ok));
+        }
+        Action::Info { master, instance } => {
+            let info = match query(instance.addr(), &["INFO".to_string()]).await {
+                Ok(RespType::BulkString { value, .. }) => Some(value),
+                _ => None,
+            };
+
+            let mut actions = Vec::new();
+            with_sentinel(&context, |sentinel| {
+                sentinel.info_reply(&master, &instance, info.as_deref(), &mut actions)
+            });
+
+            // Only reconfigurations follow an `INFO` reply
+            for action in actions {
+                if let Action::Command { addr, command } = action {
+                    tokio::spawn(send(addr, command));
+                }
+            }
+        }
+        Action::Hello { addr, message } => {
+            let _ = publish_hello(&addr, message).await;
+        }
+        Action::Subscribe { master, instance } => {
+            let subscription = tokio::spawn(subscribe_hello(context.clone(), instance.addr().clone()));
+
+            with_sentinel(&context, |sentinel| sentinel.subscribed(&master, &instance, subscription));
+        }
+        Action::AskPeer {
+            master,
+            peer,
+            addr,
+            request,
+        } => {
+            let reply = query(&addr, &request).await.ok();
+
+            with_sentinel(&context, |sentinel| sentinel.ask_reply(&master, &peer, reply));
+        }
+        Action::Command { addr, command } => send(addr, command).await,
+    }
+}
+
+async fn send(addr: Addr, command: Vec<String>) {
+    if let Err(err) = query(&addr, &command).await {
+        eprintln!("err: sending {} to {addr}: {err}", command.join(" "));
+    }
+}
+
+fn with_sentinel(context: &SharedContext, f: impl FnOnce(&mut Sentinel)) {
+    let mut ctx = lock(context);
+
+    if let Some(sentinel) = ctx.sentinel.as_mut() {
+        f(sentinel);
+        publish_events(&mut ctx);
+    }
+}
+
+/// Sends `command` to the instance at `addr` and returns its reply
+async fn query(addr: &Addr, command: &[String]) -> Result<RespType> {
+    let request = async {
+        let mut stream = TcpStream::connect((addr.host.as_str(), addr.port)).await?;
+        stream.write_all(aof::encode(command).as_bytes()).await?;
+
+        let mut buffer = BytesMut::with_capacity(1024);
+
+        loop {
+            if let Some((reply, _)) = RespType::parse(&buffer).map_err(|err| anyhow!(err.to_string()))? {
+                return Ok(reply);
+            }
+
+            if stream.read_buf(&mut buffer).await? == 0 {
+                return Err(anyhow!("connection closed"));
+            }
+        }
+    };
+
+    tokio::time::timeout(QUERY_TIMEOUT, request)
+        .await
+        .map_err(|_| anyhow!("timed out"))?
+}
+
+/// Publishes the hello of this sentinel on the instance at `addr`, announcing
+/// the address it reaches the instance from
+async fn publish_hello(addr: &Addr, mut message: Vec<String>) -> Result<()> {
+    let request = async {
+        let mut stream = TcpStream::connect((addr.host.as_str(), addr.port)).await?;
+        message[0] = stream.local_addr()?.ip().to_string();
+
+        let command = ["PUBLISH".to_string(), HELLO_CHANNEL.to_string(), message.join(",")];
+        stream.write_all(aof::encode(&command).as_bytes()).await?;
+
+        let mut reply = [0; 64];
+
+        match stream.read(&mut reply).await? {
+            0 => Err(anyhow!("connection closed")),
+            _ => Ok(()),
+        }
+    };
+
+    tokio::time::timeout(QUERY_TIMEOUT, request)
+        .await
+        .map_err(|_| anyhow!("timed out"))?
+}
+
+/// Reads the hello channel of the instance at `addr` until the instance is
+/// no longer monitored, connecting again whenever the link breaks
+async fn subscribe_hello(context: SharedContext, addr: Addr) {
+    loop {
+        let _ = read_hellos(&context, &addr).await;
+
+        tokio::time::sleep(PING_PERIOD).await;
+    }
+}
+
+async fn read_hellos(context: &SharedContext, addr: &Addr) -> Result<()> {
+    let mut stream = TcpStream::connect((addr.host.as_str(), addr.port)).await?;
+    let command = ["SUBSCRIBE".to_string(), HELLO_CHANNEL.to_string()];
+    stream.write_all(aof::encode(&command).as_bytes()).await?;
+
+    let mut buffer = BytesMut::with_capacity(1024);
+
+    loop {
+        if stream.read_buf(&mut buffer).await? == 0 {
+            return Err(anyhow!("connection closed"));
+        }
+
+        while let Some((frame, used)) = RespType::parse(&buffer).map_err(|err| anyhow!(err.to_string()))? {
+            buffer.advance(used);
+
+            let (RespType::Array { values, .. } | RespType::Push(values)) = frame else {
+                continue;
+            };
+
+            if let [RespType::BulkString { value: kind, .. }, _, RespType::BulkString { value: message, .. }] =
+                &values[..]
+            {
+                if kind == "message" {
+                    with_sentinel(context, |sentinel| sentinel.process_hello(message));
+                }
+            }
+        }
+    }
+}
+
+/// Random duration below `max`
+fn random_delay(max: Duration) -> Duration {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(Instant::now().elapsed().as_nanos());
+
+    Duration::from_millis(hasher.finish() % max.as_millis().max(1) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sentinel_with_master() -> Sentinel {
+        let mut sentinel = Sentinel::new("a".repeat(40));
+        sentinel.monitor(MonitoredMaster::new("mymaster".to_string(), Addr::new("127.0.0.1", 6379), 2));
+        sentinel
+    }
+
+    fn hello(run_id: &str, port: u16, epoch: u64, master_port: u16, config_epoch: u64) -> String {
+        format!("127.0.0.1,{port},{run_id},{epoch},mymaster,127.0.0.1,{master_port},{config_epoch}")
+    }
+
+    #[test]
+    fn hello_discovers_sentinels_and_configurations() {
+        let mut sentinel = sentinel_with_master();
+        let peer = "b".repeat(40);
+
+        sentinel.process_hello(&hello(&peer, 26380, 0, 6379, 0));
+        sentinel.process_hello(&hello(&sentinel.myid.clone(), 26379, 0, 6379, 0));
+        assert_eq!(sentinel.masters["mymaster"].sentinels.len(), 1);
+
+        // Newer configurations win, older ones are ignored
+        sentinel.process_hello(&hello(&peer, 26380, 3, 6380, 3));
+        assert_eq!(sentinel.masters["mymaster"].addr, Addr::new("127.0.0.1", 6380));
+        assert_eq!(sentinel.masters["mymaster"].replicas.keys().collect::<Vec<_>>(), [&Addr::new("127.0.0.1", 6379)]);
+        assert_eq!(sentinel.current_epoch, 3);
+
+        sentinel.process_hello(&hello(&peer, 26380, 3, 6381, 2));
+        assert_eq!(sentinel.masters["mymaster"].addr, Addr::new("127.0.0.1", 6380));
+
+        let events: Vec<String> = sentinel.events.iter().map(|(kind, _)| kind.clone()).collect();
+        assert!(events.contains(&"+sentinel".to_string()));
+        assert!(events.contains(&"+switch-master".to_string()));
+    }
+
+    #[test]
+    fn votes_once_per_epoch() {
+        let mut sentinel = sentinel_with_master();
+        let (b, c) = ("b".repeat(40), "c".repeat(40));
+
+        assert_eq!(sentinel.vote("mymaster", 1, &b), (Some(b.clone()), 1));
+        assert_eq!(sentinel.vote("mymaster", 1, &c), (Some(b.clone()), 1));
+        assert_eq!(sentinel.vote("mymaster", 2, &c), (Some(c.clone()), 2));
+        assert_eq!(sentinel.current_epoch, 2);
+    }
+
+    #[test]
+    fn elects_leader_with_majority() {
+        let mut sentinel = sentinel_with_master();
+        let myid = sentinel.myid.clone();
+
+        for (run_id, port) in [("b", 26380), ("c", 26381)] {
+            sentinel.process_hello(&hello(&run_id.repeat(40), port, 0, 6379, 0));
+        }
+
+        sentinel.start_failover("mymaster", false);
+        let epoch = sentinel.current_epoch;
+
+        // Only our own vote so far
+        assert_eq!(sentinel.leader("mymaster", epoch), None);
+
+        let peer = sentinel.masters.get_mut("mymaster").unwrap().sentinels.get_mut(&"b".repeat(40)).unwrap();
+        peer.leader = Some(myid.clone());
+        peer.leader_epoch = epoch;
+        assert_eq!(sentinel.leader("mymaster", epoch), Some(myid));
+    }
+
+    #[test]
+    fn selects_replica_by_priority_and_offset() {
+        let mut master = MonitoredMaster::new("mymaster".to_string(), Addr::new("127.0.0.1", 6379), 1);
+
+        for (port, priority, offset) in [(6380, 100, 10), (6381, 100, 20), (6382, 0, 30), (6383, 50, 5)] {
+            let mut replica = MonitoredReplica::new(Addr::new("127.0.0.1", port));
+            replica.priority = priority;
+            replica.repl_offset = offset;
+            replica.link.last_info = Some(Instant::now());
+            master.replicas.insert(replica.addr.clone(), replica);
+        }
+
+        assert_eq!(master.select_replica(), Some(Addr::new("127.0.0.1", 6383)));
+
+        master.replicas.remove(&Addr::new("127.0.0.1", 6383));
+        assert_eq!(master.select_replica(), Some(Addr::new("127.0.0.1", 6381)));
+    }
+}
@@ -0,0 +1,1959 @@
+use std::collections::BTreeSet;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+use crate::commands::{Command, RESPCommand};
+use crate::resp::{errors::Error, types::RespType};
+
+use super::acl::{self, Acl, DEFAULT_USER};
+use super::aof;
+use super::config::Role;
+use super::context::{Client, Context};
+use super::migrate::Migration;
+use super::pubsub::{Outbound, Subscription};
+use super::rdb;
+use super::scripting;
+use super::replication::{snapshot_payload, Replica};
+use super::shared_context::{lock, SharedContext};
+
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Commands queued between `MULTI` and `EXEC`
+#[derive(Default)]
+struct Transaction {
+    queue: Vec<(Command, Box<dyn RESPCommand>)>,
+    /// Set when a command failed to queue, `EXEC` then discards the transaction
+    aborted: bool,
+}
+
+/// Commands a RESP2 client can still run while subscribed to a channel
+const SUBSCRIBED_MODE_COMMANDS: [&str; 8] = [
+    "subscribe",
+    "unsubscribe",
+    "psubscribe",
+    "punsubscribe",
+    "ssubscribe",
+    "sunsubscribe",
+    "ping",
+    "quit",
+];
+
+/// Commands a replica still runs while its master is unreachable and
+/// `replica-serve-stale-data` is off
+const STALE_COMMANDS: [&str; 20] = [
+    "info",
+    "ping",
+    "replicaof",
+    "slaveof",
+    "config",
+    "hello",
+    "client",
+    "replconf",
+    "psync",
+    "subscribe",
+    "unsubscribe",
+    "psubscribe",
+    "punsubscribe",
+    "ssubscribe",
+    "sunsubscribe",
+    "publish",
+    "lastsave",
+    "auth",
+    "acl",
+    "quit",
+];
+
+/// Commands a sentinel answers, it keeps no dataset
+const SENTINEL_COMMANDS: [&str; 13] = [
+    "ping",
+    "sentinel",
+    "subscribe",
+    "unsubscribe",
+    "psubscribe",
+    "punsubscribe",
+    "publish",
+    "info",
+    "client",
+    "hello",
+    "auth",
+    "acl",
+    "quit",
+];
+
+/// State of a single client connection
+pub struct Session {
+    /// State the commands about the connection itself read and change
+    pub(crate) state: SessionState,
+    /// Address of the peer, `None` for internal sessions
+    pub(crate) addr: Option<SocketAddr>,
+    transaction: Option<Transaction>,
+    channels: BTreeSet<String>,
+    patterns: BTreeSet<String>,
+    shard_channels: BTreeSet<String>,
+    /// Messages for the client that are not replies to its requests
+    outbound: Outbound,
+    messages: Option<UnboundedReceiver<RespType>>,
+    /// Announced with `REPLCONF` by a replica before it asks to sync
+    replica_port: u16,
+    replica_capabilities: Vec<String>,
+    /// Set once `PSYNC` made the client a replica, the connection then
+    /// streams it the replication stream instead of replies
+    pub(crate) is_replica: bool,
+    /// Set on the session applying the replication stream of the master,
+    /// which writes even to read only replicas
+    pub(crate) is_master: bool,
+    replication: Option<UnboundedReceiver<Vec<u8>>>,
+    /// Set by `ASKING`, the next command may access a slot being imported
+    asking: bool,
+    /// Keys `MIGRATE` sends before the reply to the request, with the
+    /// position of its reply in the one of `EXEC`
+    migrations: Vec<(Option<usize>, Migration)>,
+    /// Whether the session is in the clients of the context
+    registered: bool,
+}
+
+/// Part of the state of a connection its commands run with, see
+/// [`RESPCommand::execute_for`]
+pub struct SessionState {
+    pub(crate) id: u64,
+    /// RESP version negotiated with `HELLO`
+    pub(crate) protocol: u8,
+    pub(crate) name: Option<String>,
+    /// User the commands run as
+    pub(crate) user: String,
+    /// Cleared until the client authenticates, when the default user needs a password
+    pub(crate) authenticated: bool,
+    /// Set by `CLIENT CACHING`, applies to the next command only
+    pub(crate) caching: Option<bool>,
+    /// Keys registered with `WATCH`, checked by the next `EXEC`
+    pub(crate) watched: Vec<String>,
+    /// Replication offset right after the last write of the client
+    pub(crate) write_offset: u64,
+    /// Set by `WAIT` and `WAITAOF` when they have to block
+    pub(crate) blocked: Option<Blocked>,
+    /// Set by `MIGRATE`, the connection sends the keys before replying
+    pub(crate) migration: Option<Migration>,
+    /// Set while `EXEC` runs the queued commands, which can't block then
+    pub(crate) in_exec: bool,
+}
+
+impl Default for SessionState {
+    fn default() -> Self {
+        Self {
+            id: NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed),
+            protocol: 2,
+            name: None,
+            user: DEFAULT_USER.to_string(),
+            authenticated: false,
+            caching: None,
+            watched: Vec::new(),
+            write_offset: 0,
+            blocked: None,
+            migration: None,
+            in_exec: false,
+        }
+    }
+}
+
+impl SessionState {
+    /// Switches to `user` when `password` is its password
+    pub(crate) fn authenticate(&mut self, user: &str, password: &str, acl: &Acl) -> Result<(), Error> {
+        if !acl.authenticate(user, password) {
+            return Err(Error::custom("WRONGPASS invalid username-password pair or user is disabled."));
+        }
+
+        self.user = user.to_string();
+        self.authenticated = true;
+
+        Ok(())
+    }
+
+    /// Forgets the keys registered with `WATCH`
+    pub(crate) fn unwatch(&mut self, context: &mut Context) {
+        context.store.unwatch(self.id, &self.watched);
+        self.watched.clear();
+    }
+
+    /// Replies right away when `blocked` is already satisfied, or inside
+    /// `EXEC`, otherwise asks replicas for their offsets and leaves the
+    /// client blocked
+    pub(crate) fn block(&mut self, blocked: Blocked, context: &mut Context) -> RespType {
+        let (done, reply) = blocked.check(context);
+
+        if done || self.in_exec {
+            return reply;
+        }
+
+        let getack = ["REPLCONF", "GETACK", "*"].map(str::to_string).to_vec();
+        context.feed_replicas(&aof::encode_all(&[getack]));
+        self.blocked = Some(blocked);
+
+        reply
+    }
+}
+
+/// Acknowledgements a client blocked by `WAIT` or `WAITAOF` waits for
+#[derive(Debug)]
+pub struct Blocked {
+    pub(crate) offset: u64,
+    /// Set for `WAITAOF`, which counts fsyncs instead of acknowledgements
+    pub(crate) aof: bool,
+    /// Whether the local append only file must be fsynced up to `offset`
+    pub(crate) local: bool,
+    pub(crate) replicas: usize,
+    /// `None` blocks until the condition holds
+    pub(crate) deadline: Option<Instant>,
+}
+
+impl Blocked {
+    /// Whether the condition holds, and the reply to send if it does or
+    /// the client stops waiting
+    fn check(&self, context: &Context) -> (bool, RespType) {
+        if !self.aof {
+            let acked = context.replicas.acked(self.offset);
+
+            return (acked >= self.replicas, RespType::Integer(acked as i64));
+        }
+
+        let local = context.aof.is_some() && context.aof_fsynced_offset >= self.offset;
+        let fsynced = context.replicas.fsynced(self.offset);
+        let reply = RespType::array(vec![
+            RespType::Integer(local as i64),
+            RespType::Integer(fsynced as i64),
+        ]);
+
+        ((local || !self.local) && fsynced >= self.replicas, reply)
+    }
+
+    /// Waits for the condition or the deadline and returns the reply
+    pub async fn resolve(self, context: &SharedContext) -> RespType {
+        let acks = lock(context).replicas.acks();
+
+        loop {
+            let notified = acks.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            let (done, reply) = self.check(&lock(context));
+
+            if done
+                || self
+                    .deadline
+                    .is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                return reply;
+            }
+
+            match self.deadline {
+                Some(deadline) => {
+                    let _ = tokio::time::timeout_at(deadline.into(), notified).await;
+                }
+                None => notified.await,
+            }
+        }
+    }
+}
+
+/// Whether the server is a replica that lost its master and shouldn't
+/// answer with the data it has
+fn is_stale(context: &SharedContext) -> bool {
+    let context = lock(context);
+
+    context.config.role == Role::Slave && !context.config.replica_serve_stale_data && !context.master_link.up
+}
+
+/// Checks the keys of a command are served by this node in cluster mode
+fn route(command: &str, keys: &[String], asking: bool, context: &SharedContext) -> Result<(), Error> {
+    let context = lock(context);
+
+    match &context.cluster {
+        Some(cluster) => cluster.route(command, keys, &context.store, asking),
+        None => Ok(()),
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Session {
+    pub fn new() -> Self {
+        let (outbound, messages) = unbounded_channel();
+
+        Self {
+            state: SessionState::default(),
+            addr: None,
+            transaction: None,
+            channels: BTreeSet::new(),
+            patterns: BTreeSet::new(),
+            shard_channels: BTreeSet::new(),
+            outbound,
+            messages: Some(messages),
+            replica_port: 0,
+            replica_capabilities: Vec::new(),
+            is_replica: false,
+            is_master: false,
+            replication: None,
+            asking: false,
+            migrations: Vec::new(),
+            registered: false,
+        }
+    }
+
+    /// Makes the session reachable by other sessions, e.g. as the target of
+    /// `CLIENT TRACKING ... REDIRECT`
+    ///
+    /// The context is held by a running script, the session then registers
+    /// with the first request it handles once the script is done.
+    pub fn register(&mut self, context: &SharedContext) {
+        if let Ok(mut context) = context.try_lock() {
+            self.register_with(&mut context);
+        }
+    }
+
+    fn register_with(&mut self, context: &mut Context) {
+        let client = Client {
+            outbound: self.outbound.clone(),
+            protocol: self.state.protocol,
+        };
+
+        context.clients.insert(self.state.id, client);
+        self.registered = true;
+        self.state.authenticated = context
+            .acl
+            .get(DEFAULT_USER)
+            .is_some_and(|user| user.enabled && user.is_nopass());
+    }
+
+    /// Hands the queue of out of band messages over to the connection
+    ///
+    /// Messages queued while handling a request must be written before the
+    /// reply to that request.
+    pub fn take_messages(&mut self) -> Option<UnboundedReceiver<RespType>> {
+        self.messages.take()
+    }
+
+    /// Hands the replication stream over to the connection, once the client
+    /// became a replica
+    pub fn take_replication(&mut self) -> Option<UnboundedReceiver<Vec<u8>>> {
+        self.replication.take()
+    }
+
+    /// Number of channels, patterns and sharded channels the session is subscribed to
+    pub fn subscriptions(&self) -> usize {
+        self.channels.len() + self.patterns.len() + self.shard_channels.len()
+    }
+
+    fn subscribed(&mut self, kind: Subscription) -> &mut BTreeSet<String> {
+        match kind {
+            Subscription::Channel => &mut self.channels,
+            Subscription::Pattern => &mut self.patterns,
+            Subscription::Shard => &mut self.shard_channels,
+        }
+    }
+
+    /// Count reported in (un)subscribe confirmations, sharded channels are
+    /// counted apart from the global namespace
+    fn subscription_count(&self, kind: Subscription) -> i64 {
+        match kind {
+            Subscription::Shard => self.shard_channels.len() as i64,
+            _ => (self.channels.len() + self.patterns.len()) as i64,
+        }
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.transaction.is_some()
+    }
+
+    /// Runs a request from the client and returns its reply
+    pub fn handle(&mut self, request: RespType, context: &SharedContext) -> RespType {
+        let command = match Command::try_from(request) {
+            Ok(command) => command,
+            Err(err) => return self.abort_with(err),
+        };
+
+        let name = command.name().to_lowercase();
+
+        // The running script holds the context, only killing it is possible
+        if scripting::is_busy() {
+            return match command.args().first() {
+                Some(subcommand) if matches!(name.as_str(), "script" | "function") && subcommand.eq_ignore_ascii_case("kill") => {
+                    scripting::kill(name == "function")
+                }
+                _ => self.abort_with(scripting::busy_error()),
+            };
+        }
+
+        if !self.registered {
+            self.register_with(&mut lock(context));
+        }
+
+        if !self.is_master {
+            if let Err(err) = self.authorize(&name, command.args(), context) {
+                return self.abort_with(err);
+            }
+        }
+
+        // `ASKING` only applies to the command right after it
+        let asking = std::mem::take(&mut self.asking) || name == "restore-asking";
+
+        if self.state.protocol < 3 && self.subscriptions() > 0 && !SUBSCRIBED_MODE_COMMANDS.contains(&name.as_str()) {
+            return Error::custom(format!(
+                "ERR Can't execute '{name}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context"
+            ))
+            .into();
+        }
+
+        let is_pubsub = matches!(
+            name.as_str(),
+            "subscribe" | "unsubscribe" | "psubscribe" | "punsubscribe" | "ssubscribe" | "sunsubscribe"
+        );
+
+        if is_pubsub && self.in_transaction() {
+            return self.abort_with(Error::custom("ERR Command not allowed inside a transaction"));
+        }
+
+        if !SENTINEL_COMMANDS.contains(&name.as_str()) && lock(context).sentinel.is_some() {
+            return self.abort_with(Error::UnknownCommand {
+                command: command.name().to_string(),
+            });
+        }
+
+        if !STALE_COMMANDS.contains(&name.as_str()) && !self.is_master && is_stale(context) {
+            return self.abort_with(Error::custom(
+                "MASTERDOWN Link with MASTER is down and replica-serve-stale-data is set to 'no'.",
+            ));
+        }
+
+        match name.as_str() {
+            "multi" | "exec" | "discard" if !command.args().is_empty() => {
+                self.abort_with(Error::WrongNumberOfArguments { command: name })
+            }
+            "watch" if command.args().is_empty() => {
+                self.abort_with(Error::WrongNumberOfArguments { command: name })
+            }
+            "multi" => self.multi(),
+            "exec" => self.exec(context),
+            "discard" => self.discard(context),
+            "watch" => self.watch(command.args(), context),
+            "subscribe" | "psubscribe" | "ssubscribe" if command.args().is_empty() => {
+                Error::WrongNumberOfArguments { command: name }.into()
+            }
+            "subscribe" => self.subscribe(Subscription::Channel, command.args(), context),
+            "psubscribe" => self.subscribe(Subscription::Pattern, command.args(), context),
+            "ssubscribe" => self.subscribe(Subscription::Shard, command.args(), context),
+            "unsubscribe" => self.unsubscribe(Subscription::Channel, command.args(), context),
+            "punsubscribe" => self.unsubscribe(Subscription::Pattern, command.args(), context),
+            "sunsubscribe" => self.unsubscribe(Subscription::Shard, command.args(), context),
+            "ping" if self.state.protocol < 3 && self.subscriptions() > 0 => {
+                let message = command.args().first().cloned().unwrap_or_default();
+                RespType::array(vec![RespType::bulk_string("pong"), RespType::bulk_string(message)])
+            }
+            "replconf" => self.replconf(command.args(), context),
+            "psync" => self.psync(command.args(), context),
+            "asking" if !command.args().is_empty() => Error::WrongNumberOfArguments { command: name }.into(),
+            "asking" => self.asking(context),
+            _ => self.run(command, context, asking),
+        }
+    }
+
+    /// Checks the user of the session may run a command and access its
+    /// channels, and its keys for the commands the session runs itself
+    ///
+    /// The keys of the other commands are checked once they are parsed.
+    fn authorize(&mut self, name: &str, args: &[String], context: &SharedContext) -> Result<(), Error> {
+        if matches!(name, "auth" | "hello" | "quit") {
+            return Ok(());
+        }
+
+        let context = lock(context);
+
+        // A deleted user has to authenticate again
+        let user = match context.acl.get(&self.state.user) {
+            Some(user) if self.state.authenticated => user,
+            _ => {
+                self.state.authenticated = false;
+                return Err(Error::custom("NOAUTH Authentication required."));
+            }
+        };
+
+        acl::check_command(user, name, args)?;
+
+        match name {
+            "watch" => acl::check_keys(user, name, args),
+            _ => Ok(()),
+        }
+    }
+
+    /// Marks the open transaction as failed and returns `err`
+    fn abort_with(&mut self, err: Error) -> RespType {
+        if let Some(transaction) = self.transaction.as_mut() {
+            transaction.aborted = true;
+        }
+
+        err.into()
+    }
+
+    fn multi(&mut self) -> RespType {
+        if self.in_transaction() {
+            return Error::custom("ERR MULTI calls can not be nested").into();
+        }
+
+        self.transaction = Some(Transaction::default());
+
+        RespType::ok()
+    }
+
+    fn exec(&mut self, context: &SharedContext) -> RespType {
+        let transaction = match self.transaction.take() {
+            Some(transaction) => transaction,
+            None => return Error::custom("ERR EXEC without MULTI").into(),
+        };
+
+        let mut context = lock(context);
+        let dirty = context.store.is_dirty(self.state.id, &self.state.watched);
+
+        self.state.unwatch(&mut context);
+
+        if transaction.aborted {
+            return Error::custom("EXECABORT Transaction discarded because of previous errors.").into();
+        }
+
+        if dirty {
+            return RespType::NullArray;
+        }
+
+        let mut propagated = Vec::new();
+        self.state.in_exec = true;
+
+        let replies = transaction
+            .queue
+            .into_iter()
+            .enumerate()
+            .map(|(position, (request, mut command))| {
+                let reply = self.execute(&request, command.as_mut(), &mut context, &mut propagated);
+
+                if let Some(migration) = self.state.migration.take() {
+                    self.migrations.push((Some(position), migration));
+                }
+
+                reply
+            })
+            .collect();
+
+        self.state.in_exec = false;
+        self.state.caching = None;
+        self.propagate(propagated, &mut context);
+
+        RespType::array(replies)
+    }
+
+    fn discard(&mut self, context: &SharedContext) -> RespType {
+        match self.transaction.take() {
+            Some(_) => {
+                self.unwatch(context);
+                RespType::ok()
+            }
+            None => Error::custom("ERR DISCARD without MULTI").into(),
+        }
+    }
+
+    fn watch(&mut self, keys: &[String], context: &SharedContext) -> RespType {
+        if self.in_transaction() {
+            return Error::custom("ERR WATCH inside MULTI is not allowed").into();
+        }
+
+        if let Err(err) = route("watch", keys, false, context) {
+            return err.into();
+        }
+
+        let mut context = lock(context);
+
+        for key in keys {
+            context.store.watch(self.state.id, key);
+
+            if !self.state.watched.contains(key) {
+                self.state.watched.push(key.to_string());
+            }
+        }
+
+        RespType::ok()
+    }
+
+    fn unwatch(&mut self, context: &SharedContext) {
+        self.state.unwatch(&mut lock(context));
+    }
+
+    /// Queues every reply but the last one as out of band messages
+    fn reply_many(&mut self, mut replies: Vec<RespType>) -> RespType {
+        let last = replies.pop().unwrap_or(RespType::NullArray);
+
+        for reply in replies {
+            let _ = self.outbound.send(reply);
+        }
+
+        last
+    }
+
+    fn subscribe(&mut self, kind: Subscription, names: &[String], context: &SharedContext) -> RespType {
+        let mut context = lock(context);
+        let mut replies = Vec::with_capacity(names.len());
+
+        for name in names {
+            if self.subscribed(kind).insert(name.to_string()) {
+                context.pubsub.subscribe(kind, name, self.state.id, &self.outbound);
+            }
+
+            replies.push(RespType::Push(vec![
+                RespType::bulk_string(kind.subscribe_command()),
+                RespType::bulk_string(name),
+                RespType::Integer(self.subscription_count(kind)),
+            ]));
+        }
+
+        self.reply_many(replies)
+    }
+
+    /// Unsubscribes from `names`, or from everything in the namespace when empty
+    fn unsubscribe(&mut self, kind: Subscription, names: &[String], context: &SharedContext) -> RespType {
+        let mut context = lock(context);
+
+        let names: Vec<String> = if names.is_empty() {
+            self.subscribed(kind).iter().cloned().collect()
+        } else {
+            names.to_vec()
+        };
+
+        if names.is_empty() {
+            return RespType::Push(vec![
+                RespType::bulk_string(kind.unsubscribe_command()),
+                RespType::Null,
+                RespType::Integer(self.subscription_count(kind)),
+            ]);
+        }
+
+        let mut replies = Vec::with_capacity(names.len());
+
+        for name in names {
+            self.subscribed(kind).remove(&name);
+            context.pubsub.unsubscribe(kind, &name, self.state.id);
+
+            replies.push(RespType::Push(vec![
+                RespType::bulk_string(kind.unsubscribe_command()),
+                RespType::bulk_string(name),
+                RespType::Integer(self.subscription_count(kind)),
+            ]));
+        }
+
+        self.reply_many(replies)
+    }
+
+    /// `REPLCONF option value [option value ...]`, sent by replicas during the handshake
+    ///
+    /// Replicas also acknowledge their offsets with `ACK offset [FACK offset]`,
+    /// which is never answered.
+    fn replconf(&mut self, args: &[String], context: &SharedContext) -> RespType {
+        if args.is_empty() || args.len() % 2 == 1 {
+            return Error::Syntax.into();
+        }
+
+        let mut ack = None;
+        let mut fack = None;
+
+        for pair in args.chunks(2) {
+            match pair[0].to_lowercase().as_str() {
+                "listening-port" => match pair[1].parse::<u16>() {
+                    Ok(port) => self.replica_port = port,
+                    Err(_) => return Error::NotAnInteger.into(),
+                },
+                "capa" => self.replica_capabilities.push(pair[1].to_lowercase()),
+                "ack" => match pair[1].parse::<u64>() {
+                    Ok(offset) => ack = Some(offset),
+                    Err(_) => return Error::NotAnInteger.into(),
+                },
+                "fack" => match pair[1].parse::<u64>() {
+                    Ok(offset) => fack = Some(offset),
+                    Err(_) => return Error::NotAnInteger.into(),
+                },
+                // Accepted for compatibility: the address is taken from the
+                // connection, and acknowledgements are only sent to a master
+                "ip-address" | "getack" => {}
+                option => return Error::custom(format!("ERR Unrecognized REPLCONF option: {option}")).into(),
+            }
+        }
+
+        if let Some(offset) = ack {
+            if self.is_replica {
+                lock(context).replicas.ack(self.state.id, offset, fack);
+            }
+        }
+
+        RespType::ok()
+    }
+
+    /// `PSYNC replicationid offset`, turns the client into a replica
+    ///
+    /// A replica asking for the next byte of a history this server knows,
+    /// still in the backlog, continues from there. Others get the whole
+    /// dataset: the snapshot is taken and the replica attached under the
+    /// same lock, so the replication stream picks up exactly where the
+    /// snapshot ends.
+    fn psync(&mut self, args: &[String], context: &SharedContext) -> RespType {
+        if args.len() != 2 {
+            return Error::WrongNumberOfArguments { command: "psync".to_string() }.into();
+        }
+
+        if self.in_transaction() {
+            return self.abort_with(Error::custom("ERR Command not allowed inside a transaction"));
+        }
+
+        if self.is_replica {
+            return RespType::ok();
+        }
+
+        let mut context = lock(context);
+        let (stream, replication) = unbounded_channel();
+        let ip = self.addr.map_or_else(|| "127.0.0.1".to_string(), |addr| addr.ip().to_string());
+        let capabilities = std::mem::take(&mut self.replica_capabilities);
+        let psync2 = capabilities.iter().any(|capa| capa == "psync2");
+        let eof = capabilities.iter().any(|capa| capa == "eof");
+        let replica = Replica::new(stream, ip, self.replica_port, capabilities);
+
+        self.is_replica = true;
+        self.replication = Some(replication);
+
+        let config = &context.config;
+        let (replid, offset) = (&args[0], args[1].parse::<u64>().ok());
+        let known = *replid == config.master_replid
+            || (*replid == config.master_replid2
+                && matches!((offset, config.second_repl_offset), (Some(offset), Some(second)) if offset <= second));
+        let missed = match offset {
+            Some(offset) if known => context.replicas.backlog().and_then(|backlog| backlog.since(offset)),
+            _ => None,
+        };
+
+        if let Some(missed) = missed {
+            let reply = match psync2 {
+                true => format!("CONTINUE {}", context.config.master_replid),
+                false => "CONTINUE".to_string(),
+            };
+
+            context.replicas.add(self.state.id, replica, missed);
+
+            return RespType::simple_string(reply);
+        }
+
+        let context = &mut *context;
+        let size = context.config.repl_backlog_size as usize;
+        let offset = context.config.master_repl_offset;
+
+        context.replicas.create_backlog(size, offset);
+
+        // Replicas that can't parse the end marker framing get a disk based sync
+        let offset = if context.config.repl_diskless_sync && eof {
+            let delay = Duration::from_secs(context.config.repl_diskless_sync_delay);
+            let store = &context.store;
+            let functions = context.functions.codes();
+
+            context.replicas.add_diskless(self.state.id, replica, offset, || rdb::encode(store, &functions), delay)
+        } else {
+            let path = context.rdb_path();
+            let snapshot = rdb::encode(&context.store, &context.functions.codes());
+
+            if let Err(err) = context.rdb.save(&path, &snapshot) {
+                eprintln!("err: saving the snapshot for replication: {err}");
+
+                return Error::custom(format!("ERR {err}")).into();
+            }
+
+            context.replicas.add(self.state.id, replica, snapshot_payload(&snapshot));
+            offset
+        };
+
+        RespType::simple_string(format!("FULLRESYNC {} {offset}", context.config.master_replid))
+    }
+
+    /// `ASKING`, the next command may access a slot this node is importing
+    fn asking(&mut self, context: &SharedContext) -> RespType {
+        if lock(context).cluster.is_none() {
+            return Error::custom("ERR This instance has cluster support disabled").into();
+        }
+
+        self.asking = true;
+
+        RespType::ok()
+    }
+
+    /// Whether `MIGRATE` has keys to send before the reply to the last
+    /// request goes out, see [`Session::migrate`]
+    pub fn is_migrating(&self) -> bool {
+        !self.migrations.is_empty()
+    }
+
+    /// Sends the keys `MIGRATE` moves and returns `reply` with the replies
+    /// of the migrations in place
+    pub async fn migrate(&mut self, mut reply: RespType, context: &SharedContext) -> RespType {
+        for (position, migration) in std::mem::take(&mut self.migrations) {
+            let migrated = migration.run(context).await;
+
+            match (position, &mut reply) {
+                (Some(position), RespType::Array { values, .. }) => values[position] = migrated,
+                _ => reply = migrated,
+            }
+        }
+
+        reply
+    }
+
+    /// Hands the condition the client is blocked on over to the connection,
+    /// which replies once [`Blocked::resolve`] returns
+    pub fn take_blocked(&mut self) -> Option<Blocked> {
+        self.state.blocked.take()
+    }
+
+    /// Releases the server side state of the connection
+    pub fn close(&mut self, context: &SharedContext) {
+        self.transaction = None;
+        self.unwatch(context);
+
+        let mut context = lock(context);
+
+        context.tracking.disable(self.state.id);
+        context.clients.remove(&self.state.id);
+        context.replicas.remove(self.state.id);
+
+        for kind in [Subscription::Channel, Subscription::Pattern, Subscription::Shard] {
+            for name in std::mem::take(self.subscribed(kind)) {
+                context.pubsub.unsubscribe(kind, &name, self.state.id);
+            }
+        }
+    }
+
+    fn run(&mut self, command: Command, context: &SharedContext, asking: bool) -> RespType {
+        let mut executable = match command.create_command() {
+            Ok(executable) => executable,
+            Err(err) => return self.abort_with(err),
+        };
+
+        if !self.is_master {
+            let keys = executable.keys();
+            let allowed = match lock(context).acl.get(&self.state.user) {
+                Some(user) => acl::check_keys(user, executable.command_name(), &keys),
+                None => Ok(()),
+            };
+
+            if let Err(err) = allowed.and_then(|_| route(executable.command_name(), &keys, asking, context)) {
+                return self.abort_with(err);
+            }
+        }
+
+        if executable.is_write() && !self.is_master {
+            let context = lock(context);
+
+            if context.config.role == Role::Slave && context.config.replica_read_only {
+                return self.abort_with(Error::custom("READONLY You can't write against a read only replica."));
+            }
+        }
+
+        if let Some(transaction) = self.transaction.as_mut() {
+            if executable.is_invalid() {
+                transaction.aborted = true;
+
+                return Error::WrongNumberOfArguments {
+                    command: executable.command_name().to_string(),
+                }
+                .into();
+            }
+
+            transaction.queue.push((command, executable));
+
+            return RespType::simple_string("QUEUED");
+        }
+
+        let mut context = lock(context);
+        let mut propagated = Vec::new();
+        let reply = self.execute(&command, executable.as_mut(), &mut context, &mut propagated);
+
+        if let Some(migration) = self.state.migration.take() {
+            self.migrations.push((None, migration));
+        }
+
+        // `CLIENT CACHING` sets it for the command after it
+        if executable.command_name() != "client" {
+            self.state.caching = None;
+        }
+
+        self.propagate(propagated, &mut context);
+
+        reply
+    }
+
+    /// Persists and replicates the writes of a request, remembering where
+    /// they end in the replication stream for `WAIT`
+    fn propagate(&mut self, commands: Vec<Vec<String>>, context: &mut Context) {
+        if commands.is_empty() {
+            return;
+        }
+
+        context.propagate(commands);
+        self.state.write_offset = context.config.master_repl_offset;
+    }
+
+    /// Executes `command`, remembering the keys it read when tracking is on
+    /// and invalidating the keys it modified
+    ///
+    /// Successful writes are added to `propagated`, to be persisted once the
+    /// whole request ran.
+    fn execute(
+        &mut self,
+        request: &Command,
+        command: &mut dyn RESPCommand,
+        context: &mut Context,
+        propagated: &mut Vec<Vec<String>>,
+    ) -> RespType {
+        if !self.is_master {
+            command.set_user(&self.state.user);
+        }
+
+        let reply = command.execute_for(&mut self.state, context);
+
+        if command.is_write() && !matches!(reply, RespType::SimpleError(_)) {
+            match command.propagate(context) {
+                Some(commands) => propagated.extend(commands),
+                None => propagated.push(request.parts()),
+            }
+        }
+
+        if let Some(options) = context.tracking.options(self.state.id) {
+            let remember = !options.bcast
+                && !command.is_write()
+                && match (options.optin, options.optout) {
+                    (true, _) => self.state.caching == Some(true),
+                    (_, true) => self.state.caching != Some(false),
+                    _ => true,
+                };
+
+            if remember {
+                context.tracking.remember(self.state.id, &command.keys());
+            }
+        }
+
+        context.invalidate(Some(self.state.id));
+
+        reply
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::context::Context;
+    use crate::utils::shared_context::create_shared_context;
+
+    fn request(args: &[&str]) -> RespType {
+        RespType::array(args.iter().map(|arg| RespType::bulk_string(*arg)).collect())
+    }
+
+    /// Context of a master starting full syncs right away, disk based ones
+    /// saving their snapshot in a temporary directory
+    fn master_context(name: &str) -> SharedContext {
+        let dir = std::env::temp_dir().join(format!("session-test-{}-{name}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut context = Context::default();
+        context.config.dir = dir.to_string_lossy().to_string();
+        context.config.repl_diskless_sync_delay = 0;
+
+        create_shared_context(context)
+    }
+
+    #[test]
+    fn exec_runs_queued_commands() {
+        let context = create_shared_context(Context::default());
+        let mut session = Session::new();
+
+        assert_eq!(session.handle(request(&["MULTI"]), &context), RespType::ok());
+        assert_eq!(
+            session.handle(request(&["SET", "key", "value"]), &context),
+            RespType::simple_string("QUEUED")
+        );
+        assert_eq!(
+            session.handle(request(&["GET", "key"]), &context),
+            RespType::simple_string("QUEUED")
+        );
+        assert!(!context.lock().unwrap().store.contains_key("key"));
+
+        assert_eq!(
+            session.handle(request(&["EXEC"]), &context),
+            RespType::array(vec![RespType::ok(), RespType::bulk_string("value")])
+        );
+        assert!(!session.in_transaction());
+    }
+
+    #[test]
+    fn invalid_command_aborts_exec() {
+        let context = create_shared_context(Context::default());
+        let mut session = Session::new();
+
+        session.handle(request(&["MULTI"]), &context);
+        session.handle(request(&["SET", "key", "value"]), &context);
+
+        let response = session.handle(request(&["GET"]), &context);
+        assert!(matches!(response, RespType::SimpleError(Error::WrongNumberOfArguments { .. })));
+
+        let response = session.handle(request(&["EXEC"]), &context);
+        assert_eq!(
+            response,
+            Error::custom("EXECABORT Transaction discarded because of previous errors.").into()
+        );
+        assert!(!context.lock().unwrap().store.contains_key("key"));
+    }
+
+    #[test]
+    fn unknown_command_aborts_exec() {
+        let context = create_shared_context(Context::default());
+        let mut session = Session::new();
+
+        session.handle(request(&["MULTI"]), &context);
+        session.handle(request(&["NOPE"]), &context);
+
+        let response = session.handle(request(&["EXEC"]), &context);
+        assert!(matches!(response, RespType::SimpleError(Error::Custom { .. })));
+    }
+
+    #[test]
+    fn discard_drops_queue() {
+        let context = create_shared_context(Context::default());
+        let mut session = Session::new();
+
+        session.handle(request(&["MULTI"]), &context);
+        session.handle(request(&["SET", "key", "value"]), &context);
+
+        assert_eq!(session.handle(request(&["DISCARD"]), &context), RespType::ok());
+        assert!(!context.lock().unwrap().store.contains_key("key"));
+        assert_eq!(
+            session.handle(request(&["EXEC"]), &context),
+            Error::custom("ERR EXEC without MULTI").into()
+        );
+    }
+
+    #[test]
+    fn watched_key_modified_by_other_session() {
+        let context = create_shared_context(Context::default());
+        let mut session = Session::new();
+        let mut other = Session::new();
+
+        assert_eq!(session.handle(request(&["WATCH", "key"]), &context), RespType::ok());
+        other.handle(request(&["SET", "key", "other"]), &context);
+
+        session.handle(request(&["MULTI"]), &context);
+        session.handle(request(&["SET", "key", "mine"]), &context);
+
+        assert_eq!(session.handle(request(&["EXEC"]), &context), RespType::NullArray);
+        assert_eq!(
+            other.handle(request(&["GET", "key"]), &context),
+            RespType::bulk_string("other")
+        );
+    }
+
+    #[test]
+    fn watches_are_cleared_after_exec() {
+        let context = create_shared_context(Context::default());
+        let mut session = Session::new();
+        let mut other = Session::new();
+
+        session.handle(request(&["WATCH", "key"]), &context);
+        other.handle(request(&["SET", "key", "other"]), &context);
+        session.handle(request(&["MULTI"]), &context);
+        assert_eq!(session.handle(request(&["EXEC"]), &context), RespType::NullArray);
+
+        session.handle(request(&["MULTI"]), &context);
+        session.handle(request(&["SET", "key", "mine"]), &context);
+        assert_eq!(
+            session.handle(request(&["EXEC"]), &context),
+            RespType::array(vec![RespType::ok()])
+        );
+    }
+
+    #[test]
+    fn flushdb_invalidates_watch() {
+        let context = create_shared_context(Context::default());
+        let mut session = Session::new();
+        let mut other = Session::new();
+
+        session.handle(request(&["WATCH", "key"]), &context);
+        other.handle(request(&["FLUSHDB"]), &context);
+        session.handle(request(&["MULTI"]), &context);
+
+        assert_eq!(session.handle(request(&["EXEC"]), &context), RespType::NullArray);
+    }
+
+    #[test]
+    fn unwatch_forgets_keys() {
+        let context = create_shared_context(Context::default());
+        let mut session = Session::new();
+        let mut other = Session::new();
+
+        session.handle(request(&["WATCH", "key"]), &context);
+        assert_eq!(session.handle(request(&["UNWATCH"]), &context), RespType::ok());
+        other.handle(request(&["SET", "key", "other"]), &context);
+        session.handle(request(&["MULTI"]), &context);
+
+        assert_eq!(session.handle(request(&["EXEC"]), &context), RespType::array(vec![]));
+    }
+
+    #[test]
+    fn connection_commands_are_queued() {
+        let context = create_shared_context(Context::default());
+        let mut session = Session::new();
+        let mut other = Session::new();
+        let queued = RespType::simple_string("QUEUED");
+
+        session.handle(request(&["WATCH", "key"]), &context);
+        session.handle(request(&["MULTI"]), &context);
+
+        for command in [&["CLIENT", "SETNAME", "conn"][..], &["UNWATCH"], &["WAIT", "1", "0"], &["ACL", "WHOAMI"]] {
+            assert_eq!(session.handle(request(command), &context), queued);
+        }
+
+        // Nothing ran yet, the watch still holds
+        assert_eq!(session.handle(request(&["CLIENT", "GETNAME"]), &context), queued);
+        other.handle(request(&["SET", "key", "other"]), &context);
+        assert_eq!(session.handle(request(&["EXEC"]), &context), RespType::NullArray);
+        assert_eq!(session.handle(request(&["CLIENT", "GETNAME"]), &context), RespType::Null);
+
+        session.handle(request(&["MULTI"]), &context);
+        session.handle(request(&["CLIENT", "SETNAME", "conn"]), &context);
+        session.handle(request(&["WAIT", "1", "0"]), &context);
+        session.handle(request(&["CLIENT", "GETNAME"]), &context);
+
+        // WAIT can't block inside a transaction
+        assert_eq!(
+            session.handle(request(&["EXEC"]), &context),
+            RespType::array(vec![RespType::ok(), RespType::Integer(0), RespType::bulk_string("conn")])
+        );
+        assert!(session.take_blocked().is_none());
+
+        session.handle(request(&["SET", "key", "value"]), &context);
+        session.handle(request(&["MULTI"]), &context);
+        session.handle(request(&["PING"]), &context);
+        session.handle(request(&["MIGRATE", "127.0.0.1", "1", "key", "0", "10"]), &context);
+        session.handle(request(&["EXEC"]), &context);
+        assert_eq!(session.migrations.len(), 1);
+        assert_eq!(session.migrations[0].0, Some(1));
+    }
+
+    #[test]
+    fn watch_inside_multi() {
+        let context = create_shared_context(Context::default());
+        let mut session = Session::new();
+
+        session.handle(request(&["MULTI"]), &context);
+
+        assert_eq!(
+            session.handle(request(&["WATCH", "key"]), &context),
+            Error::custom("ERR WATCH inside MULTI is not allowed").into()
+        );
+    }
+
+    #[test]
+    fn subscribe_and_receive_messages() {
+        let context = create_shared_context(Context::default());
+        let mut subscriber = Session::new();
+        let mut publisher = Session::new();
+        let mut messages = subscriber.take_messages().unwrap();
+
+        let reply = subscriber.handle(request(&["SUBSCRIBE", "a", "b"]), &context);
+        assert_eq!(
+            reply,
+            RespType::Push(vec![RespType::bulk_string("subscribe"), RespType::bulk_string("b"), RespType::Integer(2)])
+        );
+        assert_eq!(
+            messages.try_recv().unwrap(),
+            RespType::Push(vec![RespType::bulk_string("subscribe"), RespType::bulk_string("a"), RespType::Integer(1)])
+        );
+
+        let reply = publisher.handle(request(&["PUBLISH", "a", "hello"]), &context);
+        assert_eq!(reply, RespType::Integer(1));
+        assert_eq!(
+            messages.try_recv().unwrap(),
+            RespType::Push(vec![
+                RespType::bulk_string("message"),
+                RespType::bulk_string("a"),
+                RespType::bulk_string("hello"),
+            ])
+        );
+    }
+
+    /// Handles a request as read from the wire, the reply as sent back
+    fn wire(session: &mut Session, input: &[u8], context: &SharedContext) -> Vec<u8> {
+        let (request, _) = RespType::parse(input).unwrap().unwrap();
+
+        session.handle(request, context).to_bytes(2)
+    }
+
+    #[test]
+    fn binary_keys_and_values_round_trip() {
+        let context = create_shared_context(Context::default());
+        let mut session = Session::new();
+        let mut subscriber = Session::new();
+        let mut messages = subscriber.take_messages().unwrap();
+
+        assert_eq!(
+            wire(&mut session, b"*3\r\n$3\r\nSET\r\n$3\r\n\xff\x00k\r\n$4\r\n\xe9\x80\r\n\r\n", &context),
+            b"+OK\r\n"
+        );
+        assert_eq!(
+            wire(&mut session, b"*2\r\n$3\r\nGET\r\n$3\r\n\xff\x00k\r\n", &context),
+            b"$4\r\n\xe9\x80\r\n\r\n"
+        );
+        assert_eq!(wire(&mut session, b"*2\r\n$3\r\nGET\r\n$2\r\n\xff\x00\r\n", &context), b"$-1\r\n");
+        assert_eq!(
+            wire(&mut session, b"*2\r\n$4\r\nECHO\r\n$2\r\n\xc3\x28\r\n", &context),
+            b"$2\r\n\xc3\x28\r\n"
+        );
+
+        wire(&mut subscriber, b"*2\r\n$9\r\nSUBSCRIBE\r\n$1\r\n\xfe\r\n", &context);
+        assert_eq!(
+            wire(&mut session, b"*3\r\n$7\r\nPUBLISH\r\n$1\r\n\xfe\r\n$2\r\n\x00\xff\r\n", &context),
+            b":1\r\n"
+        );
+        assert_eq!(
+            messages.try_recv().unwrap().to_bytes(2),
+            b"*3\r\n$7\r\nmessage\r\n$1\r\n\xfe\r\n$2\r\n\x00\xff\r\n"
+        );
+    }
+
+    #[test]
+    fn subscribed_mode_restrictions() {
+        let context = create_shared_context(Context::default());
+        let mut session = Session::new();
+
+        session.handle(request(&["PSUBSCRIBE", "news.*"]), &context);
+
+        let reply = session.handle(request(&["GET", "key"]), &context);
+        assert!(matches!(reply, RespType::SimpleError(Error::Custom { .. })));
+
+        let reply = session.handle(request(&["PING"]), &context);
+        assert_eq!(
+            reply,
+            RespType::array(vec![RespType::bulk_string("pong"), RespType::bulk_string("")])
+        );
+
+        let reply = session.handle(request(&["PUNSUBSCRIBE"]), &context);
+        assert_eq!(
+            reply,
+            RespType::Push(vec![
+                RespType::bulk_string("punsubscribe"),
+                RespType::bulk_string("news.*"),
+                RespType::Integer(0),
+            ])
+        );
+        assert_eq!(session.handle(request(&["PING"]), &context), RespType::simple_string("PONG"));
+    }
+
+    #[test]
+    fn resp3_clients_are_not_restricted() {
+        let context = create_shared_context(Context::default());
+        let mut session = Session::new();
+
+        let reply = session.handle(request(&["HELLO", "3"]), &context);
+        assert!(matches!(reply, RespType::Map(_)));
+
+        session.handle(request(&["SUBSCRIBE", "a"]), &context);
+        assert_eq!(session.handle(request(&["GET", "key"]), &context), RespType::Null);
+    }
+
+    #[test]
+    fn sharded_subscriptions() {
+        let context = create_shared_context(Context::default());
+        let mut subscriber = Session::new();
+        let mut publisher = Session::new();
+        let mut messages = subscriber.take_messages().unwrap();
+
+        subscriber.handle(request(&["SUBSCRIBE", "a"]), &context);
+        let reply = subscriber.handle(request(&["SSUBSCRIBE", "{user}a"]), &context);
+        assert_eq!(
+            reply,
+            RespType::Push(vec![
+                RespType::bulk_string("ssubscribe"),
+                RespType::bulk_string("{user}a"),
+                RespType::Integer(1),
+            ])
+        );
+
+        assert_eq!(publisher.handle(request(&["SPUBLISH", "a", "x"]), &context), RespType::Integer(0));
+        assert_eq!(publisher.handle(request(&["SPUBLISH", "{user}a", "x"]), &context), RespType::Integer(1));
+        assert_eq!(
+            messages.try_recv().unwrap(),
+            RespType::Push(vec![
+                RespType::bulk_string("smessage"),
+                RespType::bulk_string("{user}a"),
+                RespType::bulk_string("x"),
+            ])
+        );
+
+        let reply = subscriber.handle(request(&["SUNSUBSCRIBE"]), &context);
+        assert_eq!(
+            reply,
+            RespType::Push(vec![
+                RespType::bulk_string("sunsubscribe"),
+                RespType::bulk_string("{user}a"),
+                RespType::Integer(0),
+            ])
+        );
+        assert_eq!(subscriber.subscriptions(), 1);
+    }
+
+    #[test]
+    fn tracking_invalidates_read_keys() {
+        let context = create_shared_context(Context::default());
+        let mut reader = Session::new();
+        let mut writer = Session::new();
+        let mut messages = reader.take_messages().unwrap();
+
+        reader.register(&context);
+        reader.handle(request(&["HELLO", "3"]), &context);
+        assert_eq!(reader.handle(request(&["CLIENT", "TRACKING", "ON"]), &context), RespType::ok());
+
+        reader.handle(request(&["GET", "key"]), &context);
+        writer.handle(request(&["SET", "key", "value"]), &context);
+
+        assert_eq!(
+            messages.try_recv().unwrap(),
+            RespType::Push(vec![
+                RespType::bulk_string("invalidate"),
+                RespType::array(vec![RespType::bulk_string("key")]),
+            ])
+        );
+
+        // The key is forgotten until it is read again
+        writer.handle(request(&["SET", "key", "other"]), &context);
+        assert!(messages.try_recv().is_err());
+    }
+
+    #[test]
+    fn tracking_redirect_to_resp2_subscriber() {
+        let context = create_shared_context(Context::default());
+        let mut listener = Session::new();
+        let mut client = Session::new();
+        let mut messages = listener.take_messages().unwrap();
+
+        listener.register(&context);
+        listener.handle(request(&["SUBSCRIBE", "__redis__:invalidate"]), &context);
+        messages.try_recv().ok();
+
+        let redirect = listener.state.id.to_string();
+        let reply = client.handle(
+            request(&["CLIENT", "TRACKING", "ON", "BCAST", "PREFIX", "user:", "REDIRECT", &redirect]),
+            &context,
+        );
+        assert_eq!(reply, RespType::ok());
+
+        client.handle(request(&["SET", "order:1", "value"]), &context);
+        client.handle(request(&["SET", "user:1", "value"]), &context);
+
+        assert_eq!(
+            messages.try_recv().unwrap(),
+            RespType::Push(vec![
+                RespType::bulk_string("message"),
+                RespType::bulk_string("__redis__:invalidate"),
+                RespType::array(vec![RespType::bulk_string("user:1")]),
+            ])
+        );
+        assert!(messages.try_recv().is_err());
+    }
+
+    #[test]
+    fn tracking_optin_needs_caching_yes() {
+        let context = create_shared_context(Context::default());
+        let mut session = Session::new();
+        let mut messages = session.take_messages().unwrap();
+
+        session.register(&context);
+        session.handle(request(&["HELLO", "3"]), &context);
+        session.handle(request(&["CLIENT", "TRACKING", "ON", "OPTIN"]), &context);
+
+        session.handle(request(&["GET", "a"]), &context);
+        assert_eq!(session.handle(request(&["CLIENT", "CACHING", "YES"]), &context), RespType::ok());
+        session.handle(request(&["GET", "b"]), &context);
+
+        Session::new().handle(request(&["SET", "a", "1"]), &context);
+        Session::new().handle(request(&["SET", "b", "1"]), &context);
+
+        assert_eq!(
+            messages.try_recv().unwrap(),
+            RespType::Push(vec![
+                RespType::bulk_string("invalidate"),
+                RespType::array(vec![RespType::bulk_string("b")]),
+            ])
+        );
+        assert!(messages.try_recv().is_err());
+    }
+
+    #[test]
+    fn tracking_option_errors() {
+        let context = create_shared_context(Context::default());
+        let mut session = Session::new();
+
+        let reply = session.handle(request(&["CLIENT", "TRACKING", "ON", "PREFIX", "a"]), &context);
+        assert!(matches!(reply, RespType::SimpleError(Error::Custom { .. })));
+
+        let reply = session.handle(request(&["CLIENT", "TRACKING", "ON", "REDIRECT", "999999"]), &context);
+        assert!(matches!(reply, RespType::SimpleError(Error::Custom { .. })));
+
+        let reply = session.handle(request(&["CLIENT", "CACHING", "YES"]), &context);
+        assert!(matches!(reply, RespType::SimpleError(Error::Custom { .. })));
+    }
+
+    #[test]
+    fn close_removes_subscriptions() {
+        let context = create_shared_context(Context::default());
+        let mut session = Session::new();
+
+        session.handle(request(&["SUBSCRIBE", "a"]), &context);
+        assert_eq!(context.lock().unwrap().pubsub.numsub("a"), 1);
+
+        session.close(&context);
+        assert_eq!(context.lock().unwrap().pubsub.numsub("a"), 0);
+    }
+
+    #[test]
+    fn nested_multi() {
+        let context = create_shared_context(Context::default());
+        let mut session = Session::new();
+
+        session.handle(request(&["MULTI"]), &context);
+
+        assert_eq!(
+            session.handle(request(&["MULTI"]), &context),
+            Error::custom("ERR MULTI calls can not be nested").into()
+        );
+    }
+
+    #[test]
+    fn psync_attaches_replica() {
+        let context = master_context("psync_attaches_replica");
+        let mut replica = Session::new();
+        let mut client = Session::new();
+
+        client.handle(request(&["SET", "before", "1"]), &context);
+
+        assert_eq!(replica.handle(request(&["REPLCONF", "listening-port", "6380"]), &context), RespType::ok());
+        assert_eq!(replica.handle(request(&["REPLCONF", "capa", "eof", "capa", "psync2"]), &context), RespType::ok());
+
+        let reply = replica.handle(request(&["PSYNC", "?", "-1"]), &context);
+        let (replid, offset) = {
+            let context = context.lock().unwrap();
+            (context.config.master_replid.clone(), context.config.master_repl_offset)
+        };
+        assert_eq!(reply, RespType::simple_string(format!("FULLRESYNC {replid} {offset}")));
+
+        let mut stream = replica.take_replication().unwrap();
+        let snapshot = stream.try_recv().unwrap();
+        assert!(snapshot.starts_with(b"$") && snapshot.windows(6).any(|window| window == b"before"));
+
+        client.handle(request(&["SET", "after", "2"]), &context);
+
+        let command = stream.try_recv().unwrap();
+        assert_eq!(command, b"*3\r\n$3\r\nSET\r\n$5\r\nafter\r\n$1\r\n2\r\n");
+        assert_eq!(context.lock().unwrap().config.master_repl_offset, offset + command.len() as u64);
+
+        let replicas = &context.lock().unwrap().replicas;
+        let attached = replicas.iter().next().unwrap();
+        assert_eq!(attached.listening_port, 6380);
+        assert_eq!(attached.capabilities, ["eof", "psync2"]);
+    }
+
+    #[test]
+    fn psync_saves_snapshot_without_eof_capability() {
+        let context = master_context("psync_saves_snapshot_without_eof_capability");
+        let mut client = Session::new();
+        client.handle(request(&["SET", "key", "value"]), &context);
+
+        let mut replica = Session::new();
+        let reply = replica.handle(request(&["PSYNC", "?", "-1"]), &context);
+        assert!(reply.to_string().starts_with("+FULLRESYNC"));
+
+        let payload = replica.take_replication().unwrap().try_recv().unwrap();
+        let saved = std::fs::read(context.lock().unwrap().rdb_path()).unwrap();
+        assert_eq!(payload, snapshot_payload(&saved));
+
+        // Diskless syncs wait for more replicas before they start
+        context.lock().unwrap().config.repl_diskless_sync_delay = 5;
+
+        let mut replica = Session::new();
+        replica.handle(request(&["REPLCONF", "capa", "eof"]), &context);
+        let reply = replica.handle(request(&["PSYNC", "?", "-1"]), &context);
+        assert!(reply.to_string().starts_with("+FULLRESYNC"));
+        assert!(replica.take_replication().unwrap().try_recv().is_err());
+        assert_eq!(context.lock().unwrap().replicas.waiting().count(), 1);
+    }
+
+    #[test]
+    fn psync_continues_from_backlog() {
+        let context = master_context("psync_continues_from_backlog");
+        let mut client = Session::new();
+
+        let mut first = Session::new();
+        first.handle(request(&["PSYNC", "?", "-1"]), &context);
+        client.handle(request(&["SET", "key", "value"]), &context);
+
+        let (replid, offset) = {
+            let mut context = context.lock().unwrap();
+            context.replicas.remove(first.state.id);
+            (context.config.master_replid.clone(), context.config.master_repl_offset)
+        };
+        client.handle(request(&["SET", "missed", "1"]), &context);
+
+        let mut replica = Session::new();
+        replica.handle(request(&["REPLCONF", "capa", "psync2"]), &context);
+        let reply = replica.handle(request(&["PSYNC", &replid, &(offset + 1).to_string()]), &context);
+        assert_eq!(reply, RespType::simple_string(format!("CONTINUE {replid}")));
+
+        let missed = replica.take_replication().unwrap().try_recv().unwrap();
+        assert_eq!(missed, b"*3\r\n$3\r\nSET\r\n$6\r\nmissed\r\n$1\r\n1\r\n");
+
+        // Unknown histories and offsets out of the backlog need a full sync
+        for (replid, offset) in [("0123", offset + 1), (replid.as_str(), 0)] {
+            let reply = Session::new().handle(request(&["PSYNC", replid, &offset.to_string()]), &context);
+            assert!(reply.to_string().starts_with("+FULLRESYNC"));
+        }
+
+        // The previous history stays valid up to where it ended
+        let new_replid = {
+            let mut context = context.lock().unwrap();
+            context.config.shift_replid("f".repeat(40));
+            context.config.master_replid.clone()
+        };
+        let reply = Session::new().handle(request(&["PSYNC", &replid, &(offset + 1).to_string()]), &context);
+        assert_eq!(reply, RespType::simple_string("CONTINUE"));
+        client.handle(request(&["SET", "later", "1"]), &context);
+
+        let end = context.lock().unwrap().config.master_repl_offset;
+        let reply = Session::new().handle(request(&["PSYNC", &replid, &(end + 1).to_string()]), &context);
+        assert!(reply.to_string().starts_with("+FULLRESYNC"));
+        let reply = Session::new().handle(request(&["PSYNC", &new_replid, &(end + 1).to_string()]), &context);
+        assert_eq!(reply, RespType::simple_string("CONTINUE"));
+    }
+
+    #[test]
+    fn wait_counts_replica_acknowledgements() {
+        let context = master_context("wait_counts_replica_acknowledgements");
+        let mut client = Session::new();
+
+        assert_eq!(client.handle(request(&["WAIT", "0", "0"]), &context), RespType::Integer(0));
+        assert!(client.take_blocked().is_none());
+
+        let mut replica = Session::new();
+        replica.handle(request(&["PSYNC", "?", "-1"]), &context);
+        let mut stream = replica.take_replication().unwrap();
+        client.handle(request(&["SET", "key", "value"]), &context);
+
+        client.handle(request(&["WAIT", "1", "100"]), &context);
+        let blocked = client.take_blocked().unwrap();
+        let offset = context.lock().unwrap().config.master_repl_offset;
+
+        // Replicas are asked for their offset right away
+        let getack = aof::encode_all(&[["REPLCONF", "GETACK", "*"].map(str::to_string).to_vec()]);
+        assert_eq!(std::iter::from_fn(|| stream.try_recv().ok()).last(), Some(getack));
+
+        assert_eq!(
+            blocked.check(&context.lock().unwrap()),
+            (false, RespType::Integer(0))
+        );
+        replica.handle(
+            request(&["REPLCONF", "ACK", &offset.to_string(), "FACK", "0"]),
+            &context,
+        );
+        assert_eq!(
+            blocked.check(&context.lock().unwrap()),
+            (true, RespType::Integer(1))
+        );
+
+        assert_eq!(
+            client.handle(request(&["WAIT", "1", "0"]), &context),
+            RespType::Integer(1)
+        );
+        let reply = client.handle(request(&["WAITAOF", "0", "1", "0"]), &context);
+        assert_eq!(
+            reply,
+            RespType::array(vec![RespType::Integer(0), RespType::Integer(0)])
+        );
+        assert!(client.take_blocked().is_some());
+    }
+
+    #[test]
+    fn wait_errors() {
+        let context = create_shared_context(Context::default());
+        let mut client = Session::new();
+
+        let reply = client.handle(request(&["WAIT", "1", "-1"]), &context);
+        assert_eq!(reply.to_string(), "-ERR timeout is negative\r\n");
+        let reply = client.handle(request(&["WAIT", "1", "soon"]), &context);
+        assert_eq!(
+            reply.to_string(),
+            "-ERR timeout is not an integer or out of range\r\n"
+        );
+        let reply = client.handle(request(&["WAITAOF", "1", "0", "0"]), &context);
+        assert_eq!(
+            reply.to_string(),
+            "-ERR WAITAOF cannot be used when numlocal is set but appendonly is disabled.\r\n"
+        );
+
+        context.lock().unwrap().config.role = Role::Slave;
+        let reply = client.handle(request(&["WAIT", "0", "0"]), &context);
+        assert!(reply
+            .to_string()
+            .starts_with("-ERR WAIT cannot be used with replica instances."));
+        let reply = client.handle(request(&["WAITAOF", "0", "0", "0"]), &context);
+        assert!(reply
+            .to_string()
+            .starts_with("-ERR WAITAOF cannot be used with replica instances."));
+    }
+
+    #[test]
+    fn replicas_are_read_only() {
+        let context = create_shared_context(Context::default());
+        context.lock().unwrap().config.role = Role::Slave;
+        let mut client = Session::new();
+
+        let reply = client.handle(request(&["SET", "key", "value"]), &context);
+        assert_eq!(reply.to_string(), "-READONLY You can't write against a read only replica.\r\n");
+        assert_eq!(client.handle(request(&["GET", "key"]), &context), RespType::Null);
+
+        let mut master = Session::new();
+        master.is_master = true;
+        assert_eq!(master.handle(request(&["SET", "key", "value"]), &context), RespType::ok());
+
+        client.handle(request(&["CONFIG", "SET", "replica-read-only", "no"]), &context);
+        assert_eq!(client.handle(request(&["SET", "local", "1"]), &context), RespType::ok());
+    }
+
+    #[test]
+    fn stale_replicas_refuse_commands() {
+        let context = create_shared_context(Context::default());
+        let mut client = Session::new();
+        client.handle(request(&["CONFIG", "SET", "replica-serve-stale-data", "no"]), &context);
+        assert_eq!(client.handle(request(&["GET", "key"]), &context), RespType::Null);
+
+        context.lock().unwrap().config.role = Role::Slave;
+        let reply = client.handle(request(&["GET", "key"]), &context);
+        assert_eq!(
+            reply.to_string(),
+            "-MASTERDOWN Link with MASTER is down and replica-serve-stale-data is set to 'no'.\r\n"
+        );
+        assert_eq!(client.handle(request(&["PING"]), &context), RespType::simple_string("PONG"));
+
+        context.lock().unwrap().master_link.up = true;
+        assert_eq!(client.handle(request(&["GET", "key"]), &context), RespType::Null);
+    }
+
+    #[test]
+    fn replicaof_switches_roles() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = runtime.enter();
+        let context = create_shared_context(Context::default());
+        let mut client = Session::new();
+        client.handle(request(&["SET", "key", "value"]), &context);
+
+        let (replid, offset) = {
+            let context = context.lock().unwrap();
+            (context.config.master_replid.clone(), context.config.master_repl_offset)
+        };
+
+        let reply = client.handle(request(&["REPLICAOF", "127.0.0.1", "port"]), &context);
+        assert_eq!(reply.to_string(), "-ERR Invalid master port\r\n");
+
+        assert_eq!(client.handle(request(&["REPLICAOF", "127.0.0.1", "1"]), &context), RespType::ok());
+        let reply = client.handle(request(&["REPLICAOF", "127.0.0.1", "1"]), &context);
+        assert_eq!(reply, RespType::simple_string("OK Already connected to specified master"));
+
+        {
+            let context = context.lock().unwrap();
+            assert_eq!(context.config.role, Role::Slave);
+            assert!(context.master_link.synced);
+        }
+
+        // The former history stays valid up to where the promotion happened
+        assert_eq!(client.handle(request(&["REPLICAOF", "NO", "ONE"]), &context), RespType::ok());
+
+        let context = context.lock().unwrap();
+        assert_eq!(context.config.role, Role::Master);
+        assert!(context.config.replication.is_none());
+        assert_eq!(context.config.master_replid2, replid);
+        assert_eq!(context.config.second_repl_offset, Some(offset + 1));
+        assert_ne!(context.config.master_replid, replid);
+    }
+
+    #[test]
+    fn cluster_redirects_keys_served_elsewhere() {
+        let config = "\
+aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa 127.0.0.1:30001@40001 myself,master - 0 0 1 connected 0-8191
+bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb 127.0.0.1:30002@40002 master - 0 0 2 connected 8192-16383
+";
+        let context = create_shared_context(Context {
+            cluster: Some(crate::utils::cluster::Cluster::parse(config).unwrap()),
+            ..Default::default()
+        });
+        let mut session = Session::new();
+
+        // "b" is in slot 3300, "foo" in slot 12182
+        assert_eq!(session.handle(request(&["SET", "b", "1"]), &context), RespType::ok());
+        assert_eq!(
+            session.handle(request(&["GET", "foo"]), &context).to_string(),
+            "-MOVED 12182 127.0.0.1:30002\r\n"
+        );
+        assert_eq!(
+            session.handle(request(&["WATCH", "foo"]), &context).to_string(),
+            "-MOVED 12182 127.0.0.1:30002\r\n"
+        );
+        assert_eq!(
+            session.handle(request(&["CMS.MERGE", "b", "2", "{b}1", "foo"]), &context).to_string(),
+            "-CROSSSLOT Keys in request don't hash to the same slot\r\n"
+        );
+
+        session.handle(request(&["MULTI"]), &context);
+        session.handle(request(&["GET", "foo"]), &context);
+        assert_eq!(
+            session.handle(request(&["EXEC"]), &context).to_string(),
+            "-EXECABORT Transaction discarded because of previous errors.\r\n"
+        );
+    }
+
+    #[test]
+    fn asking_reaches_slots_being_imported() {
+        let config = "\
+aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa 127.0.0.1:30001@40001 myself,master - 0 0 1 connected 0-8191 [12182-<-bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb]
+bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb 127.0.0.1:30002@40002 master - 0 0 2 connected 8192-16383
+";
+        let context = create_shared_context(Context {
+            cluster: Some(crate::utils::cluster::Cluster::parse(config).unwrap()),
+            ..Default::default()
+        });
+        let mut session = Session::new();
+
+        assert_eq!(
+            session.handle(request(&["SET", "foo", "1"]), &context).to_string(),
+            "-MOVED 12182 127.0.0.1:30002\r\n"
+        );
+        assert_eq!(session.handle(request(&["ASKING"]), &context), RespType::ok());
+        assert_eq!(session.handle(request(&["SET", "foo", "1"]), &context), RespType::ok());
+
+        // Only for the next command
+        assert_eq!(
+            session.handle(request(&["GET", "foo"]), &context).to_string(),
+            "-MOVED 12182 127.0.0.1:30002\r\n"
+        );
+
+        assert_eq!(
+            session.handle(request(&["MIGRATE", "127.0.0.1", "30002", "b", "0", "10"]), &context),
+            RespType::simple_string("NOKEY")
+        );
+        assert!(!session.is_migrating());
+
+        session.handle(request(&["SET", "b", "1"]), &context);
+        assert_eq!(
+            session.handle(request(&["MIGRATE", "127.0.0.1", "30002", "b", "0", "10"]), &context),
+            RespType::Null
+        );
+        assert!(session.is_migrating());
+    }
+
+    #[test]
+    fn auth_switches_user() {
+        let context = create_shared_context(Context::default());
+        let mut admin = Session::new();
+
+        assert_eq!(
+            admin.handle(request(&["AUTH", "secret"]), &context).to_string(),
+            "-ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?\r\n"
+        );
+        assert_eq!(
+            admin.handle(request(&["ACL", "SETUSER", "alice", "on", ">secret", "~app:*", "+@read"]), &context),
+            RespType::ok()
+        );
+        assert_eq!(
+            admin.handle(request(&["ACL", "SETUSER", "default", "resetpass", ">admin"]), &context),
+            RespType::ok()
+        );
+
+        // Connections authenticated before keep their user
+        assert_eq!(admin.handle(request(&["ACL", "WHOAMI"]), &context), RespType::bulk_string("default"));
+
+        let mut session = Session::new();
+
+        assert_eq!(
+            session.handle(request(&["GET", "app:1"]), &context).to_string(),
+            "-NOAUTH Authentication required.\r\n"
+        );
+        assert_eq!(
+            session.handle(request(&["AUTH", "alice", "wrong"]), &context).to_string(),
+            "-WRONGPASS invalid username-password pair or user is disabled.\r\n"
+        );
+        assert_eq!(session.handle(request(&["AUTH", "alice", "secret"]), &context), RespType::ok());
+        assert!(session
+            .handle(request(&["ACL", "WHOAMI"]), &context)
+            .to_string()
+            .starts_with("-NOPERM User alice has no permissions to run the 'acl|whoami' command"));
+        assert_eq!(session.handle(request(&["GET", "app:1"]), &context), RespType::Null);
+
+        assert_eq!(admin.handle(request(&["ACL", "DELUSER", "alice"]), &context), RespType::Integer(1));
+        assert_eq!(
+            session.handle(request(&["GET", "app:1"]), &context).to_string(),
+            "-NOAUTH Authentication required.\r\n"
+        );
+        assert_eq!(session.handle(request(&["AUTH", "admin"]), &context), RespType::ok());
+        assert_eq!(session.handle(request(&["ACL", "WHOAMI"]), &context), RespType::bulk_string("default"));
+    }
+
+    #[test]
+    fn acl_permissions() {
+        let context = create_shared_context(Context::default());
+        let mut admin = Session::new();
+        let mut session = Session::new();
+
+        admin.handle(
+            request(&["ACL", "SETUSER", "bob", "on", "nopass", "%R~app:*", "&news.*", "+@read", "+publish", "+subscribe", "+multi", "+exec"]),
+            &context,
+        );
+        assert_eq!(session.handle(request(&["AUTH", "bob", "any"]), &context), RespType::ok());
+
+        assert_eq!(
+            session.handle(request(&["SET", "app:1", "v"]), &context).to_string(),
+            "-NOPERM User bob has no permissions to run the 'set' command\r\n"
+        );
+        assert_eq!(
+            session.handle(request(&["CONFIG", "GET", "port"]), &context).to_string(),
+            "-NOPERM User bob has no permissions to run the 'config|get' command\r\n"
+        );
+        assert_eq!(
+            session.handle(request(&["GET", "other"]), &context).to_string(),
+            "-NOPERM No permissions to access a key\r\n"
+        );
+        assert_eq!(
+            session.handle(request(&["PUBLISH", "sport", "goal"]), &context).to_string(),
+            "-NOPERM No permissions to access a channel\r\n"
+        );
+        assert_eq!(
+            session.handle(request(&["PUBLISH", "news.tech", "hi"]), &context),
+            RespType::Integer(0)
+        );
+
+        // Refused commands abort the transaction
+        assert_eq!(session.handle(request(&["MULTI"]), &context), RespType::ok());
+        assert_eq!(
+            session.handle(request(&["GET", "other"]), &context).to_string(),
+            "-NOPERM No permissions to access a key\r\n"
+        );
+        assert_eq!(
+            session.handle(request(&["EXEC"]), &context).to_string(),
+            "-EXECABORT Transaction discarded because of previous errors.\r\n"
+        );
+
+        admin.handle(request(&["ACL", "SETUSER", "bob", "+config|get"]), &context);
+        assert!(!matches!(
+            session.handle(request(&["CONFIG", "GET", "port"]), &context),
+            RespType::SimpleError(_)
+        ));
+        assert!(matches!(
+            session.handle(request(&["SUBSCRIBE", "sport"]), &context),
+            RespType::SimpleError(_)
+        ));
+        assert_eq!(session.subscriptions(), 0);
+    }
+
+    #[test]
+    fn acl_checks_script_commands() {
+        let context = create_shared_context(Context::default());
+        let mut admin = Session::new();
+        let mut session = Session::new();
+
+        admin.handle(
+            request(&["ACL", "SETUSER", "carol", "on", "nopass", "~app:*", "&news.*", "+eval", "+get", "+publish"]),
+            &context,
+        );
+        assert_eq!(session.handle(request(&["AUTH", "carol", "any"]), &context), RespType::ok());
+
+        let mut eval = |script: &str| session.handle(request(&["EVAL", script, "1", "app:1"]), &context).to_string();
+
+        assert!(eval("return redis.call('set', KEYS[1], 'v')")
+            .starts_with("-NOPERM User carol has no permissions to run the 'set' command script: "));
+        assert!(eval("return redis.call('get', 'other')").starts_with("-NOPERM No permissions to access a key script: "));
+        assert!(eval("return redis.call('publish', 'sport', 'goal')")
+            .starts_with("-NOPERM No permissions to access a channel script: "));
+        assert_eq!(eval("return redis.pcall('get', 'other')"), "-NOPERM No permissions to access a key\r\n");
+        assert_eq!(eval("return redis.call('get', KEYS[1])"), "$-1\r\n");
+    }
+
+    #[test]
+    fn acl_getuser_and_list() {
+        let context = create_shared_context(Context::default());
+        let mut session = Session::new();
+
+        assert_eq!(session.handle(request(&["ACL", "GETUSER", "nobody"]), &context), RespType::Null);
+        assert_eq!(
+            session.handle(request(&["ACL", "SETUSER", "alice", "on", "+get", "bogus"]), &context).to_string(),
+            "-ERR Error in ACL SETUSER modifier 'bogus': Syntax error\r\n"
+        );
+        assert_eq!(
+            session.handle(request(&["ACL", "SETUSER", "alice", "on", "+get", "~key"]), &context),
+            RespType::ok()
+        );
+        assert_eq!(
+            session.handle(request(&["ACL", "LIST"]), &context),
+            RespType::array(vec![
+                RespType::bulk_string("user alice on ~key resetchannels -@all +get"),
+                RespType::bulk_string("user default on nopass ~* &* +@all"),
+            ])
+        );
+        assert_eq!(
+            session.handle(request(&["ACL", "USERS"]), &context),
+            RespType::array(vec![RespType::bulk_string("alice"), RespType::bulk_string("default")])
+        );
+        assert_eq!(
+            session.handle(request(&["ACL", "GETUSER", "alice"]), &context).to_string(),
+            "*12\r\n$5\r\nflags\r\n*1\r\n$2\r\non\r\n$9\r\npasswords\r\n*0\r\n$8\r\ncommands\r\n$10\r\n-@all +get\r\n$4\r\nkeys\r\n$4\r\n~key\r\n$8\r\nchannels\r\n$0\r\n\r\n$9\r\nselectors\r\n*0\r\n"
+        );
+        assert_eq!(
+            session.handle(request(&["ACL", "DELUSER", "default"]), &context).to_string(),
+            "-ERR The 'default' user cannot be removed\r\n"
+        );
+        assert_eq!(
+            session.handle(request(&["ACL", "NOSUCH"]), &context).to_string(),
+            "-ERR unknown subcommand 'NOSUCH'. Try ACL HELP.\r\n"
+        );
+    }
+
+    #[test]
+    fn hello_authenticates() {
+        let context = create_shared_context(Context::default());
+
+        context.lock().unwrap().acl.set_user("default", &["resetpass".to_string()]).unwrap();
+
+        let mut session = Session::new();
+
+        assert!(session
+            .handle(request(&["HELLO", "3"]), &context)
+            .to_string()
+            .starts_with("-NOAUTH HELLO must be called"));
+        assert_eq!(
+            session.handle(request(&["HELLO", "3", "AUTH", "default", "wrong"]), &context).to_string(),
+            "-WRONGPASS invalid username-password pair or user is disabled.\r\n"
+        );
+
+        context.lock().unwrap().acl.set_user("default", &[">pass".to_string()]).unwrap();
+
+        assert!(matches!(
+            session.handle(request(&["HELLO", "3", "AUTH", "default", "pass"]), &context),
+            RespType::Map(_)
+        ));
+        assert_eq!(session.state.protocol, 3);
+        assert_eq!(session.handle(request(&["PING"]), &context), RespType::simple_string("PONG"));
+    }
+}
@@ -1,8 +1,60 @@
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, TryLockError};
 use super::context::Context;
 
 pub type SharedContext = Arc<Mutex<Context>>;
 
-pub fn create_shared_context(context: Context) -> SharedContext {
-    Arc::new(Mutex::new(context))
+pub fn create_shared_context(mut context: Context) -> SharedContext {
+    Arc::new_cyclic(|shared| {
+        context.shared = shared.clone();
+        Mutex::new(context)
+    })
+}
+
+/// Locks the context
+///
+/// A script holds the context for as long as it runs, waiting for it hands
+/// the worker over to the other tasks of the runtime so they keep going,
+/// e.g. to reply `BUSY` or to kill the script.
+///
+/// A task panicking while holding the context poisons it, the server keeps
+/// serving with the context as the task left it rather than stopping.
+pub fn lock(context: &SharedContext) -> MutexGuard<'_, Context> {
+    match context.try_lock() {
+        Ok(context) => context,
+        Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+        Err(TryLockError::WouldBlock) => blocking(|| context.lock().unwrap_or_else(PoisonError::into_inner)),
+    }
+}
+
+/// Runs `f`, which may block for long, off the workers of the runtime
+pub fn blocking<T>(f: impl FnOnce() -> T) -> T {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(f)
+        }
+        _ => f(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recovers_poisoned_context() {
+        let context = create_shared_context(Context::default());
+        let poisoner = context.clone();
+
+        let panicked = std::thread::spawn(move || {
+            let _context = poisoner.lock().unwrap();
+            panic!("poisoning the context");
+        })
+        .join();
+
+        assert!(panicked.is_err());
+        assert!(context.is_poisoned());
+
+        lock(&context).config.port = 1234;
+        assert_eq!(lock(&context).config.port, 1234);
+    }
 }
@@ -1,12 +1,353 @@
-use std::{
-    collections::HashMap, hash::RandomState
-};
+use std::collections::{HashMap, HashSet};
 
 use crate::models::StoreValue;
 
-pub type Store = HashMap<String, StoreValue, RandomState>;
+/// Keyspace of the server
+///
+/// Besides the values it keeps track of the keys watched by each session, so
+/// any write to a watched key flags the watching sessions as dirty and their
+/// next `EXEC` is aborted.
+#[derive(Debug, Default)]
+pub struct Store {
+    data: HashMap<String, StoreValue>,
+    /// Watched key -> sessions watching it and whether the key had already
+    /// expired when it was watched
+    watched: HashMap<String, Vec<(u64, bool)>>,
+    dirty: HashSet<u64>,
+    /// Keys modified since the last [`Store::take_modified`]
+    modified: Vec<String>,
+    /// Whether the keyspace was cleared since the last [`Store::take_modified`]
+    flushed: bool,
+    /// Keys with a ttl, sampled by the active expiry cycle
+    volatile: Vec<String>,
+    /// Position of each key in `volatile`
+    volatile_positions: HashMap<String, usize>,
+    /// Position in `volatile` the next [`Store::remove_expired`] starts at
+    cursor: usize,
+}
 
 pub fn create_store() -> Store {
-    HashMap::new()
+    Store::default()
+}
+
+impl Store {
+    pub fn get(&self, key: &str) -> Option<&StoreValue> {
+        self.data.get(key)
+    }
+
+    /// Mutable access to a value, callers changing it must [`Store::touch`] the key
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut StoreValue> {
+        self.data.get_mut(key)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.data.contains_key(key)
+    }
+
+    /// Every key and value, including expired ones not removed yet
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &StoreValue)> {
+        self.data.iter()
+    }
+
+    /// Copy of every live key and value, serialized later without the store
+    pub fn snapshot(&self) -> Vec<(String, StoreValue)> {
+        self.data
+            .iter()
+            .filter(|(_, value)| !value.is_expired())
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    pub fn insert(&mut self, key: String, value: StoreValue) -> Option<StoreValue> {
+        self.touch(&key);
+
+        let previous = self.data.insert(key.clone(), value);
+        self.index(&key);
+
+        previous
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<StoreValue> {
+        self.touch(key);
+        self.unindex(key);
+        self.data.remove(key)
+    }
+
+    /// Removes every key, sessions watching any key become dirty
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.volatile.clear();
+        self.volatile_positions.clear();
+        self.cursor = 0;
+        self.flushed = true;
+
+        for watchers in self.watched.values() {
+            self.dirty.extend(watchers.iter().map(|(session, _)| *session));
+        }
+    }
+
+    /// Signals that `key` was modified
+    pub fn touch(&mut self, key: &str) {
+        self.modified.push(key.to_string());
+        self.index(key);
+
+        if let Some(watchers) = self.watched.get(key) {
+            self.dirty.extend(watchers.iter().map(|(session, _)| *session));
+        }
+    }
+
+    /// Drops `key` when its ttl has elapsed, returns whether it was removed
+    ///
+    /// Sessions that watched the key after it had already expired are not
+    /// flagged, for them the key never existed.
+    pub fn remove_if_expired(&mut self, key: &str) -> bool {
+        let expired = self.data.get(key).is_some_and(|value| value.is_expired());
+
+        if expired {
+            self.data.remove(key);
+            self.unindex(key);
+            self.modified.push(key.to_string());
+
+            if let Some(watchers) = self.watched.get(key) {
+                let live = watchers.iter().filter(|(_, expired)| !expired);
+                self.dirty.extend(live.map(|(session, _)| *session));
+            }
+        }
+
+        expired
+    }
+
+    /// Drains the keys modified so far and whether the keyspace was cleared
+    pub fn take_modified(&mut self) -> (Vec<String>, bool) {
+        (std::mem::take(&mut self.modified), std::mem::take(&mut self.flushed))
+    }
+
+    /// Checks up to `count` keys with a ttl, continuing where the last call
+    /// stopped, and removes the expired ones
+    ///
+    /// Returns the removed keys and the number of keys checked.
+    pub fn remove_expired(&mut self, count: usize) -> (Vec<String>, usize) {
+        let count = count.min(self.volatile.len());
+        let mut removed = Vec::new();
+
+        for _ in 0..count {
+            if self.cursor >= self.volatile.len() {
+                self.cursor = 0;
+            }
+
+            let key = self.volatile[self.cursor].clone();
+
+            // The last key takes the place of a removed one, it is checked next
+            if self.remove_if_expired(&key) {
+                removed.push(key);
+            } else {
+                self.cursor += 1;
+            }
+        }
+
+        (removed, count)
+    }
+
+    /// Keeps `key` in the keys with a ttl if and only if it has one
+    fn index(&mut self, key: &str) {
+        let volatile = self.data.get(key).is_some_and(|value| value.expire_time.is_some());
+
+        if !volatile {
+            self.unindex(key);
+        } else if !self.volatile_positions.contains_key(key) {
+            self.volatile_positions.insert(key.to_string(), self.volatile.len());
+            self.volatile.push(key.to_string());
+        }
+    }
+
+    fn unindex(&mut self, key: &str) {
+        let Some(position) = self.volatile_positions.remove(key) else {
+            return;
+        };
+
+        self.volatile.swap_remove(position);
+
+        if let Some(moved) = self.volatile.get(position) {
+            self.volatile_positions.insert(moved.clone(), position);
+        }
+    }
+
+    pub fn watch(&mut self, session: u64, key: &str) {
+        let expired = self.data.get(key).is_some_and(|value| value.is_expired());
+        let watchers = self.watched.entry(key.to_string()).or_default();
+
+        if !watchers.iter().any(|(id, _)| *id == session) {
+            watchers.push((session, expired));
+        }
+    }
+
+    /// Forgets the keys watched by `session` and its dirty flag
+    pub fn unwatch(&mut self, session: u64, keys: &[String]) {
+        for key in keys {
+            if let Some(watchers) = self.watched.get_mut(key) {
+                watchers.retain(|(id, _)| *id != session);
+
+                if watchers.is_empty() {
+                    self.watched.remove(key);
+                }
+            }
+        }
+
+        self.dirty.remove(&session);
+    }
+
+    /// Whether a key watched by `session` was modified since it was watched
+    pub fn is_dirty(&self, session: u64, keys: &[String]) -> bool {
+        if self.dirty.contains(&session) {
+            return true;
+        }
+
+        // Keys that expire while watched count as modified even before they
+        // are lazily removed
+        keys.iter().any(|key| {
+            let expired_when_watched = self
+                .watched
+                .get(key)
+                .and_then(|watchers| watchers.iter().find(|(id, _)| *id == session))
+                .is_some_and(|(_, expired)| *expired);
+
+            !expired_when_watched && self.data.get(key).is_some_and(|value| value.is_expired())
+        })
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+
+    fn keys(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn write_to_watched_key_marks_session_dirty() {
+        let mut store = create_store();
+
+        store.watch(1, "key");
+        store.watch(2, "other");
+        store.insert("key".to_string(), StoreValue::from("value"));
+
+        assert!(store.is_dirty(1, &keys(&["key"])));
+        assert!(!store.is_dirty(2, &keys(&["other"])));
+    }
+
+    #[test]
+    fn unwatch_clears_dirty_flag() {
+        let mut store = create_store();
+
+        store.watch(1, "key");
+        store.insert("key".to_string(), StoreValue::from("value"));
+        store.unwatch(1, &keys(&["key"]));
+
+        assert!(!store.is_dirty(1, &[]));
+
+        store.insert("key".to_string(), StoreValue::from("value"));
+        assert!(!store.is_dirty(1, &[]));
+    }
+
+    #[test]
+    fn clear_marks_all_watchers_dirty() {
+        let mut store = create_store();
+
+        store.watch(1, "a");
+        store.watch(2, "b");
+        store.clear();
+
+        assert!(store.is_dirty(1, &keys(&["a"])));
+        assert!(store.is_dirty(2, &keys(&["b"])));
+    }
+
+    #[test]
+    fn expiry_of_watched_key() {
+        let mut store = create_store();
+        let mut value = StoreValue::new("value", Some(Duration::from_secs(10)));
+
+        store.insert("key".to_string(), value);
+        store.watch(1, "key");
+        assert!(!store.is_dirty(1, &keys(&["key"])));
+
+        value = StoreValue::new("value", Some(Duration::from_secs(10)));
+        value.created_at = SystemTime::now() - Duration::from_secs(20);
+        store.data.insert("key".to_string(), value);
+
+        assert!(store.is_dirty(1, &keys(&["key"])));
+    }
+
+    #[test]
+    fn modified_keys_are_drained() {
+        let mut store = create_store();
+
+        store.insert("a".to_string(), StoreValue::from("1"));
+        store.touch("b");
+
+        assert_eq!(store.take_modified(), (keys(&["a", "b"]), false));
+
+        store.clear();
+        assert_eq!(store.take_modified(), (vec![], true));
+    }
+
+    #[test]
+    fn remove_expired_keys() {
+        let mut store = create_store();
+        let mut value = StoreValue::new("value", Some(Duration::from_secs(10)));
+        value.created_at = SystemTime::now() - Duration::from_secs(20);
+
+        store.insert("old".to_string(), value);
+        store.insert("new".to_string(), StoreValue::from("value"));
+
+        assert_eq!(store.remove_expired(20), (keys(&["old"]), 1));
+        assert!(!store.contains_key("old"));
+        assert!(store.contains_key("new"));
+    }
+
+    #[test]
+    fn remove_expired_samples_keys_with_ttl() {
+        let mut store = create_store();
+        let mut expired = StoreValue::new("value", Some(Duration::from_secs(10)));
+        expired.created_at = SystemTime::now() - Duration::from_secs(20);
+
+        for key in ["a", "b", "c"] {
+            store.insert(key.to_string(), StoreValue::new("value", Some(Duration::from_secs(100))));
+        }
+
+        store.insert("d".to_string(), expired.clone());
+        store.insert("e".to_string(), expired);
+
+        // Keys losing their ttl or removed are not checked anymore
+        store.insert("a".to_string(), StoreValue::from("value"));
+        store.remove("b");
+        assert_eq!(store.volatile.len(), 3);
+
+        let (mut removed, checked) = store.remove_expired(2);
+        let (rest, _) = store.remove_expired(2);
+        removed.extend(rest);
+        removed.sort();
+
+        assert_eq!(checked, 2);
+        assert_eq!(removed, keys(&["d", "e"]));
+        assert_eq!(store.volatile, keys(&["c"]));
+
+        store.clear();
+        assert_eq!(store.remove_expired(20), (vec![], 0));
+    }
+
+    #[test]
+    fn already_expired_key_is_not_dirty() {
+        let mut store = create_store();
+        let mut value = StoreValue::new("value", Some(Duration::from_secs(10)));
+        value.created_at = SystemTime::now() - Duration::from_secs(20);
+
+        store.data.insert("key".to_string(), value);
+        store.watch(1, "key");
+        store.remove_if_expired("key");
+
+        assert!(!store.is_dirty(1, &keys(&["key"])));
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::{HashMap, HashSet};
+
+/// Options a session enabled tracking with through `CLIENT TRACKING ON`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackingOptions {
+    /// Session receiving the invalidations instead of the tracking one
+    pub redirect: Option<u64>,
+    /// Broadcast mode, invalidate every key matching `prefixes` without
+    /// remembering reads
+    pub bcast: bool,
+    pub prefixes: Vec<String>,
+    /// Only remember keys read right after `CLIENT CACHING YES`
+    pub optin: bool,
+    /// Remember every read except those right after `CLIENT CACHING NO`
+    pub optout: bool,
+    /// Don't invalidate keys the session modified itself
+    pub noloop: bool,
+}
+
+/// Server side of client side caching, remembers which session may have
+/// cached which key
+#[derive(Debug, Default)]
+pub struct Tracking {
+    sessions: HashMap<u64, TrackingOptions>,
+    /// Key -> sessions that read it since its last invalidation
+    keys: HashMap<String, HashSet<u64>>,
+}
+
+impl Tracking {
+    pub fn enable(&mut self, session: u64, options: TrackingOptions) {
+        self.sessions.insert(session, options);
+    }
+
+    /// Stops tracking `session`, keys it read are forgotten lazily
+    pub fn disable(&mut self, session: u64) {
+        self.sessions.remove(&session);
+    }
+
+    pub fn options(&self, session: u64) -> Option<&TrackingOptions> {
+        self.sessions.get(&session)
+    }
+
+    /// Records that `session` read `keys` and may cache them
+    pub fn remember(&mut self, session: u64, keys: &[String]) {
+        for key in keys {
+            self.keys.entry(key.to_string()).or_default().insert(session);
+        }
+    }
+
+    /// Recipients of the invalidation of `keys`, modified by session `by`
+    ///
+    /// Returns the keys grouped by the session that must receive them, which
+    /// is the redirect target when the tracking session set one.
+    pub fn invalidate(&mut self, keys: &[String], by: Option<u64>) -> HashMap<u64, Vec<String>> {
+        let mut recipients: HashMap<u64, Vec<String>> = HashMap::new();
+
+        for key in keys {
+            let readers = self.keys.remove(key).unwrap_or_default();
+
+            let broadcast = self.sessions.iter().filter(|(_, options)| {
+                options.bcast
+                    && (options.prefixes.is_empty() || options.prefixes.iter().any(|p| key.starts_with(p.as_str())))
+            });
+
+            let sessions: HashSet<u64> = readers
+                .into_iter()
+                .filter(|session| self.sessions.get(session).is_some_and(|options| !options.bcast))
+                .chain(broadcast.map(|(session, _)| *session))
+                .collect();
+
+            for session in sessions {
+                let options = &self.sessions[&session];
+
+                if options.noloop && Some(session) == by {
+                    continue;
+                }
+
+                let target = options.redirect.unwrap_or(session);
+                let pending = recipients.entry(target).or_default();
+
+                if !pending.contains(key) {
+                    pending.push(key.to_string());
+                }
+            }
+        }
+
+        recipients
+    }
+
+    /// Recipients of the invalidation of the whole keyspace, after a flush
+    pub fn invalidate_all(&mut self) -> HashSet<u64> {
+        self.keys.clear();
+
+        self.sessions
+            .iter()
+            .map(|(session, options)| options.redirect.unwrap_or(*session))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn default_mode_invalidates_read_keys_once() {
+        let mut tracking = Tracking::default();
+
+        tracking.enable(1, TrackingOptions::default());
+        tracking.remember(1, &keys(&["a"]));
+
+        let recipients = tracking.invalidate(&keys(&["a", "b"]), Some(2));
+        assert_eq!(recipients, HashMap::from([(1, keys(&["a"]))]));
+
+        assert!(tracking.invalidate(&keys(&["a"]), Some(2)).is_empty());
+    }
+
+    #[test]
+    fn broadcast_prefixes_and_redirect() {
+        let mut tracking = Tracking::default();
+        let options = TrackingOptions {
+            bcast: true,
+            prefixes: keys(&["user:"]),
+            redirect: Some(7),
+            ..Default::default()
+        };
+
+        tracking.enable(1, options);
+
+        let recipients = tracking.invalidate(&keys(&["user:1", "order:1"]), None);
+        assert_eq!(recipients, HashMap::from([(7, keys(&["user:1"]))]));
+    }
+
+    #[test]
+    fn noloop_skips_own_writes() {
+        let mut tracking = Tracking::default();
+        let options = TrackingOptions {
+            noloop: true,
+            ..Default::default()
+        };
+
+        tracking.enable(1, options);
+        tracking.remember(1, &keys(&["a"]));
+
+        assert!(tracking.invalidate(&keys(&["a"]), Some(1)).is_empty());
+    }
+
+    #[test]
+    fn disabled_sessions_are_not_invalidated() {
+        let mut tracking = Tracking::default();
+
+        tracking.enable(1, TrackingOptions::default());
+        tracking.remember(1, &keys(&["a"]));
+        tracking.disable(1);
+
+        assert!(tracking.invalidate(&keys(&["a"]), None).is_empty());
+        assert!(tracking.invalidate_all().is_empty());
+    }
+}
@@ -0,0 +1,179 @@
+//! Encodings of small collections found in RDB files written by older
+//! versions of Redis: ziplists, zipmaps and intsets
+
+use crate::resp::types::bytes_to_string;
+
+const ZIPLIST_END: u8 = 0xff;
+
+/// Elements of the ziplist `data`, integers formatted as strings
+///
+/// A ziplist is a `u32` size, the `u32` offset of its last element and a
+/// `u16` element count followed by the elements and a `0xff` terminator.
+/// Each element starts with the length of the previous one.
+pub fn decode(data: &[u8]) -> Option<Vec<String>> {
+    let total = u32::from_le_bytes(data.get(..4)?.try_into().ok()?) as usize;
+
+    if total != data.len() || data.last() != Some(&ZIPLIST_END) {
+        return None;
+    }
+
+    let count = u16::from_le_bytes(data.get(8..10)?.try_into().ok()?);
+    let mut elements = Vec::new();
+    let mut position = 10;
+
+    while *data.get(position)? != ZIPLIST_END {
+        position += if data[position] < 254 { 1 } else { 5 };
+
+        let (element, len) = decode_element(data.get(position..)?)?;
+
+        elements.push(element);
+        position += len;
+    }
+
+    if count != u16::MAX && count as usize != elements.len() {
+        return None;
+    }
+
+    Some(elements)
+}
+
+/// One element, after the previous element length, and the size of its
+/// encoding and data
+fn decode_element(data: &[u8]) -> Option<(String, usize)> {
+    let first = *data.first()?;
+    let string = |from: usize, len: usize| Some((bytes_to_string(data.get(from..from + len)?), from + len));
+    let int = |len: usize| {
+        let raw = data.get(1..1 + len)?;
+        let mut buf = [0; 8];
+        buf[..len].copy_from_slice(raw);
+        if raw[len - 1] & 0x80 != 0 {
+            buf[len..].fill(0xff);
+        }
+        Some((i64::from_le_bytes(buf).to_string(), 1 + len))
+    };
+
+    match first >> 6 {
+        0 => string(1, (first & 0x3f) as usize),
+        1 => string(2, ((first as usize & 0x3f) << 8) | *data.get(1)? as usize),
+        2 => string(5, u32::from_be_bytes(data.get(1..5)?.try_into().ok()?) as usize),
+        _ => match first {
+            0xc0 => int(2),
+            0xd0 => int(4),
+            0xe0 => int(8),
+            0xf0 => int(3),
+            0xfe => int(1),
+            0xf1..=0xfd => Some(((first as i64 & 0x0f) - 1).to_string()).map(|value| (value, 1)),
+            _ => None,
+        },
+    }
+}
+
+/// Members of the intset `data`, a `u32` integer width and count followed by
+/// the sorted integers
+pub fn decode_intset(data: &[u8]) -> Option<Vec<String>> {
+    let width = u32::from_le_bytes(data.get(..4)?.try_into().ok()?) as usize;
+    let count = u32::from_le_bytes(data.get(4..8)?.try_into().ok()?) as usize;
+    let values = data.get(8..)?;
+
+    if !matches!(width, 2 | 4 | 8) || values.len() != width * count {
+        return None;
+    }
+
+    let members = values
+        .chunks(width)
+        .map(|raw| match width {
+            2 => i16::from_le_bytes([raw[0], raw[1]]) as i64,
+            4 => i32::from_le_bytes(raw.try_into().unwrap()) as i64,
+            _ => i64::from_le_bytes(raw.try_into().unwrap()),
+        })
+        .map(|value| value.to_string())
+        .collect();
+
+    Some(members)
+}
+
+/// Field value pairs of the zipmap `data`
+///
+/// After a one byte count, each field and value is prefixed by its length,
+/// values also by a number of unused trailing bytes.
+pub fn decode_zipmap(data: &[u8]) -> Option<Vec<(String, String)>> {
+    let mut pairs = Vec::new();
+    let mut position = 1;
+
+    let read_len = |position: &mut usize| -> Option<Option<usize>> {
+        let first = *data.get(*position)?;
+        *position += 1;
+
+        match first {
+            0xff => Some(None),
+            0xfe => {
+                let len = u32::from_le_bytes(data.get(*position..*position + 4)?.try_into().ok()?);
+                *position += 4;
+                Some(Some(len as usize))
+            }
+            len => Some(Some(len as usize)),
+        }
+    };
+
+    while let Some(len) = read_len(&mut position)? {
+        let field = bytes_to_string(data.get(position..position + len)?);
+        position += len;
+
+        let len = read_len(&mut position)??;
+        let free = *data.get(position)? as usize;
+        let value = bytes_to_string(data.get(position + 1..position + 1 + len)?);
+        position += 1 + len + free;
+
+        pairs.push((field, value));
+    }
+
+    Some(pairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ziplist(entries: &[&[u8]]) -> Vec<u8> {
+        let mut data = vec![0; 10];
+        let mut previous = 0;
+
+        for entry in entries {
+            data.push(previous as u8);
+            data.extend(*entry);
+            previous = entry.len() + 1;
+        }
+
+        data.push(ZIPLIST_END);
+
+        let total = data.len() as u32;
+        data[..4].copy_from_slice(&total.to_le_bytes());
+        data[8..10].copy_from_slice(&(entries.len() as u16).to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn ziplist_entries() {
+        let data = ziplist(&[b"\x02hi", b"\xf5", b"\xfe\x9c", b"\xc0\xe8\x03", b"\xf0\x00\x00\x80"]);
+
+        assert_eq!(decode(&data).unwrap(), vec!["hi", "4", "-100", "1000", "-8388608"]);
+    }
+
+    #[test]
+    fn intset_members() {
+        let data = [&2u32.to_le_bytes()[..], &2u32.to_le_bytes(), &(-3i16).to_le_bytes(), &7i16.to_le_bytes()].concat();
+
+        assert_eq!(decode_intset(&data).unwrap(), vec!["-3", "7"]);
+        assert!(decode_intset(&data[..10]).is_none());
+    }
+
+    #[test]
+    fn zipmap_pairs() {
+        let data = b"\x02\x03foo\x03\x01bar!\x01a\x00\x00\xff";
+
+        assert_eq!(
+            decode_zipmap(data).unwrap(),
+            vec![("foo".to_string(), "bar".to_string()), ("a".to_string(), "".to_string())]
+        );
+    }
+}